@@ -0,0 +1,92 @@
+//! Accounting of the gas the relayer spends, with optional caps on how much it may spend per hour and per day.
+//! Reaching a cap pauses relaying until enough of the spending has aged out of the window, so a bad price feed
+//! or a profitability bug can only burn through a bounded amount of gas before an operator looks at it.
+
+use crate::alert::alert_on_spend_cap;
+use crate::units::display_ether;
+use clarity::Uint256;
+use log::{info, warn};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+const HOUR: Duration = Duration::from_secs(60 * 60);
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Clone, Default)]
+pub struct SpendCaps {
+    /// most wei of the gas token spent within any hour
+    pub per_hour: Option<Uint256>,
+    /// most wei of the gas token spent within any day
+    pub per_day: Option<Uint256>,
+}
+
+static CAPS: LazyLock<Mutex<Option<SpendCaps>>> = LazyLock::new(|| Mutex::new(None));
+
+/// Gas paid by each relay that made it on chain over the last day
+static SPENT: LazyLock<Mutex<VecDeque<(Instant, Uint256)>>> =
+    LazyLock::new(|| Mutex::new(VecDeque::new()));
+
+/// Whether a cap was reached when last checked, so that reaching and clearing it is only logged once
+static CAPPED: AtomicBool = AtomicBool::new(false);
+
+pub fn set_spend_caps(caps: Option<SpendCaps>) {
+    *CAPS.lock().unwrap() = caps;
+}
+
+/// Adds the gas paid for a relay that made it on chain
+pub fn record_spend(gas_paid: Uint256) {
+    let mut spent = SPENT.lock().unwrap();
+    while spent.front().is_some_and(|(at, _)| at.elapsed() > DAY) {
+        spent.pop_front();
+    }
+    spent.push_back((Instant::now(), gas_paid));
+}
+
+/// Gas spent in wei within the last `period`, at most a day
+pub fn spent_within(period: Duration) -> Uint256 {
+    SPENT
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(at, _)| at.elapsed() <= period)
+        .fold(Uint256::default(), |total, (_, gas)| total + *gas)
+}
+
+/// True while the gas spent within the last hour or day has reached its cap and relaying has to stay paused
+pub fn spend_cap_reached() -> bool {
+    let Some(caps) = CAPS.lock().unwrap().clone() else {
+        return false;
+    };
+    let reached = [("hour", HOUR, caps.per_hour), ("day", DAY, caps.per_day)]
+        .into_iter()
+        .filter_map(|(name, period, cap)| Some((name, spent_within(period), cap?)))
+        .find(|(_, spent, cap)| spent >= cap);
+    match reached {
+        Some((period, spent, cap)) if !CAPPED.swap(true, Ordering::Relaxed) => {
+            warn!(
+                "Spent {} ALTHEA on gas within the last {period}, reaching the cap of {} ALTHEA. Relaying is paused until spending falls below it",
+                display_ether(spent),
+                display_ether(cap)
+            );
+            alert_on_spend_cap(Some((period, spent, cap)));
+        }
+        None if CAPPED.swap(false, Ordering::Relaxed) => {
+            info!("Gas spending is below its caps again, relaying resumed");
+            alert_on_spend_cap(None);
+        }
+        _ => {}
+    }
+    reached.is_some()
+}
+
+/// True if spending `cost` more would keep the gas spent within the last hour and day within their caps
+pub fn within_spend_caps(cost: Uint256) -> bool {
+    let Some(caps) = CAPS.lock().unwrap().clone() else {
+        return true;
+    };
+    [(HOUR, caps.per_hour), (DAY, caps.per_day)]
+        .into_iter()
+        .all(|(period, cap)| cap.is_none_or(|cap| spent_within(period) + cost <= cap))
+}
@@ -0,0 +1,288 @@
+//! Local admin API, lets operators pause relaying during incidents without killing the process and losing
+//! track of in-flight and unconfirmed relays, reload the configuration, inspect live stats and the retry queue,
+//! export the relay history, ban or unban users and confirm changed contract code.
+
+use crate::accounting::spend_cap_reached;
+use crate::banlist::{BannedUser, ban, banned_users, unban, user_strikes};
+use crate::codewatch::{confirm_contract_code, contract_code_changed};
+use crate::history::{ExportFormat, ExportReport, export_history, parse_date};
+use crate::killswitch::kill_switch_engaged;
+use crate::maintenance::in_maintenance_window;
+use crate::proxy::http_workers;
+use crate::reload::request_reload;
+use crate::retry::retry_queue;
+use crate::status::{InFlightTransaction, UnconfirmedTransaction, get_status};
+use actix_web::{App, HttpResponse, HttpServer, Responder, web};
+use clarity::Address;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::Instant;
+
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+/// Outcome of one relay attempt, counted for `/admin/stats`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Included,
+    Skipped,
+    Retried,
+    Failed,
+    Finalized,
+    Reorged,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RelayStats {
+    /// relays included in a block
+    pub included: u64,
+    /// transactions evaluated and deliberately not relayed, for example because they were unprofitable
+    pub skipped: u64,
+    /// attempts that failed transiently and were scheduled for a retry
+    pub retried: u64,
+    /// attempts that failed permanently
+    pub failed: u64,
+    /// relays that reached the configured confirmation depth
+    pub finalized: u64,
+    /// relays that were reorged out before becoming final
+    pub reorged: u64,
+}
+
+static STATS: LazyLock<Mutex<RelayStats>> = LazyLock::new(|| Mutex::new(RelayStats::default()));
+static STARTED: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+/// True while relaying is paused, from the admin api, by the kill switch, during a maintenance window, because
+/// gas spending reached its cap or because contract code changed
+pub fn is_paused() -> bool {
+    PAUSED.load(Ordering::Relaxed)
+        || kill_switch_engaged()
+        || in_maintenance_window()
+        || spend_cap_reached()
+        || contract_code_changed()
+}
+
+pub fn relay_stats() -> RelayStats {
+    STATS.lock().unwrap().clone()
+}
+
+pub fn record_outcome(outcome: Outcome) {
+    let mut stats = STATS.lock().unwrap();
+    let counter = match outcome {
+        Outcome::Included => &mut stats.included,
+        Outcome::Skipped => &mut stats.skipped,
+        Outcome::Retried => &mut stats.retried,
+        Outcome::Failed => &mut stats.failed,
+        Outcome::Finalized => &mut stats.finalized,
+        Outcome::Reorged => &mut stats.reorged,
+    };
+    *counter += 1;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdminStats {
+    pub paused: bool,
+    pub uptime_secs: u64,
+    pub relays: RelayStats,
+    pub in_flight: usize,
+    pub unconfirmed: usize,
+    pub retry_queue: usize,
+}
+
+/// A transaction waiting in the retry queue
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueuedRetry {
+    pub id: String,
+    pub chain_id: u64,
+    pub attempts: u32,
+    /// seconds until the next attempt, zero if it is due
+    pub retry_in_secs: u64,
+    pub last_error: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdminQueue {
+    pub retries: Vec<QueuedRetry>,
+    pub in_flight: Vec<InFlightTransaction>,
+    /// keyed by chain id
+    pub unconfirmed: HashMap<u64, Vec<UnconfirmedTransaction>>,
+}
+
+async fn pause() -> impl Responder {
+    if !PAUSED.swap(true, Ordering::Relaxed) {
+        info!("Relaying paused by admin request");
+    }
+    HttpResponse::Ok().body("Paused")
+}
+
+async fn resume() -> impl Responder {
+    if PAUSED.swap(false, Ordering::Relaxed) {
+        info!("Relaying resumed by admin request");
+    }
+    HttpResponse::Ok().body("Resumed")
+}
+
+async fn confirm_code() -> impl Responder {
+    let confirmed = confirm_contract_code();
+    if confirmed.is_empty() {
+        return HttpResponse::Ok().body("No contract code changes to confirm");
+    }
+    let contracts: Vec<String> = confirmed
+        .iter()
+        .map(|contract| contract.to_string())
+        .collect();
+    HttpResponse::Ok().body(format!("Confirmed the code of {}", contracts.join(", ")))
+}
+
+async fn reload() -> impl Responder {
+    info!("Configuration reload requested by admin request");
+    request_reload();
+    HttpResponse::Accepted().body("Reload requested")
+}
+
+async fn stats() -> impl Responder {
+    let status = get_status();
+    HttpResponse::Ok().json(AdminStats {
+        paused: is_paused(),
+        uptime_secs: STARTED.elapsed().as_secs(),
+        relays: relay_stats(),
+        in_flight: status.in_flight.len(),
+        unconfirmed: status.chains.values().map(|c| c.unconfirmed.len()).sum(),
+        retry_queue: retry_queue().len(),
+    })
+}
+
+/// Transactions waiting in the retry queue
+pub fn queued_retries() -> Vec<QueuedRetry> {
+    let now = Instant::now();
+    retry_queue()
+        .into_iter()
+        .map(|entry| QueuedRetry {
+            id: entry.tx.id(),
+            chain_id: entry.tx.chain_id,
+            attempts: entry.attempts,
+            retry_in_secs: entry.next_attempt.saturating_duration_since(now).as_secs(),
+            last_error: entry.last_error,
+        })
+        .collect()
+}
+
+async fn queue() -> impl Responder {
+    let status = get_status();
+    HttpResponse::Ok().json(AdminQueue {
+        retries: queued_retries(),
+        in_flight: status.in_flight,
+        unconfirmed: status
+            .chains
+            .into_iter()
+            .map(|(chain_id, chain)| (chain_id, chain.unconfirmed))
+            .collect(),
+    })
+}
+
+/// Body of `/admin/banned`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdminBanList {
+    pub banned: Vec<BannedUser>,
+    /// strikes of users who aren't banned yet, by address
+    pub strikes: HashMap<String, usize>,
+}
+
+async fn banned() -> impl Responder {
+    HttpResponse::Ok().json(AdminBanList {
+        banned: banned_users(),
+        strikes: user_strikes()
+            .into_iter()
+            .map(|(user, strikes)| (user.to_string(), strikes))
+            .collect(),
+    })
+}
+
+/// Query of `/admin/banned/{address}`
+#[derive(Debug, Clone, Deserialize)]
+pub struct BanQuery {
+    pub reason: Option<String>,
+}
+
+async fn ban_user(address: web::Path<String>, query: web::Query<BanQuery>) -> impl Responder {
+    let Ok(user) = Address::from_str(&address) else {
+        return HttpResponse::BadRequest().body(format!("Invalid address {address}"));
+    };
+    let reason = query
+        .into_inner()
+        .reason
+        .unwrap_or_else(|| "banned by an operator".to_string());
+    ban(user, reason);
+    HttpResponse::Ok().body(format!("Banned {user}"))
+}
+
+async fn unban_user(address: web::Path<String>) -> impl Responder {
+    let Ok(user) = Address::from_str(&address) else {
+        return HttpResponse::BadRequest().body(format!("Invalid address {address}"));
+    };
+    if unban(user) {
+        HttpResponse::Ok().body(format!("Unbanned {user}"))
+    } else {
+        HttpResponse::NotFound().body(format!("{user} is not banned"))
+    }
+}
+
+/// Query of `/admin/export`, dates are `YYYY-MM-DD` or unix timestamps
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExportQuery {
+    pub report: Option<ExportReport>,
+    pub format: Option<ExportFormat>,
+    pub since: Option<String>,
+    pub until: Option<String>,
+}
+
+async fn export(query: web::Query<ExportQuery>) -> impl Responder {
+    let parse = |date: &Option<String>| date.as_deref().map(parse_date).transpose();
+    let (since, until) = match (parse(&query.since), parse(&query.until)) {
+        (Ok(since), Ok(until)) => (since, until),
+        (Err(e), _) | (_, Err(e)) => return HttpResponse::BadRequest().body(e),
+    };
+    let format = query.format.unwrap_or(ExportFormat::Json);
+    match export_history(
+        since,
+        until,
+        query.report.unwrap_or(ExportReport::Relays),
+        format,
+    ) {
+        Ok(body) => HttpResponse::Ok()
+            .content_type(match format {
+                ExportFormat::Csv => "text/csv",
+                ExportFormat::Json => "application/json",
+            })
+            .body(body),
+        Err(e) => HttpResponse::NotFound().body(e),
+    }
+}
+
+/// Starts the admin server on `bind` in the background. It has no authentication so it must only be reachable
+/// by operators, binding to a loopback address is strongly recommended.
+pub fn start_admin_server(bind: &str) -> std::io::Result<()> {
+    LazyLock::force(&STARTED);
+    let server = HttpServer::new(|| {
+        App::new()
+            .route("/admin/pause", web::post().to(pause))
+            .route("/admin/resume", web::post().to(resume))
+            .route("/admin/reload", web::post().to(reload))
+            .route("/admin/code/confirm", web::post().to(confirm_code))
+            .route("/admin/stats", web::get().to(stats))
+            .route("/admin/queue", web::get().to(queue))
+            .route("/admin/export", web::get().to(export))
+            .route("/admin/banned", web::get().to(banned))
+            .route("/admin/banned/{address}", web::post().to(ban_user))
+            .route("/admin/banned/{address}", web::delete().to(unban_user))
+    })
+    .workers(http_workers())
+    .disable_signals()
+    .bind(bind)?
+    .run();
+    info!("Admin server listening on {bind}");
+    actix_rt::spawn(server);
+    Ok(())
+}
@@ -0,0 +1,357 @@
+//! Alerts pushed to Slack, Discord or Telegram on events an operator has to act on: a relayer account running
+//! low on gas, relays failing over and over, an orchestrator that stays unreachable, runs of relays that cost more
+//! than they earned, tips that never arrived, gas spending that reached its cap and contract code that changed. Conditions that persist are only alerted on once, and again
+//! once they have cleared.
+//! Alerts are sent in the background so a slow or failing sink never holds up relaying.
+
+use crate::error::RelayerError;
+use crate::orchestrator::GaslessTransaction;
+use crate::payout::MissingTip;
+use crate::proxy::http_client;
+use crate::tokens::display_token;
+use crate::units::display_ether;
+use clarity::utils::display_uint256_as_address;
+use clarity::{Address, Uint256};
+use log::warn;
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+/// Where alerts are sent
+#[derive(Debug, Clone)]
+pub enum AlertSink {
+    /// Slack incoming webhook url
+    Slack(String),
+    /// Discord webhook url
+    Discord(String),
+    Telegram {
+        bot_token: String,
+        chat_id: String,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct AlertPolicy {
+    pub sinks: Vec<AlertSink>,
+    /// relayer accounts holding less than this many wei of the gas token are alerted on
+    pub min_balance: Option<Uint256>,
+    /// consecutive failed relays that are alerted on, 0 to never alert on failures
+    pub failure_threshold: u32,
+    /// consecutive final relays that lost money that are alerted on, 0 to never alert on losses
+    pub loss_threshold: u32,
+    /// how long an orchestrator has to be unreachable before it is alerted on
+    pub orchestrator_down_after: Duration,
+    /// timeout of each request to a sink
+    pub timeout: Duration,
+}
+
+#[derive(Debug, Default)]
+struct AlertState {
+    consecutive_failures: u32,
+    /// final relays in a row that paid more for gas than their tip was worth
+    consecutive_losses: u32,
+    /// accounts currently alerted on for a low balance, by (chain id, address)
+    low_balances: HashSet<(u64, Address)>,
+    /// when each failing orchestrator was first seen failing, and whether it has been alerted on
+    unreachable: HashMap<String, (Instant, bool)>,
+}
+
+static POLICY: LazyLock<Mutex<Option<AlertPolicy>>> = LazyLock::new(|| Mutex::new(None));
+
+static STATE: LazyLock<Mutex<AlertState>> = LazyLock::new(|| Mutex::new(AlertState::default()));
+
+/// Alerts currently being sent
+static SENDING: AtomicUsize = AtomicUsize::new(0);
+
+pub fn set_alert_policy(policy: Option<AlertPolicy>) {
+    *POLICY.lock().unwrap() = policy;
+}
+
+fn alert_policy() -> Option<AlertPolicy> {
+    POLICY.lock().unwrap().clone()
+}
+
+/// Sends `message` to every configured sink
+fn send_alert(policy: &AlertPolicy, message: String) {
+    warn!("Alert: {message}");
+    for sink in policy.sinks.clone() {
+        let message = message.clone();
+        let timeout = policy.timeout;
+        SENDING.fetch_add(1, Ordering::SeqCst);
+        actix_rt::spawn(async move {
+            let client = http_client(None, timeout);
+            let (name, request) = match &sink {
+                AlertSink::Slack(url) => (
+                    "Slack",
+                    client.post(url).send_json(&json!({ "text": message })),
+                ),
+                AlertSink::Discord(url) => (
+                    "Discord",
+                    client.post(url).send_json(&json!({ "content": message })),
+                ),
+                AlertSink::Telegram { bot_token, chat_id } => (
+                    "Telegram",
+                    client
+                        .post(format!(
+                            "https://api.telegram.org/bot{bot_token}/sendMessage"
+                        ))
+                        .send_json(&json!({ "chat_id": chat_id, "text": message })),
+                ),
+            };
+            match request.await {
+                Ok(response) if response.status().is_success() => {}
+                Ok(response) => warn!("{name} rejected an alert: {}", response.status()),
+                Err(e) => warn!("Failed to send an alert to {name}: {e}"),
+            }
+            SENDING.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+}
+
+/// Waits up to `timeout` for alerts still being sent, so that alerts raised just before exiting are delivered
+pub async fn flush_alerts(timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+    while SENDING.load(Ordering::SeqCst) > 0 && Instant::now() < deadline {
+        actix_rt::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+/// Alerts once the balance of `address` on `chain` drops below the minimum, and once it has been topped up
+pub fn alert_on_balance(chain: &str, chain_id: u64, address: Address, balance: Uint256) {
+    let Some(policy) = alert_policy() else {
+        return;
+    };
+    let Some(min_balance) = policy.min_balance else {
+        return;
+    };
+    let mut state = STATE.lock().unwrap();
+    if balance < min_balance {
+        if state.low_balances.insert((chain_id, address)) {
+            send_alert(
+                &policy,
+                format!(
+                    "Relayer account {address} on {chain} is low on gas: {} ALTHEA left, below the minimum of {}",
+                    display_ether(balance),
+                    display_ether(min_balance)
+                ),
+            );
+        }
+    } else if state.low_balances.remove(&(chain_id, address)) {
+        send_alert(
+            &policy,
+            format!(
+                "Relayer account {address} on {chain} was topped up to {} ALTHEA",
+                display_ether(balance)
+            ),
+        );
+    }
+}
+
+/// Counts a failed relay, alerting once `failure_threshold` relays in a row have failed
+pub fn alert_on_failure(e: &RelayerError) {
+    let Some(policy) = alert_policy() else {
+        return;
+    };
+    let mut state = STATE.lock().unwrap();
+    state.consecutive_failures += 1;
+    if policy.failure_threshold > 0 && state.consecutive_failures == policy.failure_threshold {
+        send_alert(
+            &policy,
+            format!(
+                "{} relays in a row have failed, the last one with: {e}",
+                state.consecutive_failures
+            ),
+        );
+    }
+}
+
+/// Resets the count of failed relays once a relay is included
+pub fn reset_failure_alert() {
+    let Some(policy) = alert_policy() else {
+        return;
+    };
+    let mut state = STATE.lock().unwrap();
+    if policy.failure_threshold > 0 && state.consecutive_failures >= policy.failure_threshold {
+        send_alert(
+            &policy,
+            format!(
+                "Relays are succeeding again after {} failures in a row",
+                state.consecutive_failures
+            ),
+        );
+    }
+    state.consecutive_failures = 0;
+}
+
+/// Records a poll of the orchestrator at `url`, alerting once it has been unreachable for
+/// `orchestrator_down_after` and once it is reachable again
+pub fn alert_on_poll(url: &str, success: bool) {
+    let Some(policy) = alert_policy() else {
+        return;
+    };
+    let mut state = STATE.lock().unwrap();
+    if success {
+        if let Some((since, true)) = state.unreachable.remove(url) {
+            send_alert(
+                &policy,
+                format!(
+                    "Orchestrator {url} is reachable again after {} minutes",
+                    since.elapsed().as_secs() / 60
+                ),
+            );
+        }
+        return;
+    }
+    let (since, alerted) = state
+        .unreachable
+        .entry(url.to_string())
+        .or_insert((Instant::now(), false));
+    if !*alerted && since.elapsed() >= policy.orchestrator_down_after {
+        *alerted = true;
+        send_alert(
+            &policy,
+            format!(
+                "Orchestrator {url} has been unreachable for {} minutes",
+                since.elapsed().as_secs() / 60
+            ),
+        );
+    }
+}
+
+/// Alerts that the gas spent within `period` reached its cap and relaying paused, or with `None` that it resumed
+pub fn alert_on_spend_cap(reached: Option<(&str, Uint256, Uint256)>) {
+    let Some(policy) = alert_policy() else {
+        return;
+    };
+    let message = match reached {
+        Some((period, spent, cap)) => format!(
+            "Relaying paused: spent {} ALTHEA on gas within the last {period}, reaching the cap of {} ALTHEA",
+            display_ether(spent),
+            display_ether(cap)
+        ),
+        None => "Relaying resumed, gas spending is below its caps again".to_string(),
+    };
+    send_alert(&policy, message);
+}
+
+/// Alerts that the code of `contract` changed from the confirmed hash to a new one and relaying paused, or with
+/// `None` that it is back to the confirmed code
+pub fn alert_on_code_change(chain: &str, contract: Address, change: Option<([u8; 32], [u8; 32])>) {
+    let Some(policy) = alert_policy() else {
+        return;
+    };
+    let message = match change {
+        Some((confirmed, current)) => format!(
+            "Relaying paused: code of {contract} on {chain} changed from hash 0x{} to 0x{}, review the configuration and confirm it with POST /admin/code/confirm",
+            hex::encode(confirmed),
+            hex::encode(current)
+        ),
+        None => format!("Code of {contract} on {chain} is back to the confirmed hash"),
+    };
+    send_alert(&policy, message);
+}
+
+/// Alerts that subsidies committed within the last day passed `percent` of the daily `budget`, or with less than
+/// 100 after the budget was used up that it has room again
+pub fn alert_on_subsidy(percent: u32, committed: Uint256, budget: Uint256) {
+    let Some(policy) = alert_policy() else {
+        return;
+    };
+    let message = if percent >= 100 {
+        format!(
+            "Subsidy budget used up: {} of {} ALTHEA committed within the last day, only relaying transactions that pay for themselves",
+            display_ether(committed),
+            display_ether(budget)
+        )
+    } else {
+        format!(
+            "{percent}% of the subsidy budget used: {} of {} ALTHEA committed within the last day",
+            display_ether(committed),
+            display_ether(budget)
+        )
+    };
+    send_alert(&policy, message);
+}
+
+/// Counts the outcome of the final relay of `tx`, alerting once `loss_threshold` relays in a row paid more for gas
+/// than their tip was worth, and once a relay is profitable again
+pub fn alert_on_loss(
+    chain: &str,
+    tx: &GaslessTransaction,
+    tx_hash: Uint256,
+    gas_paid: Uint256,
+    tip_value: Uint256,
+) {
+    let Some(policy) = alert_policy() else {
+        return;
+    };
+    let mut state = STATE.lock().unwrap();
+    if gas_paid <= tip_value {
+        if policy.loss_threshold > 0 && state.consecutive_losses >= policy.loss_threshold {
+            send_alert(
+                &policy,
+                format!(
+                    "Relays are profitable again after {} lost money in a row",
+                    state.consecutive_losses
+                ),
+            );
+        }
+        state.consecutive_losses = 0;
+        return;
+    }
+    state.consecutive_losses += 1;
+    if policy.loss_threshold > 0 && state.consecutive_losses == policy.loss_threshold {
+        send_alert(
+            &policy,
+            format!(
+                "{} relays in a row have lost money, the last, {} of {} on {chain}, paid {} ALTHEA in gas for a tip worth {} ALTHEA",
+                state.consecutive_losses,
+                display_uint256_as_address(tx_hash),
+                tx.id(),
+                display_ether(gas_paid),
+                display_ether(tip_value)
+            ),
+        );
+    }
+}
+
+/// Alerts that the final relay `tx_hash` of `tx` was owed a tip its receipt doesn't show being paid
+pub fn alert_on_missing_tip(
+    chain: &str,
+    tx: &GaslessTransaction,
+    tx_hash: Uint256,
+    missing: &MissingTip,
+) {
+    let Some(policy) = alert_policy() else {
+        return;
+    };
+    send_alert(
+        &policy,
+        format!(
+            "Relay {} of {} on {chain} was owed {} of token {} at {} but its receipt only transferred {}, the tip never arrived",
+            display_uint256_as_address(tx_hash),
+            tx.id(),
+            missing.expected,
+            display_token(tx.chain_id, missing.token),
+            missing.recipient,
+            missing.received
+        ),
+    );
+}
+
+/// Alerts that a relay reverted on chain, paying `gas_paid` for nothing
+pub fn alert_on_revert(chain: &str, tx_hash: Uint256, gas_paid: Uint256, reason: &str) {
+    let Some(policy) = alert_policy() else {
+        return;
+    };
+    send_alert(
+        &policy,
+        format!(
+            "Relay {} on {chain} reverted and lost {} ALTHEA in gas: {reason}",
+            display_uint256_as_address(tx_hash),
+            display_ether(gas_paid)
+        ),
+    );
+}
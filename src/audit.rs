@@ -0,0 +1,404 @@
+//! Append-only audit log of relay decisions, so operators can later prove to a user or partner why a transaction
+//! was or wasn't relayed. Every decision is written as a JSON line along with the inputs it was made on: the gas
+//! estimate and price, what the tip was worth and the verdict of the profit policy. Each entry carries the hash of
+//! the one before it, so no entry can be changed, dropped or reordered without breaking every hash after it.
+//!
+//! Once per cycle, and on shutdown, the relayer key signs the hash of the latest entry in a seal line. A seal vouches
+//! for that entry and, through the chain, for everything written before it. The `verify-audit` subcommand checks
+//! the chain and recovers the signer of every seal.
+
+use crate::orchestrator::GaslessTransaction;
+use crate::profit::TipValuation;
+use crate::relay::RelayContext;
+use crate::signer::Signer;
+use crate::skip::SkipReason;
+use crate::status::unix_timestamp;
+use clarity::abi::get_hash;
+use clarity::utils::{display_uint256_as_address, get_ethereum_msg_hash};
+use clarity::{Address, Signature, Uint256};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LazyLock, Mutex};
+
+/// Most transactions whose decision inputs are kept until their decision is written
+const MAX_PENDING_INPUTS: usize = 100_000;
+
+/// What a decision was based on, amounts are decimal strings in wei of the gas token unless noted
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct AuditInputs {
+    /// gas the relay was estimated to use, or the least its command is known to use if it wasn't estimated
+    pub gas_estimate: Option<String>,
+    pub gas_price: Option<String>,
+    /// gas price the tip had to cover, allowing for the price rising before inclusion
+    pub required_gas_price: Option<String>,
+    /// tip in base units of `tip_token`
+    pub tip_amount: Option<String>,
+    pub tip_token: Option<String>,
+    pub tip_value: Option<String>,
+    /// tip value less the cost of selling it for the gas token
+    pub tip_net_value: Option<String>,
+    /// `profitable`, `subsidized`, `sponsored`, `subsidy_exhausted`, `unprofitable` or `predicted_unprofitable`
+    pub verdict: Option<String>,
+}
+
+impl AuditInputs {
+    pub fn new(
+        gas_estimate: Uint256,
+        gas_price: Uint256,
+        required_gas_price: Uint256,
+        tip: &TipValuation,
+        verdict: &str,
+    ) -> AuditInputs {
+        AuditInputs {
+            gas_estimate: Some(gas_estimate.to_string()),
+            gas_price: Some(gas_price.to_string()),
+            required_gas_price: Some(required_gas_price.to_string()),
+            tip_amount: Some(tip.amount.to_string()),
+            tip_token: Some(tip.token.to_string()),
+            tip_value: Some(tip.value.to_string()),
+            tip_net_value: Some(tip.net_value().to_string()),
+            verdict: Some(verdict.to_string()),
+        }
+    }
+}
+
+/// One decision about a transaction
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// position in the log, counting from zero
+    pub seq: u64,
+    /// unix timestamp of the decision
+    pub timestamp: u64,
+    pub chain_id: u64,
+    /// id of the gasless transaction
+    pub sig_hash: String,
+    /// address that signed the gasless transaction
+    pub user: Option<String>,
+    /// `accepted`, `submitted`, `skipped`, `already_relayed` or `failed`
+    pub decision: String,
+    pub skip_reason: Option<String>,
+    pub error: Option<String>,
+    /// hash of our relay transaction
+    pub tx_hash: Option<String>,
+    pub inputs: Option<AuditInputs>,
+    /// `hash` of the entry before, zero for the first one
+    pub prev_hash: String,
+    /// keccak256 of this entry serialized with an empty `hash`
+    pub hash: String,
+}
+
+impl AuditEntry {
+    fn compute_hash(&self) -> [u8; 32] {
+        let unhashed = AuditEntry {
+            hash: String::new(),
+            ..self.clone()
+        };
+        get_hash(&serde_json::to_vec(&unhashed).unwrap())
+    }
+}
+
+/// Signature by a relayer key of the hash of entry `seq`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditSeal {
+    pub seq: u64,
+    pub timestamp: u64,
+    pub hash: String,
+    pub signer: String,
+    /// Ethereum signed message signature of the 32 bytes of `hash`
+    pub signature: String,
+}
+
+/// A line of the audit log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AuditLine {
+    Entry(Box<AuditEntry>),
+    Seal(AuditSeal),
+}
+
+struct AuditLog {
+    file: File,
+    /// sequence number of the next entry
+    seq: u64,
+    /// hash of the latest entry
+    head: [u8; 32],
+    /// sequence number of the latest sealed entry
+    sealed: Option<u64>,
+}
+
+static LOG: LazyLock<Mutex<Option<AuditLog>>> = LazyLock::new(|| Mutex::new(None));
+
+/// Inputs of the decision about each transaction, by signature hash, until the decision is written
+static PENDING_INPUTS: LazyLock<Mutex<HashMap<[u8; 32], AuditInputs>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Whether a seal couldn't be signed yet, so that it is only warned about once
+static SEAL_FAILED: AtomicBool = AtomicBool::new(false);
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn parse_hash(hash: &str) -> Option<[u8; 32]> {
+    hex::decode(hash.trim_start_matches("0x"))
+        .ok()?
+        .try_into()
+        .ok()
+}
+
+/// Opens the audit log at `path`, creating it if it doesn't exist. The chain already in the file is verified and
+/// entries are appended after its last one. A final line left partly written by a crash is cut off first.
+pub fn open_audit_log(path: &Path) -> io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let verified = verify_chain(BufReader::new(File::open(path)?))
+        .map_err(|e| invalid(format!("Invalid audit log: {e}")))?;
+    if verified.summary.torn_line {
+        warn!(
+            "Audit log {} ends in a partly written line, cutting it off",
+            path.display()
+        );
+        file.set_len(verified.complete_len)?;
+    }
+    *LOG.lock().unwrap() = Some(AuditLog {
+        file,
+        seq: verified.summary.entries,
+        head: verified.head,
+        sealed: verified.summary.last_sealed,
+    });
+    Ok(())
+}
+
+fn append(file: &mut File, line: &AuditLine) -> io::Result<()> {
+    let mut bytes = serde_json::to_vec(line).unwrap();
+    bytes.push(b'\n');
+    file.write_all(&bytes)
+}
+
+/// Keeps what the decision about `tx` is being made on, to be written along with it
+pub fn record_audit_inputs(tx: &GaslessTransaction, inputs: AuditInputs) {
+    if LOG.lock().unwrap().is_none() {
+        return;
+    }
+    let mut pending = PENDING_INPUTS.lock().unwrap();
+    if pending.len() >= MAX_PENDING_INPUTS {
+        pending.clear();
+    }
+    pending.insert(tx.sig_hash(), inputs);
+}
+
+/// Inputs of the decision about `tx` without taking them, for decisions that aren't final
+pub fn audit_inputs(tx: &GaslessTransaction) -> Option<AuditInputs> {
+    PENDING_INPUTS.lock().unwrap().get(&tx.sig_hash()).cloned()
+}
+
+/// Takes the inputs of the decision about `tx`, called once its final decision is made
+pub fn take_audit_inputs(tx: &GaslessTransaction) -> Option<AuditInputs> {
+    PENDING_INPUTS.lock().unwrap().remove(&tx.sig_hash())
+}
+
+/// Appends `decision` about `tx` to the audit log if one is open
+pub fn record_decision(
+    ctx: &RelayContext,
+    tx: &GaslessTransaction,
+    decision: &str,
+    skip_reason: Option<SkipReason>,
+    error: Option<String>,
+    tx_hash: Option<Uint256>,
+    inputs: Option<AuditInputs>,
+) {
+    let mut log = LOG.lock().unwrap();
+    let Some(log) = log.as_mut() else {
+        return;
+    };
+    let mut entry = AuditEntry {
+        seq: log.seq,
+        timestamp: unix_timestamp(),
+        chain_id: ctx.chain_id,
+        sig_hash: tx.id(),
+        user: tx
+            .client(ctx.contract_address)
+            .ok()
+            .map(|user| user.to_string()),
+        decision: decision.to_string(),
+        skip_reason: skip_reason.map(|reason| reason.to_string()),
+        error,
+        tx_hash: tx_hash.map(display_uint256_as_address),
+        inputs,
+        prev_hash: format!("0x{}", hex::encode(log.head)),
+        hash: String::new(),
+    };
+    let hash = entry.compute_hash();
+    entry.hash = format!("0x{}", hex::encode(hash));
+    if let Err(e) = append(&mut log.file, &AuditLine::Entry(Box::new(entry))) {
+        warn!(
+            "Failed to write decision about {} to the audit log: {e}",
+            tx.id()
+        );
+        return;
+    }
+    log.seq += 1;
+    log.head = hash;
+}
+
+/// Signs the latest entry of the audit log with `signer` unless it is already sealed, called once per cycle and on
+/// shutdown
+pub async fn seal_audit_log(signer: &Signer) {
+    let (seq, head) = {
+        let log = LOG.lock().unwrap();
+        let Some(log) = log.as_ref() else {
+            return;
+        };
+        match log.seq.checked_sub(1) {
+            Some(seq) if log.sealed != Some(seq) => (seq, log.head),
+            _ => return,
+        }
+    };
+    let signature = match signer.sign_message(&head).await {
+        Ok(signature) => signature,
+        Err(e) => {
+            if !SEAL_FAILED.swap(true, Ordering::Relaxed) {
+                warn!("Audit log entries are left unsealed, failed to sign them: {e}");
+            }
+            return;
+        }
+    };
+    SEAL_FAILED.store(false, Ordering::Relaxed);
+    let seal = AuditSeal {
+        seq,
+        timestamp: unix_timestamp(),
+        hash: format!("0x{}", hex::encode(head)),
+        signer: signer.address().to_string(),
+        signature: format!("0x{}", hex::encode(signature.to_bytes())),
+    };
+    let mut log = LOG.lock().unwrap();
+    let Some(log) = log.as_mut() else {
+        return;
+    };
+    match append(&mut log.file, &AuditLine::Seal(seal)).and_then(|_| log.file.sync_data()) {
+        Ok(()) => log.sealed = Some(log.sealed.map_or(seq, |sealed| sealed.max(seq))),
+        Err(e) => warn!("Failed to seal the audit log: {e}"),
+    }
+}
+
+/// What an intact audit log holds
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AuditSummary {
+    pub entries: u64,
+    pub seals: u64,
+    /// addresses that signed seals
+    pub signers: BTreeSet<Address>,
+    /// sequence number of the latest sealed entry
+    pub last_sealed: Option<u64>,
+    /// entries after the latest sealed one, which no signature vouches for yet
+    pub unsealed: u64,
+    /// whether the final line was only partly written, as after a crash, it is left out of the chain
+    pub torn_line: bool,
+}
+
+/// An audit log whose chain was verified
+struct VerifiedLog {
+    summary: AuditSummary,
+    /// hash of the latest entry
+    head: [u8; 32],
+    /// bytes up to the end of the last complete line
+    complete_len: u64,
+}
+
+/// Checks that every entry of the audit log at `path` follows on from the one before it, and that every seal is a
+/// valid signature of the entry it names
+pub fn verify_audit_log(path: &Path) -> Result<AuditSummary, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {}: {e}", path.display()))?;
+    Ok(verify_chain(BufReader::new(file))?.summary)
+}
+
+/// Verifies the audit log read from `reader` as described on `verify_audit_log`. Every line written is terminated,
+/// so an unterminated final line was cut short by a crash and is left out.
+fn verify_chain(mut reader: impl BufRead) -> Result<VerifiedLog, String> {
+    let mut summary = AuditSummary::default();
+    let mut hashes = Vec::new();
+    let mut head = [0u8; 32];
+    let mut complete_len = 0;
+    let mut buf = Vec::new();
+    for number in 1.. {
+        buf.clear();
+        let read = reader
+            .read_until(b'\n', &mut buf)
+            .map_err(|e| format!("Failed to read line {number}: {e}"))?;
+        if read == 0 {
+            break;
+        }
+        if buf.last() != Some(&b'\n') {
+            summary.torn_line = true;
+            break;
+        }
+        complete_len += read as u64;
+        let fail = |message: String| format!("Line {number}: {message}");
+        let line = std::str::from_utf8(&buf).map_err(|e| fail(e.to_string()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(line).map_err(|e| fail(e.to_string()))? {
+            AuditLine::Entry(entry) => {
+                if entry.seq != summary.entries {
+                    return Err(fail(format!(
+                        "expected entry {}, found entry {}",
+                        summary.entries, entry.seq
+                    )));
+                }
+                if parse_hash(&entry.prev_hash) != Some(head) {
+                    return Err(fail(format!(
+                        "entry {} doesn't follow on from the entry before it",
+                        entry.seq
+                    )));
+                }
+                let hash = entry.compute_hash();
+                if parse_hash(&entry.hash) != Some(hash) {
+                    return Err(fail(format!("entry {} was modified", entry.seq)));
+                }
+                head = hash;
+                hashes.push(hash);
+                summary.entries += 1;
+            }
+            AuditLine::Seal(seal) => {
+                let hash = hashes
+                    .get(seal.seq as usize)
+                    .ok_or_else(|| fail(format!("seal of missing entry {}", seal.seq)))?;
+                if parse_hash(&seal.hash) != Some(*hash) {
+                    return Err(fail(format!(
+                        "seal of entry {} has its hash wrong",
+                        seal.seq
+                    )));
+                }
+                let signer = hex::decode(seal.signature.trim_start_matches("0x"))
+                    .map_err(|e| e.to_string())
+                    .and_then(|signature| {
+                        Signature::from_bytes(&signature)
+                            .and_then(|signature| signature.recover(&get_ethereum_msg_hash(hash)))
+                            .map_err(|e| e.to_string())
+                    })
+                    .map_err(|e| fail(format!("seal of entry {} is invalid: {e}", seal.seq)))?;
+                if signer.to_string() != seal.signer {
+                    return Err(fail(format!(
+                        "seal of entry {} was signed by {signer} instead of {}",
+                        seal.seq, seal.signer
+                    )));
+                }
+                summary.signers.insert(signer);
+                summary.seals += 1;
+                summary.last_sealed =
+                    Some(summary.last_sealed.map_or(seal.seq, |s| s.max(seal.seq)));
+            }
+        }
+    }
+    summary.unsealed = summary.entries - summary.last_sealed.map_or(0, |seq| seq + 1);
+    Ok(VerifiedLog {
+        summary,
+        head,
+        complete_len,
+    })
+}
@@ -0,0 +1,87 @@
+//! Retries of idempotent HTTP requests to orchestrators and the price API. A single 502 from a load balancer or a
+//! dropped connection shouldn't cost a whole cycle, so GETs that fail in a way that is likely to go away are sent
+//! again after a short exponential backoff with full jitter, up to a bounded number of times. Requests with side
+//! effects, such as claims, are never retried here.
+
+use crate::error::RelayerError;
+use crate::shutdown::sleep_or_shutdown;
+use crate::status::record_http_retry;
+use crate::throttle::Outbound;
+use awc::ClientResponse;
+use awc::http::StatusCode;
+use log::debug;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+/// Backoff between retries is never allowed to grow beyond this
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy)]
+pub struct BackoffPolicy {
+    /// times a failed request is sent again, 0 disables retries
+    pub retries: u32,
+    /// backoff before the first retry, doubling with each one after it
+    pub base_delay: Duration,
+}
+
+static POLICIES: LazyLock<Mutex<HashMap<Outbound, BackoffPolicy>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+pub fn set_backoff_policy(service: Outbound, policy: Option<BackoffPolicy>) {
+    let mut policies = POLICIES.lock().unwrap();
+    match policy {
+        Some(policy) => policies.insert(service, policy),
+        None => policies.remove(&service),
+    };
+}
+
+/// Responses worth asking again for, the server or something in front of it is overloaded or briefly down
+fn is_transient(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS
+        || status == StatusCode::REQUEST_TIMEOUT
+        || (status.is_server_error() && status != StatusCode::NOT_IMPLEMENTED)
+}
+
+/// Sends an idempotent request to `url` of `service` with `send`, which builds and sends it anew each time, and
+/// sends it again while it fails with an error or a transient status and retries are left. The last response or
+/// error is returned.
+pub async fn retry_get<S, F, Fut>(
+    service: Outbound,
+    url: &str,
+    mut send: F,
+) -> Result<ClientResponse<S>, RelayerError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<ClientResponse<S>, RelayerError>>,
+{
+    let policy = POLICIES.lock().unwrap().get(&service).copied();
+    let mut attempt = 0;
+    loop {
+        let result = send().await;
+        let failure = match &result {
+            Ok(response) if is_transient(response.status()) => response.status().to_string(),
+            Ok(_) => return result,
+            Err(e) => e.to_string(),
+        };
+        let Some(policy) = policy.filter(|policy| attempt < policy.retries) else {
+            return result;
+        };
+        attempt += 1;
+        let backoff = policy
+            .base_delay
+            .saturating_mul(1 << (attempt - 1).min(16))
+            .min(MAX_BACKOFF);
+        let delay = backoff.mul_f64(rand::random::<f64>());
+        debug!(
+            "GET {url} failed with {failure}, retrying in {}ms ({attempt}/{})",
+            delay.as_millis(),
+            policy.retries
+        );
+        record_http_retry(service);
+        if sleep_or_shutdown(delay).await {
+            return result;
+        }
+    }
+}
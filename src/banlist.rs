@@ -0,0 +1,173 @@
+//! Ban list of users, by the address recovered from the signature of their transactions. Users whose transactions
+//! keep reverting through their own fault or carry malformed tips are banned automatically after a configurable number of strikes, each
+//! distinct transaction counting once, and operators can ban and unban users through the admin API. Transactions
+//! of banned users are skipped before simulation. The list is kept in a JSON file so bans survive restarts.
+
+use crate::bech32::display_user;
+use crate::orchestrator::GaslessTransaction;
+use crate::status::unix_timestamp;
+use clarity::Address;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BannedUser {
+    pub address: Address,
+    pub reason: String,
+    /// unix timestamp of the ban
+    pub banned_at: u64,
+    /// banned by an operator rather than for strikes
+    pub manual: bool,
+}
+
+#[derive(Debug, Default)]
+struct BanList {
+    banned: HashMap<Address, BannedUser>,
+    /// signature hashes of the transactions each user got a strike for
+    strikes: HashMap<Address, HashSet<[u8; 32]>>,
+    /// strikes after which a user is banned, None to never ban automatically
+    max_strikes: Option<u32>,
+    store: Option<PathBuf>,
+}
+
+static BAN_LIST: LazyLock<Mutex<BanList>> = LazyLock::new(|| Mutex::new(BanList::default()));
+
+/// Bans users after `max_strikes` of their transactions reverted or were malformed, None never bans automatically
+pub fn set_max_strikes(max_strikes: Option<u32>) {
+    BAN_LIST.lock().unwrap().max_strikes = max_strikes.filter(|max| *max > 0);
+}
+
+/// Loads the bans kept in `path` by a previous run and writes every change back to it, a missing file means there
+/// are none yet
+pub fn open_ban_list(path: &Path) -> Result<(), String> {
+    let banned: Vec<BannedUser> = match std::fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents).map_err(|e| e.to_string())?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e.to_string()),
+    };
+    info!(
+        "Loaded {} banned users from {}",
+        banned.len(),
+        path.display()
+    );
+    let mut list = BAN_LIST.lock().unwrap();
+    list.banned = banned
+        .into_iter()
+        .map(|user| (user.address, user))
+        .collect();
+    list.store = Some(path.to_path_buf());
+    Ok(())
+}
+
+/// Rewrites the ban list file if one is configured, a failed write is logged since relaying must carry on
+fn persist(list: &BanList) {
+    let Some(path) = &list.store else {
+        return;
+    };
+    let mut banned: Vec<&BannedUser> = list.banned.values().collect();
+    banned.sort_by_key(|user| user.banned_at);
+    let contents = serde_json::to_string_pretty(&banned).expect("Bans always serialize");
+    // written to a temporary file first so a crash mid write never leaves a truncated list
+    let tmp = path.with_extension("tmp");
+    if let Err(e) = std::fs::write(&tmp, contents).and_then(|_| std::fs::rename(&tmp, path)) {
+        warn!("Failed to write ban list {}: {e}", path.display());
+    }
+}
+
+/// True if users need to be recovered from transactions, because someone is banned or may be
+pub fn bans_enabled() -> bool {
+    let list = BAN_LIST.lock().unwrap();
+    list.max_strikes.is_some() || !list.banned.is_empty()
+}
+
+pub fn is_banned(user: Address) -> bool {
+    BAN_LIST.lock().unwrap().banned.contains_key(&user)
+}
+
+/// Counts a strike against `user` for `tx`, banning them once they have `max_strikes`
+pub fn record_strike(user: Address, tx: &GaslessTransaction, reason: &str) {
+    let mut list = BAN_LIST.lock().unwrap();
+    let Some(max_strikes) = list.max_strikes else {
+        return;
+    };
+    if list.banned.contains_key(&user) {
+        return;
+    }
+    let strikes = list.strikes.entry(user).or_default();
+    strikes.insert(tx.sig_hash());
+    let count = strikes.len();
+    if count < max_strikes as usize {
+        return;
+    }
+    warn!(
+        user:% = user;
+        "Banning user {} after {count} of their transactions failed, the last with: {reason}",
+        display_user(user)
+    );
+    list.strikes.remove(&user);
+    list.banned.insert(
+        user,
+        BannedUser {
+            address: user,
+            reason: format!("{count} strikes, the last for: {reason}"),
+            banned_at: unix_timestamp(),
+            manual: false,
+        },
+    );
+    persist(&list);
+}
+
+/// Forgets the strikes of `user`, once one of their transactions has been relayed
+pub fn clear_strikes(user: Address) {
+    BAN_LIST.lock().unwrap().strikes.remove(&user);
+}
+
+/// Bans `user` at an operator's request
+pub fn ban(user: Address, reason: String) {
+    info!("Banning user {user} by admin request: {reason}");
+    let mut list = BAN_LIST.lock().unwrap();
+    list.strikes.remove(&user);
+    list.banned.insert(
+        user,
+        BannedUser {
+            address: user,
+            reason,
+            banned_at: unix_timestamp(),
+            manual: true,
+        },
+    );
+    persist(&list);
+}
+
+/// Lifts the ban of `user` and forgets their strikes, false if they weren't banned
+pub fn unban(user: Address) -> bool {
+    let mut list = BAN_LIST.lock().unwrap();
+    list.strikes.remove(&user);
+    let removed = list.banned.remove(&user).is_some();
+    if removed {
+        info!("Unbanned user {user} by admin request");
+        persist(&list);
+    }
+    removed
+}
+
+/// Every banned user, longest banned first
+pub fn banned_users() -> Vec<BannedUser> {
+    let mut banned: Vec<BannedUser> = BAN_LIST.lock().unwrap().banned.values().cloned().collect();
+    banned.sort_by_key(|user| user.banned_at);
+    banned
+}
+
+/// Strikes of every user who has some but isn't banned yet
+pub fn user_strikes() -> HashMap<Address, usize> {
+    BAN_LIST
+        .lock()
+        .unwrap()
+        .strikes
+        .iter()
+        .map(|(user, strikes)| (*user, strikes.len()))
+        .collect()
+}
@@ -0,0 +1,142 @@
+//! Bech32 addresses, as Althea L1's Cosmos side and wallets such as Keplr show accounts. The bech32 address of an
+//! account encodes the same 20 bytes as its EVM hex address under the `althea` prefix, so users often quote one
+//! where the relayer would otherwise only show the other.
+
+use clarity::Address;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Human readable part of Althea L1 account addresses
+pub const ALTHEA_PREFIX: &str = "althea";
+
+const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+const GENERATORS: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+
+/// Whether users are shown by both their hex and bech32 addresses in logs
+static SHOW_BECH32: AtomicBool = AtomicBool::new(false);
+
+pub fn set_show_bech32(show: bool) {
+    SHOW_BECH32.store(show, Ordering::Relaxed);
+}
+
+/// `address` as logs show users, followed by its bech32 address if enabled
+pub fn display_user(address: Address) -> String {
+    if SHOW_BECH32.load(Ordering::Relaxed) {
+        format!("{address} ({})", to_bech32(address))
+    } else {
+        address.to_string()
+    }
+}
+
+/// The `althea` prefixed bech32 address of the account with EVM `address`
+pub fn to_bech32(address: Address) -> String {
+    let data = convert_bits(address.as_bytes(), 8, 5, true).expect("Padding always converts");
+    let checksum = checksum(ALTHEA_PREFIX, &data);
+    let mut encoded = format!("{ALTHEA_PREFIX}1");
+    encoded.extend(
+        data.iter()
+            .chain(&checksum)
+            .map(|value| CHARSET[*value as usize] as char),
+    );
+    encoded
+}
+
+/// The EVM address of the account with `althea` prefixed bech32 address `s`
+pub fn from_bech32(s: &str) -> Result<Address, String> {
+    let invalid = |reason: &str| format!("Invalid bech32 address {s}: {reason}");
+    // mixed case is forbidden, either case alone is valid
+    if s.chars().any(|c| c.is_ascii_lowercase()) && s.chars().any(|c| c.is_ascii_uppercase()) {
+        return Err(invalid("mixed case"));
+    }
+    let lower = s.to_ascii_lowercase();
+    let (prefix, data) = lower
+        .rsplit_once('1')
+        .ok_or_else(|| invalid("no separator"))?;
+    if prefix != ALTHEA_PREFIX {
+        return Err(invalid(&format!("expected the {ALTHEA_PREFIX} prefix")));
+    }
+    let values = data
+        .bytes()
+        .map(|c| CHARSET.iter().position(|v| *v == c).map(|v| v as u8))
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(|| invalid("invalid character"))?;
+    if values.len() < 6
+        || polymod(
+            expand_prefix(prefix)
+                .into_iter()
+                .chain(values.iter().copied()),
+        ) != 1
+    {
+        return Err(invalid("bad checksum"));
+    }
+    let bytes = convert_bits(&values[..values.len() - 6], 5, 8, false)
+        .ok_or_else(|| invalid("bad padding"))?;
+    Address::from_slice(&bytes).map_err(|_| invalid("not 20 bytes"))
+}
+
+/// An address given either in EVM hex or as an `althea` bech32 address
+pub fn parse_user_address(s: &str) -> Result<Address, String> {
+    if s.to_ascii_lowercase().starts_with(ALTHEA_PREFIX) {
+        from_bech32(s)
+    } else {
+        Address::from_str(s).map_err(|e| format!("Invalid address {s}: {e}"))
+    }
+}
+
+fn polymod(values: impl IntoIterator<Item = u8>) -> u32 {
+    let mut checksum = 1u32;
+    for value in values {
+        let top = checksum >> 25;
+        checksum = ((checksum & 0x1ffffff) << 5) ^ value as u32;
+        for (i, generator) in GENERATORS.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    checksum
+}
+
+fn expand_prefix(prefix: &str) -> Vec<u8> {
+    prefix
+        .bytes()
+        .map(|c| c >> 5)
+        .chain([0])
+        .chain(prefix.bytes().map(|c| c & 31))
+        .collect()
+}
+
+fn checksum(prefix: &str, data: &[u8]) -> [u8; 6] {
+    let polymod = polymod(
+        expand_prefix(prefix)
+            .into_iter()
+            .chain(data.iter().copied())
+            .chain([0; 6]),
+    ) ^ 1;
+    std::array::from_fn(|i| ((polymod >> (5 * (5 - i))) & 31) as u8)
+}
+
+/// Regroups `data` from words of `from` bits to words of `to` bits, None if unpadded bits are left over
+fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Option<Vec<u8>> {
+    let mut acc = 0u32;
+    let mut bits = 0u32;
+    let mut converted = Vec::new();
+    let max = (1u32 << to) - 1;
+    for value in data {
+        acc = (acc << from) | *value as u32;
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            converted.push(((acc >> bits) & max) as u8);
+        }
+    }
+    if pad {
+        if bits > 0 {
+            converted.push(((acc << (to - bits)) & max) as u8);
+        }
+    } else if bits >= from || ((acc << (to - bits)) & max) != 0 {
+        return None;
+    }
+    Some(converted)
+}
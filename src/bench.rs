@@ -0,0 +1,306 @@
+//! Benchmark of the relay pipeline, run with the `bench` subcommand. A captured queue is replayed against a chain,
+//! usually a local devnet, by evaluating every transaction end to end as the relay loop would, without claiming
+//! or sending anything, with a chosen number of evaluations in flight at once. The report gives the throughput,
+//! percentiles of the time each transaction took, the time spent in each stage of the pipeline as timed by its
+//! `tracing` span, and the RPC requests made by JSON-RPC method and by stage, so regressions show up between releases.
+//! The gas, price and token caches are cleared before each replay so that every replay starts cold.
+
+use crate::error::RelayerError;
+use crate::funds::clear_funds_cache;
+use crate::gas::clear_gas_caches;
+use crate::orchestrator::GaslessTransaction;
+use crate::price::clear_price_caches;
+use crate::relay::{RelayContext, evaluate_transaction};
+use crate::skip::SkipReason;
+use crate::tokens::clear_token_cache;
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use tracing::span::{Attributes, Id};
+use tracing::{Level, Subscriber};
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+
+/// Whether RPC requests passing through the bridge are being counted
+static RECORDING: AtomicBool = AtomicBool::new(false);
+
+/// RPC requests made since recording started, by method
+static RPC_CALLS: LazyLock<Mutex<BTreeMap<String, u64>>> =
+    LazyLock::new(|| Mutex::new(BTreeMap::new()));
+
+/// When each RPC request made since recording started reached the bridge
+static RPC_ARRIVALS: Mutex<Vec<Instant>> = Mutex::new(Vec::new());
+
+/// When a span of the pipeline was opened and closed
+type Interval = (Instant, Instant);
+
+/// When each closed span of the pipeline was open, by span name
+static STAGES: LazyLock<Mutex<HashMap<&'static str, Vec<Interval>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Stage RPC requests made outside of any span are counted under
+const NO_STAGE: &str = "other";
+
+/// When a span was opened, kept in its extensions
+struct Opened(Instant);
+
+/// Times the spans of the pipeline from when they are opened to when they close
+struct StageTimer;
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for StageTimer {
+    fn on_new_span(&self, _attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(Opened(Instant::now()));
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        if let Some(Opened(at)) = span.extensions().get::<Opened>() {
+            STAGES
+                .lock()
+                .unwrap()
+                .entry(span.name())
+                .or_default()
+                .push((*at, Instant::now()));
+        }
+    }
+}
+
+/// Installs the subscriber timing the stages of the pipeline, and starts counting RPC requests. RPC requests are
+/// only seen if they go through the local bridge.
+pub fn start_recording() -> Result<(), Box<dyn std::error::Error>> {
+    tracing_subscriber::registry()
+        .with(StageTimer.with_filter(Targets::new().with_target("althea_l1_relayer", Level::INFO)))
+        .try_init()?;
+    RECORDING.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Counts the JSON-RPC request or batch of requests in `body` if recording, called by the RPC bridge
+pub fn record_rpc_request(body: &[u8]) {
+    if !RECORDING.load(Ordering::Relaxed) {
+        return;
+    }
+    let Ok(request) = serde_json::from_slice::<serde_json::Value>(body) else {
+        return;
+    };
+    let requests = match request {
+        serde_json::Value::Array(requests) => requests,
+        request => vec![request],
+    };
+    let mut calls = RPC_CALLS.lock().unwrap();
+    for request in &requests {
+        let method = request["method"].as_str().unwrap_or("unknown");
+        *calls.entry(method.to_string()).or_default() += 1;
+    }
+    let now = Instant::now();
+    RPC_ARRIVALS
+        .lock()
+        .unwrap()
+        .extend(requests.iter().map(|_| now));
+}
+
+/// Counts the RPC requests that arrived at each time in `arrivals` against the stage opened last among those
+/// still open at that time. The stage that made a request stays open until its response is back, so with one
+/// evaluation in flight every request is counted against the stage that made it. With several, a request made
+/// while the stages of different evaluations overlap can be counted against the wrong one.
+fn rpc_calls_by_stage(
+    arrivals: &[Instant],
+    stages: &HashMap<&'static str, Vec<Interval>>,
+) -> BTreeMap<&'static str, u64> {
+    let mut calls = BTreeMap::new();
+    for arrival in arrivals {
+        let stage = stages
+            .iter()
+            .flat_map(|(stage, intervals)| intervals.iter().map(move |interval| (*stage, interval)))
+            .filter(|(_, (opened, closed))| opened <= arrival && arrival <= closed)
+            .max_by_key(|(_, (opened, _))| *opened)
+            .map_or(NO_STAGE, |(stage, _)| stage);
+        *calls.entry(stage).or_default() += 1;
+    }
+    calls
+}
+
+/// Percentiles of a set of durations, in milliseconds
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Latency {
+    pub count: usize,
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+impl Latency {
+    fn of(mut samples: Vec<Duration>) -> Latency {
+        samples.sort();
+        let percentile = |p: f64| {
+            samples
+                .get(((samples.len().saturating_sub(1)) as f64 * p).round() as usize)
+                .map_or(0.0, |d| d.as_secs_f64() * 1000.0)
+        };
+        Latency {
+            count: samples.len(),
+            p50_ms: percentile(0.5),
+            p90_ms: percentile(0.9),
+            p99_ms: percentile(0.99),
+            max_ms: percentile(1.0),
+        }
+    }
+}
+
+impl fmt::Display for Latency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:>6} p50 {:>9.1}ms p90 {:>9.1}ms p99 {:>9.1}ms max {:>9.1}ms",
+            self.count, self.p50_ms, self.p90_ms, self.p99_ms, self.max_ms
+        )
+    }
+}
+
+/// Results of replaying the queue at one concurrency
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchReport {
+    pub concurrency: usize,
+    /// transactions evaluated, over every round
+    pub transactions: usize,
+    /// evaluations that ended in a relay that would be submitted
+    pub relayable: usize,
+    pub skipped: usize,
+    /// evaluations that ended in an error other than a skip
+    pub failed: usize,
+    pub elapsed_ms: f64,
+    pub per_second: f64,
+    /// time each transaction took to evaluate
+    pub latency: Latency,
+    /// time spent in each stage, by span name
+    pub stages: BTreeMap<&'static str, Latency>,
+    /// RPC requests by method
+    pub rpc_calls: BTreeMap<String, u64>,
+    /// RPC requests by the stage that made them, by span name
+    pub rpc_calls_by_stage: BTreeMap<&'static str, u64>,
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "concurrency {}: {} transactions in {:.0}ms, {:.1}/s ({} relayable, {} skipped, {} failed)",
+            self.concurrency,
+            self.transactions,
+            self.elapsed_ms,
+            self.per_second,
+            self.relayable,
+            self.skipped,
+            self.failed
+        )?;
+        writeln!(f, "    {:<28} {}", "transaction", self.latency)?;
+        for (stage, latency) in &self.stages {
+            writeln!(f, "    {stage:<28} {latency}")?;
+        }
+        for (method, calls) in &self.rpc_calls {
+            writeln!(
+                f,
+                "    {method:<28} {calls:>6} calls, {:.2} per transaction",
+                *calls as f64 / self.transactions.max(1) as f64
+            )?;
+        }
+        for (stage, calls) in &self.rpc_calls_by_stage {
+            writeln!(
+                f,
+                "    {:<28} {calls:>6} calls, {:.2} per transaction",
+                format!("{stage} requests"),
+                *calls as f64 / self.transactions.max(1) as f64
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Forgets every lookup a previous replay could have left cached
+fn clear_caches() {
+    clear_funds_cache();
+    clear_gas_caches();
+    clear_price_caches();
+    clear_token_cache();
+}
+
+/// Evaluates every transaction of `queue` `rounds` times with up to `concurrency` evaluations in flight, on the
+/// chain each one is for. Caches are cleared before each round.
+pub async fn run_bench(
+    chains: &[RelayContext],
+    queue: &[GaslessTransaction],
+    concurrency: usize,
+    rounds: usize,
+) -> BenchReport {
+    STAGES.lock().unwrap().clear();
+    RPC_CALLS.lock().unwrap().clear();
+    RPC_ARRIVALS.lock().unwrap().clear();
+    let mut results: Vec<(Duration, Result<(), RelayerError>)> = Vec::new();
+    let mut elapsed = Duration::ZERO;
+    for _ in 0..rounds {
+        clear_caches();
+        let started = Instant::now();
+        let round: Vec<_> = stream::iter(queue)
+            .map(|tx| async move {
+                let started = Instant::now();
+                let result = match chains.iter().find(|ctx| ctx.chain_id == tx.chain_id) {
+                    Some(ctx) => evaluate_transaction(ctx, tx).await.map(|_| ()),
+                    None => Err(RelayerError::Skipped(SkipReason::UnknownChain)),
+                };
+                (started.elapsed(), result)
+            })
+            .buffer_unordered(concurrency.max(1))
+            .collect()
+            .await;
+        elapsed += started.elapsed();
+        results.extend(round);
+    }
+
+    let mut report = BenchReport {
+        concurrency,
+        transactions: results.len(),
+        relayable: 0,
+        skipped: 0,
+        failed: 0,
+        elapsed_ms: elapsed.as_secs_f64() * 1000.0,
+        per_second: results.len() as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+        latency: Latency::default(),
+        stages: BTreeMap::new(),
+        rpc_calls: RPC_CALLS.lock().unwrap().clone(),
+        rpc_calls_by_stage: BTreeMap::new(),
+    };
+    let mut latencies = Vec::new();
+    for (latency, result) in results {
+        latencies.push(latency);
+        match result {
+            Ok(()) => report.relayable += 1,
+            Err(RelayerError::Skipped(_) | RelayerError::Unprofitable) => report.skipped += 1,
+            Err(_) => report.failed += 1,
+        }
+    }
+    report.latency = Latency::of(latencies);
+    let stages = std::mem::take(&mut *STAGES.lock().unwrap());
+    report.rpc_calls_by_stage = rpc_calls_by_stage(&RPC_ARRIVALS.lock().unwrap(), &stages);
+    report.stages = stages
+        .into_iter()
+        .map(|(stage, intervals)| {
+            let samples = intervals
+                .into_iter()
+                .map(|(opened, closed)| closed - opened)
+                .collect();
+            (stage, Latency::of(samples))
+        })
+        .collect();
+    report
+}
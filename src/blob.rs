@@ -0,0 +1,110 @@
+//! Strict decoding of the ABI encoded blobs of gasless transactions. The tip and conditions come straight from
+//! whoever submitted the transaction, so rather than reading each word leniently they are checked to be exactly
+//! the expected number of words, and every word to hold a value of its type with the padding the dex's ABI
+//! decoder requires. A blob the dex would reject or read differently fails with an error saying which field is
+//! wrong and how.
+
+use clarity::Address;
+use std::fmt::{self, Display};
+
+/// Why a field of a gasless transaction could not be decoded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// the blob is not the expected number of words
+    Length {
+        blob: &'static str,
+        expected: usize,
+        actual: usize,
+    },
+    /// the padding of a word isn't zero, so its value doesn't fit the type
+    Overflow { field: &'static str, bits: usize },
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::Length {
+                blob,
+                expected,
+                actual,
+            } => write!(f, "{blob} is {actual} bytes, expected {expected}"),
+            DecodeError::Overflow { field, bits } => {
+                write!(f, "{field} does not fit in {bits} bits")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// A blob of abi encoded static words, checked to be exactly `words` long
+pub struct Blob<'a> {
+    name: &'static str,
+    data: &'a [u8],
+}
+
+impl<'a> Blob<'a> {
+    pub fn new(name: &'static str, data: &'a [u8], words: usize) -> Result<Self, DecodeError> {
+        if data.len() != words * 32 {
+            return Err(DecodeError::Length {
+                blob: name,
+                expected: words * 32,
+                actual: data.len(),
+            });
+        }
+        Ok(Blob { name, data })
+    }
+
+    fn raw_word(&self, index: usize) -> Result<&'a [u8], DecodeError> {
+        self.data
+            .get(index * 32..(index + 1) * 32)
+            .ok_or(DecodeError::Length {
+                blob: self.name,
+                expected: (index + 1) * 32,
+                actual: self.data.len(),
+            })
+    }
+
+    /// Word `index` with its leading `32 - bytes` bytes checked to be zero, leaving the value in the last `bytes`
+    fn word(
+        &self,
+        index: usize,
+        field: &'static str,
+        bytes: usize,
+    ) -> Result<&'a [u8], DecodeError> {
+        let (padding, value) = self.raw_word(index)?.split_at(32 - bytes);
+        if padding.iter().any(|b| *b != 0) {
+            return Err(DecodeError::Overflow {
+                field,
+                bits: bytes * 8,
+            });
+        }
+        Ok(value)
+    }
+
+    /// Unsigned integer of up to 128 bits in word `index`
+    pub fn uint(
+        &self,
+        index: usize,
+        field: &'static str,
+        bits: usize,
+    ) -> Result<u128, DecodeError> {
+        let value = self.word(index, field, bits.div_ceil(8))?;
+        let value = value.iter().fold(0u128, |acc, b| acc << 8 | u128::from(*b));
+        // types narrower than a whole number of bytes are checked bit by bit too
+        if bits < 128 && value >> bits != 0 {
+            return Err(DecodeError::Overflow { field, bits });
+        }
+        Ok(value)
+    }
+
+    pub fn address(&self, index: usize, field: &'static str) -> Result<Address, DecodeError> {
+        let value = self.word(index, field, 20)?;
+        // infallible, the slice is always 20 bytes
+        Ok(Address::from_slice(value).unwrap_or_default())
+    }
+
+    pub fn bytes32(&self, index: usize) -> Result<[u8; 32], DecodeError> {
+        Ok(self.raw_word(index)?.try_into().unwrap_or_default())
+    }
+}
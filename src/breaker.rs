@@ -0,0 +1,98 @@
+//! Per orchestrator circuit breaker. An orchestrator that keeps failing is not polled at all for a while
+//! instead of adding a full request timeout to every cycle, once that period is over a single probe poll
+//! decides whether it is healthy again or stays cut off for another period.
+
+use log::{info, warn};
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone)]
+pub struct BreakerPolicy {
+    /// consecutive failed polls after which an orchestrator is cut off
+    pub failure_threshold: u32,
+    /// how long an orchestrator is cut off before it is probed again
+    pub open_duration: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BreakerState {
+    /// polled as usual
+    Closed,
+    /// not polled until the given instant
+    Open(Instant),
+    /// the next poll is a probe, a success closes the circuit and a failure opens it again
+    HalfOpen,
+}
+
+#[derive(Debug)]
+struct Breaker {
+    state: BreakerState,
+    failures: u32,
+}
+
+static POLICY: LazyLock<Mutex<Option<BreakerPolicy>>> = LazyLock::new(|| Mutex::new(None));
+
+static BREAKERS: LazyLock<Mutex<HashMap<String, Breaker>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+pub fn set_breaker_policy(policy: Option<BreakerPolicy>) {
+    *POLICY.lock().unwrap() = policy;
+}
+
+/// True if `url` should be polled this cycle, an open circuit whose period is over moves to half open so that
+/// this poll acts as the probe
+pub fn should_poll(url: &str) -> bool {
+    let mut breakers = BREAKERS.lock().unwrap();
+    let Some(breaker) = breakers.get_mut(url) else {
+        return true;
+    };
+    match breaker.state {
+        BreakerState::Closed | BreakerState::HalfOpen => true,
+        BreakerState::Open(until) if Instant::now() >= until => {
+            info!("Probing orchestrator {url} after its circuit was opened");
+            breaker.state = BreakerState::HalfOpen;
+            true
+        }
+        BreakerState::Open(_) => false,
+    }
+}
+
+pub fn record_poll_result(url: &str, success: bool) {
+    let Some(policy) = POLICY.lock().unwrap().clone() else {
+        return;
+    };
+    let mut breakers = BREAKERS.lock().unwrap();
+    let breaker = breakers.entry(url.to_string()).or_insert(Breaker {
+        state: BreakerState::Closed,
+        failures: 0,
+    });
+    if success {
+        if breaker.state == BreakerState::HalfOpen {
+            info!("Orchestrator {url} recovered, closing its circuit");
+        }
+        breaker.state = BreakerState::Closed;
+        breaker.failures = 0;
+        return;
+    }
+    breaker.failures += 1;
+    if breaker.state == BreakerState::HalfOpen || breaker.failures >= policy.failure_threshold {
+        warn!(
+            "Orchestrator {url} failed {} polls in a row, not polling it for {}s",
+            breaker.failures,
+            policy.open_duration.as_secs()
+        );
+        breaker.state = BreakerState::Open(Instant::now() + policy.open_duration);
+    }
+}
+
+/// Urls of the orchestrators that are currently cut off
+pub fn open_circuits() -> Vec<String> {
+    BREAKERS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, breaker)| matches!(breaker.state, BreakerState::Open(_)))
+        .map(|(url, _)| url.clone())
+        .collect()
+}
@@ -0,0 +1,295 @@
+//! Minimal ERC-4337 bundler. Besides gasless dex transactions an orchestrator may queue ERC-4337 UserOperations,
+//! which are sent one per bundle to the chain's configured EntryPoint through `handleOps`, with the relayer
+//! account as the beneficiary. The EntryPoint pays the beneficiary for the gas each operation uses at the
+//! operation's own gas price, that payment is the tip and is held to the same profit margin, gas price ceiling
+//! and gas limit as the tips of gasless transactions. Only EntryPoint v0.6 operations are supported.
+
+use crate::backoff::retry_get;
+use crate::error::{FailureKind, RelayerError};
+use crate::gas::{estimate_gas, gas_price, pessimistic_gas_price};
+use crate::history::was_executed;
+use crate::lease::may_broadcast;
+use crate::orchestrator::{Orchestrator, RELAYING_SERVICE_ROOT};
+use crate::profit::{FeeData, exceeds_gas_cost};
+use crate::relay::{
+    RelayContext, find_chain, prepare_unsigned_transaction, relay_limits,
+    send_side_transaction_once,
+};
+use crate::signer::Signer;
+use crate::throttle::Outbound;
+use clarity::abi::{AbiToken, encode_call, encode_tokens, get_hash};
+use clarity::utils::display_uint256_as_address;
+use clarity::{Address, Uint256};
+use log::{debug, info, warn};
+use num_traits::{CheckedAdd, CheckedMul};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::{LazyLock, Mutex};
+
+// function handleOps (UserOperation[] calldata ops, address payable beneficiary)
+pub const HANDLE_OPS_SIG: &str = "handleOps((address,uint256,bytes,bytes,uint256,uint256,uint256,uint256,uint256,bytes,bytes)[],address)";
+
+/// Kind bundled UserOperations are recorded as executed under in the history
+const USER_OPERATION_EXECUTION: &str = "user_operation";
+
+/// An ERC-4337 v0.6 UserOperation, with the field names of the bundler RPC
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct UserOperation {
+    pub sender: Address,
+    pub nonce: Uint256,
+    pub init_code: Vec<u8>,
+    pub call_data: Vec<u8>,
+    pub call_gas_limit: Uint256,
+    pub verification_gas_limit: Uint256,
+    pub pre_verification_gas: Uint256,
+    pub max_fee_per_gas: Uint256,
+    pub max_priority_fee_per_gas: Uint256,
+    pub paymaster_and_data: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+/// A UserOperation waiting in the orchestrator's queue
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PendingUserOperation {
+    pub chain_id: u64,
+    /// EntryPoint the operation was signed for
+    pub entry_point: Address,
+    pub user_op: UserOperation,
+}
+
+impl UserOperation {
+    fn token(&self) -> AbiToken {
+        // clarity can't encode empty bytes, an empty array encodes the same way
+        let bytes = |b: &Vec<u8>| {
+            if b.is_empty() {
+                AbiToken::Dynamic(Vec::new())
+            } else {
+                b.clone().into()
+            }
+        };
+        AbiToken::Struct(vec![
+            self.sender.into(),
+            self.nonce.into(),
+            bytes(&self.init_code),
+            bytes(&self.call_data),
+            self.call_gas_limit.into(),
+            self.verification_gas_limit.into(),
+            self.pre_verification_gas.into(),
+            self.max_fee_per_gas.into(),
+            self.max_priority_fee_per_gas.into(),
+            bytes(&self.paymaster_and_data),
+            bytes(&self.signature),
+        ])
+    }
+
+    /// Hex encoded hash of the encoded operation, identifies it across poll cycles
+    pub fn id(&self) -> String {
+        format!(
+            "0x{}",
+            hex::encode(get_hash(&encode_tokens(&[self.token()])))
+        )
+    }
+
+    /// Gas price the EntryPoint charges the operation at `base_fee`
+    fn gas_price(&self, base_fee: Option<Uint256>) -> Uint256 {
+        match base_fee.and_then(|base_fee| base_fee.checked_add(&self.max_priority_fee_per_gas)) {
+            Some(gas_price) if gas_price < self.max_fee_per_gas => gas_price,
+            _ => self.max_fee_per_gas,
+        }
+    }
+}
+
+/// Operations already bundled or rejected for good
+static DONE: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(|| Mutex::new(HashSet::new()));
+
+/// Fetches the UserOperations waiting in the orchestrator's queue
+pub async fn fetch_user_operations(
+    orchestrator: &Orchestrator,
+    signer: &Signer,
+) -> Result<Vec<PendingUserOperation>, RelayerError> {
+    let url = format!(
+        "{}/{RELAYING_SERVICE_ROOT}/userops/pending",
+        orchestrator.url
+    );
+    let client = orchestrator.http_client();
+    let mut response = retry_get(Outbound::Orchestrator, &url, || async {
+        orchestrator
+            .authenticate(client.get(&url), signer)
+            .await?
+            .send()
+            .await
+            .map_err(RelayerError::orchestrator)
+    })
+    .await?;
+    if !response.status().is_success() {
+        return Err(RelayerError::orchestrator(format!(
+            "UserOperation queue responded with {}",
+            response.status()
+        )));
+    }
+    response
+        .json()
+        .limit(orchestrator.max_response)
+        .await
+        .map_err(RelayerError::orchestrator)
+}
+
+/// Bundles `pending` on its own from the chain's primary relayer account if it pays for itself. Returns the hash
+/// of the bundle, `None` if it wasn't sent.
+async fn bundle_user_operation(
+    ctx: &RelayContext,
+    pending: &PendingUserOperation,
+) -> Result<Option<Uint256>, RelayerError> {
+    let id = pending.user_op.id();
+    if ctx.entry_point != Some(pending.entry_point) {
+        return Err(RelayerError::invalid_tx(format!(
+            "UserOperation is for EntryPoint {}, which isn't configured on {}",
+            pending.entry_point, ctx.name
+        )));
+    }
+    let web3 = ctx.web3();
+    let signer = ctx.signers.primary();
+    let data = encode_call(
+        HANDLE_OPS_SIG,
+        &[
+            AbiToken::Dynamic(vec![pending.user_op.token()]),
+            signer.address().into(),
+        ],
+    )?;
+    // estimating fails if the operation doesn't validate, before anything is spent
+    let unsigned = prepare_unsigned_transaction(
+        web3,
+        ctx.chain_id,
+        signer.address(),
+        pending.entry_point,
+        data,
+        2.0,
+    )
+    .await?;
+    let gas_used = estimate_gas(web3, &unsigned, signer.address()).await?;
+    let limits = relay_limits();
+    if let Some(max_gas_limit) = limits.max_gas_limit
+        && gas_used > max_gas_limit
+    {
+        info!(
+            tx:% = id, decision = "gas_limit_exceeded", gas_estimate:% = gas_used;
+            "UserOperation {id} gas estimate {gas_used} exceeds the limit of {max_gas_limit}, skipping"
+        );
+        return Ok(None);
+    }
+    let gas_price = gas_price(web3, ctx.chain_id).await?;
+    let gas_cost_multiple = match (limits.max_gas_price, limits.over_cap_tip_multiple) {
+        (Some(max_gas_price), None) if gas_price > max_gas_price => {
+            info!(
+                tx:% = id, decision = "gas_price_too_high", gas_estimate:% = gas_used;
+                "Gas price {gas_price} is above the ceiling of {max_gas_price}, not bundling until it falls"
+            );
+            return Ok(None);
+        }
+        (Some(max_gas_price), Some(multiple)) if gas_price > max_gas_price => multiple,
+        _ => 1,
+    };
+    // the bundle's gas stands in for the operation's, its preVerificationGas covers the bundle's overhead
+    let Some(payment) = gas_used.checked_mul(
+        &pending
+            .user_op
+            .gas_price(web3.get_base_fee_per_gas().await?),
+    ) else {
+        return Err(RelayerError::invalid_tx(
+            "UserOperation gas price overflows its payment",
+        ));
+    };
+    let fees = FeeData {
+        gas_price: pessimistic_gas_price(web3, ctx.chain_id, gas_price).await,
+        gas_cost_multiple,
+        profit_margin_percent: limits.profit_margin_percent,
+    };
+    if !exceeds_gas_cost(payment, gas_used, &fees) {
+        info!(
+            tx:% = id, decision = "unprofitable", gas_estimate:% = gas_used;
+            "UserOperation {id} from {} pays {payment} for its gas, which isn't profitable, skipping",
+            pending.user_op.sender
+        );
+        return Ok(None);
+    }
+    if ctx.dry_run {
+        info!("Dry run, not bundling UserOperation {id}");
+        return Ok(None);
+    }
+    let tx_hash =
+        send_side_transaction_once(ctx, signer, unsigned, USER_OPERATION_EXECUTION, &id).await?;
+    Ok(Some(tx_hash))
+}
+
+/// Bundles the UserOperations waiting in the orchestrator's queue, on chains with an EntryPoint configured
+pub async fn process_user_operations(
+    chains: &[RelayContext],
+    orchestrator: &Orchestrator,
+    signer: &Signer,
+) {
+    if chains.iter().all(|ctx| ctx.entry_point.is_none()) {
+        return;
+    }
+    if orchestrator.is_grpc() {
+        debug!(
+            "Not polling {} for UserOperations, the UserOperation queue is only served over http",
+            orchestrator.url
+        );
+        return;
+    }
+    let operations = match fetch_user_operations(orchestrator, signer).await {
+        Ok(operations) => operations,
+        Err(e) => {
+            warn!(
+                "Failed to fetch UserOperations from {}: {e}",
+                orchestrator.url
+            );
+            return;
+        }
+    };
+    for pending in operations {
+        let id = pending.user_op.id();
+        if DONE.lock().unwrap().contains(&id) || was_executed(USER_OPERATION_EXECUTION, &id) {
+            continue;
+        }
+        let Some(ctx) = find_chain(chains, pending.chain_id) else {
+            debug!(
+                "Skipping UserOperation {id} for chain {}, which isn't relayed on",
+                pending.chain_id
+            );
+            continue;
+        };
+        // only the instance relaying from the primary account may send from it
+        if !ctx.dry_run && !may_broadcast(ctx.chain_id, ctx.signers.primary().address()) {
+            debug!(
+                "Not the leader or lease holder on {}, standing by on UserOperation {id}",
+                ctx.name
+            );
+            continue;
+        }
+        match bundle_user_operation(ctx, &pending).await {
+            Ok(Some(tx_hash)) => {
+                DONE.lock().unwrap().insert(id.clone());
+                info!(
+                    tx:% = id, decision = "submitted", tx_hash:% = display_uint256_as_address(tx_hash);
+                    "UserOperation {id} from {} bundled on {} with hash {}",
+                    pending.user_op.sender,
+                    ctx.name,
+                    display_uint256_as_address(tx_hash)
+                );
+            }
+            Ok(None) => {}
+            Err(e) => {
+                warn!(
+                    tx:% = id, decision = "failed";
+                    "Failed to bundle UserOperation {id} from {}: {e}",
+                    pending.user_op.sender
+                );
+                if e.kind() == FailureKind::Blacklist {
+                    DONE.lock().unwrap().insert(id);
+                }
+            }
+        }
+    }
+}
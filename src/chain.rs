@@ -0,0 +1,142 @@
+//! Chain configuration, a single relayer process can serve several chains at once with each
+//! gasless transaction routed to the chain matching its `chain_id`.
+
+use crate::key::SecretString;
+use crate::price::PriceSourceKind;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// One `[[chain]]` section of the relayer config file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainConfig {
+    /// human readable name used in logs, defaults to the chain id
+    pub name: Option<String>,
+    pub chain_id: u64,
+    pub rpc_url: String,
+    /// additional endpoints for the same chain, used in order when `rpc_url` fails or falls behind
+    #[serde(default)]
+    pub fallback_rpc_urls: Vec<String>,
+    /// address of the dex contract on this chain
+    pub contract_address: String,
+    /// key used to relay on this chain, defaults to `--private-key`
+    pub private_key: Option<SecretString>,
+    /// additional keys to relay with on this chain, relays are spread across all of them
+    #[serde(default)]
+    pub private_keys: Vec<SecretString>,
+    /// Multicall3 contract used to batch relays on this chain, relays are submitted individually when unset
+    pub multicall_address: Option<String>,
+    /// private or priority endpoint to submit relays through on this chain, defaults to `--private-tx-rpc`
+    pub private_tx_rpc: Option<String>,
+    /// endpoint to trace relays on this chain with, defaults to `--trace-rpc`
+    pub trace_rpc: Option<String>,
+    /// wrapped gas token on this chain, defaults to `--wrapped-native-token`
+    pub wrapped_native_token: Option<String>,
+    /// `CrocImpact` contract used to quote tips on this chain, defaults to `--swap-quote-address`
+    pub swap_quote_address: Option<String>,
+    /// addresses other than the relayer keys that tips may pay on this chain, in addition to `--tip-receiver`
+    #[serde(default)]
+    pub tip_receivers: Vec<String>,
+    /// where tip token prices come from on this chain, defaults to `--price-source`
+    pub price_source: Option<PriceSourceKind>,
+    /// CoinGecko asset platform id of this chain, defaults to `--coingecko-platform`
+    pub coingecko_platform: Option<String>,
+    /// fixed prices used on this chain when live prices fail, as `TOKEN=PRICE`, in addition to `--static-price`
+    #[serde(default)]
+    pub static_prices: Vec<String>,
+    /// function relays are sent to on this chain, defaults to `--relay-function`
+    pub relay_function: Option<String>,
+    /// order of the tip's words on this chain, defaults to `--tip-layout`
+    pub tip_layout: Option<Vec<String>>,
+    /// tip receiver paying `msg.sender` on this chain, defaults to `--sender-receiver`
+    pub sender_receiver: Option<String>,
+    /// tip receiver paying `tx.origin` on this chain, defaults to `--origin-receiver`
+    pub origin_receiver: Option<String>,
+    /// periphery contracts transactions may be relayed to on this chain, defaults to `--relay-target`
+    #[serde(default)]
+    pub relay_targets: Vec<String>,
+    /// ERC-4337 EntryPoint UserOperations are bundled to on this chain, defaults to `--entry-point`
+    pub entry_point: Option<String>,
+}
+
+impl ChainConfig {
+    pub fn name(&self) -> String {
+        self.name
+            .clone()
+            .unwrap_or_else(|| self.chain_id.to_string())
+    }
+
+    /// Every key configured for this chain, empty if the command line keys should be used
+    pub fn private_keys(&self) -> Vec<SecretString> {
+        self.private_key
+            .iter()
+            .chain(self.private_keys.iter())
+            .cloned()
+            .collect()
+    }
+
+    /// Every RPC endpoint for this chain, primary first
+    pub fn rpc_urls(&self) -> Vec<String> {
+        let mut urls = vec![self.rpc_url.clone()];
+        urls.extend(self.fallback_rpc_urls.iter().cloned());
+        urls
+    }
+}
+
+/// The `[settings]` section of the relayer config file. Every field set here overrides the command line flag
+/// of the same name, and unlike the rest of the configuration these are re-read on SIGHUP or `POST /admin/reload`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReloadableSettings {
+    pub transaction_api_url: Option<Vec<String>>,
+    pub max_gas_price_gwei: Option<f64>,
+    pub over_cap_tip_multiple: Option<u32>,
+    pub max_gas_limit: Option<u64>,
+    pub allowed_command: Option<Vec<String>>,
+    pub profit_margin_percent: Option<u32>,
+    pub liquidation_gas: Option<u64>,
+    pub liquidation_fee_bps: Option<u32>,
+    pub reserve_balance_wei: Option<u128>,
+    pub partner: Option<Vec<String>>,
+}
+
+/// One `[[override]]` section of the relayer config file, limits that replace the `[settings]` and command line
+/// ones for transactions from one orchestrator or tipping in particular tokens. Overrides are re-read along with
+/// `[settings]`, and a transaction takes the first one that matches it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LimitOverrideConfig {
+    /// url of the orchestrator whose transactions this applies to, any orchestrator when unset
+    pub orchestrator: Option<String>,
+    /// tip tokens this applies to, any token when empty
+    #[serde(default)]
+    pub tip_tokens: Vec<String>,
+    pub profit_margin_percent: Option<u32>,
+    pub max_gas_limit: Option<u64>,
+    /// the only tip tokens relayed, transactions tipping in any other token are skipped
+    pub allowed_tip_tokens: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RelayerConfig {
+    #[serde(default)]
+    pub chain: Vec<ChainConfig>,
+    #[serde(default)]
+    pub settings: ReloadableSettings,
+    #[serde(default, rename = "override")]
+    pub overrides: Vec<LimitOverrideConfig>,
+}
+
+/// Loads the relayer config file, rejecting files that configure the same chain id twice
+pub fn load_config(path: &Path) -> Result<RelayerConfig, Box<dyn std::error::Error>> {
+    let contents = std::fs::read_to_string(path)?;
+    let config: RelayerConfig = toml::from_str(&contents)?;
+    for (idx, chain) in config.chain.iter().enumerate() {
+        if config.chain[..idx]
+            .iter()
+            .any(|c| c.chain_id == chain.chain_id)
+        {
+            return Err(format!("Chain id {} is configured more than once", chain.chain_id).into());
+        }
+    }
+    Ok(config)
+}
@@ -0,0 +1,202 @@
+//! Fault injection for integration tests, only built with the `testing` feature. A chaos config makes
+//! orchestrators serve malformed transactions, RPC requests time out and price sources return stale values at
+//! configured rates, so that retries, circuit breakers and accounting can be exercised against a real node and
+//! the mock orchestrator. Each kind of fault draws from its own generator seeded from the config, so a run
+//! injects the same faults in the same order every time.
+
+use crate::error::RelayerError;
+use crate::price::{PriceSource, TokenAmount};
+use clarity::{Address, Uint256};
+use futures::future::LocalBoxFuture;
+use log::{debug, warn};
+use num_traits::CheckedMul;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+/// Faults to inject, each rate being the probability from 0 to 1 that a transaction, RPC request or price lookup
+/// is faulted
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChaosConfig {
+    /// seeds the generators faults are drawn from
+    #[serde(default)]
+    pub seed: u64,
+    /// rate at which pending transactions served by orchestrators are malformed
+    #[serde(default)]
+    pub malformed_tx_rate: f64,
+    /// rate at which RPC requests time out
+    #[serde(default)]
+    pub rpc_timeout_rate: f64,
+    /// how long a timed out RPC request hangs before failing, long enough to trip the client's own timeout
+    #[serde(default = "default_rpc_timeout_secs")]
+    pub rpc_timeout_secs: u64,
+    /// rate at which price lookups return the value of the token's first lookup instead of the live one
+    #[serde(default)]
+    pub stale_price_rate: f64,
+}
+
+fn default_rpc_timeout_secs() -> u64 {
+    60
+}
+
+struct Chaos {
+    config: ChaosConfig,
+    orchestrator: StdRng,
+    rpc: StdRng,
+    price: StdRng,
+}
+
+static CHAOS: LazyLock<Mutex<Option<Chaos>>> = LazyLock::new(|| Mutex::new(None));
+
+/// Loads a chaos config, rejecting rates outside 0 to 1
+pub fn load_chaos_config(path: &Path) -> Result<ChaosConfig, Box<dyn std::error::Error>> {
+    let contents = std::fs::read_to_string(path)?;
+    let config: ChaosConfig = toml::from_str(&contents)?;
+    for (name, rate) in [
+        ("malformed_tx_rate", config.malformed_tx_rate),
+        ("rpc_timeout_rate", config.rpc_timeout_rate),
+        ("stale_price_rate", config.stale_price_rate),
+    ] {
+        if !(0.0..=1.0).contains(&rate) {
+            return Err(format!("{name} is {rate}, expected a rate from 0 to 1").into());
+        }
+    }
+    Ok(config)
+}
+
+pub fn set_chaos(config: Option<ChaosConfig>) {
+    if let Some(config) = &config {
+        warn!(
+            "Injecting faults with seed {}: {} of pending transactions malformed, {} of RPC requests timed out, {} of prices stale",
+            config.seed, config.malformed_tx_rate, config.rpc_timeout_rate, config.stale_price_rate
+        );
+    }
+    *CHAOS.lock().unwrap() = config.map(|config| Chaos {
+        orchestrator: StdRng::seed_from_u64(config.seed),
+        rpc: StdRng::seed_from_u64(config.seed.wrapping_add(1)),
+        price: StdRng::seed_from_u64(config.seed.wrapping_add(2)),
+        config,
+    });
+}
+
+/// True if RPC requests may be faulted, they then have to pass through the local bridge
+pub fn rpc_faults_enabled() -> bool {
+    CHAOS
+        .lock()
+        .unwrap()
+        .as_ref()
+        .is_some_and(|chaos| chaos.config.rpc_timeout_rate > 0.0)
+}
+
+/// How long the next RPC request should hang before failing, None if it goes through
+pub fn rpc_timeout() -> Option<Duration> {
+    let mut chaos = CHAOS.lock().unwrap();
+    let chaos = chaos.as_mut()?;
+    let rate = chaos.config.rpc_timeout_rate;
+    (rate > 0.0 && chaos.rpc.random_bool(rate)).then(|| {
+        debug!("Timing out an RPC request");
+        Duration::from_secs(chaos.config.rpc_timeout_secs)
+    })
+}
+
+/// Malforms some of the pending entries served by an orchestrator. Only the signature or the shape of an entry is
+/// broken, so the original transaction isn't blacklisted along with the malformed one and is relayed once it is
+/// served intact.
+pub fn malform_pending(mut entries: Vec<serde_json::Value>) -> Vec<serde_json::Value> {
+    let mut chaos = CHAOS.lock().unwrap();
+    let Some(chaos) = chaos.as_mut() else {
+        return entries;
+    };
+    let rate = chaos.config.malformed_tx_rate;
+    if rate <= 0.0 {
+        return entries;
+    }
+    for (index, entry) in entries.iter_mut().enumerate() {
+        if !chaos.orchestrator.random_bool(rate) {
+            continue;
+        }
+        let Some(fields) = entry.as_object_mut() else {
+            continue;
+        };
+        match chaos.orchestrator.random_range(0..3) {
+            0 => {
+                debug!("Removing the signature of pending entry {index}");
+                fields.remove("sig");
+            }
+            1 => {
+                debug!("Truncating the signature of pending entry {index}");
+                if let Some(sig) = fields.get_mut("sig").and_then(|sig| sig.as_array_mut()) {
+                    sig.pop();
+                }
+            }
+            _ => {
+                debug!("Replacing the cmd of pending entry {index} with a string");
+                fields.insert("cmd".to_string(), "malformed".into());
+            }
+        }
+    }
+    entries
+}
+
+/// `source` returning stale prices at the configured rate, or `source` itself if prices aren't faulted
+pub fn with_stale_prices(source: Rc<dyn PriceSource>) -> Rc<dyn PriceSource> {
+    let faulted = CHAOS
+        .lock()
+        .unwrap()
+        .as_ref()
+        .is_some_and(|chaos| chaos.config.stale_price_rate > 0.0);
+    if !faulted {
+        return source;
+    }
+    Rc::new(StalePriceSource {
+        live: source,
+        first: RefCell::new(HashMap::new()),
+    })
+}
+
+/// Values a tip at the price of the token's first successful lookup instead of asking `live`, at the configured
+/// rate
+struct StalePriceSource {
+    live: Rc<dyn PriceSource>,
+    /// amount and value of the first lookup of each token, by chain id and token
+    first: RefCell<HashMap<(u64, Address), (Uint256, Uint256)>>,
+}
+
+impl PriceSource for StalePriceSource {
+    fn value_in_gas_token(
+        &self,
+        tip: TokenAmount,
+    ) -> LocalBoxFuture<'_, Result<Uint256, RelayerError>> {
+        Box::pin(async move {
+            let first = self.first.borrow().get(&(tip.chain_id, tip.token)).copied();
+            let stale = first.is_some() && {
+                let mut chaos = CHAOS.lock().unwrap();
+                chaos.as_mut().is_some_and(|chaos| {
+                    let rate = chaos.config.stale_price_rate;
+                    chaos.price.random_bool(rate)
+                })
+            };
+            if let Some((amount, value)) = first
+                && stale
+                && !amount.is_zero()
+                && let Some(scaled) = value.checked_mul(&tip.amount)
+            {
+                debug!("Valuing {} with its first price", tip.token);
+                return Ok(scaled / amount);
+            }
+            let value = self.live.value_in_gas_token(tip).await?;
+            self.first
+                .borrow_mut()
+                .entry((tip.chain_id, tip.token))
+                .or_insert((tip.amount, value));
+            Ok(value)
+        })
+    }
+}
@@ -0,0 +1,206 @@
+//! Watches the code of the contracts relays are sent to, the dex and any relay targets. The layout of tips, the
+//! nonce rules and the relay function are all properties of that code, so a redeployment or proxy upgrade could
+//! change what the relayer is paid or whether its relays revert without any error showing up. Each contract is
+//! fingerprinted by the hash of its code, and for an EIP-1967 proxy also by the code of its implementation. Once
+//! a fingerprint changes relaying is paused and an alert sent until an operator confirms the new code through the
+//! admin api, or it changes back.
+
+use crate::alert::alert_on_code_change;
+use crate::relay::RelayContext;
+use clarity::abi::get_hash;
+use clarity::{Address, Uint256};
+use log::{info, warn};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use web30::jsonrpc::client::HttpClient;
+
+/// EIP-1967 implementation slot, `keccak256("eip1967.proxy.implementation") - 1`
+const IMPLEMENTATION_SLOT: &str =
+    "0x360894a13ba1a3210667c828492db98dca3e2076cc3735a920a3ca505d382bbc";
+
+#[derive(Debug, Clone)]
+pub struct CodeWatch {
+    /// how often code is checked
+    pub interval: Duration,
+    /// fingerprints contracts must have, by address. Contracts not listed take the fingerprint they have when
+    /// first checked
+    pub expected: HashMap<Address, [u8; 32]>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Fingerprints {
+    /// fingerprint relaying was confirmed against
+    confirmed: [u8; 32],
+    /// fingerprint at the last check
+    current: [u8; 32],
+}
+
+static WATCH: LazyLock<Mutex<Option<CodeWatch>>> = LazyLock::new(|| Mutex::new(None));
+
+/// Fingerprints of each watched contract, by (chain id, address)
+static FINGERPRINTS: LazyLock<Mutex<HashMap<(u64, Address), Fingerprints>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+static LAST_CHECK: LazyLock<Mutex<Option<Instant>>> = LazyLock::new(|| Mutex::new(None));
+
+static CHANGED: AtomicBool = AtomicBool::new(false);
+
+pub fn set_code_watch(watch: Option<CodeWatch>) {
+    *WATCH.lock().unwrap() = watch;
+}
+
+/// True while the code of a contract relays are sent to differs from the code relaying was confirmed against
+pub fn contract_code_changed() -> bool {
+    CHANGED.load(Ordering::Relaxed)
+}
+
+/// Parses `ADDRESS=HASH`, the fingerprint a contract is expected to have
+pub fn parse_expected_code_hash(s: &str) -> Result<(Address, [u8; 32]), String> {
+    let (address, hash) = s
+        .split_once('=')
+        .ok_or_else(|| format!("Expected ADDRESS=HASH, got {s}"))?;
+    let address =
+        Address::from_str(address.trim()).map_err(|e| format!("Invalid address {address}: {e}"))?;
+    let hash = hex::decode(hash.trim().trim_start_matches("0x"))
+        .ok()
+        .and_then(|hash| <[u8; 32]>::try_from(hash).ok())
+        .ok_or_else(|| format!("Invalid code hash {hash}, expected 32 hex encoded bytes"))?;
+    Ok((address, hash))
+}
+
+/// Fingerprints the contracts relays are sent to on each chain, if due, and pauses relaying while any of them
+/// differs from what was confirmed. Contracts that couldn't be fetched keep their last known state.
+pub async fn check_contract_code(chains: &[RelayContext]) {
+    let Some(watch) = WATCH.lock().unwrap().clone() else {
+        return;
+    };
+    {
+        let mut last_check = LAST_CHECK.lock().unwrap();
+        if last_check.is_some_and(|last| last.elapsed() < watch.interval) {
+            return;
+        }
+        *last_check = Some(Instant::now());
+    }
+    for ctx in chains {
+        let contracts = std::iter::once(ctx.contract_address)
+            .chain(ctx.targets.iter().map(|target| target.contract));
+        for contract in contracts {
+            let current = match fingerprint(ctx, contract).await {
+                Ok(current) => current,
+                Err(e) => {
+                    warn!(
+                        "Failed to check the code of {contract} on {}: {e}",
+                        ctx.name
+                    );
+                    continue;
+                }
+            };
+            let mut fingerprints = FINGERPRINTS.lock().unwrap();
+            let previous = fingerprints.get(&(ctx.chain_id, contract)).copied();
+            let confirmed = match previous {
+                Some(previous) => previous.confirmed,
+                None => {
+                    info!(
+                        "Code of {contract} on {} has hash {}, pass --expected-code-hash {contract}={} to require it across restarts",
+                        ctx.name,
+                        display_hash(current),
+                        display_hash(current)
+                    );
+                    watch.expected.get(&contract).copied().unwrap_or(current)
+                }
+            };
+            fingerprints.insert(
+                (ctx.chain_id, contract),
+                Fingerprints { confirmed, current },
+            );
+            let was_changed =
+                previous.is_some_and(|previous| previous.current != previous.confirmed);
+            if current != confirmed && previous.is_none_or(|previous| previous.current != current) {
+                warn!(
+                    "Code of {contract} on {} changed from hash {} to {}, relaying is paused until the new code is confirmed with POST /admin/code/confirm",
+                    ctx.name,
+                    display_hash(confirmed),
+                    display_hash(current)
+                );
+                alert_on_code_change(&ctx.name, contract, Some((confirmed, current)));
+            } else if current == confirmed && was_changed {
+                info!(
+                    "Code of {contract} on {} is back to hash {}",
+                    ctx.name,
+                    display_hash(confirmed)
+                );
+                alert_on_code_change(&ctx.name, contract, None);
+            }
+        }
+    }
+    update_changed();
+}
+
+/// Confirms the code every watched contract has now, resuming relaying if a change paused it. Returns the
+/// contracts whose confirmed code changed.
+pub fn confirm_contract_code() -> Vec<Address> {
+    let mut confirmed = Vec::new();
+    for ((chain_id, contract), fingerprints) in FINGERPRINTS.lock().unwrap().iter_mut() {
+        if fingerprints.confirmed != fingerprints.current {
+            info!(
+                "Code of {contract} on chain {chain_id} confirmed with hash {}",
+                display_hash(fingerprints.current)
+            );
+            fingerprints.confirmed = fingerprints.current;
+            confirmed.push(*contract);
+        }
+    }
+    update_changed();
+    confirmed
+}
+
+fn update_changed() {
+    let changed = FINGERPRINTS
+        .lock()
+        .unwrap()
+        .values()
+        .any(|fingerprints| fingerprints.confirmed != fingerprints.current);
+    if CHANGED.swap(changed, Ordering::Relaxed) && !changed {
+        info!("Contract code matches what was confirmed, relaying resumed");
+    }
+}
+
+/// Hash of the code of `contract`, followed by the code of its implementation if it is an EIP-1967 proxy
+async fn fingerprint(ctx: &RelayContext, contract: Address) -> Result<[u8; 32], String> {
+    let web3 = ctx.web3();
+    let client = HttpClient::new(&web3.get_url());
+    let mut code = get_code(&client, contract, web3.get_timeout()).await?;
+    let slot: Uint256 = client
+        .request_method(
+            "eth_getStorageAt",
+            (contract.to_string(), IMPLEMENTATION_SLOT, "latest"),
+            web3.get_timeout(),
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+    if !slot.is_zero() {
+        let slot = slot.to_be_bytes();
+        let implementation = Address::from_slice(&slot[12..]).map_err(|e| e.to_string())?;
+        code.extend(get_code(&client, implementation, web3.get_timeout()).await?);
+    }
+    Ok(get_hash(&code))
+}
+
+async fn get_code(
+    client: &HttpClient,
+    contract: Address,
+    timeout: Duration,
+) -> Result<Vec<u8>, String> {
+    let code: String = client
+        .request_method("eth_getCode", (contract.to_string(), "latest"), timeout)
+        .await
+        .map_err(|e| e.to_string())?;
+    hex::decode(code.trim_start_matches("0x")).map_err(|e| e.to_string())
+}
+
+fn display_hash(hash: [u8; 32]) -> String {
+    format!("0x{}", hex::encode(hash))
+}
@@ -0,0 +1,191 @@
+//! Decoding of the Ambient `cmd` carried by a gasless transaction. Commands differ wildly in gas use and risk,
+//! so operators may restrict which of them they are willing to relay.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// Ambient proxy callpath indices
+pub const HOT_PROXY: u16 = 1;
+pub const WARM_PATH: u16 = 2;
+pub const COLD_PATH: u16 = 3;
+pub const LONG_PATH: u16 = 4;
+pub const KNOCKOUT_PATH: u16 = 7;
+
+/// The kind of operation a gasless transaction performs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandKind {
+    Swap,
+    LongForm,
+    MintRange,
+    BurnRange,
+    MintAmbient,
+    BurnAmbient,
+    Harvest,
+    InitPool,
+    ApproveRouter,
+    Surplus,
+    ResetNonce,
+    LimitMint,
+    LimitBurn,
+    LimitClaim,
+    LimitRecover,
+    Other,
+}
+
+const KINDS: &[CommandKind] = &[
+    CommandKind::Swap,
+    CommandKind::LongForm,
+    CommandKind::MintRange,
+    CommandKind::BurnRange,
+    CommandKind::MintAmbient,
+    CommandKind::BurnAmbient,
+    CommandKind::Harvest,
+    CommandKind::InitPool,
+    CommandKind::ApproveRouter,
+    CommandKind::Surplus,
+    CommandKind::ResetNonce,
+    CommandKind::LimitMint,
+    CommandKind::LimitBurn,
+    CommandKind::LimitClaim,
+    CommandKind::LimitRecover,
+    CommandKind::Other,
+];
+
+impl CommandKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CommandKind::Swap => "swap",
+            CommandKind::LongForm => "long-form",
+            CommandKind::MintRange => "mint-range",
+            CommandKind::BurnRange => "burn-range",
+            CommandKind::MintAmbient => "mint-ambient",
+            CommandKind::BurnAmbient => "burn-ambient",
+            CommandKind::Harvest => "harvest",
+            CommandKind::InitPool => "init-pool",
+            CommandKind::ApproveRouter => "approve-router",
+            CommandKind::Surplus => "surplus",
+            CommandKind::ResetNonce => "reset-nonce",
+            CommandKind::LimitMint => "limit-mint",
+            CommandKind::LimitBurn => "limit-burn",
+            CommandKind::LimitClaim => "limit-claim",
+            CommandKind::LimitRecover => "limit-recover",
+            CommandKind::Other => "other",
+        }
+    }
+}
+
+impl fmt::Display for CommandKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// A decoded gasless command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Command {
+    pub callpath: u16,
+    /// the leading command code, swaps and long form orders do not have one
+    pub code: Option<u8>,
+    pub kind: CommandKind,
+}
+
+/// Decodes the command code and kind of `cmd` sent to `callpath`. Unknown callpaths and codes decode as
+/// `CommandKind::Other` rather than failing, the contract is the final authority on what is valid.
+pub fn decode_command(callpath: u16, cmd: &[u8]) -> Command {
+    let code = match callpath {
+        HOT_PROXY | LONG_PATH => None,
+        _ => leading_code(cmd),
+    };
+    let kind = match (callpath, code) {
+        (HOT_PROXY, _) => CommandKind::Swap,
+        (LONG_PATH, _) => CommandKind::LongForm,
+        (WARM_PATH, Some(1 | 11 | 12)) => CommandKind::MintRange,
+        (WARM_PATH, Some(2 | 21 | 22)) => CommandKind::BurnRange,
+        (WARM_PATH, Some(3 | 31 | 32)) => CommandKind::MintAmbient,
+        (WARM_PATH, Some(4 | 41 | 42)) => CommandKind::BurnAmbient,
+        (WARM_PATH, Some(5)) => CommandKind::Harvest,
+        (COLD_PATH, Some(71)) => CommandKind::InitPool,
+        (COLD_PATH, Some(72)) => CommandKind::ApproveRouter,
+        (COLD_PATH, Some(73..=76)) => CommandKind::Surplus,
+        (COLD_PATH, Some(77 | 78)) => CommandKind::ResetNonce,
+        (KNOCKOUT_PATH, Some(91)) => CommandKind::LimitMint,
+        (KNOCKOUT_PATH, Some(92)) => CommandKind::LimitBurn,
+        (KNOCKOUT_PATH, Some(93)) => CommandKind::LimitClaim,
+        (KNOCKOUT_PATH, Some(94)) => CommandKind::LimitRecover,
+        _ => CommandKind::Other,
+    };
+    Command {
+        callpath,
+        code,
+        kind,
+    }
+}
+
+/// The command code is the first ABI word of the command, a `uint8`
+fn leading_code(cmd: &[u8]) -> Option<u8> {
+    let word = cmd.get(..32)?;
+    if word[..31].iter().any(|b| *b != 0) {
+        return None;
+    }
+    Some(word[31])
+}
+
+/// One entry of the command allow list, either a command name such as `swap` or a raw `<callpath>` or
+/// `<callpath>:<code>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandRule {
+    Kind(CommandKind),
+    Callpath(u16, Option<u8>),
+}
+
+impl CommandRule {
+    fn matches(&self, command: &Command) -> bool {
+        match self {
+            CommandRule::Kind(kind) => command.kind == *kind,
+            CommandRule::Callpath(callpath, code) => {
+                command.callpath == *callpath && (code.is_none() || command.code == *code)
+            }
+        }
+    }
+}
+
+impl FromStr for CommandRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(kind) = KINDS.iter().find(|k| k.name() == s) {
+            return Ok(CommandRule::Kind(*kind));
+        }
+        let invalid = || {
+            let names: Vec<&str> = KINDS.iter().map(|k| k.name()).collect();
+            format!(
+                "Invalid command {s}, expected <callpath>, <callpath>:<code> or one of {}",
+                names.join(", ")
+            )
+        };
+        let (callpath, code) = match s.split_once(':') {
+            Some((callpath, code)) => (callpath, Some(code.parse().map_err(|_| invalid())?)),
+            None => (s, None),
+        };
+        Ok(CommandRule::Callpath(
+            callpath.parse().map_err(|_| invalid())?,
+            code,
+        ))
+    }
+}
+
+/// The commands an operator is willing to relay, an empty filter allows everything
+#[derive(Debug, Clone, Default)]
+pub struct CommandFilter {
+    rules: Vec<CommandRule>,
+}
+
+impl CommandFilter {
+    pub fn new(rules: Vec<CommandRule>) -> CommandFilter {
+        CommandFilter { rules }
+    }
+
+    pub fn allows(&self, command: &Command) -> bool {
+        self.rules.is_empty() || self.rules.iter().any(|rule| rule.matches(command))
+    }
+}
@@ -0,0 +1,348 @@
+//! Tracks relays from the moment they are broadcast until they are `confirmation_blocks` deep. Submitting a relay
+//! doesn't wait for it to be included, the relay is left pending here and resolved in the background, and once
+//! included a relay reorged out of the chain can be re-checked and re-submitted instead of silently losing its tip.
+//! Every transaction tracked here has an on chain submission in flight, so it is never evaluated again until it
+//! is final or its submission is gone for good. With a relay store configured every tracked relay is also written
+//! to disk, so that after a restart the relays are watched again and their nonces aren't handed out twice.
+
+use crate::orchestrator::{GaslessTransaction, Orchestrator};
+use crate::signer::Signer;
+use crate::status::unix_timestamp;
+use clarity::utils::display_uint256_as_address;
+use clarity::{Address, Transaction, Uint256};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone)]
+pub enum RelayState {
+    /// broadcast but not yet included
+    Pending {
+        submitted: Instant,
+        /// given up on after timing out or being cancelled, it is still tracked until its nonce is used because
+        /// it may yet land and sending anything else with its nonce would fail
+        abandoned: bool,
+    },
+    /// included, in the given block as (number, hash) once it has been looked up
+    Included { block: Option<(Uint256, Uint256)> },
+}
+
+#[derive(Debug, Clone)]
+pub struct WatchedRelay {
+    pub tx: GaslessTransaction,
+    /// orchestrator the transaction came from, if any
+    pub orchestrator: Option<Orchestrator>,
+    pub chain_id: u64,
+    /// hash of our relay transaction, shared by every gasless transaction in a batch
+    pub tx_hash: Uint256,
+    /// account the relay was sent from, needed to cancel it
+    pub signer: Signer,
+    /// the signed relay, replayed to check whether a competitor got there first
+    pub call: Transaction,
+    pub state: RelayState,
+}
+
+impl WatchedRelay {
+    /// Block the relay was last seen included in
+    pub fn block(&self) -> Option<(Uint256, Uint256)> {
+        match self.state {
+            RelayState::Included { block } => block,
+            RelayState::Pending { .. } => None,
+        }
+    }
+}
+
+/// A tracked relay as written to the relay store
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredRelay {
+    pub chain_id: u64,
+    /// id of the gasless transaction
+    pub sig_hash: String,
+    pub tx: GaslessTransaction,
+    /// url of the orchestrator the transaction came from, if any
+    pub orchestrator: Option<String>,
+    /// hash of our relay transaction
+    pub tx_hash: String,
+    /// account the relay was sent from
+    pub signer: Address,
+    pub nonce: Uint256,
+    /// the relay transaction, kept unsigned as it is only replayed and cancelled
+    pub call: StoredCall,
+    /// unix timestamp of the submission
+    pub submitted_at: u64,
+    pub abandoned: bool,
+    /// already seen included, only waiting for confirmations
+    pub included: bool,
+}
+
+/// Fields of an EIP-1559 relay transaction
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredCall {
+    pub to: Address,
+    /// calldata, hex encoded
+    pub data: String,
+    pub value: Uint256,
+    pub gas_limit: Uint256,
+    pub max_fee_per_gas: Uint256,
+    pub max_priority_fee_per_gas: Uint256,
+}
+
+impl From<&WatchedRelay> for StoredRelay {
+    fn from(relay: &WatchedRelay) -> StoredRelay {
+        let (submitted_at, abandoned) = match relay.state {
+            RelayState::Pending {
+                submitted,
+                abandoned,
+            } => (unix_timestamp() - submitted.elapsed().as_secs(), abandoned),
+            RelayState::Included { .. } => (unix_timestamp(), false),
+        };
+        // relays are always sent as EIP-1559 transactions
+        let (max_fee_per_gas, max_priority_fee_per_gas) = match &relay.call {
+            Transaction::Eip1559 {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+                ..
+            } => (*max_fee_per_gas, *max_priority_fee_per_gas),
+            Transaction::Legacy { gas_price, .. } | Transaction::Eip2930 { gas_price, .. } => {
+                (*gas_price, *gas_price)
+            }
+        };
+        StoredRelay {
+            chain_id: relay.chain_id,
+            sig_hash: relay.tx.id(),
+            tx: relay.tx.clone(),
+            orchestrator: relay.orchestrator.as_ref().map(|o| o.url.clone()),
+            tx_hash: display_uint256_as_address(relay.tx_hash),
+            signer: relay.signer.address(),
+            nonce: relay.call.get_nonce(),
+            call: StoredCall {
+                to: relay.call.get_to(),
+                data: format!("0x{}", hex::encode(relay.call.get_data())),
+                value: relay.call.get_value(),
+                gas_limit: relay.call.get_gas_limit(),
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+            },
+            submitted_at,
+            abandoned,
+            included: matches!(relay.state, RelayState::Included { .. }),
+        }
+    }
+}
+
+static WATCHED: LazyLock<Mutex<Vec<WatchedRelay>>> = LazyLock::new(|| Mutex::new(Vec::new()));
+
+static STORE: LazyLock<Mutex<Option<PathBuf>>> = LazyLock::new(|| Mutex::new(None));
+
+/// How long a nonce floor holds, a transaction that still isn't counted by the node by then was most likely dropped
+/// and its nonce is handed out again rather than leaving a gap every later relay gets stuck behind
+const NONCE_FLOOR_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Next nonce of each account according to its pending pool at startup or to the last transaction sent from it that
+/// isn't watched, with when it was set, by (chain id, address)
+type NonceFloors = HashMap<(u64, Address), (Uint256, Instant)>;
+
+static NONCE_FLOORS: LazyLock<Mutex<NonceFloors>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Writes every tracked relay to `path` from now on
+pub fn set_relay_store(path: Option<PathBuf>) {
+    *STORE.lock().unwrap() = path;
+}
+
+/// Reads the relays left in the store at `path` by a previous run, a missing file means there are none
+pub fn load_relay_store(path: &Path) -> Result<Vec<StoredRelay>, String> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents).map_err(|e| e.to_string()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Rewrites the relay store if one is configured, a failed write is logged since relaying must carry on
+fn persist(watched: &[WatchedRelay]) {
+    let Some(path) = STORE.lock().unwrap().clone() else {
+        return;
+    };
+    let stored: Vec<StoredRelay> = watched.iter().map(StoredRelay::from).collect();
+    let contents = serde_json::to_string(&stored).expect("Relays always serialize");
+    // written to a temporary file first so a crash mid write never leaves a truncated store
+    let tmp = path.with_extension("tmp");
+    if let Err(e) = std::fs::write(&tmp, contents).and_then(|_| std::fs::rename(&tmp, &path)) {
+        warn!("Failed to write relay store {}: {e}", path.display());
+    }
+}
+
+/// Starts watching a relay that was just broadcast on `chain_id`, replacing any earlier relay of the same transaction
+pub fn watch_relay(
+    chain_id: u64,
+    tx: &GaslessTransaction,
+    orchestrator: Option<&Orchestrator>,
+    tx_hash: Uint256,
+    signer: &Signer,
+    call: &Transaction,
+) {
+    let id = tx.sig_hash();
+    let mut watched = WATCHED.lock().unwrap();
+    watched.retain(|w| w.tx.sig_hash() != id);
+    watched.push(WatchedRelay {
+        tx: tx.clone(),
+        orchestrator: orchestrator.cloned(),
+        chain_id,
+        tx_hash,
+        signer: signer.clone(),
+        call: call.clone(),
+        state: RelayState::Pending {
+            submitted: Instant::now(),
+            abandoned: false,
+        },
+    });
+    persist(&watched);
+}
+
+/// Resumes watching a relay from the store of a previous run, sent from `signer`
+pub fn restore_relay(
+    stored: &StoredRelay,
+    signer: &Signer,
+    orchestrator: Option<&Orchestrator>,
+) -> Result<(), String> {
+    let call = Transaction::Eip1559 {
+        chain_id: stored.chain_id.into(),
+        nonce: stored.nonce,
+        max_priority_fee_per_gas: stored.call.max_priority_fee_per_gas,
+        max_fee_per_gas: stored.call.max_fee_per_gas,
+        gas_limit: stored.call.gas_limit,
+        to: stored.call.to,
+        value: stored.call.value,
+        data: hex::decode(stored.call.data.trim_start_matches("0x")).map_err(|e| e.to_string())?,
+        signature: None,
+        access_list: Vec::new(),
+    };
+    let age = Duration::from_secs(unix_timestamp().saturating_sub(stored.submitted_at));
+    let state = if stored.included {
+        RelayState::Included { block: None }
+    } else {
+        RelayState::Pending {
+            submitted: Instant::now().checked_sub(age).unwrap_or_else(Instant::now),
+            abandoned: stored.abandoned,
+        }
+    };
+    let id = stored.tx.sig_hash();
+    let mut watched = WATCHED.lock().unwrap();
+    watched.retain(|w| w.tx.sig_hash() != id);
+    watched.push(WatchedRelay {
+        tx: stored.tx.clone(),
+        orchestrator: orchestrator.cloned(),
+        chain_id: stored.chain_id,
+        tx_hash: Uint256::from_str(&stored.tx_hash).map_err(|e| e.to_string())?,
+        signer: signer.clone(),
+        call,
+        state,
+    });
+    persist(&watched);
+    Ok(())
+}
+
+/// Every relay on `chain_id` that is not yet final
+pub fn watched_relays(chain_id: u64) -> Vec<WatchedRelay> {
+    WATCHED
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|w| w.chain_id == chain_id)
+        .cloned()
+        .collect()
+}
+
+/// True if a relay of `tx` is pending or not yet final, so it must not be relayed again
+pub fn is_watched(tx: &GaslessTransaction) -> bool {
+    let id = tx.sig_hash();
+    WATCHED
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|w| w.tx.sig_hash() == id)
+}
+
+/// True while any relay on any chain is still waiting to be included, abandoned relays aren't waited for
+pub fn has_pending_relays() -> bool {
+    WATCHED.lock().unwrap().iter().any(|w| {
+        matches!(
+            w.state,
+            RelayState::Pending {
+                abandoned: false,
+                ..
+            }
+        )
+    })
+}
+
+/// Gives up on a pending relay of `tx` without forgetting it, see `RelayState::Pending::abandoned`
+pub fn abandon_relay(tx: &GaslessTransaction) {
+    let id = tx.sig_hash();
+    let mut watched = WATCHED.lock().unwrap();
+    if let Some(RelayState::Pending { abandoned, .. }) = watched
+        .iter_mut()
+        .find(|w| w.tx.sig_hash() == id)
+        .map(|w| &mut w.state)
+    {
+        *abandoned = true;
+    }
+    persist(&watched);
+}
+
+/// Records the next nonce of `address`, either according to its pending pool, covering transactions that were sent
+/// before a restart but never made it into the relay store, or after a transaction that isn't watched. A pending pool
+/// below the current floor replaces it, the transactions the floor was held for were dropped.
+pub fn set_nonce_floor(chain_id: u64, address: Address, pending_nonce: Uint256) {
+    NONCE_FLOORS
+        .lock()
+        .unwrap()
+        .insert((chain_id, address), (pending_nonce, Instant::now()));
+}
+
+/// Nonce to send the next transaction from `address` with, `chain_nonce` being its transaction count at the latest
+/// block. Relays still pending have already used the nonces up to theirs.
+pub fn next_nonce(chain_id: u64, address: Address, chain_nonce: Uint256) -> Uint256 {
+    let floor = {
+        let mut floors = NONCE_FLOORS.lock().unwrap();
+        match floors.get(&(chain_id, address)).copied() {
+            // a floor is no longer needed once the node counts it, and no longer trusted once it expired
+            Some((floor, set)) if floor > chain_nonce && set.elapsed() < NONCE_FLOOR_TTL => floor,
+            Some(_) => {
+                floors.remove(&(chain_id, address));
+                Uint256::default()
+            }
+            None => Uint256::default(),
+        }
+    };
+    WATCHED
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|w| w.chain_id == chain_id && w.signer.address() == address)
+        .filter(|w| matches!(w.state, RelayState::Pending { .. }))
+        .map(|w| w.call.get_nonce() + 1u8.into())
+        .fold(chain_nonce.max(floor), Uint256::max)
+}
+
+/// Records the block a watched relay is currently included in
+pub fn update_relay_block(tx: &GaslessTransaction, block: Option<(Uint256, Uint256)>) {
+    let id = tx.sig_hash();
+    let mut watched = WATCHED.lock().unwrap();
+    if let Some(relay) = watched.iter_mut().find(|w| w.tx.sig_hash() == id) {
+        relay.state = RelayState::Included { block };
+    }
+    persist(&watched);
+}
+
+/// Stops watching a relay, either because it is final, failed or was reorged out
+pub fn unwatch_relay(tx: &GaslessTransaction) {
+    let id = tx.sig_hash();
+    let mut watched = WATCHED.lock().unwrap();
+    watched.retain(|w| w.tx.sig_hash() != id);
+    persist(&watched);
+}
@@ -0,0 +1,80 @@
+//! Conflicts between pending transactions. A user command is only accepted while its nonce for the command's salt
+//! matches the user's on chain nonce, so of several transactions signed by the same user for the same salt and
+//! nonce at most one can succeed. Only the best candidate of each such group is evaluated, the others are
+//! deferred while it is being relayed. Once our relay of it is final the nonce has moved on and the others fail
+//! their simulation, if it can't be relayed at all the next candidate gets its turn.
+
+use crate::confirm::{is_watched, watched_relays};
+use crate::orchestrator::GaslessTransaction;
+use crate::relay::{RelayContext, find_chain};
+use crate::retry::{is_backing_off, is_blacklisted, is_cooling_down};
+use crate::skip::SkipReason;
+use crate::status::record_skip;
+use clarity::Address;
+use log::debug;
+use std::collections::HashMap;
+
+/// Chain, user, salt and nonce, transactions sharing these can't both be relayed
+type NonceKey = (u64, Address, [u8; 32], u32);
+
+fn nonce_key(ctx: &RelayContext, tx: &GaslessTransaction) -> Option<NonceKey> {
+    let user = tx.client(ctx.contract_address).ok()?;
+    let conds = tx.decode_conditions().ok()?;
+    Some((ctx.chain_id, user, conds.salt, conds.nonce))
+}
+
+/// Keeps the first transaction of `txs`, which are in the order they are evaluated in, for each user nonce, and
+/// drops transactions whose user nonce is taken by one of our relays that is not yet final. Transactions that
+/// can't be evaluated right now, such as blacklisted ones, don't take the nonce from later candidates. Dropped
+/// transactions are counted as skipped against `orchestrator_url`.
+pub fn resolve_conflicts<'a>(
+    chains: &[RelayContext],
+    orchestrator_url: Option<&str>,
+    txs: Vec<&'a GaslessTransaction>,
+) -> Vec<&'a GaslessTransaction> {
+    // transaction holding each nonce, our own relays first
+    let mut holders: HashMap<NonceKey, [u8; 32]> = HashMap::new();
+    for ctx in chains {
+        for relay in watched_relays(ctx.chain_id) {
+            if let Some(key) = nonce_key(ctx, &relay.tx) {
+                holders.insert(key, relay.tx.sig_hash());
+            }
+        }
+    }
+    if txs.len() < 2 && holders.is_empty() {
+        return txs;
+    }
+    txs.into_iter()
+        .filter(|tx| {
+            let Some(key) = find_chain(chains, tx.chain_id).and_then(|ctx| nonce_key(ctx, tx)) else {
+                return true;
+            };
+            let id = tx.sig_hash();
+            match holders.get(&key) {
+                Some(holder) if *holder != id => {
+                    debug!(
+                        tx:% = tx.id(),
+                        decision = "conflict",
+                        skip_reason:% = SkipReason::Conflict;
+                        "Transaction {} uses the same user nonce as 0x{}, deferring it while that one is relayed",
+                        tx.id(),
+                        hex::encode(holder)
+                    );
+                    record_skip(orchestrator_url, tx, SkipReason::Conflict);
+                    false
+                }
+                Some(_) => true,
+                None => {
+                    if !is_blacklisted(tx)
+                        && !is_watched(tx)
+                        && !is_backing_off(tx)
+                        && !is_cooling_down(tx)
+                    {
+                        holders.insert(key, id);
+                    }
+                    true
+                }
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,110 @@
+//! Guards against relaying into full blocks. A relay whose gas estimate takes up a large share of the block gas
+//! limit competes with everything else for the space and is slow to be included, so transactions estimated above a
+//! configurable fraction of the limit are skipped. While blocks stay close to full, inclusion odds and fees
+//! deteriorate sharply, so relaying on a chain can also pause until the average fullness of its recent blocks falls
+//! back below a threshold. Other chains keep relaying meanwhile.
+
+use crate::gas::block_usage;
+use crate::relay::RelayContext;
+use clarity::Uint256;
+use log::{info, warn};
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+
+#[derive(Debug, Clone)]
+pub struct CongestionPolicy {
+    /// largest share of the block gas limit a relay's gas estimate may take, not checked if None
+    pub max_block_gas_fraction: Option<f64>,
+    /// average share of the gas limit the sampled blocks used at or above which relaying pauses, never paused if
+    /// None
+    pub pause_threshold: Option<f64>,
+    /// number of recent blocks sampled
+    pub blocks: u64,
+}
+
+static POLICY: LazyLock<Mutex<Option<CongestionPolicy>>> = LazyLock::new(|| Mutex::new(None));
+
+/// Gas limit of the latest block on each chain, by chain id
+static BLOCK_GAS_LIMITS: LazyLock<Mutex<HashMap<u64, Uint256>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Chains whose recent blocks were at or above the pause threshold when last checked, by chain id
+static CONGESTED_CHAINS: LazyLock<Mutex<HashMap<u64, bool>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+pub fn set_congestion_policy(policy: Option<CongestionPolicy>) {
+    *POLICY.lock().unwrap() = policy;
+}
+
+/// True while relaying on `chain_id` is paused because its recent blocks are close to full
+pub fn chain_congested(chain_id: u64) -> bool {
+    CONGESTED_CHAINS
+        .lock()
+        .unwrap()
+        .get(&chain_id)
+        .copied()
+        .unwrap_or(false)
+}
+
+/// Most gas a relay on `chain_id` may be estimated at under the block gas limit fraction, None if it isn't checked or
+/// the limit isn't known yet
+pub fn block_gas_cap(chain_id: u64) -> Option<Uint256> {
+    let fraction = POLICY.lock().unwrap().as_ref()?.max_block_gas_fraction?;
+    let limit = *BLOCK_GAS_LIMITS.lock().unwrap().get(&chain_id)?;
+    // parts per million, precise enough for any fraction worth configuring
+    let ppm = (fraction.clamp(0.0, 1.0) * 1_000_000.0).round() as u32;
+    Some(limit * ppm.into() / 1_000_000u32.into())
+}
+
+/// Reads the block gas limit and recent block fullness of every chain, pausing relaying on a chain while its recent
+/// blocks are at or above the threshold. A chain that couldn't be checked keeps its last known state.
+pub async fn check_congestion(chains: &[RelayContext]) {
+    let Some(policy) = POLICY.lock().unwrap().clone() else {
+        return;
+    };
+    for ctx in chains {
+        if policy.max_block_gas_fraction.is_some() {
+            match ctx.web3().eth_get_latest_block().await {
+                Ok(block) => {
+                    BLOCK_GAS_LIMITS
+                        .lock()
+                        .unwrap()
+                        .insert(ctx.chain_id, block.gas_limit);
+                }
+                Err(e) => warn!("Failed to read the block gas limit of {}: {e}", ctx.name),
+            }
+        }
+        let Some(threshold) = policy.pause_threshold else {
+            continue;
+        };
+        let usage = match block_usage(ctx.web3(), policy.blocks).await {
+            Ok(usage) if !usage.is_empty() => usage,
+            Ok(_) => continue,
+            Err(e) => {
+                warn!("Failed to read the block usage of {}: {e}", ctx.name);
+                continue;
+            }
+        };
+        let average = usage.iter().sum::<f64>() / usage.len() as f64;
+        let congested = average >= threshold;
+        let was_congested = CONGESTED_CHAINS
+            .lock()
+            .unwrap()
+            .insert(ctx.chain_id, congested)
+            .unwrap_or(false);
+        if congested && !was_congested {
+            warn!(
+                "The last {} blocks of {} were {:.0}% full on average, at or above the congestion threshold of {:.0}%, relaying on it is paused",
+                usage.len(),
+                ctx.name,
+                average * 100.0,
+                threshold * 100.0
+            );
+        } else if !congested && was_congested {
+            info!(
+                "Blocks of {} are below the congestion threshold again, relaying on it resumed",
+                ctx.name
+            );
+        }
+    }
+}
@@ -0,0 +1,77 @@
+//! Web dashboard served by the status server, a single self-contained page operators can leave open on a
+//! monitor. It polls `/dashboard/data` for the live status, the retry queue, the most recent relays and profit
+//! over time, the latter two read from the relay history so they need `--history-db`. The history is read on a
+//! connection of its own, so an open dashboard never holds up recording relays.
+
+use crate::admin::{QueuedRetry, RelayStats, is_paused, queued_retries, relay_stats};
+use crate::history::{ProfitBucket, RelayRecord, profit_by_period, recent_relays};
+use crate::status::{RelayerStatus, get_status, unix_timestamp};
+use actix_web::{HttpResponse, Responder, web};
+use serde::{Deserialize, Serialize};
+
+const PAGE: &str = include_str!("dashboard.html");
+
+/// Relays listed as recent
+const RECENT_RELAYS: u32 = 50;
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+/// Hours charted hourly and days charted daily
+const HOURLY_PERIODS: u64 = 48;
+const DAILY_PERIODS: u64 = 30;
+
+/// Relays and profit read from the relay history
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DashboardHistory {
+    /// newest first
+    pub recent: Vec<RelayRecord>,
+    pub hourly: Vec<ProfitBucket>,
+    pub daily: Vec<ProfitBucket>,
+}
+
+/// Body of `/dashboard/data`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DashboardData {
+    pub now: u64,
+    pub paused: bool,
+    pub relays: RelayStats,
+    pub retries: Vec<QueuedRetry>,
+    pub status: RelayerStatus,
+    pub history: Option<DashboardHistory>,
+    /// why there is no history, usually that none is being recorded
+    pub history_error: Option<String>,
+}
+
+fn read_history(now: u64) -> Result<DashboardHistory, String> {
+    Ok(DashboardHistory {
+        recent: recent_relays(RECENT_RELAYS)?,
+        hourly: profit_by_period(now.saturating_sub((HOURLY_PERIODS - 1) * HOUR), HOUR)?,
+        daily: profit_by_period(now.saturating_sub((DAILY_PERIODS - 1) * DAY), DAY)?,
+    })
+}
+
+pub async fn dashboard() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(PAGE)
+}
+
+pub async fn dashboard_data() -> impl Responder {
+    let now = unix_timestamp();
+    // queries of a large history take a while, they run on the blocking pool rather than the server's worker
+    let history = web::block(move || read_history(now))
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()));
+    let (history, history_error) = match history {
+        Ok(history) => (Some(history), None),
+        Err(e) => (None, Some(e)),
+    };
+    HttpResponse::Ok().json(DashboardData {
+        now,
+        paused: is_paused(),
+        relays: relay_stats(),
+        retries: queued_retries(),
+        status: get_status(),
+        history,
+        history_error,
+    })
+}
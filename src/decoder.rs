@@ -0,0 +1,181 @@
+//! Human readable decoding of every field of a gasless transaction, so that working out why a transaction
+//! was or wasn't relayed doesn't require decoding the raw ABI blobs by hand.
+
+use crate::command::{
+    COLD_PATH, Command, HOT_PROXY, KNOCKOUT_PATH, LONG_PATH, WARM_PATH, decode_command,
+};
+use crate::orchestrator::{GaslessTransaction, RelayConditions, RelayerTip};
+use clarity::Address;
+use clarity::abi::{
+    parse_address, parse_bool, parse_i32, parse_u8, parse_u16, parse_u128, parse_uint256,
+};
+use std::fmt;
+
+/// Every field of a gasless transaction decoded, fields that fail to decode hold the reason why
+#[derive(Debug, Clone)]
+pub struct DecodedTransaction {
+    pub id: String,
+    pub chain_id: u64,
+    pub command: Command,
+    /// named parameters of the command, in ABI order
+    pub params: Result<Vec<(&'static str, String)>, String>,
+    pub tip: Result<RelayerTip, String>,
+    pub conditions: Result<RelayConditions, String>,
+    /// signature as (v, r, s)
+    pub signature: Option<(u8, [u8; 32], [u8; 32])>,
+    /// user who signed the transaction
+    pub signer: Result<Address, String>,
+}
+
+/// Decodes `tx`, recovering the signer as the dex at `dex_address` would
+pub fn decode_transaction(tx: &GaslessTransaction, dex_address: Address) -> DecodedTransaction {
+    let command = decode_command(tx.callpath, &tx.cmd);
+    DecodedTransaction {
+        id: tx.id(),
+        chain_id: tx.chain_id,
+        command,
+        params: decode_params(&command, &tx.cmd).map_err(|e| e.to_string()),
+        tip: tx.decode_tip().map_err(|e| e.to_string()),
+        conditions: tx.decode_conditions().map_err(|e| e.to_string()),
+        signature: (tx.sig.len() == 96).then(|| {
+            (
+                tx.sig[31],
+                tx.sig[32..64].try_into().unwrap(),
+                tx.sig[64..96].try_into().unwrap(),
+            )
+        }),
+        signer: tx.client(dex_address).map_err(|e| e.to_string()),
+    }
+}
+
+/// Name of an Ambient proxy callpath
+pub fn callpath_name(callpath: u16) -> &'static str {
+    match callpath {
+        HOT_PROXY => "hot proxy",
+        WARM_PATH => "warm path",
+        COLD_PATH => "cold path",
+        LONG_PATH => "long path",
+        KNOCKOUT_PATH => "knockout path",
+        _ => "unknown",
+    }
+}
+
+/// Decodes the parameters of the commands whose layout is known, others only have their length reported
+pub fn decode_params(
+    command: &Command,
+    cmd: &[u8],
+) -> Result<Vec<(&'static str, String)>, clarity::Error> {
+    let params = match (command.callpath, command.code) {
+        // swap(base, quote, poolIdx, isBuy, inBaseQty, qty, tip, limitPrice, minOut, reserveFlags)
+        (HOT_PROXY, _) => vec![
+            ("base", parse_address(cmd, 0)?.to_string()),
+            ("quote", parse_address(cmd, 32)?.to_string()),
+            ("pool index", parse_uint256(cmd, 64)?.to_string()),
+            ("is buy", parse_bool(cmd, 96)?.to_string()),
+            ("in base qty", parse_bool(cmd, 128)?.to_string()),
+            ("qty", parse_u128(cmd, 160)?.to_string()),
+            ("pool tip", parse_u16(cmd, 192)?.to_string()),
+            ("limit price", parse_u128(cmd, 224)?.to_string()),
+            ("min out", parse_u128(cmd, 256)?.to_string()),
+            ("reserve flags", parse_u8(cmd, 288)?.to_string()),
+        ],
+        // (code, base, quote, poolIdx, bidTick, askTick, liq, limitLower, limitHigher, reserveFlags, lpConduit)
+        (WARM_PATH, Some(_)) => vec![
+            ("base", parse_address(cmd, 32)?.to_string()),
+            ("quote", parse_address(cmd, 64)?.to_string()),
+            ("pool index", parse_uint256(cmd, 96)?.to_string()),
+            ("bid tick", parse_i32(cmd, 128)?.to_string()),
+            ("ask tick", parse_i32(cmd, 160)?.to_string()),
+            ("liquidity", parse_u128(cmd, 192)?.to_string()),
+            ("limit lower", parse_u128(cmd, 224)?.to_string()),
+            ("limit higher", parse_u128(cmd, 256)?.to_string()),
+            ("reserve flags", parse_u8(cmd, 288)?.to_string()),
+            ("lp conduit", parse_address(cmd, 320)?.to_string()),
+        ],
+        // initPool(code, base, quote, poolIdx, price)
+        (COLD_PATH, Some(71)) => vec![
+            ("base", parse_address(cmd, 32)?.to_string()),
+            ("quote", parse_address(cmd, 64)?.to_string()),
+            ("pool index", parse_uint256(cmd, 96)?.to_string()),
+            ("price", parse_u128(cmd, 128)?.to_string()),
+        ],
+        // (code, base, quote, poolIdx, bytes args), the args differ per knockout command
+        (KNOCKOUT_PATH, Some(_)) => vec![
+            ("base", parse_address(cmd, 32)?.to_string()),
+            ("quote", parse_address(cmd, 64)?.to_string()),
+            ("pool index", parse_uint256(cmd, 96)?.to_string()),
+        ],
+        _ => vec![("length", format!("{} bytes", cmd.len()))],
+    };
+    Ok(params)
+}
+
+/// Names the special tip receivers understood by the dex
+pub fn describe_receiver(receiver: Address) -> String {
+    match receiver.to_string().to_lowercase().as_str() {
+        "0x0000000000000000000000000000000000000100" => "msg.sender".to_string(),
+        "0x0000000000000000000000000000000000000200" => "tx.origin".to_string(),
+        _ => receiver.to_string(),
+    }
+}
+
+impl fmt::Display for DecodedTransaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "id:              {}", self.id)?;
+        writeln!(f, "chain id:        {}", self.chain_id)?;
+        writeln!(
+            f,
+            "callpath:        {} ({})",
+            self.command.callpath,
+            callpath_name(self.command.callpath)
+        )?;
+        match self.command.code {
+            Some(code) => writeln!(f, "command:         {} (code {code})", self.command.kind)?,
+            None => writeln!(f, "command:         {}", self.command.kind)?,
+        }
+        match &self.params {
+            Ok(params) => {
+                for (name, value) in params {
+                    writeln!(f, "  {:<15}{value}", format!("{name}:"))?;
+                }
+            }
+            Err(e) => writeln!(f, "  invalid command, {e}")?,
+        }
+        match &self.tip {
+            Ok(tip) => {
+                writeln!(f, "tip token:       {}", tip.token)?;
+                writeln!(f, "tip amount:      {}", tip.amount)?;
+                writeln!(f, "tip receiver:    {}", describe_receiver(tip.receiver))?;
+            }
+            Err(e) => writeln!(f, "tip:             invalid, {e}")?,
+        }
+        match &self.conditions {
+            Ok(conds) => {
+                // a zero deadline means the command never expires
+                if conds.deadline == 0 {
+                    writeln!(f, "deadline:        none")?;
+                } else {
+                    writeln!(f, "deadline:        {}", conds.deadline)?;
+                }
+                writeln!(f, "alive:           {}", conds.alive)?;
+                writeln!(f, "salt:            0x{}", hex::encode(conds.salt))?;
+                writeln!(f, "nonce:           {}", conds.nonce)?;
+                if conds.relayer == Address::default() {
+                    writeln!(f, "relayer:         any")?;
+                } else {
+                    writeln!(f, "relayer:         {}", conds.relayer)?;
+                }
+            }
+            Err(e) => writeln!(f, "conditions:      invalid, {e}")?,
+        }
+        if let Some((v, r, s)) = &self.signature {
+            writeln!(f, "sig v:           {v}")?;
+            writeln!(f, "sig r:           0x{}", hex::encode(r))?;
+            writeln!(f, "sig s:           0x{}", hex::encode(s))?;
+        }
+        match &self.signer {
+            Ok(signer) => writeln!(f, "signer:          {signer}"),
+            Err(e) => writeln!(f, "signer:          unknown, {e}"),
+        }
+    }
+}
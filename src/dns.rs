@@ -0,0 +1,212 @@
+//! Resolution of orchestrator hosts. Resolving on every poll adds a lookup's latency to each cycle, so addresses
+//! are cached for a configurable time, the system resolver doesn't expose the TTL of the records themselves. If a
+//! lookup fails once the cache expires the previous addresses keep being used. Operators can also pin the
+//! addresses of an orchestrator, which are then used without any lookup.
+//! Several addresses of one orchestrator are treated as failover targets for the same queue rather than as
+//! separate sources. The address that last answered is tried first, the others only when it fails or takes
+//! longer than the connection attempt delay to answer. Resolved addresses alternate between IPv6 and IPv4,
+//! starting with the preferred family, so that a host whose addresses of one family are unreachable is still
+//! reached quickly on the other, as in happy eyeballs (RFC 8305).
+
+use crate::orchestrator::{GRPC_SCHEME, GRPCS_SCHEME};
+use clap::ValueEnum;
+use log::{info, warn};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+/// RFC 8305's recommended delay before attempting the next address
+const DEFAULT_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Which address families orchestrators are reached over, and which is tried first
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum IpPreference {
+    #[default]
+    PreferIpv6,
+    PreferIpv4,
+    Ipv6Only,
+    Ipv4Only,
+}
+
+impl IpPreference {
+    /// Orders `addresses` alternating between families, starting with the preferred one, or drops the other
+    /// family entirely
+    fn order(self, addresses: Vec<SocketAddr>) -> Vec<SocketAddr> {
+        let (v6, v4): (Vec<_>, Vec<_>) = addresses.into_iter().partition(SocketAddr::is_ipv6);
+        let (first, second) = match self {
+            IpPreference::PreferIpv6 => (v6, v4),
+            IpPreference::PreferIpv4 => (v4, v6),
+            IpPreference::Ipv6Only => (v6, Vec::new()),
+            IpPreference::Ipv4Only => (v4, Vec::new()),
+        };
+        let mut ordered = Vec::with_capacity(first.len() + second.len());
+        let (mut first, mut second) = (first.into_iter(), second.into_iter());
+        loop {
+            match (first.next(), second.next()) {
+                (None, None) => return ordered,
+                (a, b) => ordered.extend(a.into_iter().chain(b)),
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Resolver {
+    /// how long resolved addresses are used for, zero resolves on every poll
+    ttl: Duration,
+    preference: IpPreference,
+    /// how long an address may go unanswered before the next one is tried alongside it
+    attempt_delay: Duration,
+    /// addresses operators pinned, by orchestrator url
+    pinned: HashMap<String, Vec<SocketAddr>>,
+    /// resolved addresses and when they were resolved, by orchestrator url
+    cache: HashMap<String, (Instant, Vec<SocketAddr>)>,
+    /// address of each orchestrator that last answered
+    preferred: HashMap<String, SocketAddr>,
+}
+
+static RESOLVER: LazyLock<Mutex<Resolver>> = LazyLock::new(|| {
+    Mutex::new(Resolver {
+        ttl: Duration::ZERO,
+        preference: IpPreference::default(),
+        attempt_delay: DEFAULT_ATTEMPT_DELAY,
+        pinned: HashMap::new(),
+        cache: HashMap::new(),
+        preferred: HashMap::new(),
+    })
+});
+
+pub fn set_dns_cache_ttl(ttl: Duration) {
+    RESOLVER.lock().unwrap().ttl = ttl;
+}
+
+pub fn set_ip_preference(preference: IpPreference) {
+    RESOLVER.lock().unwrap().preference = preference;
+}
+
+pub fn set_attempt_delay(delay: Duration) {
+    RESOLVER.lock().unwrap().attempt_delay = delay;
+}
+
+/// How long an orchestrator address may go unanswered before the next one is tried alongside it
+pub fn attempt_delay() -> Duration {
+    RESOLVER.lock().unwrap().attempt_delay
+}
+
+/// Pins orchestrator addresses given as `<URL>=<IP>[:<PORT>]` entries, several entries for one url are failover
+/// targets in the order given. Without a port the url's port is used.
+pub fn set_pinned_ips(entries: &[String]) -> Result<(), String> {
+    let mut pinned: HashMap<String, Vec<SocketAddr>> = HashMap::new();
+    for entry in entries {
+        let Some((url, address)) = entry.rsplit_once('=') else {
+            return Err(format!(
+                "Invalid orchestrator ip {entry}, expected <URL>=<IP>[:<PORT>]"
+            ));
+        };
+        let address = match address.parse::<SocketAddr>() {
+            Ok(address) => address,
+            Err(_) => {
+                let ip = address
+                    .trim_matches(|c| c == '[' || c == ']')
+                    .parse::<IpAddr>()
+                    .map_err(|e| format!("Invalid orchestrator ip {entry}: {e}"))?;
+                SocketAddr::new(ip, url_port(url))
+            }
+        };
+        pinned
+            .entry(url.trim_end_matches('/').to_string())
+            .or_default()
+            .push(address);
+    }
+    RESOLVER.lock().unwrap().pinned = pinned;
+    Ok(())
+}
+
+/// Host and port part of an orchestrator url
+fn authority(url: &str) -> &str {
+    let rest = url
+        .strip_prefix("http://")
+        .or_else(|| url.strip_prefix("https://"))
+        .or_else(|| url.strip_prefix(GRPC_SCHEME))
+        .or_else(|| url.strip_prefix(GRPCS_SCHEME))
+        .unwrap_or(url);
+    rest.split('/').next().unwrap_or(rest)
+}
+
+fn url_port(url: &str) -> u16 {
+    let authority = authority(url);
+    match authority.rsplit_once(':') {
+        Some((_, port)) if !authority.ends_with(']') => port.parse().unwrap_or(80),
+        _ if url.starts_with("https://") || url.starts_with(GRPCS_SCHEME) => 443,
+        _ => 80,
+    }
+}
+
+/// Addresses of the orchestrator at `url` in the order to try them, the one that last answered first. Pinned
+/// addresses are kept in the order given, resolved ones are ordered by family.
+pub fn resolve(url: &str) -> Result<Vec<SocketAddr>, String> {
+    let key = url.trim_end_matches('/');
+    let mut resolver = RESOLVER.lock().unwrap();
+    let mut addresses = match resolver.pinned.get(key) {
+        Some(pinned) => pinned.clone(),
+        None => match resolver.cache.get(key) {
+            Some((resolved_at, addresses)) if resolved_at.elapsed() < resolver.ttl => {
+                addresses.clone()
+            }
+            cached => {
+                let stale = cached.map(|(_, addresses)| addresses.clone());
+                // the lookup blocks, so the lock is released for it
+                drop(resolver);
+                let result = authority(url)
+                    .to_socket_addrs()
+                    .map(|addresses| addresses.collect::<Vec<_>>())
+                    .map_err(|e| format!("Failed to resolve orchestrator URL: {e}"));
+                resolver = RESOLVER.lock().unwrap();
+                match (result, stale) {
+                    (Ok(addresses), _) if !addresses.is_empty() => {
+                        resolver
+                            .cache
+                            .insert(key.to_string(), (Instant::now(), addresses.clone()));
+                        addresses
+                    }
+                    (Ok(_), Some(stale)) | (Err(_), Some(stale)) if !stale.is_empty() => {
+                        warn!("Failed to resolve {url}, using the addresses it last resolved to");
+                        stale
+                    }
+                    (Ok(addresses), _) => addresses,
+                    (Err(e), _) => return Err(e),
+                }
+            }
+        },
+    };
+    if !resolver.pinned.contains_key(key) {
+        addresses = resolver.preference.order(addresses);
+        if addresses.is_empty() {
+            return Err(format!(
+                "Orchestrator {url} has no addresses of the allowed family"
+            ));
+        }
+    }
+    if let Some(preferred) = resolver.preferred.get(key)
+        && let Some(index) = addresses.iter().position(|address| address == preferred)
+    {
+        addresses[..=index].rotate_right(1);
+    }
+    Ok(addresses)
+}
+
+/// Records that `address` of the orchestrator at `url` answered, it is tried first from now on
+pub fn record_answer(url: &str, address: SocketAddr) {
+    let key = url.trim_end_matches('/');
+    let previous = RESOLVER
+        .lock()
+        .unwrap()
+        .preferred
+        .insert(key.to_string(), address);
+    if let Some(previous) = previous
+        && previous != address
+    {
+        info!("Orchestrator {url} failed over from {previous} to {address}");
+    }
+}
@@ -0,0 +1,220 @@
+//! Errors of the relay pipeline. Each variant is classified by what the relay loop should do with the
+//! transaction that hit it: retry it later, never attempt it again, or just report the failure.
+
+use crate::blob::DecodeError;
+use crate::revert::revert_reason;
+use crate::skip::SkipReason;
+use clarity::Uint256;
+use std::fmt::{self, Display};
+use web30::jsonrpc::error::Web3Error;
+
+#[derive(Debug)]
+pub enum RelayerError {
+    /// an orchestrator could not be reached or rejected the request
+    OrchestratorError(String),
+    /// the value of the tip could not be looked up
+    PriceError(String),
+    /// an RPC call failed in a way that is likely to go away on its own, such as a timeout or an unreachable node
+    RpcTransient(Web3Error),
+    /// an RPC call was rejected by the node
+    RpcError(Web3Error),
+    /// the relay reverts, either when estimated or simulated or once included on chain
+    SimulationRevert(String),
+    /// the tip is not worth the cost of relaying
+    Unprofitable,
+    /// the transaction is malformed and can never be relayed
+    InvalidTx(String),
+    /// a field of the transaction is not encoded the way the dex expects, it can never be relayed
+    Malformed(DecodeError),
+    /// the remote signer could not be reached
+    SignerTransient(String),
+    /// signing failed or produced an unexpected signature
+    SignerError(String),
+    /// a competitor relayed the transaction first, or it stopped being valid while our relay was pending
+    AlreadyRelayed(String),
+    /// the relay was broadcast but its outcome is unknown, it must not be submitted again
+    Unconfirmed(String),
+    /// the RPC endpoint is on a different chain than the transaction is for
+    WrongChain { expected: u64, actual: u64 },
+    /// relaying could take the relayer's balance below the reserve it must keep
+    BelowReserve { balance: Uint256, reserve: Uint256 },
+    /// tracing the relay showed it doesn't pay the tip it claims or moves funds out of our accounts
+    UnexpectedEffects(String),
+    /// the transaction was deliberately not relayed
+    Skipped(SkipReason),
+    /// the history database couldn't record something that has to be recorded before sending
+    HistoryError(String),
+    /// gas spending reached its cap, nothing more is sent until it falls below it
+    SpendCapReached,
+}
+
+/// What to do with a transaction whose relay failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureKind {
+    /// the failure is likely to go away on its own, try again after a backoff
+    Retry,
+    /// the transaction can never be relayed, ignore it from now on
+    Blacklist,
+    /// record the failure, the transaction is evaluated afresh if an orchestrator serves it again
+    Report,
+}
+
+impl RelayerError {
+    pub fn orchestrator(e: impl Display) -> Self {
+        RelayerError::OrchestratorError(e.to_string())
+    }
+
+    pub fn price(e: impl Display) -> Self {
+        RelayerError::PriceError(e.to_string())
+    }
+
+    pub fn invalid_tx(e: impl Display) -> Self {
+        RelayerError::InvalidTx(e.to_string())
+    }
+
+    pub fn signer(e: impl Display) -> Self {
+        RelayerError::SignerError(e.to_string())
+    }
+
+    pub fn kind(&self) -> FailureKind {
+        match self {
+            RelayerError::OrchestratorError(_)
+            | RelayerError::PriceError(_)
+            | RelayerError::RpcTransient(_)
+            | RelayerError::SignerTransient(_)
+            | RelayerError::BelowReserve { .. }
+            | RelayerError::HistoryError(_)
+            | RelayerError::SpendCapReached => FailureKind::Retry,
+            RelayerError::InvalidTx(_)
+            | RelayerError::Malformed(_)
+            | RelayerError::UnexpectedEffects(_) => FailureKind::Blacklist,
+            RelayerError::RpcError(_)
+            | RelayerError::SimulationRevert(_)
+            | RelayerError::Unprofitable
+            | RelayerError::AlreadyRelayed(_)
+            | RelayerError::SignerError(_)
+            | RelayerError::Unconfirmed(_)
+            | RelayerError::WrongChain { .. }
+            | RelayerError::Skipped(_) => FailureKind::Report,
+        }
+    }
+
+    /// Why the transaction wasn't relayed, for errors that mean it was passed over rather than that relaying it
+    /// failed. Reverts and rejected traces count as skips as long as the relay wasn't broadcast.
+    pub fn skip_reason(&self) -> Option<SkipReason> {
+        match self {
+            RelayerError::Skipped(reason) => Some(*reason),
+            RelayerError::Unprofitable => Some(SkipReason::Unprofitable),
+            RelayerError::AlreadyRelayed(_) => Some(SkipReason::NonceConsumed),
+            RelayerError::InvalidTx(_) | RelayerError::Malformed(_) => {
+                Some(SkipReason::DecodeError)
+            }
+            RelayerError::SimulationRevert(_) => Some(SkipReason::SimulationFailed),
+            RelayerError::UnexpectedEffects(_) => Some(SkipReason::TraceRejected),
+            _ => None,
+        }
+    }
+
+    /// True if the error points at the RPC endpoint itself being unreachable or unhealthy
+    pub fn is_endpoint_failure(&self) -> bool {
+        matches!(
+            self,
+            RelayerError::RpcTransient(
+                Web3Error::FailedToSend(_) | Web3Error::BadResponse(_) | Web3Error::SyncingNode(_)
+            )
+        )
+    }
+}
+
+impl Display for RelayerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RelayerError::OrchestratorError(e) => write!(f, "Orchestrator request failed: {e}"),
+            RelayerError::PriceError(e) => write!(f, "Price lookup failed: {e}"),
+            RelayerError::RpcTransient(e) | RelayerError::RpcError(e) => write!(f, "{e}"),
+            RelayerError::SimulationRevert(reason) => write!(f, "Reverted: {reason}"),
+            RelayerError::Unprofitable => write!(f, "Tip does not cover the cost of relaying"),
+            RelayerError::InvalidTx(e) => write!(f, "Invalid transaction: {e}"),
+            RelayerError::Malformed(e) => write!(f, "Malformed transaction: {e}"),
+            RelayerError::AlreadyRelayed(reason) => {
+                write!(f, "Already relayed or no longer valid: {reason}")
+            }
+            RelayerError::SignerTransient(e) => write!(f, "Remote signer failed: {e}"),
+            RelayerError::SignerError(e) => write!(f, "Signing failed: {e}"),
+            RelayerError::Unconfirmed(e) => write!(f, "{e}"),
+            RelayerError::WrongChain { expected, actual } => {
+                write!(f, "RPC endpoint is on chain {actual}, expected {expected}")
+            }
+            RelayerError::BelowReserve { balance, reserve } => write!(
+                f,
+                "Relaying could take the balance of {balance} wei below the reserve of {reserve} wei"
+            ),
+            RelayerError::UnexpectedEffects(e) => write!(f, "Unexpected effects when traced: {e}"),
+            RelayerError::Skipped(reason) => write!(f, "Skipped: {reason}"),
+            RelayerError::HistoryError(e) => write!(f, "History database failed: {e}"),
+            RelayerError::SpendCapReached => write!(f, "Gas spending reached its cap"),
+        }
+    }
+}
+
+impl std::error::Error for RelayerError {}
+
+/// JSON-RPC error messages of nodes that are overloaded or briefly behind, matched in lower case. Any other error a
+/// node returns, such as a nonce that is too low or funds that fall short, is reported rather than retried.
+const TRANSIENT_RPC_ERRORS: &[&str] = &[
+    "rate limit",
+    "too many requests",
+    "limit exceeded",
+    "timeout",
+    "timed out",
+    "header not found",
+    "unknown block",
+    "busy",
+    "try again",
+    "temporarily unavailable",
+];
+
+/// Reverts become `SimulationRevert` with the decoded reason, node and network failures and the known transient
+/// JSON-RPC errors are transient and anything else the node rejected is permanent
+impl From<Web3Error> for RelayerError {
+    fn from(e: Web3Error) -> Self {
+        match &e {
+            Web3Error::JsonRpcError { message, .. } => {
+                if let Some(reason) = revert_reason(&e) {
+                    return RelayerError::SimulationRevert(reason);
+                }
+                let lower = message.to_lowercase();
+                if lower.contains("revert") {
+                    RelayerError::SimulationRevert(message.clone())
+                } else if TRANSIENT_RPC_ERRORS
+                    .iter()
+                    .any(|transient| lower.contains(transient))
+                {
+                    RelayerError::RpcTransient(e)
+                } else {
+                    RelayerError::RpcError(e)
+                }
+            }
+            Web3Error::FailedToSend(_)
+            | Web3Error::BadResponse(_)
+            | Web3Error::TransactionTimeout
+            | Web3Error::SyncingNode(_)
+            | Web3Error::NoBlockProduced { .. }
+            | Web3Error::InsufficientGas { .. } => RelayerError::RpcTransient(e),
+            _ => RelayerError::RpcError(e),
+        }
+    }
+}
+
+impl From<DecodeError> for RelayerError {
+    fn from(e: DecodeError) -> Self {
+        RelayerError::Malformed(e)
+    }
+}
+
+/// ABI encoding and decoding failures, which only happen on malformed transaction fields
+impl From<clarity::Error> for RelayerError {
+    fn from(e: clarity::Error) -> Self {
+        RelayerError::InvalidTx(e.to_string())
+    }
+}
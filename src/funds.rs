@@ -0,0 +1,157 @@
+//! Checks that the user of a transaction can pay for it before it is simulated. Queues regularly hold transactions
+//! from wallets that were drained after signing, and those fail estimation again every cycle. Reading a balance and
+//! an allowance is cheaper than an estimate, so with the check enabled a transaction is skipped unless its user holds
+//! the input of a swap whose input amount is fixed, and has approved the dex to spend it. The tip isn't checked, it
+//! is debited from the user's surplus collateral rather than their wallet. Balances and allowances are read once per
+//! cycle, every transaction of a user in that cycle is checked against the same reads.
+
+use crate::command::HOT_PROXY;
+use crate::error::RelayerError;
+use crate::orchestrator::GaslessTransaction;
+use crate::relay::RelayContext;
+use crate::tokens::display_token;
+use clarity::abi::{parse_address, parse_bool, parse_u8, parse_u128};
+use clarity::{Address, Uint256};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Display};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LazyLock, Mutex};
+
+/// Reserve flag of a swap settling its base side with the user's surplus collateral instead of their wallet
+const BASE_FROM_SURPLUS: u8 = 0x1;
+/// Reserve flag of a swap settling its quote side with the user's surplus collateral instead of their wallet
+const QUOTE_FROM_SURPLUS: u8 = 0x2;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Balances and allowances read this cycle, by (chain id, user, token), the allowance is `None` until it is read
+type Reads = HashMap<(u64, Address, Address), (Uint256, Option<Uint256>)>;
+
+static READ: LazyLock<Mutex<Reads>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+pub fn set_funds_check(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn funds_check_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Forgets the balances and allowances read so far, called at the start of each cycle
+pub fn clear_funds_cache() {
+    READ.lock().unwrap().clear();
+}
+
+/// A token the user of a transaction can't pay enough of
+#[derive(Debug, Clone)]
+pub struct Shortfall {
+    pub chain_id: u64,
+    pub user: Address,
+    pub token: Address,
+    pub needed: Uint256,
+    /// the user's balance, or their allowance to the dex if that is what falls short
+    pub available: Uint256,
+    pub allowance: bool,
+}
+
+impl Display for Shortfall {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} of {} but needs {}",
+            self.user,
+            if self.allowance {
+                "approved the dex for"
+            } else {
+                "holds"
+            },
+            self.available,
+            display_token(self.chain_id, self.token),
+            self.needed
+        )
+    }
+}
+
+/// Input of the swap `cmd` as (token, amount) if it comes from the user's wallet and its amount is fixed. A swap
+/// fixing its output instead only bounds its input by the limit price, so nothing is required of it.
+fn swap_input(cmd: &[u8]) -> Option<(Address, Uint256)> {
+    let base = parse_address(cmd, 0).ok()?;
+    let quote = parse_address(cmd, 32).ok()?;
+    let is_buy = parse_bool(cmd, 96).ok()?;
+    let in_base_qty = parse_bool(cmd, 128).ok()?;
+    let qty = parse_u128(cmd, 160).ok()?;
+    let reserve_flags = parse_u8(cmd, 288).ok()?;
+    // buying pays in base, selling pays in quote
+    let (token, from_surplus) = if is_buy {
+        (base, reserve_flags & BASE_FROM_SURPLUS != 0)
+    } else {
+        (quote, reserve_flags & QUOTE_FROM_SURPLUS != 0)
+    };
+    (is_buy == in_base_qty && !from_surplus).then_some((token, qty.into()))
+}
+
+/// Amount of each token relaying `tx` takes from its user's wallet, as far as it can be decoded
+fn required_funds(tx: &GaslessTransaction) -> BTreeMap<Address, Uint256> {
+    let mut required = BTreeMap::new();
+    if tx.callpath == HOT_PROXY
+        && let Some((token, amount)) = swap_input(&tx.cmd)
+        && !amount.is_zero()
+    {
+        *required.entry(token).or_default() += amount;
+    }
+    required
+}
+
+/// Checks that `user` holds and has approved the dex of `ctx` for everything relaying `tx` takes from their wallet,
+/// returning what falls short first. The native token has no allowance, only its balance is checked.
+pub async fn check_funds(
+    ctx: &RelayContext,
+    tx: &GaslessTransaction,
+    user: Address,
+) -> Result<Option<Shortfall>, RelayerError> {
+    let web3 = ctx.web3();
+    for (token, needed) in required_funds(tx) {
+        let key = (ctx.chain_id, user, token);
+        let shortfall = |available, allowance| Shortfall {
+            chain_id: ctx.chain_id,
+            user,
+            token,
+            needed,
+            available,
+            allowance,
+        };
+        let read = READ.lock().unwrap().get(&key).copied();
+        let balance = match read {
+            Some((balance, _)) => balance,
+            None => {
+                let balance = if token == Address::default() {
+                    web3.eth_get_balance(user).await?
+                } else {
+                    web3.get_erc20_balance(token, user, Vec::new()).await?
+                };
+                READ.lock().unwrap().insert(key, (balance, None));
+                balance
+            }
+        };
+        if balance < needed {
+            return Ok(Some(shortfall(balance, false)));
+        }
+        if token == Address::default() {
+            continue;
+        }
+        let allowance = match read.and_then(|(_, allowance)| allowance) {
+            Some(allowance) => allowance,
+            None => {
+                let allowance = web3
+                    .get_erc20_allowance(token, user, ctx.contract_address, Vec::new())
+                    .await?;
+                READ.lock().unwrap().insert(key, (balance, Some(allowance)));
+                allowance
+            }
+        };
+        if allowance < needed {
+            return Ok(Some(shortfall(allowance, true)));
+        }
+    }
+    Ok(None)
+}
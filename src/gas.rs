@@ -0,0 +1,456 @@
+//! Fee selection from recent blocks. Rather than taking `eth_gasPrice` at face value the oracle samples the
+//! priority fees paid in the last few blocks through `eth_feeHistory` and picks one at a configurable percentile.
+//! The normal profile uses a low percentile to stay profitable while blocks are calm, the aggressive profile a
+//! high one to get included quickly while they are congested, and the auto profile switches between them based
+//! on how full the sampled blocks were.
+//!
+//! The fee history also gives a band of what a relay may end up paying per gas by the time it is included, from
+//! an optimistic low percentile to a pessimistic high one. With the band enabled, tips have to clear the
+//! pessimistic end rather than the price of the moment, so a relay that only pays while fees are calm isn't sent
+//! just before they rise.
+//!
+//! Relays can also carry an EIP-2930 access list from `eth_createAccessList`, which prepays the storage the dex
+//! touches at a discount. It is only kept when it lowers the gas estimate.
+//!
+//! Successful gas estimates are cached per gasless transaction for a short while, so a transaction that is
+//! passed over, such as for an unprofitable tip, and served again next cycle doesn't cost another
+//! `eth_estimateGas`.
+
+use clap::ValueEnum;
+use clarity::{Address, Transaction, Uint256};
+use log::{debug, warn};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use web30::client::Web3;
+use web30::jsonrpc::client::HttpClient;
+use web30::jsonrpc::error::Web3Error;
+use web30::types::TransactionRequest;
+
+/// How long a fee suggestion is reused before the fee history is sampled again
+const FEE_CACHE_TTL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum GasProfile {
+    /// aggressive while the sampled blocks are congested, normal otherwise
+    Auto,
+    Normal,
+    Aggressive,
+}
+
+#[derive(Debug, Clone)]
+pub struct GasOraclePolicy {
+    pub profile: GasProfile,
+    /// number of recent blocks sampled
+    pub blocks: u64,
+    /// percentile of the priority fees paid in each block used by the normal profile
+    pub normal_percentile: f64,
+    /// percentile of the priority fees paid in each block used by the aggressive profile
+    pub aggressive_percentile: f64,
+    /// average gas used ratio of the sampled blocks at or above which the auto profile turns aggressive
+    pub congestion_threshold: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct FeeBandPolicy {
+    /// number of recent blocks sampled
+    pub blocks: u64,
+    /// percentile of the sampled fees the optimistic end of the band is at
+    pub optimistic_percentile: f64,
+    /// percentile of the sampled fees the pessimistic end of the band is at
+    pub pessimistic_percentile: f64,
+}
+
+/// Prices per gas a relay may pay by the time it is included
+#[derive(Debug, Clone, Copy)]
+pub struct FeeBand {
+    /// next block's base fee with priority fees at the optimistic percentile
+    pub optimistic: Uint256,
+    /// the next or sampled base fees at the pessimistic percentile, whichever is higher, with priority fees at
+    /// that percentile too
+    pub pessimistic: Uint256,
+}
+
+/// Fees suggested for the next block
+#[derive(Debug, Clone, Copy)]
+pub struct FeeSuggestion {
+    pub base_fee: Uint256,
+    pub priority_fee: Uint256,
+    pub aggressive: bool,
+}
+
+impl FeeSuggestion {
+    /// Price per gas expected to be paid
+    pub fn gas_price(&self) -> Uint256 {
+        self.base_fee + self.priority_fee
+    }
+
+    /// Most that may be paid per gas, leaves room for the base fee to rise before the transaction lands
+    pub fn max_fee_per_gas(&self) -> Uint256 {
+        self.base_fee * 2u8.into() + self.priority_fee
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FeeHistory {
+    /// base fee of each sampled block followed by that of the next block
+    base_fee_per_gas: Vec<Uint256>,
+    gas_used_ratio: Vec<f64>,
+    /// priority fees paid at each requested percentile, per block
+    #[serde(default)]
+    reward: Vec<Vec<Uint256>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AccessListEntry {
+    address: Address,
+    storage_keys: Vec<Uint256>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AccessListResult {
+    access_list: Vec<AccessListEntry>,
+    /// set when the call reverts, the access list is then incomplete
+    #[serde(default)]
+    error: Option<String>,
+}
+
+static ACCESS_LISTS: AtomicBool = AtomicBool::new(false);
+
+static POLICY: LazyLock<Mutex<Option<GasOraclePolicy>>> = LazyLock::new(|| Mutex::new(None));
+
+static CACHE: LazyLock<Mutex<HashMap<u64, (Instant, FeeSuggestion)>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+static BAND_POLICY: LazyLock<Mutex<Option<FeeBandPolicy>>> = LazyLock::new(|| Mutex::new(None));
+
+static BAND_CACHE: LazyLock<Mutex<HashMap<u64, (Instant, FeeBand)>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Successful gas estimates by signature hash of the gasless transaction, along with when they were made
+type Estimates = HashMap<[u8; 32], (Instant, Uint256)>;
+
+static ESTIMATES: LazyLock<Mutex<Estimates>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+static ESTIMATE_TTL: Mutex<Duration> = Mutex::new(Duration::ZERO);
+
+/// How long a gas estimate is reused, zero to estimate again every time
+pub fn set_estimate_cache_ttl(ttl: Duration) {
+    *ESTIMATE_TTL.lock().unwrap() = ttl;
+}
+
+/// Gas estimate of the gasless transaction with signature hash `key`, if one was made recently
+pub fn cached_estimate(key: &[u8; 32]) -> Option<Uint256> {
+    let ttl = *ESTIMATE_TTL.lock().unwrap();
+    ESTIMATES
+        .lock()
+        .unwrap()
+        .get(key)
+        .filter(|(at, _)| at.elapsed() < ttl)
+        .map(|(_, gas)| *gas)
+}
+
+/// Caches the gas estimate of the gasless transaction with signature hash `key`
+pub fn cache_estimate(key: [u8; 32], gas: Uint256) {
+    let ttl = *ESTIMATE_TTL.lock().unwrap();
+    if ttl.is_zero() {
+        return;
+    }
+    let mut estimates = ESTIMATES.lock().unwrap();
+    estimates.retain(|_, (at, _)| at.elapsed() < ttl);
+    estimates.insert(key, (Instant::now(), gas));
+}
+
+/// Forgets the gas estimates and fee suggestions made so far, so that the next lookups go to the chain
+pub fn clear_gas_caches() {
+    ESTIMATES.lock().unwrap().clear();
+    CACHE.lock().unwrap().clear();
+    BAND_CACHE.lock().unwrap().clear();
+}
+
+pub fn set_gas_oracle(policy: Option<GasOraclePolicy>) {
+    *POLICY.lock().unwrap() = policy;
+}
+
+/// Fees suggested for the next block on `chain_id` from its recent fee history, `None` if the oracle is disabled
+pub async fn suggest_fees(web3: &Web3, chain_id: u64) -> Result<Option<FeeSuggestion>, Web3Error> {
+    let Some(policy) = POLICY.lock().unwrap().clone() else {
+        return Ok(None);
+    };
+    if let Some((at, fees)) = CACHE.lock().unwrap().get(&chain_id)
+        && at.elapsed() < FEE_CACHE_TTL
+    {
+        return Ok(Some(*fees));
+    }
+    let history: FeeHistory = HttpClient::new(&web3.get_url())
+        .request_method(
+            "eth_feeHistory",
+            vec![
+                json!(format!("{:#x}", policy.blocks.max(1))),
+                json!("latest"),
+                json!([policy.normal_percentile, policy.aggressive_percentile]),
+            ],
+            web3.get_timeout(),
+        )
+        .await?;
+    let base_fee = *history
+        .base_fee_per_gas
+        .last()
+        .ok_or_else(|| Web3Error::BadResponse("Empty fee history".to_string()))?;
+    let congestion = match history.gas_used_ratio.len() {
+        0 => 0.0,
+        len => history.gas_used_ratio.iter().sum::<f64>() / len as f64,
+    };
+    let aggressive = match policy.profile {
+        GasProfile::Auto => congestion >= policy.congestion_threshold,
+        GasProfile::Normal => false,
+        GasProfile::Aggressive => true,
+    };
+    // empty blocks report no priority fees at all, they say nothing about what it takes to be included
+    let mut rewards: Vec<Uint256> = history
+        .reward
+        .iter()
+        .zip(&history.gas_used_ratio)
+        .filter(|(_, ratio)| **ratio > 0.0)
+        .filter_map(|(rewards, _)| rewards.get(aggressive as usize).copied())
+        .collect();
+    rewards.sort();
+    let priority_fee = rewards.get(rewards.len() / 2).copied().unwrap_or_default();
+    let fees = FeeSuggestion {
+        base_fee,
+        priority_fee,
+        aggressive,
+    };
+    debug!(
+        "Suggested fees on {chain_id}: base fee {base_fee}, priority fee {priority_fee}, {} profile at {:.0}% average block usage",
+        if aggressive { "aggressive" } else { "normal" },
+        congestion * 100.0
+    );
+    CACHE
+        .lock()
+        .unwrap()
+        .insert(chain_id, (Instant::now(), fees));
+    Ok(Some(fees))
+}
+
+/// Share of its gas limit each of the last `blocks` blocks used, oldest first
+pub async fn block_usage(web3: &Web3, blocks: u64) -> Result<Vec<f64>, Web3Error> {
+    let history: FeeHistory = HttpClient::new(&web3.get_url())
+        .request_method(
+            "eth_feeHistory",
+            vec![
+                json!(format!("{:#x}", blocks.max(1))),
+                json!("latest"),
+                json!([]),
+            ],
+            web3.get_timeout(),
+        )
+        .await?;
+    Ok(history.gas_used_ratio)
+}
+
+/// Price per gas a relay on `chain_id` is expected to pay, from the oracle if it is enabled and `eth_gasPrice`
+/// otherwise
+pub async fn gas_price(web3: &Web3, chain_id: u64) -> Result<Uint256, Web3Error> {
+    match suggest_fees(web3, chain_id).await? {
+        Some(fees) => Ok(fees.gas_price()),
+        None => web3.eth_gas_price().await,
+    }
+}
+
+pub fn set_fee_band(policy: Option<FeeBandPolicy>) {
+    *BAND_POLICY.lock().unwrap() = policy;
+}
+
+/// Band of prices per gas a relay on `chain_id` may pay by the time it is included, from its recent fee history,
+/// `None` if the band is disabled
+pub async fn fee_band(web3: &Web3, chain_id: u64) -> Result<Option<FeeBand>, Web3Error> {
+    let Some(policy) = BAND_POLICY.lock().unwrap().clone() else {
+        return Ok(None);
+    };
+    if let Some((at, band)) = BAND_CACHE.lock().unwrap().get(&chain_id)
+        && at.elapsed() < FEE_CACHE_TTL
+    {
+        return Ok(Some(*band));
+    }
+    let history: FeeHistory = HttpClient::new(&web3.get_url())
+        .request_method(
+            "eth_feeHistory",
+            vec![
+                json!(format!("{:#x}", policy.blocks.max(1))),
+                json!("latest"),
+                json!([policy.optimistic_percentile, policy.pessimistic_percentile]),
+            ],
+            web3.get_timeout(),
+        )
+        .await?;
+    let next_base_fee = *history
+        .base_fee_per_gas
+        .last()
+        .ok_or_else(|| Web3Error::BadResponse("Empty fee history".to_string()))?;
+    // the median block's priority fee at each percentile, empty blocks report none and are left out
+    let priority_fee = |index: usize| {
+        let mut rewards: Vec<Uint256> = history
+            .reward
+            .iter()
+            .zip(&history.gas_used_ratio)
+            .filter(|(_, ratio)| **ratio > 0.0)
+            .filter_map(|(rewards, _)| rewards.get(index).copied())
+            .collect();
+        rewards.sort();
+        rewards.get(rewards.len() / 2).copied().unwrap_or_default()
+    };
+    let mut base_fees = history.base_fee_per_gas.clone();
+    base_fees.sort();
+    let index = ((base_fees.len() - 1) as f64 * policy.pessimistic_percentile / 100.0).round();
+    let pessimistic_base_fee = base_fees[index as usize].max(next_base_fee);
+    let band = FeeBand {
+        optimistic: next_base_fee + priority_fee(0),
+        pessimistic: pessimistic_base_fee + priority_fee(1),
+    };
+    debug!(
+        "Fee band on {chain_id}: {} optimistic to {} pessimistic per gas",
+        band.optimistic, band.pessimistic
+    );
+    BAND_CACHE
+        .lock()
+        .unwrap()
+        .insert(chain_id, (Instant::now(), band));
+    Ok(Some(band))
+}
+
+/// Price per gas a tip on `chain_id` has to cover, the pessimistic end of the fee band if it is enabled and above
+/// `gas_price`, the price expected right now. Falls back to `gas_price` if the fee history can't be read.
+pub async fn pessimistic_gas_price(web3: &Web3, chain_id: u64, gas_price: Uint256) -> Uint256 {
+    match fee_band(web3, chain_id).await {
+        Ok(Some(band)) => band.pessimistic.max(gas_price),
+        Ok(None) => gas_price,
+        Err(e) => {
+            warn!(
+                "Failed to read the fee band on {chain_id}, using the gas price of the moment: {e}"
+            );
+            gas_price
+        }
+    }
+}
+
+pub fn set_access_lists(enabled: bool) {
+    ACCESS_LISTS.store(enabled, Ordering::Relaxed);
+}
+
+/// JSON-RPC form of `request` carrying `access_list`, web30 serializes access lists in a form nodes ignore
+fn request_json(
+    request: &TransactionRequest,
+    access_list: &[(Address, Vec<Uint256>)],
+) -> serde_json::Value {
+    let mut value = json!(request);
+    if let Some(fields) = value.as_object_mut() {
+        fields.remove("access_list");
+        if !access_list.is_empty() {
+            let entries: Vec<serde_json::Value> = access_list
+                .iter()
+                .map(|(address, keys)| {
+                    let keys: Vec<String> =
+                        keys.iter().map(|key| format!("0x{key:064x}")).collect();
+                    json!({ "address": address, "storageKeys": keys })
+                })
+                .collect();
+            fields.insert("accessList".to_string(), json!(entries));
+        }
+    }
+    value
+}
+
+/// Estimates the gas of `call` sent from `from`, including its access list if it has one
+pub async fn estimate_gas(
+    web3: &Web3,
+    call: &Transaction,
+    from: Address,
+) -> Result<Uint256, Web3Error> {
+    let request = TransactionRequest::from_transaction(call, from);
+    match call {
+        Transaction::Eip1559 { access_list, .. } | Transaction::Eip2930 { access_list, .. }
+            if !access_list.is_empty() =>
+        {
+            HttpClient::new(&web3.get_url())
+                .request_method(
+                    "eth_estimateGas",
+                    vec![request_json(&request, access_list)],
+                    web3.get_timeout(),
+                )
+                .await
+        }
+        _ => web3.eth_estimate_gas(request).await,
+    }
+}
+
+/// Access list for `request` along with the gas estimate it brings, if access lists are enabled and one lowers
+/// the gas estimate below `estimate`
+pub async fn cheaper_access_list(
+    web3: &Web3,
+    request: &TransactionRequest,
+    estimate: Uint256,
+) -> Option<(Vec<(Address, Vec<Uint256>)>, Uint256)> {
+    if !ACCESS_LISTS.load(Ordering::Relaxed) {
+        return None;
+    }
+    let client = HttpClient::new(&web3.get_url());
+    // the access list is traced by executing the call, give it room to run
+    let mut traced = request.clone();
+    traced.set_gas_limit(estimate * 2u8.into());
+    let result: AccessListResult = match client
+        .request_method(
+            "eth_createAccessList",
+            vec![request_json(&traced, &[]), json!("latest")],
+            web3.get_timeout(),
+        )
+        .await
+    {
+        Ok(result) => result,
+        Err(e) => {
+            debug!("Failed to create an access list: {e}");
+            return None;
+        }
+    };
+    if let Some(error) = result.error {
+        debug!("Not using an access list, the call fails with it: {error}");
+        return None;
+    }
+    if result.access_list.is_empty() {
+        return None;
+    }
+    let access_list: Vec<(Address, Vec<Uint256>)> = result
+        .access_list
+        .into_iter()
+        .map(|entry| (entry.address, entry.storage_keys))
+        .collect();
+    let gas: Result<Uint256, _> = client
+        .request_method(
+            "eth_estimateGas",
+            vec![request_json(request, &access_list)],
+            web3.get_timeout(),
+        )
+        .await;
+    match gas {
+        Ok(gas) if gas < estimate => {
+            debug!("Access list lowers the gas estimate from {estimate} to {gas}");
+            Some((access_list, gas))
+        }
+        Ok(gas) => {
+            debug!(
+                "Not using an access list, the gas estimate with it is {gas} against {estimate}"
+            );
+            None
+        }
+        Err(e) => {
+            debug!("Failed to estimate gas with an access list: {e}");
+            None
+        }
+    }
+}
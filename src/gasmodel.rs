@@ -0,0 +1,76 @@
+//! Gas used by each kind of command, learned from the receipts of our own relays. Once a command has been relayed
+//! often enough, its usual gas use is known well enough to rule out transactions whose tip can't cover it before
+//! paying for a gas estimate. On queues dominated by small swaps that is most of them.
+
+use crate::command::Command;
+use clarity::Uint256;
+use num_traits::ToPrimitive;
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+
+/// Weight of the newest receipt in the moving average once it has this many samples' worth of history
+const SMOOTHING: f64 = 0.2;
+
+#[derive(Debug, Clone, Copy)]
+pub struct GasModelPolicy {
+    /// relays of a command that must have been seen before its average is trusted
+    pub min_samples: u32,
+    /// transactions are only ruled out if their tip can't cover this many percent less gas than the average
+    pub tolerance_percent: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct GasAverage {
+    average: f64,
+    samples: u32,
+}
+
+/// Chain id, callpath and command code
+type CommandKey = (u64, u16, Option<u8>);
+
+static POLICY: LazyLock<Mutex<Option<GasModelPolicy>>> = LazyLock::new(|| Mutex::new(None));
+
+/// Moving average of the gas used by relays of each command
+static MODEL: LazyLock<Mutex<HashMap<CommandKey, GasAverage>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+pub fn set_gas_model(policy: Option<GasModelPolicy>) {
+    *POLICY.lock().unwrap() = policy.map(|policy| GasModelPolicy {
+        min_samples: policy.min_samples.max(1),
+        tolerance_percent: policy.tolerance_percent.min(100),
+    });
+}
+
+/// Adds the gas a relay of `command` used on `chain_id`, as read from its receipt, to the model
+pub fn record_gas_used(chain_id: u64, command: &Command, gas_used: Uint256) {
+    if POLICY.lock().unwrap().is_none() {
+        return;
+    }
+    let Some(gas_used) = gas_used.to_u64() else {
+        return;
+    };
+    let mut model = MODEL.lock().unwrap();
+    let entry = model
+        .entry((chain_id, command.callpath, command.code))
+        .or_insert(GasAverage {
+            average: gas_used as f64,
+            samples: 0,
+        });
+    entry.samples = entry.samples.saturating_add(1);
+    // a plain average until there are enough samples, so the first receipt doesn't dominate for long
+    let weight = (1.0 / entry.samples as f64).max(SMOOTHING);
+    entry.average += (gas_used as f64 - entry.average) * weight;
+}
+
+/// The least gas a relay of `command` on `chain_id` can be expected to use, None until enough relays of it were
+/// seen or if the model is disabled
+pub fn expected_min_gas(chain_id: u64, command: &Command) -> Option<Uint256> {
+    let policy = (*POLICY.lock().unwrap())?;
+    let model = MODEL.lock().unwrap();
+    let entry = model.get(&(chain_id, command.callpath, command.code))?;
+    if entry.samples < policy.min_samples {
+        return None;
+    }
+    let min_gas = entry.average * f64::from(100 - policy.tolerance_percent) / 100.0;
+    Some(Uint256::from(min_gas as u64))
+}
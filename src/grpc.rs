@@ -0,0 +1,488 @@
+//! gRPC transport for orchestrators with a `grpc://` or, over TLS, a `grpcs://` url, speaking the protocol in
+//! `proto/orchestrator.proto`.
+//! It offers the same pending queue, claims and subscription as the HTTP protocol, and the same authentication,
+//! sent as request metadata instead of headers. The messages are declared by hand rather than generated so
+//! building the relayer doesn't need `protoc`, they must be kept in sync with the proto file.
+
+use crate::error::RelayerError;
+use crate::orchestrator::{
+    GRPC_SCHEME, GRPCS_SCHEME, GaslessTransaction, Orchestrator, PendingQuery, screen_pending,
+};
+use crate::proxy::tls_connector_builder;
+use crate::registration::{Heartbeat, Registration};
+use crate::signer::Signer;
+use crate::throttle::{Outbound, throttle};
+use clarity::Address;
+use futures::channel::mpsc::UnboundedSender;
+use hyper_util::rt::TokioIo;
+use log::{debug, info, warn};
+use openssl::ssl::SslConnector;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio_openssl::SslStream;
+use tonic::client::Grpc;
+use tonic::codec::{CompressionEncoding, ProstCodec};
+use tonic::codegen::http::Uri;
+use tonic::codegen::http::uri::PathAndQuery;
+use tonic::metadata::{MetadataKey, MetadataValue};
+use tonic::transport::{Channel, Endpoint};
+use tonic::{Code, Request};
+use tower::service_fn;
+
+const PENDING_PATH: &str = "/orchestrator.Orchestrator/Pending";
+const CLAIM_PATH: &str = "/orchestrator.Orchestrator/Claim";
+const SUBSCRIBE_PATH: &str = "/orchestrator.Orchestrator/Subscribe";
+const REGISTER_PATH: &str = "/orchestrator.Orchestrator/Register";
+const HEARTBEAT_PATH: &str = "/orchestrator.Orchestrator/Heartbeat";
+const DEREGISTER_PATH: &str = "/orchestrator.Orchestrator/Deregister";
+/// gRPC streams carry no keepalive messages, a subscription is instead kept alive with HTTP/2 pings sent this often
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(20);
+
+mod proto {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct GaslessTransaction {
+        #[prost(uint64, tag = "1")]
+        pub chain_id: u64,
+        #[prost(uint32, tag = "2")]
+        pub callpath: u32,
+        #[prost(bytes = "vec", tag = "3")]
+        pub cmd: Vec<u8>,
+        #[prost(bytes = "vec", tag = "4")]
+        pub conds: Vec<u8>,
+        #[prost(bytes = "vec", tag = "5")]
+        pub tip: Vec<u8>,
+        #[prost(bytes = "vec", tag = "6")]
+        pub sig: Vec<u8>,
+        #[prost(uint64, tag = "7")]
+        pub submitted_at: u64,
+        #[prost(string, optional, tag = "8")]
+        pub target: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct PendingRequest {
+        #[prost(uint32, optional, tag = "1")]
+        pub limit: Option<u32>,
+        #[prost(uint64, optional, tag = "2")]
+        pub since: Option<u64>,
+        #[prost(uint64, optional, tag = "3")]
+        pub chain_id: Option<u64>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct PendingResponse {
+        #[prost(message, repeated, tag = "1")]
+        pub transactions: Vec<GaslessTransaction>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ClaimRequest {
+        #[prost(string, tag = "1")]
+        pub id: String,
+        #[prost(string, tag = "2")]
+        pub relayer: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ClaimResponse {
+        #[prost(bool, tag = "1")]
+        pub granted: bool,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct SubscribeRequest {}
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct RegisterRequest {
+        #[prost(string, tag = "1")]
+        pub relayer: String,
+        #[prost(uint64, repeated, tag = "2")]
+        pub chain_ids: Vec<u64>,
+        #[prost(string, repeated, tag = "3")]
+        pub tokens: Vec<String>,
+        #[prost(string, optional, tag = "4")]
+        pub min_tip: Option<String>,
+        #[prost(string, tag = "5")]
+        pub version: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct HeartbeatRequest {
+        #[prost(string, tag = "1")]
+        pub relayer: String,
+        #[prost(bool, tag = "2")]
+        pub paused: bool,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Ack {}
+}
+
+impl TryFrom<proto::GaslessTransaction> for GaslessTransaction {
+    type Error = RelayerError;
+
+    fn try_from(tx: proto::GaslessTransaction) -> Result<Self, Self::Error> {
+        Ok(GaslessTransaction {
+            chain_id: tx.chain_id,
+            callpath: tx.callpath.try_into().map_err(|_| {
+                RelayerError::orchestrator(format!("Invalid callpath {}", tx.callpath))
+            })?,
+            cmd: tx.cmd,
+            conds: tx.conds,
+            tip: tx.tip,
+            sig: tx.sig,
+            submitted_at: tx.submitted_at,
+            target: tx
+                .target
+                .map(|target| {
+                    Address::from_str(&target)
+                        .map_err(|_| RelayerError::orchestrator(format!("Invalid target {target}")))
+                })
+                .transpose()?,
+        })
+    }
+}
+
+/// Channels opened so far, by orchestrator url and the host and port they connect to
+static CHANNELS: LazyLock<Mutex<HashMap<(String, String), Channel>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Client on the channel to `authority` of `orchestrator`, opened on first use and shared by every call after it.
+/// The channel connects when a call first needs it and reconnects on its own if the connection drops. A `grpcs://`
+/// orchestrator is connected to over TLS with its certificate checked against its host name, even when `authority`
+/// is one of its addresses.
+fn client(orchestrator: &Orchestrator, authority: &str) -> Result<Grpc<Channel>, RelayerError> {
+    let key = (orchestrator.url.clone(), authority.to_string());
+    let cached = CHANNELS.lock().unwrap().get(&key).cloned();
+    let channel = match cached {
+        Some(channel) => channel,
+        None => {
+            let endpoint = Endpoint::from_shared(format!("http://{authority}"))
+                .map_err(RelayerError::orchestrator)?
+                .connect_timeout(orchestrator.timeout)
+                .http2_keep_alive_interval(KEEPALIVE_INTERVAL)
+                .keep_alive_while_idle(true);
+            let channel = if orchestrator.url.starts_with(GRPCS_SCHEME) {
+                let tls = match &orchestrator.tls {
+                    Some(tls) => tls.clone(),
+                    None => tls_connector_builder()
+                        .map_err(RelayerError::orchestrator)?
+                        .build(),
+                };
+                let domain = host(orchestrator).to_string();
+                endpoint.connect_with_connector_lazy(service_fn(move |uri: Uri| {
+                    connect_tls(tls.clone(), domain.clone(), uri)
+                }))
+            } else {
+                endpoint.connect_lazy()
+            };
+            CHANNELS.lock().unwrap().insert(key, channel.clone());
+            channel
+        }
+    };
+    Ok(Grpc::new(channel)
+        .accept_compressed(CompressionEncoding::Gzip)
+        .accept_compressed(CompressionEncoding::Deflate)
+        .max_decoding_message_size(orchestrator.max_response))
+}
+
+/// Opens a TLS connection to the host and port of `uri`, verifying the certificate presented for `domain`
+async fn connect_tls(
+    tls: SslConnector,
+    domain: String,
+    uri: Uri,
+) -> Result<TokioIo<SslStream<TcpStream>>, io::Error> {
+    let authority = uri
+        .authority()
+        .ok_or_else(|| io::Error::other(format!("No host in {uri}")))?;
+    let tcp = TcpStream::connect(authority.as_str()).await?;
+    let ssl = tls
+        .configure()
+        .and_then(|config| config.into_ssl(&domain))
+        .map_err(io::Error::other)?;
+    let mut stream = SslStream::new(ssl, tcp).map_err(io::Error::other)?;
+    Pin::new(&mut stream)
+        .connect()
+        .await
+        .map_err(io::Error::other)?;
+    Ok(TokioIo::new(stream))
+}
+
+/// Host and port of a `grpc://` or `grpcs://` url
+fn authority(orchestrator: &Orchestrator) -> &str {
+    orchestrator
+        .url
+        .strip_prefix(GRPC_SCHEME)
+        .or_else(|| orchestrator.url.strip_prefix(GRPCS_SCHEME))
+        .unwrap_or(&orchestrator.url)
+        .trim_end_matches('/')
+}
+
+/// Host of a `grpc://` or `grpcs://` url, without its port
+fn host(orchestrator: &Orchestrator) -> &str {
+    let authority = authority(orchestrator);
+    match authority.rsplit_once(':') {
+        Some((host, _)) if !authority.ends_with(']') => host,
+        _ => authority,
+    }
+    .trim_start_matches('[')
+    .trim_end_matches(']')
+}
+
+/// Wraps `message` in a request carrying the orchestrator's authentication as metadata, once the request is within
+/// the orchestrator's ceiling
+async fn authenticated<T>(
+    orchestrator: &Orchestrator,
+    signer: &Signer,
+    message: T,
+) -> Result<Request<T>, RelayerError> {
+    throttle(Outbound::Orchestrator, &orchestrator.url).await;
+    let mut request = Request::new(message);
+    for (name, value) in orchestrator.auth_headers(signer).await? {
+        let key = MetadataKey::from_bytes(name.to_lowercase().as_bytes())
+            .map_err(RelayerError::orchestrator)?;
+        let value = MetadataValue::try_from(value).map_err(RelayerError::orchestrator)?;
+        request.metadata_mut().insert(key, value);
+    }
+    Ok(request)
+}
+
+async fn unary<Req, Resp>(
+    orchestrator: &Orchestrator,
+    authority: &str,
+    signer: &Signer,
+    path: &'static str,
+    message: Req,
+) -> Result<Resp, tonic::Status>
+where
+    Req: prost::Message + Send + Sync + 'static,
+    Resp: prost::Message + Default + Send + Sync + 'static,
+{
+    let error = |e: RelayerError| tonic::Status::unavailable(e.to_string());
+    let mut client = client(orchestrator, authority).map_err(error)?;
+    let mut request = authenticated(orchestrator, signer, message)
+        .await
+        .map_err(error)?;
+    request.set_timeout(orchestrator.timeout);
+    client
+        .ready()
+        .await
+        .map_err(|e| tonic::Status::unavailable(e.to_string()))?;
+    let response = client
+        .unary(
+            request,
+            PathAndQuery::from_static(path),
+            ProstCodec::<Req, Resp>::default(),
+        )
+        .await?;
+    Ok(response.into_inner())
+}
+
+/// Fetches a single page of the pending queue from a specific IP of the orchestrator
+pub async fn fetch_pending_page(
+    orchestrator: &Orchestrator,
+    ip: SocketAddr,
+    signer: &Signer,
+    query: &PendingQuery,
+) -> Result<Vec<GaslessTransaction>, RelayerError> {
+    let request = proto::PendingRequest {
+        limit: query.limit,
+        since: query.since,
+        chain_id: query.chain_id,
+    };
+    let response: proto::PendingResponse =
+        unary(orchestrator, &ip.to_string(), signer, PENDING_PATH, request)
+            .await
+            .map_err(|status| {
+                RelayerError::orchestrator(format!(
+                    "Failed to fetch pending transactions: {}",
+                    status.message()
+                ))
+            })?;
+    let txs = response
+        .transactions
+        .into_iter()
+        .map(GaslessTransaction::try_from)
+        .collect::<Result<Vec<_>, _>>()?;
+    let txs = screen_pending(orchestrator, query.chain_id, txs);
+    debug!("Found {} pending transactions", txs.len());
+    Ok(txs)
+}
+
+/// Attempts to reserve a transaction with the orchestrator, returns true only if the claim was granted
+pub async fn claim_transaction(
+    orchestrator: &Orchestrator,
+    tx: &GaslessTransaction,
+    signer: &Signer,
+) -> bool {
+    let request = proto::ClaimRequest {
+        id: tx.id(),
+        relayer: signer.address().to_string(),
+    };
+    let response: Result<proto::ClaimResponse, _> = unary(
+        orchestrator,
+        authority(orchestrator),
+        signer,
+        CLAIM_PATH,
+        request,
+    )
+    .await;
+    match response {
+        Ok(response) if response.granted => true,
+        Ok(_) => {
+            debug!("Claim for {} rejected", tx.id());
+            false
+        }
+        Err(status)
+            if matches!(
+                status.code(),
+                Code::AlreadyExists | Code::FailedPrecondition | Code::NotFound
+            ) =>
+        {
+            debug!("Claim for {} rejected with {}", tx.id(), status.message());
+            false
+        }
+        Err(status) => {
+            warn!(
+                "Failed to claim transaction {}: {}",
+                tx.id(),
+                status.message()
+            );
+            false
+        }
+    }
+}
+
+/// Streams newly submitted transactions from the orchestrator into `sender` until the stream ends
+pub async fn subscribe(
+    orchestrator: &Orchestrator,
+    signer: &Signer,
+    sender: &UnboundedSender<(Orchestrator, GaslessTransaction)>,
+    connected: &Cell<bool>,
+) -> Result<(), RelayerError> {
+    let orchestrator_url = &orchestrator.url;
+    debug!("Opening subscription to {orchestrator_url}");
+    // the stream stays open indefinitely, a dead connection is detected by the keepalive pings instead
+    let mut client = client(orchestrator, authority(orchestrator))?;
+    let request = authenticated(orchestrator, signer, proto::SubscribeRequest {}).await?;
+    client.ready().await.map_err(RelayerError::orchestrator)?;
+    let mut stream = client
+        .server_streaming(
+            request,
+            PathAndQuery::from_static(SUBSCRIBE_PATH),
+            ProstCodec::<proto::SubscribeRequest, proto::GaslessTransaction>::default(),
+        )
+        .await
+        .map_err(|status| RelayerError::orchestrator(status.message()))?
+        .into_inner();
+    info!("Subscribed to {orchestrator_url}");
+    connected.set(true);
+
+    while let Some(tx) = stream
+        .message()
+        .await
+        .map_err(|status| RelayerError::orchestrator(status.message()))?
+    {
+        let tx = match GaslessTransaction::try_from(tx) {
+            Ok(tx) => tx,
+            Err(e) => {
+                warn!("Ignoring invalid transaction from {orchestrator_url}: {e}");
+                continue;
+            }
+        };
+        if sender.unbounded_send((orchestrator.clone(), tx)).is_err() {
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+/// Announces the relayer to the orchestrator
+pub async fn register_relayer(
+    orchestrator: &Orchestrator,
+    signer: &Signer,
+    registration: &Registration,
+) -> Result<(), RelayerError> {
+    let request = proto::RegisterRequest {
+        relayer: registration.relayer.clone(),
+        chain_ids: registration.chain_ids.clone(),
+        tokens: registration.tokens.clone(),
+        min_tip: registration.min_tip.map(|min_tip| min_tip.to_string()),
+        version: registration.version.clone(),
+    };
+    let _: proto::Ack = unary(
+        orchestrator,
+        authority(orchestrator),
+        signer,
+        REGISTER_PATH,
+        request,
+    )
+    .await
+    .map_err(|status| {
+        RelayerError::orchestrator(format!("Registration rejected: {}", status.message()))
+    })?;
+    Ok(())
+}
+
+/// Tells the orchestrator the relayer is still up, returns false if the orchestrator doesn't know the relayer
+pub async fn send_heartbeat(
+    orchestrator: &Orchestrator,
+    signer: &Signer,
+    heartbeat: &Heartbeat,
+) -> Result<bool, RelayerError> {
+    let request = proto::HeartbeatRequest {
+        relayer: heartbeat.relayer.clone(),
+        paused: heartbeat.paused,
+    };
+    let response: Result<proto::Ack, _> = unary(
+        orchestrator,
+        authority(orchestrator),
+        signer,
+        HEARTBEAT_PATH,
+        request,
+    )
+    .await;
+    match response {
+        Ok(_) => Ok(true),
+        Err(status) if status.code() == Code::NotFound => Ok(false),
+        Err(status) => Err(RelayerError::orchestrator(format!(
+            "Heartbeat rejected: {}",
+            status.message()
+        ))),
+    }
+}
+
+/// Tells the orchestrator to stop routing transactions to the relayer
+pub async fn deregister_relayer(
+    orchestrator: &Orchestrator,
+    signer: &Signer,
+    heartbeat: &Heartbeat,
+) -> Result<(), RelayerError> {
+    let request = proto::HeartbeatRequest {
+        relayer: heartbeat.relayer.clone(),
+        paused: heartbeat.paused,
+    };
+    let response: Result<proto::Ack, _> = unary(
+        orchestrator,
+        authority(orchestrator),
+        signer,
+        DEREGISTER_PATH,
+        request,
+    )
+    .await;
+    match response {
+        Ok(_) => Ok(()),
+        Err(status) if status.code() == Code::NotFound => Ok(()),
+        Err(status) => Err(RelayerError::orchestrator(format!(
+            "Deregistration rejected: {}",
+            status.message()
+        ))),
+    }
+}
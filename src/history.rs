@@ -0,0 +1,769 @@
+//! Persistent history of relays in SQLite, kept for accounting and to answer users asking whether their
+//! transaction was relayed. A row is written for every on chain outcome of a relay, so a transaction that
+//! was included and later became final has two rows.
+
+use crate::bech32::to_bech32;
+use crate::status::unix_timestamp;
+use clap::ValueEnum;
+use clarity::{Address, Uint256};
+use log::warn;
+use rusqlite::types::Value;
+use rusqlite::{Connection, OpenFlags, params, params_from_iter};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{LazyLock, Mutex};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS relays (
+    id INTEGER PRIMARY KEY,
+    timestamp INTEGER NOT NULL,
+    chain_id INTEGER NOT NULL,
+    sig_hash TEXT NOT NULL,
+    user TEXT,
+    tip_token TEXT,
+    tip_amount TEXT,
+    gas_paid TEXT,
+    tip_value TEXT,
+    tx_hash TEXT,
+    outcome TEXT NOT NULL,
+    error TEXT,
+    trace TEXT,
+    tip_symbol TEXT,
+    skip_reason TEXT
+);
+CREATE INDEX IF NOT EXISTS relays_sig_hash ON relays (sig_hash);
+CREATE INDEX IF NOT EXISTS relays_user ON relays (user);
+CREATE INDEX IF NOT EXISTS relays_timestamp ON relays (timestamp);
+CREATE TABLE IF NOT EXISTS tip_splits (
+    id INTEGER PRIMARY KEY,
+    timestamp INTEGER NOT NULL,
+    chain_id INTEGER NOT NULL,
+    sig_hash TEXT NOT NULL,
+    relayer TEXT NOT NULL,
+    partner TEXT NOT NULL,
+    token TEXT NOT NULL,
+    amount TEXT NOT NULL,
+    payout_tx_hash TEXT,
+    payout_nonce TEXT,
+    paid INTEGER NOT NULL DEFAULT 0
+);
+CREATE UNIQUE INDEX IF NOT EXISTS tip_splits_share ON tip_splits (sig_hash, partner);
+CREATE TABLE IF NOT EXISTS executed (
+    kind TEXT NOT NULL,
+    id TEXT NOT NULL,
+    chain_id INTEGER NOT NULL,
+    timestamp INTEGER NOT NULL,
+    tx_hash TEXT,
+    PRIMARY KEY (kind, id)
+);
+";
+
+/// One outcome of a relay
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RelayRecord {
+    /// unix timestamp of the outcome
+    pub timestamp: u64,
+    pub chain_id: u64,
+    /// id of the gasless transaction
+    pub sig_hash: String,
+    /// address that signed the gasless transaction
+    pub user: Option<String>,
+    /// `user` as an `althea` bech32 address, derived rather than stored
+    #[serde(default)]
+    pub user_bech32: Option<String>,
+    pub tip_token: Option<String>,
+    /// `symbol()` of `tip_token`, if it could be read
+    #[serde(default)]
+    pub tip_symbol: Option<String>,
+    /// tip in base units of `tip_token`, as a decimal string
+    pub tip_amount: Option<String>,
+    /// gas fee paid for the relay in wei, as a decimal string. A batch's fee is split evenly across its relays.
+    pub gas_paid: Option<String>,
+    /// what the tip was worth in wei of the gas token when relayed, as a decimal string
+    #[serde(default)]
+    pub tip_value: Option<String>,
+    /// hash of our relay transaction
+    pub tx_hash: Option<String>,
+    /// `included`, `final`, `reorged`, `already_relayed`, `failed` or `skipped`
+    pub outcome: String,
+    pub error: Option<String>,
+    /// why the transaction wasn't relayed, see `SkipReason`, for skips and for failures before broadcasting
+    #[serde(default)]
+    pub skip_reason: Option<String>,
+    /// `callTracer` trace of a relay that reverted on chain, if the RPC could trace it
+    #[serde(default)]
+    pub trace: Option<serde_json::Value>,
+}
+
+/// A pool partner's share of the tip of a final relay, owed until it is paid out by `payout_tx_hash`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TipSplit {
+    /// unix timestamp the relay became final
+    pub timestamp: u64,
+    pub chain_id: u64,
+    /// id of the gasless transaction
+    pub sig_hash: String,
+    /// relayer account the tip was paid to and the share is paid from
+    pub relayer: String,
+    pub partner: String,
+    /// tip token, the zero address for ALTHEA
+    pub token: String,
+    /// share in base units of `token`, as a decimal string
+    pub amount: String,
+    /// transfer the share was paid out in, once sent
+    pub payout_tx_hash: Option<String>,
+    /// nonce of that transfer, to tell once it can no longer be included
+    pub payout_nonce: Option<String>,
+    /// true once the transfer's receipt showed it succeeded
+    pub paid: bool,
+}
+
+/// Restricts a history query, every field that is set must match
+#[derive(Debug, Clone, Default)]
+pub struct HistoryFilter {
+    pub sig_hash: Option<String>,
+    pub user: Option<String>,
+    pub tip_token: Option<String>,
+    pub outcome: Option<String>,
+    pub skip_reason: Option<String>,
+    /// unix timestamp, inclusive
+    pub since: Option<u64>,
+    /// unix timestamp, exclusive
+    pub until: Option<u64>,
+    pub limit: Option<u32>,
+}
+
+/// Tips earned and gas paid by the final relays within one period, both in wei of the gas token so that they can
+/// be netted. Relays recorded before tip values were kept count as earning nothing.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProfitBucket {
+    /// unix timestamp the period starts at
+    pub start: u64,
+    pub relays: u64,
+    pub tip_value: String,
+    pub gas_paid: String,
+}
+
+/// Totals of the final relays tipping in one token, tips are in base units of the token and gas in wei of
+/// the gas token so they can't be netted without a price
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenSummary {
+    pub tip_token: String,
+    #[serde(default)]
+    pub tip_symbol: Option<String>,
+    pub relays: u64,
+    pub tips: String,
+    pub gas_paid: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportReport {
+    /// every recorded relay outcome
+    Relays,
+    /// tips earned and gas paid per tip token
+    Summary,
+}
+
+/// Largest trace stored with a failed relay, in bytes of JSON, see `capped_trace`
+const MAX_TRACE_BYTES: usize = 256 * 1024;
+
+static HISTORY: LazyLock<Mutex<Option<Connection>>> = LazyLock::new(|| Mutex::new(None));
+
+/// Path of the history being recorded to along with a read only connection to it for the dashboard, opened on first
+/// use. The dashboard's queries run on their own connection so that they never hold up recording relays.
+static READER: LazyLock<Mutex<(Option<PathBuf>, Option<Connection>)>> =
+    LazyLock::new(|| Mutex::new((None, None)));
+
+/// Opens the history database at `path`, creating it if needed
+pub fn open_database(path: &Path) -> rusqlite::Result<Connection> {
+    let conn = Connection::open(path)?;
+    conn.pragma_update(None, "journal_mode", "WAL")?;
+    conn.execute_batch(SCHEMA)?;
+    // databases created before tip values, traces, token symbols or skip reasons were recorded lack their columns
+    for column in ["tip_value", "trace", "tip_symbol", "skip_reason"] {
+        let exists = conn
+            .prepare("SELECT 1 FROM pragma_table_info('relays') WHERE name = ?1")?
+            .exists([column])?;
+        if !exists {
+            conn.execute(&format!("ALTER TABLE relays ADD COLUMN {column} TEXT"), [])?;
+        }
+    }
+    // ledgers from before payouts waited for their receipt took a payout's hash to mean it was paid
+    let exists = conn
+        .prepare("SELECT 1 FROM pragma_table_info('tip_splits') WHERE name = 'paid'")?
+        .exists([])?;
+    if !exists {
+        conn.execute_batch(
+            "ALTER TABLE tip_splits ADD COLUMN payout_nonce TEXT;
+             ALTER TABLE tip_splits ADD COLUMN paid INTEGER NOT NULL DEFAULT 0;
+             UPDATE tip_splits SET paid = 1 WHERE payout_tx_hash IS NOT NULL;",
+        )?;
+    }
+    Ok(conn)
+}
+
+/// Starts recording relays into the database at `path`
+pub fn open_history(path: &Path) -> rusqlite::Result<()> {
+    *HISTORY.lock().unwrap() = Some(open_database(path)?);
+    *READER.lock().unwrap() = (Some(path.to_path_buf()), None);
+    Ok(())
+}
+
+/// Runs `query` on the dashboard's read only connection to the history, opening it if needed
+fn read_only<T>(query: impl FnOnce(&Connection) -> Result<T, String>) -> Result<T, String> {
+    let mut reader = READER.lock().unwrap();
+    let (path, conn) = &mut *reader;
+    let path = path
+        .as_ref()
+        .ok_or("No history is being recorded, set --history-db")?;
+    let conn = match conn {
+        Some(conn) => conn,
+        None => conn.insert(
+            Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+                .map_err(|e| e.to_string())?,
+        ),
+    };
+    query(conn)
+}
+
+/// Appends `record` to the history if one is open, a failed write is logged and otherwise ignored since the
+/// history must never hold up relaying
+pub fn record_relay(record: RelayRecord) {
+    let history = HISTORY.lock().unwrap();
+    let Some(conn) = history.as_ref() else {
+        return;
+    };
+    if let Err(e) = conn.execute(
+        "INSERT INTO relays (timestamp, chain_id, sig_hash, user, tip_token, tip_amount, gas_paid, tip_value, tx_hash, outcome, error, trace, tip_symbol, skip_reason)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+        params![
+            record.timestamp as i64,
+            record.chain_id as i64,
+            record.sig_hash,
+            record.user,
+            record.tip_token,
+            record.tip_amount,
+            record.gas_paid,
+            record.tip_value,
+            record.tx_hash,
+            record.outcome,
+            record.error,
+            record.trace.as_ref().map(|trace| trace.to_string()),
+            record.tip_symbol,
+            record.skip_reason,
+        ],
+    ) {
+        warn!("Failed to record relay of {} in the history: {e}", record.sig_hash);
+    }
+}
+
+/// Stores `trace` with the failures recorded for relay transaction `tx_hash`, every relay a batch carried
+pub fn attach_trace(tx_hash: &str, trace: &serde_json::Value) {
+    let history = HISTORY.lock().unwrap();
+    let Some(conn) = history.as_ref() else {
+        return;
+    };
+    if let Err(e) = conn.execute(
+        "UPDATE relays SET trace = ?1 WHERE lower(tx_hash) = lower(?2) AND outcome = 'failed'",
+        params![capped_trace(trace), tx_hash],
+    ) {
+        warn!("Failed to record the trace of {tx_hash} in the history: {e}");
+    }
+}
+
+/// `trace` as stored, a trace larger than `MAX_TRACE_BYTES` keeps only its top call, without the calls and logs
+/// below it, and records how large it was under `truncated_from`
+fn capped_trace(trace: &serde_json::Value) -> String {
+    let json = trace.to_string();
+    if json.len() <= MAX_TRACE_BYTES {
+        return json;
+    }
+    let mut top = match trace {
+        serde_json::Value::Object(frame) => frame.clone(),
+        _ => serde_json::Map::new(),
+    };
+    top.remove("calls");
+    top.remove("logs");
+    top.insert("truncated_from".to_string(), json.len().into());
+    let capped = serde_json::Value::Object(top).to_string();
+    if capped.len() <= MAX_TRACE_BYTES {
+        capped
+    } else {
+        serde_json::json!({ "truncated_from": json.len() }).to_string()
+    }
+}
+
+/// Records matching `filter`, oldest first
+pub fn query_history(
+    conn: &Connection,
+    filter: &HistoryFilter,
+) -> rusqlite::Result<Vec<RelayRecord>> {
+    let mut conditions = Vec::new();
+    let mut values: Vec<Value> = Vec::new();
+    // addresses and hashes are compared case insensitively since they may be checksummed
+    for (column, value) in [
+        ("sig_hash", &filter.sig_hash),
+        ("user", &filter.user),
+        ("tip_token", &filter.tip_token),
+        ("outcome", &filter.outcome),
+        ("skip_reason", &filter.skip_reason),
+    ] {
+        if let Some(value) = value {
+            conditions.push(format!("lower({column}) = lower(?)"));
+            values.push(Value::Text(value.clone()));
+        }
+    }
+    if let Some(since) = filter.since {
+        conditions.push("timestamp >= ?".to_string());
+        values.push(Value::Integer(since as i64));
+    }
+    if let Some(until) = filter.until {
+        conditions.push("timestamp < ?".to_string());
+        values.push(Value::Integer(until as i64));
+    }
+    let mut sql = format!("SELECT {COLUMNS} FROM relays");
+    if !conditions.is_empty() {
+        sql += &format!(" WHERE {}", conditions.join(" AND "));
+    }
+    sql += " ORDER BY timestamp, id";
+    if let Some(limit) = filter.limit {
+        sql += &format!(" LIMIT {limit}");
+    }
+    let mut statement = conn.prepare(&sql)?;
+    let rows = statement.query_map(params_from_iter(values), read_record)?;
+    rows.collect()
+}
+
+/// Columns `read_record` reads, in order
+const COLUMNS: &str = "timestamp, chain_id, sig_hash, user, tip_token, tip_amount, gas_paid, tip_value, tx_hash, outcome, error, trace, tip_symbol, skip_reason";
+
+fn read_record(row: &rusqlite::Row) -> rusqlite::Result<RelayRecord> {
+    Ok(RelayRecord {
+        timestamp: row.get::<_, i64>(0)? as u64,
+        chain_id: row.get::<_, i64>(1)? as u64,
+        sig_hash: row.get(2)?,
+        user_bech32: row
+            .get::<_, Option<String>>(3)?
+            .and_then(|user| Address::from_str(&user).ok())
+            .map(to_bech32),
+        user: row.get(3)?,
+        tip_token: row.get(4)?,
+        tip_amount: row.get(5)?,
+        gas_paid: row.get(6)?,
+        tip_value: row.get(7)?,
+        tx_hash: row.get(8)?,
+        outcome: row.get(9)?,
+        error: row.get(10)?,
+        trace: row
+            .get::<_, Option<String>>(11)?
+            .and_then(|trace| serde_json::from_str(&trace).ok()),
+        tip_symbol: row.get(12)?,
+        skip_reason: row.get(13)?,
+    })
+}
+
+/// The `limit` most recent outcomes in the history the relayer is recording to, newest first
+pub fn recent_relays(limit: u32) -> Result<Vec<RelayRecord>, String> {
+    read_only(|conn| {
+        let mut statement = conn
+            .prepare(&format!(
+                "SELECT {COLUMNS} FROM relays ORDER BY timestamp DESC, id DESC LIMIT {limit}"
+            ))
+            .map_err(|e| e.to_string())?;
+        let rows = statement
+            .query_map([], read_record)
+            .map_err(|e| e.to_string())?;
+        rows.collect::<rusqlite::Result<_>>()
+            .map_err(|e| e.to_string())
+    })
+}
+
+/// Tips earned and gas paid by the final relays in the history the relayer is recording to, in periods of
+/// `period` seconds from `since` on, oldest first. Periods without relays are included so they chart as zero.
+pub fn profit_by_period(since: u64, period: u64) -> Result<Vec<ProfitBucket>, String> {
+    let period = period.max(1);
+    let since = since - since % period;
+    let records = read_only(|conn| {
+        query_history(
+            conn,
+            &HistoryFilter {
+                outcome: Some("final".to_string()),
+                since: Some(since),
+                ..Default::default()
+            },
+        )
+        .map_err(|e| e.to_string())
+    })?;
+    let now = unix_timestamp();
+    let mut buckets: Vec<(u64, u64, Uint256, Uint256)> = (since..=now.max(since))
+        .step_by(period as usize)
+        .map(|start| (start, 0, Uint256::default(), Uint256::default()))
+        .collect();
+    for record in &records {
+        let index = ((record.timestamp - since) / period) as usize;
+        let Some((_, relays, tip_value, gas_paid)) = buckets.get_mut(index) else {
+            continue;
+        };
+        *relays += 1;
+        *tip_value += parse_amount(&record.tip_value);
+        *gas_paid += parse_amount(&record.gas_paid);
+    }
+    Ok(buckets
+        .into_iter()
+        .map(|(start, relays, tip_value, gas_paid)| ProfitBucket {
+            start,
+            relays,
+            tip_value: tip_value.to_string(),
+            gas_paid: gas_paid.to_string(),
+        })
+        .collect())
+}
+
+/// Tokens the final relays on each chain were tipped in, by chain id
+pub fn earned_tokens() -> Result<Vec<(u64, String)>, String> {
+    let history = HISTORY.lock().unwrap();
+    let Some(conn) = history.as_ref() else {
+        return Ok(Vec::new());
+    };
+    let mut statement = conn
+        .prepare(
+            "SELECT DISTINCT chain_id, tip_token FROM relays WHERE outcome = 'final' AND tip_token IS NOT NULL",
+        )
+        .map_err(|e| e.to_string())?;
+    let rows = statement
+        .query_map([], |row| Ok((row.get::<_, i64>(0)? as u64, row.get(1)?)))
+        .map_err(|e| e.to_string())?;
+    rows.collect::<rusqlite::Result<_>>()
+        .map_err(|e| e.to_string())
+}
+
+/// Records a partner's share of a tip in the ledger, a share already recorded for the same transaction and partner
+/// is kept. Fails if no history is being recorded, shares are only paid out of the ledger.
+pub fn record_tip_split(split: &TipSplit) -> Result<(), String> {
+    let history = HISTORY.lock().unwrap();
+    let conn = history
+        .as_ref()
+        .ok_or("No history is being recorded, set --history-db")?;
+    conn.execute(
+        "INSERT OR IGNORE INTO tip_splits (timestamp, chain_id, sig_hash, relayer, partner, token, amount, payout_tx_hash, payout_nonce, paid)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        params![
+            split.timestamp as i64,
+            split.chain_id as i64,
+            split.sig_hash,
+            split.relayer,
+            split.partner,
+            split.token,
+            split.amount,
+            split.payout_tx_hash,
+            split.payout_nonce,
+            split.paid,
+        ],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Shares on `chain_id` that haven't been paid out yet along with their row ids, oldest first. Shares whose payout
+/// was sent but hasn't succeeded yet are included.
+pub fn owed_tip_splits(chain_id: u64) -> Result<Vec<(i64, TipSplit)>, String> {
+    let history = HISTORY.lock().unwrap();
+    let Some(conn) = history.as_ref() else {
+        return Ok(Vec::new());
+    };
+    let mut statement = conn
+        .prepare(
+            "SELECT id, timestamp, chain_id, sig_hash, relayer, partner, token, amount, payout_tx_hash, payout_nonce, paid
+             FROM tip_splits WHERE chain_id = ?1 AND paid = 0 ORDER BY id",
+        )
+        .map_err(|e| e.to_string())?;
+    let rows = statement
+        .query_map([chain_id as i64], |row| {
+            Ok((
+                row.get(0)?,
+                TipSplit {
+                    timestamp: row.get::<_, i64>(1)? as u64,
+                    chain_id: row.get::<_, i64>(2)? as u64,
+                    sig_hash: row.get(3)?,
+                    relayer: row.get(4)?,
+                    partner: row.get(5)?,
+                    token: row.get(6)?,
+                    amount: row.get(7)?,
+                    payout_tx_hash: row.get(8)?,
+                    payout_nonce: row.get(9)?,
+                    paid: row.get(10)?,
+                },
+            ))
+        })
+        .map_err(|e| e.to_string())?;
+    rows.collect::<rusqlite::Result<_>>()
+        .map_err(|e| e.to_string())
+}
+
+/// Records that the shares with row ids `ids` are being paid out by `tx_hash`, sent with `nonce`. They stay owed
+/// until `settle_tip_split_payout` records the outcome.
+pub fn mark_tip_splits_pending(ids: &[i64], tx_hash: &str, nonce: &str) -> Result<(), String> {
+    let history = HISTORY.lock().unwrap();
+    let conn = history
+        .as_ref()
+        .ok_or("No history is being recorded, set --history-db")?;
+    for id in ids {
+        conn.execute(
+            "UPDATE tip_splits SET payout_tx_hash = ?1, payout_nonce = ?2 WHERE id = ?3",
+            params![tx_hash, nonce, id],
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Records the outcome of the payout `tx_hash`: its shares are paid if it succeeded, and owed again otherwise
+pub fn settle_tip_split_payout(tx_hash: &str, succeeded: bool) -> Result<(), String> {
+    let history = HISTORY.lock().unwrap();
+    let conn = history
+        .as_ref()
+        .ok_or("No history is being recorded, set --history-db")?;
+    let statement = if succeeded {
+        "UPDATE tip_splits SET paid = 1 WHERE payout_tx_hash = ?1"
+    } else {
+        "UPDATE tip_splits SET payout_tx_hash = NULL, payout_nonce = NULL WHERE payout_tx_hash = ?1"
+    };
+    conn.execute(statement, params![tx_hash])
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// True if the command of `kind` with `id`, such as a protocol command, was recorded as executed
+pub fn was_executed(kind: &str, id: &str) -> bool {
+    let history = HISTORY.lock().unwrap();
+    let Some(conn) = history.as_ref() else {
+        return false;
+    };
+    conn.prepare("SELECT 1 FROM executed WHERE kind = ?1 AND id = ?2")
+        .and_then(|mut statement| statement.exists(params![kind, id]))
+        .unwrap_or_else(|e| {
+            warn!("Failed to look up whether {kind} {id} was executed: {e}");
+            false
+        })
+}
+
+/// Records the command of `kind` with `id` as executed on `chain_id`, before it is sent so that a restart in between
+/// never sends it again. Nothing is recorded if no history is.
+pub fn record_execution(kind: &str, id: &str, chain_id: u64) -> Result<(), String> {
+    let history = HISTORY.lock().unwrap();
+    let Some(conn) = history.as_ref() else {
+        return Ok(());
+    };
+    conn.execute(
+        "INSERT OR IGNORE INTO executed (kind, id, chain_id, timestamp) VALUES (?1, ?2, ?3, ?4)",
+        params![kind, id, chain_id as i64, unix_timestamp() as i64],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Attaches the hash of the transaction that executed the command of `kind` with `id`, or forgets the execution if
+/// sending it failed
+pub fn finish_execution(kind: &str, id: &str, tx_hash: Option<&str>) {
+    let history = HISTORY.lock().unwrap();
+    let Some(conn) = history.as_ref() else {
+        return;
+    };
+    let result = match tx_hash {
+        Some(tx_hash) => conn.execute(
+            "UPDATE executed SET tx_hash = ?1 WHERE kind = ?2 AND id = ?3",
+            params![tx_hash, kind, id],
+        ),
+        None => conn.execute(
+            "DELETE FROM executed WHERE kind = ?1 AND id = ?2",
+            params![kind, id],
+        ),
+    };
+    if let Err(e) = result {
+        warn!("Failed to record the execution of {kind} {id}: {e}");
+    }
+}
+
+fn parse_amount(amount: &Option<String>) -> Uint256 {
+    amount
+        .as_deref()
+        .and_then(|amount| Uint256::from_str(amount).ok())
+        .unwrap_or_default()
+}
+
+/// Per token totals of the final relays in `records`
+pub fn summarize(records: &[RelayRecord]) -> Vec<TokenSummary> {
+    let mut totals: BTreeMap<String, (Option<String>, u64, Uint256, Uint256)> = BTreeMap::new();
+    for record in records.iter().filter(|record| record.outcome == "final") {
+        let token = record.tip_token.clone().unwrap_or_default();
+        let (symbol, relays, tips, gas_paid) = totals.entry(token).or_default();
+        if record.tip_symbol.is_some() {
+            symbol.clone_from(&record.tip_symbol);
+        }
+        *relays += 1;
+        *tips += parse_amount(&record.tip_amount);
+        *gas_paid += parse_amount(&record.gas_paid);
+    }
+    totals
+        .into_iter()
+        .map(
+            |(tip_token, (tip_symbol, relays, tips, gas_paid))| TokenSummary {
+                tip_token,
+                tip_symbol,
+                relays,
+                tips: tips.to_string(),
+                gas_paid: gas_paid.to_string(),
+            },
+        )
+        .collect()
+}
+
+/// Renders the relays recorded in `conn` between `since` and `until` as `report` in `format`
+pub fn export(
+    conn: &Connection,
+    since: Option<u64>,
+    until: Option<u64>,
+    report: ExportReport,
+    format: ExportFormat,
+) -> rusqlite::Result<String> {
+    let records = query_history(
+        conn,
+        &HistoryFilter {
+            since,
+            until,
+            ..Default::default()
+        },
+    )?;
+    let optional = |value: &Option<String>| csv_field(value.as_deref().unwrap_or_default());
+    Ok(match (report, format) {
+        (ExportReport::Relays, ExportFormat::Json) => serde_json::to_string(&records).unwrap(),
+        (ExportReport::Summary, ExportFormat::Json) => {
+            serde_json::to_string(&summarize(&records)).unwrap()
+        }
+        (ExportReport::Relays, ExportFormat::Csv) => {
+            let mut csv = "timestamp,chain_id,sig_hash,user,tip_token,tip_amount,gas_paid,tip_value,tx_hash,outcome,error,tip_symbol,skip_reason\n".to_string();
+            for record in &records {
+                csv += &format!(
+                    "{},{},{},{},{},{},{},{},{},{},{},{},{}\n",
+                    format_timestamp(record.timestamp),
+                    record.chain_id,
+                    record.sig_hash,
+                    optional(&record.user),
+                    optional(&record.tip_token),
+                    optional(&record.tip_amount),
+                    optional(&record.gas_paid),
+                    optional(&record.tip_value),
+                    optional(&record.tx_hash),
+                    csv_field(&record.outcome),
+                    optional(&record.error),
+                    optional(&record.tip_symbol),
+                    optional(&record.skip_reason)
+                );
+            }
+            csv
+        }
+        (ExportReport::Summary, ExportFormat::Csv) => {
+            let mut csv = "tip_token,relays,tips,gas_paid,tip_symbol\n".to_string();
+            for summary in summarize(&records) {
+                csv += &format!(
+                    "{},{},{},{},{}\n",
+                    summary.tip_token,
+                    summary.relays,
+                    summary.tips,
+                    summary.gas_paid,
+                    optional(&summary.tip_symbol)
+                );
+            }
+            csv
+        }
+    })
+}
+
+/// Same as `export` on the history the relayer is recording to, if any
+pub fn export_history(
+    since: Option<u64>,
+    until: Option<u64>,
+    report: ExportReport,
+    format: ExportFormat,
+) -> Result<String, String> {
+    let history = HISTORY.lock().unwrap();
+    let conn = history
+        .as_ref()
+        .ok_or("No history is being recorded, set --history-db")?;
+    export(conn, since, until, report, format).map_err(|e| e.to_string())
+}
+
+/// Quotes a CSV field if it contains a separator, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Parses a date given either as a unix timestamp or as `YYYY-MM-DD` in UTC
+pub fn parse_date(s: &str) -> Result<u64, String> {
+    if let Ok(timestamp) = s.parse::<u64>() {
+        return Ok(timestamp);
+    }
+    let invalid = || format!("Invalid date {s}, expected YYYY-MM-DD or a unix timestamp");
+    let mut parts = s.splitn(3, '-').map(|part| part.parse::<i64>());
+    let (Some(Ok(year)), Some(Ok(month)), Some(Ok(day))) =
+        (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid());
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || year < 1970 {
+        return Err(invalid());
+    }
+    Ok((days_from_civil(year, month, day) * 86_400) as u64)
+}
+
+/// Formats a unix timestamp as an ISO 8601 UTC date and time
+pub fn format_timestamp(timestamp: u64) -> String {
+    let days = (timestamp / 86_400) as i64;
+    let seconds = timestamp % 86_400;
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date, Howard Hinnant's algorithm
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Inverse of `days_from_civil`
+pub(crate) fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
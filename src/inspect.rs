@@ -0,0 +1,235 @@
+//! Tracing of high value relays before they are broadcast. The `cmd` of a relay is chosen by the user, so a crafted
+//! command could pass a plain simulation while never paying its tip, or while moving funds held by the relayer
+//! account. Relays whose tip is worth enough are traced with `debug_traceCall` against the latest state, either on
+//! the chain's RPC or on a dedicated endpoint such as a local fork, and are only broadcast if the trace shows the tip
+//! reaching us and nothing leaving our accounts. Relays that revert on chain anyway are traced after the fact with
+//! `debug_traceTransaction`, so the cause can be looked into once the state they ran against is long gone. A node
+//! that can't trace, or doesn't record logs in its traces, can't tell either way, and relays are then left to the
+//! simulation they already passed rather than rejected.
+
+use crate::error::RelayerError;
+use crate::relay::RelayContext;
+use crate::rpc::redact_url;
+use crate::signer::Signer;
+use crate::tokens::display_token;
+use clarity::utils::display_uint256_as_address;
+use clarity::{Address, Transaction, Uint256};
+use log::{debug, warn};
+use serde::Deserialize;
+use serde_json::json;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use web30::client::Web3;
+use web30::jsonrpc::client::HttpClient;
+use web30::types::TransactionRequest;
+
+/// keccak256("Transfer(address,address,uint256)")
+const TRANSFER_TOPIC: &str = "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef";
+/// keccak256("Approval(address,address,uint256)")
+const APPROVAL_TOPIC: &str = "0x8c5be1e5ebec7d5bd14f71427d1e84f3dd0314c0f7b2291e5b200ac8c7c3b925";
+
+/// Whether a trace that couldn't tell anything was already warned about, it is only logged at debug level after
+static WARNED_UNTRACEABLE: AtomicBool = AtomicBool::new(false);
+
+/// One call of a `callTracer` trace, along with the calls it made and the logs it emitted
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CallFrame {
+    from: Address,
+    to: Option<Address>,
+    value: Option<Uint256>,
+    error: Option<String>,
+    revert_reason: Option<String>,
+    #[serde(default)]
+    calls: Vec<CallFrame>,
+    #[serde(default)]
+    logs: Vec<CallLog>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CallLog {
+    address: Address,
+    topics: Vec<String>,
+    data: String,
+}
+
+/// What a traced relay pays our accounts, and what it takes from them
+#[derive(Debug, Default)]
+struct ValueFlows {
+    /// wei of the gas token received
+    native: Uint256,
+    /// base units of the tip token received
+    tip_token: Uint256,
+    /// every movement of funds out of our accounts, described for the log
+    outflows: Vec<String>,
+}
+
+/// Traces `call` from `from` and checks that it pays at least `tip_amount` of `tip_token` to one of our accounts
+/// without moving anything out of them, the zero address standing for the gas token
+pub async fn inspect_relay(
+    ctx: &RelayContext,
+    call: &Transaction,
+    from: Address,
+    tip_token: Address,
+    tip_amount: Uint256,
+) -> Result<(), RelayerError> {
+    let web3 = ctx.trace_rpc.as_ref().unwrap_or(ctx.web3());
+    let trace: Result<CallFrame, _> = HttpClient::new(&web3.get_url())
+        .request_method(
+            "debug_traceCall",
+            vec![
+                json!(TransactionRequest::from_transaction(call, from)),
+                json!("latest"),
+                json!({"tracer": "callTracer", "tracerConfig": {"withLog": true}}),
+            ],
+            web3.get_timeout(),
+        )
+        .await;
+    // transient failures are retried, a node without the tracer just can't tell
+    let trace = match trace.map_err(RelayerError::from) {
+        Ok(trace) => trace,
+        Err(RelayerError::RpcError(e)) => {
+            untraceable(ctx, &format!("it can't trace the call: {e}"));
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    };
+    if let Some(error) = &trace.error {
+        return Err(RelayerError::SimulationRevert(
+            trace.revert_reason.clone().unwrap_or_else(|| error.clone()),
+        ));
+    }
+
+    let ours: Vec<Address> = ctx
+        .signers
+        .signers()
+        .iter()
+        .map(Signer::address)
+        .chain(ctx.tip_receivers.iter().copied())
+        .collect();
+    let mut flows = ValueFlows::default();
+    collect_flows(&trace, &ours, tip_token, &mut flows);
+    if !flows.outflows.is_empty() {
+        return Err(RelayerError::UnexpectedEffects(flows.outflows.join(", ")));
+    }
+    let received = if tip_token == Address::default() {
+        flows.native
+    } else {
+        flows.tip_token
+    };
+    // without `withLog` a node leaves the logs out, so a relay calling the tip token without a single log anywhere
+    // can't be told apart from one that pays nothing. One that never calls the tip token can't pay it either way
+    if tip_token != Address::default() && calls(&trace, tip_token) && !has_logs(&trace) {
+        untraceable(ctx, "its trace holds no logs");
+        return Ok(());
+    }
+    if received < tip_amount {
+        return Err(RelayerError::UnexpectedEffects(format!(
+            "pays {received} of its {tip_amount} tip in {}",
+            display_token(ctx.chain_id, tip_token)
+        )));
+    }
+    Ok(())
+}
+
+/// Logs that the trace RPC of `ctx` couldn't tell whether a relay pays its tip, once as a warning
+fn untraceable(ctx: &RelayContext, why: &str) {
+    let web3 = ctx.trace_rpc.as_ref().unwrap_or(ctx.web3());
+    if !WARNED_UNTRACEABLE.swap(true, Ordering::Relaxed) {
+        warn!(
+            "Relays on {} aren't inspected by {}, {why}. Relying on simulation alone",
+            ctx.name,
+            redact_url(&web3.get_url())
+        );
+    } else {
+        debug!("Not inspecting a relay on {}, {why}", ctx.name);
+    }
+}
+
+/// True if `frame` or any call below it emitted a log
+fn has_logs(frame: &CallFrame) -> bool {
+    !frame.logs.is_empty() || frame.calls.iter().any(has_logs)
+}
+
+/// True if `frame` or any call below it that didn't fail calls `contract`
+fn calls(frame: &CallFrame, contract: Address) -> bool {
+    frame.error.is_none()
+        && (frame.to == Some(contract) || frame.calls.iter().any(|call| calls(call, contract)))
+}
+
+/// `callTracer` trace of the mined transaction `tx_hash`, with the logs of its calls. Only some RPCs serve
+/// `debug_traceTransaction`, so failing is expected.
+pub async fn trace_transaction(
+    web3: &Web3,
+    tx_hash: Uint256,
+) -> Result<serde_json::Value, RelayerError> {
+    Ok(HttpClient::new(&web3.get_url())
+        .request_method(
+            "debug_traceTransaction",
+            vec![
+                json!(display_uint256_as_address(tx_hash)),
+                json!({"tracer": "callTracer", "tracerConfig": {"withLog": true}}),
+            ],
+            web3.get_timeout(),
+        )
+        .await?)
+}
+
+/// Adds up the value flows of `frame` and the calls below it, calls that failed are skipped since everything they
+/// did is rolled back
+fn collect_flows(frame: &CallFrame, ours: &[Address], tip_token: Address, flows: &mut ValueFlows) {
+    if frame.error.is_some() {
+        return;
+    }
+    if let Some(value) = frame.value
+        && value > Uint256::default()
+    {
+        if ours.contains(&frame.from) {
+            flows
+                .outflows
+                .push(format!("sends {value} wei from {}", frame.from));
+        }
+        if frame.to.is_some_and(|to| ours.contains(&to)) {
+            flows.native += value;
+        }
+    }
+    for log in &frame.logs {
+        // ERC-721 transfers carry the token id as a fourth topic, only fungible transfers count towards a tip
+        let (Some(topic), Some(owner)) = (
+            log.topics.first(),
+            log.topics.get(1).and_then(|t| topic_address(t)),
+        ) else {
+            continue;
+        };
+        if topic.eq_ignore_ascii_case(APPROVAL_TOPIC) && ours.contains(&owner) {
+            flows
+                .outflows
+                .push(format!("approves spending {} of {owner}", log.address));
+        }
+        if !topic.eq_ignore_ascii_case(TRANSFER_TOPIC) {
+            continue;
+        }
+        if ours.contains(&owner) {
+            flows
+                .outflows
+                .push(format!("transfers {} out of {owner}", log.address));
+        }
+        if log.address == tip_token
+            && log.topics.len() == 3
+            && let Some(to) = log.topics.get(2).and_then(|t| topic_address(t))
+            && ours.contains(&to)
+            && let Ok(amount) = Uint256::from_str(&log.data)
+        {
+            flows.tip_token += amount;
+        }
+    }
+    for call in &frame.calls {
+        collect_flows(call, ours, tip_token, flows);
+    }
+}
+
+/// The address in an indexed event topic, which is left padded to 32 bytes
+fn topic_address(topic: &str) -> Option<Address> {
+    let hex = topic.trim_start_matches("0x");
+    Address::from_str(hex.get(hex.len().checked_sub(40)?..)?).ok()
+}
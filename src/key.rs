@@ -0,0 +1,174 @@
+//! Loading of the relayer's private key from sources other than a plaintext command line argument, and the
+//! containers keys are held in so they are wiped from memory once dropped and never end up in logs or dumps.
+
+use clarity::{Address, PrivateKey, Signature, Transaction};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::convert::Infallible;
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use zeroize::Zeroizing;
+
+/// A private key held in process. clarity's `PrivateKey` is `Copy` and prints the key in its `Debug` output, so it
+/// is kept in a single heap allocation that is wiped on drop and is only ever lent out to sign.
+pub struct LocalKey {
+    key: Box<PrivateKey>,
+    address: Address,
+}
+
+impl LocalKey {
+    /// Takes ownership of `key`, wiping the copy that was passed in
+    pub fn new(mut key: PrivateKey) -> Self {
+        let local = LocalKey {
+            key: Box::new(key),
+            address: key.to_address(),
+        };
+        wipe(&mut key);
+        local
+    }
+
+    pub fn address(&self) -> Address {
+        self.address
+    }
+
+    pub fn sign_transaction(&self, tx: Transaction) -> Transaction {
+        tx.sign(&self.key, None)
+    }
+
+    pub fn sign_message(&self, data: &[u8]) -> Signature {
+        self.key.sign_ethereum_msg(data)
+    }
+}
+
+impl FromStr for LocalKey {
+    type Err = clarity::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(LocalKey::new(PrivateKey::from_str(s)?))
+    }
+}
+
+impl Clone for LocalKey {
+    fn clone(&self) -> Self {
+        LocalKey {
+            key: Box::new(*self.key),
+            address: self.address,
+        }
+    }
+}
+
+impl Drop for LocalKey {
+    fn drop(&mut self) {
+        wipe(&mut self.key);
+    }
+}
+
+impl fmt::Debug for LocalKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalKey")
+            .field("address", &self.address)
+            .finish_non_exhaustive()
+    }
+}
+
+fn wipe(key: &mut PrivateKey) {
+    // Safety: a private key is plain bytes, the key and its address, for which all zeroes is a valid value
+    unsafe { zeroize::zeroize_flat_type(key as *mut PrivateKey) }
+}
+
+/// A hex private key as given on the command line or in the config file, kept as text until the signers are
+/// built. It is wiped from memory when dropped and is redacted from `Debug` output and when serialized.
+#[derive(Clone, Default)]
+pub struct SecretString(Zeroizing<String>);
+
+impl SecretString {
+    pub fn to_key(&self) -> Result<LocalKey, clarity::Error> {
+        LocalKey::from_str(self.0.trim())
+    }
+}
+
+// parsing is left to `to_key` so a mistyped key isn't echoed back in clap's error message
+impl FromStr for SecretString {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(SecretString(Zeroizing::new(s.to_owned())))
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("\"<redacted>\"")
+    }
+}
+
+// redacted like `Debug`, so that serializing a config that holds a key never writes the key out
+impl Serialize for SecretString {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str("<redacted>")
+    }
+}
+
+impl<'de> Deserialize<'de> for SecretString {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(|s| SecretString(Zeroizing::new(s)))
+    }
+}
+
+/// Reads a hex private key from the environment variable `var`
+pub fn load_private_key_env(var: &str) -> Result<LocalKey, Box<dyn Error>> {
+    let key = Zeroizing::new(std::env::var(var).map_err(|e| format!("Failed to read {var}: {e}"))?);
+    Ok(LocalKey::from_str(key.trim())?)
+}
+
+/// Reads a hex private key from a file, such as a mounted Kubernetes secret or a systemd credential,
+/// surrounding whitespace is ignored
+pub fn load_private_key_file(path: &Path) -> Result<LocalKey, Box<dyn Error>> {
+    let key = Zeroizing::new(
+        std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {e}", path.display()))?,
+    );
+    Ok(LocalKey::from_str(key.trim())?)
+}
+
+/// Decrypts a web3 v3 JSON keystore file. When `password_fd` is provided the passphrase is read from
+/// that file descriptor, otherwise it is prompted for interactively.
+pub fn load_keystore(path: &Path, password_fd: Option<i32>) -> Result<LocalKey, Box<dyn Error>> {
+    let password = Zeroizing::new(match password_fd {
+        Some(fd) => read_password_fd(fd)?,
+        None => rpassword::prompt_password(format!("Passphrase for {}: ", path.display()))?,
+    });
+    let key = Zeroizing::new(eth_keystore::decrypt_key(path, password.as_bytes())?);
+    let key: Zeroizing<[u8; 32]> = Zeroizing::new(
+        key.as_slice()
+            .try_into()
+            .map_err(|_| "Keystore does not contain a 32 byte private key")?,
+    );
+    Ok(LocalKey::new(PrivateKey::from_bytes(*key)?))
+}
+
+/// Reads a passphrase from an already open file descriptor, such as one set up with `3<passphrase.txt`,
+/// a single trailing newline is stripped
+#[cfg(unix)]
+fn read_password_fd(fd: i32) -> Result<String, Box<dyn Error>> {
+    use std::io::Read;
+    use std::os::fd::FromRawFd;
+
+    // Safety: the fd is provided by the operator for this purpose and is not used anywhere else
+    let mut file = unsafe { std::fs::File::from_raw_fd(fd) };
+    let mut password = String::new();
+    file.read_to_string(&mut password)?;
+    if password.ends_with('\n') {
+        password.pop();
+        if password.ends_with('\r') {
+            password.pop();
+        }
+    }
+    Ok(password)
+}
+
+#[cfg(not(unix))]
+fn read_password_fd(_fd: i32) -> Result<String, Box<dyn Error>> {
+    Err("Reading the keystore passphrase from a file descriptor is only supported on unix".into())
+}
@@ -0,0 +1,114 @@
+//! Kill switch checked every cycle, so that an operator or an automated monitor can halt all submissions without
+//! access to the box. The switch is either a local file or an http(s) url, reading `pause` to halt relaying and
+//! `run` to let it continue. A missing file reads as `run`, so the switch can be flipped by creating the file.
+//! If the switch can't be read, or reads anything else, relaying is halted too unless the switch was configured to
+//! fail open, in which case its last known state is kept.
+
+use crate::proxy::http_client;
+use log::{info, warn};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub enum KillSwitch {
+    File(PathBuf),
+    Url { url: String, timeout: Duration },
+}
+
+impl KillSwitch {
+    pub fn new(location: &str, timeout: Duration) -> Self {
+        if location.starts_with("http://") || location.starts_with("https://") {
+            KillSwitch::Url {
+                url: location.to_string(),
+                timeout,
+            }
+        } else {
+            KillSwitch::File(PathBuf::from(location))
+        }
+    }
+
+    fn location(&self) -> String {
+        match self {
+            KillSwitch::File(path) => path.display().to_string(),
+            KillSwitch::Url { url, .. } => url.clone(),
+        }
+    }
+
+    /// Reads the switch, returning the text it currently holds
+    async fn read(&self) -> Result<String, String> {
+        match self {
+            KillSwitch::File(path) => match std::fs::read_to_string(path) {
+                Ok(text) => Ok(text),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok("run".to_string()),
+                Err(e) => Err(e.to_string()),
+            },
+            KillSwitch::Url { url, timeout } => {
+                let mut response = http_client(None, *timeout)
+                    .get(url)
+                    .send()
+                    .await
+                    .map_err(|e| e.to_string())?;
+                if !response.status().is_success() {
+                    return Err(format!("responded with {}", response.status()));
+                }
+                let body = response.body().await.map_err(|e| e.to_string())?;
+                Ok(String::from_utf8_lossy(&body).into_owned())
+            }
+        }
+    }
+}
+
+static SWITCH: LazyLock<Mutex<Option<KillSwitch>>> = LazyLock::new(|| Mutex::new(None));
+
+static ENGAGED: AtomicBool = AtomicBool::new(false);
+
+/// Whether a switch that can't be read keeps its last known state rather than halting relaying
+static FAIL_OPEN: AtomicBool = AtomicBool::new(false);
+
+pub fn set_kill_switch(switch: Option<KillSwitch>, fail_open: bool) {
+    if switch.is_none() {
+        ENGAGED.store(false, Ordering::Relaxed);
+    }
+    FAIL_OPEN.store(fail_open, Ordering::Relaxed);
+    *SWITCH.lock().unwrap() = switch;
+}
+
+/// True while the kill switch last read `pause`, or couldn't be read unless it fails open
+pub fn kill_switch_engaged() -> bool {
+    ENGAGED.load(Ordering::Relaxed)
+}
+
+/// Reads the kill switch, if there is one, and updates whether relaying is halted by it
+pub async fn check_kill_switch() {
+    let Some(switch) = SWITCH.lock().unwrap().clone() else {
+        return;
+    };
+    let location = switch.location();
+    let engaged = match switch.read().await {
+        Ok(text) => match text.trim().to_ascii_lowercase().as_str() {
+            "pause" => true,
+            "run" => false,
+            other => {
+                warn!("Kill switch {location} reads {other:?}, expected pause or run");
+                if FAIL_OPEN.load(Ordering::Relaxed) {
+                    return;
+                }
+                true
+            }
+        },
+        Err(e) => {
+            warn!("Failed to read kill switch {location}: {e}");
+            if FAIL_OPEN.load(Ordering::Relaxed) {
+                return;
+            }
+            true
+        }
+    };
+    match (ENGAGED.swap(engaged, Ordering::Relaxed), engaged) {
+        (false, true) => warn!("Kill switch {location} engaged, relaying paused"),
+        (true, false) => info!("Kill switch {location} released, relaying resumed"),
+        _ => {}
+    }
+}
@@ -0,0 +1,181 @@
+//! Layout of the dex's relaying entry point. The iFi dex relays through `userCmdRelayer` with an
+//! `(address token, uint128 amount, address receiver)` tip, where the receivers 0x100 and 0x200 pay `msg.sender`
+//! and `tx.origin`. Forks and future upgrades of the dex may name the function differently, order the tip's
+//! words differently or use other receiver markers, so all of these can be configured for each chain. Some
+//! gasless flows target periphery contracts such as a router instead of the dex, these are configured as extra
+//! relay targets that transactions select with their `target` field.
+
+use crate::blob::{Blob, DecodeError};
+use crate::orchestrator::RelayerTip;
+use clarity::Address;
+use std::fmt;
+use std::str::FromStr;
+
+// function userCmdRelayer (uint16 callpath, bytes calldata cmd,
+//                          bytes calldata conds, bytes calldata relayerTip,
+//                          bytes calldata signature)
+pub const USER_CMD_RELAYER_SIG: &str = "userCmdRelayer(uint16,bytes,bytes,bytes,bytes)";
+
+pub const SENDER_RECEIVER: &str = "0x0000000000000000000000000000000000000100";
+pub const ORIGIN_RECEIVER: &str = "0x0000000000000000000000000000000000000200";
+
+/// Arguments every relay function takes, the callpath, command, conditions, tip and signature
+const RELAY_FUNCTION_ARGS: &str = "(uint16,bytes,bytes,bytes,bytes)";
+
+fn check_relay_function(function: &str) -> Result<(), String> {
+    if !function.ends_with(RELAY_FUNCTION_ARGS) || function.len() == RELAY_FUNCTION_ARGS.len() {
+        return Err(format!(
+            "Invalid relay function {function}, expected <name>{RELAY_FUNCTION_ARGS}"
+        ));
+    }
+    Ok(())
+}
+
+/// A periphery contract gasless transactions may be relayed to besides the dex, written as its address
+/// optionally followed by `=` and the function relays are sent to, such as
+/// `0x1234...=relay(uint16,bytes,bytes,bytes,bytes)`. Without a function the dex's relay function is used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayTarget {
+    pub contract: Address,
+    pub relay_function: Option<String>,
+}
+
+impl FromStr for RelayTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (contract, relay_function) = match s.split_once('=') {
+            Some((contract, function)) => {
+                check_relay_function(function.trim())?;
+                (contract, Some(function.trim().to_string()))
+            }
+            None => (s, None),
+        };
+        Ok(RelayTarget {
+            contract: Address::from_str(contract.trim())
+                .map_err(|e| format!("Invalid relay target {s}: {e}"))?,
+            relay_function,
+        })
+    }
+}
+
+/// One 32 byte word of an abi encoded tip
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TipField {
+    Token,
+    Amount,
+    Receiver,
+    /// a word the relayer doesn't use
+    Skip,
+}
+
+impl FromStr for TipField {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "token" => Ok(TipField::Token),
+            "amount" => Ok(TipField::Amount),
+            "receiver" => Ok(TipField::Receiver),
+            "_" => Ok(TipField::Skip),
+            _ => Err(format!(
+                "Invalid tip field {s}, expected token, amount, receiver or _"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for TipField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TipField::Token => "token",
+            TipField::Amount => "amount",
+            TipField::Receiver => "receiver",
+            TipField::Skip => "_",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContractLayout {
+    /// signature of the function relays are sent to, it takes the callpath, command, conditions, tip and
+    /// signature in that order
+    pub relay_function: String,
+    /// the tip's words in order
+    pub tip_layout: Vec<TipField>,
+    /// receiver that pays whoever sends the relay, `msg.sender`
+    pub sender_receiver: Address,
+    /// receiver that pays the account the relay originates from, `tx.origin`
+    pub origin_receiver: Address,
+}
+
+impl Default for ContractLayout {
+    fn default() -> Self {
+        ContractLayout {
+            relay_function: USER_CMD_RELAYER_SIG.to_string(),
+            tip_layout: vec![TipField::Token, TipField::Amount, TipField::Receiver],
+            sender_receiver: Address::from_str(SENDER_RECEIVER).unwrap(),
+            origin_receiver: Address::from_str(ORIGIN_RECEIVER).unwrap(),
+        }
+    }
+}
+
+impl ContractLayout {
+    /// Layout with the given overrides of the default, checking that the tip layout names each field once
+    pub fn new(
+        relay_function: Option<&str>,
+        tip_layout: Option<&[String]>,
+        sender_receiver: Option<&str>,
+        origin_receiver: Option<&str>,
+    ) -> Result<Self, String> {
+        let mut layout = ContractLayout::default();
+        if let Some(function) = relay_function {
+            check_relay_function(function)?;
+            layout.relay_function = function.to_string();
+        }
+        if let Some(fields) = tip_layout {
+            layout.tip_layout = fields
+                .iter()
+                .map(|field| TipField::from_str(field))
+                .collect::<Result<_, _>>()?;
+            for field in [TipField::Token, TipField::Amount, TipField::Receiver] {
+                if layout.tip_layout.iter().filter(|f| **f == field).count() != 1 {
+                    return Err(format!(
+                        "Invalid tip layout, {field} must appear exactly once"
+                    ));
+                }
+            }
+        }
+        if let Some(address) = sender_receiver {
+            layout.sender_receiver = Address::from_str(address)
+                .map_err(|e| format!("Invalid sender receiver {address}: {e}"))?;
+        }
+        if let Some(address) = origin_receiver {
+            layout.origin_receiver = Address::from_str(address)
+                .map_err(|e| format!("Invalid origin receiver {address}: {e}"))?;
+        }
+        Ok(layout)
+    }
+
+    /// Decodes an abi encoded tip laid out as configured
+    pub fn decode_tip(&self, tip: &[u8]) -> Result<RelayerTip, DecodeError> {
+        let index = |field: TipField| {
+            self.tip_layout
+                .iter()
+                .position(|f| *f == field)
+                .unwrap_or_default()
+        };
+        let tip = Blob::new("tip", tip, self.tip_layout.len())?;
+        Ok(RelayerTip {
+            token: tip.address(index(TipField::Token), "tip token")?,
+            amount: tip.uint(index(TipField::Amount), "tip amount", 128)?,
+            receiver: tip.address(index(TipField::Receiver), "tip receiver")?,
+        })
+    }
+
+    /// True if a tip to `receiver` pays whichever account sends the relay
+    pub fn pays_any_sender(&self, receiver: Address) -> bool {
+        receiver == self.sender_receiver || receiver == self.origin_receiver
+    }
+}
@@ -0,0 +1,698 @@
+//! Coordination of redundant instances relaying from the same accounts through leases in a shared Redis or etcd
+//! store. Every cycle each instance renews a lease on each of its relayer accounts, and only relays from the
+//! accounts it holds, so that a single instance hands out their nonces. The others keep evaluating transactions as
+//! a hot standby, ready to take over once the holder stops renewing. On top of that each transaction is leased
+//! once it passed every check, right before it is claimed and broadcast, so that it is only ever relayed by one
+//! instance. The lease is released again if the relay is skipped or fails to be sent. If the store can't be
+//! reached nothing is relayed, since submitting blind risks two instances using the same nonce.
+//!
+//! Leader election goes further for active/standby deployments. A single leader lease, heartbeated several
+//! times per poll interval, decides which instance broadcasts anything at all. The standby instances still
+//! poll and simulate every transaction, so when the leader's heartbeat stops one of them takes over within a
+//! poll interval with warm caches.
+
+use crate::orchestrator::{GaslessTransaction, refetch_pending};
+use crate::proxy::http_client;
+use crate::relay::RelayContext;
+use crate::shutdown::sleep_or_shutdown;
+use crate::status::record_leader;
+use actix_rt::net::TcpStream;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use clarity::Address;
+use log::{info, warn};
+use serde_json::{Value, json};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+/// Prefix of every key the relayer writes to the store
+const KEY_PREFIX: &str = "althea-relayer";
+
+/// Takes or extends the lease in KEYS[1] for the instance in ARGV[1] for ARGV[2] milliseconds
+const REDIS_ACQUIRE: &str = "local holder = redis.call('GET', KEYS[1]) \
+    if holder == false then redis.call('SET', KEYS[1], ARGV[1], 'PX', ARGV[2]) return 1 end \
+    if holder == ARGV[1] then redis.call('PEXPIRE', KEYS[1], ARGV[2]) return 1 end \
+    return 0";
+
+/// Deletes the lease in KEYS[1] if it is held by the instance in ARGV[1]
+const REDIS_RELEASE: &str = "if redis.call('GET', KEYS[1]) == ARGV[1] then return redis.call('DEL', KEYS[1]) end \
+    return 0";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LeaseStore {
+    /// Redis server at `address` (`host:port`)
+    Redis {
+        address: String,
+        password: Option<String>,
+        db: Option<u32>,
+    },
+    /// etcd reached through its v3 JSON gateway at `url`
+    Etcd { url: String },
+}
+
+impl LeaseStore {
+    /// Parses `redis://[:PASSWORD@]HOST[:PORT][/DB]` or `etcd+http(s)://HOST[:PORT]`
+    pub fn parse(location: &str) -> Result<Self, String> {
+        if let Some(rest) = location.strip_prefix("redis://") {
+            let (credentials, rest) = match rest.rsplit_once('@') {
+                Some((credentials, rest)) => (Some(credentials), rest),
+                None => (None, rest),
+            };
+            let (host, db) = match rest.split_once('/') {
+                Some((host, "")) => (host, None),
+                Some((host, db)) => (
+                    host,
+                    Some(
+                        db.parse()
+                            .map_err(|_| format!("{location}: invalid database {db}"))?,
+                    ),
+                ),
+                None => (rest, None),
+            };
+            if host.is_empty() {
+                return Err(format!("{location}: missing host"));
+            }
+            let address = if host.rsplit_once(':').is_some_and(|(_, port)| {
+                !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit())
+            }) {
+                host.to_string()
+            } else {
+                format!("{host}:6379")
+            };
+            // the user name is ignored, only the password of the default user is supported
+            let password = credentials
+                .map(|c| c.rsplit_once(':').map_or(c, |(_, password)| password))
+                .filter(|password| !password.is_empty())
+                .map(str::to_string);
+            Ok(LeaseStore::Redis {
+                address,
+                password,
+                db,
+            })
+        } else if let Some(url) = location.strip_prefix("etcd+") {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err(format!(
+                    "{location}: expected etcd+http:// or etcd+https://"
+                ));
+            }
+            Ok(LeaseStore::Etcd {
+                url: url.trim_end_matches('/').to_string(),
+            })
+        } else {
+            Err(format!(
+                "{location}: expected a redis:// or etcd+http(s):// url"
+            ))
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            LeaseStore::Redis { address, .. } => format!("redis://{address}"),
+            LeaseStore::Etcd { url } => format!("etcd+{url}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LeasePolicy {
+    pub store: LeaseStore,
+    /// identifies this instance as the holder of its leases
+    pub instance: String,
+    /// how long a lease lasts without being renewed
+    pub ttl: Duration,
+    /// timeout of each request to the store
+    pub timeout: Duration,
+}
+
+static POLICY: LazyLock<Mutex<Option<LeasePolicy>>> = LazyLock::new(|| Mutex::new(None));
+
+/// When the lease on each relayer account was last renewed, by chain id and address
+static HELD: LazyLock<Mutex<HashMap<(u64, Address), Instant>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Accounts known to be leased by another instance, so standing by is only logged when it starts
+static STANDING_BY: LazyLock<Mutex<HashMap<(u64, Address), bool>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Poll interval the leader heartbeat is derived from, None unless leader election is enabled
+static ELECTION: LazyLock<Mutex<Option<Duration>>> = LazyLock::new(|| Mutex::new(None));
+
+/// When this instance last renewed the leader lease, None while it isn't the leader
+static LEADER: LazyLock<Mutex<Option<Instant>>> = LazyLock::new(|| Mutex::new(None));
+
+/// Ids of the transactions this instance leased and hasn't yet relayed or given up on
+static RELAY_LEASES: LazyLock<Mutex<HashSet<String>>> =
+    LazyLock::new(|| Mutex::new(HashSet::new()));
+
+/// Idle connections to the Redis store with its address, already authenticated and on the configured database
+type RedisConnections = Vec<(String, BufReader<TcpStream>)>;
+
+static REDIS_CONNECTIONS: LazyLock<Mutex<RedisConnections>> =
+    LazyLock::new(|| Mutex::new(Vec::new()));
+
+/// Most idle Redis connections kept open, more are only opened while commands overlap
+const MAX_IDLE_REDIS_CONNECTIONS: usize = 4;
+
+/// etcd lease id attached to each key we hold in etcd
+static ETCD_LEASES: LazyLock<Mutex<HashMap<String, String>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+pub fn set_lease_policy(policy: Option<LeasePolicy>) {
+    if let Some(policy) = &policy {
+        info!(
+            "Coordinating with other instances through {} as {}, leases last {}s",
+            policy.store.describe(),
+            policy.instance,
+            policy.ttl.as_secs()
+        );
+    }
+    *POLICY.lock().unwrap() = policy;
+}
+
+/// Enables leader election among the instances sharing the lease store, which must be set first. The leader
+/// lease lasts three quarters of `poll_interval` and is renewed every quarter, so a standby takes over within
+/// one poll interval of the leader's last heartbeat.
+pub fn set_leader_election(poll_interval: Option<Duration>) {
+    if poll_interval.is_some() {
+        record_leader(false);
+    }
+    *ELECTION.lock().unwrap() = poll_interval;
+}
+
+/// True if this instance may broadcast, that is if leader election is disabled or this instance renewed the
+/// leader lease within the last half of its ttl
+pub fn is_leader() -> bool {
+    let Some(poll_interval) = *ELECTION.lock().unwrap() else {
+        return true;
+    };
+    LEADER
+        .lock()
+        .unwrap()
+        .is_some_and(|renewed| renewed.elapsed() < leader_ttl(poll_interval) / 2)
+}
+
+fn leader_ttl(poll_interval: Duration) -> Duration {
+    poll_interval * 3 / 4
+}
+
+fn leader_key(address: Address) -> String {
+    format!("{KEY_PREFIX}:leader:{address}")
+}
+
+/// Heartbeats the leader lease of the instances relaying from `address` until shutdown, taking it over whenever
+/// it is free, and steps down at shutdown
+pub async fn leader_election_loop(address: Address) {
+    let (Some(policy), Some(poll_interval)) =
+        (POLICY.lock().unwrap().clone(), *ELECTION.lock().unwrap())
+    else {
+        return;
+    };
+    let key = leader_key(address);
+    let ttl = leader_ttl(poll_interval);
+    let mut leading = None;
+    loop {
+        let renewed = Instant::now();
+        match acquire(&policy, &key, ttl).await {
+            Ok(true) => {
+                *LEADER.lock().unwrap() = Some(renewed);
+                if leading != Some(true) {
+                    info!("Elected leader as {}, broadcasting relays", policy.instance);
+                    record_leader(true);
+                    // the queue was only evaluated while standing by
+                    refetch_pending();
+                }
+                leading = Some(true);
+            }
+            Ok(false) => {
+                *LEADER.lock().unwrap() = None;
+                match leading {
+                    Some(true) => warn!("Another instance took over as leader, standing by"),
+                    None => info!("Another instance is the leader, standing by"),
+                    Some(false) => {}
+                }
+                record_leader(false);
+                leading = Some(false);
+            }
+            // leadership lapses on its own if heartbeats keep failing
+            Err(e) => warn!(
+                "Failed to send leader heartbeat through {}: {e}",
+                policy.store.describe()
+            ),
+        }
+        if sleep_or_shutdown(poll_interval / 4).await {
+            break;
+        }
+    }
+    if LEADER.lock().unwrap().take().is_some() {
+        record_leader(false);
+        match release(&policy, &key).await {
+            Ok(()) => info!("Stepped down as leader"),
+            Err(e) => warn!("Failed to release the leader lease: {e}"),
+        }
+    }
+}
+
+/// An instance id unique to this process, used unless one is configured
+pub fn default_instance_id() -> String {
+    let host = std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|host| host.trim().to_string())
+        .ok()
+        .filter(|host| !host.is_empty())
+        .unwrap_or_else(|| "relayer".to_string());
+    format!(
+        "{host}-{}-{:08x}",
+        std::process::id(),
+        rand::random::<u32>()
+    )
+}
+
+fn account_key(chain_id: u64, address: Address) -> String {
+    format!("{KEY_PREFIX}:account:{chain_id}:{address}")
+}
+
+/// Renews, or tries to take over, the lease on every relayer account of every chain. Called once per cycle, so
+/// the lease ttl must comfortably exceed the time a cycle takes.
+pub async fn renew_account_leases(chains: &[RelayContext]) {
+    let Some(policy) = POLICY.lock().unwrap().clone() else {
+        return;
+    };
+    // the leader relays from every account
+    if ELECTION.lock().unwrap().is_some() {
+        return;
+    }
+    for ctx in chains {
+        for signer in ctx.signers.signers() {
+            let account = (ctx.chain_id, signer.address());
+            let renewed = Instant::now();
+            let held = match acquire(
+                &policy,
+                &account_key(ctx.chain_id, signer.address()),
+                policy.ttl,
+            )
+            .await
+            {
+                Ok(held) => held,
+                Err(e) => {
+                    warn!(
+                        "Failed to renew the lease on {} on {} through {}: {e}",
+                        signer.address(),
+                        ctx.name,
+                        policy.store.describe()
+                    );
+                    continue;
+                }
+            };
+            let was_held = if held {
+                HELD.lock().unwrap().insert(account, renewed).is_some()
+            } else {
+                HELD.lock().unwrap().remove(&account).is_some()
+            };
+            let was_standing_by = STANDING_BY
+                .lock()
+                .unwrap()
+                .insert(account, !held)
+                .unwrap_or(false);
+            match (was_held, was_standing_by, held) {
+                (false, _, true) => {
+                    info!(
+                        "Holding the lease on {} on {}, relaying from it",
+                        signer.address(),
+                        ctx.name
+                    );
+                    // transactions passed over while standing by are served again
+                    refetch_pending();
+                }
+                (true, _, false) => warn!(
+                    "Lost the lease on {} on {} to another instance, standing by",
+                    signer.address(),
+                    ctx.name
+                ),
+                (false, false, false) => info!(
+                    "Another instance holds the lease on {} on {}, standing by",
+                    signer.address(),
+                    ctx.name
+                ),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// True if this instance may relay from `address` on `chain_id`, that is if leasing is disabled or the lease on
+/// the account was renewed within the last half of its ttl. The margin covers renewals that were delayed, so
+/// another instance can't have taken over yet. With leader election only the leader may relay.
+pub fn holds_account_lease(chain_id: u64, address: Address) -> bool {
+    let Some(ttl) = POLICY.lock().unwrap().as_ref().map(|policy| policy.ttl) else {
+        return true;
+    };
+    if ELECTION.lock().unwrap().is_some() {
+        return is_leader();
+    }
+    HELD.lock()
+        .unwrap()
+        .get(&(chain_id, address))
+        .is_some_and(|renewed| renewed.elapsed() < ttl / 2)
+}
+
+/// True if this instance may broadcast from `address` on `chain_id`, as the leader if leader election is enabled
+/// and as the holder of the lease on the account
+pub fn may_broadcast(chain_id: u64, address: Address) -> bool {
+    is_leader() && holds_account_lease(chain_id, address)
+}
+
+/// Takes the leases needed to broadcast from the accounts of `chains` once, the leader lease of `address` with
+/// leader election and the account leases otherwise. For one-off broadcasts outside the relay loop, such as manual
+/// relays, which must not send from an account a running instance relays from.
+pub async fn acquire_leases(chains: &[RelayContext], address: Address) {
+    let Some(policy) = POLICY.lock().unwrap().clone() else {
+        return;
+    };
+    let Some(poll_interval) = *ELECTION.lock().unwrap() else {
+        renew_account_leases(chains).await;
+        return;
+    };
+    let renewed = Instant::now();
+    match acquire(&policy, &leader_key(address), leader_ttl(poll_interval)).await {
+        Ok(true) => *LEADER.lock().unwrap() = Some(renewed),
+        Ok(false) => info!("Another instance is the leader, not broadcasting"),
+        Err(e) => warn!(
+            "Failed to take the leader lease through {}: {e}",
+            policy.store.describe()
+        ),
+    }
+}
+
+/// Releases the leases taken by `acquire_leases`
+pub async fn release_leases(address: Address) {
+    release_account_leases().await;
+    let Some(policy) = POLICY.lock().unwrap().clone() else {
+        return;
+    };
+    if LEADER.lock().unwrap().take().is_some()
+        && let Err(e) = release(&policy, &leader_key(address)).await
+    {
+        warn!("Failed to release the leader lease: {e}");
+    }
+}
+
+fn relay_key(id: &str) -> String {
+    format!("{KEY_PREFIX}:relay:{id}")
+}
+
+/// Leases `tx` to this instance for one ttl, true if it may relay it. Leases aren't released after relaying so
+/// that the other instances keep passing over it until it is gone from their queues, see `keep_relay_lease`.
+pub async fn acquire_relay_lease(tx: &GaslessTransaction) -> bool {
+    let Some(policy) = POLICY.lock().unwrap().clone() else {
+        return true;
+    };
+    match acquire(&policy, &relay_key(&tx.id()), policy.ttl).await {
+        Ok(held) => {
+            if held {
+                RELAY_LEASES.lock().unwrap().insert(tx.id());
+            }
+            held
+        }
+        Err(e) => {
+            warn!(
+                tx:% = tx.id();
+                "Failed to lease transaction {} through {}, not relaying it: {e}",
+                tx.id(),
+                policy.store.describe()
+            );
+            false
+        }
+    }
+}
+
+/// Gives the lease on `tx` back if this instance holds it, for a transaction that was skipped or failed to be sent
+/// so that another instance may relay it right away. The store is updated in the background.
+pub fn release_relay_lease(tx: &GaslessTransaction) {
+    let id = tx.id();
+    if !RELAY_LEASES.lock().unwrap().remove(&id) {
+        return;
+    }
+    let Some(policy) = POLICY.lock().unwrap().clone() else {
+        return;
+    };
+    actix_rt::spawn(async move {
+        if let Err(e) = release(&policy, &relay_key(&id)).await {
+            warn!(
+                tx:% = id;
+                "Failed to release the lease on transaction {id} through {}: {e}",
+                policy.store.describe()
+            );
+        }
+    });
+}
+
+/// Stops tracking the lease on `tx` once it was relayed, it is left to expire in the store
+pub fn keep_relay_lease(tx: &GaslessTransaction) {
+    RELAY_LEASES.lock().unwrap().remove(&tx.id());
+}
+
+/// Releases the leases on our relayer accounts, so that a standby instance takes over without waiting for them
+/// to expire
+pub async fn release_account_leases() {
+    let Some(policy) = POLICY.lock().unwrap().clone() else {
+        return;
+    };
+    let accounts: Vec<(u64, Address)> = HELD.lock().unwrap().drain().map(|(a, _)| a).collect();
+    for (chain_id, address) in accounts {
+        if let Err(e) = release(&policy, &account_key(chain_id, address)).await {
+            warn!("Failed to release the lease on {address} on chain {chain_id}: {e}");
+        } else {
+            info!("Released the lease on {address} on chain {chain_id}");
+        }
+    }
+}
+
+/// Takes or extends the lease in `key` for `ttl`, true if this instance holds it
+async fn acquire(policy: &LeasePolicy, key: &str, ttl: Duration) -> Result<bool, String> {
+    match &policy.store {
+        LeaseStore::Redis { .. } => {
+            let ttl_ms = ttl.as_millis().max(1).to_string();
+            let reply = redis_command(
+                policy,
+                &["EVAL", REDIS_ACQUIRE, "1", key, &policy.instance, &ttl_ms],
+            )
+            .await?;
+            Ok(reply == RedisReply::Integer(1))
+        }
+        LeaseStore::Etcd { url } => etcd_acquire(policy, url, key, ttl).await,
+    }
+}
+
+async fn release(policy: &LeasePolicy, key: &str) -> Result<(), String> {
+    match &policy.store {
+        LeaseStore::Redis { .. } => {
+            redis_command(policy, &["EVAL", REDIS_RELEASE, "1", key, &policy.instance])
+                .await
+                .map(|_| ())
+        }
+        LeaseStore::Etcd { url } => {
+            let Some(lease) = ETCD_LEASES.lock().unwrap().remove(key) else {
+                return Ok(());
+            };
+            etcd_request(policy, url, "lease/revoke", json!({ "ID": lease }))
+                .await
+                .map(|_| ())
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum RedisReply {
+    Nil,
+    Status,
+    Integer(i64),
+    Bulk,
+}
+
+/// Runs a single command on an idle connection, or on a new one which authenticates and selects the database first
+/// if configured. The connection is kept for the next command unless the command failed.
+async fn redis_command(policy: &LeasePolicy, args: &[&str]) -> Result<RedisReply, String> {
+    let LeaseStore::Redis {
+        address,
+        password,
+        db,
+    } = &policy.store
+    else {
+        return Err("not a redis store".to_string());
+    };
+    let idle = {
+        let mut connections = REDIS_CONNECTIONS.lock().unwrap();
+        connections
+            .iter()
+            .position(|(at, _)| at == address)
+            .map(|i| connections.swap_remove(i).1)
+    };
+    let exchange = async {
+        let mut conn = match idle {
+            Some(conn) => conn,
+            None => {
+                let mut conn = BufReader::new(TcpStream::connect(address).await?);
+                if let Some(password) = password {
+                    redis_exchange(&mut conn, &["AUTH", password]).await?;
+                }
+                if let Some(db) = db {
+                    redis_exchange(&mut conn, &["SELECT", &db.to_string()]).await?;
+                }
+                conn
+            }
+        };
+        let reply = redis_exchange(&mut conn, args).await?;
+        Ok::<_, io::Error>((conn, reply))
+    };
+    // a connection that failed or timed out mid command may have a reply left unread, so it is dropped
+    match actix_rt::time::timeout(policy.timeout, exchange).await {
+        Ok(Ok((conn, reply))) => {
+            let mut connections = REDIS_CONNECTIONS.lock().unwrap();
+            if connections.len() < MAX_IDLE_REDIS_CONNECTIONS {
+                connections.push((address.clone(), conn));
+            }
+            Ok(reply)
+        }
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err(format!("timed out after {}s", policy.timeout.as_secs())),
+    }
+}
+
+async fn redis_exchange(conn: &mut BufReader<TcpStream>, args: &[&str]) -> io::Result<RedisReply> {
+    let mut request = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        request.extend(format!("${}\r\n", arg.len()).as_bytes());
+        request.extend(arg.as_bytes());
+        request.extend(b"\r\n");
+    }
+    conn.get_mut().write_all(&request).await?;
+    read_reply(conn).await
+}
+
+/// Reads one RESP reply, none of the commands we send reply with an array
+async fn read_reply(conn: &mut BufReader<TcpStream>) -> io::Result<RedisReply> {
+    let line = read_line(conn).await?;
+    let (kind, rest) = line.split_at(1.min(line.len()));
+    match kind {
+        "+" => Ok(RedisReply::Status),
+        "-" => Err(io::Error::other(rest.to_string())),
+        ":" => Ok(RedisReply::Integer(parse_len(rest)?)),
+        "$" => {
+            let len = parse_len(rest)?;
+            if len < 0 {
+                return Ok(RedisReply::Nil);
+            }
+            let mut data = vec![0u8; len as usize + 2];
+            conn.read_exact(&mut data).await?;
+            Ok(RedisReply::Bulk)
+        }
+        _ => Err(io::Error::other(format!(
+            "unexpected reply {line} from redis"
+        ))),
+    }
+}
+
+async fn read_line(conn: &mut BufReader<TcpStream>) -> io::Result<String> {
+    let mut line = String::new();
+    if conn.read_line(&mut line).await? == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(line.trim_end_matches("\r\n").to_string())
+}
+
+fn parse_len(text: &str) -> io::Result<i64> {
+    text.parse()
+        .map_err(|_| io::Error::other(format!("invalid length {text} from redis")))
+}
+
+/// Holds `key` in etcd. A key we hold is attached to a lease of its own, which is kept alive for as long as we
+/// keep acquiring the key. Otherwise a new lease is granted and the key created with it, unless it already exists.
+async fn etcd_acquire(
+    policy: &LeasePolicy,
+    url: &str,
+    key: &str,
+    ttl: Duration,
+) -> Result<bool, String> {
+    let existing = ETCD_LEASES.lock().unwrap().get(key).cloned();
+    if let Some(lease) = existing {
+        let response = etcd_request(policy, url, "lease/keepalive", json!({ "ID": lease })).await?;
+        // an expired lease is reported with no ttl left, its keys are already gone
+        let ttl = response["result"]["TTL"]
+            .as_str()
+            .and_then(|ttl| ttl.parse::<i64>().ok())
+            .or_else(|| response["result"]["TTL"].as_i64())
+            .unwrap_or(0);
+        if ttl > 0 {
+            return Ok(true);
+        }
+        ETCD_LEASES.lock().unwrap().remove(key);
+    }
+
+    let granted = etcd_request(
+        policy,
+        url,
+        "lease/grant",
+        json!({ "TTL": ttl.as_secs().max(1) }),
+    )
+    .await?;
+    let Some(lease) = granted["ID"]
+        .as_str()
+        .map(str::to_string)
+        .or_else(|| granted["ID"].as_i64().map(|id| id.to_string()))
+    else {
+        return Err(format!("no lease id in {granted}"));
+    };
+    let encoded_key = BASE64.encode(key);
+    let created = etcd_request(
+        policy,
+        url,
+        "kv/txn",
+        json!({
+            "compare": [{
+                "key": encoded_key,
+                "result": "EQUAL",
+                "target": "CREATE",
+                "create_revision": "0",
+            }],
+            "success": [{
+                "request_put": {
+                    "key": encoded_key,
+                    "value": BASE64.encode(&policy.instance),
+                    "lease": lease,
+                }
+            }],
+        }),
+    )
+    .await?;
+    if created["succeeded"].as_bool() == Some(true) {
+        ETCD_LEASES.lock().unwrap().insert(key.to_string(), lease);
+        Ok(true)
+    } else {
+        // the lease we were granted isn't needed, it is left to expire
+        Ok(false)
+    }
+}
+
+async fn etcd_request(
+    policy: &LeasePolicy,
+    url: &str,
+    path: &str,
+    body: Value,
+) -> Result<Value, String> {
+    let mut response = http_client(None, policy.timeout)
+        .post(format!("{url}/v3/{path}"))
+        .send_json(&body)
+        .await
+        .map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        let body = response.body().await.unwrap_or_default();
+        return Err(format!(
+            "{path} responded with {}: {}",
+            response.status(),
+            String::from_utf8_lossy(&body)
+        ));
+    }
+    response.json().await.map_err(|e| e.to_string())
+}
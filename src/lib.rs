@@ -0,0 +1,84 @@
+//! Althea L1 gasless transaction relayer, fetches pending gasless transactions from orchestrators, evaluates
+//! their profitability and relays them to the iFi dex. The binary in `main.rs` wires these modules together.
+
+pub mod accounting;
+pub mod admin;
+pub mod alert;
+pub mod audit;
+pub mod backoff;
+pub mod banlist;
+pub mod bech32;
+pub mod bench;
+pub mod blob;
+pub mod breaker;
+pub mod bundler;
+pub mod chain;
+#[cfg(feature = "testing")]
+pub mod chaos;
+pub mod codewatch;
+pub mod command;
+pub mod confirm;
+pub mod conflict;
+pub mod congestion;
+pub mod dashboard;
+pub mod decoder;
+pub mod dns;
+pub mod error;
+pub mod funds;
+pub mod gas;
+pub mod gasmodel;
+pub mod grpc;
+pub mod history;
+pub mod inspect;
+pub mod key;
+pub mod killswitch;
+pub mod layout;
+pub mod lease;
+pub mod logging;
+pub mod maintenance;
+pub mod manual;
+pub mod margin;
+pub mod mempool;
+pub mod mock;
+pub mod multicall;
+pub mod observer;
+pub mod orchestrator;
+pub mod overflow;
+pub mod payout;
+pub mod preflight;
+pub mod price;
+pub mod profit;
+pub mod protocol;
+pub mod proxy;
+pub mod quality;
+pub mod quote;
+pub mod ratelimit;
+pub mod receipts;
+pub mod registration;
+pub mod relay;
+pub mod reload;
+pub mod report;
+pub mod retry;
+pub mod revert;
+pub mod rpc;
+pub mod scan;
+pub mod service;
+pub mod shutdown;
+pub mod signer;
+pub mod skip;
+pub mod slo;
+pub mod source;
+pub mod split;
+pub mod status;
+pub mod submit;
+pub mod subsidy;
+pub mod summary;
+pub mod sweep;
+pub mod systemd;
+pub mod telemetry;
+pub mod tendermint;
+pub mod terms;
+pub mod throttle;
+pub mod tokens;
+pub mod units;
+pub mod urgency;
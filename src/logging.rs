@@ -0,0 +1,131 @@
+//! Log output setup. Besides the default human readable format logs can be written as one JSON object per
+//! line so they can be ingested into Loki or Elasticsearch, the structured fields attached to relay decisions
+//! (transaction id, tip, gas estimate, decision, tx hash) become top level fields of the object. Error level
+//! records are also handed to error reporting, which sends them on if it is enabled. Logs can be appended to a
+//! file instead of stderr for hosts where nothing captures the relayer's output, the file is reopened on SIGHUP
+//! so it can be rotated.
+
+use crate::report::report_log;
+use clap::ValueEnum;
+use log::kv::{Key, Value, VisitSource};
+use log::{Log, Metadata, Record};
+use serde_json::{Map, Value as JsonValue};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+
+/// File logs are appended to, along with its path so it can be reopened
+static LOG_FILE: LazyLock<Mutex<Option<(PathBuf, File)>>> = LazyLock::new(|| Mutex::new(None));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+/// Appends logs to `path` instead of writing them to stderr, must be called before `init_logging`
+pub fn set_log_file(path: &Path) -> std::io::Result<()> {
+    let file = open_log_file(path)?;
+    *LOG_FILE.lock().unwrap() = Some((path.to_path_buf(), file));
+    Ok(())
+}
+
+/// Reopens the log file, if logs go to one, so that logs go to a new file once the old one was rotated away
+pub fn reopen_log_file() {
+    let mut log_file = LOG_FILE.lock().unwrap();
+    let Some((path, file)) = log_file.as_mut() else {
+        return;
+    };
+    match open_log_file(path) {
+        Ok(reopened) => *file = reopened,
+        // the old file is kept, logging to a rotated file beats not logging
+        Err(e) => {
+            let _ = writeln!(file, "Failed to reopen log file {}: {e}", path.display());
+        }
+    }
+}
+
+fn open_log_file(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Writes to the log file, or to stderr if there is none
+struct LogFileWriter;
+
+impl Write for LogFileWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match LOG_FILE.lock().unwrap().as_mut() {
+            Some((_, file)) => file.write(buf),
+            None => std::io::stderr().write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match LOG_FILE.lock().unwrap().as_mut() {
+            Some((_, file)) => file.flush(),
+            None => std::io::stderr().flush(),
+        }
+    }
+}
+
+pub fn init_logging(level: &str, format: LogFormat) {
+    let mut builder =
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(level));
+    if LOG_FILE.lock().unwrap().is_some() {
+        builder
+            .target(env_logger::Target::Pipe(Box::new(LogFileWriter)))
+            .write_style(env_logger::WriteStyle::Never);
+    }
+    if format == LogFormat::Json {
+        builder.format(|buf, record| {
+            let mut fields = Map::new();
+            fields.insert("timestamp".into(), buf.timestamp().to_string().into());
+            fields.insert("level".into(), record.level().as_str().into());
+            fields.insert("target".into(), record.target().into());
+            fields.insert("message".into(), record.args().to_string().into());
+            let _ = record.key_values().visit(&mut JsonFields(&mut fields));
+            writeln!(buf, "{}", JsonValue::Object(fields))
+        });
+    }
+    let logger = builder.build();
+    let max_level = logger.filter();
+    log::set_boxed_logger(Box::new(ReportingLogger(logger))).expect("Logger already initialized");
+    log::set_max_level(max_level);
+}
+
+/// Writes records through env_logger and reports the error level ones
+struct ReportingLogger(env_logger::Logger);
+
+impl Log for ReportingLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.0.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.0.matches(record) {
+            report_log(record);
+        }
+        self.0.log(record);
+    }
+
+    fn flush(&self) {
+        self.0.flush();
+    }
+}
+
+struct JsonFields<'a>(&'a mut Map<String, JsonValue>);
+
+impl<'kvs> VisitSource<'kvs> for JsonFields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+        let value = if let Some(v) = value.to_u64() {
+            v.into()
+        } else if let Some(v) = value.to_bool() {
+            v.into()
+        } else {
+            value.to_string().into()
+        };
+        self.0.insert(key.to_string(), value);
+        Ok(())
+    }
+}
@@ -1,50 +1,306 @@
-use actix_web::dev::RequestHead;
-use awc::{Client as HttpClient, http::Method};
-use clap::Parser;
-use clarity::abi::{parse_address, parse_u128};
-use clarity::{
-    Address, PrivateKey, Transaction, Uint256, abi::encode_call, utils::display_uint256_as_address,
+use actix_rt::task::JoinHandle;
+use althea_l1_relayer::accounting::{SpendCaps, set_spend_caps};
+use althea_l1_relayer::admin::{is_paused, relay_stats, start_admin_server};
+use althea_l1_relayer::alert::{
+    AlertPolicy, AlertSink, alert_on_balance, alert_on_poll, flush_alerts, set_alert_policy,
 };
-use log::{debug, error, info, trace};
+use althea_l1_relayer::audit::{open_audit_log, seal_audit_log, verify_audit_log};
+use althea_l1_relayer::backoff::{BackoffPolicy, set_backoff_policy};
+use althea_l1_relayer::banlist::{open_ban_list, set_max_strikes};
+use althea_l1_relayer::bech32::{parse_user_address, set_show_bech32};
+use althea_l1_relayer::bench::{run_bench, start_recording};
+use althea_l1_relayer::breaker::{
+    BreakerPolicy, open_circuits, record_poll_result, set_breaker_policy, should_poll,
+};
+use althea_l1_relayer::bundler::process_user_operations;
+use althea_l1_relayer::chain::{ChainConfig, LimitOverrideConfig, load_config};
+use althea_l1_relayer::codewatch::{
+    CodeWatch, check_contract_code, parse_expected_code_hash, set_code_watch,
+};
+use althea_l1_relayer::command::{CommandFilter, CommandRule};
+use althea_l1_relayer::confirm::{load_relay_store, restore_relay, set_relay_store};
+use althea_l1_relayer::congestion::{CongestionPolicy, check_congestion, set_congestion_policy};
+use althea_l1_relayer::decoder::decode_transaction;
+use althea_l1_relayer::dns::{
+    IpPreference, set_attempt_delay, set_dns_cache_ttl, set_ip_preference, set_pinned_ips,
+};
+use althea_l1_relayer::error::RelayerError;
+use althea_l1_relayer::funds::set_funds_check;
+use althea_l1_relayer::gas::{
+    FeeBandPolicy, GasOraclePolicy, GasProfile, set_access_lists, set_estimate_cache_ttl,
+    set_fee_band, set_gas_oracle,
+};
+use althea_l1_relayer::gasmodel::{GasModelPolicy, set_gas_model};
+use althea_l1_relayer::history::{
+    ExportFormat, ExportReport, HistoryFilter, earned_tokens, format_timestamp, open_database,
+    open_history, parse_date, query_history,
+};
+use althea_l1_relayer::key::{
+    SecretString, load_keystore, load_private_key_env, load_private_key_file,
+};
+use althea_l1_relayer::killswitch::{KillSwitch, check_kill_switch, set_kill_switch};
+use althea_l1_relayer::layout::{ContractLayout, RelayTarget};
+use althea_l1_relayer::lease::{
+    LeasePolicy, LeaseStore, acquire_leases, default_instance_id, leader_election_loop,
+    release_account_leases, release_leases, renew_account_leases, set_leader_election,
+    set_lease_policy,
+};
+use althea_l1_relayer::logging::{LogFormat, init_logging, set_log_file};
+use althea_l1_relayer::maintenance::{
+    MaintenanceWindow, check_maintenance_windows, set_maintenance_windows,
+};
+use althea_l1_relayer::manual::{GasOverrides, relay_one};
+use althea_l1_relayer::margin::{MarginPolicy, required_margin, set_margin_policy};
+use althea_l1_relayer::mempool::{MempoolSource, set_mempool_source};
+use althea_l1_relayer::mock::{parse_mock_transactions, start_mock_orchestrator};
+use althea_l1_relayer::observer::{log_observer_summary, set_observing};
+use althea_l1_relayer::orchestrator::{
+    GaslessTransaction, Orchestrator, OrchestratorTls, PendingQuery, long_poll_loop,
+    set_conditional_polling, set_incremental_fetch, subscribe_loop,
+};
+use althea_l1_relayer::overflow::{set_max_txs_per_cycle, start_cycle};
+use althea_l1_relayer::preflight::{
+    ChainChecks, PreflightCheck, Verdict, check_chain, check_orchestrators,
+};
+use althea_l1_relayer::price::{
+    CoinGeckoPriceSource, HttpPriceSource, PriceHeaders, PriceSource, PriceSourceKind,
+    StaticPriceFallback, SwapQuoter, parse_static_price, token_decimals,
+};
+use althea_l1_relayer::profit::MarginProfitPolicy;
+use althea_l1_relayer::protocol::{
+    ProtocolCallpath, ProtocolPolicy, process_protocol_commands, set_protocol_policy,
+};
+use althea_l1_relayer::proxy::{
+    HttpPool, ProxyConfig, TlsTrust, rpc_url, set_http_pool, set_proxy, set_tls_trust,
+};
+use althea_l1_relayer::quality::{
+    due_by_quality, orchestrator_quality, record_served, set_max_demotion,
+};
+use althea_l1_relayer::quote::{serve_quotes, start_quote_server};
+use althea_l1_relayer::ratelimit::{
+    RateLimit, SimulationBudget, set_rate_limit, set_simulation_budget,
+};
+use althea_l1_relayer::registration::{
+    RegistrationPolicy, finish_registrations, registration_loop, registration_policy,
+    set_registration_policy,
+};
+use althea_l1_relayer::relay::{
+    EvaluationOrder, LimitOverride, RelayContext, RelayLimits, evaluate_transaction,
+    process_deferred, process_retries, process_transactions, relay_limits, resync_nonces,
+    set_evaluation_order, set_relay_limits, track_relays, wait_for_new_block,
+};
+use althea_l1_relayer::reload::{listen_for_reload_signal, take_reload_request};
+use althea_l1_relayer::report::{
+    ReportPolicy, ReportSink, flush_error_reports, init_error_reporting,
+};
+use althea_l1_relayer::retry::{RetryPolicy, set_retry_policy};
+use althea_l1_relayer::rpc::RpcPool;
+use althea_l1_relayer::scan::scan_relays;
+use althea_l1_relayer::service::{
+    PidFile, default_log_file, report_service_stopped, start_service,
+};
+use althea_l1_relayer::shutdown::{
+    is_shutting_down, listen_for_signals, sleep_or_shutdown, wait_for_shutdown,
+};
+use althea_l1_relayer::signer::{RemoteSigner, Signer, SignerPool};
+use althea_l1_relayer::skip::SkipReason;
+use althea_l1_relayer::slo::{LatencySlo, set_latency_slo};
+use althea_l1_relayer::source::{FileSource, StdinSource, TransactionSource};
+use althea_l1_relayer::split::{
+    SplitPolicy, parse_tip_split, pay_tip_splits_if_due, set_split_policy,
+};
+use althea_l1_relayer::status::{
+    QueuedTransaction, TokenBalance, add_in_flight, get_status, init_chain_status, init_status,
+    record_chain_status, record_loop, record_open_circuits, record_orchestrator_quality,
+    record_poll_success, record_profit_margin, record_queue, record_rpc_health,
+    record_token_balances, start_status_server, token_balances_due, track_token, tracked_tokens,
+};
+use althea_l1_relayer::submit::{SubmitKey, SubmitLimit, start_submit_server};
+use althea_l1_relayer::subsidy::{SubsidyBudget, set_subsidy_budget};
+use althea_l1_relayer::summary::{SummaryPolicy, report_summaries, set_summary_policy};
+use althea_l1_relayer::sweep::{SweepPolicy, parse_sweep_token, set_sweep_policy, sweep_if_due};
+use althea_l1_relayer::systemd::{notify_ready, notify_watchdog};
+use althea_l1_relayer::telemetry::init_tracing;
+use althea_l1_relayer::tendermint::tendermint_subscribe_loop;
+use althea_l1_relayer::terms::{
+    TERMS, TERMS_VERSION, confirm_interactively, record_acceptance, recorded_acceptance,
+};
+use althea_l1_relayer::throttle::{Ceiling, Outbound, OutboundLimits, set_outbound_limits};
+use althea_l1_relayer::tokens::resolve_token;
+use althea_l1_relayer::urgency::{UrgencyPolicy, set_urgency_policy};
+use clap::{ArgGroup, Args, Parser};
+use clarity::utils::display_uint256_as_address;
+use clarity::{Address, Uint256};
+use futures::StreamExt;
+use futures::channel::mpsc::{UnboundedReceiver, UnboundedSender, unbounded};
+use futures::future::{join, join_all};
+use futures::stream::FuturesUnordered;
+use log::{debug, error, info, warn};
 use num_traits::ToPrimitive;
 use rustls::crypto::CryptoProvider;
-use serde::{Deserialize, Serialize};
-use std::{net::ToSocketAddrs, str::FromStr, thread::sleep, time::Duration};
-use web30::{
-    client::Web3,
-    jsonrpc::error::Web3Error,
-    types::{Data, SendTxOption, TransactionRequest},
-};
-
-static OX_100_ADDRESS: &str = "0x0000000000000000000000000000000000000100";
-static OX_200_ADDRESS: &str = "0x0000000000000000000000000000000000000200";
-pub const RELAYING_SERVICE_ROOT: &str = "orchestrator";
-
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct GaslessTransaction {
-    pub chain_id: u64,
-    pub callpath: u16,
-    pub cmd: Vec<u8>,
-    pub conds: Vec<u8>,
-    pub tip: Vec<u8>,
-    pub sig: Vec<u8>,
-    pub submitted_at: u64,
-}
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Instant;
+use std::{str::FromStr, time::Duration};
+use web30::client::Web3;
 
 #[derive(Debug, Parser)]
 #[command(name = "ifi-relayer", about = "iFi Dex transaction relayer")]
+enum Cli {
+    /// Relay pending transactions until shutdown, the default when no subcommand is given
+    Run(RelayerOpts),
+    /// Validate the configuration, RPC reachability and relayer balances without relaying anything
+    Check(RelayerOpts),
+    /// Pretty print a gasless transaction given as JSON or as hex blobs
+    Decode(DecodeOpts),
+    /// Evaluate one transaction end to end, exactly as the relay loop would, without sending it
+    Simulate(SimulateOpts),
+    /// Relay one transaction right away with the given gas parameters, bypassing orchestrators and the
+    /// profitability checks, to rescue a user's stuck transaction
+    RelayOne(RelayOneOpts),
+    /// Query the relay history recorded with --history-db
+    History(HistoryOpts),
+    /// Export the relay history or per token totals as CSV or JSON
+    Export(ExportOpts),
+    /// Scan past blocks for relays to the dex, with tips captured and gas spent by each relayer
+    Scan(ScanOpts),
+    /// Serve a queue of gasless transactions over the orchestrator protocol, for testing the relayer locally
+    MockOrchestrator(MockOrchestratorOpts),
+    /// Replay a captured queue through the relay pipeline, without sending anything, and report its performance
+    Bench(BenchOpts),
+    /// Check the hash chain and seal signatures of an audit log written with --audit-log
+    VerifyAudit(VerifyAuditOpts),
+}
+
+#[derive(Debug, Clone, Args)]
+#[command(group(
+    ArgGroup::new("key")
+        .required(true)
+        .args([
+            "private_key",
+            "private_key_env",
+            "private_key_file",
+            "keystore",
+            "remote_signer_url",
+            "observe"
+        ])
+))]
 pub struct RelayerOpts {
-    #[arg(long, value_name = "PRIVATE_KEY")]
-    pub private_key: String,
+    #[arg(
+        long,
+        value_name = "PRIVATE_KEY",
+        help = "Relayer private key, may be provided multiple times to spread relays across a pool of funded accounts"
+    )]
+    pub private_key: Vec<SecretString>,
+
+    #[arg(
+        long,
+        value_name = "VAR",
+        help = "Environment variable to read the relayer private key from"
+    )]
+    pub private_key_env: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "File to read the relayer private key from, such as a Kubernetes secret or systemd credential, may be provided multiple times"
+    )]
+    pub private_key_file: Vec<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "KEYSTORE",
+        help = "Encrypted web3 v3 JSON keystore to load the relayer key from, the passphrase is prompted for unless --keystore-password-fd is set"
+    )]
+    pub keystore: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FD",
+        requires = "keystore",
+        help = "File descriptor to read the keystore passphrase from, for example 3 with 3<passphrase.txt"
+    )]
+    pub keystore_password_fd: Option<i32>,
+
+    #[arg(
+        long,
+        value_name = "URL",
+        requires = "remote_signer_address",
+        help = "Delegate signing to a remote signer implementing eth_signTransaction and eth_sign, such as web3signer, instead of holding the key in process"
+    )]
+    pub remote_signer_url: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "ADDRESS",
+        requires = "remote_signer_url",
+        help = "Address of the key held by the remote signer"
+    )]
+    pub remote_signer_address: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "ADDRESS",
+        help = "Run watch-only without a key, simulating relays from this funded address and recording what they would have earned and whether a competitor relayed them first, implies --dry-run"
+    )]
+    pub observe: Option<String>,
 
     #[arg(
         long,
-        default_value = "https://althea.link:8443",
         value_name = "TRANSACTION_SERVER_URL",
-        help = "URLs of the service to fetch pending transactions"
+        help = "URLs of the service to fetch pending transactions, defaults to https://althea.link:8443 unless \
+                transactions are read from a file or stdin instead"
     )]
     pub transaction_api_url: Vec<String>,
 
+    #[arg(
+        long,
+        alias = "tx-file",
+        value_name = "PATH",
+        help = "JSON file holding a pending transaction or an array of them, read again whenever it changes"
+    )]
+    pub transaction_file: Vec<PathBuf>,
+
+    #[arg(
+        long,
+        alias = "tx-stdin",
+        help = "Read pending transactions from stdin as JSON, one per line"
+    )]
+    pub transaction_stdin: bool,
+
+    #[arg(
+        long,
+        value_name = "SUBMIT_BIND",
+        help = "Address to serve POST /submit on, where trusted frontends holding a --submit-key can hand transactions to this relayer directly"
+    )]
+    pub submit_bind: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "NAME=KEY",
+        help = "Key a frontend presents in the X-Api-Key header to submit transactions on --submit-bind, its submissions are a source named submit:<NAME>. May be provided multiple times"
+    )]
+    pub submit_key: Vec<String>,
+
+    #[arg(
+        long,
+        default_value = "600",
+        value_name = "SUBMISSIONS",
+        help = "Accept at most this many transactions from one --submit-key per --submit-rate-window"
+    )]
+    pub max_submissions_per_source: u32,
+
+    #[arg(
+        long,
+        default_value = "60",
+        value_name = "SECONDS",
+        help = "Window in seconds over which --max-submissions-per-source is enforced"
+    )]
+    pub submit_rate_window: u64,
+
     #[arg(
         long,
         default_value = "https://althea.link:8443",
@@ -55,393 +311,3845 @@ pub struct RelayerOpts {
 
     #[arg(
         long,
-        default_value = "https://rpc.althea.zone:8545",
-        value_name = "ALTHEA_EVM_RPC"
+        value_name = "[URL=]NAME: VALUE",
+        help = "Header to send with price requests, as <NAME>: <VALUE> for every price url or <URL>=<NAME>: <VALUE> for urls under <URL>, may be provided multiple times"
     )]
-    pub alhtea_evm_rpc: String,
+    pub price_api_header: Vec<String>,
 
-    #[arg(long, default_value = "5", value_name = "POLL_INTERVAL")]
-    pub poll_interval: u64,
+    #[arg(
+        long,
+        value_name = "[URL=]TOKEN",
+        help = "Bearer token to authenticate price requests with, as <TOKEN> for every price url or <URL>=<TOKEN> for urls under <URL>, may be provided multiple times"
+    )]
+    pub price_api_bearer_token: Vec<String>,
 
-    #[arg(long, default_value = "12", value_name = "CONFIRMATION_BLOCKS")]
-    pub confirmation_blocks: u64,
+    #[arg(
+        long,
+        value_enum,
+        default_value = "http",
+        help = "Where tip token prices come from, the price API or CoinGecko, a chain's price_source overrides this"
+    )]
+    pub price_source: PriceSourceKind,
 
     #[arg(
         long,
-        // address of the iFi dex on Althea L1, use explorer.althea.link to verify
-        default_value = "0xd263DC98dEc57828e26F69bA8687281BA5D052E0",
-        value_name = "CONTRACT_ADDRESS"
+        default_value = "https://api.coingecko.com/api/v3",
+        value_name = "URL",
+        help = "CoinGecko API root used with --price-source coingecko"
     )]
-    pub contract_address: String,
+    pub coingecko_url: String,
 
     #[arg(
         long,
-        default_value = "info",
-        value_name = "LOG_LEVEL",
-        help = "Set the logging level (e.g., info, debug, error)"
+        value_name = "KEY",
+        help = "CoinGecko API key, sent as a pro key to pro-api.coingecko.com and as a demo key otherwise"
     )]
-    pub log_level: String,
+    pub coingecko_api_key: Option<String>,
 
     #[arg(
         long,
-        default_value = "10",
-        value_name = "TIMEOUT",
-        help = "Timeout for all operations in seconds"
+        value_name = "PLATFORM",
+        help = "CoinGecko asset platform id of the chain tips are paid on, a chain's coingecko_platform overrides this"
     )]
-    pub timeout: u64,
+    pub coingecko_platform: Option<String>,
 
     #[arg(
         long,
-        default_value = "false",
-        value_name = "AGREE",
-        help = "Agree to the terms and conditions"
+        default_value = "althea",
+        value_name = "COIN_ID",
+        help = "CoinGecko coin id of the gas token"
     )]
-    pub agree: bool,
-}
+    pub coingecko_gas_token_id: String,
 
-const TERMS: &str = "This software is provided AS IS as a reference gassless transaction relayer. This software may contain bugs, lose funds, or even spend all the ALTHEA it has access to.\
-do not put more tokens in the wallet than you can afford to lose. Monitor this application closely at all times. Default RPC endpoints are not guaranteed to stay online, or to be accurate. \
-You have a license under Apache-2.0 to modify and improve this software with attribution. No support or updates are guaranteed. This software is used entirely at your own risk. Pass --agree to agree to these terms.";
+    #[arg(
+        long,
+        default_value = "60",
+        value_name = "SECONDS",
+        help = "How long CoinGecko prices are reused before being fetched again"
+    )]
+    pub coingecko_cache_secs: u64,
 
-#[actix_rt::main]
-async fn main() {
-    CryptoProvider::install_default(rustls::crypto::aws_lc_rs::default_provider()).unwrap();
-    unsafe {
-        openssl_probe::init_openssl_env_vars();
-    }
+    #[arg(
+        long,
+        value_name = "TOKEN=PRICE",
+        help = "Fixed price of one whole token in ALTHEA used when live prices fail, such as 0x...=12.5, can be repeated"
+    )]
+    pub static_price: Vec<String>,
 
-    let opts = RelayerOpts::parse();
-    if !opts.agree {
-        println!("{TERMS}");
-        return;
-    }
-    // Initialize with specific logging level
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(opts.log_level))
-        .init();
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        help = "Warn that static prices may be stale once live prices have been failing for this long"
+    )]
+    pub static_price_warn_after: Option<u64>,
 
-    // let transport = web3::transports::Http::new(&opts.eth_rpc).expect("Failed to create HTTP transport");
-    let web3 = Web3::new(&opts.alhtea_evm_rpc, Duration::from_secs(30));
-    let private_key = PrivateKey::from_str(&opts.private_key).expect("Invalid private key");
+    #[arg(
+        long,
+        value_name = "WRAPPED_NATIVE_TOKEN",
+        help = "Address of wrapped ALTHEA, tips paid in it or in the native token are valued without the price API"
+    )]
+    pub wrapped_native_token: Option<String>,
 
-    let contract_address =
-        Address::from_str(&opts.contract_address).expect("Invalid contract address");
+    #[arg(
+        long,
+        value_name = "ADDRESS",
+        help = "Also relay transactions whose tip pays this address, such as the treasury of a relayer pool, may be provided multiple times"
+    )]
+    pub tip_receiver: Vec<String>,
 
-    info!("Starting Ambient transaction relayer");
-    info!("Orchestrator URLs: {:?}", opts.transaction_api_url);
-    info!("Ethereum RPC: {}", opts.alhtea_evm_rpc);
-    info!("Contract Address: {}", opts.contract_address);
-    info!("Poll interval: {} seconds", opts.poll_interval);
-    info!("Relayer address: {}", private_key.to_address());
-    info!(
-        "Relayer balance: {} ALTHEA",
-        web3.eth_get_balance(private_key.to_address())
-            .await
-            .unwrap()
-            .to_u128()
-            .unwrap() as f64
-            / 1e18
-    );
-    info!("Waiting for transactions to relay...");
+    #[arg(
+        long,
+        value_name = "SWAP_QUOTE_ADDRESS",
+        help = "Address of the Ambient CrocImpact contract, when set tips are valued by quoting the swap of the whole tip into ALTHEA instead of at the price API's spot price"
+    )]
+    pub swap_quote_address: Option<String>,
 
-    loop {
-        // An orchestrator is a service that users submit their pending transactions to to be picked up
-        // by relayers. This loop will iterate over all orchestrator URLs provided in the options
-        for orchestrator_url in &opts.transaction_api_url {
-            if let Err(e) = process_pending_transactions(
-                &web3,
-                orchestrator_url,
-                &private_key,
-                contract_address,
-                &opts.price_api_url,
-            )
-            .await
-            {
-                error!("Error processing pending transactions from {orchestrator_url}: {e}");
-            }
-        }
+    #[arg(
+        long,
+        default_value = "36000",
+        value_name = "POOL_IDX",
+        help = "Pool type index of the pools tips are quoted against with --swap-quote-address"
+    )]
+    pub swap_quote_pool_idx: u64,
 
-        sleep(Duration::from_secs(opts.poll_interval));
-    }
-}
+    #[arg(
+        long,
+        alias = "eth-rpc",
+        default_value = "https://rpc.althea.zone:8545",
+        value_name = "ALTHEA_EVM_RPC",
+        help = "RPC endpoint, may be provided multiple times in which case the next endpoint is used when the current one fails or stops advancing"
+    )]
+    pub alhtea_evm_rpc: Vec<String>,
 
-#[derive(Clone, Serialize, Deserialize, Debug)]
-pub struct PriceQuery {
-    pub from: Address,
-}
-/// Fetches the current price of a given token from a price server, this is where you would add in other price feeds if you wanted to
-/// this curently uses a simple custom api, but you could use anything you like, or even merge multiple price feeds together. Returns the price
-/// of one unit of the request token in units of the gas token (ALTHEA).
-async fn fetch_value_in_gas_token(
-    price_api_url: &str,
-    from: Address,
-    amount: Uint256,
-) -> Result<Uint256, Box<dyn std::error::Error>> {
-    let url = format!("{price_api_url}/value_in_gas_token/{from}");
-    debug!("Fetching price from {url}");
+    #[arg(
+        long,
+        value_name = "CHAIN_ID",
+        help = "Chain id the RPC endpoints must be on, refuses to start otherwise. By default it is taken from the first endpoint that responds"
+    )]
+    pub expected_chain_id: Option<u64>,
 
-    let client = HttpClient::default();
-    let mut response = client.request(Method::GET, url).send().await?;
+    #[arg(
+        long,
+        default_value = "false",
+        help = "Start relaying without checking the RPC endpoints, contracts, price API, balances and orchestrators first"
+    )]
+    pub skip_preflight: bool,
 
-    if !response.status().is_success() {
-        let body = response.body().await?;
-        error!("Failed to fetch price: {}", response.status());
-        let error_text = String::from_utf8_lossy(&body);
-        error!("Failed to fetch price: {error_text}");
-        return Err(error_text.into());
-    }
-    let amount: f64 = amount.to_f64().ok_or("Failed to convert amount to f64")?;
+    #[arg(
+        long,
+        value_name = "ADDRESS",
+        help = "Token the price API is asked to value when checking it, the wrapped native token by default"
+    )]
+    pub check_price_token: Option<String>,
 
-    let price: f64 = response.json().await?;
-    info!("Fetched price: {price} and tip amount is {amount}");
-    Ok(Uint256::from((amount * price) as u128))
-}
+    #[arg(
+        long,
+        default_value = "5",
+        value_name = "POLL_INTERVAL",
+        help = "Longest time in seconds to wait between relay cycles, a cycle starts as soon as a new block is seen"
+    )]
+    pub poll_interval: u64,
 
-/// This loop fetches pending transactions from the orchestrator service, iterating over A records if the service has multiple IPs.
-/// it then checks if each transaction is valid and profitable to relay before submitting it to the network.
-async fn process_pending_transactions(
-    web3: &Web3,
-    orchestrator_url: &str,
-    private_key: &PrivateKey,
-    contract_address: Address,
-    price_api_url: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
-    info!("Fetching pending transactions from {orchestrator_url}/{RELAYING_SERVICE_ROOT}/pending");
-    let url_without_protocol = orchestrator_url
-        .strip_prefix("http://")
-        .or_else(|| orchestrator_url.strip_prefix("https://"))
-        .unwrap_or(orchestrator_url);
-    // iterate over all the A records for the orchestrator url
-    let socket_addrs = url_without_protocol
-        .to_socket_addrs()
-        .map_err(|e| format!("Failed to resolve orchestrator URL: {e}"))?;
-    for ip in socket_addrs {
-        debug!("Orchestrator IP: {ip:?}");
-        let mut request_head = RequestHead::default();
-        request_head.peer_addr = Some(ip);
-        request_head.method = Method::GET;
-
-        let client = HttpClient::default();
-        let mut response = client
-            .request_from(
-                format!("{orchestrator_url}/{RELAYING_SERVICE_ROOT}/pending"),
-                &request_head,
-            )
-            .send()
-            .await?;
+    #[arg(
+        long,
+        default_value = "500",
+        value_name = "HEAD_POLL_INTERVAL_MS",
+        help = "Milliseconds between checks for a new block, which trigger the next relay cycle"
+    )]
+    pub head_poll_interval_ms: u64,
 
-        if !response.status().is_success() {
-            let body = response.body().await?;
-            let error_text = String::from_utf8_lossy(&body);
-            error!("Failed to fetch pending transactions: {error_text}");
-            return Err(error_text.into());
-        }
+    #[arg(
+        long,
+        value_name = "URL",
+        help = "CometBFT RPC of an Althea L1 node, such as http://localhost:26657, to subscribe to new blocks on. Each new block starts the next relay cycle straight away, block polling is only used while the subscription is down"
+    )]
+    pub tendermint_rpc: Option<String>,
 
-        let txs: Vec<GaslessTransaction> = response.json().await?;
-        debug!("Found {} pending transactions", txs.len());
+    #[arg(
+        long,
+        value_enum,
+        value_name = "SOURCE",
+        help = "Watch the mempool for someone else relaying the same command, through a pending transaction filter or the node's txpool API. Our relay is then skipped, or cancelled if already sent, instead of reverting behind theirs"
+    )]
+    pub watch_mempool: Option<MempoolSource>,
 
-        for (idx, tx) in txs.iter().enumerate() {
-            debug!("Processing transaction {}/{}", idx + 1, txs.len());
-            debug!(
-                "Transaction details - Chain ID: {}, Callpath: {}",
-                tx.chain_id, tx.callpath
-            );
+    #[arg(
+        long,
+        default_value = "500",
+        value_name = "POLL_JITTER_MS",
+        help = "Each orchestrator is polled after a random delay of up to this many milliseconds into each cycle, so relayers started together don't poll in lockstep"
+    )]
+    pub poll_jitter_ms: u64,
 
-            match relay_transaction(web3, tx, private_key, contract_address, price_api_url).await {
-                Ok(Some(tx_hash)) => {
-                    info!("Transaction submitted successfully: {tx_hash}");
-                }
-                Ok(None) => {}
-                Err(e) => {
-                    debug!("Relay attempt failed with error: {}", &e);
-                }
-            }
-        }
-    }
+    #[arg(
+        long,
+        default_value = "12",
+        value_name = "CONFIRMATION_BLOCKS",
+        help = "Blocks a relay must be buried under before it is final, relays reorged out before then are relayed again"
+    )]
+    pub confirmation_blocks: u64,
 
-    Ok(())
-}
+    #[arg(
+        long,
+        // address of the iFi dex on Althea L1, use explorer.althea.link to verify
+        default_value = "0xd263DC98dEc57828e26F69bA8687281BA5D052E0",
+        value_name = "CONTRACT_ADDRESS"
+    )]
+    pub contract_address: String,
 
-/// Estimates if a transaction is profitable to relay based on the current gas price and the transaction's conditions.
-async fn estimate_if_transaction_is_profitable(
-    tip: Uint256,
-    tip_token: Address,
-    gas_used: Uint256,
-    gas_price: Uint256,
-    price_api_url: &str,
-) -> bool {
-    let gas_estimate = gas_used * gas_price;
-    let value = match fetch_value_in_gas_token(price_api_url, tip_token, tip).await {
-        Ok(value) => value,
-        Err(e) => {
-            error!("Failed to fetch tip value in gas token, skipping until the next loop: {e}");
-            return false;
-        }
-    };
-    // 10% profit margin
-    let gas_estimate = gas_estimate + gas_estimate / 10u8.into();
-    if value > gas_estimate {
-        info!("Transaction is profitable: tip value {value} > gas estimate {gas_estimate}");
-        true
-    } else {
-        info!(
-            "Transaction is not profitable Gas Price: {gas_price} Gas Amount {gas_used} tip value {value} <= gas estimate {gas_estimate}"
-        );
-        false
-    }
-}
-
-/// Checks if the receiver address will actually pay this relayer or if it's locked
-/// to some other address, this is used to prevent relaying transactions that will not pay us
-fn is_valid_receiver_address(receiver: Address, our_address: Address) -> bool {
-    // Check if the address is one of the special addresses
-    receiver == Address::from_str(OX_100_ADDRESS).unwrap()
-        || receiver == Address::from_str(OX_200_ADDRESS).unwrap()
-        || receiver == our_address
-}
-
-async fn relay_transaction(
-    web3: &Web3,
-    tx: &GaslessTransaction,
-    private_key: &PrivateKey,
-    contract_address: Address,
-    price_api_url: &str,
-) -> Result<Option<Uint256>, Box<dyn std::error::Error>> {
-    trace!("!!!!! STARTING TRANSACTION RELAY LOGGING !!!!!");
-
-    // Check if transaction data is valid before attempting to parse
-    if tx.cmd.is_empty() {
-        error!("Transaction command data is empty, skipping transaction");
-        return Err("Empty transaction command data".into());
-    }
-
-    // Decode tip data using proper ABI decoding
-    let (tip_token, tip_amount) = if !tx.tip.is_empty() {
-        let token = parse_address(&tx.tip, 0)?;
-        let amount = parse_u128(&tx.tip, 32)?;
-        let receiver = parse_address(&tx.tip, 64)?;
-        trace!("Decoded Tip:");
-        trace!("  Token: 0x{token:?}");
-        trace!("  Amount: {amount}");
-        trace!("  Receiver: {receiver:?}");
-
-        if is_valid_receiver_address(receiver, private_key.to_address()) {
-            (token, Uint256::from(amount))
-        } else {
-            info!("Transaction with invalid receiver address {receiver}, skipping");
-            return Ok(None);
-        }
-    } else {
-        info!("Transaction with no tip data, skipping");
-        return Ok(None);
-    };
+    #[arg(
+        long,
+        value_name = "SIGNATURE",
+        help = "Function relays are sent to, for forks or upgrades of the dex. It must take (uint16,bytes,bytes,bytes,bytes) like the default userCmdRelayer"
+    )]
+    pub relay_function: Option<String>,
 
-    let call = match user_cmd_relayer_tx(*private_key, web3, contract_address, tx).await {
-        Ok(call) => call,
-        Err(e) => {
-            debug!("Failed to prepare transaction: {e:?}");
-            return Err(e.into());
-        }
-    };
+    #[arg(
+        long,
+        value_name = "FIELDS",
+        value_delimiter = ',',
+        help = "Order of the 32 byte words of the tip, a comma separated list naming token, amount and receiver once each and _ for unused words. Defaults to token,amount,receiver"
+    )]
+    pub tip_layout: Option<Vec<String>>,
 
-    let tx_req = TransactionRequest::from_transaction(&call, private_key.to_address());
-    trace!("Tx from: {}", tx_req.get_from());
+    #[arg(
+        long,
+        value_name = "ADDRESS",
+        help = "Tip receiver that pays msg.sender, 0x100 by default"
+    )]
+    pub sender_receiver: Option<String>,
 
-    trace!("Simulating transaction to estimate gas");
-    let gas_used = match web3.eth_estimate_gas(tx_req).await {
-        Ok(gas) => {
-            info!("Gas estimate: {gas}");
-            gas
-        }
-        Err(e) => {
-            error!("Failed to estimate gas: {e:?}");
-            return Err(e.into());
-        }
-    };
-    let gas_price = match web3.eth_gas_price().await {
-        Ok(gp) => gp,
-        Err(e) => return Err(e.into()),
-    };
+    #[arg(
+        long,
+        value_name = "ADDRESS",
+        help = "Tip receiver that pays tx.origin, 0x200 by default"
+    )]
+    pub origin_receiver: Option<String>,
 
-    if estimate_if_transaction_is_profitable(
-        tip_amount,
-        tip_token,
-        gas_used,
-        gas_price,
-        price_api_url,
-    )
-    .await
-    {
-        trace!("Transaction is profitable, proceeding to send");
-    } else {
-        info!("Transaction is not profitable, skipping");
-        return Ok(None);
-    }
+    #[arg(
+        long,
+        value_name = "ADDRESS[=FUNCTION]",
+        help = "Periphery contract, such as a router, that transactions may select with their target field, optionally followed by =<function> when it isn't relayed through the dex's relay function. Can be passed several times, transactions selecting any other contract are refused"
+    )]
+    pub relay_target: Vec<String>,
 
-    trace!("Submitting transaction...");
-    let result = web3.send_prepared_transaction(call).await;
+    #[arg(
+        long,
+        value_name = "ADDRESS",
+        help = "ERC-4337 v0.6 EntryPoint to bundle UserOperations from the orchestrators' UserOperation queue to, bundling is disabled when unset"
+    )]
+    pub entry_point: Option<String>,
+
+    #[arg(
+        long,
+        default_value = "info",
+        value_name = "LOG_LEVEL",
+        help = "Set the logging level (e.g., info, debug, error)"
+    )]
+    pub log_level: String,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "text",
+        value_name = "LOG_FORMAT",
+        help = "Log output format, json emits one structured object per event for log aggregation"
+    )]
+    pub log_format: LogFormat,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Append logs to this file instead of stderr, it is reopened on SIGHUP so it can be rotated"
+    )]
+    pub log_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Write the relayer's PID to this file while it runs, refusing to start if it names a relayer that is still running"
+    )]
+    pub pid_file: Option<PathBuf>,
+
+    #[cfg(feature = "testing")]
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Inject the faults configured in this TOML file: malformed pending transactions, RPC timeouts and stale prices, drawn from a seeded generator so runs are reproducible. For integration tests only"
+    )]
+    pub chaos_config: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Run unattended as a service. On Windows the relayer runs under the service control manager and logs next to the executable unless --log-file is set, elsewhere it runs in the foreground for the init system to supervise, usually with --pid-file and --log-file"
+    )]
+    pub service: bool,
+
+    #[arg(
+        long,
+        help = "Show users in logs by their althea bech32 address, as Keplr shows it, alongside their EVM address"
+    )]
+    pub show_bech32: bool,
+
+    #[arg(
+        long,
+        default_value = "10",
+        value_name = "TIMEOUT",
+        help = "Timeout in seconds for requests to orchestrators, the price API, RPC endpoints and the remote signer, unless overridden for that service"
+    )]
+    pub timeout: u64,
+
+    #[arg(
+        long,
+        value_name = "ORCHESTRATOR_TIMEOUT",
+        help = "Timeout in seconds for orchestrator requests, defaults to --timeout"
+    )]
+    pub orchestrator_timeout: Option<u64>,
+
+    #[arg(
+        long,
+        default_value = "16",
+        value_name = "MEGABYTES",
+        help = "Largest orchestrator response accepted, measured after decompression, so a misbehaving orchestrator can't exhaust memory"
+    )]
+    pub orchestrator_max_response_mb: usize,
+
+    #[arg(
+        long,
+        value_name = "PRICE_TIMEOUT",
+        help = "Timeout in seconds for price API requests, defaults to --timeout"
+    )]
+    pub price_timeout: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "RPC_TIMEOUT",
+        help = "Timeout in seconds for RPC calls, defaults to --timeout"
+    )]
+    pub rpc_timeout: Option<u64>,
+
+    #[arg(
+        long,
+        default_value = "60",
+        value_name = "RECEIPT_TIMEOUT",
+        help = "Seconds to wait for a submitted relay to be included before its outcome is treated as unknown"
+    )]
+    pub receipt_timeout: u64,
+
+    #[arg(
+        long,
+        default_value = "false",
+        value_name = "AGREE",
+        help = "Agree to the terms and conditions"
+    )]
+    pub agree: bool,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Directory to record acceptance of the terms in, later runs then don't need --agree until the terms change. Without --agree the terms are confirmed on the terminal"
+    )]
+    pub state_dir: Option<PathBuf>,
+
+    #[arg(
+        long,
+        default_value = "false",
+        value_name = "DRY_RUN",
+        help = "Fetch, decode, estimate and evaluate transactions as normal but never broadcast them"
+    )]
+    pub dry_run: bool,
+
+    #[arg(
+        long,
+        default_value = "false",
+        value_name = "SUBSCRIBE",
+        help = "Subscribe to the orchestrator's streaming endpoint for new transactions, polling is used as a fallback while the stream is down"
+    )]
+    pub subscribe: bool,
+
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        conflicts_with = "subscribe",
+        help = "Long poll the orchestrator's pending queue, asking it to hold each request for up to this many seconds until new transactions arrive, polling is used as a fallback while long polls fail"
+    )]
+    pub long_poll: Option<u64>,
+
+    #[arg(
+        long,
+        default_value = "false",
+        value_name = "ONCE",
+        conflicts_with_all = ["subscribe", "long_poll"],
+        help = "Run a single cycle, polling every orchestrator once and relaying what it returns, then exit. Exits with 1 if an orchestrator could not be polled and 2 if a relay failed"
+    )]
+    pub once: bool,
+
+    #[arg(
+        long,
+        value_name = "URL=KEY",
+        help = "API key to send to a specific orchestrator as <URL>=<KEY>, may be provided multiple times"
+    )]
+    pub orchestrator_api_key: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "URL=IP",
+        help = "Address to reach a specific orchestrator at without looking it up, as <URL>=<IP>[:<PORT>], may be provided multiple times to fail over between addresses"
+    )]
+    pub orchestrator_ip: Vec<String>,
+
+    #[arg(
+        long,
+        default_value = "60",
+        value_name = "SECONDS",
+        help = "How long resolved orchestrator addresses are cached for, 0 to resolve on every poll"
+    )]
+    pub dns_cache_secs: u64,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "prefer-ipv6",
+        value_name = "PREFERENCE",
+        help = "Address family orchestrators are reached over first, or the only one they are reached over"
+    )]
+    pub ip_preference: IpPreference,
+
+    #[arg(
+        long,
+        default_value = "250",
+        value_name = "MILLISECONDS",
+        help = "How long an orchestrator address may go unanswered before its next address is tried alongside it"
+    )]
+    pub connection_attempt_delay_ms: u64,
+
+    #[arg(
+        long,
+        value_name = "URL",
+        help = "Orchestrator URL that requires the relayer to sign a timestamped challenge with its key, may be provided multiple times"
+    )]
+    pub orchestrator_signed_auth: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "URL=ADDRESS",
+        help = "Address a specific orchestrator signs its pending transactions with as <URL>=<ADDRESS>, payloads from it that aren't signed by this address are rejected, may be provided multiple times"
+    )]
+    pub orchestrator_signer: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "PEM_FILE",
+        requires = "orchestrator_client_key",
+        help = "Client certificate chain to present to orchestrators that require mutual TLS"
+    )]
+    pub orchestrator_client_cert: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "PEM_FILE",
+        requires = "orchestrator_client_cert",
+        help = "Private key of --orchestrator-client-cert"
+    )]
+    pub orchestrator_client_key: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "PEM_FILE",
+        help = "CA bundle trusted for orchestrator certificates in addition to the system trust roots, for orchestrators using a private CA"
+    )]
+    pub orchestrator_ca_bundle: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "PROXY_URL",
+        help = "Route orchestrator, price and RPC traffic through this proxy, either http://[user:pass@]host:port or socks5://[user:pass@]host:port"
+    )]
+    pub proxy_url: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "PEM file of a CA certificate to trust for orchestrator, price and RPC requests on top of the system's, may be provided multiple times"
+    )]
+    pub extra_ca_cert: Vec<PathBuf>,
+
+    #[arg(
+        long,
+        default_value = "false",
+        help = "Accept any TLS certificate from orchestrators, price APIs and RPC endpoints. Dangerous, anyone on the network path can then impersonate them"
+    )]
+    pub insecure_skip_tls_verify: bool,
+
+    #[arg(
+        long,
+        default_value = "1",
+        value_name = "THREADS",
+        help = "Worker threads of each HTTP server the relayer runs: the status, admin, quote and submit servers and the local RPC bridge"
+    )]
+    pub http_workers: usize,
+
+    #[arg(
+        long,
+        default_value = "100",
+        value_name = "CONNECTIONS",
+        help = "Most connections each outbound HTTP client keeps open at once, to orchestrators, price APIs and RPC endpoints through the bridge"
+    )]
+    pub http_max_connections: usize,
+
+    #[arg(
+        long,
+        default_value = "15",
+        value_name = "SECONDS",
+        help = "How long an idle outbound HTTP connection is kept open to be reused"
+    )]
+    pub http_keep_alive_secs: u64,
+
+    #[arg(
+        long,
+        default_value = "75",
+        value_name = "SECONDS",
+        help = "How long an outbound HTTP connection is reused for before it is replaced"
+    )]
+    pub http_connection_lifetime_secs: u64,
+
+    #[arg(
+        long,
+        default_value = "false",
+        help = "Send RPC requests through the local bridge so that they reuse pooled connections, instead of opening a new connection for each one"
+    )]
+    pub pool_rpc_connections: bool,
+
+    #[arg(
+        long,
+        default_value = "3",
+        value_name = "ORCHESTRATOR_FAILURE_THRESHOLD",
+        help = "Stop polling an orchestrator after this many consecutive failed polls, 0 to always poll every orchestrator"
+    )]
+    pub orchestrator_failure_threshold: u32,
+
+    #[arg(
+        long,
+        default_value = "60",
+        value_name = "ORCHESTRATOR_OPEN_DURATION",
+        help = "Seconds a failing orchestrator is not polled before it is probed again"
+    )]
+    pub orchestrator_open_duration: u64,
+
+    #[arg(
+        long,
+        default_value = "4",
+        value_name = "MAX_DEMOTION",
+        help = "Orchestrators that are slow, mostly serve duplicates of other orchestrators or entries that can never be relayed are polled less often, at most once every this many cycles. 1 polls every orchestrator every cycle"
+    )]
+    pub max_demotion: u64,
+
+    #[arg(
+        long,
+        default_value = "false",
+        value_name = "CLAIM",
+        help = "Reserve each transaction with its orchestrator once it passed every check and only relay if the claim succeeds"
+    )]
+    pub claim: bool,
+
+    #[arg(
+        long,
+        value_name = "PENDING_PAGE_SIZE",
+        help = "Request the orchestrator's pending queue in pages of this many transactions"
+    )]
+    pub pending_page_size: Option<u32>,
+
+    #[arg(
+        long,
+        value_name = "PENDING_CHAIN_ID",
+        help = "Only request pending transactions for this chain id from the orchestrator"
+    )]
+    pub pending_chain_id: Option<u64>,
+
+    #[arg(
+        long,
+        default_value = "false",
+        help = "Always fetch the whole pending queue instead of sending ETag / If-Modified-Since validators and reusing the last queue when unchanged"
+    )]
+    pub unconditional_polling: bool,
+
+    #[arg(
+        long,
+        default_value = "false",
+        help = "Only fetch pending transactions submitted since the newest one already fetched from each orchestrator, evaluating just the new ones, the orchestrator must honor the since filter"
+    )]
+    pub incremental_fetch: bool,
+
+    #[arg(
+        long,
+        default_value = "300",
+        value_name = "SECONDS",
+        help = "How often the whole pending queue is fetched and evaluated again when fetching incrementally"
+    )]
+    pub full_fetch_interval: u64,
+
+    #[arg(
+        long,
+        value_name = "STATUS_BIND",
+        help = "Address to serve /healthz, /status, /metrics and /dashboard on, for example 127.0.0.1:9090"
+    )]
+    pub status_bind: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "ADMIN_BIND",
+        help = "Address to serve the unauthenticated admin API on (/admin/pause, /admin/resume, /admin/stats, /admin/queue), use a loopback address such as 127.0.0.1:9091"
+    )]
+    pub admin_bind: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "QUOTE_BIND",
+        help = "Address to serve POST /quote on, where orchestrators and wallets can ask what tip a transaction needs before submitting it"
+    )]
+    pub quote_bind: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "QUOTES",
+        default_value_t = 60,
+        help = "Most quotes each client IP may ask for on --quote-bind within a minute, each costs gas estimate and price RPCs on the relay loop's endpoints"
+    )]
+    pub quote_rate_limit: u32,
+
+    #[arg(
+        long,
+        default_value = "60",
+        value_name = "SHUTDOWN_TIMEOUT",
+        help = "Seconds to wait for in-flight transactions to confirm after a shutdown signal before exiting"
+    )]
+    pub shutdown_timeout: u64,
+
+    #[arg(
+        long,
+        default_value = "5",
+        value_name = "RETRY_BASE_DELAY",
+        help = "Seconds to wait before the first retry of a transiently failed relay, doubling with each attempt"
+    )]
+    pub retry_base_delay: u64,
+
+    #[arg(
+        long,
+        default_value = "5",
+        value_name = "MAX_RETRY_ATTEMPTS",
+        help = "Maximum number of retries for a transiently failed relay"
+    )]
+    pub max_retry_attempts: u32,
+
+    #[arg(
+        long,
+        default_value = "30",
+        value_name = "FAILURE_COOLDOWN",
+        help = "Seconds a transaction that failed permanently, for example by reverting, is not attempted again, doubling with each consecutive failure"
+    )]
+    pub failure_cooldown: u64,
+
+    #[arg(
+        long,
+        default_value = "10",
+        value_name = "MAX_IDENTICAL_FAILURES",
+        help = "Ignore a transaction for good once it has failed with the same error this many times in a row"
+    )]
+    pub max_identical_failures: u32,
+
+    #[arg(
+        long,
+        value_name = "CONFIG",
+        help = "TOML config file with one [[chain]] section per chain to relay on, replaces --alhtea-evm-rpc and --contract-address"
+    )]
+    pub config: Option<PathBuf>,
+
+    #[arg(
+        long,
+        default_value = "60",
+        value_name = "RPC_STALE_AFTER",
+        help = "Seconds without a new block after which an RPC endpoint is considered stale and the next one is used"
+    )]
+    pub rpc_stale_after: u64,
+
+    #[arg(
+        long,
+        default_value = "3",
+        value_name = "RPC_MAX_LAG_BLOCKS",
+        help = "RPC endpoints whose head is more than this many blocks behind the best known head are quarantined"
+    )]
+    pub rpc_max_lag_blocks: u64,
+
+    #[arg(
+        long,
+        default_value = "0",
+        value_name = "WEI",
+        help = "Wei of the gas token each relayer account keeps back for liquidating tips and cancelling stuck relays. No new relay is submitted that could take the balance below it, relays already in flight are still seen through"
+    )]
+    pub reserve_balance_wei: u128,
+
+    #[arg(
+        long,
+        value_name = "ADDRESS",
+        help = "Cold address to sweep profits to, set --sweep-ceiling-wei and/or --sweep-token to choose what is swept"
+    )]
+    pub sweep_to: Option<Address>,
+
+    #[arg(
+        long,
+        value_name = "WEI",
+        requires = "sweep_to",
+        help = "Wei of the gas token each relayer account keeps, everything above it is swept to --sweep-to. Must exceed --reserve-balance-wei, the sweep is paid for out of the difference"
+    )]
+    pub sweep_ceiling_wei: Option<u128>,
+
+    #[arg(
+        long,
+        value_name = "TOKEN[=KEEP]",
+        requires = "sweep_to",
+        help = "Token to sweep to --sweep-to, leaving KEEP base units in each relayer account (none by default), may be provided multiple times"
+    )]
+    pub sweep_token: Vec<String>,
+
+    #[arg(
+        long,
+        default_value = "3600",
+        value_name = "SECONDS",
+        help = "How often relayer accounts are swept"
+    )]
+    pub sweep_interval_secs: u64,
+
+    #[arg(
+        long,
+        value_name = "ADDRESS=PERCENT",
+        requires = "history_db",
+        help = "Pool partner given PERCENT of every tip paid to the shared 0x100/0x200 receivers, transferred to it once the relay is final. May be provided multiple times, needs --history-db to keep the ledger of shares"
+    )]
+    pub tip_split: Vec<String>,
+
+    #[arg(
+        long,
+        default_value = "3600",
+        value_name = "SECONDS",
+        help = "How often tip shares owed to --tip-split partners are paid out"
+    )]
+    pub tip_split_interval_secs: u64,
+
+    #[arg(
+        long,
+        value_name = "ALTHEA",
+        help = "Pause relaying and alert once relays have spent this much on gas within the last hour"
+    )]
+    pub max_gas_spend_per_hour: Option<f64>,
+
+    #[arg(
+        long,
+        value_name = "ALTHEA",
+        help = "Pause relaying and alert once relays have spent this much on gas within the last day"
+    )]
+    pub max_gas_spend_per_day: Option<f64>,
+
+    #[arg(
+        long,
+        value_name = "ALTHEA",
+        help = "Relay transactions whose tip doesn't cover the gas on up to this much of a sponsor's ALTHEA within any day. Each relay reserves the most it could cost until final, once the budget is used up only profitable transactions are relayed"
+    )]
+    pub subsidy_budget_per_day: Option<f64>,
+
+    #[arg(
+        long,
+        value_name = "ALTHEA",
+        help = "Most of the subsidy budget the transactions of any one user may use within any day. Defaults to a tenth of --subsidy-budget-per-day"
+    )]
+    pub subsidy_per_user_per_day: Option<f64>,
+
+    #[arg(
+        long,
+        value_name = "PERCENTS",
+        value_delimiter = ',',
+        default_value = "50,80",
+        help = "Comma separated percentages of the subsidy budget to alert at as it is used, an alert is always sent once it is used up"
+    )]
+    pub subsidy_alert_percent: Vec<u32>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "File subsidy spending is kept in, so a restart within the day doesn't spend the budget twice"
+    )]
+    pub subsidy_ledger: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "PATH_OR_URL",
+        help = "File or http(s) url checked every cycle, relaying is paused while it reads pause and continues while it reads run. A missing file reads as run"
+    )]
+    pub kill_switch: Option<String>,
+
+    #[arg(
+        long,
+        default_value = "false",
+        requires = "kill_switch",
+        help = "Keep relaying as before when --kill-switch can't be read or holds anything but pause or run, instead of pausing"
+    )]
+    pub kill_switch_fail_open: bool,
+
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        help = "Check the code of the dex and relay targets this often, pausing relaying and alerting if it changes until confirmed with POST /admin/code/confirm. EIP-1967 proxies are also checked for a new implementation"
+    )]
+    pub code_check_interval: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "ADDRESS=HASH",
+        value_delimiter = ',',
+        value_parser = parse_expected_code_hash,
+        requires = "code_check_interval",
+        help = "Code hash a contract must have as logged at startup, so that a change while the relayer was down also pauses relaying. Without it a contract's code is confirmed as first seen"
+    )]
+    pub expected_code_hash: Vec<(Address, [u8; 32])>,
+
+    #[arg(
+        long,
+        value_name = "URL",
+        help = "Coordinate redundant instances relaying from the same key through leases in redis://[:PASSWORD@]HOST[:PORT][/DB] or an etcd JSON gateway at etcd+http(s)://HOST:PORT. Only the instance holding an account's lease relays from it, the others stand by"
+    )]
+    pub lease_store: Option<String>,
+
+    #[arg(
+        long,
+        default_value = "60",
+        value_name = "SECONDS",
+        requires = "lease_store",
+        help = "How long a lease lasts without being renewed, after which a standby instance takes over. Leases are renewed every cycle, so this must be at least four times the poll interval"
+    )]
+    pub lease_ttl_secs: u64,
+
+    #[arg(
+        long,
+        value_name = "ID",
+        requires = "lease_store",
+        help = "Name this instance holds its leases under, must be unique among the instances. Defaults to the host name, process id and a random suffix"
+    )]
+    pub instance_id: Option<String>,
+
+    #[arg(
+        long,
+        default_value = "false",
+        requires = "lease_store",
+        help = "Elect a single leader among the instances relaying from the same key through the lease store. Only the leader claims and broadcasts relays, the others keep polling and simulating and one takes over within a poll interval if the leader's heartbeat stops"
+    )]
+    pub leader_election: bool,
+
+    #[arg(
+        long,
+        value_name = "CRON LENGTH",
+        help = "Pause new submissions during a maintenance window, given as a five field cron expression in UTC followed by the window's length, such as \"0 2 * * 0 2h\". Can be passed several times"
+    )]
+    pub maintenance_window: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "ADDRESS",
+        requires = "protocol_callpath",
+        help = "Relay governance and protocol commands signed by this account from the orchestrators' protocol queue to the dex's protocolCmd, the relayer account must be the dex's authority. May be provided multiple times"
+    )]
+    pub protocol_submitter: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "CALLPATH[,sudo][,max-gas=GAS]",
+        requires = "protocol_submitter",
+        help = "Callpath protocol commands may be sent to, sudo commands only if sudo is given and at most max-gas gas if given. May be provided multiple times"
+    )]
+    pub protocol_callpath: Vec<String>,
+
+    #[arg(
+        long,
+        default_value = "false",
+        help = "Choose fees from the priority fees paid in recent blocks, sampled with eth_feeHistory, instead of eth_gasPrice"
+    )]
+    pub gas_oracle: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "auto",
+        help = "Fee profile of the gas oracle, auto is aggressive while recent blocks are congested and normal otherwise"
+    )]
+    pub gas_profile: GasProfile,
+
+    #[arg(
+        long,
+        default_value = "20",
+        value_name = "BLOCKS",
+        help = "Number of recent blocks the gas oracle and the fee band sample"
+    )]
+    pub gas_oracle_blocks: u64,
+
+    #[arg(
+        long,
+        default_value = "50",
+        value_name = "PERCENTILE",
+        help = "Percentile of the priority fees paid in each sampled block used by the normal profile"
+    )]
+    pub normal_fee_percentile: f64,
+
+    #[arg(
+        long,
+        default_value = "90",
+        value_name = "PERCENTILE",
+        help = "Percentile of the priority fees paid in each sampled block used by the aggressive profile"
+    )]
+    pub aggressive_fee_percentile: f64,
+
+    #[arg(
+        long,
+        default_value = "false",
+        help = "Require tips to cover the gas at the pessimistic end of a band of fees sampled with eth_feeHistory, rather than at the price of the moment, so relays don't lose money when fees rise before inclusion"
+    )]
+    pub fee_band: bool,
+
+    #[arg(
+        long,
+        default_value = "25",
+        value_name = "PERCENTILE",
+        help = "Percentile of the sampled fees at the optimistic end of the fee band"
+    )]
+    pub optimistic_fee_percentile: f64,
+
+    #[arg(
+        long,
+        default_value = "90",
+        value_name = "PERCENTILE",
+        help = "Percentile of the sampled fees at the pessimistic end of the fee band, which tips have to cover"
+    )]
+    pub pessimistic_fee_percentile: f64,
+
+    #[arg(
+        long,
+        default_value = "80",
+        value_name = "PERCENT",
+        help = "Average usage of the sampled blocks at or above which the auto profile turns aggressive"
+    )]
+    pub congestion_percent: f64,
+
+    #[arg(
+        long,
+        default_value = "false",
+        help = "Attach the access list from eth_createAccessList to relays whenever it lowers their gas estimate"
+    )]
+    pub access_lists: bool,
+
+    #[arg(
+        long,
+        value_name = "MAX_GAS_PRICE_GWEI",
+        help = "Pause relaying while the network gas price is above this many gwei"
+    )]
+    pub max_gas_price_gwei: Option<f64>,
+
+    #[arg(
+        long,
+        value_name = "MULTIPLE",
+        requires = "max_gas_price_gwei",
+        help = "While the gas price is above --max-gas-price-gwei keep relaying transactions whose tip is worth at least this many times the gas cost"
+    )]
+    pub over_cap_tip_multiple: Option<u32>,
+
+    #[arg(
+        long,
+        value_name = "MAX_GAS_LIMIT",
+        help = "Never relay transactions whose gas estimate exceeds this, regardless of apparent profitability"
+    )]
+    pub max_gas_limit: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "FRACTION",
+        help = "Skip transactions whose gas estimate exceeds this fraction of the latest block gas limit, for example 0.25"
+    )]
+    pub max_block_gas_fraction: Option<f64>,
+
+    #[arg(
+        long,
+        value_name = "FRACTION",
+        help = "Pause relaying while the last --congestion-blocks blocks of a chain used at least this fraction of their gas limit on average, for example 0.95"
+    )]
+    pub congestion_pause_threshold: Option<f64>,
+
+    #[arg(
+        long,
+        default_value = "20",
+        value_name = "BLOCKS",
+        help = "Number of recent blocks averaged for --congestion-pause-threshold"
+    )]
+    pub congestion_blocks: u64,
+
+    #[arg(
+        long,
+        value_name = "MAX_TX_AGE_SECS",
+        help = "Skip transactions submitted to their orchestrator more than this many seconds ago without simulating them, these have almost always expired, been relayed or been abandoned"
+    )]
+    pub max_tx_age_secs: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "WEI",
+        help = "Trace relays whose tip is worth at least this many wei of the gas token with debug_traceCall before broadcasting them, skipping any that don't pay their tip or move funds out of the relayer accounts"
+    )]
+    pub trace_min_tip_wei: Option<u128>,
+
+    #[arg(
+        long,
+        value_name = "URL",
+        requires = "trace_min_tip_wei",
+        help = "RPC endpoint supporting debug_traceCall to trace relays on, such as a local fork of the chain, defaults to the chain's RPC"
+    )]
+    pub trace_rpc: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "RELAYS",
+        help = "Learn the gas each kind of command uses from the receipts of our relays, and once this many relays of a kind were seen skip transactions whose tip can't cover its usual gas without estimating them"
+    )]
+    pub gas_model_samples: Option<u32>,
+
+    #[arg(
+        long,
+        default_value = "30",
+        value_name = "PERCENT",
+        requires = "gas_model_samples",
+        help = "Transactions are only skipped by the gas model if their tip can't cover this many percent less gas than their command uses on average"
+    )]
+    pub gas_model_tolerance_percent: u32,
+
+    #[arg(
+        long,
+        value_name = "SECS",
+        help = "Relay transactions whose deadline is less than this many seconds away ahead of the queue with a raised priority fee, and skip those whose deadline is closer than relays are currently taking to be included"
+    )]
+    pub urgent_window_secs: Option<u64>,
+
+    #[arg(
+        long,
+        default_value = "100",
+        value_name = "PERCENT",
+        requires = "urgent_window_secs",
+        help = "Raise the priority fee of relays close to their deadline by this many percent"
+    )]
+    pub urgent_fee_bump_percent: u32,
+
+    #[arg(
+        long,
+        value_name = "GWEI",
+        requires = "urgent_window_secs",
+        help = "Never raise the priority fee of relays close to their deadline above this many gwei"
+    )]
+    pub urgent_max_priority_fee_gwei: Option<f64>,
+
+    #[arg(
+        long,
+        default_value = "30",
+        value_name = "SECONDS",
+        help = "Reuse the gas estimate of a transaction for this many seconds, so one passed over and served again next cycle isn't estimated again. 0 estimates every time"
+    )]
+    pub gas_estimate_cache_secs: u64,
+
+    #[arg(
+        long,
+        value_name = "MAX_TXS_PER_CYCLE",
+        help = "Evaluate at most this many transactions per cycle, the most profitable looking first. The rest are deferred to later cycles so that a flood of submissions can't hold up confirmation and fee bump checks"
+    )]
+    pub max_txs_per_cycle: Option<usize>,
+
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        help = "95th percentile time from a transaction's submission to its relay being included to stay under. While recent relays take longer, transactions expected to earn less than --latency-slo-min-profit-wei are deferred"
+    )]
+    pub latency_slo_secs: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "WEI",
+        default_value_t = 1,
+        help = "Expected profit in wei of the gas token below which transactions are deferred while latency is over --latency-slo-secs"
+    )]
+    pub latency_slo_min_profit_wei: u128,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "profit",
+        value_name = "ORDER",
+        help = "Order transactions are evaluated in. `profit` takes the most profitable looking first. `shuffle` and `salted`, a fixed order unique to the relayer's address, spread relayers working through the same queue across it instead of all racing for its top"
+    )]
+    pub evaluation_order: EvaluationOrder,
+
+    #[arg(
+        long,
+        default_value = "10",
+        value_name = "PROFIT_MARGIN_PERCENT",
+        help = "Only relay transactions whose tip exceeds the gas cost by at least this many percent"
+    )]
+    pub profit_margin_percent: u32,
+
+    #[arg(
+        long,
+        default_value = "0",
+        value_name = "GAS",
+        help = "Gas used by the transaction that later sells a tip for ALTHEA, its cost at the current gas price is subtracted from tips not paid in ALTHEA"
+    )]
+    pub liquidation_gas: u64,
+
+    #[arg(
+        long,
+        help = "Raise the profit margin while relays revert on chain or are frontrun and lower it again after sustained success"
+    )]
+    pub adaptive_margin: bool,
+
+    #[arg(
+        long,
+        default_value = "5",
+        value_name = "PERCENT",
+        help = "Percentage points the adaptive margin moves by at a time"
+    )]
+    pub adaptive_margin_step: u32,
+
+    #[arg(
+        long,
+        default_value = "50",
+        value_name = "PERCENT",
+        help = "Most percentage points the adaptive margin may add to --profit-margin-percent"
+    )]
+    pub adaptive_margin_max: u32,
+
+    #[arg(
+        long,
+        default_value = "10",
+        value_name = "RELAYS",
+        help = "Consecutive included relays after which the adaptive margin is lowered by one step"
+    )]
+    pub adaptive_margin_relax_after: u32,
+
+    #[arg(
+        long,
+        default_value = "0",
+        value_name = "BPS",
+        help = "Pool fees paid when selling a tip for ALTHEA in basis points, subtracted from tips valued at the spot price"
+    )]
+    pub liquidation_fee_bps: u32,
+
+    #[arg(
+        long,
+        value_name = "MULTICALL_ADDRESS",
+        help = "Multicall3 contract used to bundle several relays into one transaction, relays are submitted individually when unset"
+    )]
+    pub multicall_address: Option<String>,
+
+    #[arg(
+        long,
+        default_value = "10",
+        value_name = "MAX_BATCH_SIZE",
+        help = "Maximum number of relays bundled into one multicall transaction"
+    )]
+    pub max_batch_size: usize,
+
+    #[arg(
+        long,
+        default_value = "8",
+        value_name = "COUNT",
+        help = "Gas estimates and tip valuations made at once ahead of relaying each cycle's transactions, 1 makes them one at a time"
+    )]
+    pub max_concurrent_estimates: usize,
+
+    #[arg(
+        long,
+        value_name = "COMMAND",
+        help = "Only relay this kind of command, either a name such as swap, limit-mint or init-pool, a callpath or <callpath>:<code>, may be provided multiple times. All commands are relayed if not provided"
+    )]
+    pub allowed_command: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "ADDRESS",
+        help = "User whose transactions are relayed even when their tip doesn't cover the gas, such as under a subsidized relaying agreement. Every other check still applies. May be provided multiple times"
+    )]
+    pub partner: Vec<String>,
+
+    /// `[[override]]` sections of the config file, set along with `[settings]`
+    #[arg(skip)]
+    pub overrides: Vec<LimitOverrideConfig>,
+
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Send at most this many requests per minute to each orchestrator, requests over it wait their turn"
+    )]
+    pub max_orchestrator_requests_per_minute: Option<u32>,
+
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Send at most this many JSON-RPC requests per second to each RPC endpoint, requests over it wait their turn. Waiting counts towards --rpc-timeout"
+    )]
+    pub max_rpc_calls_per_second: Option<u32>,
+
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Look up at most this many prices per minute from the price API, lookups over it wait their turn"
+    )]
+    pub max_price_lookups_per_minute: Option<u32>,
+
+    #[arg(
+        long,
+        default_value = "2",
+        value_name = "N",
+        help = "Send GET requests to orchestrators that fail with a connection error, a timeout or a 429 or 5xx response up to this many more times. 0 disables retries"
+    )]
+    pub orchestrator_retries: u32,
+
+    #[arg(
+        long,
+        default_value = "250",
+        value_name = "MILLISECONDS",
+        help = "Backoff before the first retry of an orchestrator request, doubling with every retry after it. Each wait is randomized between zero and the backoff"
+    )]
+    pub orchestrator_retry_delay_ms: u64,
+
+    #[arg(
+        long,
+        default_value = "2",
+        value_name = "N",
+        help = "Send price lookups that fail with a connection error, a timeout or a 429 or 5xx response up to this many more times. 0 disables retries"
+    )]
+    pub price_retries: u32,
+
+    #[arg(
+        long,
+        default_value = "250",
+        value_name = "MILLISECONDS",
+        help = "Backoff before the first retry of a price lookup, doubling with every retry after it. Each wait is randomized between zero and the backoff"
+    )]
+    pub price_retry_delay_ms: u64,
+
+    #[arg(
+        long,
+        value_name = "MAX_RELAYS_PER_USER",
+        help = "Relay at most this many transactions from one user address per --rate-limit-window, unlimited if not provided"
+    )]
+    pub max_relays_per_user: Option<u32>,
+
+    #[arg(
+        long,
+        default_value = "3600",
+        value_name = "RATE_LIMIT_WINDOW",
+        help = "Window in seconds over which --max-relays-per-user is enforced"
+    )]
+    pub rate_limit_window: u64,
+
+    #[arg(
+        long,
+        value_name = "FAILURES",
+        help = "Stop simulating transactions of a user once this many of them failed simulation within --simulation-budget-window, until the window is over. Unlimited if not provided"
+    )]
+    pub max_failed_simulations_per_user: Option<u32>,
+
+    #[arg(
+        long,
+        default_value = "3600",
+        value_name = "SECONDS",
+        help = "Window in seconds over which --max-failed-simulations-per-user is enforced"
+    )]
+    pub simulation_budget_window: u64,
+
+    #[arg(
+        long,
+        help = "Before simulating a transaction, check that its user holds and has approved the dex for its tip and, for swaps with a fixed input, the input token. Transactions that fall short are skipped"
+    )]
+    pub check_user_funds: bool,
+
+    #[arg(
+        long,
+        value_name = "PRIVATE_TX_RPC",
+        help = "Private or priority RPC endpoint to submit relays through so they can't be frontrun from the public mempool, falls back to public broadcast if it fails"
+    )]
+    pub private_tx_rpc: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "OTLP_ENDPOINT",
+        help = "OTLP/HTTP endpoint to export tracing spans of the relay pipeline to, such as http://localhost:4318/v1/traces"
+    )]
+    pub otlp_endpoint: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "SQLite database to record every relay outcome in, query it with the history subcommand"
+    )]
+    pub history_db: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Append-only file to record every relay decision and its inputs in, hash chained and sealed with the relayer key every cycle. Check it with the verify-audit subcommand"
+    )]
+    pub audit_log: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "JSON file to keep submitted relays in until they are final, so they are watched again after a restart"
+    )]
+    pub relay_store: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "JSON file to keep banned users in, so bans survive a restart"
+    )]
+    pub ban_list: Option<PathBuf>,
+
+    #[arg(
+        long,
+        default_value = "3",
+        value_name = "STRIKES",
+        help = "Ban a user once this many of their transactions were malformed or our relays of them reverted on chain, 0 to never ban automatically"
+    )]
+    pub ban_after_strikes: u32,
+
+    #[arg(
+        long,
+        value_name = "URL",
+        help = "Slack incoming webhook to send alerts to"
+    )]
+    pub alert_slack_webhook: Option<String>,
+
+    #[arg(long, value_name = "URL", help = "Discord webhook to send alerts to")]
+    pub alert_discord_webhook: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "TOKEN",
+        requires = "alert_telegram_chat_id",
+        help = "Telegram bot token to send alerts with"
+    )]
+    pub alert_telegram_bot_token: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "CHAT_ID",
+        requires = "alert_telegram_bot_token",
+        help = "Telegram chat to send alerts to"
+    )]
+    pub alert_telegram_chat_id: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "ALTHEA",
+        help = "Alert when a relayer account holds less than this much of the gas token"
+    )]
+    pub alert_min_balance: Option<f64>,
+
+    #[arg(
+        long,
+        default_value = "5",
+        value_name = "COUNT",
+        help = "Alert when this many relays in a row fail, 0 to never alert on failures"
+    )]
+    pub alert_failure_threshold: u32,
+
+    #[arg(
+        long,
+        default_value = "3",
+        value_name = "COUNT",
+        help = "Alert when this many final relays in a row paid more for gas than their tip was worth, 0 to never alert on losses"
+    )]
+    pub alert_losing_relays: u32,
+
+    #[arg(
+        long,
+        default_value = "10",
+        value_name = "MINUTES",
+        help = "Alert when an orchestrator has been unreachable for this long"
+    )]
+    pub alert_orchestrator_down_minutes: u64,
+
+    #[arg(
+        long,
+        value_name = "DSN",
+        help = "Report panics and error level logs to the Sentry project with this DSN"
+    )]
+    pub sentry_dsn: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "URL",
+        help = "Post panics and error level logs to this url as JSON"
+    )]
+    pub error_webhook: Option<String>,
+
+    #[arg(
+        long,
+        default_value = "false",
+        help = "Log a summary of relays, gas spent, tips earned and failures every --summary-interval"
+    )]
+    pub summary: bool,
+
+    #[arg(
+        long,
+        value_name = "URL",
+        help = "Post every summary as JSON to this webhook, implies --summary"
+    )]
+    pub summary_webhook: Option<String>,
+
+    #[arg(
+        long,
+        default_value = "86400",
+        value_name = "SECONDS",
+        help = "Period covered by each summary"
+    )]
+    pub summary_interval: u64,
+
+    #[arg(
+        long,
+        default_value = "false",
+        conflicts_with = "once",
+        help = "Register with every orchestrator, announcing the chains, tokens and minimum tip this relayer accepts, and keep sending heartbeats so transactions are routed to it"
+    )]
+    pub register: bool,
+
+    #[arg(
+        long,
+        value_name = "ADDRESS",
+        requires = "register",
+        help = "Token tips are accepted in, announced when registering, may be provided multiple times. Any token that can be valued is announced as accepted if not provided"
+    )]
+    pub register_token: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "ALTHEA",
+        requires = "register",
+        help = "Smallest tip value in ALTHEA announced when registering"
+    )]
+    pub register_min_tip: Option<f64>,
+
+    #[arg(
+        long,
+        default_value = "60",
+        value_name = "SECONDS",
+        help = "Time between heartbeats sent to the orchestrators registered with"
+    )]
+    pub heartbeat_interval: u64,
+}
+
+impl RelayerOpts {
+    /// Timeout of a service with its own timeout option, falling back to `--timeout`
+    fn service_timeout(&self, timeout: Option<u64>) -> Duration {
+        Duration::from_secs(timeout.unwrap_or(self.timeout))
+    }
+}
+
+/// Legacy command line without a subcommand, equivalent to `run`
+#[derive(Debug, Parser)]
+#[command(name = "ifi-relayer", about = "iFi Dex transaction relayer")]
+struct LegacyCli {
+    #[command(flatten)]
+    opts: RelayerOpts,
+}
+
+/// A gasless transaction given either as JSON or as its hex encoded fields
+#[derive(Debug, Args)]
+#[command(group(
+    ArgGroup::new("input")
+        .required(true)
+        .args(["transaction", "cmd"])
+))]
+pub struct TransactionInput {
+    #[arg(
+        value_name = "TRANSACTION",
+        help = "Transaction as returned by the orchestrator, either inline JSON or a path to a JSON file"
+    )]
+    pub transaction: Option<String>,
+
+    #[arg(long, default_value = "417834", value_name = "CHAIN_ID")]
+    pub chain_id: u64,
+
+    #[arg(long, default_value = "1", value_name = "CALLPATH")]
+    pub callpath: u16,
+
+    #[arg(long, value_name = "HEX", requires_all = ["conds", "tip", "sig"], help = "Hex encoded cmd")]
+    pub cmd: Option<String>,
+
+    #[arg(long, value_name = "HEX", help = "Hex encoded conds")]
+    pub conds: Option<String>,
+
+    #[arg(long, value_name = "HEX", help = "Hex encoded tip")]
+    pub tip: Option<String>,
+
+    #[arg(long, value_name = "HEX", help = "Hex encoded signature")]
+    pub sig: Option<String>,
+}
+
+impl TransactionInput {
+    fn read(&self) -> GaslessTransaction {
+        if let Some(input) = &self.transaction {
+            return read_transaction(input);
+        }
+        let hex_arg = |arg: &Option<String>| {
+            let arg = arg.as_deref().unwrap_or_default();
+            hex::decode(arg.strip_prefix("0x").unwrap_or(arg)).expect("Invalid hex")
+        };
+        GaslessTransaction {
+            chain_id: self.chain_id,
+            callpath: self.callpath,
+            cmd: hex_arg(&self.cmd),
+            conds: hex_arg(&self.conds),
+            tip: hex_arg(&self.tip),
+            sig: hex_arg(&self.sig),
+            submitted_at: 0,
+            target: None,
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct DecodeOpts {
+    #[command(flatten)]
+    pub input: TransactionInput,
+
+    #[arg(
+        long,
+        default_value = "0xd263DC98dEc57828e26F69bA8687281BA5D052E0",
+        value_name = "CONTRACT_ADDRESS",
+        help = "Dex contract the transaction was signed for, used to recover the signing user"
+    )]
+    pub contract_address: String,
+}
+
+#[derive(Debug, Args)]
+pub struct SimulateOpts {
+    #[command(flatten)]
+    pub relayer: RelayerOpts,
+
+    #[arg(
+        long,
+        value_name = "TRANSACTION",
+        help = "Transaction to evaluate as returned by the orchestrator, either inline JSON or a path to a JSON file"
+    )]
+    pub transaction: String,
+}
+
+#[derive(Debug, Args)]
+pub struct BenchOpts {
+    #[command(flatten)]
+    pub relayer: RelayerOpts,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "JSON file holding the captured queue, an array of transactions as returned by the orchestrator"
+    )]
+    pub queue: PathBuf,
+
+    #[arg(
+        long,
+        default_value = "1",
+        value_name = "N",
+        value_delimiter = ',',
+        help = "Transactions evaluated at once, a comma separated list to replay the queue at each concurrency in turn"
+    )]
+    pub concurrency: Vec<usize>,
+
+    #[arg(
+        long,
+        default_value = "1",
+        value_name = "ROUNDS",
+        help = "Times the queue is replayed at each concurrency"
+    )]
+    pub rounds: usize,
+
+    #[arg(
+        long,
+        help = "Print one JSON object per concurrency instead of a table"
+    )]
+    pub json: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct VerifyAuditOpts {
+    #[arg(long, value_name = "PATH", help = "Audit log to verify")]
+    pub audit_log: PathBuf,
+
+    #[arg(
+        long,
+        value_name = "ADDRESS",
+        help = "Fail unless the log is sealed up to its last entry and every seal was signed by this address, may be provided multiple times"
+    )]
+    pub signer: Vec<Address>,
+
+    #[arg(long, help = "Print the summary as JSON")]
+    pub json: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct RelayOneOpts {
+    #[command(flatten)]
+    pub relayer: RelayerOpts,
+
+    #[command(flatten)]
+    pub input: TransactionInput,
+
+    #[arg(
+        long,
+        value_name = "GAS",
+        help = "Gas limit of the relay, twice the estimate by default"
+    )]
+    pub gas_limit: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "WEI",
+        help = "Max fee per gas of the relay, chosen as for any relay by default"
+    )]
+    pub max_fee_per_gas: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "WEI",
+        help = "Max priority fee per gas of the relay, chosen as for any relay by default"
+    )]
+    pub max_priority_fee_per_gas: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "NONCE",
+        help = "Nonce of the relay, to replace a stuck transaction of the relayer account. The next nonce by default"
+    )]
+    pub nonce: Option<u64>,
+}
+
+#[derive(Debug, Args)]
+pub struct HistoryOpts {
+    #[arg(long, value_name = "PATH", help = "Relay history database")]
+    pub history_db: PathBuf,
+
+    #[arg(
+        long,
+        value_name = "ID",
+        help = "Only relays of this gasless transaction"
+    )]
+    pub tx: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "ADDRESS",
+        value_parser = parse_user_address,
+        help = "Only relays of transactions signed by this user, given by EVM or althea bech32 address"
+    )]
+    pub user: Option<Address>,
+
+    #[arg(
+        long,
+        value_name = "ADDRESS",
+        help = "Only relays tipping in this token"
+    )]
+    pub token: Option<Address>,
+
+    #[arg(
+        long,
+        value_name = "OUTCOME",
+        help = "Only this outcome: included, final, reorged, already_relayed, failed or skipped"
+    )]
+    pub outcome: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "REASON",
+        value_parser = SkipReason::from_str,
+        help = "Only transactions not relayed for this reason, such as unprofitable or no_tip"
+    )]
+    pub skip_reason: Option<SkipReason>,
+
+    #[arg(long, value_name = "DATE", value_parser = parse_date, help = "Only relays at or after this date, YYYY-MM-DD or a unix timestamp")]
+    pub since: Option<u64>,
+
+    #[arg(long, value_name = "DATE", value_parser = parse_date, help = "Only relays before this date, YYYY-MM-DD or a unix timestamp")]
+    pub until: Option<u64>,
+
+    #[arg(long, value_name = "N", help = "Show at most this many relays")]
+    pub limit: Option<u32>,
+
+    #[arg(long, help = "Print one JSON object per line instead of a table")]
+    pub json: bool,
+
+    #[arg(
+        long,
+        help = "Show users by their althea bech32 address too, JSON output always includes it"
+    )]
+    pub bech32: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ScanOpts {
+    #[arg(
+        long,
+        alias = "eth-rpc",
+        default_value = "https://rpc.althea.zone:8545",
+        value_name = "ALTHEA_EVM_RPC",
+        help = "RPC endpoint to scan, an archive node is needed for receipts of old blocks on some chains"
+    )]
+    pub alhtea_evm_rpc: String,
+
+    #[arg(
+        long,
+        default_value = "0xd263DC98dEc57828e26F69bA8687281BA5D052E0",
+        value_name = "CONTRACT_ADDRESS"
+    )]
+    pub contract_address: String,
+
+    #[arg(
+        long,
+        value_name = "SIGNATURE",
+        help = "Relay function of the dex, as for run"
+    )]
+    pub relay_function: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "FIELDS",
+        value_delimiter = ',',
+        help = "Order of the words of the tip, as for run"
+    )]
+    pub tip_layout: Option<Vec<String>>,
+
+    #[arg(long, value_name = "BLOCK", help = "First block to scan")]
+    pub from_block: u64,
+
+    #[arg(
+        long,
+        value_name = "BLOCK",
+        help = "Last block to scan, the latest block by default"
+    )]
+    pub to_block: Option<u64>,
+
+    #[arg(
+        long,
+        default_value = "30",
+        value_name = "SECONDS",
+        help = "Timeout of each RPC request"
+    )]
+    pub timeout: u64,
+
+    #[arg(long, help = "Print one JSON object per relayer instead of a table")]
+    pub json: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ExportOpts {
+    #[arg(long, value_name = "PATH", help = "Relay history database")]
+    pub history_db: PathBuf,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "relays",
+        help = "Every relay outcome, or tips earned and gas paid per tip token"
+    )]
+    pub report: ExportReport,
+
+    #[arg(long, value_enum, default_value = "csv")]
+    pub format: ExportFormat,
+
+    #[arg(long, value_name = "DATE", value_parser = parse_date, help = "Only relays at or after this date, YYYY-MM-DD or a unix timestamp")]
+    pub since: Option<u64>,
+
+    #[arg(long, value_name = "DATE", value_parser = parse_date, help = "Only relays before this date, YYYY-MM-DD or a unix timestamp")]
+    pub until: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "File to write the export to instead of stdout"
+    )]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct MockOrchestratorOpts {
+    #[arg(
+        long,
+        default_value = "127.0.0.1:8090",
+        value_name = "ADDRESS:PORT",
+        help = "Address to serve the orchestrator API on"
+    )]
+    pub bind: String,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "JSON file holding a transaction or an array of them to serve, may be provided multiple times"
+    )]
+    pub transactions: Vec<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "KEY",
+        help = "Reject requests that don't carry this key in the X-Api-Key header"
+    )]
+    pub api_key: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "PRIVATE_KEY",
+        help = "Sign the pending queue with this key, for relayers requiring signed responses"
+    )]
+    pub signing_key: Option<SecretString>,
+
+    #[arg(
+        long,
+        default_value = "info",
+        value_name = "LOG_LEVEL",
+        help = "Set the logging level (e.g., info, debug, error)"
+    )]
+    pub log_level: String,
+}
+
+/// Orchestrator polled when no source of pending transactions is configured
+const DEFAULT_TRANSACTION_API_URL: &str = "https://althea.link:8443";
+
+const AGREE_HINT: &str = "Pass --agree to agree to these terms, with --state-dir set the acceptance is recorded so later runs don't need it until the terms change.";
+
+#[actix_rt::main]
+async fn main() {
+    CryptoProvider::install_default(rustls::crypto::aws_lc_rs::default_provider()).unwrap();
+    unsafe {
+        openssl_probe::init_openssl_env_vars();
+    }
+
+    match parse_cli() {
+        Cli::Run(opts) => run(opts).await,
+        Cli::Check(opts) => check(opts).await,
+        Cli::Decode(opts) => decode(opts),
+        Cli::Simulate(opts) => simulate(opts).await,
+        Cli::RelayOne(opts) => relay_manually(opts).await,
+        Cli::History(opts) => history(opts),
+        Cli::Export(opts) => export(opts),
+        Cli::Scan(opts) => scan(opts).await,
+        Cli::MockOrchestrator(opts) => mock_orchestrator(opts).await,
+        Cli::Bench(opts) => bench(opts).await,
+        Cli::VerifyAudit(opts) => verify_audit(opts),
+    }
+}
+
+/// Parses the command line, invocations without a subcommand predate subcommands and are treated as `run`
+fn parse_cli() -> Cli {
+    match std::env::args().nth(1).as_deref() {
+        None
+        | Some(
+            "run" | "check" | "decode" | "simulate" | "relay-one" | "history" | "export" | "scan"
+            | "mock-orchestrator" | "bench" | "verify-audit" | "help" | "-h" | "--help",
+        ) => Cli::parse(),
+        Some(_) => Cli::Run(LegacyCli::parse().opts),
+    }
+}
+
+async fn run(opts: RelayerOpts) {
+    if !agreed_to_terms(&opts) {
+        return;
+    }
+    let log_file = opts
+        .log_file
+        .clone()
+        .or_else(|| opts.service.then(default_log_file).flatten());
+    if let Some(path) = &log_file {
+        set_log_file(path).expect("Failed to open log file");
+    }
+    // Initialize with specific logging level
+    init_logging(&opts.log_level, opts.log_format);
+    if opts.service {
+        start_service().expect("Failed to run as a service");
+    }
+    let pid_file = opts
+        .pid_file
+        .as_deref()
+        .map(|path| PidFile::create(path).expect("Invalid PID file"));
+    let tracer_provider = opts
+        .otlp_endpoint
+        .as_ref()
+        .map(|endpoint| init_tracing(endpoint).expect("Failed to set up OTLP tracing"));
+    listen_for_signals();
+    listen_for_reload_signal();
+
+    // the command line is kept as is so that settings removed from the config file revert to it on reload
+    let mut cli_opts = opts;
+    let mut opts = apply_settings(&cli_opts).expect("Invalid config file");
+    // keys are only needed to build the signers, reloads don't rebuild them
+    cli_opts.private_key.clear();
+    #[cfg(feature = "testing")]
+    althea_l1_relayer::chaos::set_chaos(opts.chaos_config.as_deref().map(|path| {
+        althea_l1_relayer::chaos::load_chaos_config(path).expect("Invalid chaos config")
+    }));
+    set_proxy(build_proxy(&opts));
+    set_http_pool(build_http_pool(&opts));
+    set_tls_trust(build_tls_trust(&opts)).expect("Invalid TLS configuration");
+    set_outbound_limits(build_outbound_limits(&opts));
+    set_backoff_policies(&opts);
+    if let Some(policy) = build_report_policy(&opts) {
+        init_error_reporting(policy);
+    }
+    let signers = build_signers(&mut opts);
+    // orchestrators always see the same relayer identity regardless of which account relays
+    let signer = signers.primary().clone();
+    let orchestrators = build_orchestrators(&opts).expect("Invalid orchestrator configuration");
+
+    info!("Starting Ambient transaction relayer");
+    info!("Orchestrator URLs: {:?}", opts.transaction_api_url);
+    if !opts.transaction_file.is_empty() {
+        info!("Transaction files: {:?}", opts.transaction_file);
+    }
+    if opts.transaction_stdin {
+        info!("Reading transactions from stdin");
+    }
+    if opts.submit_bind.is_some() {
+        let mut names = HashSet::new();
+        for entry in &opts.submit_key {
+            let key = SubmitKey::parse(entry).expect("Invalid submit key");
+            if !names.insert(key.name.clone()) {
+                panic!("Submit key {} is configured more than once", key.name);
+            }
+        }
+        if names.is_empty() {
+            error!("--submit-bind needs at least one --submit-key");
+            std::process::exit(1);
+        }
+    }
+    info!("Poll interval: {} seconds", opts.poll_interval);
+    for signer in signers.signers() {
+        info!("Relayer address: {}", signer.address());
+    }
+    if opts.observe.is_some() {
+        set_observing(true);
+        info!(
+            "Observer mode enabled, recording what relays would have earned without broadcasting them"
+        );
+    } else if opts.dry_run {
+        info!("Dry run mode enabled, no transactions will be broadcast");
+    }
+    if let Some(gwei) = opts.max_gas_price_gwei {
+        info!("Gas price ceiling: {gwei} gwei");
+    }
+    set_retry_policy(RetryPolicy {
+        base_delay: Duration::from_secs(opts.retry_base_delay),
+        max_attempts: opts.max_retry_attempts,
+        failure_cooldown: Duration::from_secs(opts.failure_cooldown),
+        max_identical_failures: opts.max_identical_failures,
+    });
+    set_margin_policy(opts.adaptive_margin.then(|| MarginPolicy {
+        step_percent: opts.adaptive_margin_step,
+        max_extra_percent: opts.adaptive_margin_max,
+        relax_after: opts.adaptive_margin_relax_after.max(1),
+    }));
+    set_breaker_policy(
+        (opts.orchestrator_failure_threshold > 0).then(|| BreakerPolicy {
+            failure_threshold: opts.orchestrator_failure_threshold,
+            open_duration: Duration::from_secs(opts.orchestrator_open_duration),
+        }),
+    );
+    set_max_demotion(opts.max_demotion);
+    set_max_txs_per_cycle(opts.max_txs_per_cycle);
+    set_latency_slo(opts.latency_slo_secs.map(|secs| LatencySlo {
+        target: Duration::from_secs(secs),
+        min_profit: opts.latency_slo_min_profit_wei.into(),
+    }));
+    set_evaluation_order(opts.evaluation_order);
+    set_max_strikes(Some(opts.ban_after_strikes));
+    set_show_bech32(opts.show_bech32);
+    set_estimate_cache_ttl(Duration::from_secs(opts.gas_estimate_cache_secs));
+    set_rate_limit(opts.max_relays_per_user.map(|max_relays| RateLimit {
+        max_relays,
+        window: Duration::from_secs(opts.rate_limit_window),
+    }));
+    set_simulation_budget(opts.max_failed_simulations_per_user.map(|max_failures| {
+        SimulationBudget {
+            max_failures,
+            window: Duration::from_secs(opts.simulation_budget_window),
+        }
+    }));
+    set_funds_check(opts.check_user_funds);
+    set_relay_limits(build_relay_limits(&opts).expect("Invalid relay limits"));
+    set_alert_policy(build_alert_policy(&opts));
+    set_gas_oracle(build_gas_oracle(&opts));
+    set_congestion_policy(build_congestion_policy(&opts).expect("Invalid congestion guard"));
+    set_fee_band(build_fee_band(&opts));
+    set_spend_caps(build_spend_caps(&opts));
+    set_subsidy_budget(build_subsidy_budget(&opts)).expect("Invalid subsidy ledger");
+    set_protocol_policy(build_protocol_policy(&opts));
+    set_sweep_policy(build_sweep_policy(&opts).expect("Invalid sweep configuration"));
+    set_split_policy(build_split_policy(&opts).expect("Invalid tip split configuration"));
+    set_lease_policy(build_lease_policy(&opts).expect("Invalid lease configuration"));
+    set_leader_election(
+        opts.leader_election
+            .then(|| Duration::from_secs(opts.poll_interval.max(1))),
+    );
+    set_urgency_policy(opts.urgent_window_secs.map(|secs| {
+        UrgencyPolicy {
+            window: Duration::from_secs(secs),
+            fee_bump_percent: opts.urgent_fee_bump_percent,
+            max_priority_fee: opts
+                .urgent_max_priority_fee_gwei
+                .map(|gwei| Uint256::from((gwei * 1e9) as u128)),
+        }
+    }));
+    set_gas_model(opts.gas_model_samples.map(|min_samples| GasModelPolicy {
+        min_samples,
+        tolerance_percent: opts.gas_model_tolerance_percent,
+    }));
+    set_kill_switch(
+        opts.kill_switch
+            .as_deref()
+            .map(|location| KillSwitch::new(location, opts.service_timeout(None))),
+        opts.kill_switch_fail_open,
+    );
+    set_code_watch(opts.code_check_interval.map(|secs| CodeWatch {
+        interval: Duration::from_secs(secs),
+        expected: opts.expected_code_hash.iter().copied().collect(),
+    }));
+    set_maintenance_windows(
+        opts.maintenance_window
+            .iter()
+            .map(|window| MaintenanceWindow::from_str(window).expect("Invalid maintenance window"))
+            .collect(),
+    );
+    set_access_lists(opts.access_lists);
+    set_mempool_source(opts.watch_mempool);
+    set_conditional_polling(!opts.unconditional_polling);
+    set_incremental_fetch(
+        opts.incremental_fetch
+            .then(|| Duration::from_secs(opts.full_fetch_interval)),
+    );
+    let summary_policy = (opts.summary || opts.summary_webhook.is_some()).then(|| SummaryPolicy {
+        interval: Duration::from_secs(opts.summary_interval.max(1)),
+        webhook: opts.summary_webhook.clone(),
+        timeout: opts.service_timeout(None),
+    });
+    set_summary_policy(summary_policy.clone());
+    init_status(
+        signer.address().to_string(),
+        (opts.poll_interval * 10).max(300),
+    );
+
+    let mut chains = Vec::new();
+    for ctx in build_chains(&opts, &signers).await {
+        verify_chain_id(&ctx, &opts)
+            .await
+            .expect("Refusing to start");
+        let addresses: Vec<String> = ctx
+            .signers
+            .signers()
+            .iter()
+            .map(|s| s.address().to_string())
+            .collect();
+        info!(
+            "Relaying on chain {} ({}) via {} to contract {} from {}",
+            ctx.name,
+            ctx.chain_id,
+            ctx.rpc.urls().join(", "),
+            ctx.contract_address,
+            addresses.join(", ")
+        );
+        init_chain_status(ctx.chain_id, ctx.name.clone(), addresses);
+        update_chain_status(&ctx).await;
+        if let Some(chain) = get_status().chains.get(&ctx.chain_id) {
+            for wallet in &chain.wallets {
+                match wallet.balance {
+                    Some(balance) => info!(
+                        "Relayer balance of {} on {}: {} ALTHEA",
+                        wallet.address,
+                        ctx.name,
+                        balance.to_u128().unwrap_or(u128::MAX) as f64 / 1e18
+                    ),
+                    None => warn!(
+                        "Failed to get relayer balance of {} on {}",
+                        wallet.address, ctx.name
+                    ),
+                }
+            }
+            if let Some(total) = chain.wallet_balance {
+                info!(
+                    "Pooled relayer balance on {}: {} ALTHEA",
+                    ctx.name,
+                    total.to_u128().unwrap_or(u128::MAX) as f64 / 1e18
+                );
+            }
+        }
+        chains.push(ctx);
+    }
+
+    if !opts.skip_preflight {
+        let failures: Vec<PreflightCheck> =
+            preflight(&opts, &chains, &orchestrators, &signer, false)
+                .await
+                .into_iter()
+                .filter(|result| result.verdict != Verdict::Pass)
+                .collect();
+        for result in &failures {
+            match result.verdict {
+                Verdict::Fail => error!("Preflight check failed: {}", result.what),
+                _ => info!("Preflight check skipped: {}", result.what),
+            }
+        }
+        if failures
+            .iter()
+            .any(|result| result.verdict == Verdict::Fail)
+        {
+            error!("Refusing to start, fix the failed checks above or pass --skip-preflight");
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(bind) = &opts.status_bind {
+        start_status_server(bind).expect("Failed to start status server");
+    }
+    if let Some(bind) = &opts.admin_bind {
+        start_admin_server(bind).expect("Failed to start admin server");
+    }
+    let quote_requests = opts.quote_bind.as_ref().map(|bind| {
+        start_quote_server(bind, opts.quote_rate_limit).expect("Failed to start quote server")
+    });
+    if let Some(path) = &opts.history_db {
+        open_history(path).expect("Failed to open history database");
+        // balances of tokens earned in earlier runs show on the dashboard from the start
+        for (chain_id, token) in earned_tokens().unwrap_or_default() {
+            if let Ok(token) = Address::from_str(&token) {
+                track_token(chain_id, token);
+            }
+        }
+    }
+    if let Some(path) = &opts.audit_log {
+        open_audit_log(path).expect("Failed to open audit log");
+    }
+    if let Some(path) = &opts.ban_list {
+        open_ban_list(path).expect("Invalid ban list");
+    }
+    if let Some(path) = &opts.relay_store {
+        restore_relays(&chains, &orchestrators, path);
+    }
+    for ctx in &chains {
+        resync_nonces(ctx).await;
+    }
+    if let Some(policy) = summary_policy {
+        actix_rt::spawn(report_summaries(policy));
+    }
+    set_registration_policy(build_registration_policy(&opts, &chains));
+    if opts.leader_election {
+        actix_rt::spawn(leader_election_loop(signer.address()));
+    }
+    if let Some(url) = &opts.tendermint_rpc {
+        actix_rt::spawn(tendermint_subscribe_loop(
+            url.clone(),
+            Duration::from_secs(opts.timeout),
+        ));
+    }
+    info!("Waiting for transactions to relay...");
+
+    // when subscribing each orchestrator gets a background task pushing transactions into this channel
+    let (sender, mut receiver) = unbounded();
+    let orchestrators = start_orchestrators(orchestrators, &opts, &signer, &sender);
+
+    // submitted relays are tracked alongside the relay loop, including while paused, and any still pending
+    // once it stops are seen through before exiting
+    let relaying = Cell::new(true);
+    let relay_loop = async {
+        relay_loop(
+            &chains,
+            &signer,
+            &cli_opts,
+            &opts,
+            orchestrators,
+            &sender,
+            &mut receiver,
+        )
+        .await;
+        relaying.set(false);
+    };
+    let tracker = track_relays(&chains, || !relaying.get());
+    let quotes = async {
+        match quote_requests {
+            Some(requests) => serve_quotes(&chains, requests).await,
+            None => std::future::pending().await,
+        }
+    };
+    let shutdown_deadline = async {
+        wait_for_shutdown().await;
+        actix_rt::time::sleep(Duration::from_secs(opts.shutdown_timeout)).await;
+    };
+    tokio::select! {
+        _ = join(relay_loop, tracker) => info!("Relay loop stopped"),
+        _ = quotes => {}
+        _ = shutdown_deadline => warn!("Shutdown timeout reached, abandoning in-flight work"),
+    }
+    for tx in get_status().in_flight {
+        warn!(
+            "Transaction {} relaying {} was still unconfirmed at shutdown",
+            tx.tx_hash, tx.id
+        );
+    }
+    seal_audit_log(&signer).await;
+    release_account_leases().await;
+    log_observer_summary();
+    finish_registrations(opts.service_timeout(None)).await;
+    flush_alerts(opts.service_timeout(None)).await;
+    flush_error_reports(opts.service_timeout(None)).await;
+    if let Some(provider) = tracer_provider
+        && let Err(e) = provider.shutdown()
+    {
+        warn!("Failed to flush traces: {e}");
+    }
+    info!("Shutdown complete");
+    drop(pid_file);
+    report_service_stopped();
+    if opts.once {
+        std::process::exit(once_exit_code(&opts));
+    }
+}
+
+/// Exit code of a `--once` run, 1 if any orchestrator could not be polled, otherwise 2 if any relay failed
+fn once_exit_code(opts: &RelayerOpts) -> i32 {
+    let polled = get_status().last_successful_poll;
+    if let Some(url) = opts
+        .transaction_api_url
+        .iter()
+        .find(|url| !polled.contains_key(*url))
+    {
+        error!("Orchestrator {url} could not be polled");
+        return 1;
+    }
+    let failed = relay_stats().failed;
+    if failed > 0 {
+        error!("{failed} relays failed");
+        return 2;
+    }
+    0
+}
+
+/// Checks that the configuration is valid, every RPC endpoint is reachable and on the expected chain, every
+/// relayer account is funded and every orchestrator responds, exiting with an error if anything is wrong
+async fn check(opts: RelayerOpts) {
+    init_logging(&opts.log_level, opts.log_format);
+    let mut opts = apply_settings(&opts).expect("Invalid config file");
+    set_proxy(build_proxy(&opts));
+    set_http_pool(build_http_pool(&opts));
+    set_tls_trust(build_tls_trust(&opts)).expect("Invalid TLS configuration");
+    set_outbound_limits(build_outbound_limits(&opts));
+    set_backoff_policies(&opts);
+    let signers = build_signers(&mut opts);
+    let signer = signers.primary().clone();
+    let orchestrators = build_orchestrators(&opts).expect("Invalid orchestrator configuration");
+    let chains = build_chains(&opts, &signers).await;
+    let results = preflight(&opts, &chains, &orchestrators, &signer, true).await;
+    for result in &results {
+        println!("{result}");
+    }
+    let failures = results
+        .iter()
+        .filter(|result| result.verdict == Verdict::Fail)
+        .count();
+    if failures > 0 {
+        println!("{failures} checks failed");
+        std::process::exit(1);
+    }
+    println!("All checks passed");
+}
+
+/// Runs the preflight checks of every chain and orchestrator, with `poll` the orchestrators' queues are fetched
+/// rather than only resolved
+async fn preflight(
+    opts: &RelayerOpts,
+    chains: &[RelayContext],
+    orchestrators: &[Orchestrator],
+    signer: &Signer,
+    poll: bool,
+) -> Vec<PreflightCheck> {
+    let checks = ChainChecks {
+        rpc_timeout: opts.service_timeout(opts.rpc_timeout),
+        // balances only matter if relays are broadcast
+        reserve_balance: (opts.observe.is_none() && !opts.dry_run)
+            .then(|| opts.reserve_balance_wei.into()),
+        price_token: opts
+            .check_price_token
+            .as_ref()
+            .map(|address| Address::from_str(address).expect("Invalid price check token")),
+    };
+    let mut results = Vec::new();
+    for ctx in chains {
+        results.extend(check_chain(ctx, &checks).await);
+    }
+    results.extend(check_orchestrators(orchestrators, signer, poll).await);
+    results
+}
+
+/// Prints the relays in the history database that match the given filters
+fn history(opts: HistoryOpts) {
+    let conn = open_database(&opts.history_db).expect("Failed to open history database");
+    let records = query_history(
+        &conn,
+        &HistoryFilter {
+            sig_hash: opts.tx,
+            user: opts.user.map(|user| user.to_string()),
+            tip_token: opts.token.map(|token| token.to_string()),
+            outcome: opts.outcome,
+            skip_reason: opts.skip_reason.map(|reason| reason.to_string()),
+            since: opts.since,
+            until: opts.until,
+            limit: opts.limit,
+        },
+    )
+    .expect("Failed to query history database");
+    for record in records {
+        if opts.json {
+            println!("{}", serde_json::to_string(&record).unwrap());
+            continue;
+        }
+        let optional = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".to_string());
+        let user = match (&record.user_bech32, opts.bech32) {
+            (Some(bech32), true) => format!("{} ({bech32})", optional(&record.user)),
+            _ => optional(&record.user),
+        };
+        println!(
+            "{} chain {} {} {} user {} tip {} of {}{} gas {} tx {}{}",
+            format_timestamp(record.timestamp),
+            record.chain_id,
+            match &record.skip_reason {
+                Some(reason) => format!("{} ({reason})", record.outcome),
+                None => record.outcome.clone(),
+            },
+            record.sig_hash,
+            user,
+            optional(&record.tip_amount),
+            match (&record.tip_symbol, &record.tip_token) {
+                (Some(symbol), Some(token)) => format!("{symbol} ({token})"),
+                _ => optional(&record.tip_token),
+            },
+            record
+                .tip_value
+                .as_ref()
+                .map(|value| format!(" worth {value}"))
+                .unwrap_or_default(),
+            optional(&record.gas_paid),
+            optional(&record.tx_hash),
+            record
+                .error
+                .map(|e| format!(" error {e}"))
+                .unwrap_or_default()
+        );
+    }
+}
+
+/// Serves the transactions in the given files over the orchestrator protocol until shutdown
+async fn mock_orchestrator(opts: MockOrchestratorOpts) {
+    init_logging(&opts.log_level, LogFormat::Text);
+    listen_for_signals();
+    let mut txs = Vec::new();
+    for path in &opts.transactions {
+        let json = std::fs::read_to_string(path).expect("Failed to read transactions");
+        txs.extend(parse_mock_transactions(&json).expect("Invalid transactions"));
+    }
+    info!("Serving {} transactions", txs.len());
+    let signing_key = opts
+        .signing_key
+        .map(|key| key.to_key().expect("Invalid signing key"));
+    start_mock_orchestrator(&opts.bind, txs, opts.api_key, signing_key)
+        .expect("Failed to start mock orchestrator");
+    wait_for_shutdown().await;
+}
+
+/// Writes the relay history or its per token summary to a file or stdout
+fn export(opts: ExportOpts) {
+    let conn = open_database(&opts.history_db).expect("Failed to open history database");
+    let export =
+        althea_l1_relayer::history::export(&conn, opts.since, opts.until, opts.report, opts.format)
+            .expect("Failed to query history database");
+    match &opts.output {
+        Some(path) => std::fs::write(path, export).expect("Failed to write export"),
+        None => print!("{export}"),
+    }
+}
+
+async fn scan(opts: ScanOpts) {
+    let timeout = Duration::from_secs(opts.timeout);
+    let url = opts.alhtea_evm_rpc;
+    let dex = Address::from_str(&opts.contract_address).expect("Invalid contract address");
+    let layout = ContractLayout::new(
+        opts.relay_function.as_deref(),
+        opts.tip_layout.as_deref(),
+        None,
+        None,
+    )
+    .expect("Invalid contract layout");
+    let to = match opts.to_block {
+        Some(block) => block,
+        None => Web3::new(&url, timeout)
+            .eth_block_number()
+            .await
+            .expect("Failed to get the latest block")
+            .to_u64()
+            .expect("Invalid block number"),
+    };
+    info!(
+        "Scanning blocks {} to {to} for {} calls to {dex}",
+        opts.from_block, layout.relay_function
+    );
+    let stats = scan_relays(&url, timeout, dex, &layout, opts.from_block, to)
+        .await
+        .expect("Failed to scan blocks");
+    for relayer in stats {
+        if opts.json {
+            println!("{}", serde_json::to_string(&relayer).unwrap());
+        } else {
+            println!("{relayer}");
+        }
+    }
+}
+
+/// Prints every field of a gasless transaction in human readable form
+fn decode(opts: DecodeOpts) {
+    let tx = opts.input.read();
+    let dex = Address::from_str(&opts.contract_address).expect("Invalid contract address");
+    print!("{}", decode_transaction(&tx, dex));
+}
+
+/// Runs one transaction through every relay check against the configured chains, without claiming or broadcasting it
+async fn simulate(opts: SimulateOpts) {
+    let SimulateOpts {
+        relayer: opts,
+        transaction,
+    } = opts;
+    init_logging(&opts.log_level, opts.log_format);
+    let mut opts = apply_settings(&opts).expect("Invalid config file");
+    opts.dry_run = true;
+    opts.claim = false;
+    set_rate_limit(None);
+    set_relay_limits(build_relay_limits(&opts).expect("Invalid relay limits"));
+    set_proxy(build_proxy(&opts));
+    set_http_pool(build_http_pool(&opts));
+    set_tls_trust(build_tls_trust(&opts)).expect("Invalid TLS configuration");
+    let tx = read_transaction(&transaction);
+    println!("Simulating {}", tx.id());
+
+    let signers = build_signers(&mut opts);
+    let chains = build_chains(&opts, &signers).await;
+    let Some(ctx) = chains.iter().find(|ctx| ctx.chain_id == tx.chain_id) else {
+        println!("Not configured for chain {}", tx.chain_id);
+        std::process::exit(1);
+    };
+    match evaluate_transaction(ctx, &tx).await {
+        Ok(relay) => {
+            println!(
+                "Would relay on {} from {}",
+                ctx.name,
+                relay.signer.address()
+            );
+            println!("gas used:    {}", relay.gas_used);
+            println!("gas price:   {}", relay.gas_price);
+            println!("tip:         {} of {}", relay.tip_amount, relay.tip_token);
+            println!("batchable:   {}", relay.batchable);
+        }
+        Err(RelayerError::Skipped(reason)) => {
+            println!("Would not relay: {reason}, see the log above for details")
+        }
+        Err(e) => {
+            println!("Would not relay: {e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Replays the queue at each concurrency in turn, evaluating every transaction as `simulate` does, and prints the
+/// throughput, latencies and RPC requests of each replay
+async fn bench(opts: BenchOpts) {
+    let BenchOpts {
+        relayer: opts,
+        queue,
+        concurrency,
+        rounds,
+        json,
+    } = opts;
+    init_logging(&opts.log_level, opts.log_format);
+    let mut opts = apply_settings(&opts).expect("Invalid config file");
+    opts.dry_run = true;
+    opts.claim = false;
+    // RPC requests are counted by the bridge
+    opts.pool_rpc_connections = true;
+    set_rate_limit(None);
+    set_relay_limits(build_relay_limits(&opts).expect("Invalid relay limits"));
+    set_proxy(build_proxy(&opts));
+    set_http_pool(build_http_pool(&opts));
+    set_tls_trust(build_tls_trust(&opts)).expect("Invalid TLS configuration");
+    set_estimate_cache_ttl(Duration::from_secs(opts.gas_estimate_cache_secs));
+    set_funds_check(opts.check_user_funds);
+    start_recording().expect("Failed to start recording the pipeline");
+    let json_queue = std::fs::read_to_string(&queue).expect("Failed to read the queue");
+    let txs = match serde_json::from_str::<Vec<GaslessTransaction>>(&json_queue) {
+        Ok(txs) => txs,
+        Err(_) => vec![serde_json::from_str(&json_queue).expect("Invalid queue")],
+    };
+    info!(
+        "Replaying {} transactions from {}",
+        txs.len(),
+        queue.display()
+    );
+
+    let signers = build_signers(&mut opts);
+    let chains = build_chains(&opts, &signers).await;
+    for concurrency in concurrency {
+        let report = run_bench(&chains, &txs, concurrency, rounds.max(1)).await;
+        if json {
+            println!("{}", serde_json::to_string(&report).unwrap());
+        } else {
+            print!("{report}");
+        }
+    }
+}
+
+/// Checks an audit log, exiting with an error if it was tampered with or sealed by an unexpected key
+fn verify_audit(opts: VerifyAuditOpts) {
+    let summary = match verify_audit_log(&opts.audit_log) {
+        Ok(summary) => summary,
+        Err(e) => {
+            println!("Audit log {} is invalid: {e}", opts.audit_log.display());
+            std::process::exit(1);
+        }
+    };
+    if opts.json {
+        println!("{}", serde_json::to_string(&summary).unwrap());
+    } else {
+        println!(
+            "{} entries, {} seals, {} entries unsealed",
+            summary.entries, summary.seals, summary.unsealed
+        );
+        if let Some(seq) = summary.last_sealed {
+            println!("Sealed up to entry {seq}");
+        }
+        for signer in &summary.signers {
+            println!("Sealed by {signer}");
+        }
+        if summary.torn_line {
+            println!("Final line was only partly written and was left out");
+        }
+    }
+    if let Some(signer) = summary
+        .signers
+        .iter()
+        .find(|signer| !opts.signer.is_empty() && !opts.signer.contains(signer))
+    {
+        println!("Audit log was sealed by unexpected signer {signer}");
+        std::process::exit(1);
+    }
+    // stripping the seals must not get a log past the signer check
+    if !opts.signer.is_empty() && (summary.seals == 0 || summary.unsealed > 0) {
+        println!(
+            "Audit log isn't sealed up to its last entry, {} entries are unsealed",
+            summary.unsealed
+        );
+        std::process::exit(1);
+    }
+}
+
+/// Relays one transaction from the primary account as soon as possible with the given gas parameters, then waits for
+/// its receipt
+async fn relay_manually(opts: RelayOneOpts) {
+    let RelayOneOpts {
+        relayer: opts,
+        input,
+        gas_limit,
+        max_fee_per_gas,
+        max_priority_fee_per_gas,
+        nonce,
+    } = opts;
+    if !agreed_to_terms(&opts) {
+        return;
+    }
+    init_logging(&opts.log_level, opts.log_format);
+    let mut opts = apply_settings(&opts).expect("Invalid config file");
+    set_relay_limits(build_relay_limits(&opts).expect("Invalid relay limits"));
+    set_spend_caps(build_spend_caps(&opts));
+    set_proxy(build_proxy(&opts));
+    set_http_pool(build_http_pool(&opts));
+    set_tls_trust(build_tls_trust(&opts)).expect("Invalid TLS configuration");
+    if let Some(path) = &opts.history_db {
+        open_history(path).expect("Failed to open history database");
+    }
+    let tx = input.read();
+
+    let signers = build_signers(&mut opts);
+    let chains = build_chains(&opts, &signers).await;
+    let Some(ctx) = chains.iter().find(|ctx| ctx.chain_id == tx.chain_id) else {
+        println!("Not configured for chain {}", tx.chain_id);
+        std::process::exit(1);
+    };
+    let overrides = GasOverrides {
+        gas_limit: gas_limit.map(Uint256::from),
+        max_fee_per_gas: max_fee_per_gas.map(Uint256::from),
+        max_priority_fee_per_gas: max_priority_fee_per_gas.map(Uint256::from),
+        nonce: nonce.map(Uint256::from),
+    };
+    // the manual relay is only sent if no running instance relays from the account
+    set_lease_policy(build_lease_policy(&opts).expect("Invalid lease configuration"));
+    set_leader_election(
+        opts.leader_election
+            .then(|| Duration::from_secs(opts.poll_interval.max(1))),
+    );
+    let address = ctx.signers.primary().address();
+    acquire_leases(&chains, address).await;
+    let result = relay_one(ctx, &tx, overrides).await;
+    release_leases(address).await;
     match result {
-        Ok(pending_tx) => {
-            info!(
-                "Transaction submitted with hash, waiting: {}",
-                display_uint256_as_address(pending_tx)
-            );
-            match web3
-                .wait_for_transaction(pending_tx, web3.get_timeout(), None)
+        Ok(None) => println!("Dry run, {} was not relayed", tx.id()),
+        Ok(Some(relay)) => {
+            let hash = display_uint256_as_address(relay.tx_hash);
+            match (relay.success, relay.gas_paid) {
+                (Some(true), Some(gas_paid)) => {
+                    println!(
+                        "Relayed {} in {hash}, paying {gas_paid} wei in gas",
+                        tx.id()
+                    )
+                }
+                (Some(false), _) => {
+                    println!("Relay {hash} of {} reverted on chain", tx.id());
+                    std::process::exit(1);
+                }
+                _ => {
+                    println!(
+                        "No receipt for relay {hash} of {} within the receipt timeout, it may still be included",
+                        tx.id()
+                    );
+                    std::process::exit(1);
+                }
+            }
+        }
+        Err(e) => {
+            println!("Failed to relay {}: {e}", tx.id());
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Reads a gasless transaction given inline as JSON or as the path of a JSON file
+fn read_transaction(input: &str) -> GaslessTransaction {
+    let json = if input.trim_start().starts_with('{') {
+        input.to_string()
+    } else {
+        std::fs::read_to_string(input).expect("Failed to read transaction file")
+    };
+    serde_json::from_str(&json).expect("Invalid transaction")
+}
+
+/// Whether the operator agreed to the terms, with `--agree`, an acceptance of the current terms recorded in
+/// `--state-dir` or by confirming them on the terminal. Agreeing while a state directory is set records it there.
+fn agreed_to_terms(opts: &RelayerOpts) -> bool {
+    let Some(dir) = &opts.state_dir else {
+        if !opts.agree {
+            println!("{TERMS} {AGREE_HINT}");
+        }
+        return opts.agree;
+    };
+    match recorded_acceptance(dir).expect("Invalid terms acceptance record") {
+        Some(acceptance) if acceptance.version == TERMS_VERSION => return true,
+        Some(acceptance) if !opts.agree => println!(
+            "The terms changed since version {} was accepted, they have to be accepted again",
+            acceptance.version
+        ),
+        _ => {}
+    }
+    if !opts.agree {
+        if !std::io::stdin().is_terminal() {
+            println!("{TERMS} {AGREE_HINT}");
+            return false;
+        }
+        if !confirm_interactively().expect("Failed to read terms confirmation") {
+            println!("The terms were not agreed to");
+            return false;
+        }
+    }
+    record_acceptance(dir).expect("Failed to record terms acceptance");
+    println!(
+        "Recorded acceptance of version {TERMS_VERSION} of the terms in {}",
+        dir.display()
+    );
+    true
+}
+
+/// Every relayer account configured on the command line, plaintext keys are taken out of `opts` so they are
+/// wiped once parsed
+fn build_signers(opts: &mut RelayerOpts) -> SignerPool {
+    SignerPool::new(if !opts.private_key.is_empty() {
+        std::mem::take(&mut opts.private_key)
+            .iter()
+            .map(|key| Signer::Local(key.to_key().expect("Invalid private key")))
+            .collect()
+    } else if let Some(var) = &opts.private_key_env {
+        vec![Signer::Local(
+            load_private_key_env(var).expect("Invalid private key environment variable"),
+        )]
+    } else if !opts.private_key_file.is_empty() {
+        opts.private_key_file
+            .iter()
+            .map(|path| {
+                Signer::Local(load_private_key_file(path).expect("Invalid private key file"))
+            })
+            .collect()
+    } else if let Some(path) = &opts.keystore {
+        vec![Signer::Local(
+            load_keystore(path, opts.keystore_password_fd).expect("Failed to load keystore"),
+        )]
+    } else if let (Some(url), Some(address)) =
+        (&opts.remote_signer_url, &opts.remote_signer_address)
+    {
+        vec![Signer::Remote(RemoteSigner {
+            url: url.clone(),
+            address: Address::from_str(address).expect("Invalid remote signer address"),
+            timeout: Duration::from_secs(opts.timeout),
+        })]
+    } else if let Some(address) = &opts.observe {
+        vec![Signer::Watch(
+            Address::from_str(address).expect("Invalid observer address"),
+        )]
+    } else {
+        unreachable!("clap requires a key source")
+    })
+}
+
+/// Alerting, `None` if no sink is configured
+fn build_alert_policy(opts: &RelayerOpts) -> Option<AlertPolicy> {
+    let mut sinks = Vec::new();
+    if let Some(url) = &opts.alert_slack_webhook {
+        sinks.push(AlertSink::Slack(url.clone()));
+    }
+    if let Some(url) = &opts.alert_discord_webhook {
+        sinks.push(AlertSink::Discord(url.clone()));
+    }
+    if let (Some(bot_token), Some(chat_id)) =
+        (&opts.alert_telegram_bot_token, &opts.alert_telegram_chat_id)
+    {
+        sinks.push(AlertSink::Telegram {
+            bot_token: bot_token.clone(),
+            chat_id: chat_id.clone(),
+        });
+    }
+    (!sinks.is_empty()).then(|| AlertPolicy {
+        sinks,
+        min_balance: opts
+            .alert_min_balance
+            .map(|althea| Uint256::from((althea * 1e18) as u128)),
+        failure_threshold: opts.alert_failure_threshold,
+        loss_threshold: opts.alert_losing_relays,
+        orchestrator_down_after: Duration::from_secs(opts.alert_orchestrator_down_minutes * 60),
+        timeout: opts.service_timeout(None),
+    })
+}
+
+fn build_report_policy(opts: &RelayerOpts) -> Option<ReportPolicy> {
+    let mut sinks = Vec::new();
+    if let Some(dsn) = &opts.sentry_dsn {
+        sinks.push(ReportSink::sentry(dsn).expect("Invalid Sentry DSN"));
+    }
+    if let Some(url) = &opts.error_webhook {
+        sinks.push(ReportSink::Webhook(url.clone()));
+    }
+    (!sinks.is_empty()).then(|| ReportPolicy {
+        sinks,
+        timeout: opts.service_timeout(None),
+    })
+}
+
+fn build_protocol_policy(opts: &RelayerOpts) -> Option<ProtocolPolicy> {
+    (!opts.protocol_submitter.is_empty()).then(|| ProtocolPolicy {
+        submitters: opts
+            .protocol_submitter
+            .iter()
+            .map(|submitter| Address::from_str(submitter).expect("Invalid protocol submitter"))
+            .collect(),
+        callpaths: opts
+            .protocol_callpath
+            .iter()
+            .map(|callpath| {
+                ProtocolCallpath::from_str(callpath).expect("Invalid protocol callpath")
+            })
+            .collect(),
+    })
+}
+
+fn build_sweep_policy(opts: &RelayerOpts) -> Result<Option<SweepPolicy>, String> {
+    let Some(to) = opts.sweep_to else {
+        return Ok(None);
+    };
+    if opts.sweep_ceiling_wei.is_none() && opts.sweep_token.is_empty() {
+        return Err("--sweep-to needs --sweep-ceiling-wei or --sweep-token".to_string());
+    }
+    if let Some(ceiling) = opts.sweep_ceiling_wei
+        && ceiling <= opts.reserve_balance_wei
+    {
+        return Err(format!(
+            "--sweep-ceiling-wei {ceiling} must exceed --reserve-balance-wei {}",
+            opts.reserve_balance_wei
+        ));
+    }
+    Ok(Some(SweepPolicy {
+        to,
+        ceiling: opts.sweep_ceiling_wei.map(Uint256::from),
+        tokens: opts
+            .sweep_token
+            .iter()
+            .map(|entry| parse_sweep_token(entry))
+            .collect::<Result<_, _>>()?,
+        interval: Duration::from_secs(opts.sweep_interval_secs.max(1)),
+    }))
+}
+
+fn build_split_policy(opts: &RelayerOpts) -> Result<Option<SplitPolicy>, String> {
+    if opts.tip_split.is_empty() {
+        return Ok(None);
+    }
+    let partners = opts
+        .tip_split
+        .iter()
+        .map(|entry| parse_tip_split(entry))
+        .collect::<Result<Vec<_>, _>>()?;
+    let total: u32 = partners.iter().map(|(_, bps)| bps).sum();
+    if total > 10_000 {
+        return Err(format!(
+            "--tip-split shares add up to {}%, more than the whole tip",
+            total as f64 / 100.0
+        ));
+    }
+    Ok(Some(SplitPolicy {
+        partners,
+        interval: Duration::from_secs(opts.tip_split_interval_secs.max(1)),
+    }))
+}
+
+fn build_lease_policy(opts: &RelayerOpts) -> Result<Option<LeasePolicy>, String> {
+    let Some(location) = &opts.lease_store else {
+        return Ok(None);
+    };
+    // a lease is only trusted for half its ttl after it was renewed, that has to outlast a cycle
+    if opts.lease_ttl_secs < opts.poll_interval * 4 {
+        return Err(format!(
+            "--lease-ttl-secs {} must be at least four times --poll-interval {}",
+            opts.lease_ttl_secs, opts.poll_interval
+        ));
+    }
+    Ok(Some(LeasePolicy {
+        store: LeaseStore::parse(location)?,
+        instance: opts.instance_id.clone().unwrap_or_else(default_instance_id),
+        ttl: Duration::from_secs(opts.lease_ttl_secs),
+        timeout: opts.service_timeout(None),
+    }))
+}
+
+fn build_outbound_limits(opts: &RelayerOpts) -> OutboundLimits {
+    let ceiling = |requests: Option<u32>, per: u64| {
+        requests.map(|requests| Ceiling {
+            requests: requests.max(1),
+            per: Duration::from_secs(per),
+        })
+    };
+    OutboundLimits {
+        orchestrator: ceiling(opts.max_orchestrator_requests_per_minute, 60),
+        rpc: ceiling(opts.max_rpc_calls_per_second, 1),
+        price: ceiling(opts.max_price_lookups_per_minute, 60),
+    }
+}
+
+fn set_backoff_policies(opts: &RelayerOpts) {
+    let policy = |retries: u32, delay_ms: u64| {
+        (retries > 0).then(|| BackoffPolicy {
+            retries,
+            base_delay: Duration::from_millis(delay_ms),
+        })
+    };
+    set_backoff_policy(
+        Outbound::Orchestrator,
+        policy(opts.orchestrator_retries, opts.orchestrator_retry_delay_ms),
+    );
+    set_backoff_policy(
+        Outbound::Price,
+        policy(opts.price_retries, opts.price_retry_delay_ms),
+    );
+}
+
+fn build_spend_caps(opts: &RelayerOpts) -> Option<SpendCaps> {
+    let to_wei = |althea: f64| Uint256::from((althea * 1e18) as u128);
+    (opts.max_gas_spend_per_hour.is_some() || opts.max_gas_spend_per_day.is_some()).then(|| {
+        SpendCaps {
+            per_hour: opts.max_gas_spend_per_hour.map(to_wei),
+            per_day: opts.max_gas_spend_per_day.map(to_wei),
+        }
+    })
+}
+
+fn build_subsidy_budget(opts: &RelayerOpts) -> Option<SubsidyBudget> {
+    opts.subsidy_budget_per_day.map(|althea| SubsidyBudget {
+        per_day: Uint256::from((althea * 1e18) as u128),
+        per_user_per_day: Uint256::from(
+            (opts.subsidy_per_user_per_day.unwrap_or(althea / 10.0) * 1e18) as u128,
+        ),
+        alert_percents: opts.subsidy_alert_percent.clone(),
+        ledger: opts.subsidy_ledger.clone(),
+    })
+}
+
+fn build_fee_band(opts: &RelayerOpts) -> Option<FeeBandPolicy> {
+    for percentile in [
+        opts.optimistic_fee_percentile,
+        opts.pessimistic_fee_percentile,
+    ] {
+        if !(0.0..=100.0).contains(&percentile) {
+            panic!("Invalid fee percentile {percentile}, expected 0 to 100");
+        }
+    }
+    opts.fee_band.then(|| FeeBandPolicy {
+        blocks: opts.gas_oracle_blocks.clamp(1, 1024),
+        optimistic_percentile: opts.optimistic_fee_percentile,
+        pessimistic_percentile: opts.pessimistic_fee_percentile,
+    })
+}
+
+fn build_gas_oracle(opts: &RelayerOpts) -> Option<GasOraclePolicy> {
+    for percentile in [opts.normal_fee_percentile, opts.aggressive_fee_percentile] {
+        if !(0.0..=100.0).contains(&percentile) {
+            panic!("Invalid fee percentile {percentile}, expected 0 to 100");
+        }
+    }
+    opts.gas_oracle.then(|| GasOraclePolicy {
+        profile: opts.gas_profile,
+        blocks: opts.gas_oracle_blocks.clamp(1, 1024),
+        normal_percentile: opts.normal_fee_percentile,
+        aggressive_percentile: opts.aggressive_fee_percentile,
+        congestion_threshold: opts.congestion_percent / 100.0,
+    })
+}
+
+fn build_registration_policy(
+    opts: &RelayerOpts,
+    chains: &[RelayContext],
+) -> Option<RegistrationPolicy> {
+    opts.register.then(|| RegistrationPolicy {
+        chain_ids: chains.iter().map(|ctx| ctx.chain_id).collect(),
+        tokens: opts
+            .register_token
+            .iter()
+            .map(|token| Address::from_str(token).expect("Invalid registration token"))
+            .collect(),
+        min_tip: opts
+            .register_min_tip
+            .map(|althea| Uint256::from((althea * 1e18) as u128)),
+        heartbeat_interval: Duration::from_secs(opts.heartbeat_interval.max(1)),
+    })
+}
+
+fn build_tls_trust(opts: &RelayerOpts) -> TlsTrust {
+    TlsTrust {
+        extra_ca_certs: opts.extra_ca_cert.clone(),
+        insecure_skip_verify: opts.insecure_skip_tls_verify,
+    }
+}
+
+fn build_congestion_policy(opts: &RelayerOpts) -> Result<Option<CongestionPolicy>, String> {
+    if opts.max_block_gas_fraction.is_none() && opts.congestion_pause_threshold.is_none() {
+        return Ok(None);
+    }
+    for (flag, fraction) in [
+        ("--max-block-gas-fraction", opts.max_block_gas_fraction),
+        (
+            "--congestion-pause-threshold",
+            opts.congestion_pause_threshold,
+        ),
+    ] {
+        if let Some(fraction) = fraction
+            && !(fraction > 0.0 && fraction <= 1.0)
+        {
+            return Err(format!("{flag} {fraction} must be above 0 and at most 1"));
+        }
+    }
+    Ok(Some(CongestionPolicy {
+        max_block_gas_fraction: opts.max_block_gas_fraction,
+        pause_threshold: opts.congestion_pause_threshold,
+        blocks: opts.congestion_blocks.clamp(1, 1024),
+    }))
+}
+
+fn build_proxy(opts: &RelayerOpts) -> Option<ProxyConfig> {
+    opts.proxy_url
+        .as_ref()
+        .map(|url| ProxyConfig::from_str(url).expect("Invalid proxy url"))
+}
+
+fn build_http_pool(opts: &RelayerOpts) -> HttpPool {
+    HttpPool {
+        workers: opts.http_workers.max(1),
+        max_connections: opts.http_max_connections.max(1),
+        keep_alive: Duration::from_secs(opts.http_keep_alive_secs),
+        lifetime: Duration::from_secs(opts.http_connection_lifetime_secs),
+        pool_rpc: opts.pool_rpc_connections,
+    }
+}
+
+/// The command line with the `[settings]` section of the config file, if there is one, applied on top
+fn apply_settings(cli_opts: &RelayerOpts) -> Result<RelayerOpts, String> {
+    let mut opts = cli_opts.clone();
+    let (settings, overrides) = match &opts.config {
+        Some(path) => {
+            let config = load_config(path).map_err(|e| e.to_string())?;
+            (config.settings, config.overrides)
+        }
+        None => Default::default(),
+    };
+    if let Some(urls) = settings.transaction_api_url {
+        opts.transaction_api_url = urls;
+    }
+    if let Some(gwei) = settings.max_gas_price_gwei {
+        opts.max_gas_price_gwei = Some(gwei);
+    }
+    if let Some(multiple) = settings.over_cap_tip_multiple {
+        opts.over_cap_tip_multiple = Some(multiple);
+    }
+    if let Some(limit) = settings.max_gas_limit {
+        opts.max_gas_limit = Some(limit);
+    }
+    if let Some(commands) = settings.allowed_command {
+        opts.allowed_command = commands;
+    }
+    if let Some(margin) = settings.profit_margin_percent {
+        opts.profit_margin_percent = margin;
+    }
+    if let Some(gas) = settings.liquidation_gas {
+        opts.liquidation_gas = gas;
+    }
+    if let Some(bps) = settings.liquidation_fee_bps {
+        opts.liquidation_fee_bps = bps;
+    }
+    if let Some(reserve) = settings.reserve_balance_wei {
+        opts.reserve_balance_wei = reserve;
+    }
+    if let Some(partners) = settings.partner {
+        opts.partner = partners;
+    }
+    opts.overrides = overrides;
+    if opts.transaction_api_url.is_empty()
+        && opts.transaction_file.is_empty()
+        && !opts.transaction_stdin
+        && opts.submit_bind.is_none()
+    {
+        opts.transaction_api_url = vec![DEFAULT_TRANSACTION_API_URL.to_string()];
+    }
+    // an observer holds no key, so there is nothing it could broadcast
+    if opts.observe.is_some() {
+        opts.dry_run = true;
+    }
+    Ok(opts)
+}
+
+fn build_relay_limits(opts: &RelayerOpts) -> Result<RelayLimits, String> {
+    let mut rules = Vec::new();
+    for rule in &opts.allowed_command {
+        rules.push(CommandRule::from_str(rule).map_err(|e| e.to_string())?);
+    }
+    if opts.liquidation_fee_bps > 10_000 {
+        return Err(format!(
+            "Liquidation fee of {} basis points is above 100%",
+            opts.liquidation_fee_bps
+        ));
+    }
+    Ok(RelayLimits {
+        max_gas_price: opts
+            .max_gas_price_gwei
+            .map(|gwei| Uint256::from((gwei * 1e9) as u128)),
+        over_cap_tip_multiple: opts.over_cap_tip_multiple,
+        max_gas_limit: opts.max_gas_limit.map(Uint256::from),
+        allowed_commands: CommandFilter::new(rules),
+        profit_margin_percent: opts.profit_margin_percent,
+        liquidation_gas: opts.liquidation_gas,
+        liquidation_fee_bps: opts.liquidation_fee_bps,
+        reserve_balance: opts.reserve_balance_wei.into(),
+        max_tx_age: opts.max_tx_age_secs.map(Duration::from_secs),
+        trace_min_tip: opts.trace_min_tip_wei.map(Uint256::from),
+        partners: opts
+            .partner
+            .iter()
+            .map(|partner| {
+                Address::from_str(partner).map_err(|_| format!("Invalid partner address {partner}"))
+            })
+            .collect::<Result<_, _>>()?,
+        overrides: opts
+            .overrides
+            .iter()
+            .map(build_limit_override)
+            .collect::<Result<_, _>>()?,
+    })
+}
+
+fn build_limit_override(config: &LimitOverrideConfig) -> Result<LimitOverride, String> {
+    let tokens = |tokens: &[String]| {
+        tokens
+            .iter()
+            .map(|token| {
+                Address::from_str(token).map_err(|_| format!("Invalid tip token address {token}"))
+            })
+            .collect::<Result<HashSet<_>, _>>()
+    };
+    Ok(LimitOverride {
+        orchestrator: config.orchestrator.clone(),
+        tip_tokens: tokens(&config.tip_tokens)?,
+        profit_margin_percent: config.profit_margin_percent,
+        max_gas_limit: config.max_gas_limit.map(Uint256::from),
+        allowed_tip_tokens: config
+            .allowed_tip_tokens
+            .as_deref()
+            .map(tokens)
+            .transpose()?,
+    })
+}
+
+fn build_orchestrators(opts: &RelayerOpts) -> Result<Vec<Orchestrator>, String> {
+    set_pinned_ips(&opts.orchestrator_ip)?;
+    set_dns_cache_ttl(Duration::from_secs(opts.dns_cache_secs));
+    set_ip_preference(opts.ip_preference);
+    set_attempt_delay(Duration::from_millis(opts.connection_attempt_delay_ms));
+    let mut orchestrators = Orchestrator::from_opts(
+        &opts.transaction_api_url,
+        &opts.orchestrator_api_key,
+        &opts.orchestrator_signed_auth,
+        PendingQuery {
+            limit: opts.pending_page_size,
+            since: None,
+            chain_id: opts.pending_chain_id,
+            wait: None,
+        },
+        &OrchestratorTls {
+            client_cert: opts.orchestrator_client_cert.clone(),
+            client_key: opts.orchestrator_client_key.clone(),
+            ca_bundle: opts.orchestrator_ca_bundle.clone(),
+        },
+        opts.service_timeout(opts.orchestrator_timeout),
+        opts.orchestrator_max_response_mb * 1024 * 1024,
+    )?;
+    Orchestrator::require_signed_responses(&mut orchestrators, &opts.orchestrator_signer)?;
+    Ok(orchestrators)
+}
+
+/// An orchestrator along with the state of its subscription
+struct PolledOrchestrator {
+    orchestrator: Orchestrator,
+    /// true while the subscription stream is up, the orchestrator is only polled while it is down
+    connected: Rc<Cell<bool>>,
+    subscription: Option<JoinHandle<()>>,
+    /// keeps the relayer registered with the orchestrator if registration is enabled
+    registration: Option<JoinHandle<()>>,
+}
+
+/// Starts a subscription to or long poll of each orchestrator if either is enabled, and registers with it if registration is
+/// enabled
+fn start_orchestrators(
+    orchestrators: Vec<Orchestrator>,
+    opts: &RelayerOpts,
+    signer: &Signer,
+    sender: &UnboundedSender<(Orchestrator, GaslessTransaction)>,
+) -> Vec<PolledOrchestrator> {
+    orchestrators
+        .into_iter()
+        .map(|orchestrator| {
+            let connected = Rc::new(Cell::new(false));
+            let subscription = if opts.subscribe {
+                Some(actix_rt::spawn(subscribe_loop(
+                    orchestrator.clone(),
+                    signer.clone(),
+                    sender.clone(),
+                    connected.clone(),
+                )))
+            } else {
+                opts.long_poll.map(|wait| {
+                    actix_rt::spawn(long_poll_loop(
+                        orchestrator.clone(),
+                        signer.clone(),
+                        sender.clone(),
+                        connected.clone(),
+                        Duration::from_secs(wait),
+                        Duration::from_secs(opts.poll_interval),
+                    ))
+                })
+            };
+            let registration = registration_policy().map(|policy| {
+                actix_rt::spawn(registration_loop(
+                    orchestrator.clone(),
+                    signer.clone(),
+                    policy,
+                ))
+            });
+            PolledOrchestrator {
+                orchestrator,
+                connected,
+                subscription,
+                registration,
+            }
+        })
+        .collect()
+}
+
+/// Re-reads the reloadable settings and applies them, restarting orchestrator subscriptions if the orchestrator
+/// list changed. Invalid settings are logged and the current ones are kept.
+fn reload_settings(
+    cli_opts: &RelayerOpts,
+    opts: &RelayerOpts,
+    signer: &Signer,
+    orchestrators: &mut Vec<PolledOrchestrator>,
+    sender: &UnboundedSender<(Orchestrator, GaslessTransaction)>,
+) {
+    let reloaded = apply_settings(cli_opts).and_then(|reloaded| {
+        Ok((
+            build_relay_limits(&reloaded)?,
+            build_orchestrators(&reloaded)?,
+        ))
+    });
+    let (limits, new_orchestrators) = match reloaded {
+        Ok(reloaded) => reloaded,
+        Err(e) => {
+            error!("Failed to reload configuration, keeping the current settings: {e}");
+            return;
+        }
+    };
+    info!("Reloaded settings: {limits:?}");
+    set_relay_limits(limits);
+
+    let urls: Vec<&String> = new_orchestrators.iter().map(|o| &o.url).collect();
+    if orchestrators
+        .iter()
+        .map(|o| &o.orchestrator.url)
+        .eq(urls.iter().copied())
+    {
+        return;
+    }
+    info!("Orchestrator URLs changed to {urls:?}");
+    for old in orchestrators.drain(..) {
+        if let Some(subscription) = old.subscription {
+            subscription.abort();
+        }
+        if let Some(registration) = old.registration {
+            registration.abort();
+        }
+    }
+    *orchestrators = start_orchestrators(new_orchestrators, opts, signer, sender);
+}
+
+/// Price source selected for `chain`, CoinGecko requires an asset platform for the chain. Static prices, if any
+/// are configured, back it up.
+fn build_price_source(opts: &RelayerOpts, chain: &ChainConfig) -> Rc<dyn PriceSource> {
+    let headers = PriceHeaders::from_opts(&opts.price_api_header, &opts.price_api_bearer_token)
+        .expect("Invalid price API header");
+    let live: Rc<dyn PriceSource> = match chain.price_source.unwrap_or(opts.price_source) {
+        PriceSourceKind::Http => Rc::new(HttpPriceSource {
+            url: opts.price_api_url.clone(),
+            headers,
+            timeout: opts.service_timeout(opts.price_timeout),
+        }),
+        PriceSourceKind::Coingecko => Rc::new(CoinGeckoPriceSource {
+            url: opts.coingecko_url.trim_end_matches('/').to_string(),
+            api_key: opts.coingecko_api_key.clone(),
+            headers,
+            platform: chain
+                .coingecko_platform
+                .clone()
+                .or(opts.coingecko_platform.clone())
+                .unwrap_or_else(|| {
+                    panic!(
+                        "CoinGecko prices on chain {} need --coingecko-platform or coingecko_platform in its config",
+                        chain.name()
+                    )
+                }),
+            gas_token_id: opts.coingecko_gas_token_id.clone(),
+            timeout: opts.service_timeout(opts.price_timeout),
+            cache_duration: Duration::from_secs(opts.coingecko_cache_secs),
+        }),
+    };
+    // entries of the chain come last so they override the command line
+    let prices: HashMap<_, _> = opts
+        .static_price
+        .iter()
+        .chain(chain.static_prices.iter())
+        .map(|entry| parse_static_price(entry).expect("Invalid static price"))
+        .collect();
+    let source = if prices.is_empty() {
+        live
+    } else {
+        info!(
+            "Static prices for {} tokens on chain {}",
+            prices.len(),
+            chain.name()
+        );
+        Rc::new(StaticPriceFallback::new(
+            live,
+            prices,
+            opts.static_price_warn_after.map(Duration::from_secs),
+        ))
+    };
+    #[cfg(feature = "testing")]
+    let source = althea_l1_relayer::chaos::with_stale_prices(source);
+    source
+}
+
+/// Relay context for every chain to relay on, `signers` is used on chains that don't configure keys of their own
+async fn build_chains(opts: &RelayerOpts, signers: &SignerPool) -> Vec<RelayContext> {
+    let mut chains = Vec::new();
+    for chain in chain_configs(opts).await {
+        chains.push(RelayContext {
+            chain_id: chain.chain_id,
+            name: chain.name(),
+            rpc: RpcPool::new(
+                &chain.rpc_urls(),
+                opts.service_timeout(opts.rpc_timeout),
+                Duration::from_secs(opts.rpc_stale_after),
+                opts.rpc_max_lag_blocks,
+            ),
+            signers: match chain.private_keys() {
+                keys if keys.is_empty() || opts.observe.is_some() => signers.clone(),
+                keys => SignerPool::new(
+                    keys.iter()
+                        .map(|key| Signer::Local(key.to_key().expect("Invalid chain private key")))
+                        .collect(),
+                ),
+            },
+            tip_receivers: chain
+                .tip_receivers
+                .iter()
+                .chain(opts.tip_receiver.iter())
+                .map(|address| Address::from_str(address).expect("Invalid tip receiver"))
+                .collect(),
+            contract_address: Address::from_str(&chain.contract_address)
+                .expect("Invalid contract address"),
+            layout: ContractLayout::new(
+                chain
+                    .relay_function
+                    .as_ref()
+                    .or(opts.relay_function.as_ref())
+                    .map(String::as_str),
+                chain
+                    .tip_layout
+                    .as_ref()
+                    .or(opts.tip_layout.as_ref())
+                    .map(Vec::as_slice),
+                chain
+                    .sender_receiver
+                    .as_ref()
+                    .or(opts.sender_receiver.as_ref())
+                    .map(String::as_str),
+                chain
+                    .origin_receiver
+                    .as_ref()
+                    .or(opts.origin_receiver.as_ref())
+                    .map(String::as_str),
+            )
+            .expect("Invalid contract layout"),
+            targets: if chain.relay_targets.is_empty() {
+                &opts.relay_target
+            } else {
+                &chain.relay_targets
+            }
+            .iter()
+            .map(|target| RelayTarget::from_str(target).expect("Invalid relay target"))
+            .collect(),
+            entry_point: chain
+                .entry_point
+                .as_ref()
+                .or(opts.entry_point.as_ref())
+                .map(|address| Address::from_str(address).expect("Invalid EntryPoint address")),
+            price_source: build_price_source(opts, &chain),
+            wrapped_native_token: chain
+                .wrapped_native_token
+                .as_ref()
+                .or(opts.wrapped_native_token.as_ref())
+                .map(|address| Address::from_str(address).expect("Invalid wrapped native token")),
+            swap_quoter: chain
+                .swap_quote_address
+                .as_ref()
+                .or(opts.swap_quote_address.as_ref())
+                .map(|address| SwapQuoter {
+                    impact: Address::from_str(address).expect("Invalid swap quote address"),
+                    pool_idx: opts.swap_quote_pool_idx,
+                }),
+            profit_policy: Rc::new(MarginProfitPolicy),
+            receipt_timeout: Duration::from_secs(opts.receipt_timeout),
+            dry_run: opts.dry_run,
+            claim: opts.claim,
+            multicall: chain
+                .multicall_address
+                .as_ref()
+                .or(opts.multicall_address.as_ref())
+                .map(|address| Address::from_str(address).expect("Invalid multicall address")),
+            max_batch_size: opts.max_batch_size.max(1),
+            estimate_concurrency: opts.max_concurrent_estimates,
+            confirmation_blocks: opts.confirmation_blocks,
+            private_rpc: chain
+                .private_tx_rpc
+                .as_ref()
+                .or(opts.private_tx_rpc.as_ref())
+                .map(|url| Web3::new(&rpc_url(url), opts.service_timeout(opts.rpc_timeout))),
+            trace_rpc: chain
+                .trace_rpc
+                .as_ref()
+                .or(opts.trace_rpc.as_ref())
+                .map(|url| Web3::new(&rpc_url(url), opts.service_timeout(opts.rpc_timeout))),
+        });
+    }
+    chains
+}
+
+/// Chains to relay on, either every `[[chain]]` section of the config file or a single chain built
+/// from the command line flags whose chain id is queried from the RPC
+async fn chain_configs(opts: &RelayerOpts) -> Vec<ChainConfig> {
+    if let Some(path) = &opts.config {
+        let config = load_config(path).expect("Invalid config file");
+        if !config.chain.is_empty() {
+            return config.chain;
+        }
+    }
+    // any endpoint will do, they must all be for the same chain
+    let mut chain_id = opts.expected_chain_id;
+    if chain_id.is_none() {
+        for url in &opts.alhtea_evm_rpc {
+            match Web3::new(&rpc_url(url), opts.service_timeout(opts.rpc_timeout))
+                .eth_chainid()
                 .await
             {
-                Ok(_) => {
-                    info!("Transaction included in block, getting receipt");
-                    let receipt = web3.eth_get_transaction_receipt(pending_tx).await;
-                    info!("Receipt is {receipt:?}");
-                    Ok(Some(pending_tx))
+                Ok(id) => {
+                    chain_id = Some(id.to_u64().expect("Invalid chain id"));
+                    break;
+                }
+                Err(e) => warn!("Failed to get chain id from {url}: {e}"),
+            }
+        }
+    }
+    let (rpc_url, fallback_rpc_urls) = opts
+        .alhtea_evm_rpc
+        .split_first()
+        .expect("At least one RPC endpoint is required");
+    vec![ChainConfig {
+        name: None,
+        chain_id: chain_id.expect("Failed to get chain id from any RPC endpoint"),
+        rpc_url: rpc_url.clone(),
+        fallback_rpc_urls: fallback_rpc_urls.to_vec(),
+        contract_address: opts.contract_address.clone(),
+        private_key: None,
+        private_keys: Vec::new(),
+        multicall_address: opts.multicall_address.clone(),
+        private_tx_rpc: opts.private_tx_rpc.clone(),
+        trace_rpc: opts.trace_rpc.clone(),
+        wrapped_native_token: opts.wrapped_native_token.clone(),
+        swap_quote_address: opts.swap_quote_address.clone(),
+        tip_receivers: Vec::new(),
+        price_source: None,
+        coingecko_platform: None,
+        static_prices: Vec::new(),
+        relay_function: None,
+        tip_layout: None,
+        sender_receiver: None,
+        origin_receiver: None,
+        relay_targets: Vec::new(),
+        entry_point: None,
+    }]
+}
+
+/// Checks that every RPC endpoint of `ctx` is on the configured chain, transactions signed for one chain would
+/// only revert on another. Endpoints that can't be reached are checked once they are in use.
+async fn verify_chain_id(ctx: &RelayContext, opts: &RelayerOpts) -> Result<(), String> {
+    for url in ctx.rpc.urls() {
+        match Web3::new(&rpc_url(url), opts.service_timeout(opts.rpc_timeout))
+            .eth_chainid()
+            .await
+        {
+            Ok(id) if id.to_u64() == Some(ctx.chain_id) => {}
+            Ok(id) => {
+                return Err(format!(
+                    "RPC endpoint {url} is on chain {id}, expected {}",
+                    ctx.chain_id
+                ));
+            }
+            Err(e) => warn!("Failed to verify the chain id of {url}: {e}"),
+        }
+    }
+    Ok(())
+}
+
+/// Resumes watching the relays a previous run left in the relay store at `path`, which is kept up to date from now on
+fn restore_relays(chains: &[RelayContext], orchestrators: &[Orchestrator], path: &Path) {
+    let stored = load_relay_store(path).expect("Invalid relay store");
+    set_relay_store(Some(path.to_path_buf()));
+    for relay in stored {
+        let hash = &relay.tx_hash;
+        let Some(ctx) = chains.iter().find(|ctx| ctx.chain_id == relay.chain_id) else {
+            warn!(
+                "Not resuming relay {hash} of {}, chain {} is not configured",
+                relay.sig_hash, relay.chain_id
+            );
+            continue;
+        };
+        let Some(signer) = ctx.signers.find(relay.signer) else {
+            warn!(
+                "Not resuming relay {hash} of {}, account {} is not configured",
+                relay.sig_hash, relay.signer
+            );
+            continue;
+        };
+        let orchestrator = relay
+            .orchestrator
+            .as_ref()
+            .and_then(|url| orchestrators.iter().find(|o| &o.url == url));
+        if let Err(e) = restore_relay(&relay, signer, orchestrator) {
+            warn!("Not resuming relay {hash} of {}: {e}", relay.sig_hash);
+            continue;
+        }
+        if !relay.included {
+            add_in_flight(hash.clone(), relay.sig_hash.clone());
+        }
+        info!(
+            "Resuming relay {hash} of {} on {} with nonce {}",
+            relay.sig_hash, ctx.name, relay.nonce
+        );
+    }
+}
+
+/// Polls orchestrators and relays transactions until shutdown is requested, work already in progress
+/// is allowed to complete but no new transactions are picked up once shutdown begins
+async fn relay_loop(
+    chains: &[RelayContext],
+    signer: &Signer,
+    cli_opts: &RelayerOpts,
+    opts: &RelayerOpts,
+    mut orchestrators: Vec<PolledOrchestrator>,
+    sender: &UnboundedSender<(Orchestrator, GaslessTransaction)>,
+    receiver: &mut UnboundedReceiver<(Orchestrator, GaslessTransaction)>,
+) {
+    let sources = build_transaction_sources(opts);
+    while !is_shutting_down() {
+        if take_reload_request() {
+            reload_settings(cli_opts, opts, signer, &mut orchestrators, sender);
+        }
+        check_kill_switch().await;
+        renew_account_leases(chains).await;
+        check_maintenance_windows();
+        check_contract_code(chains).await;
+        check_congestion(chains).await;
+        start_cycle();
+        // An orchestrator is a service that users submit their pending transactions to to be picked up
+        // by relayers. This loop will iterate over all orchestrator URLs provided in the options
+        // orchestrators commonly share queues, each transaction is only processed once per cycle no
+        // matter how many of them return it
+        let mut seen = HashSet::new();
+        // every orchestrator gets its own random offset into the cycle so that many relayers triggered by the
+        // same block don't all hit it at once. They are fetched concurrently and each queue is processed as soon
+        // as it arrives, so a slow orchestrator doesn't hold up the others.
+        let mut fetches: FuturesUnordered<_> = orchestrators
+            .iter()
+            .enumerate()
+            .filter(|(_, polled)| {
+                let url = &polled.orchestrator.url;
+                if polled.connected.get() || is_shutting_down() || is_paused() {
+                    return false;
+                }
+                if !should_poll(url) {
+                    debug!("Circuit for {url} is open, skipping it");
+                    return false;
+                }
+                if !due_by_quality(url) {
+                    debug!("{url} is demoted for poor quality, skipping it this cycle");
+                    return false;
+                }
+                true
+            })
+            .map(|(idx, polled)| {
+                let orchestrator = polled.orchestrator.clone();
+                let signer = signer.clone();
+                let offset = jitter(opts.poll_jitter_ms);
+                actix_rt::spawn(async move {
+                    if sleep_or_shutdown(offset).await {
+                        return None;
+                    }
+                    info!("Fetching pending transactions from {}", orchestrator.url);
+                    Some((idx, orchestrator.fetch_pending(&signer).await))
+                })
+            })
+            .collect();
+        while let Some(fetched) = fetches.next().await {
+            let Ok(Some((idx, result))) = fetched else {
+                continue;
+            };
+            if is_shutting_down() || is_paused() {
+                continue;
+            }
+            let orchestrator = &orchestrators[idx].orchestrator;
+            match result {
+                Ok(txs) => {
+                    record_served(&orchestrator.url, &txs);
+                    process_fetched_transactions(chains, orchestrator, txs, &mut seen).await;
+                    record_poll_success(&orchestrator.url);
+                    record_poll_result(&orchestrator.url, true);
+                    alert_on_poll(&orchestrator.url, true);
                 }
                 Err(e) => {
-                    error!("Error waiting for transaction confirmation: {e:?}");
-                    Err(e.into())
+                    error!(
+                        orchestrator:% = orchestrator.url;
+                        "Error processing pending transactions from {}: {e}",
+                        orchestrator.url
+                    );
+                    record_poll_result(&orchestrator.url, false);
+                    alert_on_poll(&orchestrator.url, false);
                 }
             }
         }
-        Err(e) => {
-            error!("Transaction failed: {e:?}");
-            Err(e.into())
-        }
-    }
-}
-
-// function userCmdRelayer (uint16 callpath, bytes calldata cmd,
-//                          bytes calldata conds, bytes calldata relayerTip,
-//                          bytes calldata signature)
-pub const USER_CMD_RELAYER_SIG: &str = "userCmdRelayer(uint16,bytes,bytes,bytes,bytes)";
-
-pub async fn user_cmd_relayer_tx(
-    private_key: PrivateKey,
-    web3: &Web3,
-    dex_addr: Address,
-    tx: &GaslessTransaction,
-) -> Result<Transaction, Web3Error> {
-    web3.prepare_transaction(
-        dex_addr,
-        encode_call(
-            USER_CMD_RELAYER_SIG,
-            &[
-                tx.callpath.into(),
-                tx.cmd.clone().into(),
-                tx.conds.clone().into(),
-                tx.tip.clone().into(),
-                tx.sig.clone().into(),
-            ],
-        )?,
-        0u8.into(),
-        private_key,
-        vec![SendTxOption::GasLimitMultiplier(2.0)],
-    )
-    .await
+        for source in &sources {
+            if is_shutting_down() || is_paused() {
+                break;
+            }
+            match process_pending_transactions(chains, source.as_ref(), signer, &mut seen).await {
+                Ok(()) => record_poll_success(&source.name()),
+                Err(e) => error!(
+                    "Error processing pending transactions from {}: {e}",
+                    source.name()
+                ),
+            }
+        }
+        for PolledOrchestrator { orchestrator, .. } in &orchestrators {
+            if is_shutting_down() || is_paused() {
+                break;
+            }
+            if should_poll(&orchestrator.url) {
+                process_protocol_commands(chains, orchestrator, signer).await;
+                process_user_operations(chains, orchestrator, signer).await;
+            }
+        }
+        record_open_circuits(open_circuits());
+        record_orchestrator_quality(orchestrator_quality());
+        record_profit_margin(required_margin(relay_limits().profit_margin_percent));
+        if !is_paused() {
+            process_deferred(chains).await;
+            process_retries(chains).await;
+        }
+        for ctx in chains {
+            update_chain_status(ctx).await;
+            // partners are paid before sweeping, so their shares are never swept
+            pay_tip_splits_if_due(ctx).await;
+            sweep_if_due(ctx).await;
+        }
+        seal_audit_log(signer).await;
+        record_loop();
+        // ready once an orchestrator has been reached and every chain has reported a block
+        let status = get_status();
+        if (!status.last_successful_poll.is_empty()
+            || orchestrators.iter().any(|o| o.connected.get()))
+            && status
+                .chains
+                .values()
+                .all(|chain| chain.last_block.is_some())
+        {
+            notify_ready();
+        }
+        notify_watchdog();
+        if opts.once {
+            break;
+        }
+
+        // while paused subscribed transactions are left queued until relaying resumes
+        if (opts.subscribe || opts.long_poll.is_some()) && !is_paused() {
+            process_subscribed_transactions(
+                chains,
+                receiver,
+                Duration::from_secs(opts.poll_interval),
+            )
+            .await;
+        } else {
+            // the fallback interval is jittered by up to a tenth as well, for chains that stop producing blocks
+            wait_for_new_block(
+                chains,
+                Duration::from_millis(opts.head_poll_interval_ms.max(1)),
+                Duration::from_secs(opts.poll_interval) + jitter(opts.poll_interval * 100),
+            )
+            .await;
+        }
+    }
+}
+
+/// A random delay of up to `max_ms` milliseconds
+fn jitter(max_ms: u64) -> Duration {
+    Duration::from_millis(rand::random_range(0..=max_ms))
+}
+
+/// Records the latest block, the balance of every relayer account and RPC health for the status endpoint, switching to a
+/// healthier RPC endpoint if one is available
+async fn update_chain_status(ctx: &RelayContext) {
+    let block = ctx.rpc.check_head().await.map(Uint256::from);
+    record_rpc_health(ctx.chain_id, ctx.rpc.health());
+    let mut balances = Vec::new();
+    for signer in ctx.signers.signers() {
+        let balance = ctx.web3().eth_get_balance(signer.address()).await.ok();
+        if let Some(balance) = balance {
+            alert_on_balance(&ctx.name, ctx.chain_id, signer.address(), balance);
+        }
+        balances.push(balance);
+    }
+    record_chain_status(ctx.chain_id, block, balances);
+    if token_balances_due(ctx.chain_id) {
+        update_token_balances(ctx).await;
+    }
+}
+
+/// Records the balance of every relayer account in the wrapped gas token and each token tips were earned in. The
+/// balances of an account are read together, and one that can't be read keeps its last known value.
+async fn update_token_balances(ctx: &RelayContext) {
+    let mut tokens = tracked_tokens(ctx.chain_id);
+    if let Some(wrapped) = ctx.wrapped_native_token
+        && !tokens.contains(&wrapped)
+    {
+        tokens.push(wrapped);
+    }
+    let known = get_status()
+        .chains
+        .remove(&ctx.chain_id)
+        .map(|chain| chain.wallets)
+        .unwrap_or_default();
+    let mut balances = Vec::new();
+    for (idx, signer) in ctx.signers.signers().iter().enumerate() {
+        let read = join_all(tokens.iter().map(|token| {
+            ctx.web3()
+                .get_erc20_balance(*token, signer.address(), Vec::new())
+        }))
+        .await;
+        let mut wallet = Vec::new();
+        for (token, balance) in tokens.iter().zip(read) {
+            let balance = match balance {
+                Ok(balance) => balance,
+                Err(_) => match known.get(idx).and_then(|wallet| {
+                    wallet
+                        .tokens
+                        .iter()
+                        .find(|known| known.token == token.to_string())
+                }) {
+                    Some(known) => known.balance,
+                    None => continue,
+                },
+            };
+            wallet.push(TokenBalance {
+                token: token.to_string(),
+                symbol: resolve_token(ctx.web3(), ctx.chain_id, *token)
+                    .await
+                    .map(|metadata| metadata.symbol),
+                balance,
+                decimals: token_decimals(ctx.web3(), ctx.chain_id, *token).await.ok(),
+            });
+        }
+        balances.push(wallet);
+    }
+    record_token_balances(ctx.chain_id, balances);
+}
+
+/// Fetches pending transactions from `source` and checks if each one is valid and profitable to relay before
+/// submitting it to the network. When `dry_run` is set every step up to submission is performed but nothing is broadcast.
+/// Transactions whose signature hash is already in `seen` were returned by another source this cycle and are skipped.
+async fn process_pending_transactions(
+    chains: &[RelayContext],
+    source: &dyn TransactionSource,
+    signer: &Signer,
+    seen: &mut HashSet<[u8; 32]>,
+) -> Result<(), RelayerError> {
+    info!("Fetching pending transactions from {}", source.name());
+    let txs = source.fetch_pending(signer).await?;
+    process_fetched_transactions(chains, source, txs, seen).await;
+    Ok(())
+}
+
+/// Relays transactions fetched from `source`, skipping those already seen this cycle
+async fn process_fetched_transactions(
+    chains: &[RelayContext],
+    source: &dyn TransactionSource,
+    mut txs: Vec<GaslessTransaction>,
+    seen: &mut HashSet<[u8; 32]>,
+) {
+    record_queue(
+        &source.name(),
+        txs.iter()
+            .map(|tx| QueuedTransaction {
+                id: tx.id(),
+                chain_id: tx.chain_id,
+            })
+            .collect(),
+    );
+    let fetched = txs.len();
+    txs.retain(|tx| seen.insert(tx.sig_hash()));
+    if txs.len() < fetched {
+        debug!(
+            "Skipping {} transactions from {} already seen this cycle",
+            fetched - txs.len(),
+            source.name()
+        );
+    }
+    process_transactions(chains, source.orchestrator(), &txs).await;
 }
 
-pub fn get_call_data(request: &Transaction) -> Data {
-    match request {
-        Transaction::Legacy { data, .. } => Data(data.clone()),
-        Transaction::Eip1559 { data, .. } => Data(data.clone()),
-        Transaction::Eip2930 { data, .. } => Data(data.clone()),
+/// Sources of pending transactions polled besides the orchestrators
+fn build_transaction_sources(opts: &RelayerOpts) -> Vec<Box<dyn TransactionSource>> {
+    let mut sources: Vec<Box<dyn TransactionSource>> = Vec::new();
+    for path in &opts.transaction_file {
+        sources.push(Box::new(FileSource::new(path.clone())));
+    }
+    if opts.transaction_stdin {
+        sources.push(Box::new(StdinSource::new()));
+    }
+    if let Some(bind) = &opts.submit_bind {
+        let keys = opts
+            .submit_key
+            .iter()
+            .map(|entry| SubmitKey::parse(entry))
+            .collect::<Result<Vec<_>, _>>()
+            .expect("Invalid submit key");
+        let limit = SubmitLimit {
+            max_submissions: opts.max_submissions_per_source,
+            window: Duration::from_secs(opts.submit_rate_window),
+        };
+        for source in start_submit_server(bind, keys, limit).expect("Failed to start submit server")
+        {
+            sources.push(Box::new(source));
+        }
+    }
+    sources
+}
+
+/// Relays transactions pushed by orchestrator subscriptions as they arrive, returning once `wait` has elapsed
+/// so that the main loop can poll any orchestrators whose subscription is currently down
+async fn process_subscribed_transactions(
+    chains: &[RelayContext],
+    receiver: &mut UnboundedReceiver<(Orchestrator, GaslessTransaction)>,
+    wait: Duration,
+) {
+    let deadline = Instant::now() + wait;
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        let next = tokio::select! {
+            next = actix_rt::time::timeout(remaining, receiver.next()) => next,
+            _ = wait_for_shutdown() => return,
+        };
+        match next {
+            Ok(Some((orchestrator, tx))) => {
+                debug!(
+                    "Received transaction from subscription to {}",
+                    orchestrator.url
+                );
+                record_served(&orchestrator.url, std::slice::from_ref(&tx));
+                process_transactions(chains, Some(&orchestrator), &[tx]).await
+            }
+            Ok(None) | Err(_) => return,
+        }
     }
 }
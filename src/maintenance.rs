@@ -0,0 +1,241 @@
+//! Scheduled maintenance windows, for example planned chain upgrades, during which no new relays are submitted.
+//! Each window starts whenever a cron expression matches and lasts for a fixed length, relaying resumes on its
+//! own once it ends. Expressions have the usual five fields (minute, hour, day of month, month and day of week,
+//! with 0 or 7 for Sunday) and are evaluated in UTC, each field takes `*`, values, ranges, lists and steps.
+
+use crate::history::{civil_from_days, format_timestamp};
+use log::{info, warn};
+use std::fmt::{self, Display};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Longest a window may last, bounds the search for the start of a window that is in progress
+const MAX_WINDOW: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// How far ahead the next window is looked for when logging the schedule
+const LOOKAHEAD_MINUTES: u64 = 366 * 24 * 60;
+
+/// A field of a cron expression, the values it matches
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CronField(Vec<bool>);
+
+impl CronField {
+    fn parse(field: &str, min: u32, max: u32) -> Result<Self, String> {
+        let mut matches = vec![false; max as usize + 1];
+        for part in field.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => (
+                    range,
+                    step.parse::<u32>()
+                        .ok()
+                        .filter(|step| *step > 0)
+                        .ok_or_else(|| format!("Invalid step in {field}"))?,
+                ),
+                None => (part, 1),
+            };
+            let value = |v: &str| {
+                v.parse::<u32>()
+                    .ok()
+                    .filter(|v| (min..=max).contains(v))
+                    .ok_or_else(|| format!("Invalid value {v} in {field}, expected {min} to {max}"))
+            };
+            let (start, end) = match range {
+                "*" => (min, max),
+                range => match range.split_once('-') {
+                    Some((start, end)) => (value(start)?, value(end)?),
+                    None if step > 1 => (value(range)?, max),
+                    None => (value(range)?, value(range)?),
+                },
+            };
+            if start > end {
+                return Err(format!("Invalid range {range} in {field}"));
+            }
+            for v in (start..=end).step_by(step as usize) {
+                matches[v as usize] = true;
+            }
+        }
+        Ok(CronField(matches))
+    }
+
+    fn matches(&self, value: u32) -> bool {
+        self.0.get(value as usize).copied().unwrap_or(false)
+    }
+
+    fn is_wildcard(&self, min: u32) -> bool {
+        self.0[min as usize..].iter().all(|m| *m)
+    }
+}
+
+/// A recurring maintenance window, written as a cron expression followed by its length, such as
+/// `0 2 * * 0 2h` for two hours from 02:00 UTC every Sunday
+#[derive(Debug, Clone)]
+pub struct MaintenanceWindow {
+    expression: String,
+    minute: CronField,
+    hour: CronField,
+    day: CronField,
+    month: CronField,
+    weekday: CronField,
+    pub length: Duration,
+}
+
+impl FromStr for MaintenanceWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let [minute, hour, day, month, weekday, length] = fields[..] else {
+            return Err(format!(
+                "Invalid maintenance window {s}, expected a five field cron expression followed by a length such as 2h"
+            ));
+        };
+        let mut weekday = CronField::parse(weekday, 0, 7)?;
+        // 7 is Sunday as well
+        if weekday.matches(0) || weekday.matches(7) {
+            weekday.0[0] = true;
+            weekday.0[7] = true;
+        }
+        Ok(MaintenanceWindow {
+            expression: fields[..5].join(" "),
+            minute: CronField::parse(minute, 0, 59)?,
+            hour: CronField::parse(hour, 0, 23)?,
+            day: CronField::parse(day, 1, 31)?,
+            month: CronField::parse(month, 1, 12)?,
+            weekday,
+            length: parse_length(length)?,
+        })
+    }
+}
+
+impl Display for MaintenanceWindow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} for {} minutes",
+            self.expression,
+            self.length.as_secs() / 60
+        )
+    }
+}
+
+/// Parses a window length such as `90m`, `2h` or `1d`
+fn parse_length(s: &str) -> Result<Duration, String> {
+    let invalid = || {
+        format!(
+            "Invalid maintenance window length {s}, expected minutes, hours or days such as 90m, 2h or 1d"
+        )
+    };
+    let (value, unit) = s.split_at(s.len().saturating_sub(1));
+    let value: u64 = value.parse().map_err(|_| invalid())?;
+    let minutes = match unit {
+        "m" => Some(value),
+        "h" => value.checked_mul(60),
+        "d" => value.checked_mul(24 * 60),
+        _ => return Err(invalid()),
+    };
+    let secs = minutes
+        .and_then(|minutes| minutes.checked_mul(60))
+        .ok_or_else(invalid)?;
+    let length = Duration::from_secs(secs);
+    if length.is_zero() || length > MAX_WINDOW {
+        return Err(format!(
+            "Invalid maintenance window length {s}, expected at most 7 days"
+        ));
+    }
+    Ok(length)
+}
+
+impl MaintenanceWindow {
+    /// True if the window starts at `minute`, counted in minutes since the unix epoch
+    fn starts_at(&self, minute: u64) -> bool {
+        let days = (minute / (24 * 60)) as i64;
+        let (_, month, day) = civil_from_days(days);
+        // 1970-01-01 was a Thursday
+        let weekday = (days + 4).rem_euclid(7) as u32;
+        let minute_of_day = minute % (24 * 60);
+        // as in cron, when both days are restricted either one matching is enough
+        let day_matches = if self.day.is_wildcard(1) || self.weekday.is_wildcard(0) {
+            self.day.matches(day as u32) && self.weekday.matches(weekday)
+        } else {
+            self.day.matches(day as u32) || self.weekday.matches(weekday)
+        };
+        self.minute.matches((minute_of_day % 60) as u32)
+            && self.hour.matches((minute_of_day / 60) as u32)
+            && self.month.matches(month as u32)
+            && day_matches
+    }
+
+    /// Start of the occurrence of the window in progress at `now`, in minutes since the unix epoch
+    fn current_start(&self, now: u64) -> Option<u64> {
+        let minute = now / 60;
+        let length = self.length.as_secs() / 60;
+        (minute.saturating_sub(length - 1)..=minute)
+            .rev()
+            .find(|start| self.starts_at(*start))
+    }
+
+    /// Start of the next occurrence of the window after `now`, in minutes since the unix epoch
+    fn next_start(&self, now: u64) -> Option<u64> {
+        let minute = now / 60 + 1;
+        (minute..minute + LOOKAHEAD_MINUTES).find(|start| self.starts_at(*start))
+    }
+}
+
+static WINDOWS: LazyLock<Mutex<Vec<MaintenanceWindow>>> = LazyLock::new(|| Mutex::new(Vec::new()));
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Sets the maintenance windows and logs when each one next starts
+pub fn set_maintenance_windows(windows: Vec<MaintenanceWindow>) {
+    let now = unix_now();
+    for window in &windows {
+        match window.next_start(now) {
+            Some(start) => info!(
+                "Maintenance window {window}, next starting at {}",
+                format_timestamp(start * 60)
+            ),
+            None => warn!("Maintenance window {window} doesn't start within the next year"),
+        }
+    }
+    *WINDOWS.lock().unwrap() = windows;
+}
+
+/// True while a maintenance window is in progress
+pub fn in_maintenance_window() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// Updates whether a maintenance window is in progress, logging when one starts and once they have all ended
+pub fn check_maintenance_windows() {
+    let now = unix_now();
+    let windows = WINDOWS.lock().unwrap();
+    let current = windows.iter().find_map(|window| {
+        let start = window.current_start(now)?;
+        Some((window, start * 60 + window.length.as_secs()))
+    });
+    match (ACTIVE.swap(current.is_some(), Ordering::Relaxed), current) {
+        (false, Some((window, end))) => warn!(
+            "Maintenance window {window} started, new submissions are paused until {}",
+            format_timestamp(end)
+        ),
+        (true, None) => {
+            let next = windows
+                .iter()
+                .filter_map(|window| window.next_start(now))
+                .min()
+                .map(|start| format_timestamp(start * 60))
+                .unwrap_or_else(|| "none within a year".to_string());
+            info!("Maintenance window ended, relaying resumed. Next window: {next}")
+        }
+        _ => {}
+    }
+}
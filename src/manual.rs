@@ -0,0 +1,181 @@
+//! Manual relays, for rescuing a user's gasless transaction during a support incident. The transaction is relayed
+//! right away without going through an orchestrator, and without the profitability and per user checks of the
+//! relay loop, with gas parameters the operator may set by hand. It is still simulated by estimating its gas, a
+//! relay that would revert only burns gas. Gas set by hand may only raise the fee over what the relay loop would
+//! pay, and the relay is still held to the gas price ceiling, the reserve balance and the spend caps.
+
+use crate::accounting::within_spend_caps;
+use crate::error::RelayerError;
+use crate::lease::may_broadcast;
+use crate::orchestrator::GaslessTransaction;
+use crate::relay::{
+    RelayContext, pending_spend, prepare_unsigned_transaction, record_history, relay_limits,
+    send_transaction, user_cmd_relayer_data,
+};
+use crate::skip::SkipReason;
+use clarity::utils::display_uint256_as_address;
+use clarity::{Transaction, Uint256};
+use log::{info, warn};
+use std::time::{Duration, Instant};
+
+/// How often the receipt of a manual relay is checked for
+const RECEIPT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Gas parameters set by hand, those left unset are chosen as for any relay
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GasOverrides {
+    pub gas_limit: Option<Uint256>,
+    pub max_fee_per_gas: Option<Uint256>,
+    pub max_priority_fee_per_gas: Option<Uint256>,
+    /// replaces the pending transaction of the relayer account at this nonce, such as a stuck relay
+    pub nonce: Option<Uint256>,
+}
+
+/// Outcome of a manual relay
+#[derive(Debug, Clone, Copy)]
+pub struct ManualRelay {
+    pub tx_hash: Uint256,
+    /// None if no receipt turned up within the receipt timeout
+    pub success: Option<bool>,
+    /// gas fee paid in wei, once included
+    pub gas_paid: Option<Uint256>,
+}
+
+/// Max fee and priority fee of `call`
+fn fees(call: &Transaction) -> (Uint256, Uint256) {
+    match call {
+        Transaction::Eip1559 {
+            max_fee_per_gas,
+            max_priority_fee_per_gas,
+            ..
+        } => (*max_fee_per_gas, *max_priority_fee_per_gas),
+        Transaction::Legacy { gas_price, .. } | Transaction::Eip2930 { gas_price, .. } => {
+            (*gas_price, *gas_price)
+        }
+    }
+}
+
+/// Relays `tx` on `ctx` from its primary account with `overrides` applied, then waits for its receipt. In dry run
+/// mode the relay is built and logged but not sent.
+pub async fn relay_one(
+    ctx: &RelayContext,
+    tx: &GaslessTransaction,
+    overrides: GasOverrides,
+) -> Result<Option<ManualRelay>, RelayerError> {
+    let signer = ctx.signers.primary();
+    let (target, relay_function) = ctx.relay_target(tx)?;
+    let data = user_cmd_relayer_data(relay_function, tx)?;
+    let mut unsigned =
+        prepare_unsigned_transaction(ctx.web3(), tx.chain_id, signer.address(), target, data, 2.0)
+            .await?;
+    let chosen = fees(&unsigned);
+    if let Some(gas_limit) = overrides.gas_limit {
+        unsigned.set_gas_limit(gas_limit);
+    }
+    if let Some(max_fee_per_gas) = overrides.max_fee_per_gas {
+        unsigned.set_max_fee_per_gas(max_fee_per_gas);
+    }
+    if let Some(max_priority_fee_per_gas) = overrides.max_priority_fee_per_gas {
+        unsigned.set_max_priority_fee_per_gas(max_priority_fee_per_gas);
+    }
+    if let (Some(nonce), Transaction::Eip1559 { nonce: current, .. }) =
+        (overrides.nonce, &mut unsigned)
+    {
+        *current = nonce;
+    }
+    let Transaction::Eip1559 {
+        nonce,
+        gas_limit,
+        max_fee_per_gas,
+        max_priority_fee_per_gas,
+        ..
+    } = &unsigned
+    else {
+        unreachable!("Relays are always EIP-1559 transactions");
+    };
+    if max_priority_fee_per_gas > max_fee_per_gas {
+        return Err(RelayerError::InvalidTx(format!(
+            "Priority fee {max_priority_fee_per_gas} exceeds the max fee {max_fee_per_gas}"
+        )));
+    }
+    let (chosen_max_fee, chosen_priority_fee) = chosen;
+    if *max_fee_per_gas < chosen_max_fee || *max_priority_fee_per_gas < chosen_priority_fee {
+        return Err(RelayerError::InvalidTx(format!(
+            "Fees set by hand may only raise the max fee of {chosen_max_fee} and the priority fee of {chosen_priority_fee} chosen for the relay"
+        )));
+    }
+    let limits = relay_limits();
+    if let Some(max_gas_price) = limits.max_gas_price
+        && *max_fee_per_gas > max_gas_price
+    {
+        warn!("Max fee {max_fee_per_gas} is above the gas price ceiling of {max_gas_price}");
+        return Err(RelayerError::Skipped(SkipReason::GasPriceTooHigh));
+    }
+    // the relay is held to the same balance as any other, only now with the fees set by hand
+    let max_cost = *gas_limit * *max_fee_per_gas;
+    let balance = ctx.web3().eth_get_balance(signer.address()).await?;
+    let spoken_for = pending_spend(ctx.chain_id, signer.address()) + limits.reserve_balance;
+    if balance < spoken_for + max_cost {
+        return Err(RelayerError::BelowReserve {
+            balance,
+            reserve: limits.reserve_balance,
+        });
+    }
+    if !within_spend_caps(max_cost) {
+        return Err(RelayerError::SpendCapReached);
+    }
+    info!(
+        tx:% = tx.id();
+        "Manually relaying {} on {} from {} to {target} with nonce {nonce}, gas limit {gas_limit}, max fee {max_fee_per_gas} and priority fee {max_priority_fee_per_gas}",
+        tx.id(),
+        ctx.name,
+        signer.address()
+    );
+    if ctx.dry_run {
+        info!(tx:% = tx.id(), decision = "dry_run"; "Dry run: not sending the manual relay");
+        return Ok(None);
+    }
+    // a running instance relaying from the same account would hand out the same nonces
+    if !may_broadcast(ctx.chain_id, signer.address()) {
+        return Err(RelayerError::Skipped(SkipReason::Standby));
+    }
+    let signed = signer.sign_transaction(unsigned).await?;
+    let tx_hash = send_transaction(ctx, signed).await?;
+    let hash = display_uint256_as_address(tx_hash);
+    info!(
+        tx:% = tx.id(), decision = "submitted", tx_hash:% = hash;
+        "Manual relay of {} submitted with hash {hash}, waiting for its receipt",
+        tx.id()
+    );
+
+    let submitted = Instant::now();
+    while submitted.elapsed() < ctx.receipt_timeout {
+        actix_rt::time::sleep(RECEIPT_POLL_INTERVAL).await;
+        let receipt = match ctx.web3().eth_get_transaction_receipt(tx_hash).await {
+            Ok(Some(receipt)) if receipt.get_block_number().is_some() => receipt,
+            Ok(_) => continue,
+            Err(e) => {
+                warn!("Failed to get receipt for {hash}: {e}");
+                continue;
+            }
+        };
+        let success = receipt.get_success();
+        let gas_paid = receipt.get_gas_used() * receipt.get_effective_gas_price();
+        let (outcome, error) = if success {
+            ("included", None)
+        } else {
+            ("failed", Some("Manual relay reverted on chain".to_string()))
+        };
+        record_history(ctx, tx, outcome, Some(tx_hash), Some(gas_paid), None, error);
+        return Ok(Some(ManualRelay {
+            tx_hash,
+            success: Some(success),
+            gas_paid: Some(gas_paid),
+        }));
+    }
+    Ok(Some(ManualRelay {
+        tx_hash,
+        success: None,
+        gas_paid: None,
+    }))
+}
@@ -0,0 +1,86 @@
+//! Adaptive profit margin. Relays that revert on chain or lose the race to a competitor still cost gas, and
+//! relays whose receipt shows they paid more for gas than their tip was worth lost money, so while that keeps
+//! happening the margin required on top of the gas cost is raised step by step, and after a run of relays that
+//! were included it is lowered again towards the configured margin.
+
+use log::info;
+use std::sync::{LazyLock, Mutex};
+
+#[derive(Debug, Clone)]
+pub struct MarginPolicy {
+    /// percentage points the margin moves by at a time
+    pub step_percent: u32,
+    /// the margin is never raised by more than this many percentage points
+    pub max_extra_percent: u32,
+    /// consecutive included relays after which the margin is lowered by one step
+    pub relax_after: u32,
+}
+
+#[derive(Debug, Default)]
+struct MarginState {
+    /// percentage points currently added to the configured margin
+    extra_percent: u32,
+    /// included relays since the margin last moved
+    successes: u32,
+}
+
+static POLICY: LazyLock<Mutex<Option<MarginPolicy>>> = LazyLock::new(|| Mutex::new(None));
+
+static STATE: LazyLock<Mutex<MarginState>> = LazyLock::new(|| Mutex::new(MarginState::default()));
+
+pub fn set_margin_policy(policy: Option<MarginPolicy>) {
+    *POLICY.lock().unwrap() = policy;
+}
+
+/// Margin in percent to require given the configured `base` margin
+pub fn required_margin(base: u32) -> u32 {
+    if POLICY.lock().unwrap().is_none() {
+        return base;
+    }
+    base + STATE.lock().unwrap().extra_percent
+}
+
+/// A relay was paid for but earned nothing, because it reverted or a competitor relayed first
+pub fn record_wasted_relay() {
+    raise_margin("Relay wasted gas");
+}
+
+/// A final relay paid more for gas than its tip was worth
+pub fn record_losing_relay() {
+    raise_margin("Relay lost money");
+}
+
+fn raise_margin(cause: &str) {
+    let Some(policy) = POLICY.lock().unwrap().clone() else {
+        return;
+    };
+    let mut state = STATE.lock().unwrap();
+    state.successes = 0;
+    if state.extra_percent < policy.max_extra_percent {
+        state.extra_percent =
+            (state.extra_percent + policy.step_percent).min(policy.max_extra_percent);
+        info!(
+            "{cause}, profit margin is now raised by {} points",
+            state.extra_percent
+        );
+    }
+}
+
+pub fn record_included_relay() {
+    let Some(policy) = POLICY.lock().unwrap().clone() else {
+        return;
+    };
+    let mut state = STATE.lock().unwrap();
+    if state.extra_percent == 0 {
+        return;
+    }
+    state.successes += 1;
+    if state.successes >= policy.relax_after {
+        state.successes = 0;
+        state.extra_percent = state.extra_percent.saturating_sub(policy.step_percent);
+        info!(
+            "{} relays included in a row, profit margin is now raised by {} points",
+            policy.relax_after, state.extra_percent
+        );
+    }
+}
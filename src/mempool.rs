@@ -0,0 +1,246 @@
+//! Watching the mempool for competing relays. Without it a competitor relaying the same user command is only
+//! noticed once their relay lands and ours starts failing simulation, by which point ours may be included right
+//! behind it and revert on the consumed user nonce. With it, pending transactions that touch the dex are picked
+//! up through a pending transaction filter or the node's txpool API. Their relay calls are decoded, at the top
+//! level or nested in a multicall, and one whose signature is that of a command we are relaying means it is being
+//! relayed by someone else. A signature merely appearing somewhere in the calldata doesn't count. Our relay is then skipped if not yet sent, or
+//! cancelled with a higher fee if it is already pending.
+
+use crate::orchestrator::GaslessTransaction;
+use crate::relay::RelayContext;
+use clap::ValueEnum;
+use clarity::Address;
+use clarity::abi::derive_method_id;
+use log::{debug, warn};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use web30::jsonrpc::client::HttpClient;
+
+/// How long a pending transaction seen through a filter is remembered, filters don't report when it leaves
+const SEEN_TTL: Duration = Duration::from_secs(120);
+
+/// Upper bound on pending transactions looked up per refresh, so a flood of them can't stall the relayer
+const MAX_LOOKUPS: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MempoolSource {
+    /// `eth_newPendingTransactionFilter`, looking up each new pending transaction
+    Filter,
+    /// `txpool_content` of geth compatible nodes
+    Txpool,
+}
+
+/// A pending transaction from someone else that touches the dex
+#[derive(Debug, Clone)]
+struct SeenTransaction {
+    hash: String,
+    /// signatures of the relay calls it makes
+    signatures: Vec<Vec<u8>>,
+    seen: Instant,
+}
+
+static SOURCE: LazyLock<Mutex<Option<MempoolSource>>> = LazyLock::new(|| Mutex::new(None));
+
+/// Pending transaction filter installed on each chain
+static FILTERS: LazyLock<Mutex<HashMap<u64, String>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Pending transactions that touch the dex on each chain
+static SEEN: LazyLock<Mutex<HashMap<u64, Vec<SeenTransaction>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+pub fn set_mempool_source(source: Option<MempoolSource>) {
+    *SOURCE.lock().unwrap() = source;
+}
+
+/// Hash of a pending relay of `tx` by someone else on `chain_id`, if one has been seen
+pub fn competing_relay(chain_id: u64, tx: &GaslessTransaction) -> Option<String> {
+    // an empty signature can't be relayed, and would match any relay of another empty one
+    if tx.sig.is_empty() {
+        return None;
+    }
+    SEEN.lock()
+        .unwrap()
+        .get(&chain_id)?
+        .iter()
+        .find(|seen| seen.signatures.contains(&tx.sig))
+        .map(|seen| seen.hash.clone())
+}
+
+/// Selectors of the functions relays are sent through on `ctx`, of the dex and of each periphery contract
+pub(crate) fn relay_selectors(ctx: &RelayContext) -> Vec<[u8; 4]> {
+    std::iter::once(ctx.layout.relay_function.as_str())
+        .chain(
+            ctx.targets
+                .iter()
+                .filter_map(|t| t.relay_function.as_deref()),
+        )
+        .filter_map(|function| derive_method_id(function).ok())
+        .collect()
+}
+
+/// Signatures of the relay calls in `input`, calls through one of `selectors` taking the callpath followed by the
+/// command, conditions, tip and signature as `bytes`. Calls nested in a multicall are ABI encoded as `bytes` of
+/// their own, so every occurrence of a selector is decoded as a call, and only those that decode are kept.
+pub(crate) fn relayed_signatures(input: &[u8], selectors: &[[u8; 4]]) -> Vec<Vec<u8>> {
+    (0..input.len().saturating_sub(4))
+        .filter(|at| selectors.iter().any(|s| input[*at..].starts_with(s)))
+        .filter_map(|at| decode_relay_signature(&input[at + 4..]))
+        .collect()
+}
+
+/// The signature, the fifth argument, of `(uint16,bytes,bytes,bytes,bytes)` encoded in `args`, None unless every
+/// argument decodes and the signature isn't empty
+fn decode_relay_signature(args: &[u8]) -> Option<Vec<u8>> {
+    let word = |at: usize| -> Option<usize> {
+        let word = args.get(at..at.checked_add(32)?)?;
+        // offsets and lengths beyond 8 bytes can't fit in any calldata
+        if word[..24].iter().any(|b| *b != 0) {
+            return None;
+        }
+        usize::try_from(u64::from_be_bytes(word[24..].try_into().ok()?)).ok()
+    };
+    if word(0)? > u16::MAX as usize {
+        return None;
+    }
+    let mut signature = None;
+    for arg in 1..=4 {
+        let offset = word(32 * arg)?;
+        let len = word(offset)?;
+        let start = offset.checked_add(32)?;
+        let bytes = args.get(start..start.checked_add(len)?)?;
+        signature = Some(bytes);
+    }
+    signature.filter(|sig| !sig.is_empty()).map(<[u8]>::to_vec)
+}
+
+/// Picks up the pending transactions on `ctx` that touch the dex, either by calling it or one of the periphery
+/// contracts or by carrying its address, as a multicall to it does. Our own relays are left out.
+pub async fn refresh_mempool(ctx: &RelayContext) {
+    let Some(source) = *SOURCE.lock().unwrap() else {
+        return;
+    };
+    let client = HttpClient::new(&ctx.web3().get_url());
+    let timeout = ctx.web3().get_timeout();
+    let pending = match source {
+        MempoolSource::Filter => filter_changes(ctx, &client, timeout).await,
+        MempoolSource::Txpool => txpool_content(&client, timeout).await,
+    };
+    let pending = match pending {
+        Ok(pending) => pending,
+        Err(e) => {
+            warn!("Failed to read the mempool of {}: {e}", ctx.name);
+            return;
+        }
+    };
+    let own: Vec<Address> = ctx.signers.signers().iter().map(|s| s.address()).collect();
+    let selectors = relay_selectors(ctx);
+    let contracts: Vec<Address> = std::iter::once(ctx.contract_address)
+        .chain(ctx.targets.iter().map(|t| t.contract))
+        .collect();
+    let now = Instant::now();
+    let relevant = pending.into_iter().filter_map(|tx| {
+        let address = |field: &str| tx[field].as_str().and_then(|a| Address::from_str(a).ok());
+        if address("from").is_some_and(|from| own.contains(&from)) {
+            return None;
+        }
+        let input = tx["input"].as_str()?;
+        let input = hex::decode(input.strip_prefix("0x").unwrap_or(input)).ok()?;
+        let touches_dex = address("to").is_some_and(|to| contracts.contains(&to))
+            || input
+                .windows(20)
+                .any(|w| w == ctx.contract_address.as_bytes());
+        if !touches_dex {
+            return None;
+        }
+        let signatures = relayed_signatures(&input, &selectors);
+        (!signatures.is_empty()).then(|| SeenTransaction {
+            hash: tx["hash"].as_str().unwrap_or_default().to_string(),
+            signatures,
+            seen: now,
+        })
+    });
+    let mut seen = SEEN.lock().unwrap();
+    let chain = seen.entry(ctx.chain_id).or_default();
+    match source {
+        // the txpool is a full snapshot of what is pending right now
+        MempoolSource::Txpool => *chain = relevant.collect(),
+        MempoolSource::Filter => {
+            chain.retain(|tx| tx.seen.elapsed() < SEEN_TTL);
+            chain.extend(relevant);
+        }
+    }
+}
+
+/// New pending transactions since the last call, installing the filter first if needed
+async fn filter_changes(
+    ctx: &RelayContext,
+    client: &HttpClient,
+    timeout: Duration,
+) -> Result<Vec<Value>, String> {
+    let filter = FILTERS.lock().unwrap().get(&ctx.chain_id).cloned();
+    let filter = match filter {
+        Some(filter) => filter,
+        None => {
+            let filter: String = client
+                .request_method(
+                    "eth_newPendingTransactionFilter",
+                    Vec::<String>::new(),
+                    timeout,
+                )
+                .await
+                .map_err(|e| e.to_string())?;
+            debug!(
+                "Installed pending transaction filter {filter} on {}",
+                ctx.name
+            );
+            FILTERS.lock().unwrap().insert(ctx.chain_id, filter.clone());
+            filter
+        }
+    };
+    let hashes: Vec<String> = match client
+        .request_method("eth_getFilterChanges", vec![filter], timeout)
+        .await
+    {
+        Ok(hashes) => hashes,
+        Err(e) => {
+            // filters expire when not polled for a while, a new one is installed next time
+            FILTERS.lock().unwrap().remove(&ctx.chain_id);
+            return Err(e.to_string());
+        }
+    };
+    let mut pending = Vec::new();
+    for hash in hashes.into_iter().take(MAX_LOOKUPS) {
+        match client
+            .request_method::<_, Option<Value>>("eth_getTransactionByHash", vec![hash], timeout)
+            .await
+        {
+            Ok(Some(tx)) => pending.push(tx),
+            Ok(None) => {}
+            Err(e) => debug!(
+                "Failed to look up a pending transaction on {}: {e}",
+                ctx.name
+            ),
+        }
+    }
+    Ok(pending)
+}
+
+/// Every pending transaction in the node's txpool
+async fn txpool_content(client: &HttpClient, timeout: Duration) -> Result<Vec<Value>, String> {
+    let content: Value = client
+        .request_method("txpool_content", Vec::<String>::new(), timeout)
+        .await
+        .map_err(|e| e.to_string())?;
+    let Some(accounts) = content["pending"].as_object() else {
+        return Ok(Vec::new());
+    };
+    Ok(accounts
+        .values()
+        .filter_map(Value::as_object)
+        .flat_map(|txs| txs.values().cloned())
+        .collect())
+}
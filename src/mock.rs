@@ -0,0 +1,328 @@
+//! Mock orchestrator speaking the real orchestrator protocol, for end to end tests of the relayer against a local
+//! node such as anvil or hardhat. It serves a queue of gasless transactions on `/orchestrator/pending`, honoring
+//! the `limit`, `since` and `chain_id` filters and answering 304 Not Modified to a poll whose ETag still matches,
+//! after holding it for up to `wait` if it is a long poll. It grants claims on `/orchestrator/claim/{id}`, a
+//! claimed transaction is no longer served. Transactions can be queued at startup or POSTed to
+//! `/orchestrator/pending` at any time, and the claims granted so far are listed on `/mock/claims`. Relayers
+//! registering through `/orchestrator/register` are listed on `/mock/relayers` along with their last heartbeat.
+//! Given a signing key, it signs the pending queue it serves as an orchestrator requiring signed responses would.
+
+use crate::key::LocalKey;
+use crate::orchestrator::{
+    API_KEY_HEADER, ClaimRequest, GaslessTransaction, ORCHESTRATOR_SIGNATURE_HEADER,
+    ORCHESTRATOR_TIMESTAMP_HEADER, PendingQuery, RELAYING_SERVICE_ROOT, signed_payload,
+};
+use crate::registration::{Heartbeat, Registration};
+use actix_web::http::header;
+use actix_web::middleware::Compress;
+use actix_web::{App, HttpRequest, HttpResponse, HttpServer, Responder, web};
+use clarity::abi::get_hash;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Longest a long poll is held, whatever the relayer asks for
+const MAX_LONG_POLL: Duration = Duration::from_secs(60);
+
+/// How often a held long poll checks the queue for changes
+const LONG_POLL_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Default)]
+struct MockQueue {
+    txs: Vec<GaslessTransaction>,
+    /// relayer address that claimed each transaction, by transaction id
+    claims: HashMap<String, String>,
+    /// api key requests must carry, if any
+    api_key: Option<String>,
+    /// key the pending queue is signed with, if any
+    signing_key: Option<LocalKey>,
+    /// registered relayers by address
+    relayers: HashMap<String, MockRelayer>,
+}
+
+/// A relayer registered with the mock orchestrator
+#[derive(Debug, Clone, Serialize)]
+pub struct MockRelayer {
+    pub registration: Registration,
+    /// unix timestamp of the last registration or heartbeat
+    pub last_seen: u64,
+    pub paused: bool,
+}
+
+static QUEUE: LazyLock<Mutex<MockQueue>> = LazyLock::new(|| Mutex::new(MockQueue::default()));
+
+/// A single transaction or an array of them
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Transactions {
+    One(GaslessTransaction),
+    Many(Vec<GaslessTransaction>),
+}
+
+/// Adds `txs` to the queue served by the mock orchestrator, replacing queued transactions with the same id
+pub fn queue_mock_transactions(txs: Vec<GaslessTransaction>) {
+    let mut queue = QUEUE.lock().unwrap();
+    for tx in txs {
+        let id = tx.id();
+        queue.txs.retain(|queued| queued.id() != id);
+        queue.txs.push(tx);
+    }
+}
+
+/// Claims granted so far as (transaction id, relayer address)
+pub fn mock_claims() -> Vec<(String, String)> {
+    QUEUE
+        .lock()
+        .unwrap()
+        .claims
+        .iter()
+        .map(|(id, relayer)| (id.clone(), relayer.clone()))
+        .collect()
+}
+
+/// Relayers currently registered
+pub fn mock_relayers() -> Vec<MockRelayer> {
+    QUEUE.lock().unwrap().relayers.values().cloned().collect()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn authorized(request: &HttpRequest) -> bool {
+    let queue = QUEUE.lock().unwrap();
+    let Some(api_key) = &queue.api_key else {
+        return true;
+    };
+    request
+        .headers()
+        .get(API_KEY_HEADER)
+        .is_some_and(|value| value.as_bytes() == api_key.as_bytes())
+}
+
+/// The queue as served to `query` along with its ETag
+fn serve_queue(query: &PendingQuery) -> (Vec<u8>, String) {
+    let queue = QUEUE.lock().unwrap();
+    let mut txs: Vec<&GaslessTransaction> = queue
+        .txs
+        .iter()
+        .filter(|tx| !queue.claims.contains_key(&tx.id()))
+        .filter(|tx| query.since.is_none_or(|since| tx.submitted_at >= since))
+        .filter(|tx| {
+            query
+                .chain_id
+                .is_none_or(|chain_id| tx.chain_id == chain_id)
+        })
+        .collect();
+    txs.sort_by_key(|tx| tx.submitted_at);
+    if let Some(limit) = query.limit {
+        txs.truncate(limit as usize);
+    }
+    let body = serde_json::to_vec(&txs).unwrap_or_default();
+    let etag = format!("\"{}\"", hex::encode(get_hash(&body)));
+    (body, etag)
+}
+
+async fn pending(request: HttpRequest, query: web::Query<PendingQuery>) -> impl Responder {
+    if !authorized(&request) {
+        return HttpResponse::Unauthorized().body("Invalid api key");
+    }
+    let unchanged = |etag: &str| {
+        request
+            .headers()
+            .get(header::IF_NONE_MATCH)
+            .is_some_and(|value| value.as_bytes() == etag.as_bytes())
+    };
+    let wait = query
+        .wait
+        .as_deref()
+        .and_then(|wait| wait.trim_end_matches('s').parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or_default();
+    // a long poll is held until the queue changes or the wait is up
+    let deadline = Instant::now() + wait.min(MAX_LONG_POLL);
+    let (body, etag) = loop {
+        let (body, etag) = serve_queue(&query);
+        if !unchanged(&etag) || Instant::now() >= deadline {
+            break (body, etag);
+        }
+        actix_rt::time::sleep(LONG_POLL_CHECK_INTERVAL).await;
+    };
+    if unchanged(&etag) {
+        return HttpResponse::NotModified().finish();
+    }
+    let mut response = HttpResponse::Ok();
+    if let Some(key) = &QUEUE.lock().unwrap().signing_key {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let signature = key.sign_message(&signed_payload(timestamp, &body));
+        response.insert_header((ORCHESTRATOR_TIMESTAMP_HEADER, timestamp.to_string()));
+        response.insert_header((
+            ORCHESTRATOR_SIGNATURE_HEADER,
+            format!("0x{}", hex::encode(signature.to_bytes())),
+        ));
+    }
+    response
+        .insert_header((header::ETAG, etag))
+        .content_type("application/json")
+        .body(body)
+}
+
+async fn enqueue(request: HttpRequest, body: web::Json<Transactions>) -> impl Responder {
+    if !authorized(&request) {
+        return HttpResponse::Unauthorized().body("Invalid api key");
+    }
+    let txs = match body.into_inner() {
+        Transactions::One(tx) => vec![tx],
+        Transactions::Many(txs) => txs,
+    };
+    info!("Queued {} transactions", txs.len());
+    queue_mock_transactions(txs);
+    HttpResponse::Ok().body("Queued")
+}
+
+async fn claim(
+    request: HttpRequest,
+    id: web::Path<String>,
+    body: web::Json<ClaimRequest>,
+) -> impl Responder {
+    if !authorized(&request) {
+        return HttpResponse::Unauthorized().body("Invalid api key");
+    }
+    let id = id.into_inner();
+    let mut queue = QUEUE.lock().unwrap();
+    if !queue.txs.iter().any(|tx| tx.id() == id) {
+        return HttpResponse::NotFound().body("Unknown transaction");
+    }
+    match queue.claims.get(&id) {
+        Some(relayer) if *relayer != body.relayer => {
+            HttpResponse::Conflict().body("Already claimed")
+        }
+        _ => {
+            info!("Transaction {id} claimed by {}", body.relayer);
+            queue.claims.insert(id, body.relayer.clone());
+            HttpResponse::Ok().body("Claimed")
+        }
+    }
+}
+
+async fn claims() -> impl Responder {
+    HttpResponse::Ok().json(mock_claims())
+}
+
+async fn register(request: HttpRequest, body: web::Json<Registration>) -> impl Responder {
+    if !authorized(&request) {
+        return HttpResponse::Unauthorized().body("Invalid api key");
+    }
+    let registration = body.into_inner();
+    info!("Relayer {} registered", registration.relayer);
+    QUEUE.lock().unwrap().relayers.insert(
+        registration.relayer.clone(),
+        MockRelayer {
+            registration,
+            last_seen: now(),
+            paused: false,
+        },
+    );
+    HttpResponse::Ok().body("Registered")
+}
+
+async fn heartbeat(request: HttpRequest, body: web::Json<Heartbeat>) -> impl Responder {
+    if !authorized(&request) {
+        return HttpResponse::Unauthorized().body("Invalid api key");
+    }
+    match QUEUE.lock().unwrap().relayers.get_mut(&body.relayer) {
+        Some(relayer) => {
+            relayer.last_seen = now();
+            relayer.paused = body.paused;
+            HttpResponse::Ok().body("Ok")
+        }
+        None => HttpResponse::NotFound().body("Unknown relayer"),
+    }
+}
+
+async fn deregister(request: HttpRequest, body: web::Json<Heartbeat>) -> impl Responder {
+    if !authorized(&request) {
+        return HttpResponse::Unauthorized().body("Invalid api key");
+    }
+    match QUEUE.lock().unwrap().relayers.remove(&body.relayer) {
+        Some(_) => {
+            info!("Relayer {} deregistered", body.relayer);
+            HttpResponse::Ok().body("Deregistered")
+        }
+        None => HttpResponse::NotFound().body("Unknown relayer"),
+    }
+}
+
+async fn relayers() -> impl Responder {
+    HttpResponse::Ok().json(mock_relayers())
+}
+
+/// Starts the mock orchestrator on `bind` in the background serving `txs`, requests must carry `api_key` in the
+/// `X-Api-Key` header if one is given and the pending queue is signed with `signing_key` if one is given
+pub fn start_mock_orchestrator(
+    bind: &str,
+    txs: Vec<GaslessTransaction>,
+    api_key: Option<String>,
+    signing_key: Option<LocalKey>,
+) -> std::io::Result<()> {
+    {
+        let mut queue = QUEUE.lock().unwrap();
+        queue.api_key = api_key;
+        queue.signing_key = signing_key;
+    }
+    queue_mock_transactions(txs);
+    let server = HttpServer::new(|| {
+        App::new()
+            // compresses responses for relayers that ask for it, as a production orchestrator would
+            .wrap(Compress::default())
+            .route(
+                &format!("/{RELAYING_SERVICE_ROOT}/pending"),
+                web::get().to(pending),
+            )
+            .route(
+                &format!("/{RELAYING_SERVICE_ROOT}/pending"),
+                web::post().to(enqueue),
+            )
+            .route(
+                &format!("/{RELAYING_SERVICE_ROOT}/claim/{{id}}"),
+                web::post().to(claim),
+            )
+            .route(
+                &format!("/{RELAYING_SERVICE_ROOT}/register"),
+                web::post().to(register),
+            )
+            .route(
+                &format!("/{RELAYING_SERVICE_ROOT}/heartbeat"),
+                web::post().to(heartbeat),
+            )
+            .route(
+                &format!("/{RELAYING_SERVICE_ROOT}/deregister"),
+                web::post().to(deregister),
+            )
+            .route("/mock/claims", web::get().to(claims))
+            .route("/mock/relayers", web::get().to(relayers))
+    })
+    .workers(1)
+    .disable_signals()
+    .bind(bind)?
+    .run();
+    info!("Mock orchestrator listening on {bind}");
+    actix_rt::spawn(server);
+    Ok(())
+}
+
+/// Reads the transactions in `json`, either a single transaction or an array of them
+pub fn parse_mock_transactions(json: &str) -> Result<Vec<GaslessTransaction>, serde_json::Error> {
+    Ok(match serde_json::from_str(json)? {
+        Transactions::One(tx) => vec![tx],
+        Transactions::Many(txs) => txs,
+    })
+}
@@ -0,0 +1,54 @@
+//! Encoding for Multicall3 style `aggregate3` calls, used to carry several relays in one transaction
+
+use clarity::Address;
+use clarity::abi::derive_method_id;
+
+pub const AGGREGATE3_SIG: &str = "aggregate3((address,bool,bytes)[])";
+
+/// Encodes `aggregate3(calls)` with `allowFailure` set on every call, so that one item reverting does not
+/// take the rest of the batch down with it
+pub fn encode_aggregate3(calls: &[(Address, Vec<u8>)]) -> Vec<u8> {
+    let mut out = derive_method_id(AGGREGATE3_SIG)
+        .expect("Valid signature")
+        .to_vec();
+    // offset of the array, it's the only argument
+    out.extend(word(32));
+    out.extend(word(calls.len() as u64));
+
+    // each tuple is dynamic because of its bytes member, so the array head is a list of offsets relative
+    // to the start of the head followed by the tuples themselves
+    let encoded: Vec<Vec<u8>> = calls
+        .iter()
+        .map(|(target, data)| encode_call3(*target, data))
+        .collect();
+    let mut offset = 32 * calls.len() as u64;
+    for tuple in &encoded {
+        out.extend(word(offset));
+        offset += tuple.len() as u64;
+    }
+    for tuple in encoded {
+        out.extend(tuple);
+    }
+    out
+}
+
+/// Encodes a single `(address target, bool allowFailure, bytes callData)` tuple
+fn encode_call3(target: Address, data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut address = [0u8; 32];
+    address[12..].copy_from_slice(target.as_bytes());
+    out.extend(address);
+    out.extend(word(1));
+    // offset of the bytes member within the tuple
+    out.extend(word(96));
+    out.extend(word(data.len() as u64));
+    out.extend(data);
+    out.resize(out.len() + (32 - data.len() % 32) % 32, 0);
+    out
+}
+
+fn word(value: u64) -> [u8; 32] {
+    let mut out = [0u8; 32];
+    out[24..].copy_from_slice(&value.to_be_bytes());
+    out
+}
@@ -0,0 +1,302 @@
+//! Watch-only observer mode. Without a key the relayer still fetches, decodes, estimates and evaluates every
+//! transaction as if relaying, simulating from a funded address it doesn't control, and records what each
+//! profitable relay would have earned instead of submitting it. New blocks are then watched for someone else
+//! relaying the same user command, to tell how much of that would actually have been ours and what the
+//! competition paid in fees to land it first.
+
+use crate::mempool::{relay_selectors, relayed_signatures};
+use crate::orchestrator::GaslessTransaction;
+use crate::relay::{RelayContext, record_history};
+use clarity::Uint256;
+use log::{debug, info, warn};
+use num_traits::ToPrimitive;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use web30::jsonrpc::client::HttpClient;
+
+/// How long a competitor is watched for before the transaction is counted as not relayed by anyone
+const OBSERVATION_TTL: Duration = Duration::from_secs(3600);
+
+/// Upper bound on blocks scanned per cycle, so catching up after an outage can't stall the relay loop
+const MAX_BLOCKS_PER_CYCLE: u64 = 20;
+
+/// A relay we would have submitted
+#[derive(Debug, Clone)]
+struct Observation {
+    tx: GaslessTransaction,
+    /// net value in wei of the tip, if it was valued
+    tip_value: Option<Uint256>,
+    /// estimated cost in wei of our relay
+    gas_cost: Uint256,
+    observed: Instant,
+}
+
+impl Observation {
+    /// Tip value less the gas cost, zero if the tip wasn't valued
+    fn earnings(&self) -> Uint256 {
+        match self.tip_value {
+            Some(value) if value > self.gas_cost => value - self.gas_cost,
+            _ => 0u8.into(),
+        }
+    }
+}
+
+/// Totals since startup
+#[derive(Debug, Clone, Default)]
+struct ObserverTotals {
+    observed: u64,
+    would_earn: Uint256,
+    relayed_by_competitors: u64,
+    lost_to_competitors: Uint256,
+    competitor_fees: Uint256,
+    not_relayed: u64,
+}
+
+static OBSERVING: AtomicBool = AtomicBool::new(false);
+
+/// Observations still waiting for a competitor on each chain, by transaction id
+static OBSERVATIONS: LazyLock<Mutex<HashMap<u64, HashMap<String, Observation>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Last block scanned for competitors on each chain
+static SCANNED: LazyLock<Mutex<HashMap<u64, u64>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+static TOTALS: LazyLock<Mutex<ObserverTotals>> =
+    LazyLock::new(|| Mutex::new(ObserverTotals::default()));
+
+pub fn set_observing(observing: bool) {
+    OBSERVING.store(observing, Ordering::Relaxed);
+}
+
+/// True when running without a key, recording relays instead of submitting them
+pub fn is_observing() -> bool {
+    OBSERVING.load(Ordering::Relaxed)
+}
+
+/// Records that `tx` would have been relayed on `ctx` for an estimated `gas_cost`, once per transaction
+pub fn record_observation(
+    ctx: &RelayContext,
+    tx: &GaslessTransaction,
+    tip_value: Option<Uint256>,
+    gas_cost: Uint256,
+) {
+    let id = tx.id();
+    let mut observations = OBSERVATIONS.lock().unwrap();
+    let chain = observations.entry(ctx.chain_id).or_default();
+    if chain.contains_key(&id) {
+        return;
+    }
+    let observation = Observation {
+        tx: tx.clone(),
+        tip_value,
+        gas_cost,
+        observed: Instant::now(),
+    };
+    let earnings = observation.earnings();
+    info!(
+        tx:% = id, decision = "observed", gas_cost:% = gas_cost, earnings:% = earnings;
+        "Would have relayed {id} on {} for an estimated {gas_cost} wei of gas, earning {earnings} wei",
+        ctx.name
+    );
+    chain.insert(id, observation);
+    let mut totals = TOTALS.lock().unwrap();
+    totals.observed += 1;
+    totals.would_earn += earnings;
+}
+
+/// Scans the blocks on `ctx` since the last call for relays of observed transactions by someone else, and
+/// expires observations nobody relayed
+pub async fn check_observations(ctx: &RelayContext) {
+    if !is_observing() {
+        return;
+    }
+    expire_observations(ctx);
+    let Ok(head) = ctx.web3().eth_block_number().await else {
+        return;
+    };
+    let Some(head) = head.to_u64() else {
+        return;
+    };
+    // blocks from before startup can't hold relays of what we observe
+    let from = match SCANNED.lock().unwrap().insert(ctx.chain_id, head) {
+        Some(last) if last < head => (last + 1).max(head.saturating_sub(MAX_BLOCKS_PER_CYCLE - 1)),
+        _ => return,
+    };
+    let client = HttpClient::new(&ctx.web3().get_url());
+    let timeout = ctx.web3().get_timeout();
+    for number in from..=head {
+        if let Err(e) = scan_block(ctx, &client, timeout, number).await {
+            warn!(
+                "Failed to scan block {number} on {} for competing relays: {e}",
+                ctx.name
+            );
+        }
+    }
+}
+
+async fn scan_block(
+    ctx: &RelayContext,
+    client: &HttpClient,
+    timeout: Duration,
+    number: u64,
+) -> Result<(), String> {
+    if OBSERVATIONS
+        .lock()
+        .unwrap()
+        .get(&ctx.chain_id)
+        .is_none_or(HashMap::is_empty)
+    {
+        return Ok(());
+    }
+    let block: Value = client
+        .request_method(
+            "eth_getBlockByNumber",
+            vec![json!(format!("{number:#x}")), json!(true)],
+            timeout,
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+    let Some(txs) = block["transactions"].as_array() else {
+        return Ok(());
+    };
+    let selectors = relay_selectors(ctx);
+    for relay in txs {
+        let Some(input) = relay["input"].as_str() else {
+            continue;
+        };
+        let Ok(input) = hex::decode(input.strip_prefix("0x").unwrap_or(input)) else {
+            continue;
+        };
+        let signatures = relayed_signatures(&input, &selectors);
+        if signatures.is_empty() {
+            continue;
+        }
+        let found = OBSERVATIONS
+            .lock()
+            .unwrap()
+            .get(&ctx.chain_id)
+            .and_then(|chain| {
+                chain
+                    .values()
+                    .find(|o| !o.tx.sig.is_empty() && signatures.contains(&o.tx.sig))
+                    .cloned()
+            });
+        let Some(observation) = found else {
+            continue;
+        };
+        let receipt: Value = client
+            .request_method(
+                "eth_getTransactionReceipt",
+                vec![relay["hash"].clone()],
+                timeout,
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+        if receipt["status"].as_str() != Some("0x1") {
+            debug!(
+                "Competing relay {} of {} reverted, still watching",
+                relay["hash"],
+                observation.tx.id()
+            );
+            continue;
+        }
+        let uint = |value: &Value| {
+            value
+                .as_str()
+                .and_then(|v| Uint256::from_str(v).ok())
+                .unwrap_or_default()
+        };
+        let fee = uint(&receipt["gasUsed"]) * uint(&receipt["effectiveGasPrice"]);
+        let competitor = relay["from"].as_str().unwrap_or("?");
+        let id = observation.tx.id();
+        info!(
+            tx:% = id, decision = "observed_competitor", competitor = competitor, competitor_fee:% = fee;
+            "{id} was relayed on {} by {competitor} in block {number} for a fee of {fee} wei, it would have earned us {} wei",
+            ctx.name,
+            observation.earnings()
+        );
+        let tx_hash = relay["hash"]
+            .as_str()
+            .and_then(|h| Uint256::from_str(h).ok());
+        record_history(
+            ctx,
+            &observation.tx,
+            "observed_competitor",
+            tx_hash,
+            Some(fee),
+            None,
+            None,
+        );
+        if let Some(chain) = OBSERVATIONS.lock().unwrap().get_mut(&ctx.chain_id) {
+            chain.remove(&id);
+        }
+        let mut totals = TOTALS.lock().unwrap();
+        totals.relayed_by_competitors += 1;
+        totals.lost_to_competitors += observation.earnings();
+        totals.competitor_fees += fee;
+    }
+    Ok(())
+}
+
+/// Counts observations nobody relayed within `OBSERVATION_TTL` as not relayed
+fn expire_observations(ctx: &RelayContext) {
+    let expired: Vec<Observation> = {
+        let mut observations = OBSERVATIONS.lock().unwrap();
+        let Some(chain) = observations.get_mut(&ctx.chain_id) else {
+            return;
+        };
+        let expired = chain
+            .values()
+            .filter(|o| o.observed.elapsed() >= OBSERVATION_TTL)
+            .cloned()
+            .collect();
+        chain.retain(|_, o| o.observed.elapsed() < OBSERVATION_TTL);
+        expired
+    };
+    for observation in expired {
+        info!(
+            tx:% = observation.tx.id(), decision = "observed_unrelayed";
+            "{} was not relayed by anyone within {} minutes",
+            observation.tx.id(),
+            OBSERVATION_TTL.as_secs() / 60
+        );
+        record_history(
+            ctx,
+            &observation.tx,
+            "observed_unrelayed",
+            None,
+            None,
+            None,
+            None,
+        );
+        TOTALS.lock().unwrap().not_relayed += 1;
+    }
+}
+
+/// Logs the totals since startup, called at shutdown
+pub fn log_observer_summary() {
+    if !is_observing() {
+        return;
+    }
+    let totals = TOTALS.lock().unwrap().clone();
+    let pending: usize = OBSERVATIONS
+        .lock()
+        .unwrap()
+        .values()
+        .map(HashMap::len)
+        .sum();
+    info!(
+        "Observed {} relays worth {} wei net, {} relayed by competitors who paid {} wei in fees and took {} wei of it, {} not relayed by anyone, {} still unresolved",
+        totals.observed,
+        totals.would_earn,
+        totals.relayed_by_competitors,
+        totals.competitor_fees,
+        totals.lost_to_competitors,
+        totals.not_relayed,
+        pending
+    );
+}
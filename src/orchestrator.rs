@@ -0,0 +1,1116 @@
+use crate::backoff::retry_get;
+use crate::blob::{Blob, DecodeError};
+use crate::dns;
+use crate::error::RelayerError;
+use crate::grpc;
+use crate::layout::ContractLayout;
+use crate::proxy::{http_client, tls_connector_builder};
+use crate::retry::{blacklist, is_blacklisted};
+use crate::signer::Signer;
+use crate::status::record_malformed;
+use crate::throttle::{Outbound, throttle};
+use actix_web::dev::RequestHead;
+use awc::http::header::{self, HeaderValue};
+use awc::http::{Method, StatusCode};
+use awc::{Client as HttpClient, ClientRequest};
+use clarity::abi::{AbiToken, encode_tokens, get_hash, parse_u8};
+use clarity::utils::get_ethereum_msg_hash;
+use clarity::{Address, Signature};
+use futures::StreamExt;
+use futures::channel::mpsc::UnboundedSender;
+use futures::future::{self, Either};
+use futures::stream::FuturesUnordered;
+use log::{debug, error, info, trace, warn};
+use openssl::ssl::{SslConnector, SslFiletype};
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{Instrument, info_span};
+
+const EIP712_DOMAIN_TYPE: &str =
+    "EIP712Domain(string name,uint256 chainId,address verifyingContract)";
+const EIP712_DOMAIN_NAME: &str = "CrocSwap";
+const RELAYER_CALL_TYPE: &str = "CrocRelayerCall(uint8 callpath,bytes cmd,bytes conds,bytes tip)";
+
+pub const RELAYING_SERVICE_ROOT: &str = "orchestrator";
+/// Orchestrators with urls starting with this are spoken to over gRPC, see `proto/orchestrator.proto`
+pub const GRPC_SCHEME: &str = "grpc://";
+/// As `GRPC_SCHEME`, over TLS
+pub const GRPCS_SCHEME: &str = "grpcs://";
+/// If a subscription stream produces no data at all (including keepalive comments) for this long
+/// it is considered dead and the relayer falls back to polling while it reconnects
+pub const SUBSCRIPTION_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+/// How long to wait before attempting to re-establish a dropped subscription
+pub const SUBSCRIPTION_RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// Upper bound on pages fetched from one orchestrator ip in a single poll, protects against
+/// an orchestrator that never stops returning full pages
+pub const MAX_PENDING_PAGES: usize = 100;
+/// Versions of the `{"version": .., "txs": [..]}` envelope around pending transactions this relayer understands,
+/// advertised to orchestrators in `PAYLOAD_VERSION_HEADER`. Bare arrays of transactions are accepted as well.
+pub const PAYLOAD_VERSIONS: RangeInclusive<u32> = 1..=1;
+/// Header carrying the newest payload version the relayer understands
+pub const PAYLOAD_VERSION_HEADER: &str = "X-Relayer-Payload-Version";
+/// Longest `cmd`, `conds` or `tip` accepted from an orchestrator, far above anything the dex takes
+pub const MAX_FIELD_LEN: usize = 16 * 1024;
+
+/// Validators of the pending queue last served by an orchestrator ip, sent back to have it answer 304 Not
+/// Modified if the queue hasn't changed since, along with the queue itself to use again when it does
+#[derive(Debug, Clone, Default)]
+struct QueueValidators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    txs: Vec<GaslessTransaction>,
+}
+
+/// Pending transactions already fetched from an orchestrator ip when fetching incrementally
+#[derive(Debug, Clone)]
+struct WorkingSet {
+    /// `submitted_at` of the newest entry, the next poll asks for entries from then on
+    since: u64,
+    /// signature hashes of every entry fetched since the last full fetch
+    known: HashSet<[u8; 32]>,
+    last_full_fetch: Instant,
+}
+
+static CONDITIONAL_POLLING: AtomicBool = AtomicBool::new(true);
+
+/// How often the whole queue is fetched when fetching incrementally, `None` if every poll fetches it whole
+static INCREMENTAL_FETCH: LazyLock<Mutex<Option<Duration>>> = LazyLock::new(|| Mutex::new(None));
+
+static WORKING_SETS: LazyLock<Mutex<HashMap<(String, SocketAddr), WorkingSet>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+static QUEUE_VALIDATORS: LazyLock<Mutex<HashMap<(String, SocketAddr), QueueValidators>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Whether unpaged polls of the pending queue are conditional, on by default
+pub fn set_conditional_polling(enabled: bool) {
+    CONDITIONAL_POLLING.store(enabled, Ordering::Relaxed);
+}
+
+/// Fetches the pending queue incrementally, polls only ask for entries submitted since the newest one already
+/// fetched and the whole queue is fetched again every `full_fetch_interval`. `None` fetches it whole every time.
+pub fn set_incremental_fetch(full_fetch_interval: Option<Duration>) {
+    *INCREMENTAL_FETCH.lock().unwrap() = full_fetch_interval;
+}
+
+/// Forgets what earlier polls fetched, so that the next poll of every orchestrator returns its whole queue even
+/// if it hasn't changed. Used when transactions that were passed over may now be relayed.
+pub fn refetch_pending() {
+    QUEUE_VALIDATORS.lock().unwrap().clear();
+    WORKING_SETS.lock().unwrap().clear();
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GaslessTransaction {
+    pub chain_id: u64,
+    pub callpath: u16,
+    pub cmd: Vec<u8>,
+    pub conds: Vec<u8>,
+    pub tip: Vec<u8>,
+    pub sig: Vec<u8>,
+    pub submitted_at: u64,
+    /// periphery contract, such as a router, the transaction is relayed to instead of the dex
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<Address>,
+}
+
+/// Decoded `tip` of a gasless transaction, what the user pays the relayer and who receives it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RelayerTip {
+    pub token: Address,
+    pub amount: u128,
+    /// 0x100 pays `msg.sender`, 0x200 pays `tx.origin`, anything else pays that address. Forks of the dex may
+    /// use other markers, see `ContractLayout`
+    pub receiver: Address,
+}
+
+/// Decoded `conds` of a gasless transaction, under which the dex will accept the relay
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RelayConditions {
+    /// unix timestamp after which the command can no longer be relayed
+    pub deadline: u64,
+    /// unix timestamp before which the command can not be relayed yet
+    pub alive: u64,
+    pub salt: [u8; 32],
+    /// user nonce for `salt`, a command can only be relayed while it matches the on chain nonce
+    pub nonce: u32,
+    /// only this relayer may relay the command, zero if anyone may
+    pub relayer: Address,
+}
+
+impl RelayConditions {
+    /// True if the command can be relayed at unix time `now`, a zero deadline is treated as no deadline
+    pub fn is_live(&self, now: u64) -> bool {
+        now >= self.alive && (self.deadline == 0 || now <= self.deadline)
+    }
+}
+
+impl GaslessTransaction {
+    /// Decodes the `(address token, uint128 amount, address receiver)` tip of the iFi dex, tips of chains
+    /// configured with another layout are decoded by `RelayContext::decode_tip`
+    pub fn decode_tip(&self) -> Result<RelayerTip, DecodeError> {
+        ContractLayout::default().decode_tip(&self.tip)
+    }
+
+    /// Decodes the `(uint48 deadline, uint48 alive, bytes32 salt, uint32 nonce, address relayer)` conditions
+    pub fn decode_conditions(&self) -> Result<RelayConditions, DecodeError> {
+        let conds = Blob::new("conds", &self.conds, 5)?;
+        Ok(RelayConditions {
+            deadline: conds.uint(0, "deadline", 48)? as u64,
+            alive: conds.uint(1, "alive", 48)? as u64,
+            salt: conds.bytes32(2)?,
+            nonce: conds.uint(3, "nonce", 32)? as u32,
+            relayer: conds.address(4, "relayer")?,
+        })
+    }
+
+    /// Checks the invariants every transaction served by an orchestrator must hold before it is looked at any
+    /// closer. The tip and conditions are decoded strictly only when the transaction is relayed.
+    pub fn screen(&self) -> Result<(), String> {
+        if self.chain_id == 0 {
+            return Err("chain_id is 0".to_string());
+        }
+        // the dex signs and dispatches the callpath as a uint8
+        if self.callpath > u8::MAX.into() {
+            return Err(format!("callpath {} is out of range", self.callpath));
+        }
+        if self.sig.len() != 96 {
+            return Err(format!("sig is {} bytes, expected 96", self.sig.len()));
+        }
+        if self.cmd.is_empty() {
+            return Err("cmd is empty".to_string());
+        }
+        for (field, value) in [
+            ("cmd", &self.cmd),
+            ("conds", &self.conds),
+            ("tip", &self.tip),
+        ] {
+            if value.len() > MAX_FIELD_LEN {
+                return Err(format!(
+                    "{field} is {} bytes, at most {MAX_FIELD_LEN} are accepted",
+                    value.len()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Keccak256 hash of the user signature, every valid gasless transaction carries a unique signature
+    /// so this is used to identify transactions across orchestrators and poll cycles
+    pub fn sig_hash(&self) -> [u8; 32] {
+        get_hash(&self.sig)
+    }
+
+    /// Hex encoded signature hash, used as the transaction id in orchestrator requests
+    pub fn id(&self) -> String {
+        format!("0x{}", hex::encode(self.sig_hash()))
+    }
+
+    /// Recovers the address of the user who signed this transaction, mirroring the EIP-712 digest the dex at
+    /// `dex_address` checks in `userCmdRelayer`
+    pub fn client(&self, dex_address: Address) -> Result<Address, RelayerError> {
+        let content_hash = get_hash(&encode_tokens(&[
+            AbiToken::Bytes(get_hash(RELAYER_CALL_TYPE.as_bytes()).to_vec()),
+            AbiToken::Uint(self.callpath.into()),
+            AbiToken::Bytes(get_hash(&self.cmd).to_vec()),
+            AbiToken::Bytes(get_hash(&self.conds).to_vec()),
+            AbiToken::Bytes(get_hash(&self.tip).to_vec()),
+        ]));
+        recover_signer(
+            &self.sig,
+            &eip712_digest(self.chain_id, dex_address, &content_hash),
+        )
+    }
+}
+
+/// EIP-712 digest of a struct hashing to `content_hash`, signed for the dex at `dex_address` on `chain_id`
+pub(crate) fn eip712_digest(chain_id: u64, dex_address: Address, content_hash: &[u8]) -> Vec<u8> {
+    let domain_separator = get_hash(&encode_tokens(&[
+        AbiToken::Bytes(get_hash(EIP712_DOMAIN_TYPE.as_bytes()).to_vec()),
+        AbiToken::Bytes(get_hash(EIP712_DOMAIN_NAME.as_bytes()).to_vec()),
+        AbiToken::Uint(chain_id.into()),
+        AbiToken::Address(dex_address),
+    ]));
+    let mut digest = vec![0x19, 0x01];
+    digest.extend(domain_separator);
+    digest.extend(content_hash);
+    digest
+}
+
+/// Recovers who signed `digest`, the signature being abi encoded as (uint8 v, bytes32 r, bytes32 s)
+pub(crate) fn recover_signer(sig: &[u8], digest: &[u8]) -> Result<Address, RelayerError> {
+    if sig.len() != 96 {
+        return Err(RelayerError::invalid_tx(format!(
+            "Invalid signature length {}",
+            sig.len()
+        )));
+    }
+    let mut signature = sig[32..96].to_vec();
+    signature.push(parse_u8(sig, 0)?);
+    Ok(Signature::from_bytes(&signature)?.recover(&get_hash(digest))?)
+}
+
+/// Body of a claim request, identifies which relayer is reserving the transaction
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ClaimRequest {
+    pub relayer: String,
+}
+
+/// Header carrying a static api key for orchestrators that require one
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+/// Headers used by the signed challenge scheme, the relayer signs `<address>:<unix timestamp>` as an
+/// Ethereum signed message so the orchestrator can recover the relayer address and reject stale timestamps
+pub const RELAYER_ADDRESS_HEADER: &str = "X-Relayer-Address";
+pub const RELAYER_TIMESTAMP_HEADER: &str = "X-Relayer-Timestamp";
+pub const RELAYER_SIGNATURE_HEADER: &str = "X-Relayer-Signature";
+/// Header carrying the orchestrator's signature over the body of its response, an Ethereum signed message of
+/// `signed_payload`. Subscription events carry it as a `signature:` field instead.
+pub const ORCHESTRATOR_SIGNATURE_HEADER: &str = "X-Orchestrator-Signature";
+/// Header carrying the unix timestamp the orchestrator signed its response at. Subscription events carry it as a
+/// `timestamp:` field instead.
+pub const ORCHESTRATOR_TIMESTAMP_HEADER: &str = "X-Orchestrator-Timestamp";
+/// Most seconds a signed payload's timestamp may be away from our clock, so that a captured payload can't be
+/// replayed for longer than that
+const MAX_PAYLOAD_AGE: u64 = 300;
+
+/// Message an orchestrator signs to vouch for `payload` at `timestamp`, the body as sent before compression or the
+/// data of a subscription event, prefixed with the timestamp
+pub fn signed_payload(timestamp: u64, payload: &[u8]) -> Vec<u8> {
+    let mut message = format!("{timestamp}:").into_bytes();
+    message.extend_from_slice(payload);
+    message
+}
+
+/// Query parameters for the `/pending` endpoint, fields that are `None` are not sent
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PendingQuery {
+    /// maximum number of transactions per page, when set the relayer iterates pages
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u32>,
+    /// only return transactions with a `submitted_at` at or after this value
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub since: Option<u64>,
+    /// only return transactions for this chain id
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chain_id: Option<u64>,
+    /// long poll, the orchestrator holds the request for up to this long, such as `30s`, until the queue changes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wait: Option<String>,
+}
+
+/// How the relayer identifies itself to a specific orchestrator
+#[derive(Debug, Clone, Default)]
+pub struct OrchestratorAuth {
+    pub api_key: Option<String>,
+    pub signed_challenge: bool,
+}
+
+/// PEM files used for TLS connections to orchestrators, `client_cert` and `client_key` present a client
+/// certificate for orchestrators requiring mutual TLS and `ca_bundle` is trusted alongside the system trust roots
+#[derive(Debug, Clone, Default)]
+pub struct OrchestratorTls {
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+    pub ca_bundle: Option<String>,
+}
+
+impl OrchestratorTls {
+    /// Builds the TLS connector, `None` if nothing is configured and the default connector should be used
+    pub fn connector(&self) -> Result<Option<SslConnector>, String> {
+        if self.client_cert.is_none() && self.client_key.is_none() && self.ca_bundle.is_none() {
+            return Ok(None);
+        }
+        let mut builder = tls_connector_builder()?;
+        match (&self.client_cert, &self.client_key) {
+            (Some(cert), Some(key)) => {
+                builder
+                    .set_certificate_chain_file(cert)
+                    .map_err(|e| format!("Invalid client certificate {cert}: {e}"))?;
+                builder
+                    .set_private_key_file(key, SslFiletype::PEM)
+                    .map_err(|e| format!("Invalid client key {key}: {e}"))?;
+                builder
+                    .check_private_key()
+                    .map_err(|e| format!("Client key {key} does not match {cert}: {e}"))?;
+            }
+            (None, None) => {}
+            _ => return Err("A client certificate and key must be provided together".to_string()),
+        }
+        if let Some(ca_bundle) = &self.ca_bundle {
+            builder
+                .set_ca_file(ca_bundle)
+                .map_err(|e| format!("Invalid CA bundle {ca_bundle}: {e}"))?;
+        }
+        Ok(Some(builder.build()))
+    }
+}
+
+/// An orchestrator url along with any per orchestrator settings
+#[derive(Debug, Clone)]
+pub struct Orchestrator {
+    pub url: String,
+    pub auth: OrchestratorAuth,
+    /// page size and filters applied when fetching the pending queue
+    pub query: PendingQuery,
+    /// custom TLS configuration, the default connector is used if this is `None`
+    pub tls: Option<SslConnector>,
+    /// timeout of each request, a subscription stream may stay open indefinitely once established
+    pub timeout: Duration,
+    /// largest response body accepted once decompressed, in bytes, a subscription event is held to it too
+    pub max_response: usize,
+    /// address every pending queue payload must be signed by, see `ORCHESTRATOR_SIGNATURE_HEADER`
+    pub response_signer: Option<Address>,
+}
+
+impl Orchestrator {
+    /// Builds the orchestrator list from the configured urls, `api_keys` are `<URL>=<KEY>` pairs and
+    /// `signed_auth` is the list of urls that require a signed challenge
+    pub fn from_opts(
+        urls: &[String],
+        api_keys: &[String],
+        signed_auth: &[String],
+        query: PendingQuery,
+        tls: &OrchestratorTls,
+        timeout: Duration,
+        max_response: usize,
+    ) -> Result<Vec<Orchestrator>, String> {
+        let tls = tls.connector()?;
+        let mut keys = Vec::new();
+        for entry in api_keys {
+            match entry.split_once('=') {
+                Some((url, key)) => keys.push((url.trim_end_matches('/'), key)),
+                None => {
+                    return Err(format!(
+                        "Invalid orchestrator api key {entry}, expected <URL>=<KEY>"
+                    ));
+                }
+            }
+        }
+        let mut orchestrators = Vec::new();
+        for url in urls {
+            let normalized = url.trim_end_matches('/');
+            let api_key = keys
+                .iter()
+                .find(|(key_url, _)| *key_url == normalized)
+                .map(|(_, key)| key.to_string());
+            let signed_challenge = signed_auth
+                .iter()
+                .any(|signed_url| signed_url.trim_end_matches('/') == normalized);
+            orchestrators.push(Orchestrator {
+                url: url.clone(),
+                auth: OrchestratorAuth {
+                    api_key,
+                    signed_challenge,
+                },
+                query: query.clone(),
+                tls: tls.clone(),
+                timeout,
+                max_response,
+                response_signer: None,
+            });
+        }
+        Ok(orchestrators)
+    }
+
+    /// Requires the payloads of orchestrators to be signed, `entries` being `<URL>=<ADDRESS>` pairs of the
+    /// address each of them signs with
+    pub fn require_signed_responses(
+        orchestrators: &mut [Orchestrator],
+        entries: &[String],
+    ) -> Result<(), String> {
+        for entry in entries {
+            let (url, address) = entry.split_once('=').ok_or_else(|| {
+                format!("Invalid orchestrator signer {entry}, expected <URL>=<ADDRESS>")
+            })?;
+            let address: Address = address
+                .parse()
+                .map_err(|_| format!("Invalid orchestrator signer address {address}"))?;
+            let orchestrator = orchestrators
+                .iter_mut()
+                .find(|orchestrator| {
+                    orchestrator.url.trim_end_matches('/') == url.trim_end_matches('/')
+                })
+                .ok_or_else(|| {
+                    format!("Orchestrator signer given for unknown orchestrator {url}")
+                })?;
+            if orchestrator.is_grpc() {
+                return Err(format!(
+                    "Signed responses are only supported over HTTP, {url} is spoken to over gRPC"
+                ));
+            }
+            orchestrator.response_signer = Some(address);
+        }
+        Ok(())
+    }
+
+    /// Checks that `payload` served by this orchestrator carries a valid `signature` by its signer made at
+    /// `timestamp`, and that the timestamp is recent, if it has a signer
+    pub fn verify_payload(
+        &self,
+        payload: &[u8],
+        timestamp: Option<&str>,
+        signature: Option<&str>,
+    ) -> Result<(), RelayerError> {
+        let Some(response_signer) = self.response_signer else {
+            return Ok(());
+        };
+        let signature = signature.ok_or_else(|| RelayerError::orchestrator("Unsigned payload"))?;
+        let timestamp: u64 = timestamp
+            .ok_or_else(|| RelayerError::orchestrator("Signed payload carries no timestamp"))?
+            .trim()
+            .parse()
+            .map_err(|_| RelayerError::orchestrator("Invalid payload timestamp"))?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        if now.abs_diff(timestamp) > MAX_PAYLOAD_AGE {
+            return Err(RelayerError::orchestrator(format!(
+                "Payload signed at {timestamp} is stale, it is now {now}"
+            )));
+        }
+        let signature = hex::decode(signature.trim().trim_start_matches("0x"))
+            .map_err(|_| RelayerError::orchestrator("Payload signature is not hex"))?;
+        let message = signed_payload(timestamp, payload);
+        let signer = Signature::from_bytes(&signature)
+            .and_then(|signature| signature.recover(&get_ethereum_msg_hash(&message)))
+            .map_err(|e| RelayerError::orchestrator(format!("Invalid payload signature: {e}")))?;
+        if signer != response_signer {
+            return Err(RelayerError::orchestrator(format!(
+                "Payload signed by {signer} instead of {response_signer}"
+            )));
+        }
+        Ok(())
+    }
+
+    /// HTTP client for requests to this orchestrator, using its TLS configuration if it has one
+    pub fn http_client(&self) -> HttpClient {
+        http_client(self.tls.as_ref(), self.timeout)
+    }
+
+    /// Authentication headers to send to this orchestrator as (name, value) pairs
+    pub async fn auth_headers(
+        &self,
+        signer: &Signer,
+    ) -> Result<Vec<(&'static str, String)>, RelayerError> {
+        let mut headers = Vec::new();
+        if let Some(api_key) = &self.auth.api_key {
+            headers.push((API_KEY_HEADER, api_key.clone()));
+        }
+        if self.auth.signed_challenge {
+            let address = signer.address();
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            let signature = signer
+                .sign_message(format!("{address}:{timestamp}").as_bytes())
+                .await?;
+            headers.push((RELAYER_ADDRESS_HEADER, address.to_string()));
+            headers.push((RELAYER_TIMESTAMP_HEADER, timestamp.to_string()));
+            headers.push((
+                RELAYER_SIGNATURE_HEADER,
+                format!("0x{}", hex::encode(signature.to_bytes())),
+            ));
+        }
+        Ok(headers)
+    }
+
+    /// Attaches the configured authentication headers to a request for this orchestrator, once the request is
+    /// within the orchestrator's ceiling
+    pub async fn authenticate(
+        &self,
+        mut request: ClientRequest,
+        signer: &Signer,
+    ) -> Result<ClientRequest, RelayerError> {
+        throttle(Outbound::Orchestrator, &self.url).await;
+        for header in self.auth_headers(signer).await? {
+            request = request.insert_header(header);
+        }
+        Ok(request)
+    }
+
+    /// Whether this orchestrator is reached over gRPC rather than HTTP
+    pub fn is_grpc(&self) -> bool {
+        self.url.starts_with(GRPC_SCHEME) || self.url.starts_with(GRPCS_SCHEME)
+    }
+}
+
+/// Pending transactions as served by an orchestrator, either wrapped in a versioned envelope or, as orchestrators
+/// predating the envelope serve them, bare. A single server sent event may also carry just one transaction.
+/// Entries of a queue are kept as json until screened so that one bad entry doesn't cost the others. Anything else,
+/// such as an error object served with a success status, is an error rather than an empty queue.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum PendingPayload {
+    Versioned {
+        version: u32,
+        txs: Vec<serde_json::Value>,
+    },
+    Many(Vec<serde_json::Value>),
+    Failed {
+        error: serde_json::Value,
+    },
+    One(Box<GaslessTransaction>),
+}
+
+/// Parses a pending queue payload from `orchestrator`, failing if it is wrapped in an envelope version this
+/// relayer doesn't understand. Entries that don't deserialize or fail `screen` are dropped and reported, see
+/// `screen_pending`.
+fn parse_pending(
+    orchestrator: &Orchestrator,
+    chain_id: Option<u64>,
+    body: &[u8],
+) -> Result<Vec<GaslessTransaction>, RelayerError> {
+    let entries = match serde_json::from_slice(body).map_err(RelayerError::orchestrator)? {
+        PendingPayload::Versioned { version, txs } if PAYLOAD_VERSIONS.contains(&version) => txs,
+        PendingPayload::Versioned { version, .. } => {
+            return Err(RelayerError::orchestrator(format!(
+                "Unsupported payload version {version}, this relayer understands versions {} to {}, it may need \
+                 upgrading",
+                PAYLOAD_VERSIONS.start(),
+                PAYLOAD_VERSIONS.end()
+            )));
+        }
+        PendingPayload::Many(txs) => txs,
+        PendingPayload::Failed { error } => {
+            return Err(RelayerError::orchestrator(format!(
+                "Orchestrator returned an error instead of pending transactions: {error}"
+            )));
+        }
+        PendingPayload::One(tx) => return Ok(screen_pending(orchestrator, chain_id, vec![*tx])),
+    };
+    #[cfg(feature = "testing")]
+    let entries = crate::chaos::malform_pending(entries);
+    let mut txs = Vec::with_capacity(entries.len());
+    for (index, entry) in entries.into_iter().enumerate() {
+        match serde_json::from_value::<GaslessTransaction>(entry) {
+            Ok(tx) => txs.push(tx),
+            Err(e) => {
+                warn!(
+                    orchestrator:% = orchestrator.url;
+                    "Dropping pending entry {index} that isn't a gasless transaction: {e}"
+                );
+                record_malformed(&orchestrator.url, format!("entry {index}"), e.to_string());
+            }
+        }
+    }
+    Ok(screen_pending(orchestrator, chain_id, txs))
+}
+
+/// Drops the transactions served by `orchestrator` for another chain than `chain_id`, if it was asked for one,
+/// and those that fail `GaslessTransaction::screen`. The latter are reported as malformed and blacklisted, so each
+/// is only reported the first time it is served.
+pub(crate) fn screen_pending(
+    orchestrator: &Orchestrator,
+    chain_id: Option<u64>,
+    txs: Vec<GaslessTransaction>,
+) -> Vec<GaslessTransaction> {
+    txs.into_iter()
+        .filter(|tx| {
+            // the transaction may well be fine, the orchestrator just didn't filter its queue as asked
+            let asked_for = chain_id.is_none_or(|chain_id| tx.chain_id == chain_id);
+            if !asked_for {
+                debug!(
+                    orchestrator:% = orchestrator.url,
+                    tx:% = tx.id();
+                    "Ignoring pending transaction for chain {}", tx.chain_id
+                );
+            }
+            asked_for
+        })
+        .filter(|tx| match tx.screen() {
+            Ok(()) => true,
+            Err(_) if is_blacklisted(tx) => false,
+            Err(error) => {
+                warn!(
+                    orchestrator:% = orchestrator.url,
+                    tx:% = tx.id(),
+                    decision = "rejected";
+                    "Dropping pending transaction that fails validation: {error}"
+                );
+                record_malformed(&orchestrator.url, tx.id(), error);
+                blacklist(tx);
+                false
+            }
+        })
+        .collect()
+}
+
+/// Resolves the addresses of the orchestrator url so that each can be queried, the one that last answered first.
+/// Lookups are cached and addresses may be pinned, see the `dns` module.
+pub fn resolve_orchestrator(orchestrator_url: &str) -> Result<Vec<SocketAddr>, String> {
+    dns::resolve(orchestrator_url)
+}
+
+/// Fetches the pending transaction queue of `orchestrator`, trying its addresses in turn until one answers. Every
+/// address serves the same queue, so the others are only there to fail over to. As in happy eyeballs, the next
+/// address is tried as soon as one fails or alongside it once it has gone unanswered for the attempt delay, and
+/// whichever answers first is used. Long polls are held open on purpose, so they only move on once one fails.
+pub async fn fetch_with_failover(
+    orchestrator: &Orchestrator,
+    signer: &Signer,
+) -> Result<Vec<GaslessTransaction>, RelayerError> {
+    let ips = resolve_orchestrator(&orchestrator.url).map_err(RelayerError::orchestrator)?;
+    let delay = orchestrator.query.wait.is_none().then(dns::attempt_delay);
+    let attempt = |ip: SocketAddr| async move {
+        let result = fetch_pending_transactions(orchestrator, ip, signer)
+            .instrument(info_span!("fetch", orchestrator = %orchestrator.url, %ip))
+            .await;
+        (ip, result)
+    };
+    let mut remaining = ips.into_iter();
+    let mut attempts = FuturesUnordered::new();
+    let mut error = RelayerError::orchestrator("No addresses for orchestrator");
+    loop {
+        match remaining.next() {
+            Some(ip) => attempts.push(attempt(ip)),
+            None if attempts.is_empty() => return Err(error),
+            None => {}
+        }
+        let stagger = match delay {
+            Some(delay) if remaining.len() > 0 => Either::Left(actix_rt::time::sleep(delay)),
+            _ => Either::Right(future::pending()),
+        };
+        // every branch moves on to the next address, if there is one
+        tokio::select! {
+            finished = attempts.next() => match finished {
+                Some((ip, Ok(txs))) => {
+                    dns::record_answer(&orchestrator.url, ip);
+                    return Ok(txs);
+                }
+                Some((ip, Err(e))) => {
+                    debug!("Orchestrator {} failed at {ip}: {e}", orchestrator.url);
+                    error = e;
+                }
+                None => {}
+            },
+            _ = stagger => {}
+        }
+    }
+}
+
+/// Fetches the pending transaction queue from a specific IP of the orchestrator. When fetching incrementally
+/// only the entries that are new since the last poll of `ip` are returned, see `set_incremental_fetch`.
+pub async fn fetch_pending_transactions(
+    orchestrator: &Orchestrator,
+    ip: SocketAddr,
+    signer: &Signer,
+) -> Result<Vec<GaslessTransaction>, RelayerError> {
+    debug!("Orchestrator IP: {ip:?}");
+    let Some(full_fetch_interval) = *INCREMENTAL_FETCH.lock().unwrap() else {
+        return fetch_queue(orchestrator, ip, signer, orchestrator.query.clone()).await;
+    };
+    let key = (orchestrator.url.clone(), ip);
+    let since = WORKING_SETS
+        .lock()
+        .unwrap()
+        .get(&key)
+        .filter(|set| set.last_full_fetch.elapsed() < full_fetch_interval)
+        .map(|set| set.since);
+    let mut query = orchestrator.query.clone();
+    if let Some(since) = since {
+        query.since = Some(query.since.unwrap_or_default().max(since));
+    }
+    let txs = fetch_queue(orchestrator, ip, signer, query).await?;
+    let newest = txs.iter().map(|tx| tx.submitted_at).max();
+    let mut sets = WORKING_SETS.lock().unwrap();
+    match (since, sets.get_mut(&key)) {
+        (Some(since), Some(set)) => {
+            set.since = newest.unwrap_or(since).max(since);
+            // the cursor is inclusive, entries sharing the boundary timestamp come back every time
+            let txs: Vec<GaslessTransaction> = txs
+                .into_iter()
+                .filter(|tx| set.known.insert(tx.sig_hash()))
+                .collect();
+            debug!("{} new pending transactions since {since}", txs.len());
+            Ok(txs)
+        }
+        _ => {
+            // a full fetch resyncs the working set, dropping entries the orchestrator no longer has
+            sets.insert(
+                key,
+                WorkingSet {
+                    since: newest.unwrap_or_default(),
+                    known: txs.iter().map(GaslessTransaction::sig_hash).collect(),
+                    last_full_fetch: Instant::now(),
+                },
+            );
+            Ok(txs)
+        }
+    }
+}
+
+/// Fetches the pending queue matching `query`, if a page size is configured pages are requested using the
+/// `submitted_at` of the newest entry seen so far as the `since` cursor until a short page is returned
+async fn fetch_queue(
+    orchestrator: &Orchestrator,
+    ip: SocketAddr,
+    signer: &Signer,
+    mut query: PendingQuery,
+) -> Result<Vec<GaslessTransaction>, RelayerError> {
+    let limit = match query.limit {
+        Some(limit) => limit as usize,
+        None => return fetch_pending_page(orchestrator, ip, signer, &query).await,
+    };
+
+    let mut seen = HashSet::new();
+    let mut txs = Vec::new();
+    for page in 0..MAX_PENDING_PAGES {
+        let page_txs = fetch_pending_page(orchestrator, ip, signer, &query).await?;
+        let page_len = page_txs.len();
+        let mut new_entries = 0;
+        for tx in page_txs {
+            query.since = Some(query.since.unwrap_or_default().max(tx.submitted_at));
+            // the cursor is inclusive so entries sharing the boundary timestamp are returned again
+            if seen.insert(tx.sig_hash()) {
+                new_entries += 1;
+                txs.push(tx);
+            }
+        }
+        trace!("Fetched page {page} with {page_len} entries, {new_entries} new");
+        if page_len < limit || new_entries == 0 {
+            break;
+        }
+    }
+    debug!("Found {} pending transactions across all pages", txs.len());
+    Ok(txs)
+}
+
+/// Fetches a single page of the pending queue. An unpaged queue is requested conditionally, if the orchestrator
+/// answers that it hasn't changed since the last poll of `ip` the queue that poll returned is returned again, its
+/// transactions are still pending.
+async fn fetch_pending_page(
+    orchestrator: &Orchestrator,
+    ip: SocketAddr,
+    signer: &Signer,
+    query: &PendingQuery,
+) -> Result<Vec<GaslessTransaction>, RelayerError> {
+    if orchestrator.is_grpc() {
+        return grpc::fetch_pending_page(orchestrator, ip, signer, query).await;
+    }
+    let mut request_head = RequestHead::default();
+    request_head.peer_addr = Some(ip);
+    request_head.method = Method::GET;
+
+    let client = orchestrator.http_client();
+    let url = format!("{}/{RELAYING_SERVICE_ROOT}/pending", orchestrator.url);
+    // a cursor moves between pages and polls, so only a queue fetched whole can be compared against the last poll
+    let conditional = query.limit.is_none()
+        && CONDITIONAL_POLLING.load(Ordering::Relaxed)
+        && INCREMENTAL_FETCH.lock().unwrap().is_none();
+    let key = (orchestrator.url.clone(), ip);
+    let mut response = retry_get(Outbound::Orchestrator, &url, || async {
+        let request = client
+            .request_from(&url, &request_head)
+            // connect to this address rather than whatever the host resolves to, the url still sets Host and SNI
+            .address(ip)
+            .query(query)
+            .map_err(RelayerError::orchestrator)?
+            .insert_header((PAYLOAD_VERSION_HEADER, PAYLOAD_VERSIONS.end().to_string()));
+        let mut request = orchestrator.authenticate(request, signer).await?;
+        if conditional && let Some(validators) = QUEUE_VALIDATORS.lock().unwrap().get(&key) {
+            if let Some(etag) = &validators.etag {
+                request = request.insert_header((header::IF_NONE_MATCH, etag.clone()));
+            }
+            if let Some(last_modified) = &validators.last_modified {
+                request = request.insert_header((header::IF_MODIFIED_SINCE, last_modified.clone()));
+            }
+        }
+        request.send().await.map_err(RelayerError::orchestrator)
+    })
+    .await?;
+
+    if response.status() == StatusCode::NOT_MODIFIED {
+        debug!("Pending queue unchanged since the last poll");
+        let validators = QUEUE_VALIDATORS.lock().unwrap();
+        return Ok(validators
+            .get(&key)
+            .map(|validators| validators.txs.clone())
+            .unwrap_or_default());
+    }
+    if !response.status().is_success() {
+        let body = response
+            .body()
+            .limit(orchestrator.max_response)
+            .await
+            .map_err(RelayerError::orchestrator)?;
+        let error_text = String::from_utf8_lossy(&body);
+        error!(
+            orchestrator:% = orchestrator.url;
+            "Failed to fetch pending transactions: {error_text}"
+        );
+        return Err(RelayerError::orchestrator(error_text));
+    }
+
+    // awc negotiates compression and decodes the body as it streams in, the limit applies to the decoded body
+    let body = response
+        .body()
+        .limit(orchestrator.max_response)
+        .await
+        .map_err(RelayerError::orchestrator)?;
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value: &HeaderValue| value.to_str().ok())
+    };
+    orchestrator.verify_payload(
+        &body,
+        header(ORCHESTRATOR_TIMESTAMP_HEADER),
+        header(ORCHESTRATOR_SIGNATURE_HEADER),
+    )?;
+    let txs = parse_pending(orchestrator, query.chain_id, &body)?;
+    debug!("Found {} pending transactions", txs.len());
+    if conditional {
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value: &HeaderValue| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let validators = QueueValidators {
+            etag: header(header::ETAG),
+            last_modified: header(header::LAST_MODIFIED),
+            txs: txs.clone(),
+        };
+        QUEUE_VALIDATORS.lock().unwrap().insert(key, validators);
+    }
+    Ok(txs)
+}
+
+/// Attempts to reserve a transaction with the orchestrator it came from by POSTing to `/claim/{id}`, so that
+/// other relayers don't waste gas simulating and racing it. Returns true only if the claim was granted.
+pub async fn claim_transaction(
+    orchestrator: &Orchestrator,
+    tx: &GaslessTransaction,
+    signer: &Signer,
+) -> bool {
+    if orchestrator.is_grpc() {
+        return grpc::claim_transaction(orchestrator, tx, signer).await;
+    }
+    let url = format!(
+        "{}/{RELAYING_SERVICE_ROOT}/claim/{}",
+        orchestrator.url,
+        tx.id()
+    );
+    let client = orchestrator.http_client();
+    let request = match orchestrator.authenticate(client.post(url), signer).await {
+        Ok(request) => request,
+        Err(e) => {
+            warn!("Failed to authenticate claim for {}: {e}", tx.id());
+            return false;
+        }
+    };
+    let claim = ClaimRequest {
+        relayer: signer.address().to_string(),
+    };
+    match request.send_json(&claim).await {
+        Ok(response) if response.status().is_success() => true,
+        Ok(response) => {
+            debug!("Claim for {} rejected with {}", tx.id(), response.status());
+            false
+        }
+        Err(e) => {
+            warn!("Failed to claim transaction {}: {e}", tx.id());
+            false
+        }
+    }
+}
+
+/// Long polls the orchestrator's pending queue until `sender` is closed, forwarding the queue into `sender`
+/// whenever it changes. Each request asks the orchestrator to hold it for up to `wait` until the queue differs
+/// from the validators of the last response, the IPs of the orchestrator taking turns. An orchestrator that
+/// answers straight away without anything new is only asked again after `idle_delay`, in case it doesn't
+/// support long polling. `connected` is kept up to date so that the main loop polls it while long polls fail.
+pub async fn long_poll_loop(
+    orchestrator: Orchestrator,
+    signer: Signer,
+    sender: UnboundedSender<(Orchestrator, GaslessTransaction)>,
+    connected: Rc<Cell<bool>>,
+    wait: Duration,
+    idle_delay: Duration,
+) {
+    if orchestrator.is_grpc() {
+        warn!(
+            "Long polling is only supported over http, polling {} instead",
+            orchestrator.url
+        );
+        return;
+    }
+    let mut long_poll = orchestrator.clone();
+    long_poll.query.wait = Some(format!("{}s", wait.as_secs()));
+    // the request is held for up to `wait` before the orchestrator even starts answering
+    long_poll.timeout += wait;
+    let mut last: HashSet<[u8; 32]> = HashSet::new();
+    // the first request carries no validators yet, so there is nothing for the orchestrator to hold it against
+    let mut first = true;
+    while !sender.is_closed() {
+        let start = Instant::now();
+        let result = fetch_with_failover(&long_poll, &signer).await;
+        let txs = match result {
+            Ok(txs) => txs,
+            Err(e) => {
+                warn!(
+                    "Long poll of {} failed, falling back to polling: {e}",
+                    orchestrator.url
+                );
+                connected.set(false);
+                actix_rt::time::sleep(SUBSCRIPTION_RECONNECT_DELAY).await;
+                continue;
+            }
+        };
+        if !connected.replace(true) {
+            info!("Long polling {}", orchestrator.url);
+        }
+        // an empty response is most likely the queue being unchanged
+        let changed = txs.iter().any(|tx| !last.contains(&tx.sig_hash()));
+        if !txs.is_empty() {
+            last = txs.iter().map(GaslessTransaction::sig_hash).collect();
+        }
+        if changed {
+            debug!(
+                "Long poll of {} returned {} transactions",
+                orchestrator.url,
+                txs.len()
+            );
+            for tx in txs {
+                if sender.unbounded_send((orchestrator.clone(), tx)).is_err() {
+                    return;
+                }
+            }
+        } else if !first && start.elapsed() < wait {
+            actix_rt::time::sleep(idle_delay).await;
+        }
+        first = false;
+    }
+}
+
+/// Maintains a server sent events subscription to the orchestrator's `/subscribe` endpoint forever, forwarding
+/// every pushed transaction into `sender` tagged with the orchestrator it came from. `connected` is kept up to date so that the main loop knows when it must
+/// fall back to polling this orchestrator.
+pub async fn subscribe_loop(
+    orchestrator: Orchestrator,
+    signer: Signer,
+    sender: UnboundedSender<(Orchestrator, GaslessTransaction)>,
+    connected: Rc<Cell<bool>>,
+) {
+    let orchestrator_url = &orchestrator.url;
+    loop {
+        if let Err(e) = subscribe(&orchestrator, &signer, &sender, &connected).await {
+            warn!("Subscription to {orchestrator_url} failed, falling back to polling: {e}");
+        } else {
+            warn!("Subscription to {orchestrator_url} closed, falling back to polling");
+        }
+        connected.set(false);
+        if sender.is_closed() {
+            return;
+        }
+        actix_rt::time::sleep(SUBSCRIPTION_RECONNECT_DELAY).await;
+    }
+}
+
+async fn subscribe(
+    orchestrator: &Orchestrator,
+    signer: &Signer,
+    sender: &UnboundedSender<(Orchestrator, GaslessTransaction)>,
+    connected: &Cell<bool>,
+) -> Result<(), RelayerError> {
+    if orchestrator.is_grpc() {
+        return grpc::subscribe(orchestrator, signer, sender, connected).await;
+    }
+    let orchestrator_url = &orchestrator.url;
+    let url = format!("{orchestrator_url}/{RELAYING_SERVICE_ROOT}/subscribe");
+    debug!("Opening subscription to {url}");
+
+    let client = orchestrator.http_client();
+    let request = client
+        .get(url)
+        .insert_header(("Accept", "text/event-stream"))
+        .insert_header((PAYLOAD_VERSION_HEADER, PAYLOAD_VERSIONS.end().to_string()));
+    let mut response = orchestrator
+        .authenticate(request, signer)
+        .await?
+        .send()
+        .await
+        .map_err(RelayerError::orchestrator)?;
+
+    if !response.status().is_success() {
+        let body = response
+            .body()
+            .limit(orchestrator.max_response)
+            .await
+            .map_err(RelayerError::orchestrator)?;
+        return Err(RelayerError::orchestrator(String::from_utf8_lossy(&body)));
+    }
+    info!("Subscribed to {orchestrator_url}");
+    connected.set(true);
+
+    let mut buffer = String::new();
+    loop {
+        let chunk = match actix_rt::time::timeout(SUBSCRIPTION_IDLE_TIMEOUT, response.next()).await
+        {
+            Ok(Some(chunk)) => chunk.map_err(RelayerError::orchestrator)?,
+            Ok(None) => return Ok(()),
+            Err(_) => return Err(RelayerError::orchestrator("Subscription idle timeout")),
+        };
+        buffer.push_str(&String::from_utf8_lossy(&chunk));
+        // normalize line endings so events are always separated by a blank line
+        if buffer.contains('\r') {
+            buffer = buffer.replace("\r\n", "\n");
+        }
+
+        while let Some(end) = buffer.find("\n\n") {
+            let event: String = buffer.drain(..end + 2).collect();
+            for tx in parse_event(orchestrator, &event) {
+                if sender.unbounded_send((orchestrator.clone(), tx)).is_err() {
+                    return Ok(());
+                }
+            }
+        }
+        // whatever is left is an event still coming in
+        if buffer.len() > orchestrator.max_response {
+            return Err(RelayerError::orchestrator(format!(
+                "Subscription event exceeds {} bytes",
+                orchestrator.max_response
+            )));
+        }
+    }
+}
+
+/// Parses a single server sent event block, lines starting with ':' are keepalive comments
+/// and all `data:` lines are concatenated into one json payload, signed by the `signature:` line at the
+/// `timestamp:` line if any
+fn parse_event(orchestrator: &Orchestrator, event: &str) -> Vec<GaslessTransaction> {
+    let data: Vec<&str> = event
+        .lines()
+        .filter_map(|line| line.strip_prefix("data:"))
+        .map(|line| line.trim_start())
+        .collect();
+    if data.is_empty() {
+        return Vec::new();
+    }
+    let field = |name| event.lines().find_map(|line: &str| line.strip_prefix(name));
+    let data = data.join("\n");
+    if let Err(e) =
+        orchestrator.verify_payload(data.as_bytes(), field("timestamp:"), field("signature:"))
+    {
+        error!("Rejected subscription event: {e}");
+        return Vec::new();
+    }
+    match parse_pending(orchestrator, None, data.as_bytes()) {
+        Ok(txs) => txs,
+        Err(e) => {
+            error!("Failed to parse subscription event: {e}");
+            Vec::new()
+        }
+    }
+}
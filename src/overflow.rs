@@ -0,0 +1,105 @@
+//! Cap on the transactions evaluated per relay cycle. A flood of submissions could otherwise make one cycle take
+//! minutes, starving the confirmation and fee bump checks that run between cycles. Transactions are evaluated
+//! most profitable first until the cap is reached, the rest are deferred to an overflow backlog that is worked
+//! through in later cycles alongside whatever the orchestrators serve then.
+
+use crate::orchestrator::{GaslessTransaction, Orchestrator};
+use log::{debug, info};
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+/// Deferred transactions are dropped after this long, if still pending their orchestrator serves them again
+const OVERFLOW_TTL: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Clone)]
+pub struct Deferred {
+    pub tx: GaslessTransaction,
+    /// orchestrator the transaction came from, if any
+    pub orchestrator: Option<Orchestrator>,
+    deferred_at: Instant,
+}
+
+#[derive(Debug, Default)]
+struct CycleCap {
+    max_per_cycle: Option<usize>,
+    /// evaluations left this cycle
+    remaining: usize,
+    /// transactions newly deferred this cycle, logged when the next one starts
+    deferred_this_cycle: usize,
+    backlog: HashMap<[u8; 32], Deferred>,
+}
+
+static CYCLE_CAP: LazyLock<Mutex<CycleCap>> = LazyLock::new(|| Mutex::new(CycleCap::default()));
+
+/// Evaluate at most `max` transactions per cycle, None for no cap
+pub fn set_max_txs_per_cycle(max: Option<usize>) {
+    let mut cap = CYCLE_CAP.lock().unwrap();
+    cap.max_per_cycle = max;
+    cap.remaining = max.unwrap_or_default();
+}
+
+/// Resets the evaluations left at the start of each relay cycle
+pub fn start_cycle() {
+    let mut cap = CYCLE_CAP.lock().unwrap();
+    let Some(max) = cap.max_per_cycle else {
+        return;
+    };
+    if cap.deferred_this_cycle > 0 {
+        info!(
+            "Deferred {} transactions over the cap of {max} per cycle, {} waiting in the overflow backlog",
+            cap.deferred_this_cycle,
+            cap.backlog.len()
+        );
+    }
+    cap.remaining = max;
+    cap.deferred_this_cycle = 0;
+}
+
+/// Evaluations left this cycle, unbounded without a cap
+pub fn remaining() -> usize {
+    let cap = CYCLE_CAP.lock().unwrap();
+    match cap.max_per_cycle {
+        Some(_) => cap.remaining,
+        None => usize::MAX,
+    }
+}
+
+/// True if `tx` may be evaluated this cycle, which counts against the cap. Otherwise it is deferred to the
+/// overflow backlog.
+pub fn admit(tx: &GaslessTransaction, orchestrator: Option<&Orchestrator>) -> bool {
+    let mut cap = CYCLE_CAP.lock().unwrap();
+    if cap.max_per_cycle.is_none() {
+        return true;
+    }
+    let key = tx.sig_hash();
+    if cap.remaining > 0 {
+        cap.remaining -= 1;
+        cap.backlog.remove(&key);
+        return true;
+    }
+    debug!(
+        tx:% = tx.id(), decision = "deferred";
+        "Cycle cap reached, deferring transaction {} to the next cycle",
+        tx.id()
+    );
+    if let Entry::Vacant(entry) = cap.backlog.entry(key) {
+        entry.insert(Deferred {
+            tx: tx.clone(),
+            orchestrator: orchestrator.cloned(),
+            deferred_at: Instant::now(),
+        });
+        cap.deferred_this_cycle += 1;
+    }
+    false
+}
+
+/// Transactions in the overflow backlog, dropping those deferred longer than `OVERFLOW_TTL`. They stay in the
+/// backlog until admitted.
+pub fn deferred() -> Vec<Deferred> {
+    let mut cap = CYCLE_CAP.lock().unwrap();
+    cap.backlog
+        .retain(|_, deferred| deferred.deferred_at.elapsed() < OVERFLOW_TTL);
+    cap.backlog.values().cloned().collect()
+}
@@ -0,0 +1,83 @@
+//! Verification that relayed tips were actually paid. The value of a relay is worked out from the tip as decoded
+//! from the gasless transaction, so if the dex, a periphery contract or the tip token behaves differently than
+//! assumed, or the tip is decoded wrong, the relayer would keep paying for relays that earn nothing. Once a relay
+//! is final the ERC-20 `Transfer` logs of its receipt are checked to hold the tip. Native tips don't emit logs
+//! and are not checked.
+
+use crate::confirm::WatchedRelay;
+use crate::relay::RelayContext;
+use clarity::abi::get_hash;
+use clarity::{Address, Uint256};
+use num_traits::ToPrimitive;
+use web30::types::Log;
+
+/// Tip a final relay was owed but that its receipt doesn't show being paid
+#[derive(Debug, Clone, Copy)]
+pub struct MissingTip {
+    pub token: Address,
+    pub recipient: Address,
+    /// total the relays of the batch were owed in `token` at `recipient`
+    pub expected: u128,
+    /// total the receipt shows being transferred
+    pub received: u128,
+}
+
+/// Account a tip to `receiver` ends up with when relayed by `signer`
+fn tip_recipient(ctx: &RelayContext, receiver: Address, signer: Address) -> Address {
+    if ctx.layout.pays_any_sender(receiver) {
+        signer
+    } else {
+        receiver
+    }
+}
+
+/// Total of `token` that the `Transfer` events in `logs` moved to `recipient`
+fn transferred(logs: &[Log], token: Address, recipient: Address) -> u128 {
+    let topic = get_hash(b"Transfer(address,address,uint256)");
+    logs.iter()
+        .filter(|log| log.address == token && log.removed != Some(true))
+        .filter(|log| {
+            log.topics.len() == 3
+                && log.topics[0].0 == topic
+                && log.topics[2].0.get(12..) == Some(recipient.as_bytes())
+        })
+        .map(|log| {
+            Uint256::from_be_bytes(&log.data.0)
+                .to_u128()
+                .unwrap_or(u128::MAX)
+        })
+        .fold(0u128, u128::saturating_add)
+}
+
+/// Checks the receipt `logs` of the final relay `watched` for its tip. Every relay of a batch shares the receipt,
+/// so the tips `batch` owes the same account in the same token are checked together.
+pub fn missing_tip(
+    ctx: &RelayContext,
+    watched: &WatchedRelay,
+    batch: &[WatchedRelay],
+    logs: &[Log],
+) -> Option<MissingTip> {
+    let tip = ctx.decode_tip(&watched.tx).ok()?;
+    if tip.amount == 0 || tip.token == Address::default() {
+        return None;
+    }
+    let recipient = tip_recipient(ctx, tip.receiver, watched.signer.address());
+    let expected = batch
+        .iter()
+        .filter(|relay| relay.tx_hash == watched.tx_hash)
+        .filter_map(|relay| Some((relay, ctx.decode_tip(&relay.tx).ok()?)))
+        .filter(|(relay, other)| {
+            other.token == tip.token
+                && tip_recipient(ctx, other.receiver, relay.signer.address()) == recipient
+        })
+        .fold(0u128, |total, (_, other)| {
+            total.saturating_add(other.amount)
+        });
+    let received = transferred(logs, tip.token, recipient);
+    (received < expected).then_some(MissingTip {
+        token: tip.token,
+        recipient,
+        expected,
+        received,
+    })
+}
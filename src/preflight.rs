@@ -0,0 +1,272 @@
+//! Preflight checks of the configuration against the outside world. A wrong contract address, an RPC endpoint on
+//! the wrong chain or a price API that doesn't answer would otherwise only show up as the same error on every
+//! relay cycle. These checks run before relaying starts, refusing to start if any fails, and on their own with
+//! the `check` subcommand. Each failure says what to fix.
+
+use crate::orchestrator::{Orchestrator, fetch_with_failover, resolve_orchestrator};
+use crate::price::{TokenAmount, token_decimals};
+use crate::proxy::rpc_url;
+use crate::relay::RelayContext;
+use crate::revert::revert_reason;
+use crate::signer::Signer;
+use crate::tokens::{display_token, resolve_token};
+use crate::units::display_ether;
+use clarity::abi::derive_method_id;
+use clarity::{Address, Uint256};
+use num_traits::ToPrimitive;
+use std::fmt::{self, Display};
+use std::time::Duration;
+use web30::client::Web3;
+use web30::jsonrpc::client::HttpClient;
+use web30::types::TransactionRequest;
+
+/// PUSH4, which function dispatchers compare the selector of a call against
+const PUSH4: u8 = 0x63;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Pass,
+    Fail,
+    /// the check could not run with this configuration
+    Skip,
+}
+
+#[derive(Debug, Clone)]
+pub struct PreflightCheck {
+    pub verdict: Verdict,
+    pub what: String,
+}
+
+impl Display for PreflightCheck {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let verdict = match self.verdict {
+            Verdict::Pass => "OK  ",
+            Verdict::Fail => "FAIL",
+            Verdict::Skip => "SKIP",
+        };
+        write!(f, "{verdict} {}", self.what)
+    }
+}
+
+fn pass(what: String) -> PreflightCheck {
+    PreflightCheck {
+        verdict: Verdict::Pass,
+        what,
+    }
+}
+
+fn fail(what: String) -> PreflightCheck {
+    PreflightCheck {
+        verdict: Verdict::Fail,
+        what,
+    }
+}
+
+/// What to check on each chain
+#[derive(Debug, Clone)]
+pub struct ChainChecks {
+    pub rpc_timeout: Duration,
+    /// relayer accounts must hold more than this, None skips balances since nothing will be broadcast
+    pub reserve_balance: Option<Uint256>,
+    /// token the price API is asked to value, the wrapped gas token if None
+    pub price_token: Option<Address>,
+}
+
+/// Checks every RPC endpoint, the contracts relayed to, the price API and the relayer accounts of `ctx`
+pub async fn check_chain(ctx: &RelayContext, checks: &ChainChecks) -> Vec<PreflightCheck> {
+    let mut results = Vec::new();
+    for url in ctx.rpc.urls() {
+        let web3 = Web3::new(&rpc_url(url), checks.rpc_timeout);
+        results.push(match web3.eth_chainid().await {
+            Ok(id) if id.to_u64() == Some(ctx.chain_id) => {
+                pass(format!("{url} is on chain {}", ctx.chain_id))
+            }
+            Ok(id) => fail(format!(
+                "{url} is on chain {id}, expected {}, check the RPC endpoints and --expected-chain-id",
+                ctx.chain_id
+            )),
+            Err(e) => fail(format!("{url} chain id: {e}, check the endpoint is reachable")),
+        });
+        results.push(match web3.eth_block_number().await {
+            Ok(block) => pass(format!("{url} is at block {block}")),
+            Err(e) => fail(format!("{url} block number: {e}")),
+        });
+    }
+
+    let mut contracts = vec![(ctx.contract_address, ctx.layout.relay_function.as_str())];
+    for target in &ctx.targets {
+        contracts.push((
+            target.contract,
+            target
+                .relay_function
+                .as_deref()
+                .unwrap_or(&ctx.layout.relay_function),
+        ));
+    }
+    for (contract, function) in contracts {
+        results.push(check_contract(ctx, contract, function).await);
+    }
+
+    results.push(check_price(ctx, checks.price_token).await);
+
+    if let Some(reserve) = checks.reserve_balance {
+        for signer in ctx.signers.signers() {
+            results.push(check_balance(ctx, signer.address(), reserve).await);
+        }
+    }
+    results
+}
+
+/// Checks that `contract` has code that implements `function`. A selector the dispatcher compares against shows
+/// up in the bytecode, contracts that dispatch differently pass as long as calling the bare selector doesn't
+/// revert without a reason, which is how calls to missing functions fail.
+async fn check_contract(ctx: &RelayContext, contract: Address, function: &str) -> PreflightCheck {
+    let web3 = ctx.web3();
+    let client = HttpClient::new(&web3.get_url());
+    let code: Result<String, _> = client
+        .request_method(
+            "eth_getCode",
+            (contract.to_string(), "latest"),
+            web3.get_timeout(),
+        )
+        .await;
+    let code = match code.map(|code| hex::decode(code.trim_start_matches("0x"))) {
+        Ok(Ok(code)) if !code.is_empty() => code,
+        Ok(Ok(_)) => {
+            return fail(format!(
+                "No contract at {contract} on {}, check --contract-address and --relay-target",
+                ctx.name
+            ));
+        }
+        Ok(Err(e)) => return fail(format!("Code of {contract} on {}: {e}", ctx.name)),
+        Err(e) => return fail(format!("Code of {contract} on {}: {e}", ctx.name)),
+    };
+    let Ok(selector) = derive_method_id(function) else {
+        return fail(format!("Invalid relay function {function}"));
+    };
+    let mut push = vec![PUSH4];
+    push.extend_from_slice(&selector);
+    if code.windows(push.len()).any(|window| window == push) {
+        return pass(format!("{contract} on {} implements {function}", ctx.name));
+    }
+    let call = TransactionRequest::quick_tx(Address::default(), contract, selector.to_vec());
+    match web3.eth_call(call).await {
+        Ok(_) => pass(format!("{contract} on {} accepts {function}", ctx.name)),
+        Err(e) => match revert_reason(&e) {
+            Some(reason) => pass(format!(
+                "{contract} on {} accepts {function}, rejecting an empty call with: {reason}",
+                ctx.name
+            )),
+            None => fail(format!(
+                "{contract} on {} does not seem to implement {function}, check --contract-address and --relay-function ({e})",
+                ctx.name
+            )),
+        },
+    }
+}
+
+/// Asks the price source of `ctx` to value one whole `token`
+async fn check_price(ctx: &RelayContext, token: Option<Address>) -> PreflightCheck {
+    let Some(token) = token.or(ctx.wrapped_native_token) else {
+        return PreflightCheck {
+            verdict: Verdict::Skip,
+            what: format!(
+                "Price source of {}, pass --check-price-token or --wrapped-native-token to check it",
+                ctx.name
+            ),
+        };
+    };
+    let decimals = match token_decimals(ctx.web3(), ctx.chain_id, token).await {
+        Ok(decimals) => decimals,
+        Err(e) => {
+            return fail(format!(
+                "Decimals of {token} on {}: {e}, check the token address",
+                ctx.name
+            ));
+        }
+    };
+    resolve_token(ctx.web3(), ctx.chain_id, token).await;
+    let shown = display_token(ctx.chain_id, token);
+    let amount = TokenAmount {
+        chain_id: ctx.chain_id,
+        token,
+        decimals,
+        amount: 10u128
+            .checked_pow(decimals.into())
+            .unwrap_or(u128::MAX)
+            .into(),
+    };
+    match ctx.price_source.value_in_gas_token(amount).await {
+        Ok(value) => pass(format!(
+            "Price source of {} values one {shown} at {} ALTHEA",
+            ctx.name,
+            display_ether(value)
+        )),
+        Err(e) => fail(format!(
+            "Price source of {} failed to value {shown}: {e}, check --price-api-url",
+            ctx.name
+        )),
+    }
+}
+
+async fn check_balance(ctx: &RelayContext, address: Address, reserve: Uint256) -> PreflightCheck {
+    match ctx.web3().eth_get_balance(address).await {
+        Ok(balance) if balance > reserve => pass(format!(
+            "{address} has {} ALTHEA on {}",
+            display_ether(balance),
+            ctx.name
+        )),
+        Ok(balance) => fail(format!(
+            "{address} has {} ALTHEA on {}, not more than the reserve of {}, fund the account",
+            display_ether(balance),
+            ctx.name,
+            display_ether(reserve)
+        )),
+        Err(e) => fail(format!("{address} balance on {}: {e}", ctx.name)),
+    }
+}
+
+/// Checks that every orchestrator resolves, and with `poll` that its pending queue can be fetched
+pub async fn check_orchestrators(
+    orchestrators: &[Orchestrator],
+    signer: &Signer,
+    poll: bool,
+) -> Vec<PreflightCheck> {
+    let mut results = Vec::new();
+    for orchestrator in orchestrators {
+        let ips = match resolve_orchestrator(&orchestrator.url) {
+            Ok(ips) if !ips.is_empty() => ips,
+            Ok(_) => {
+                results.push(fail(format!(
+                    "{} resolves to no addresses, check --transaction-api-url",
+                    orchestrator.url
+                )));
+                continue;
+            }
+            Err(e) => {
+                results.push(fail(format!(
+                    "{}: {e}, check --transaction-api-url",
+                    orchestrator.url
+                )));
+                continue;
+            }
+        };
+        if !poll {
+            results.push(pass(format!(
+                "{} resolves to {} addresses",
+                orchestrator.url,
+                ips.len()
+            )));
+            continue;
+        }
+        results.push(match fetch_with_failover(orchestrator, signer).await {
+            Ok(txs) => pass(format!(
+                "{} has {} pending transactions",
+                orchestrator.url,
+                txs.len()
+            )),
+            Err(e) => fail(format!("{}: {e}", orchestrator.url)),
+        });
+    }
+    results
+}
@@ -0,0 +1,704 @@
+use crate::backoff::retry_get;
+use crate::error::RelayerError;
+use crate::proxy::http_client;
+use crate::throttle::{Outbound, throttle};
+use awc::ClientRequest;
+use awc::http::Method;
+use awc::http::header::{AUTHORIZATION, HeaderName, HeaderValue};
+use clap::ValueEnum;
+use clarity::abi::{AbiToken, encode_call, parse_i128};
+use clarity::{Address, Uint256};
+use futures::future::LocalBoxFuture;
+use log::{debug, error, info, warn};
+use num_traits::{CheckedMul, ToPrimitive};
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use web30::client::Web3;
+use web30::types::TransactionRequest;
+
+/// Largest power of ten that fits in a Uint256
+const MAX_DECIMAL_EXPONENT: u32 = 77;
+/// Decimals of the gas token, tip values are compared against gas costs in its smallest unit
+pub const GAS_TOKEN_DECIMALS: u8 = 18;
+
+/// Ambient `CrocImpact` query, previews a swap against the pool's current curve without executing it
+const CALC_IMPACT_SIG: &str =
+    "calcImpact(address,address,uint256,bool,bool,uint128,uint16,uint128)";
+/// Lowest square root price Ambient accepts, used as the limit of a sell so the whole quantity is quoted
+const MIN_SQRT_PRICE: u128 = 65538;
+
+/// `decimals()` of each tip token by chain id, a token's decimals never change so they are looked up once
+static DECIMALS: LazyLock<Mutex<HashMap<(u64, Address), u8>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// USD prices fetched from CoinGecko by request url, along with when they were fetched
+static USD_PRICES: LazyLock<Mutex<HashMap<String, (Instant, Price)>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct PriceQuery {
+    pub from: Address,
+}
+
+/// A header sent with price requests, to every price url or only to those under `url`
+#[derive(Clone, PartialEq, Eq)]
+struct PriceHeader {
+    url: Option<String>,
+    name: String,
+    value: String,
+}
+
+/// Headers sent with price requests, such as the bearer token a price service requires
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct PriceHeaders(Vec<PriceHeader>);
+
+/// Keeps tokens out of logs
+impl fmt::Debug for PriceHeaders {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|header| {
+                format!(
+                    "{}{}: <redacted>",
+                    header
+                        .url
+                        .as_deref()
+                        .map(|url| format!("{url}="))
+                        .unwrap_or_default(),
+                    header.name
+                )
+            }))
+            .finish()
+    }
+}
+
+/// Splits the `<URL>=` prefix off an entry, a value of its own may contain `=` so only urls are split off
+fn split_url(entry: &str) -> (Option<String>, &str) {
+    if (entry.starts_with("http://") || entry.starts_with("https://"))
+        && let Some((url, rest)) = entry.split_once('=')
+    {
+        return (Some(url.trim_end_matches('/').to_string()), rest);
+    }
+    (None, entry)
+}
+
+impl PriceHeaders {
+    /// Parses `headers` of the form `[<URL>=]<NAME>: <VALUE>` and `bearer_tokens` of the form `[<URL>=]<TOKEN>`,
+    /// entries with a url are only sent to price urls under it
+    pub fn from_opts(headers: &[String], bearer_tokens: &[String]) -> Result<PriceHeaders, String> {
+        let mut parsed = Vec::new();
+        for entry in headers {
+            let (url, header) = split_url(entry);
+            let Some((name, value)) = header.split_once(':') else {
+                return Err(format!(
+                    "Invalid price api header {entry}, expected [<URL>=]<NAME>: <VALUE>"
+                ));
+            };
+            let name = name.trim();
+            HeaderName::from_str(name)
+                .map_err(|e| format!("Invalid price api header {name}: {e}"))?;
+            HeaderValue::from_str(value.trim())
+                .map_err(|e| format!("Invalid value of price api header {name}: {e}"))?;
+            parsed.push(PriceHeader {
+                url,
+                name: name.to_string(),
+                value: value.trim().to_string(),
+            });
+        }
+        for entry in bearer_tokens {
+            let (url, token) = split_url(entry);
+            let value = format!("Bearer {}", token.trim());
+            HeaderValue::from_str(&value)
+                .map_err(|e| format!("Invalid price api bearer token: {e}"))?;
+            parsed.push(PriceHeader {
+                url,
+                name: AUTHORIZATION.to_string(),
+                value,
+            });
+        }
+        // headers for every url come first, so that those for a specific url replace them
+        parsed.sort_by_key(|header| header.url.is_some());
+        Ok(PriceHeaders(parsed))
+    }
+
+    /// Adds the headers that apply to `url` to `request`
+    fn apply(&self, url: &str, mut request: ClientRequest) -> ClientRequest {
+        for header in &self.0 {
+            if header
+                .url
+                .as_ref()
+                .is_none_or(|prefix| url.starts_with(prefix.as_str()))
+            {
+                request = request.insert_header((header.name.as_str(), header.value.as_str()));
+            }
+        }
+        request
+    }
+}
+
+/// Exact ratio between two token amounts, so that no precision is lost between the decimal returned by the
+/// price API and the final valuation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Price {
+    pub numerator: Uint256,
+    pub denominator: Uint256,
+}
+
+impl Price {
+    /// Price of the gas token in itself
+    pub fn one() -> Price {
+        Price {
+            numerator: 1u8.into(),
+            denominator: 1u8.into(),
+        }
+    }
+
+    /// Converts a price of one whole token in whole gas tokens, as quoted by the price API, into the value of
+    /// one base unit of a token with `decimals` decimals in wei
+    pub fn per_base_unit(self, decimals: u8) -> Result<Price, String> {
+        let gas_decimals = u32::from(GAS_TOKEN_DECIMALS);
+        let decimals = u32::from(decimals);
+        if decimals > MAX_DECIMAL_EXPONENT {
+            return Err(format!("Unsupported token decimals {decimals}"));
+        }
+        let overflow = || format!("Price {self} overflows for {decimals} decimals");
+        if decimals <= gas_decimals {
+            Ok(Price {
+                numerator: self
+                    .numerator
+                    .checked_mul(&power_of_ten(gas_decimals - decimals))
+                    .ok_or_else(overflow)?,
+                denominator: self.denominator,
+            })
+        } else {
+            Ok(Price {
+                numerator: self.numerator,
+                denominator: self
+                    .denominator
+                    .checked_mul(&power_of_ten(decimals - gas_decimals))
+                    .ok_or_else(overflow)?,
+            })
+        }
+    }
+
+    /// Value of `amount` base units in wei of the gas token, rounded down so a tip is never overvalued.
+    /// `None` if the value doesn't fit in a `Uint256`.
+    pub fn value_of(&self, amount: Uint256) -> Option<Uint256> {
+        match amount.checked_mul(&self.numerator) {
+            Some(product) => Some(product / self.denominator),
+            None => (amount / self.denominator).checked_mul(&self.numerator),
+        }
+    }
+}
+
+impl fmt::Display for Price {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.numerator, self.denominator)
+    }
+}
+
+/// Parses a non negative decimal such as `1`, `0.25` or `2.5e-7` exactly, JSON strings holding a decimal are
+/// accepted as well
+impl FromStr for Price {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid price {s}");
+        let decimal = s.trim().trim_matches('"');
+        let (mantissa, exponent) = match decimal.split_once(['e', 'E']) {
+            Some((mantissa, exponent)) => (
+                mantissa,
+                exponent
+                    .trim_start_matches('+')
+                    .parse::<i32>()
+                    .map_err(|_| invalid())?,
+            ),
+            None => (decimal, 0),
+        };
+        let (integer, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        let digits = format!("{integer}{fraction}");
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        let digits = digits.trim_start_matches('0');
+        let numerator = if digits.is_empty() {
+            Uint256::default()
+        } else {
+            Uint256::from_str(digits).map_err(|_| invalid())?
+        };
+        let scale = exponent
+            .checked_sub(i32::try_from(fraction.len()).map_err(|_| invalid())?)
+            .ok_or_else(invalid)?;
+        if scale.unsigned_abs() > MAX_DECIMAL_EXPONENT {
+            return Err(invalid());
+        }
+        let power = power_of_ten(scale.unsigned_abs());
+        if scale >= 0 {
+            Ok(Price {
+                numerator: numerator.checked_mul(&power).ok_or_else(invalid)?,
+                denominator: 1u8.into(),
+            })
+        } else {
+            Ok(Price {
+                numerator,
+                denominator: power,
+            })
+        }
+    }
+}
+
+/// 10^`exponent`, at most `MAX_DECIMAL_EXPONENT`
+fn power_of_ten(exponent: u32) -> Uint256 {
+    Uint256::from_str(&format!("1{}", "0".repeat(exponent as usize))).unwrap()
+}
+
+/// Forgets the token decimals and USD prices looked up so far, so that the next lookups are made again
+pub fn clear_price_caches() {
+    DECIMALS.lock().unwrap().clear();
+    USD_PRICES.lock().unwrap().clear();
+}
+
+/// Decimals of `token` on the chain with `chain_id`, queried from the token on first use and cached after that
+pub async fn token_decimals(
+    web3: &Web3,
+    chain_id: u64,
+    token: Address,
+) -> Result<u8, RelayerError> {
+    if let Some(decimals) = DECIMALS.lock().unwrap().get(&(chain_id, token)) {
+        return Ok(*decimals);
+    }
+    let decimals = web3
+        .get_erc20_decimals(token, Address::default(), vec![])
+        .await?;
+    let decimals = decimals
+        .to_u8()
+        .ok_or_else(|| RelayerError::price(format!("Token {token} reports {decimals} decimals")))?;
+    debug!("Token {token} on chain {chain_id} has {decimals} decimals");
+    DECIMALS.lock().unwrap().insert((chain_id, token), decimals);
+    Ok(decimals)
+}
+
+/// An amount of a tip token to be valued
+#[derive(Debug, Clone, Copy)]
+pub struct TokenAmount {
+    pub chain_id: u64,
+    pub token: Address,
+    /// decimals of `token`
+    pub decimals: u8,
+    /// in base units of `token`
+    pub amount: Uint256,
+}
+
+/// Values tip tokens in the gas token. The price API is the default, anything else, such as an exchange API,
+/// an on chain TWAP or a fixed table, can be used by implementing this and setting `RelayContext::price_source`,
+/// or even by merging several sources in one implementation.
+pub trait PriceSource {
+    /// Value of `tip` in wei of the gas token
+    fn value_in_gas_token(
+        &self,
+        tip: TokenAmount,
+    ) -> LocalBoxFuture<'_, Result<Uint256, RelayerError>>;
+}
+
+/// The price API at `url`, which quotes one whole token in whole gas tokens
+#[derive(Debug, Clone)]
+pub struct HttpPriceSource {
+    pub url: String,
+    pub headers: PriceHeaders,
+    pub timeout: Duration,
+}
+
+impl PriceSource for HttpPriceSource {
+    fn value_in_gas_token(
+        &self,
+        tip: TokenAmount,
+    ) -> LocalBoxFuture<'_, Result<Uint256, RelayerError>> {
+        Box::pin(fetch_value_in_gas_token(
+            &self.url,
+            &self.headers,
+            tip.token,
+            tip.amount,
+            tip.decimals,
+            self.timeout,
+        ))
+    }
+}
+
+/// Built in price sources that can be selected from the command line or per chain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PriceSourceKind {
+    /// the price API at `--price-api-url`
+    Http,
+    /// CoinGecko USD prices of the tip token and the gas token
+    Coingecko,
+}
+
+/// CoinGecko, which prices tip tokens by contract address and the gas token by its coin id, both in USD. The
+/// ratio of the two is the price of the tip token in the gas token.
+#[derive(Debug, Clone)]
+pub struct CoinGeckoPriceSource {
+    /// API root, such as `https://api.coingecko.com/api/v3`
+    pub url: String,
+    /// sent as a pro API key to `pro-api.coingecko.com` and as a demo API key anywhere else
+    pub api_key: Option<String>,
+    pub headers: PriceHeaders,
+    /// CoinGecko asset platform id of the chain tips are paid on
+    pub platform: String,
+    /// CoinGecko coin id of the gas token
+    pub gas_token_id: String,
+    pub timeout: Duration,
+    /// fetched USD prices are reused for this long, free plans are heavily rate limited
+    pub cache_duration: Duration,
+}
+
+impl CoinGeckoPriceSource {
+    /// Price of one whole `token` in whole gas tokens
+    pub async fn fetch_price(&self, token: Address) -> Result<Price, RelayerError> {
+        let token_id = format!("{token:?}").to_lowercase();
+        let token_usd = self
+            .usd_price(
+                &format!(
+                    "{}/simple/token_price/{}?contract_addresses={token_id}&vs_currencies=usd",
+                    self.url, self.platform
+                ),
+                &token_id,
+            )
+            .await?;
+        let gas_usd = self
+            .usd_price(
+                &format!(
+                    "{}/simple/price?ids={}&vs_currencies=usd",
+                    self.url, self.gas_token_id
+                ),
+                &self.gas_token_id,
+            )
+            .await?;
+        if gas_usd.numerator == Uint256::default() {
+            return Err(RelayerError::price(format!(
+                "CoinGecko prices {} at zero",
+                self.gas_token_id
+            )));
+        }
+        let overflow = || RelayerError::price(format!("Price {token_usd} / {gas_usd} overflows"));
+        Ok(Price {
+            numerator: token_usd
+                .numerator
+                .checked_mul(&gas_usd.denominator)
+                .ok_or_else(overflow)?,
+            denominator: token_usd
+                .denominator
+                .checked_mul(&gas_usd.numerator)
+                .ok_or_else(overflow)?,
+        })
+    }
+
+    /// USD price of `id` in the response to `url`, cached for `cache_duration`
+    async fn usd_price(&self, url: &str, id: &str) -> Result<Price, RelayerError> {
+        if let Some((fetched, price)) = USD_PRICES.lock().unwrap().get(url)
+            && fetched.elapsed() < self.cache_duration
+        {
+            return Ok(*price);
+        }
+        debug!("Fetching price from {url}");
+        let client = http_client(None, self.timeout);
+        let mut response = retry_get(Outbound::Price, url, || async {
+            throttle(Outbound::Price, &self.url).await;
+            let mut request = self.headers.apply(url, client.request(Method::GET, url));
+            if let Some(key) = &self.api_key {
+                let header = if self.url.contains("pro-api.coingecko.com") {
+                    "x-cg-pro-api-key"
+                } else {
+                    "x-cg-demo-api-key"
+                };
+                request = request.insert_header((header, key.as_str()));
+            }
+            request.send().await.map_err(RelayerError::price)
+        })
+        .await?;
+        let body = response.body().await.map_err(RelayerError::price)?;
+        if !response.status().is_success() {
+            return Err(RelayerError::price(format!(
+                "CoinGecko returned {}: {}",
+                response.status(),
+                String::from_utf8_lossy(&body)
+            )));
+        }
+        let prices: HashMap<String, HashMap<String, serde_json::Number>> =
+            serde_json::from_slice(&body).map_err(RelayerError::price)?;
+        // serde_json prints the shortest decimal that reads back as the same number, which is what was sent
+        let price = prices
+            .get(id)
+            .and_then(|prices| prices.get("usd"))
+            .ok_or_else(|| RelayerError::price(format!("CoinGecko has no USD price for {id}")))?
+            .to_string()
+            .parse::<Price>()
+            .map_err(RelayerError::price)?;
+        USD_PRICES
+            .lock()
+            .unwrap()
+            .insert(url.to_string(), (Instant::now(), price));
+        Ok(price)
+    }
+}
+
+impl PriceSource for CoinGeckoPriceSource {
+    fn value_in_gas_token(
+        &self,
+        tip: TokenAmount,
+    ) -> LocalBoxFuture<'_, Result<Uint256, RelayerError>> {
+        Box::pin(async move {
+            let price = self.fetch_price(tip.token).await?;
+            info!(
+                "Fetched CoinGecko price: {price} and tip amount is {} with {} decimals",
+                tip.amount, tip.decimals
+            );
+            let price = price
+                .per_base_unit(tip.decimals)
+                .map_err(RelayerError::price)?;
+            price
+                .value_of(tip.amount)
+                .ok_or_else(|| value_overflow(tip.amount, tip.token))
+        })
+    }
+}
+
+/// Fixed prices that keep tips valued while `live` is failing, so that relaying of major pairs such as stable
+/// coins continues through an outage of the price infrastructure
+pub struct StaticPriceFallback {
+    pub live: Rc<dyn PriceSource>,
+    /// price of one whole token in whole gas tokens, by token address
+    pub prices: HashMap<Address, Price>,
+    /// warn that static prices may be stale once `live` has been failing for this long
+    pub warn_after: Option<Duration>,
+    /// when `live` started failing, reset once it succeeds again
+    failing_since: Cell<Option<Instant>>,
+}
+
+impl StaticPriceFallback {
+    pub fn new(
+        live: Rc<dyn PriceSource>,
+        prices: HashMap<Address, Price>,
+        warn_after: Option<Duration>,
+    ) -> StaticPriceFallback {
+        StaticPriceFallback {
+            live,
+            prices,
+            warn_after,
+            failing_since: Cell::new(None),
+        }
+    }
+}
+
+impl PriceSource for StaticPriceFallback {
+    fn value_in_gas_token(
+        &self,
+        tip: TokenAmount,
+    ) -> LocalBoxFuture<'_, Result<Uint256, RelayerError>> {
+        Box::pin(async move {
+            let e = match self.live.value_in_gas_token(tip).await {
+                Ok(value) => {
+                    self.failing_since.set(None);
+                    return Ok(value);
+                }
+                Err(e) => e,
+            };
+            let Some(price) = self.prices.get(&tip.token) else {
+                return Err(e);
+            };
+            let failing_since = self.failing_since.get().unwrap_or_else(Instant::now);
+            self.failing_since.set(Some(failing_since));
+            let failing_for = failing_since.elapsed();
+            if let Some(warn_after) = self.warn_after
+                && failing_for >= warn_after
+            {
+                warn!(
+                    "Live prices have been failing for {}s, valuing {} with the static price {price} which may be stale: {e}",
+                    failing_for.as_secs(),
+                    tip.token
+                );
+            } else {
+                info!(
+                    "Live price lookup failed, valuing {} with the static price {price}: {e}",
+                    tip.token
+                );
+            }
+            let price = price
+                .per_base_unit(tip.decimals)
+                .map_err(RelayerError::price)?;
+            price
+                .value_of(tip.amount)
+                .ok_or_else(|| value_overflow(tip.amount, tip.token))
+        })
+    }
+}
+
+/// Parses a static price entry of the form `TOKEN_ADDRESS=PRICE`, the price being of one whole token in whole
+/// gas tokens and optionally followed by `ALTHEA`, such as `0x...=12.5 ALTHEA`
+pub fn parse_static_price(entry: &str) -> Result<(Address, Price), String> {
+    let (token, price) = entry
+        .split_once('=')
+        .ok_or_else(|| format!("Static price {entry} is not of the form TOKEN=PRICE"))?;
+    let token = Address::from_str(token.trim()).map_err(|e| format!("{entry}: {e}"))?;
+    let price = price.trim();
+    let price = Price::from_str(price.strip_suffix("ALTHEA").unwrap_or(price))?;
+    Ok((token, price))
+}
+
+/// Value of one base unit of `token` according to `source`, found by valuing one whole token so that a single
+/// lookup prices any number of tips in the same token
+pub async fn unit_price(
+    source: &dyn PriceSource,
+    chain_id: u64,
+    token: Address,
+    decimals: u8,
+) -> Result<Price, RelayerError> {
+    let one = power_of_ten(u32::from(decimals).min(MAX_DECIMAL_EXPONENT));
+    let value = source
+        .value_in_gas_token(TokenAmount {
+            chain_id,
+            token,
+            decimals,
+            amount: one,
+        })
+        .await?;
+    Ok(Price {
+        numerator: value,
+        denominator: one,
+    })
+}
+
+/// Values tips by quoting the swap of the whole tip into the gas token on the dex, so that the price impact
+/// and fees of selling a large tip into a thin pool are accounted for rather than trusting the spot price
+#[derive(Debug, Clone, Copy)]
+pub struct SwapQuoter {
+    /// address of the `CrocImpact` contract
+    pub impact: Address,
+    /// pool type index of the pools tips are sold into
+    pub pool_idx: u64,
+}
+
+impl SwapQuoter {
+    /// Wei of the native gas token received for selling `amount` of `token` into its pool with the gas token
+    pub async fn quote(
+        &self,
+        web3: &Web3,
+        token: Address,
+        amount: Uint256,
+    ) -> Result<Uint256, RelayerError> {
+        // the native token is the zero address and so always the base side of its pools
+        let data = encode_call(
+            CALC_IMPACT_SIG,
+            &[
+                AbiToken::Address(Address::default()),
+                AbiToken::Address(token),
+                AbiToken::Uint(self.pool_idx.into()),
+                AbiToken::Bool(false),
+                AbiToken::Bool(false),
+                AbiToken::Uint(amount),
+                AbiToken::Uint(0u8.into()),
+                AbiToken::Uint(MIN_SQRT_PRICE.into()),
+            ],
+        )
+        .map_err(RelayerError::price)?;
+        let result = web3
+            .eth_call(TransactionRequest::quick_tx(
+                Address::default(),
+                self.impact,
+                data,
+            ))
+            .await
+            .map_err(|e| RelayerError::price(format!("Swap quote for {token} failed: {e}")))?;
+        // flows are from the pool's point of view, the base it pays out is negative
+        let base_flow = parse_i128(&result, 0).map_err(RelayerError::price)?;
+        let received = Uint256::from(base_flow.min(0).unsigned_abs());
+        info!("Selling {amount} of {token} is quoted at {received}");
+        Ok(received)
+    }
+}
+
+/// Fetches the current price of a given token from the price API, other price feeds can be used by implementing
+/// `PriceSource`. Returns the price of one unit of the request token in units of the gas token (ALTHEA).
+pub async fn fetch_price(
+    price_api_url: &str,
+    headers: &PriceHeaders,
+    from: Address,
+    timeout: Duration,
+) -> Result<Price, RelayerError> {
+    let url = format!("{price_api_url}/value_in_gas_token/{from}");
+    debug!("Fetching price from {url}");
+
+    let client = http_client(None, timeout);
+    let mut response = retry_get(Outbound::Price, &url, || async {
+        throttle(Outbound::Price, price_api_url).await;
+        headers
+            .apply(&url, client.request(Method::GET, &url))
+            .send()
+            .await
+            .map_err(RelayerError::price)
+    })
+    .await?;
+
+    let body = response.body().await.map_err(RelayerError::price)?;
+    if !response.status().is_success() {
+        error!("Failed to fetch price: {}", response.status());
+        let error_text = String::from_utf8_lossy(&body);
+        error!("Failed to fetch price: {error_text}");
+        return Err(RelayerError::price(error_text));
+    }
+    // the decimal is parsed from the raw body, deserializing it as a JSON number would round it to an f64
+    Price::from_str(&String::from_utf8_lossy(&body)).map_err(RelayerError::price)
+}
+
+/// Value of `amount` base units of the `from` token, which has `decimals` decimals, in wei of the gas token
+pub async fn fetch_value_in_gas_token(
+    price_api_url: &str,
+    headers: &PriceHeaders,
+    from: Address,
+    amount: Uint256,
+    decimals: u8,
+    timeout: Duration,
+) -> Result<Uint256, RelayerError> {
+    let price = fetch_price(price_api_url, headers, from, timeout).await?;
+    info!("Fetched price: {price} and tip amount is {amount} with {decimals} decimals");
+    let price = price.per_base_unit(decimals).map_err(RelayerError::price)?;
+    price
+        .value_of(amount)
+        .ok_or_else(|| value_overflow(amount, from))
+}
+
+fn value_overflow(amount: Uint256, token: Address) -> RelayerError {
+    RelayerError::price(format!("Value of {amount} of {token} overflows"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn price(numerator: u64, denominator: u64) -> Price {
+        Price {
+            numerator: numerator.into(),
+            denominator: denominator.into(),
+        }
+    }
+
+    #[test]
+    fn parses_exact_ratios() {
+        assert_eq!(Price::from_str("0.25"), Ok(price(25, 100)));
+        assert_eq!(Price::from_str("2.5e-7"), Ok(price(25, 100_000_000)));
+        assert_eq!(Price::from_str("\"1.5E+2\""), Ok(price(150, 1)));
+    }
+
+    #[test]
+    fn rejects_bad_exponents() {
+        assert!(Price::from_str("1.5e").is_err());
+        assert!(Price::from_str("1.5e-x").is_err());
+        assert!(Price::from_str("1e100").is_err());
+        assert!(Price::from_str("1.5e-2147483648").is_err());
+    }
+}
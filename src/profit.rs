@@ -0,0 +1,111 @@
+//! The decision whether a transaction is worth relaying once its gas and tip have been estimated. The default
+//! requires the tip to exceed the gas cost by the configured margin, embedders can swap in their own policy,
+//! for example to always relay for a partner's addresses, by implementing `ProfitPolicy`.
+
+use crate::decoder::DecodedTransaction;
+use crate::margin::required_margin;
+use crate::orchestrator::GaslessTransaction;
+use clarity::{Address, Uint256};
+use log::info;
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+
+/// Gas price the relay would pay
+#[derive(Debug, Clone, Copy)]
+pub struct FeeData {
+    /// in wei
+    pub gas_price: Uint256,
+    /// the tip has to cover the gas cost this many times over, more than 1 while the gas price is above the
+    /// configured ceiling
+    pub gas_cost_multiple: u32,
+    /// the tip has to exceed the gas cost by this many percent, before the adaptive margin raises it
+    pub profit_margin_percent: u32,
+}
+
+/// What the tip is worth to us
+#[derive(Debug, Clone, Copy)]
+pub struct TipValuation {
+    pub token: Address,
+    /// in base units of `token`
+    pub amount: Uint256,
+    /// in wei of the gas token
+    pub value: Uint256,
+    /// estimated cost in wei of selling the tip for the gas token, zero for tips paid in the gas token
+    pub liquidation_cost: Uint256,
+}
+
+impl TipValuation {
+    /// Value left once the tip has been sold for the gas token
+    pub fn net_value(&self) -> Uint256 {
+        if self.value > self.liquidation_cost {
+            self.value - self.liquidation_cost
+        } else {
+            Uint256::default()
+        }
+    }
+}
+
+/// Everything known about a transaction when deciding whether to relay it
+#[derive(Debug)]
+pub struct ProfitInputs<'a> {
+    pub tx: &'a DecodedTransaction,
+    /// gas the relay is estimated to use
+    pub gas_estimate: Uint256,
+    pub fees: FeeData,
+    pub tip: TipValuation,
+}
+
+pub trait ProfitPolicy {
+    /// True if the transaction should be relayed
+    fn is_profitable(&self, inputs: &ProfitInputs) -> bool;
+}
+
+/// Relays when the net tip value exceeds the gas cost by the configured profit margin, raised by the adaptive
+/// margin if enabled
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MarginProfitPolicy;
+
+impl ProfitPolicy for MarginProfitPolicy {
+    fn is_profitable(&self, inputs: &ProfitInputs) -> bool {
+        let ProfitInputs {
+            gas_estimate,
+            fees,
+            tip,
+            ..
+        } = inputs;
+        exceeds_gas_cost(tip.net_value(), *gas_estimate, fees)
+    }
+}
+
+/// True if `value` exceeds the cost of `gas_estimate` gas at `fees` by its profit margin, raised by the adaptive
+/// margin if enabled
+pub fn exceeds_gas_cost(value: Uint256, gas_estimate: Uint256, fees: &FeeData) -> bool {
+    let gas_cost = gas_estimate * fees.gas_price * fees.gas_cost_multiple.into();
+    let margin = required_margin(fees.profit_margin_percent);
+    let gas_cost = gas_cost + gas_cost * margin.into() / 100u8.into();
+    if value > gas_cost {
+        info!("Transaction is profitable: tip value {value} > gas estimate {gas_cost}");
+        true
+    } else {
+        info!(
+            "Transaction is not profitable Gas Price: {} Gas Amount {gas_estimate} tip value {value} <= gas estimate {gas_cost}",
+            fees.gas_price
+        );
+        false
+    }
+}
+
+/// Net value in wei of the tip of each transaction being relayed, by signature hash, so that a relay can be
+/// checked against what it ended up costing
+static TIP_VALUES: LazyLock<Mutex<HashMap<[u8; 32], Uint256>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Remembers what the tip of `tx` is worth once it has been found profitable
+pub fn record_tip_value(tx: &GaslessTransaction, value: Uint256) {
+    TIP_VALUES.lock().unwrap().insert(tx.sig_hash(), value);
+}
+
+/// Takes the tip value recorded for `tx`, called once its relay is final or has failed
+pub fn take_tip_value(tx: &GaslessTransaction) -> Option<Uint256> {
+    TIP_VALUES.lock().unwrap().remove(&tx.sig_hash())
+}
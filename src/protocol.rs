@@ -0,0 +1,320 @@
+//! Relaying of governance and protocol commands for operators running the relayer as a service for the dex team.
+//! These come from a queue of their own on the orchestrator and are sent to the dex's `protocolCmd` rather than
+//! `userCmdRelayer`, so the relayer account has to be the dex's authority for them to go through. Every command
+//! must be signed by one of the configured submitters and sent to a callpath configured for protocol commands,
+//! sudo commands only on callpaths that allow them, before the relayer spends any gas on it.
+
+use crate::backoff::retry_get;
+use crate::error::{FailureKind, RelayerError};
+use crate::history::was_executed;
+use crate::lease::may_broadcast;
+use crate::orchestrator::{Orchestrator, RELAYING_SERVICE_ROOT, eip712_digest, recover_signer};
+use crate::relay::{
+    RelayContext, find_chain, prepare_unsigned_transaction, send_side_transaction_once,
+};
+use crate::signer::Signer;
+use crate::throttle::Outbound;
+use clarity::abi::{AbiToken, encode_call, encode_tokens, get_hash};
+use clarity::utils::display_uint256_as_address;
+use clarity::{Address, Uint256};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::sync::{LazyLock, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// function protocolCmd (uint16 callpath, bytes calldata cmd, bool sudo)
+pub const PROTOCOL_CMD_SIG: &str = "protocolCmd(uint16,bytes,bool)";
+
+/// Kind protocol commands are recorded as executed under in the history
+const PROTOCOL_EXECUTION: &str = "protocol";
+
+const PROTOCOL_CALL_TYPE: &str =
+    "CrocProtocolCall(uint16 callpath,bytes cmd,bool sudo,uint64 deadline)";
+
+/// A governance or protocol command waiting to be relayed
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProtocolCommand {
+    pub chain_id: u64,
+    pub callpath: u16,
+    pub cmd: Vec<u8>,
+    pub sudo: bool,
+    /// unix timestamp after which the command must no longer be relayed
+    pub deadline: u64,
+    /// EIP-712 signature of the submitter over the command, abi encoded as (uint8 v, bytes32 r, bytes32 s)
+    pub sig: Vec<u8>,
+}
+
+impl ProtocolCommand {
+    /// Hex encoded hash of the submitter's signature, identifies the command across poll cycles
+    pub fn id(&self) -> String {
+        format!("0x{}", hex::encode(get_hash(&self.sig)))
+    }
+
+    /// Recovers the address of the submitter who signed this command for the dex at `dex_address`
+    pub fn submitter(&self, dex_address: Address) -> Result<Address, RelayerError> {
+        let content_hash = get_hash(&encode_tokens(&[
+            AbiToken::Bytes(get_hash(PROTOCOL_CALL_TYPE.as_bytes()).to_vec()),
+            AbiToken::Uint(self.callpath.into()),
+            AbiToken::Bytes(get_hash(&self.cmd).to_vec()),
+            AbiToken::Bool(self.sudo),
+            AbiToken::Uint(self.deadline.into()),
+        ]));
+        recover_signer(
+            &self.sig,
+            &eip712_digest(self.chain_id, dex_address, &content_hash),
+        )
+    }
+}
+
+/// A callpath protocol commands may be sent to, written as the callpath followed by comma separated options:
+/// `sudo` to allow sudo commands and `max-gas=<gas>` to cap the gas limit, such as `3,sudo,max-gas=500000`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProtocolCallpath {
+    pub callpath: u16,
+    pub sudo: bool,
+    pub max_gas: Option<Uint256>,
+}
+
+impl FromStr for ProtocolCallpath {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid =
+            || format!("Invalid protocol callpath {s}, expected <callpath>[,sudo][,max-gas=<gas>]");
+        let mut parts = s.split(',');
+        let callpath = parts
+            .next()
+            .and_then(|callpath| callpath.trim().parse().ok())
+            .ok_or_else(invalid)?;
+        let mut config = ProtocolCallpath {
+            callpath,
+            sudo: false,
+            max_gas: None,
+        };
+        for option in parts {
+            match option.trim().split_once('=') {
+                None if option.trim() == "sudo" => config.sudo = true,
+                Some(("max-gas", gas)) => {
+                    config.max_gas = Some(gas.parse::<u64>().map_err(|_| invalid())?.into())
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ProtocolPolicy {
+    /// accounts whose signed commands are relayed
+    pub submitters: Vec<Address>,
+    pub callpaths: Vec<ProtocolCallpath>,
+}
+
+static POLICY: LazyLock<Mutex<Option<ProtocolPolicy>>> = LazyLock::new(|| Mutex::new(None));
+
+/// Commands already relayed or rejected for good, the dex doesn't protect protocol commands against replay
+static DONE: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(|| Mutex::new(HashSet::new()));
+
+pub fn set_protocol_policy(policy: Option<ProtocolPolicy>) {
+    *POLICY.lock().unwrap() = policy;
+}
+
+pub fn protocol_policy() -> Option<ProtocolPolicy> {
+    POLICY.lock().unwrap().clone()
+}
+
+/// Fetches the protocol commands waiting in the orchestrator's protocol queue
+pub async fn fetch_protocol_commands(
+    orchestrator: &Orchestrator,
+    signer: &Signer,
+) -> Result<Vec<ProtocolCommand>, RelayerError> {
+    let url = format!(
+        "{}/{RELAYING_SERVICE_ROOT}/protocol/pending",
+        orchestrator.url
+    );
+    let client = orchestrator.http_client();
+    let mut response = retry_get(Outbound::Orchestrator, &url, || async {
+        orchestrator
+            .authenticate(client.get(&url), signer)
+            .await?
+            .send()
+            .await
+            .map_err(RelayerError::orchestrator)
+    })
+    .await?;
+    if !response.status().is_success() {
+        return Err(RelayerError::orchestrator(format!(
+            "Protocol queue responded with {}",
+            response.status()
+        )));
+    }
+    response
+        .json()
+        .limit(orchestrator.max_response)
+        .await
+        .map_err(RelayerError::orchestrator)
+}
+
+/// Checks that `command` may be relayed under `policy`, returning the configuration of its callpath
+fn authorize<'a>(
+    ctx: &RelayContext,
+    policy: &'a ProtocolPolicy,
+    command: &ProtocolCommand,
+) -> Result<&'a ProtocolCallpath, RelayerError> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    if command.deadline < now {
+        return Err(RelayerError::invalid_tx(format!(
+            "Protocol command expired at {}",
+            command.deadline
+        )));
+    }
+    let submitter = command.submitter(ctx.contract_address)?;
+    if !policy.submitters.contains(&submitter) {
+        return Err(RelayerError::invalid_tx(format!(
+            "Protocol command signed by {submitter}, who is not an allowed submitter"
+        )));
+    }
+    let callpath = policy
+        .callpaths
+        .iter()
+        .find(|c| c.callpath == command.callpath)
+        .ok_or_else(|| {
+            RelayerError::invalid_tx(format!(
+                "Protocol commands are not allowed on callpath {}",
+                command.callpath
+            ))
+        })?;
+    if command.sudo && !callpath.sudo {
+        return Err(RelayerError::invalid_tx(format!(
+            "Sudo protocol commands are not allowed on callpath {}",
+            command.callpath
+        )));
+    }
+    Ok(callpath)
+}
+
+/// Relays `command` from the chain's primary relayer account, which has to be the dex's authority. Returns the
+/// hash of the relay, `None` on a dry run.
+async fn relay_protocol_command(
+    ctx: &RelayContext,
+    policy: &ProtocolPolicy,
+    command: &ProtocolCommand,
+) -> Result<Option<Uint256>, RelayerError> {
+    let callpath = authorize(ctx, policy, command)?;
+    let signer = ctx.signers.primary();
+    let data = encode_call(
+        PROTOCOL_CMD_SIG,
+        &[
+            command.callpath.into(),
+            command.cmd.clone().into(),
+            AbiToken::Bool(command.sudo),
+        ],
+    )?;
+    // estimating fails if the relayer account isn't the dex's authority, before anything is spent
+    let unsigned = prepare_unsigned_transaction(
+        ctx.web3(),
+        ctx.chain_id,
+        signer.address(),
+        ctx.contract_address,
+        data,
+        2.0,
+    )
+    .await?;
+    if let Some(max_gas) = callpath.max_gas
+        && unsigned.get_gas_limit() > max_gas
+    {
+        return Err(RelayerError::invalid_tx(format!(
+            "Protocol command needs a gas limit of {}, above the maximum of {max_gas} for callpath {}",
+            unsigned.get_gas_limit(),
+            command.callpath
+        )));
+    }
+    if ctx.dry_run {
+        return Ok(None);
+    }
+    let tx_hash =
+        send_side_transaction_once(ctx, signer, unsigned, PROTOCOL_EXECUTION, &command.id())
+            .await?;
+    Ok(Some(tx_hash))
+}
+
+/// Relays the protocol commands waiting in the orchestrator's protocol queue
+pub async fn process_protocol_commands(
+    chains: &[RelayContext],
+    orchestrator: &Orchestrator,
+    signer: &Signer,
+) {
+    let Some(policy) = protocol_policy() else {
+        return;
+    };
+    if orchestrator.is_grpc() {
+        debug!(
+            "Not polling {} for protocol commands, the protocol queue is only served over http",
+            orchestrator.url
+        );
+        return;
+    }
+    let commands = match fetch_protocol_commands(orchestrator, signer).await {
+        Ok(commands) => commands,
+        Err(e) => {
+            warn!(
+                "Failed to fetch protocol commands from {}: {e}",
+                orchestrator.url
+            );
+            return;
+        }
+    };
+    for command in commands {
+        let id = command.id();
+        if DONE.lock().unwrap().contains(&id) || was_executed(PROTOCOL_EXECUTION, &id) {
+            continue;
+        }
+        let Some(ctx) = find_chain(chains, command.chain_id) else {
+            debug!(
+                "Skipping protocol command {id} for chain {}, which isn't relayed on",
+                command.chain_id
+            );
+            continue;
+        };
+        // only the instance relaying from the primary account may send from it
+        if !ctx.dry_run && !may_broadcast(ctx.chain_id, ctx.signers.primary().address()) {
+            debug!(
+                "Not the leader or lease holder on {}, standing by on protocol command {id}",
+                ctx.name
+            );
+            continue;
+        }
+        match relay_protocol_command(ctx, &policy, &command).await {
+            Ok(Some(tx_hash)) => {
+                DONE.lock().unwrap().insert(id.clone());
+                info!(
+                    tx:% = id, decision = "submitted", tx_hash:% = display_uint256_as_address(tx_hash);
+                    "Protocol command {id} on callpath {} submitted on {} with hash {}",
+                    command.callpath,
+                    ctx.name,
+                    display_uint256_as_address(tx_hash)
+                );
+            }
+            Ok(None) => info!(
+                "Dry run, not submitting protocol command {id} on callpath {}",
+                command.callpath
+            ),
+            Err(e) => {
+                warn!(
+                    tx:% = id, decision = "failed";
+                    "Failed to relay protocol command {id} on callpath {}: {e}",
+                    command.callpath
+                );
+                if e.kind() == FailureKind::Blacklist {
+                    DONE.lock().unwrap().insert(id);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,540 @@
+//! Routing of outbound HTTP traffic through an HTTP or SOCKS5 proxy, and the certificates it trusts. Orchestrator
+//! and price requests are made with shared awc clients whose connections are tunneled through the proxy and that
+//! trust any extra CA certificates on top of the system's. web30 always creates its own client, so RPC endpoints are
+//! instead reached through a local bridge that forwards each JSON-RPC request with such a client.
+
+use crate::bench::record_rpc_request;
+use crate::throttle::{Outbound, rpc_throttled, throttle};
+use actix_rt::net::TcpStream;
+use actix_service::Service;
+use actix_tls::connect::{
+    ConnectError as TcpConnectError, ConnectInfo as TcpConnectInfo, Connection as TcpConnection,
+};
+use actix_web::{App, HttpRequest, HttpResponse, HttpServer, web};
+use awc::http::Uri;
+use awc::http::header::CONTENT_TYPE;
+use awc::{Client as HttpClient, Connector};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use futures::future::LocalBoxFuture;
+use log::{debug, info, warn};
+use openssl::ssl::{SslConnector, SslConnectorBuilder, SslMethod, SslVerifyMode};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// Largest response the RPC bridge forwards, generous enough for any log query
+const MAX_BRIDGE_RESPONSE: usize = 128 * 1024 * 1024;
+/// Largest proxy response header accepted while establishing a tunnel
+const MAX_CONNECT_RESPONSE: usize = 8 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyKind {
+    /// HTTP proxy, connections are tunneled with `CONNECT`
+    Http,
+    /// SOCKS5 proxy, host names are resolved by the proxy
+    Socks5,
+}
+
+/// An outbound proxy parsed from `http://[user:pass@]host:port` or `socks5://[user:pass@]host:port`
+#[derive(Clone, PartialEq, Eq)]
+pub struct ProxyConfig {
+    pub kind: ProxyKind,
+    /// `host:port` of the proxy itself
+    pub address: String,
+    pub credentials: Option<(String, String)>,
+}
+
+/// Keeps credentials out of logs
+impl fmt::Debug for ProxyConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProxyConfig")
+            .field("kind", &self.kind)
+            .field("address", &self.address)
+            .field(
+                "credentials",
+                &self.credentials.as_ref().map(|_| "<redacted>"),
+            )
+            .finish()
+    }
+}
+
+impl FromStr for ProxyConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (scheme, rest) = s
+            .split_once("://")
+            .ok_or_else(|| format!("Invalid proxy url {s}, expected <scheme>://<host>:<port>"))?;
+        let kind = match scheme {
+            "http" => ProxyKind::Http,
+            "socks5" | "socks5h" => ProxyKind::Socks5,
+            _ => {
+                return Err(format!(
+                    "Unsupported proxy scheme {scheme}, expected http or socks5"
+                ));
+            }
+        };
+        let rest = rest.trim_end_matches('/');
+        let (credentials, address) = match rest.rsplit_once('@') {
+            Some((userinfo, address)) => {
+                let (user, pass) = userinfo.split_once(':').unwrap_or((userinfo, ""));
+                (Some((user.to_string(), pass.to_string())), address)
+            }
+            None => (None, rest),
+        };
+        if address
+            .rsplit_once(':')
+            .is_none_or(|(_, port)| port.parse::<u16>().is_err())
+        {
+            return Err(format!(
+                "Invalid proxy address {address}, expected <host>:<port>"
+            ));
+        }
+        Ok(ProxyConfig {
+            kind,
+            address: address.to_string(),
+            credentials,
+        })
+    }
+}
+
+impl ProxyConfig {
+    /// Opens a connection to `target` (`host:port`) through the proxy
+    pub async fn connect(&self, target: &str) -> io::Result<TcpStream> {
+        let mut stream = TcpStream::connect(&self.address).await?;
+        match self.kind {
+            ProxyKind::Http => self.http_connect(&mut stream, target).await?,
+            ProxyKind::Socks5 => self.socks5_connect(&mut stream, target).await?,
+        }
+        debug!("Connected to {target} through proxy {}", self.address);
+        Ok(stream)
+    }
+
+    async fn http_connect(&self, stream: &mut TcpStream, target: &str) -> io::Result<()> {
+        let mut request = format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n");
+        if let Some((user, pass)) = &self.credentials {
+            let token = BASE64.encode(format!("{user}:{pass}"));
+            request.push_str(&format!("Proxy-Authorization: Basic {token}\r\n"));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await?;
+
+        // read byte by byte so that nothing after the header, which belongs to the tunnel, is consumed
+        let mut response = Vec::new();
+        while !response.ends_with(b"\r\n\r\n") {
+            if response.len() >= MAX_CONNECT_RESPONSE {
+                return Err(proxy_error("Proxy response header too long"));
+            }
+            response.push(stream.read_u8().await?);
+        }
+        let response = String::from_utf8_lossy(&response);
+        let status = response.lines().next().unwrap_or_default();
+        match status.split_whitespace().nth(1) {
+            Some("200") => Ok(()),
+            _ => Err(proxy_error(format!(
+                "Proxy refused tunnel to {target}: {status}"
+            ))),
+        }
+    }
+
+    async fn socks5_connect(&self, stream: &mut TcpStream, target: &str) -> io::Result<()> {
+        let (host, port) = split_host_port(target)?;
+        // greeting, offering username/password authentication only if we have credentials
+        let method = if self.credentials.is_some() {
+            0x02
+        } else {
+            0x00
+        };
+        stream.write_all(&[0x05, 0x01, method]).await?;
+        let mut reply = [0u8; 2];
+        stream.read_exact(&mut reply).await?;
+        if reply != [0x05, method] {
+            return Err(proxy_error(
+                "SOCKS5 proxy rejected the authentication method",
+            ));
+        }
+        if let Some((user, pass)) = &self.credentials {
+            let mut auth = vec![0x01, socks5_len("username", user)?];
+            auth.extend(user.as_bytes());
+            auth.push(socks5_len("password", pass)?);
+            auth.extend(pass.as_bytes());
+            stream.write_all(&auth).await?;
+            stream.read_exact(&mut reply).await?;
+            if reply[1] != 0x00 {
+                return Err(proxy_error("SOCKS5 proxy rejected the credentials"));
+            }
+        }
+
+        let mut request = vec![0x05, 0x01, 0x00];
+        match host.parse::<IpAddr>() {
+            Ok(IpAddr::V4(ip)) => {
+                request.push(0x01);
+                request.extend(ip.octets());
+            }
+            Ok(IpAddr::V6(ip)) => {
+                request.push(0x04);
+                request.extend(ip.octets());
+            }
+            Err(_) => {
+                request.extend([0x03, socks5_len("host name", host)?]);
+                request.extend(host.as_bytes());
+            }
+        }
+        request.extend(port.to_be_bytes());
+        stream.write_all(&request).await?;
+
+        let mut header = [0u8; 4];
+        stream.read_exact(&mut header).await?;
+        if header[1] != 0x00 {
+            return Err(proxy_error(format!(
+                "SOCKS5 proxy failed to connect to {target} with code {}",
+                header[1]
+            )));
+        }
+        // skip the bound address and port the proxy reports
+        let address_len = match header[3] {
+            0x01 => 4,
+            0x04 => 16,
+            0x03 => stream.read_u8().await? as usize,
+            _ => return Err(proxy_error("SOCKS5 proxy sent an invalid reply")),
+        };
+        let mut bound = vec![0u8; address_len + 2];
+        stream.read_exact(&mut bound).await?;
+        Ok(())
+    }
+}
+
+/// Length of a SOCKS5 field, which is sent as a single byte, so longer values are refused rather than truncated
+fn socks5_len(field: &str, value: &str) -> io::Result<u8> {
+    u8::try_from(value.len()).map_err(|_| {
+        proxy_error(format!(
+            "SOCKS5 {field} is {} bytes long, at most 255 are supported",
+            value.len()
+        ))
+    })
+}
+
+fn proxy_error(message: impl Into<String>) -> io::Error {
+    io::Error::other(message.into())
+}
+
+fn split_host_port(target: &str) -> io::Result<(&str, u16)> {
+    let (host, port) = target
+        .rsplit_once(':')
+        .ok_or_else(|| proxy_error(format!("Missing port in {target}")))?;
+    let port = port
+        .parse()
+        .map_err(|_| proxy_error(format!("Invalid port in {target}")))?;
+    Ok((host.trim_start_matches('[').trim_end_matches(']'), port))
+}
+
+static PROXY: LazyLock<Mutex<Option<ProxyConfig>>> = LazyLock::new(|| Mutex::new(None));
+
+pub fn set_proxy(proxy: Option<ProxyConfig>) {
+    *PROXY.lock().unwrap() = proxy;
+    CLIENT_GENERATION.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn proxy() -> Option<ProxyConfig> {
+    PROXY.lock().unwrap().clone()
+}
+
+/// Pooling of outbound HTTP connections and the size of the HTTP servers the relayer runs
+#[derive(Debug, Clone)]
+pub struct HttpPool {
+    /// worker threads of each HTTP server, the RPC bridge included
+    pub workers: usize,
+    /// most connections one client keeps open at once
+    pub max_connections: usize,
+    /// how long an idle connection is kept open to be reused
+    pub keep_alive: Duration,
+    /// how long a connection is reused for before it is replaced
+    pub lifetime: Duration,
+    /// RPC requests go through the bridge even when nothing else needs it, since web30 opens a new connection for
+    /// every request while the bridge reuses its pooled ones
+    pub pool_rpc: bool,
+}
+
+/// The same as awc's defaults, with one worker per server
+impl Default for HttpPool {
+    fn default() -> Self {
+        HttpPool {
+            workers: 1,
+            max_connections: 100,
+            keep_alive: Duration::from_secs(15),
+            lifetime: Duration::from_secs(75),
+            pool_rpc: false,
+        }
+    }
+}
+
+static HTTP_POOL: LazyLock<Mutex<HttpPool>> = LazyLock::new(|| Mutex::new(HttpPool::default()));
+
+pub fn set_http_pool(pool: HttpPool) {
+    *HTTP_POOL.lock().unwrap() = pool;
+    CLIENT_GENERATION.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn http_pool() -> HttpPool {
+    HTTP_POOL.lock().unwrap().clone()
+}
+
+/// Worker threads each HTTP server is started with
+pub fn http_workers() -> usize {
+    HTTP_POOL.lock().unwrap().workers.max(1)
+}
+
+/// Certificates trusted for outbound TLS connections besides the system's
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TlsTrust {
+    /// PEM files of CA certificates to trust as well, such as an internal CA
+    pub extra_ca_certs: Vec<PathBuf>,
+    /// accept any certificate, leaving connections open to interception
+    pub insecure_skip_verify: bool,
+}
+
+impl TlsTrust {
+    fn is_default(&self) -> bool {
+        *self == TlsTrust::default()
+    }
+}
+
+/// The configured trust and the connector built from it, None while the default connector will do
+static TLS_TRUST: LazyLock<Mutex<(TlsTrust, Option<SslConnector>)>> =
+    LazyLock::new(|| Mutex::new((TlsTrust::default(), None)));
+
+/// Sets the certificates outbound requests trust, failing if a CA certificate can't be loaded
+pub fn set_tls_trust(trust: TlsTrust) -> Result<(), String> {
+    if trust.insecure_skip_verify {
+        warn!("TLS certificate verification is DISABLED for orchestrator, price and RPC requests");
+        warn!(
+            "Anyone on the network path can impersonate these services and feed the relayer forged data"
+        );
+    }
+    let connector = if trust.is_default() {
+        None
+    } else {
+        Some(tls_builder_for(&trust)?.build())
+    };
+    *TLS_TRUST.lock().unwrap() = (trust, connector);
+    CLIENT_GENERATION.fetch_add(1, Ordering::Relaxed);
+    Ok(())
+}
+
+/// A TLS connector builder trusting the system's CA certificates and the configured extra ones, for clients that
+/// need further settings such as a client certificate
+pub fn tls_connector_builder() -> Result<SslConnectorBuilder, String> {
+    let trust = TLS_TRUST.lock().unwrap().0.clone();
+    tls_builder_for(&trust)
+}
+
+fn tls_builder_for(trust: &TlsTrust) -> Result<SslConnectorBuilder, String> {
+    let mut builder = SslConnector::builder(SslMethod::tls()).map_err(|e| e.to_string())?;
+    for path in &trust.extra_ca_certs {
+        builder
+            .set_ca_file(path)
+            .map_err(|e| format!("Invalid CA certificate {}: {e}", path.display()))?;
+    }
+    if trust.insecure_skip_verify {
+        builder.set_verify(SslVerifyMode::NONE);
+    }
+    // the same protocols awc offers with its default connector
+    builder
+        .set_alpn_protos(b"\x02h2\x08http/1.1")
+        .map_err(|e| e.to_string())?;
+    Ok(builder)
+}
+
+/// awc connector service that tunnels every connection through a proxy, TLS is layered on top by awc
+#[derive(Clone)]
+struct ProxyConnector {
+    proxy: Rc<ProxyConfig>,
+}
+
+impl Service<TcpConnectInfo<Uri>> for ProxyConnector {
+    type Response = TcpConnection<Uri, TcpStream>;
+    type Error = TcpConnectError;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    actix_service::always_ready!();
+
+    fn call(&self, req: TcpConnectInfo<Uri>) -> Self::Future {
+        let proxy = self.proxy.clone();
+        Box::pin(async move {
+            // requests pinned to an address, such as polls of each orchestrator ip, are tunneled to that address
+            let target = match req.addrs().next() {
+                Some(addr) => addr.to_string(),
+                None => format!("{}:{}", req.hostname(), req.port()),
+            };
+            let stream = proxy.connect(&target).await.map_err(TcpConnectError::Io)?;
+            Ok(TcpConnection::new(req.request().clone(), stream))
+        })
+    }
+}
+
+/// Clients built so far on this thread along with the generation of the settings they were built with, by the TLS
+/// connector they use and their timeout. awc clients hold their own connection pool, so reusing them keeps
+/// connections and their TLS sessions open from one request to the next.
+type ClientCache = (u64, HashMap<(usize, Duration), HttpClient>);
+
+thread_local! {
+    static CLIENTS: RefCell<ClientCache> = RefCell::new((0, HashMap::new()));
+}
+
+/// Bumped whenever the proxy, trust or pooling settings change, so clients built with the old ones are dropped
+static CLIENT_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Clients kept per thread, more only come from orchestrators being reconfigured and the cache is then rebuilt
+const MAX_CACHED_CLIENTS: usize = 64;
+
+/// HTTP client for outbound requests with the given request timeout, tunneled through the configured proxy if
+/// there is one and using `tls` instead of the default TLS configuration if given. The client is shared with every
+/// other caller on this thread asking for the same TLS configuration and timeout.
+pub fn http_client(tls: Option<&SslConnector>, timeout: Duration) -> HttpClient {
+    let key = (
+        tls.map_or(0, |tls| std::ptr::from_ref(tls.context()) as usize),
+        timeout,
+    );
+    let generation = CLIENT_GENERATION.load(Ordering::Relaxed);
+    CLIENTS.with_borrow_mut(|(built_with, clients)| {
+        if *built_with != generation || clients.len() >= MAX_CACHED_CLIENTS {
+            *built_with = generation;
+            clients.clear();
+        }
+        clients
+            .entry(key)
+            .or_insert_with(|| build_client(tls, Some(timeout)))
+            .clone()
+    })
+}
+
+fn build_client(tls: Option<&SslConnector>, timeout: Option<Duration>) -> HttpClient {
+    let builder = match timeout {
+        Some(timeout) => HttpClient::builder().timeout(timeout),
+        None => HttpClient::builder().disable_timeout(),
+    };
+    let pool = http_pool();
+    let mut connector = Connector::new()
+        .limit(pool.max_connections)
+        .conn_keep_alive(pool.keep_alive)
+        .conn_lifetime(pool.lifetime);
+    if let Some(tls) = tls.cloned().or_else(|| TLS_TRUST.lock().unwrap().1.clone()) {
+        connector = connector.openssl(tls);
+    }
+    match proxy() {
+        Some(proxy) => builder
+            .connector(connector.connector(ProxyConnector {
+                proxy: Rc::new(proxy),
+            }))
+            .finish(),
+        None => builder.connector(connector).finish(),
+    }
+}
+
+/// Upstream RPC urls served by the bridge, indexed by the path they are served under
+static BRIDGE_UPSTREAMS: LazyLock<Mutex<Vec<String>>> = LazyLock::new(|| Mutex::new(Vec::new()));
+static BRIDGE_ADDRESS: LazyLock<Mutex<Option<SocketAddr>>> = LazyLock::new(|| Mutex::new(None));
+
+/// The url to pass to web30 for the RPC endpoint at `url`. Without a proxy, custom TLS trust, RPC throttling or
+/// pooled RPC connections this is `url` itself, otherwise it is a local bridge url that forwards every request to
+/// `url` through the proxy, with the configured trust and within the endpoint's ceiling.
+pub fn rpc_url(url: &str) -> String {
+    if proxy().is_none()
+        && !http_pool().pool_rpc
+        && TLS_TRUST.lock().unwrap().0.is_default()
+        && !rpc_throttled()
+        && !rpc_faults_enabled()
+    {
+        return url.to_string();
+    }
+    let address = start_bridge().expect("Failed to start the RPC proxy bridge");
+    let mut upstreams = BRIDGE_UPSTREAMS.lock().unwrap();
+    let idx = match upstreams.iter().position(|upstream| upstream == url) {
+        Some(idx) => idx,
+        None => {
+            upstreams.push(url.to_string());
+            upstreams.len() - 1
+        }
+    };
+    format!("http://{address}/{idx}")
+}
+
+/// True if RPC requests may be faulted for testing, they are then injected by the bridge
+#[cfg(feature = "testing")]
+fn rpc_faults_enabled() -> bool {
+    crate::chaos::rpc_faults_enabled()
+}
+
+#[cfg(not(feature = "testing"))]
+fn rpc_faults_enabled() -> bool {
+    false
+}
+
+/// Starts the bridge on a local port the first time it is needed
+fn start_bridge() -> io::Result<SocketAddr> {
+    let mut bridge = BRIDGE_ADDRESS.lock().unwrap();
+    if let Some(address) = *bridge {
+        return Ok(address);
+    }
+    let server = HttpServer::new(|| {
+        // web30 applies its own timeout to each request, the bridge must not cut it short
+        let client = build_client(None, None);
+        App::new()
+            .app_data(web::Data::new(client))
+            .route("/{idx}", web::post().to(forward))
+    })
+    .workers(http_workers())
+    .disable_signals()
+    .bind("127.0.0.1:0")?;
+    let address = server.addrs()[0];
+    actix_rt::spawn(server.run());
+    info!("Forwarding RPC requests via {address}");
+    *bridge = Some(address);
+    Ok(address)
+}
+
+/// Forwards one JSON-RPC request to the upstream at index `idx` and relays the response back unchanged
+async fn forward(
+    client: web::Data<HttpClient>,
+    idx: web::Path<usize>,
+    request: HttpRequest,
+    body: web::Bytes,
+) -> HttpResponse {
+    let Some(upstream) = BRIDGE_UPSTREAMS.lock().unwrap().get(*idx).cloned() else {
+        return HttpResponse::NotFound().finish();
+    };
+    record_rpc_request(&body);
+    throttle(Outbound::Rpc, &upstream).await;
+    #[cfg(feature = "testing")]
+    if let Some(delay) = crate::chaos::rpc_timeout() {
+        actix_rt::time::sleep(delay).await;
+        return HttpResponse::GatewayTimeout().finish();
+    }
+    let mut upstream_request = client.post(&upstream);
+    if let Some(content_type) = request.headers().get(CONTENT_TYPE) {
+        upstream_request = upstream_request.insert_header((CONTENT_TYPE, content_type.clone()));
+    }
+    let mut response = match upstream_request.send_body(body).await {
+        Ok(response) => response,
+        Err(e) => return HttpResponse::BadGateway().body(e.to_string()),
+    };
+    let body = match response.body().limit(MAX_BRIDGE_RESPONSE).await {
+        Ok(body) => body,
+        Err(e) => return HttpResponse::BadGateway().body(e.to_string()),
+    };
+    let mut reply = HttpResponse::build(response.status());
+    if let Some(content_type) = response.headers().get(CONTENT_TYPE) {
+        reply.insert_header((CONTENT_TYPE, content_type.clone()));
+    }
+    reply.body(body)
+}
@@ -0,0 +1,204 @@
+//! Per orchestrator quality scoring. Each entry an orchestrator serves for the first time is a sample of how
+//! long after submission it was picked up, whether another source had already served it and, once evaluated,
+//! whether it turned out to be invalid. Moving averages of these make up a score between 0 and 1, and an
+//! orchestrator whose score falls is only polled every few cycles so that sources mostly serving junk or
+//! duplicates of faster sources cost less. A demoted orchestrator serves fewer samples to recover with, so what
+//! counts against an orchestrator also fades with time. Scores are published on the status endpoint.
+
+use crate::orchestrator::GaslessTransaction;
+use crate::retry::is_blacklisted;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Weight of each new sample in the moving averages
+const SAMPLE_WEIGHT: f64 = 0.05;
+
+/// Samples needed before an orchestrator can be demoted, so a handful of bad entries at startup don't count
+const MIN_SAMPLES: u64 = 20;
+
+/// Pickup latency in seconds that halves the latency part of the score
+const LATENCY_SCALE_SECS: f64 = 30.0;
+
+/// Time over which the pickup latency, duplicate rate and invalid rate of an orchestrator fade to half
+const DECAY_HALF_LIFE: Duration = Duration::from_secs(10 * 60);
+
+/// How long the sources that served an entry are remembered
+const SIGHTING_TTL: Duration = Duration::from_secs(3600);
+
+/// Quality of one orchestrator as published on the status endpoint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrchestratorQuality {
+    /// entries sampled so far
+    pub samples: u64,
+    /// moving average of the seconds between an entry's submission and it being served to us
+    pub pickup_latency_secs: f64,
+    /// moving average fraction of entries another source served first
+    pub duplicate_rate: f64,
+    /// moving average fraction of entries that could never be relayed
+    pub invalid_rate: f64,
+    /// between 0 and 1, higher is better
+    pub score: f64,
+    /// the orchestrator is polled once every this many cycles
+    pub poll_every: u64,
+}
+
+impl Default for OrchestratorQuality {
+    fn default() -> Self {
+        OrchestratorQuality {
+            samples: 0,
+            pickup_latency_secs: 0.0,
+            duplicate_rate: 0.0,
+            invalid_rate: 0.0,
+            score: 1.0,
+            poll_every: 1,
+        }
+    }
+}
+
+impl OrchestratorQuality {
+    fn sample(&mut self, latency_secs: f64, duplicate: bool, invalid: bool) {
+        let average = |average: f64, sample: f64| {
+            if self.samples == 0 {
+                sample
+            } else {
+                average + SAMPLE_WEIGHT * (sample - average)
+            }
+        };
+        self.pickup_latency_secs = average(self.pickup_latency_secs, latency_secs);
+        self.duplicate_rate = average(self.duplicate_rate, f64::from(u8::from(duplicate)));
+        self.invalid_rate = average(self.invalid_rate, f64::from(u8::from(invalid)));
+        self.samples += 1;
+    }
+
+    /// Fades what counts against the orchestrator by `elapsed` worth of `DECAY_HALF_LIFE`
+    fn decay(&mut self, elapsed: Duration) {
+        let kept = 0.5f64.powf(elapsed.as_secs_f64() / DECAY_HALF_LIFE.as_secs_f64());
+        self.pickup_latency_secs *= kept;
+        self.duplicate_rate *= kept;
+        self.invalid_rate *= kept;
+    }
+
+    fn rescore(&mut self, max_demotion: u64) {
+        let latency = LATENCY_SCALE_SECS / (LATENCY_SCALE_SECS + self.pickup_latency_secs);
+        // duplicates still confirm what other sources serve, so they weigh less than junk
+        self.score = (1.0 - self.invalid_rate) * (1.0 - self.duplicate_rate / 2.0) * latency;
+        self.poll_every = if self.samples < MIN_SAMPLES {
+            1
+        } else {
+            ((1.0 / self.score.max(f64::EPSILON)).round() as u64).clamp(1, max_demotion.max(1))
+        };
+    }
+}
+
+#[derive(Debug, Default)]
+struct QualityState {
+    scores: HashMap<String, OrchestratorQuality>,
+    /// orchestrators that served each entry, first one first, by signature hash
+    sightings: HashMap<[u8; 32], (Instant, Vec<String>)>,
+    /// cycles each orchestrator has been skipped for since it was last polled
+    skipped: HashMap<String, u64>,
+    /// when the score of each orchestrator last decayed
+    decayed_at: HashMap<String, Instant>,
+    max_demotion: u64,
+}
+
+static STATE: LazyLock<Mutex<QualityState>> = LazyLock::new(|| {
+    Mutex::new(QualityState {
+        max_demotion: 1,
+        ..Default::default()
+    })
+});
+
+/// Longest an orchestrator is demoted to, polled once every this many cycles, 1 never demotes
+pub fn set_max_demotion(cycles: u64) {
+    STATE.lock().unwrap().max_demotion = cycles;
+}
+
+/// Samples the entries `url` served that it hadn't served before
+pub fn record_served(url: &str, txs: &[GaslessTransaction]) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let mut state = STATE.lock().unwrap();
+    state
+        .sightings
+        .retain(|_, (first_seen, _)| first_seen.elapsed() < SIGHTING_TTL);
+    let max_demotion = state.max_demotion;
+    let QualityState {
+        scores, sightings, ..
+    } = &mut *state;
+    let quality = scores.entry(url.to_string()).or_default();
+    for tx in txs {
+        let (_, sources) = sightings
+            .entry(tx.sig_hash())
+            .or_insert_with(|| (Instant::now(), Vec::new()));
+        if sources.iter().any(|source| source == url) {
+            continue;
+        }
+        let duplicate = !sources.is_empty();
+        sources.push(url.to_string());
+        let latency = now.saturating_sub(tx.submitted_at) as f64;
+        quality.sample(latency, duplicate, is_blacklisted(tx));
+    }
+    quality.rescore(max_demotion);
+}
+
+/// Counts `tx` as invalid against the orchestrator that served it first, once it turns out it can never be
+/// relayed
+pub fn record_invalid(tx: &GaslessTransaction) {
+    let mut state = STATE.lock().unwrap();
+    let max_demotion = state.max_demotion;
+    let QualityState {
+        scores, sightings, ..
+    } = &mut *state;
+    let Some((_, sources)) = sightings.get(&tx.sig_hash()) else {
+        return;
+    };
+    let Some(quality) = sources.first().and_then(|url| scores.get_mut(url)) else {
+        return;
+    };
+    // the entry was sampled as valid when it was served, this turns that sample around
+    quality.invalid_rate = (quality.invalid_rate + SAMPLE_WEIGHT).min(1.0);
+    quality.rescore(max_demotion);
+}
+
+/// True if `url` is due a poll this cycle given its score, counts the cycle as skipped otherwise
+pub fn due_by_quality(url: &str) -> bool {
+    let mut state = STATE.lock().unwrap();
+    let max_demotion = state.max_demotion;
+    let QualityState {
+        scores, decayed_at, ..
+    } = &mut *state;
+    let decayed_at = decayed_at
+        .entry(url.to_string())
+        .or_insert_with(Instant::now);
+    let poll_every = match scores.get_mut(url) {
+        Some(quality) => {
+            quality.decay(decayed_at.elapsed());
+            quality.rescore(max_demotion);
+            quality.poll_every
+        }
+        None => 1,
+    };
+    *decayed_at = Instant::now();
+    let skipped = state.skipped.entry(url.to_string()).or_default();
+    if *skipped + 1 >= poll_every {
+        if *skipped > 0 {
+            info!("Polling demoted orchestrator {url} after skipping {skipped} cycles");
+        }
+        *skipped = 0;
+        true
+    } else {
+        *skipped += 1;
+        false
+    }
+}
+
+/// Quality of every orchestrator sampled so far, by url
+pub fn orchestrator_quality() -> HashMap<String, OrchestratorQuality> {
+    STATE.lock().unwrap().scores.clone()
+}
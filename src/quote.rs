@@ -0,0 +1,253 @@
+//! Quotes for prospective transactions, served on `POST /quote` so that orchestrators and wallets can suggest a
+//! tip before a user signs. A quote is evaluated by the same `prepare_relay` as a relay, without any of its side
+//! effects: nothing is claimed, leased, rate limited or counted against the user, and the relay is only estimated.
+//! The HTTP server runs on its own threads while quotes need the chains of the relay loop, so requests are passed
+//! to a task on the relay loop's thread and answered from there. Every quote costs RPC calls on the relay loop's
+//! endpoints, so only so many may wait at once and each client IP may only ask for so many a minute.
+
+use crate::admin::is_paused;
+use crate::margin::required_margin;
+use crate::orchestrator::GaslessTransaction;
+use crate::profit::TipValuation;
+use crate::proxy::http_workers;
+use crate::registration::registration_policy;
+use crate::relay::{Evaluation, RelayContext, find_chain, prepare_relay, relay_limits};
+use crate::skip::SkipReason;
+use actix_web::{App, HttpRequest, HttpResponse, HttpServer, Responder, web};
+use clarity::Uint256;
+use futures::StreamExt;
+use futures::channel::mpsc::{Receiver, Sender, channel};
+use futures::channel::oneshot;
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+/// Quotes worked on at once, each takes a gas estimate and a price lookup
+const QUOTE_CONCURRENCY: usize = 4;
+
+/// Quotes that may wait to be worked on, beyond that requests are turned away until the queue drains
+const QUOTE_QUEUE: usize = 64;
+
+/// Window the per IP limit applies to
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Times each client IP asked for a quote within the last `RATE_WINDOW`, oldest first
+static RECENT: LazyLock<Mutex<HashMap<IpAddr, VecDeque<Instant>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// What relaying a transaction would take, as returned by `POST /quote`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Quote {
+    /// id of the gasless transaction
+    pub id: String,
+    /// true if the transaction would be relayed if it were submitted now
+    pub accepted: bool,
+    /// why it wouldn't be relayed, None if it would or relaying is paused
+    pub reason: Option<SkipReason>,
+    /// relaying is paused, nothing is accepted until it resumes
+    pub paused: bool,
+    /// gas the relay is estimated to use
+    pub gas_estimate: Option<Uint256>,
+    /// gas price in wei the tip has to cover, allowing for the price rising before the relay is included
+    pub gas_price: Option<Uint256>,
+    pub tip_token: Option<String>,
+    /// tip the transaction carries, in base units of `tip_token`
+    pub tip_amount: Option<Uint256>,
+    /// smallest tip in base units of `tip_token` that would currently be accepted under the profit margin.
+    /// Tips valued by swap quotes aren't linear in their amount, so this is an estimate for them
+    pub min_tip: Option<Uint256>,
+}
+
+/// Query of `POST /quote`
+#[derive(Debug, Clone, Deserialize)]
+pub struct QuoteQuery {
+    /// url of the orchestrator the transaction would be submitted to, for its `[[override]]` limits
+    pub orchestrator: Option<String>,
+}
+
+/// A quote asked for over HTTP, answered on `respond`
+pub struct QuoteRequest {
+    tx: GaslessTransaction,
+    orchestrator: Option<String>,
+    respond: oneshot::Sender<Result<Quote, String>>,
+}
+
+/// Most quotes a single client IP may ask for within `RATE_WINDOW`
+#[derive(Debug, Clone, Copy)]
+struct PerIpLimit(u32);
+
+/// Counts a quote asked for by `ip`, false if it already asked for its limit within the window
+fn within_limit(ip: IpAddr, limit: PerIpLimit) -> bool {
+    let now = Instant::now();
+    let mut recent = RECENT.lock().unwrap();
+    // forget clients with nothing left in the window so the map doesn't grow forever
+    recent.retain(|_, times| {
+        while times
+            .front()
+            .is_some_and(|at| now.duration_since(*at) >= RATE_WINDOW)
+        {
+            times.pop_front();
+        }
+        !times.is_empty()
+    });
+    let times = recent.entry(ip).or_default();
+    if times.len() >= limit.0 as usize {
+        return false;
+    }
+    times.push_back(now);
+    true
+}
+
+async fn quote(
+    requests: web::Data<Sender<QuoteRequest>>,
+    limit: web::Data<PerIpLimit>,
+    request: HttpRequest,
+    query: web::Query<QuoteQuery>,
+    tx: web::Json<GaslessTransaction>,
+) -> impl Responder {
+    if let Some(addr) = request.peer_addr()
+        && !within_limit(addr.ip(), **limit)
+    {
+        return HttpResponse::TooManyRequests().body("Too many quotes from this address");
+    }
+    let (respond, response) = oneshot::channel();
+    let request = QuoteRequest {
+        tx: tx.into_inner(),
+        orchestrator: query.into_inner().orchestrator,
+        respond,
+    };
+    match requests.as_ref().clone().try_send(request) {
+        Ok(()) => {}
+        Err(e) if e.is_full() => {
+            return HttpResponse::ServiceUnavailable().body("Too many quotes waiting, try again");
+        }
+        Err(_) => return HttpResponse::ServiceUnavailable().body("Relayer is shutting down"),
+    }
+    match response.await {
+        Ok(Ok(quote)) => HttpResponse::Ok().json(quote),
+        Ok(Err(e)) => HttpResponse::ServiceUnavailable().body(e),
+        Err(_) => HttpResponse::ServiceUnavailable().body("Relayer is shutting down"),
+    }
+}
+
+/// Starts the quote server on `bind` in the background, serving `POST /quote` to at most `per_ip_per_minute`
+/// requests from each client IP a minute. The returned requests have to be answered with `serve_quotes`.
+pub fn start_quote_server(
+    bind: &str,
+    per_ip_per_minute: u32,
+) -> std::io::Result<Receiver<QuoteRequest>> {
+    let (sender, receiver) = channel(QUOTE_QUEUE);
+    let sender = web::Data::new(sender);
+    let limit = web::Data::new(PerIpLimit(per_ip_per_minute));
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(sender.clone())
+            .app_data(limit.clone())
+            .route("/quote", web::post().to(quote))
+    })
+    .workers(http_workers())
+    .disable_signals()
+    .bind(bind)?
+    .run();
+    info!("Quote server listening on {bind}");
+    actix_rt::spawn(server);
+    Ok(receiver)
+}
+
+/// Answers quote requests for as long as the relayer runs, never returns
+pub async fn serve_quotes(chains: &[RelayContext], requests: Receiver<QuoteRequest>) {
+    requests
+        .for_each_concurrent(QUOTE_CONCURRENCY, |request| async move {
+            let result =
+                quote_transaction(chains, request.orchestrator.as_deref(), &request.tx).await;
+            if let Err(e) = &result {
+                debug!("Failed to quote {}: {e}", request.tx.id());
+            }
+            let _ = request.respond.send(result);
+        })
+        .await;
+    std::future::pending::<()>().await;
+}
+
+/// Quotes relaying `tx` if it were submitted to `orchestrator` now, running the checks of a relay through
+/// `prepare_relay`. Fails only if the chain couldn't be reached or the tip couldn't be valued, anything wrong with
+/// the transaction itself is a reason in the quote.
+pub async fn quote_transaction(
+    chains: &[RelayContext],
+    orchestrator: Option<&str>,
+    tx: &GaslessTransaction,
+) -> Result<Quote, String> {
+    let mut quote = Quote {
+        id: tx.id(),
+        paused: is_paused(),
+        ..Default::default()
+    };
+    let Some(ctx) = find_chain(chains, tx.chain_id) else {
+        quote.reason = Some(SkipReason::UnknownChain);
+        return Ok(quote);
+    };
+    if let Ok(tip) = ctx.decode_tip(tx) {
+        quote.tip_token = Some(tip.token.to_string());
+        quote.tip_amount = Some(tip.amount.into());
+    }
+    let relay = match prepare_relay(ctx, Evaluation::Quote(orchestrator), tx, None).await {
+        Ok(relay) => relay,
+        Err(e) => match e.skip_reason() {
+            Some(reason) => {
+                quote.reason = Some(reason);
+                return Ok(quote);
+            }
+            None => return Err(e.to_string()),
+        },
+    };
+    quote.gas_estimate = Some(relay.gas_used);
+    quote.gas_price = Some(relay.fees.gas_price);
+    quote.min_tip = min_tip(
+        ctx,
+        &relay.tip,
+        relay.gas_used * relay.fees.gas_price * relay.fees.gas_cost_multiple.into(),
+        required_margin(relay.fees.profit_margin_percent),
+        relay.gas_price,
+    );
+    if !relay.profitable {
+        quote.reason = Some(SkipReason::Unprofitable);
+        return Ok(quote);
+    }
+    quote.accepted = !quote.paused;
+    Ok(quote)
+}
+
+/// Smallest tip whose value, net of selling it for the gas token, exceeds `gas_cost` by `margin` percent. Scales
+/// the tip being quoted, so None for a tip of nothing or one worth nothing.
+fn min_tip(
+    ctx: &RelayContext,
+    tip: &TipValuation,
+    gas_cost: Uint256,
+    margin: u32,
+    gas_price: Uint256,
+) -> Option<Uint256> {
+    let required = gas_cost + gas_cost * margin.into() / 100u8.into() + 1u8.into();
+    // never less than the minimum tip announced to orchestrators when registering
+    let required = registration_policy()
+        .and_then(|policy| policy.min_tip)
+        .map_or(required, |min_tip| required.max(min_tip));
+    if ctx.is_gas_token(tip.token) {
+        return Some(required);
+    }
+    if tip.amount.is_zero() || tip.value.is_zero() {
+        return None;
+    }
+    // the liquidation is valued as in `value_tip`, pool fees only apply when they aren't in a swap quote
+    let limits = relay_limits();
+    let fee_bps = match ctx.swap_quoter {
+        Some(_) => 0,
+        None => limits.liquidation_fee_bps.min(9_999),
+    };
+    let value = (required + gas_price * limits.liquidation_gas.into()) * 10_000u16.into()
+        / (10_000 - fee_bps).into();
+    // rounded up, so the tip quoted is always enough
+    Some((value * tip.amount + tip.value - 1u8.into()) / tip.value)
+}
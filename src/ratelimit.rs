@@ -0,0 +1,135 @@
+//! Per user rate limiting, caps how many distinct transactions from one signing address are relayed within
+//! a sliding window so a single spammy user can't monopolize the relayer's gas budget and simulation bandwidth.
+//!
+//! Each address also has a budget of failed simulations. Simulating costs RPC quota and time even when nothing
+//! is relayed, so once an address' transactions have failed simulation too often within a window, none of its
+//! transactions are simulated until the window is over.
+
+use crate::bech32::display_user;
+use clarity::Address;
+use log::warn;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+    /// most transactions relayed for one address within `window`
+    pub max_relays: u32,
+    pub window: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct SimulationBudget {
+    /// most failed simulations of one address' transactions within `window`
+    pub max_failures: u32,
+    pub window: Duration,
+}
+
+static RATE_LIMIT: LazyLock<Mutex<Option<RateLimit>>> = LazyLock::new(|| Mutex::new(None));
+
+/// Transactions counted against one address, as (submitted, signature hash) pairs oldest first
+type SeenTransactions = VecDeque<(Instant, [u8; 32])>;
+
+static RELAYS: LazyLock<Mutex<HashMap<Address, SeenTransactions>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+static SIMULATION_BUDGET: LazyLock<Mutex<Option<SimulationBudget>>> =
+    LazyLock::new(|| Mutex::new(None));
+
+/// Failed simulations of each address in its current window, as (window start, failures)
+static FAILED_SIMULATIONS: LazyLock<Mutex<HashMap<Address, (Instant, u32)>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+pub fn set_rate_limit(limit: Option<RateLimit>) {
+    *RATE_LIMIT.lock().unwrap() = limit;
+}
+
+pub fn rate_limit_enabled() -> bool {
+    RATE_LIMIT.lock().unwrap().is_some()
+}
+
+/// True if transaction `id` of `client` is within the limit. Only relays that were submitted are counted, see
+/// `charge_rate_limit`, and one already counted is always within it.
+pub fn within_rate_limit(client: Address, id: [u8; 32]) -> bool {
+    let Some(limit) = RATE_LIMIT.lock().unwrap().clone() else {
+        return true;
+    };
+    let mut relays = RELAYS.lock().unwrap();
+    forget_expired(&mut relays, &limit);
+    relays.get(&client).is_none_or(|seen| {
+        seen.len() < limit.max_relays as usize || seen.iter().any(|(_, seen_id)| *seen_id == id)
+    })
+}
+
+/// Counts transaction `id` against `client` once a relay of it was submitted. The same transaction submitted
+/// again, for example after a reorg, is only ever counted once.
+pub fn charge_rate_limit(client: Address, id: [u8; 32]) {
+    let Some(limit) = RATE_LIMIT.lock().unwrap().clone() else {
+        return;
+    };
+    let mut relays = RELAYS.lock().unwrap();
+    forget_expired(&mut relays, &limit);
+    let seen = relays.entry(client).or_default();
+    if !seen.iter().any(|(_, seen_id)| *seen_id == id) {
+        seen.push_back((Instant::now(), id));
+    }
+}
+
+/// Drops transactions counted longer than the window ago, and addresses with nothing left in it so the map doesn't
+/// grow forever
+fn forget_expired(relays: &mut HashMap<Address, SeenTransactions>, limit: &RateLimit) {
+    let now = Instant::now();
+    relays.retain(|_, seen| {
+        while seen
+            .front()
+            .is_some_and(|(at, _)| now.duration_since(*at) > limit.window)
+        {
+            seen.pop_front();
+        }
+        !seen.is_empty()
+    });
+}
+
+pub fn set_simulation_budget(budget: Option<SimulationBudget>) {
+    *SIMULATION_BUDGET.lock().unwrap() = budget;
+}
+
+pub fn simulation_budget_enabled() -> bool {
+    SIMULATION_BUDGET.lock().unwrap().is_some()
+}
+
+/// True if transactions of `client` may still be simulated, false once their failed simulations used up the
+/// budget of the current window
+pub fn within_simulation_budget(client: Address) -> bool {
+    let Some(budget) = SIMULATION_BUDGET.lock().unwrap().clone() else {
+        return true;
+    };
+    let mut failures = FAILED_SIMULATIONS.lock().unwrap();
+    failures.retain(|_, (start, _)| start.elapsed() < budget.window);
+    failures
+        .get(&client)
+        .is_none_or(|(_, count)| *count < budget.max_failures)
+}
+
+/// Counts a failed simulation of a transaction from `client` against its budget
+pub fn record_failed_simulation(client: Address) {
+    let Some(budget) = SIMULATION_BUDGET.lock().unwrap().clone() else {
+        return;
+    };
+    let mut failures = FAILED_SIMULATIONS.lock().unwrap();
+    let (start, count) = failures.entry(client).or_insert((Instant::now(), 0));
+    if start.elapsed() >= budget.window {
+        *start = Instant::now();
+        *count = 0;
+    }
+    *count += 1;
+    if *count == budget.max_failures {
+        warn!(
+            user:% = client;
+            "Transactions of {} failed simulation {count} times, not simulating any more of them for {} seconds",
+            display_user(client),
+            budget.window.saturating_sub(start.elapsed()).as_secs()
+        );
+    }
+}
@@ -0,0 +1,169 @@
+//! Receipts of the relays in flight on each chain, polled for all of them at once. A receipt can only appear,
+//! change or disappear with a new block, so they are fetched once per block in a single JSON-RPC batch rather than
+//! once per relay every cycle, keeping RPC load flat however many relays are in flight. Endpoints that don't
+//! accept batches are asked for each receipt on its own, still only once per block.
+
+use crate::proxy::http_client;
+use crate::relay::RelayContext;
+use crate::rpc::redact_url;
+use clarity::Uint256;
+use clarity::utils::display_uint256_as_address;
+use log::debug;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::collections::{HashMap, HashSet};
+use std::sync::{LazyLock, Mutex};
+use web30::types::TransactionReceipt;
+
+/// Receipts carry the logs of their transaction, a batch of them can run large
+const MAX_BATCH_RESPONSE: usize = 16 * 1024 * 1024;
+
+/// Receipts polled at `head`, None for relays that weren't mined as of it
+struct Polled {
+    head: Uint256,
+    receipts: HashMap<Uint256, Option<TransactionReceipt>>,
+}
+
+/// Latest poll of each chain, by chain id
+static POLLED: LazyLock<Mutex<HashMap<u64, Polled>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Endpoints that rejected a batch request
+static NO_BATCHES: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(|| Mutex::new(HashSet::new()));
+
+#[derive(Debug, Deserialize)]
+struct BatchResponse {
+    id: usize,
+    #[serde(default)]
+    result: Option<TransactionReceipt>,
+    #[serde(default)]
+    error: Option<Value>,
+}
+
+/// Receipts of `hashes` on `ctx` as of block `head`, None for a relay that isn't mined yet. Only hashes not yet
+/// polled at `head` are fetched, those whose receipt couldn't be fetched are left out to be tried again.
+pub async fn poll_receipts(
+    ctx: &RelayContext,
+    head: Uint256,
+    hashes: &[Uint256],
+) -> HashMap<Uint256, Option<TransactionReceipt>> {
+    let missing: Vec<Uint256> = {
+        let mut polled = POLLED.lock().unwrap();
+        let polled = polled.entry(ctx.chain_id).or_insert_with(|| Polled {
+            head,
+            receipts: HashMap::new(),
+        });
+        if polled.head != head {
+            polled.head = head;
+            polled.receipts.clear();
+        }
+        hashes
+            .iter()
+            .filter(|hash| !polled.receipts.contains_key(hash))
+            .copied()
+            .collect()
+    };
+    let fetched = fetch_receipts(ctx, &missing).await;
+    let mut polled = POLLED.lock().unwrap();
+    let polled = polled
+        .get_mut(&ctx.chain_id)
+        .expect("Chain was polled above");
+    if polled.head == head {
+        polled.receipts.extend(fetched);
+    }
+    // relays that are no longer in flight aren't kept around
+    polled.receipts.retain(|hash, _| hashes.contains(hash));
+    polled.receipts.clone()
+}
+
+/// Fetches the receipts of `hashes` in one batch if the endpoint accepts batches, each on its own otherwise
+async fn fetch_receipts(
+    ctx: &RelayContext,
+    hashes: &[Uint256],
+) -> HashMap<Uint256, Option<TransactionReceipt>> {
+    let web3 = ctx.web3();
+    let url = web3.get_url();
+    if hashes.len() > 1 && !NO_BATCHES.lock().unwrap().contains(&url) {
+        match fetch_batch(ctx, hashes).await {
+            Ok(receipts) => return receipts,
+            Err(e) => {
+                debug!(
+                    "{url} doesn't accept batched receipt lookups, looking each up on its own: {e}"
+                );
+                NO_BATCHES.lock().unwrap().insert(url);
+            }
+        }
+    }
+    let mut receipts = HashMap::new();
+    for hash in hashes {
+        match web3.eth_get_transaction_receipt(*hash).await {
+            Ok(receipt) => {
+                receipts.insert(*hash, receipt);
+            }
+            Err(e) => debug!(
+                "Failed to get receipt for {}: {e}",
+                display_uint256_as_address(*hash)
+            ),
+        }
+    }
+    receipts
+}
+
+/// Looks up the receipts of `hashes` in a single JSON-RPC batch, receipts the endpoint answered with an error for
+/// are left out
+async fn fetch_batch(
+    ctx: &RelayContext,
+    hashes: &[Uint256],
+) -> Result<HashMap<Uint256, Option<TransactionReceipt>>, String> {
+    let web3 = ctx.web3();
+    let batch: Vec<Value> = hashes
+        .iter()
+        .enumerate()
+        .map(|(id, hash)| {
+            json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "method": "eth_getTransactionReceipt",
+                "params": [display_uint256_as_address(*hash)],
+            })
+        })
+        .collect();
+    // the endpoint being unreachable says nothing about batches, nothing is fetched this cycle
+    let mut response = match http_client(None, web3.get_timeout())
+        .post(web3.get_url())
+        .send_json(&batch)
+        .await
+    {
+        Ok(response) => response,
+        Err(e) => {
+            debug!(
+                "Failed to get receipts from {}: {e}",
+                redact_url(&web3.get_url())
+            );
+            return Ok(HashMap::new());
+        }
+    };
+    if !response.status().is_success() {
+        return Err(format!("status {}", response.status()));
+    }
+    let responses: Vec<BatchResponse> = response
+        .json()
+        .limit(MAX_BATCH_RESPONSE)
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut receipts = HashMap::new();
+    for response in responses {
+        let Some(hash) = hashes.get(response.id) else {
+            continue;
+        };
+        match response.error {
+            Some(error) => debug!(
+                "Failed to get receipt for {}: {error}",
+                display_uint256_as_address(*hash)
+            ),
+            None => {
+                receipts.insert(*hash, response.result);
+            }
+        }
+    }
+    Ok(receipts)
+}
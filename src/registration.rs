@@ -0,0 +1,212 @@
+//! Opt-in registration of the relayer with its orchestrators. The relayer announces its address, the chains it
+//! relays on, the tokens it accepts tips in and its minimum tip, then keeps sending heartbeats so orchestrators
+//! can route transactions to relayers that are up and will actually accept them. It registers again whenever an
+//! orchestrator stops recognizing its heartbeats, and deregisters on shutdown.
+
+use crate::admin::is_paused;
+use crate::error::RelayerError;
+use crate::grpc;
+use crate::orchestrator::{Orchestrator, RELAYING_SERVICE_ROOT};
+use crate::shutdown::sleep_or_shutdown;
+use crate::signer::Signer;
+use awc::http::StatusCode;
+use clarity::{Address, Uint256};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone)]
+pub struct RegistrationPolicy {
+    pub chain_ids: Vec<u64>,
+    /// tokens tips are accepted in, empty if any token the relayer can value is accepted
+    pub tokens: Vec<Address>,
+    /// smallest tip value in wei of ALTHEA the relayer accepts, if announced
+    pub min_tip: Option<Uint256>,
+    /// time between heartbeats, and between attempts to register while registration fails
+    pub heartbeat_interval: Duration,
+}
+
+/// Body of a registration request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Registration {
+    pub relayer: String,
+    pub chain_ids: Vec<u64>,
+    pub tokens: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_tip: Option<Uint256>,
+    pub version: String,
+}
+
+/// Body of heartbeat and deregistration requests
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Heartbeat {
+    pub relayer: String,
+    /// true while relaying is paused from the admin api, the relayer should not be routed transactions
+    pub paused: bool,
+}
+
+static POLICY: LazyLock<Mutex<Option<RegistrationPolicy>>> = LazyLock::new(|| Mutex::new(None));
+
+/// Registration loops still running, so that shutdown can wait for them to deregister
+static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+
+/// Counts a registration loop as running until dropped, including when the loop is aborted
+struct ActiveLoop;
+
+impl Drop for ActiveLoop {
+    fn drop(&mut self) {
+        ACTIVE.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+pub fn set_registration_policy(policy: Option<RegistrationPolicy>) {
+    *POLICY.lock().unwrap() = policy;
+}
+
+pub fn registration_policy() -> Option<RegistrationPolicy> {
+    POLICY.lock().unwrap().clone()
+}
+
+impl RegistrationPolicy {
+    fn registration(&self, signer: &Signer) -> Registration {
+        Registration {
+            relayer: signer.address().to_string(),
+            chain_ids: self.chain_ids.clone(),
+            tokens: self.tokens.iter().map(|token| token.to_string()).collect(),
+            min_tip: self.min_tip,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+}
+
+/// POSTs `body` to `path` under the orchestrator's service root, returning the response status
+async fn post<T: Serialize>(
+    orchestrator: &Orchestrator,
+    signer: &Signer,
+    path: &str,
+    body: &T,
+) -> Result<StatusCode, RelayerError> {
+    let url = format!("{}/{RELAYING_SERVICE_ROOT}/{path}", orchestrator.url);
+    let client = orchestrator.http_client();
+    let response = orchestrator
+        .authenticate(client.post(url), signer)
+        .await?
+        .send_json(body)
+        .await
+        .map_err(RelayerError::orchestrator)?;
+    Ok(response.status())
+}
+
+/// Announces the relayer to the orchestrator
+pub async fn register_relayer(
+    orchestrator: &Orchestrator,
+    signer: &Signer,
+    registration: &Registration,
+) -> Result<(), RelayerError> {
+    if orchestrator.is_grpc() {
+        return grpc::register_relayer(orchestrator, signer, registration).await;
+    }
+    match post(orchestrator, signer, "register", registration).await? {
+        status if status.is_success() => Ok(()),
+        status => Err(RelayerError::orchestrator(format!(
+            "Registration rejected with {status}"
+        ))),
+    }
+}
+
+/// Tells the orchestrator the relayer is still up, returns false if the orchestrator doesn't know the relayer
+/// and it has to register again
+pub async fn send_heartbeat(
+    orchestrator: &Orchestrator,
+    signer: &Signer,
+    heartbeat: &Heartbeat,
+) -> Result<bool, RelayerError> {
+    if orchestrator.is_grpc() {
+        return grpc::send_heartbeat(orchestrator, signer, heartbeat).await;
+    }
+    match post(orchestrator, signer, "heartbeat", heartbeat).await? {
+        status if status.is_success() => Ok(true),
+        StatusCode::NOT_FOUND => Ok(false),
+        status => Err(RelayerError::orchestrator(format!(
+            "Heartbeat rejected with {status}"
+        ))),
+    }
+}
+
+/// Tells the orchestrator to stop routing transactions to the relayer
+pub async fn deregister_relayer(
+    orchestrator: &Orchestrator,
+    signer: &Signer,
+    heartbeat: &Heartbeat,
+) -> Result<(), RelayerError> {
+    if orchestrator.is_grpc() {
+        return grpc::deregister_relayer(orchestrator, signer, heartbeat).await;
+    }
+    match post(orchestrator, signer, "deregister", heartbeat).await? {
+        status if status.is_success() || status == StatusCode::NOT_FOUND => Ok(()),
+        status => Err(RelayerError::orchestrator(format!(
+            "Deregistration rejected with {status}"
+        ))),
+    }
+}
+
+/// Keeps the relayer registered with `orchestrator` until shutdown, then deregisters it
+pub async fn registration_loop(
+    orchestrator: Orchestrator,
+    signer: Signer,
+    policy: RegistrationPolicy,
+) {
+    ACTIVE.fetch_add(1, Ordering::SeqCst);
+    let _active = ActiveLoop;
+    let url = &orchestrator.url;
+    let registration = policy.registration(&signer);
+    let mut registered = false;
+    loop {
+        if !registered {
+            match register_relayer(&orchestrator, &signer, &registration).await {
+                Ok(()) => {
+                    info!("Registered with {url}");
+                    registered = true;
+                }
+                Err(e) => warn!("Failed to register with {url}: {e}"),
+            }
+        } else {
+            let heartbeat = Heartbeat {
+                relayer: registration.relayer.clone(),
+                paused: is_paused(),
+            };
+            match send_heartbeat(&orchestrator, &signer, &heartbeat).await {
+                Ok(true) => debug!("Sent heartbeat to {url}"),
+                Ok(false) => {
+                    warn!("{url} no longer knows this relayer, registering again");
+                    registered = false;
+                    continue;
+                }
+                Err(e) => warn!("Failed to send heartbeat to {url}: {e}"),
+            }
+        }
+        if sleep_or_shutdown(policy.heartbeat_interval).await {
+            break;
+        }
+    }
+    if registered {
+        let heartbeat = Heartbeat {
+            relayer: registration.relayer.clone(),
+            paused: true,
+        };
+        match deregister_relayer(&orchestrator, &signer, &heartbeat).await {
+            Ok(()) => info!("Deregistered from {url}"),
+            Err(e) => warn!("Failed to deregister from {url}: {e}"),
+        }
+    }
+}
+
+/// Waits up to `timeout` for registration loops to deregister after shutdown was requested
+pub async fn finish_registrations(timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+    while ACTIVE.load(Ordering::SeqCst) > 0 && Instant::now() < deadline {
+        actix_rt::time::sleep(Duration::from_millis(50)).await;
+    }
+}
@@ -0,0 +1,3112 @@
+use crate::accounting::{record_spend, spend_cap_reached};
+use crate::admin::{Outcome, is_paused, record_outcome};
+use crate::alert::{
+    alert_on_failure, alert_on_loss, alert_on_missing_tip, alert_on_revert, reset_failure_alert,
+};
+use crate::audit::{
+    AuditInputs, audit_inputs, record_audit_inputs, record_decision, take_audit_inputs,
+};
+use crate::banlist::{bans_enabled, clear_strikes, is_banned, record_strike};
+use crate::bech32::{display_user, to_bech32};
+use crate::blob::DecodeError;
+use crate::command::{CommandFilter, decode_command};
+use crate::confirm::{
+    RelayState, WatchedRelay, abandon_relay, has_pending_relays, is_watched, next_nonce,
+    set_nonce_floor, unwatch_relay, update_relay_block, watch_relay, watched_relays,
+};
+use crate::conflict::resolve_conflicts;
+use crate::congestion::{block_gas_cap, chain_congested};
+use crate::decoder::decode_transaction;
+use crate::error::{FailureKind, RelayerError};
+use crate::funds::{check_funds, clear_funds_cache, funds_check_enabled};
+use crate::gas::{
+    cache_estimate, cached_estimate, cheaper_access_list, gas_price, pessimistic_gas_price,
+    suggest_fees,
+};
+use crate::gasmodel::{expected_min_gas, record_gas_used};
+use crate::history::{RelayRecord, attach_trace, finish_execution, record_execution, record_relay};
+use crate::inspect::{inspect_relay, trace_transaction};
+use crate::layout::{ContractLayout, RelayTarget};
+use crate::lease::{
+    acquire_relay_lease, holds_account_lease, is_leader, keep_relay_lease, may_broadcast,
+    release_relay_lease,
+};
+use crate::margin::{record_included_relay, record_losing_relay, record_wasted_relay};
+use crate::mempool::{competing_relay, refresh_mempool};
+use crate::multicall::encode_aggregate3;
+use crate::observer::{check_observations, is_observing, record_observation};
+use crate::orchestrator::{GaslessTransaction, Orchestrator, RelayerTip, claim_transaction};
+use crate::overflow::{self, admit};
+use crate::payout::missing_tip;
+use crate::price::{Price, PriceSource, SwapQuoter, TokenAmount, token_decimals, unit_price};
+use crate::profit::{
+    FeeData, ProfitInputs, ProfitPolicy, TipValuation, record_tip_value, take_tip_value,
+};
+use crate::quality::record_invalid;
+use crate::ratelimit::{
+    charge_rate_limit, rate_limit_enabled, record_failed_simulation, simulation_budget_enabled,
+    within_rate_limit, within_simulation_budget,
+};
+use crate::receipts::poll_receipts;
+use crate::registration::registration_policy;
+use crate::retry::{
+    blacklist, clear_failures, clear_retry, due_retries, is_backing_off, is_blacklisted,
+    is_cooling_down, record_failure_cooldown, requeue, schedule_retry,
+};
+use crate::revert::is_user_fault;
+use crate::rpc::{RpcPool, redact_url};
+use crate::shutdown::{is_shutting_down, sleep_or_shutdown};
+use crate::signer::{Signer, SignerPool};
+use crate::skip::SkipReason;
+use crate::slo::{exceeded_latency_slo, record_inclusion};
+use crate::split::accrue_tip_split;
+use crate::status::{
+    UnconfirmedTransaction, add_in_flight, record_malformed, record_skip, record_unconfirmed,
+    remove_in_flight, track_token, unix_timestamp,
+};
+use crate::subsidy::{
+    release_subsidy, reserve_subsidy, settle_subsidy, subsidy_available, subsidy_enabled,
+    subsidy_left,
+};
+use crate::summary::{
+    record_failed_relay, record_final_relay, record_gas_spent, record_relay_attempt,
+};
+use crate::tendermint::wait_for_tendermint_block;
+use crate::tokens::{display_token, resolve_token, token_symbol};
+use crate::urgency::{Urgency, escalate_priority_fee, record_inclusion_latency, urgency};
+use clap::ValueEnum;
+use clarity::{
+    Address, Transaction, Uint256, abi::derive_method_id, abi::get_hash,
+    utils::display_uint256_as_address,
+};
+use futures::future::{join, join5};
+use futures::stream::{self, StreamExt};
+use log::{debug, error, info, trace, warn};
+use num_traits::ToPrimitive;
+use rand::seq::SliceRandom;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::sync::{LazyLock, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{Instrument, Span, info_span};
+use web30::{
+    client::Web3,
+    jsonrpc::client::HttpClient,
+    jsonrpc::error::Web3Error,
+    types::{Data, TransactionReceipt, TransactionRequest},
+};
+
+/// Gas used by a typical relay, only used to rank transactions before they are simulated
+const ESTIMATED_RELAY_GAS: u32 = 250_000;
+/// How often relays are checked in the background, pending relays are also re-simulated each time to detect a
+/// competitor relaying the same command first
+const CONFIRMATION_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// Blocks in a row a pending relay must revert against the pending block before it is cancelled, a single revert
+/// may only be the market moving before the relay is included
+const REVERTS_BEFORE_CANCEL: u32 = 3;
+
+/// Reverts seen in a row for each pending relay by tx hash, with the block the last one was seen at
+static REVERTS: LazyLock<Mutex<HashMap<Uint256, (u32, Uint256)>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Settings and connections used to relay on a single chain
+pub struct RelayContext {
+    /// chain this context relays on, transactions for other chains are never sent here
+    pub chain_id: u64,
+    /// human readable chain name for logs
+    pub name: String,
+    pub rpc: RpcPool,
+    /// relayer accounts available on this chain
+    pub signers: SignerPool,
+    /// addresses other than our accounts that tips may pay, such as the treasury of a relayer pool
+    pub tip_receivers: Vec<Address>,
+    pub contract_address: Address,
+    /// relay function, tip layout and receiver markers of the dex on this chain
+    pub layout: ContractLayout,
+    /// periphery contracts transactions may select with their `target` besides the dex
+    pub targets: Vec<RelayTarget>,
+    /// ERC-4337 EntryPoint UserOperations are bundled to, bundling is disabled when unset
+    pub entry_point: Option<Address>,
+    /// values tips that aren't paid in the gas token
+    pub price_source: Rc<dyn PriceSource>,
+    /// wrapped gas token, tips in it are worth their amount in wei like native tips
+    pub wrapped_native_token: Option<Address>,
+    /// values tips by the dex quote for selling them instead of `price_source` when set
+    pub swap_quoter: Option<SwapQuoter>,
+    /// decides whether a transaction is worth relaying once its gas and tip have been estimated
+    pub profit_policy: Rc<dyn ProfitPolicy>,
+    /// how long to wait for a submitted relay to be included before its outcome is treated as unknown
+    pub receipt_timeout: Duration,
+    /// perform every step up to submission but never broadcast
+    pub dry_run: bool,
+    /// reserve each transaction with its orchestrator before simulating it
+    pub claim: bool,
+    /// multicall contract used to bundle several relays into one transaction, batching is disabled when unset
+    pub multicall: Option<Address>,
+    /// maximum number of relays carried by one multicall transaction
+    pub max_batch_size: usize,
+    /// gas estimates and tip valuations looked up at once ahead of relaying each cycle's transactions, they are
+    /// looked up one transaction at a time while relaying when this is below 2
+    pub estimate_concurrency: usize,
+    /// relays are watched for reorgs until they are this many blocks deep
+    pub confirmation_blocks: u64,
+    /// private or priority endpoint relays are submitted through so competing relayers can't frontrun them
+    /// from the public mempool, falls back to public broadcast if it fails
+    pub private_rpc: Option<Web3>,
+    /// endpoint high value relays are traced on with `debug_traceCall`, such as a local fork, the chain's RPC
+    /// when unset
+    pub trace_rpc: Option<Web3>,
+}
+
+/// Profitability and safety limits shared by every chain, these can be replaced while running when the
+/// configuration is reloaded
+#[derive(Debug, Clone)]
+pub struct RelayLimits {
+    /// gas price in wei above which relaying is paused
+    pub max_gas_price: Option<Uint256>,
+    /// while the gas price is above `max_gas_price` still relay transactions whose tip is worth at least
+    /// this many times the gas cost
+    pub over_cap_tip_multiple: Option<u32>,
+    /// transactions whose gas estimate exceeds this are never relayed, however profitable they look
+    pub max_gas_limit: Option<Uint256>,
+    /// commands the operator is willing to relay
+    pub allowed_commands: CommandFilter,
+    /// the tip must exceed the gas cost by this many percent
+    pub profit_margin_percent: u32,
+    /// gas used by the transaction that sells a tip for the gas token
+    pub liquidation_gas: u64,
+    /// pool fees paid when selling a tip for the gas token, in basis points of its value
+    pub liquidation_fee_bps: u32,
+    /// wei of the gas token each relayer account keeps back for liquidating tips and cancelling stuck relays,
+    /// no relay is submitted that could take the balance below it
+    pub reserve_balance: Uint256,
+    /// transactions submitted to their orchestrator longer ago than this are skipped without being simulated
+    pub max_tx_age: Option<Duration>,
+    /// relays whose tip is worth at least this many wei of the gas token are traced before they are broadcast,
+    /// and skipped unless the trace shows the tip paid and nothing leaving our accounts
+    pub trace_min_tip: Option<Uint256>,
+    /// users whose transactions are relayed whether or not their tip covers the gas, as agreed with them off chain.
+    /// Every other check still applies
+    pub partners: HashSet<Address>,
+    /// limits that replace these for transactions from particular orchestrators or tipping in particular tokens,
+    /// the first one matching a transaction applies
+    pub overrides: Vec<LimitOverride>,
+}
+
+/// Limits for the transactions of one orchestrator or tip token, see `RelayLimits::for_tip`
+#[derive(Debug, Clone, Default)]
+pub struct LimitOverride {
+    /// url of the orchestrator this applies to, any orchestrator when None
+    pub orchestrator: Option<String>,
+    /// tip tokens this applies to, any token when empty
+    pub tip_tokens: HashSet<Address>,
+    pub profit_margin_percent: Option<u32>,
+    pub max_gas_limit: Option<Uint256>,
+    /// the only tip tokens relayed, any token when None
+    pub allowed_tip_tokens: Option<HashSet<Address>>,
+}
+
+impl LimitOverride {
+    fn matches(&self, orchestrator: Option<&str>, tip_token: Address) -> bool {
+        let orchestrator_matches = match &self.orchestrator {
+            Some(url) => {
+                orchestrator.is_some_and(|o| o.trim_end_matches('/') == url.trim_end_matches('/'))
+            }
+            None => true,
+        };
+        orchestrator_matches && (self.tip_tokens.is_empty() || self.tip_tokens.contains(&tip_token))
+    }
+}
+
+impl RelayLimits {
+    /// The first override matching a transaction from `orchestrator` tipping in `tip_token`
+    fn matching_override(
+        &self,
+        orchestrator: Option<&str>,
+        tip_token: Address,
+    ) -> Option<&LimitOverride> {
+        self.overrides
+            .iter()
+            .find(|o| o.matches(orchestrator, tip_token))
+    }
+
+    /// These limits with the first override matching a transaction from `orchestrator` tipping in `tip_token`
+    /// applied
+    pub fn for_tip(&self, orchestrator: Option<&str>, tip_token: Address) -> RelayLimits {
+        let mut limits = self.clone();
+        if let Some(o) = self.matching_override(orchestrator, tip_token) {
+            if let Some(margin) = o.profit_margin_percent {
+                limits.profit_margin_percent = margin;
+            }
+            if let Some(max_gas_limit) = o.max_gas_limit {
+                limits.max_gas_limit = Some(max_gas_limit);
+            }
+        }
+        limits
+    }
+
+    /// False if the override matching a transaction from `orchestrator` tipping in `tip_token` doesn't allow
+    /// the token
+    pub fn allows_tip_token(&self, orchestrator: Option<&str>, tip_token: Address) -> bool {
+        self.matching_override(orchestrator, tip_token)
+            .and_then(|o| o.allowed_tip_tokens.as_ref())
+            .is_none_or(|allowed| allowed.contains(&tip_token))
+    }
+}
+
+impl Default for RelayLimits {
+    fn default() -> Self {
+        RelayLimits {
+            max_gas_price: None,
+            over_cap_tip_multiple: None,
+            max_gas_limit: None,
+            allowed_commands: CommandFilter::default(),
+            profit_margin_percent: 10,
+            liquidation_gas: 0,
+            liquidation_fee_bps: 0,
+            reserve_balance: Uint256::default(),
+            max_tx_age: None,
+            trace_min_tip: None,
+            partners: HashSet::new(),
+            overrides: Vec::new(),
+        }
+    }
+}
+
+static RELAY_LIMITS: LazyLock<RwLock<RelayLimits>> =
+    LazyLock::new(|| RwLock::new(RelayLimits::default()));
+
+pub fn set_relay_limits(limits: RelayLimits) {
+    *RELAY_LIMITS.write().unwrap() = limits;
+}
+
+pub fn relay_limits() -> RelayLimits {
+    RELAY_LIMITS.read().unwrap().clone()
+}
+
+impl RelayContext {
+    /// Client for the chain's currently active RPC endpoint
+    pub fn web3(&self) -> &Web3 {
+        self.rpc.web3()
+    }
+
+    /// Decodes the tip of `tx` as laid out by this chain's dex
+    pub fn decode_tip(&self, tx: &GaslessTransaction) -> Result<RelayerTip, DecodeError> {
+        self.layout.decode_tip(&tx.tip)
+    }
+
+    /// Contract `tx` is relayed to and the function it is relayed through, the dex unless `tx` selects one of the
+    /// configured periphery contracts. Transactions selecting any other contract are refused.
+    pub fn relay_target(&self, tx: &GaslessTransaction) -> Result<(Address, &str), RelayerError> {
+        let Some(target) = tx.target.filter(|target| *target != self.contract_address) else {
+            return Ok((self.contract_address, &self.layout.relay_function));
+        };
+        self.targets
+            .iter()
+            .find(|t| t.contract == target)
+            .map(|t| {
+                (
+                    t.contract,
+                    t.relay_function
+                        .as_deref()
+                        .unwrap_or(&self.layout.relay_function),
+                )
+            })
+            .ok_or_else(|| {
+                RelayerError::invalid_tx(format!("Unknown relay target {target} on {}", self.name))
+            })
+    }
+
+    /// True if tips in `token` are paid in the gas token, either natively (the zero address) or wrapped,
+    /// these need no price lookup
+    pub fn is_gas_token(&self, token: Address) -> bool {
+        token == Address::default() || Some(token) == self.wrapped_native_token
+    }
+}
+
+/// Drops transactions older than `max_tx_age`, these are almost always expired, already relayed or abandoned
+/// and not worth an RPC round trip to find out
+fn drop_stale(
+    orchestrator_url: Option<&str>,
+    txs: &[GaslessTransaction],
+) -> Vec<GaslessTransaction> {
+    let Some(max_age) = relay_limits().max_tx_age else {
+        return txs.to_vec();
+    };
+    let now = unix_timestamp();
+    txs.iter()
+        .filter(|tx| {
+            let age = now.saturating_sub(tx.submitted_at);
+            if age > max_age.as_secs() {
+                debug!(
+                    tx:% = tx.id(),
+                    decision = "stale",
+                    skip_reason:% = SkipReason::Stale;
+                    "Transaction {} was submitted {age} seconds ago, skipping it as stale",
+                    tx.id()
+                );
+                record_skip(orchestrator_url, tx, SkipReason::Stale);
+                return false;
+            }
+            true
+        })
+        .cloned()
+        .collect()
+}
+
+/// Moves transactions whose deadline is close to the front of the queue, keeping the order among the urgent ones
+/// and among the rest
+fn urgent_first(mut txs: Vec<&GaslessTransaction>) -> Vec<&GaslessTransaction> {
+    let now = unix_time();
+    txs.sort_by_cached_key(|tx| {
+        !tx.decode_conditions()
+            .is_ok_and(|conds| urgency(tx.chain_id, conds.deadline, now) == Urgency::Urgent)
+    });
+    txs
+}
+
+/// Finds the chain a transaction should be relayed on based on its `chain_id`
+pub fn find_chain(chains: &[RelayContext], chain_id: u64) -> Option<&RelayContext> {
+    chains.iter().find(|ctx| ctx.chain_id == chain_id)
+}
+
+/// Checks if each transaction is valid and profitable to relay before submitting it to the chain it
+/// targets, transactions for chains we are not configured for are skipped. When a multicall contract is
+/// configured transactions that can share a sender are bundled together. The most profitable looking
+/// transactions are processed first, unless another evaluation order is set, and only the first of several
+/// transactions using the same user nonce. `orchestrator` is the source the transactions were fetched from, if any.
+pub async fn process_transactions(
+    chains: &[RelayContext],
+    orchestrator: Option<&Orchestrator>,
+    txs: &[GaslessTransaction],
+) {
+    let orchestrator_url = orchestrator.map(|orchestrator| orchestrator.url.as_str());
+    let txs = drop_stale(orchestrator_url, txs);
+    let txs = match evaluation_order() {
+        EvaluationOrder::Profit => prioritize(chains, &txs).await,
+        order => spread(chains, &txs, order),
+    };
+    let txs = defer_low_value(chains, orchestrator_url, txs).await;
+    let txs = urgent_first(txs);
+    let txs = resolve_conflicts(chains, orchestrator_url, txs);
+    prefetch(chains, orchestrator_url, &txs).await;
+    // relays waiting to be bundled into one multicall transaction
+    let mut batch: Vec<PreparedRelay> = Vec::new();
+    for (idx, &tx) in txs.iter().enumerate() {
+        if is_shutting_down() {
+            debug!("Shutdown requested, not processing remaining transactions");
+            break;
+        }
+        debug!("Processing transaction {}/{}", idx + 1, txs.len());
+        debug!(
+            "Transaction details - Chain ID: {}, Callpath: {}",
+            tx.chain_id, tx.callpath
+        );
+
+        let Some(ctx) = find_chain(chains, tx.chain_id) else {
+            debug!(
+                tx:% = tx.id(),
+                skip_reason:% = SkipReason::UnknownChain;
+                "Transaction {} is for unconfigured chain {}, skipping",
+                tx.id(),
+                tx.chain_id
+            );
+            record_skip(orchestrator_url, tx, SkipReason::UnknownChain);
+            continue;
+        };
+
+        if is_blacklisted(tx) {
+            debug!(
+                tx:% = tx.id(),
+                skip_reason:% = SkipReason::Blacklisted;
+                "Transaction {} can never be relayed, skipping",
+                tx.id()
+            );
+            record_skip(orchestrator_url, tx, SkipReason::Blacklisted);
+            continue;
+        }
+
+        if is_watched(tx) {
+            debug!(
+                tx:% = tx.id(),
+                skip_reason:% = SkipReason::AlreadyRelayed;
+                "Transaction {} was already relayed by us, skipping",
+                tx.id()
+            );
+            record_skip(orchestrator_url, tx, SkipReason::AlreadyRelayed);
+            continue;
+        }
+
+        if is_backing_off(tx) {
+            debug!("Transaction {} is waiting to be retried, skipping", tx.id());
+            continue;
+        }
+
+        if is_cooling_down(tx) {
+            debug!(
+                "Transaction {} failed recently and is cooling down, skipping",
+                tx.id()
+            );
+            continue;
+        }
+
+        if !admit(tx, orchestrator) {
+            continue;
+        }
+
+        if ctx.multicall.is_none() {
+            let result = relay_transaction(ctx, orchestrator, tx, None)
+                .instrument(relay_span(ctx, tx))
+                .await;
+            handle_relay_result(ctx, orchestrator, tx, result);
+            continue;
+        }
+
+        // batches are built per chain, flush whatever we have for other chains first
+        if let Some(pending) = batch.first()
+            && pending.ctx.chain_id != ctx.chain_id
+        {
+            relay_batch(orchestrator, std::mem::take(&mut batch)).await;
+        }
+        let preferred = batch.first().map(|relay| relay.signer);
+        match prepare_relay(ctx, Evaluation::Relay(orchestrator), tx, preferred)
+            .instrument(relay_span(ctx, tx))
+            .await
+        {
+            Ok(relay)
+                if relay.batchable
+                    && preferred.is_none_or(|s| s.address() == relay.signer.address()) =>
+            {
+                batch.push(relay);
+                if batch.len() >= ctx.max_batch_size {
+                    relay_batch(orchestrator, std::mem::take(&mut batch)).await;
+                }
+            }
+            Ok(relay) => {
+                let result = submit_relay(ctx, orchestrator, relay)
+                    .instrument(relay_span(ctx, tx))
+                    .await;
+                handle_relay_result(ctx, orchestrator, tx, result);
+            }
+            Err(e) => handle_relay_result(ctx, orchestrator, tx, Err(e)),
+        }
+    }
+    relay_batch(orchestrator, batch).await;
+}
+
+/// Gas estimate and tip value of a transaction looked up ahead of relaying it
+#[derive(Debug, Clone)]
+struct Prefetched {
+    /// the revert reason if the estimate reverted
+    gas_used: Result<Uint256, String>,
+    /// `None` if the tip could not be valued
+    tip: Option<TipValuation>,
+}
+
+/// Lookups made ahead for the transactions being relayed, by signature hash, each is taken when its transaction
+/// is evaluated
+static PREFETCHED: LazyLock<Mutex<HashMap<[u8; 32], Prefetched>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Estimates the gas and values the tip of every transaction about to be evaluated, up to `estimate_concurrency`
+/// at a time on each chain, so that a long queue doesn't wait for each lookup in turn. Lookups that fail for any
+/// reason other than a revert are left to be made again when the transaction is evaluated.
+async fn prefetch(
+    chains: &[RelayContext],
+    orchestrator_url: Option<&str>,
+    txs: &[&GaslessTransaction],
+) {
+    PREFETCHED.lock().unwrap().clear();
+    clear_funds_cache();
+    let now = unix_time();
+    let limits = relay_limits();
+    for ctx in chains {
+        if ctx.estimate_concurrency < 2 {
+            continue;
+        }
+        let candidates: Vec<(&GaslessTransaction, RelayerTip)> = txs
+            .iter()
+            .filter(|tx| {
+                tx.chain_id == ctx.chain_id
+                    && !tx.cmd.is_empty()
+                    && !is_blacklisted(tx)
+                    && !is_watched(tx)
+                    && !is_backing_off(tx)
+                    && !is_cooling_down(tx)
+                    && tx.decode_conditions().is_ok_and(|conds| conds.is_live(now))
+                    && may_simulate(ctx, tx)
+            })
+            .filter_map(|tx| Some((*tx, ctx.decode_tip(tx).ok()?)))
+            .filter(|(_, tip)| limits.allows_tip_token(orchestrator_url, tip.token))
+            // whatever is over this cycle's cap is deferred, not evaluated
+            .take(overflow::remaining())
+            .collect();
+        if candidates.len() < 2 {
+            continue;
+        }
+        let Ok(gas_price) = gas_price(ctx.web3(), ctx.chain_id).await else {
+            continue;
+        };
+        let started = Instant::now();
+        let results: Vec<([u8; 32], Option<Prefetched>)> = stream::iter(candidates)
+            .map(|(tx, tip)| {
+                let margin = limits
+                    .for_tip(orchestrator_url, tip.token)
+                    .profit_margin_percent;
+                async move {
+                    (
+                        tx.sig_hash(),
+                        prefetch_one(ctx, tx, tip, gas_price, margin).await,
+                    )
+                }
+            })
+            .buffer_unordered(ctx.estimate_concurrency)
+            .collect()
+            .await;
+        debug!(
+            "Looked up {} transactions on {} in {}ms",
+            results.len(),
+            ctx.name,
+            started.elapsed().as_millis()
+        );
+        let mut prefetched = PREFETCHED.lock().unwrap();
+        for (key, result) in results {
+            if let Some(result) = result {
+                prefetched.insert(key, result);
+            }
+        }
+    }
+}
+
+/// False if `tx` is from a user whose transactions failed simulation too often lately
+fn may_simulate(ctx: &RelayContext, tx: &GaslessTransaction) -> bool {
+    !simulation_budget_enabled()
+        || tx
+            .client(ctx.contract_address)
+            .is_ok_and(within_simulation_budget)
+}
+
+/// Estimates the gas of relaying `tx` and values its `tip` at `gas_price`, ruling it out without an estimate if
+/// the tip can't cover its command's least gas at `profit_margin_percent`
+async fn prefetch_one(
+    ctx: &RelayContext,
+    tx: &GaslessTransaction,
+    tip: RelayerTip,
+    gas_price: Uint256,
+    profit_margin_percent: u32,
+) -> Option<Prefetched> {
+    // the account relaying it is only picked when relaying, any account that can estimate it will do
+    let from = ctx
+        .signers
+        .find(tip.receiver)
+        .unwrap_or(ctx.signers.primary())
+        .address();
+    let (target, relay_function) = ctx.relay_target(tx).ok()?;
+    let data = user_cmd_relayer_data(relay_function, tx).ok()?;
+    // left for relaying to rule out, without an estimate
+    if funds_check_enabled()
+        && !matches!(
+            check_funds(ctx, tx, tx.client(ctx.contract_address).ok()?).await,
+            Ok(None)
+        )
+    {
+        return None;
+    }
+    if cached_estimate(&tx.sig_hash()).is_none()
+        && let Some(min_gas) = expected_min_gas(ctx.chain_id, &decode_command(tx.callpath, &tx.cmd))
+        && let Ok(tip) = value_tip(ctx, tip.amount.into(), tip.token, gas_price).await
+        && predicted_unprofitable(ctx, tx, min_gas, tip, gas_price, profit_margin_percent)
+    {
+        return None;
+    }
+    let estimate = async {
+        match cached_estimate(&tx.sig_hash()) {
+            Some(gas) => Ok(gas),
+            None => {
+                ctx.web3()
+                    .eth_estimate_gas(TransactionRequest::quick_tx(from, target, data))
+                    .await
+            }
+        }
+    };
+    let (gas_used, tip) = join(
+        estimate,
+        value_tip(ctx, tip.amount.into(), tip.token, gas_price),
+    )
+    .await;
+    let gas_used = match gas_used.map_err(RelayerError::from) {
+        Ok(gas) => {
+            cache_estimate(tx.sig_hash(), gas);
+            Ok(gas)
+        }
+        Err(RelayerError::SimulationRevert(reason)) => Err(reason),
+        Err(_) => return None,
+    };
+    Some(Prefetched {
+        gas_used,
+        tip: tip.ok(),
+    })
+}
+
+/// True if `tip` can't cover the least gas the command of `tx` is expected to use at `gas_price`
+fn predicted_unprofitable(
+    ctx: &RelayContext,
+    tx: &GaslessTransaction,
+    min_gas: Uint256,
+    tip: TipValuation,
+    gas_price: Uint256,
+    profit_margin_percent: u32,
+) -> bool {
+    !ctx.profit_policy.is_profitable(&ProfitInputs {
+        tx: &decode_transaction(tx, ctx.contract_address),
+        gas_estimate: min_gas,
+        fees: FeeData {
+            gas_price,
+            gas_cost_multiple: 1,
+            profit_margin_percent,
+        },
+        tip,
+    })
+}
+
+/// Order transactions are evaluated in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum EvaluationOrder {
+    /// most profitable looking first
+    #[default]
+    Profit,
+    /// a new random order every time
+    Shuffle,
+    /// an order fixed for each relayer account, by the hash of its address and the signature
+    Salted,
+}
+
+static EVALUATION_ORDER: RwLock<EvaluationOrder> = RwLock::new(EvaluationOrder::Profit);
+
+pub fn set_evaluation_order(order: EvaluationOrder) {
+    *EVALUATION_ORDER.write().unwrap() = order;
+}
+
+pub fn evaluation_order() -> EvaluationOrder {
+    *EVALUATION_ORDER.read().unwrap()
+}
+
+/// Orders transactions randomly or by a salt unique to this relayer, so that relayers working through the same
+/// queue start in different places instead of all racing for the transactions at its top
+fn spread<'a>(
+    chains: &[RelayContext],
+    txs: &'a [GaslessTransaction],
+    order: EvaluationOrder,
+) -> Vec<&'a GaslessTransaction> {
+    let mut ordered: Vec<&GaslessTransaction> = txs.iter().collect();
+    if order == EvaluationOrder::Shuffle {
+        ordered.shuffle(&mut rand::rng());
+        return ordered;
+    }
+    ordered.sort_by_cached_key(|tx| {
+        let relayer = find_chain(chains, tx.chain_id)
+            .map(|ctx| ctx.signers.primary().address())
+            .unwrap_or_default();
+        get_hash(&[relayer.as_bytes(), &tx.sig_hash()].concat())
+    });
+    ordered
+}
+
+/// Orders transactions by expected profit, highest first, using only checks that are cheap compared to
+/// simulating them: the tip is valued with one spot price lookup per token and chain, even when tips are
+/// valued by swap quotes when relaying, and the gas of a typical relay
+/// at the current gas price is subtracted. Transactions without a tip, outside their validity window or whose
+/// tip can't be valued go last in their original order, the full checks made when relaying skip them.
+async fn prioritize<'a>(
+    chains: &[RelayContext],
+    txs: &'a [GaslessTransaction],
+) -> Vec<&'a GaslessTransaction> {
+    if txs.len() < 2 {
+        return txs.iter().collect();
+    }
+    let ranked = rank(chains, txs.iter()).await;
+    trace!(
+        "Prioritized {} transactions, best expected profit {}",
+        ranked.len(),
+        ranked[0].0.unwrap_or_default()
+    );
+    ranked.into_iter().map(|(_, tx)| tx).collect()
+}
+
+/// While the latency SLO is exceeded, leaves out transactions whose expected profit, as ranked by `prioritize`, is
+/// below the SLO's minimum. Transactions whose tip couldn't be valued are kept, as are those of partners and, with a
+/// subsidy budget, those of users with some of it left, which are relayed even when they don't pay. Deferred
+/// transactions are evaluated again once latency is back within the SLO.
+async fn defer_low_value<'a>(
+    chains: &[RelayContext],
+    orchestrator_url: Option<&str>,
+    txs: Vec<&'a GaslessTransaction>,
+) -> Vec<&'a GaslessTransaction> {
+    let Some(slo) = exceeded_latency_slo() else {
+        return txs;
+    };
+    let partners = relay_limits().partners;
+    let mut kept = Vec::new();
+    for (profit, tx) in rank(chains, txs.into_iter()).await {
+        let Some(profit) = profit.filter(|profit| *profit < slo.min_profit) else {
+            kept.push(tx);
+            continue;
+        };
+        let exempt = (!partners.is_empty() || subsidy_enabled())
+            && find_chain(chains, tx.chain_id)
+                .and_then(|ctx| tx.client(ctx.contract_address).ok())
+                .is_some_and(|client| partners.contains(&client) || subsidy_left(client));
+        if exempt {
+            kept.push(tx);
+            continue;
+        }
+        info!(
+            tx:% = tx.id(),
+            decision = "deprioritized",
+            skip_reason:% = SkipReason::Deprioritized;
+            "Transaction {} is expected to earn {profit} wei, deferring it while latency is over the SLO",
+            tx.id()
+        );
+        record_skip(orchestrator_url, tx, SkipReason::Deprioritized);
+    }
+    kept
+}
+
+/// Pairs each transaction with its expected profit as described on `prioritize`, highest first, `None` if its tip
+/// couldn't be valued. Those rank as earning nothing.
+async fn rank<'a>(
+    chains: &[RelayContext],
+    txs: impl Iterator<Item = &'a GaslessTransaction>,
+) -> Vec<(Option<Uint256>, &'a GaslessTransaction)> {
+    let now = unix_time();
+    let mut gas_prices: HashMap<u64, Option<Uint256>> = HashMap::new();
+    let mut prices: HashMap<(u64, Address), Option<Price>> = HashMap::new();
+    let mut ranked = Vec::new();
+    for tx in txs {
+        let Some(ctx) = find_chain(chains, tx.chain_id) else {
+            ranked.push((None, tx));
+            continue;
+        };
+        let (Ok(tip), Ok(conds)) = (ctx.decode_tip(tx), tx.decode_conditions()) else {
+            ranked.push((Some(Uint256::default()), tx));
+            continue;
+        };
+        if tip.amount == 0 || !conds.is_live(now) {
+            ranked.push((Some(Uint256::default()), tx));
+            continue;
+        }
+        let gas_price = match gas_prices.get(&ctx.chain_id) {
+            Some(gas_price) => *gas_price,
+            None => {
+                let gas_price = gas_price(ctx.web3(), ctx.chain_id).await.ok();
+                gas_prices.insert(ctx.chain_id, gas_price);
+                gas_price
+            }
+        };
+        let price = match prices.get(&(ctx.chain_id, tip.token)) {
+            _ if ctx.is_gas_token(tip.token) => Some(Price::one()),
+            Some(price) => *price,
+            None => {
+                let price = match token_decimals(ctx.web3(), ctx.chain_id, tip.token).await {
+                    Ok(decimals) => {
+                        unit_price(ctx.price_source.as_ref(), ctx.chain_id, tip.token, decimals)
+                            .await
+                            .ok()
+                    }
+                    Err(_) => None,
+                };
+                prices.insert((ctx.chain_id, tip.token), price);
+                price
+            }
+        };
+        let profit = match (gas_price, price) {
+            // a value too large to count is left unpriced rather than ranked
+            (Some(gas_price), Some(price)) => price.value_of(tip.amount.into()).map(|value| {
+                let cost = gas_price * ESTIMATED_RELAY_GAS.into();
+                if value > cost {
+                    value - cost
+                } else {
+                    Uint256::default()
+                }
+            }),
+            _ => None,
+        };
+        ranked.push((profit, tx));
+    }
+    // stable, so equally ranked transactions keep the orchestrator's order
+    ranked.sort_by_key(|(profit, _)| std::cmp::Reverse(profit.unwrap_or_default()));
+    ranked
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Span covering the evaluation and submission of one transaction
+fn relay_span(ctx: &RelayContext, tx: &GaslessTransaction) -> Span {
+    info_span!("relay", tx = %tx.id(), chain = %ctx.name)
+}
+
+/// Updates the retry queue and RPC health based on the outcome of relaying `tx`, a relay that was broadcast is
+/// tracked by `process_confirmations` from here on
+fn handle_relay_result(
+    ctx: &RelayContext,
+    orchestrator: Option<&Orchestrator>,
+    tx: &GaslessTransaction,
+    result: Result<Option<Uint256>, RelayerError>,
+) {
+    match result {
+        Ok(Some(_)) => {
+            keep_relay_lease(tx);
+            clear_retry(tx);
+        }
+        Ok(None) => {
+            release_relay_lease(tx);
+            record_outcome(Outcome::Skipped);
+            release_subsidy(tx);
+            take_audit_inputs(tx);
+            clear_retry(tx);
+        }
+        Err(e) => {
+            release_relay_lease(tx);
+            handle_relay_failure(ctx, orchestrator, tx, None, &e);
+        }
+    }
+}
+
+/// Records a failed relay of `tx`, `tx_hash` being our relay transaction if it was broadcast
+fn handle_relay_failure(
+    ctx: &RelayContext,
+    orchestrator: Option<&Orchestrator>,
+    tx: &GaslessTransaction,
+    tx_hash: Option<Uint256>,
+    e: &RelayerError,
+) {
+    ctx.rpc.report_error(e);
+    take_tip_value(tx);
+    let inputs = take_audit_inputs(tx);
+    // a relay that was broadcast may have spent gas, it's charged the whole reservation
+    match tx_hash {
+        Some(_) => settle_subsidy(tx, None),
+        None => release_subsidy(tx),
+    }
+    record_failure(orchestrator, tx, e);
+    // estimate reverts don't count, commands someone else relayed first revert too. Of our own relays' reverts only
+    // those the user caused count, not those of the price moving or someone else filling first
+    let strike = match e {
+        RelayerError::Malformed(_) => true,
+        RelayerError::SimulationRevert(reason) => tx_hash.is_some() && is_user_fault(reason),
+        _ => false,
+    };
+    if strike
+        && bans_enabled()
+        && let Ok(user) = tx.client(ctx.contract_address)
+    {
+        record_strike(user, tx, &e.to_string());
+    }
+    // reverts of our own relays are counted as strikes, this budget is for simulations that never got that far
+    if let (RelayerError::SimulationRevert(_), None) = (e, tx_hash)
+        && simulation_budget_enabled()
+        && let Ok(user) = tx.client(ctx.contract_address)
+    {
+        record_failed_simulation(user);
+    }
+    // a relay that fails once broadcast wasn't passed over, unless someone else consumed its nonce first
+    let skip_reason = e
+        .skip_reason()
+        .filter(|reason| tx_hash.is_none() || *reason == SkipReason::NonceConsumed);
+    if let Some(reason) = skip_reason {
+        record_skip(
+            orchestrator.map(|orchestrator| orchestrator.url.as_str()),
+            tx,
+            reason,
+        );
+    }
+    let outcome = match (e, e.kind()) {
+        (_, FailureKind::Retry) => return,
+        (RelayerError::Unprofitable | RelayerError::Skipped(_), _) => "skipped",
+        (RelayerError::AlreadyRelayed(_), _) => "already_relayed",
+        _ => "failed",
+    };
+    // transactions passed over are evaluated again every poll, a skip is only kept when its reason changes
+    if outcome == "skipped" && skip_reason.is_some_and(|reason| !first_skip(tx, reason)) {
+        return;
+    }
+    // a skip's reason says all there is to say about it
+    let error = (!matches!(e, RelayerError::Skipped(_))).then(|| e.to_string());
+    record_decision(
+        ctx,
+        tx,
+        outcome,
+        skip_reason,
+        error.clone(),
+        tx_hash,
+        inputs,
+    );
+    record_relay(RelayRecord {
+        tx_hash: tx_hash.map(display_uint256_as_address),
+        error,
+        skip_reason: skip_reason.map(|reason| reason.to_string()),
+        ..history_record(ctx, tx, outcome)
+    });
+}
+
+/// Reason each transaction was last skipped for in the history, cleared when it grows too large
+static SKIPS_RECORDED: LazyLock<Mutex<HashMap<[u8; 32], SkipReason>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Most transactions whose last skip is remembered
+const MAX_SKIPS_RECORDED: usize = 100_000;
+
+/// True unless `tx` was last skipped for `reason` too
+fn first_skip(tx: &GaslessTransaction, reason: SkipReason) -> bool {
+    let mut recorded = SKIPS_RECORDED.lock().unwrap();
+    if recorded.len() >= MAX_SKIPS_RECORDED {
+        recorded.clear();
+    }
+    recorded.insert(tx.sig_hash(), reason) != Some(reason)
+}
+
+/// Writes an outcome of relaying `tx` to the relay history
+pub(crate) fn record_history(
+    ctx: &RelayContext,
+    tx: &GaslessTransaction,
+    outcome: &str,
+    tx_hash: Option<Uint256>,
+    gas_paid: Option<Uint256>,
+    tip_value: Option<Uint256>,
+    error: Option<String>,
+) {
+    record_relay(RelayRecord {
+        gas_paid: gas_paid.map(|gas| gas.to_string()),
+        tip_value: tip_value.map(|value| value.to_string()),
+        tx_hash: tx_hash.map(display_uint256_as_address),
+        error,
+        ..history_record(ctx, tx, outcome)
+    });
+}
+
+/// History record of `outcome` for `tx`, with what is known of the transaction itself filled in
+fn history_record(ctx: &RelayContext, tx: &GaslessTransaction, outcome: &str) -> RelayRecord {
+    let tip = ctx.decode_tip(tx).ok();
+    let user = tx.client(ctx.contract_address).ok();
+    RelayRecord {
+        timestamp: unix_time(),
+        chain_id: ctx.chain_id,
+        sig_hash: tx.id(),
+        user: user.map(|user| user.to_string()),
+        user_bech32: user.map(to_bech32),
+        tip_token: tip.as_ref().map(|tip| tip.token.to_string()),
+        tip_symbol: tip
+            .as_ref()
+            .and_then(|tip| token_symbol(ctx.chain_id, tip.token)),
+        tip_amount: tip.as_ref().map(|tip| tip.amount.to_string()),
+        gas_paid: None,
+        tip_value: None,
+        tx_hash: None,
+        outcome: outcome.to_string(),
+        error: None,
+        skip_reason: None,
+        trace: None,
+    }
+}
+
+/// Retries, blacklists or just records a failed relay of `tx` depending on how `e` is classified
+fn record_failure(orchestrator: Option<&Orchestrator>, tx: &GaslessTransaction, e: &RelayerError) {
+    match e.kind() {
+        FailureKind::Retry => {
+            debug!(
+                tx:% = tx.id(),
+                decision = "retry";
+                "Relay attempt failed with transient error: {e}"
+            );
+            record_outcome(Outcome::Retried);
+            schedule_retry(tx, orchestrator, e.to_string());
+        }
+        FailureKind::Blacklist => {
+            warn!(
+                tx:% = tx.id(),
+                decision = "blacklisted";
+                "Transaction {} can never be relayed, ignoring it from now on: {e}",
+                tx.id()
+            );
+            if let (RelayerError::Malformed(error), Some(orchestrator)) = (e, orchestrator) {
+                record_malformed(&orchestrator.url, tx.id(), error.to_string());
+            }
+            record_outcome(Outcome::Failed);
+            record_failed_relay(e);
+            alert_on_failure(e);
+            blacklist(tx);
+            record_invalid(tx);
+        }
+        FailureKind::Report => {
+            clear_retry(tx);
+            // losing a race or an unprofitable tip isn't a failure, the transaction just wasn't ours to relay
+            if matches!(
+                e,
+                RelayerError::Unprofitable
+                    | RelayerError::AlreadyRelayed(_)
+                    | RelayerError::Skipped(_)
+            ) {
+                record_outcome(Outcome::Skipped);
+                return;
+            }
+            debug!(
+                tx:% = tx.id(),
+                decision = "failed";
+                "Relay attempt failed with error: {e}"
+            );
+            record_outcome(Outcome::Failed);
+            record_failed_relay(e);
+            alert_on_failure(e);
+            if record_failure_cooldown(tx, e.to_string()) {
+                warn!(
+                    tx:% = tx.id(),
+                    decision = "blacklisted";
+                    "Transaction {} keeps failing with the same error, ignoring it from now on: {e}",
+                    tx.id()
+                );
+                blacklist(tx);
+            }
+        }
+    }
+}
+
+/// Re-attempts every transaction in the retry queue whose backoff has elapsed
+pub async fn process_retries(chains: &[RelayContext]) {
+    for entry in due_retries() {
+        debug!(
+            "Retrying transaction {} after {} failed attempts, last error: {}",
+            entry.tx.id(),
+            entry.attempts,
+            entry.last_error
+        );
+        process_transactions(chains, entry.orchestrator.as_ref(), &[entry.tx]).await;
+    }
+}
+
+/// Evaluates transactions deferred from earlier cycles by the cap on transactions per cycle, as far as this
+/// cycle's cap allows
+pub async fn process_deferred(chains: &[RelayContext]) {
+    let mut by_orchestrator: HashMap<
+        Option<String>,
+        (Option<Orchestrator>, Vec<GaslessTransaction>),
+    > = HashMap::new();
+    for deferred in overflow::deferred() {
+        let url = deferred.orchestrator.as_ref().map(|o| o.url.clone());
+        let (_, txs) = by_orchestrator
+            .entry(url)
+            .or_insert_with(|| (deferred.orchestrator, Vec::new()));
+        txs.push(deferred.tx);
+    }
+    for (orchestrator, txs) in by_orchestrator.into_values() {
+        debug!("Evaluating {} deferred transactions", txs.len());
+        process_transactions(chains, orchestrator.as_ref(), &txs).await;
+    }
+}
+
+/// Checks every relay that is not yet final against the chain. Pending relays are resolved as included, reverted,
+/// taken by a competitor or timed out. A relay is only considered successful once it is `confirmation_blocks`
+/// deep, at which point it is forgotten. Relays that have been reorged out before then are queued to be relayed
+/// again, which re-simulates them so commands whose user nonce has since been consumed are not re-submitted.
+pub async fn process_confirmations(chains: &[RelayContext]) {
+    for ctx in chains {
+        refresh_mempool(ctx).await;
+        check_observations(ctx).await;
+        let watched = watched_relays(ctx.chain_id);
+        if watched.is_empty() {
+            record_unconfirmed(ctx.chain_id, Vec::new());
+            continue;
+        }
+        let Ok(head) = ctx.web3().eth_block_number().await else {
+            continue;
+        };
+        let mut hashes: Vec<Uint256> = watched.iter().map(|w| w.tx_hash).collect();
+        hashes.sort();
+        hashes.dedup();
+        let receipts = poll_receipts(ctx, head, &hashes).await;
+        process_submissions(ctx, &receipts).await;
+        if watched_relays(ctx.chain_id).is_empty() {
+            record_unconfirmed(ctx.chain_id, Vec::new());
+            continue;
+        }
+        let confirmations = |number: Uint256| {
+            if head > number {
+                (head - number).to_u64().unwrap_or(u64::MAX)
+            } else {
+                0
+            }
+        };
+        let pending = watched_relays(ctx.chain_id);
+        for watched in pending.iter().cloned() {
+            if matches!(watched.state, RelayState::Pending { .. }) {
+                continue;
+            }
+            let Some(receipt) = receipts.get(&watched.tx_hash).cloned() else {
+                continue;
+            };
+            // a batch's fee is shared by every relay it carried
+            let batch_size = pending
+                .iter()
+                .filter(|w| w.tx_hash == watched.tx_hash)
+                .count();
+            let gas_paid = receipt.as_ref().map(|r| {
+                r.get_gas_used() * r.get_effective_gas_price() / (batch_size as u64).into()
+            });
+            // only relays sent on their own say how much gas their command uses
+            let gas_used = receipt
+                .as_ref()
+                .filter(|_| batch_size == 1)
+                .map(|r| r.get_gas_used());
+            let logs = receipt.as_ref().map(|r| r.get_logs());
+            let block = receipt.and_then(|r| Some((r.get_block_number()?, r.get_block_hash()?)));
+            if block.is_some() && watched.block().is_none() {
+                record_inclusion(&watched.tx);
+            }
+            match (block, watched.block()) {
+                (Some((number, _)), _) if confirmations(number) >= ctx.confirmation_blocks => {
+                    info!(
+                        tx:% = watched.tx.id(),
+                        decision = "final",
+                        tx_hash:% = display_uint256_as_address(watched.tx_hash);
+                        "Transaction {} relayed successfully, final after {} confirmations in block {number}",
+                        watched.tx.id(),
+                        ctx.confirmation_blocks
+                    );
+                    record_outcome(Outcome::Finalized);
+                    let mut tip_value = take_tip_value(&watched.tx);
+                    let mut tip_paid = true;
+                    if let Some(missing) = logs
+                        .as_deref()
+                        .and_then(|logs| missing_tip(ctx, &watched, &pending, logs))
+                    {
+                        warn!(
+                            tx:% = watched.tx.id(),
+                            decision = "tip_missing",
+                            tx_hash:% = display_uint256_as_address(watched.tx_hash);
+                            "Relay {} of {} was owed {} of token {} at {} but its receipt only transferred {}",
+                            display_uint256_as_address(watched.tx_hash),
+                            watched.tx.id(),
+                            missing.expected,
+                            display_token(ctx.chain_id, missing.token),
+                            missing.recipient,
+                            missing.received
+                        );
+                        alert_on_missing_tip(&ctx.name, &watched.tx, watched.tx_hash, &missing);
+                        // the tip was valued as if paid in full, which it wasn't
+                        tip_value = Some(Uint256::default());
+                        tip_paid = false;
+                    }
+                    // the tip made up part of what the relay cost, the subsidy only the rest
+                    let subsidy = gas_paid.map(|gas_paid| match tip_value {
+                        Some(tip_value) if tip_value < gas_paid => gas_paid - tip_value,
+                        Some(_) => Uint256::default(),
+                        None => gas_paid,
+                    });
+                    settle_subsidy(&watched.tx, subsidy);
+                    if let Some(gas_paid) = gas_paid {
+                        record_gas_spent(gas_paid);
+                        record_spend(gas_paid);
+                        if let Some(tip_value) = tip_value {
+                            if gas_paid > tip_value {
+                                warn!(
+                                    tx:% = watched.tx.id(),
+                                    decision = "losing_relay",
+                                    tx_hash:% = display_uint256_as_address(watched.tx_hash);
+                                    "LOSING RELAY: {} of {} paid {gas_paid} wei in gas for a tip worth {tip_value} wei, losing {} wei",
+                                    display_uint256_as_address(watched.tx_hash),
+                                    watched.tx.id(),
+                                    gas_paid - tip_value
+                                );
+                                record_losing_relay();
+                            }
+                            alert_on_loss(
+                                &ctx.name,
+                                &watched.tx,
+                                watched.tx_hash,
+                                gas_paid,
+                                tip_value,
+                            );
+                        }
+                    }
+                    let tip = ctx.decode_tip(&watched.tx).ok();
+                    if let Some(tip) = &tip
+                        && tip.token != Address::default()
+                    {
+                        track_token(ctx.chain_id, tip.token);
+                    }
+                    if let Some(tip) = &tip
+                        && tip_paid
+                    {
+                        accrue_tip_split(ctx, &watched, tip);
+                    }
+                    record_final_relay(tip, tip_value);
+                    if let Some(gas_used) = gas_used {
+                        record_gas_used(
+                            ctx.chain_id,
+                            &decode_command(watched.tx.callpath, &watched.tx.cmd),
+                            gas_used,
+                        );
+                    }
+                    record_history(
+                        ctx,
+                        &watched.tx,
+                        "final",
+                        Some(watched.tx_hash),
+                        gas_paid,
+                        tip_value,
+                        None,
+                    );
+                    unwatch_relay(&watched.tx);
+                }
+                (Some(block), previous) => {
+                    match previous {
+                        Some((previous_number, previous_hash)) if previous_hash != block.1 => {
+                            warn!(
+                                "Relay of {} was reorged out of block {previous_number} and re-included in block {}",
+                                watched.tx.id(),
+                                block.0
+                            )
+                        }
+                        Some(_) => {}
+                        None => info!(
+                            "Transaction {} included in block {} but not final, {} of {} confirmations",
+                            watched.tx.id(),
+                            block.0,
+                            confirmations(block.0),
+                            ctx.confirmation_blocks
+                        ),
+                    }
+                    update_relay_block(&watched.tx, Some(block));
+                }
+                // picked up again once relaying resumes
+                (None, _) if is_paused() => {}
+                // a missing receipt may just be a lagging node, the relay is only given up on once its block is gone
+                (None, _) if !reorged_out(ctx, &watched).await => {
+                    debug!(
+                        "No receipt for relay {} of {} on {}, still watching it",
+                        display_uint256_as_address(watched.tx_hash),
+                        watched.tx.id(),
+                        ctx.name
+                    );
+                }
+                (None, _) => {
+                    record_outcome(Outcome::Reorged);
+                    record_history(
+                        ctx,
+                        &watched.tx,
+                        "reorged",
+                        Some(watched.tx_hash),
+                        None,
+                        None,
+                        None,
+                    );
+                    warn!(
+                        tx:% = watched.tx.id(),
+                        decision = "reorged",
+                        tx_hash:% = display_uint256_as_address(watched.tx_hash);
+                        "Relay {} of transaction {} was reorged out on {}, relaying again",
+                        display_uint256_as_address(watched.tx_hash),
+                        watched.tx.id(),
+                        ctx.name
+                    );
+                    unwatch_relay(&watched.tx);
+                    requeue(&watched.tx, watched.orchestrator.as_ref());
+                }
+            }
+        }
+        record_unconfirmed(
+            ctx.chain_id,
+            watched_relays(ctx.chain_id)
+                .into_iter()
+                .map(|watched| UnconfirmedTransaction {
+                    tx_hash: display_uint256_as_address(watched.tx_hash),
+                    id: watched.tx.id(),
+                    block: watched.block().map(|(number, _)| number),
+                    confirmations: watched
+                        .block()
+                        .map(|(number, _)| confirmations(number))
+                        .unwrap_or_default(),
+                })
+                .collect(),
+        );
+    }
+}
+
+/// True once the block `watched` was included in has been replaced and its nonce is free to be used again. Until
+/// then a relay without a receipt may still be included, and relaying it again would pay for it twice.
+async fn reorged_out(ctx: &RelayContext, watched: &WatchedRelay) -> bool {
+    let web3 = ctx.web3();
+    if let Some((number, hash)) = watched.block() {
+        match web3.eth_get_concise_block_by_number(number).await {
+            Ok(block) if block.hash != hash => {}
+            _ => return false,
+        }
+    }
+    match web3
+        .eth_get_transaction_count(watched.signer.address())
+        .await
+    {
+        Ok(nonce) => nonce <= watched.call.get_nonce(),
+        Err(_) => false,
+    }
+}
+
+/// Tracks submitted relays in the background, so that relaying never waits on receipts, until `is_done` and no
+/// relay is waiting to be included any more
+pub async fn track_relays(chains: &[RelayContext], is_done: impl Fn() -> bool) {
+    loop {
+        process_confirmations(chains).await;
+        if is_done() && !has_pending_relays() {
+            return;
+        }
+        actix_rt::time::sleep(CONFIRMATION_CHECK_INTERVAL).await;
+    }
+}
+
+/// What became of a relay transaction that was pending
+enum Submission {
+    Pending,
+    Included,
+    /// reverted on chain
+    Failed(RelayerError),
+    /// timed out or overtaken by a competitor and cancelled, it is tracked until its nonce is used
+    Abandoned(RelayerError),
+    /// an abandoned relay whose nonce was used by another transaction
+    Dropped,
+}
+
+/// Resolves the pending relays on `ctx`, each broadcast relay transaction, which may carry a batch of gasless
+/// transactions, is included, reverts, is overtaken by a competitor or times out
+async fn process_submissions(
+    ctx: &RelayContext,
+    receipts: &HashMap<Uint256, Option<TransactionReceipt>>,
+) {
+    let pending: Vec<WatchedRelay> = watched_relays(ctx.chain_id)
+        .into_iter()
+        .filter(|w| matches!(w.state, RelayState::Pending { .. }))
+        .collect();
+    let mut hashes: Vec<Uint256> = pending.iter().map(|w| w.tx_hash).collect();
+    hashes.sort();
+    hashes.dedup();
+    for tx_hash in hashes {
+        let relays: Vec<&WatchedRelay> = pending.iter().filter(|w| w.tx_hash == tx_hash).collect();
+        let relay = relays[0];
+        let RelayState::Pending {
+            submitted,
+            abandoned,
+        } = relay.state
+        else {
+            continue;
+        };
+        let hash = display_uint256_as_address(tx_hash);
+        // a receipt that couldn't be fetched is looked up again next cycle, meanwhile the relay is checked as if it
+        // had none yet so that timeouts, cancels and abandoned nonces are still handled through an RPC outage
+        let receipt = receipts.get(&tx_hash).cloned().flatten();
+        let mut submission = check_submission(ctx, relay, receipt, submitted, abandoned).await;
+        // a batch is only cancelled once every command in it is being relayed by someone else
+        if let (Submission::Pending, false) = (&submission, abandoned)
+            && let Some(competitor) = relays
+                .iter()
+                .map(|relay| competing_relay(ctx.chain_id, &relay.tx))
+                .collect::<Option<Vec<String>>>()
+                .and_then(|competitors| competitors.into_iter().next())
+        {
+            warn!(
+                decision = "competing_relay",
+                tx_hash:% = hash;
+                "Relay {hash} is competing with {competitor} in the mempool, cancelling it"
+            );
+            cancel_transaction(ctx, &relay.signer, &relay.call).await;
+            submission = Submission::Abandoned(RelayerError::AlreadyRelayed(format!(
+                "competing relay {competitor} seen in the mempool"
+            )));
+        }
+        if !matches!(submission, Submission::Pending) {
+            REVERTS.lock().unwrap().remove(&tx_hash);
+        }
+        match submission {
+            Submission::Pending => {}
+            Submission::Included => {
+                remove_in_flight(&hash);
+                record_included_relay();
+                reset_failure_alert();
+                for relay in relays {
+                    info!(
+                        tx:% = relay.tx.id(),
+                        decision = "included",
+                        tx_hash:% = hash;
+                        "Transaction {} included, waiting for {} confirmations",
+                        relay.tx.id(),
+                        ctx.confirmation_blocks
+                    );
+                    record_outcome(Outcome::Included);
+                    record_history(ctx, &relay.tx, "included", Some(tx_hash), None, None, None);
+                    clear_failures(&relay.tx);
+                    if bans_enabled()
+                        && let Ok(user) = relay.tx.client(ctx.contract_address)
+                    {
+                        clear_strikes(user);
+                    }
+                    update_relay_block(&relay.tx, None);
+                }
+            }
+            Submission::Failed(e) => {
+                remove_in_flight(&hash);
+                for relay in relays {
+                    unwatch_relay(&relay.tx);
+                    handle_relay_failure(
+                        ctx,
+                        relay.orchestrator.as_ref(),
+                        &relay.tx,
+                        Some(tx_hash),
+                        &e,
+                    );
+                }
+                // it simulated fine before broadcasting, the trace is kept to find out what changed. Tracing can
+                // take a while on a busy node, so it is left to run on its own
+                let web3 = ctx.web3().clone();
+                actix_rt::spawn(async move {
+                    match trace_transaction(&web3, tx_hash).await {
+                        Ok(trace) => attach_trace(&hash, &trace),
+                        Err(e) => debug!("Failed to trace reverted relay {hash}: {e}"),
+                    }
+                });
+            }
+            Submission::Abandoned(e) => {
+                for relay in relays {
+                    abandon_relay(&relay.tx);
+                    handle_relay_failure(
+                        ctx,
+                        relay.orchestrator.as_ref(),
+                        &relay.tx,
+                        Some(tx_hash),
+                        &e,
+                    );
+                }
+            }
+            Submission::Dropped => {
+                info!(
+                    "Nonce {} of abandoned relay {hash} was used by another transaction, no longer tracking it",
+                    relay.call.get_nonce()
+                );
+                remove_in_flight(&hash);
+                for relay in relays {
+                    unwatch_relay(&relay.tx);
+                }
+            }
+        }
+    }
+}
+
+/// Checks on a relay broadcast at `submitted`, given its latest `receipt`. A relay that keeps reverting against the pending block, or
+/// that reverts past its deadline, is being overtaken by a competitor or can no longer succeed and is cancelled. An `abandoned` relay is only checked for landing late or for its nonce being used.
+async fn check_submission(
+    ctx: &RelayContext,
+    relay: &WatchedRelay,
+    receipt: Option<TransactionReceipt>,
+    submitted: Instant,
+    abandoned: bool,
+) -> Submission {
+    let (tx_hash, signer, call) = (relay.tx_hash, &relay.signer, &relay.call);
+    let web3 = ctx.web3();
+    let hash = display_uint256_as_address(tx_hash);
+    let request = TransactionRequest::from_transaction(call, signer.address());
+    if let Some(receipt) = receipt
+        && let Some(block) = receipt.get_block_number()
+    {
+        debug!("Receipt is {receipt:?}");
+        if receipt.get_success() {
+            if !abandoned {
+                record_inclusion_latency(ctx.chain_id, submitted.elapsed());
+            }
+            return Submission::Included;
+        }
+        if abandoned {
+            return Submission::Dropped;
+        }
+        // replaying the call is the only way to learn why it reverted
+        let reason = match web3.eth_call_at_height(request, block).await {
+            Err(e) => match RelayerError::from(e) {
+                RelayerError::SimulationRevert(reason) => Some(reason),
+                _ => None,
+            },
+            Ok(_) => None,
+        }
+        .unwrap_or_else(|| "unknown reason".to_string());
+        error!(
+            tx:% = relay.tx.id(),
+            tx_hash:% = hash,
+            rpc:% = redact_url(&web3.get_url()),
+            decision = "reverted",
+            revert_reason:% = reason;
+            "Transaction {hash} reverted on {}: {reason}",
+            ctx.name
+        );
+        record_wasted_relay();
+        let gas_paid = receipt.get_gas_used() * receipt.get_effective_gas_price();
+        record_gas_spent(gas_paid);
+        record_spend(gas_paid);
+        alert_on_revert(&ctx.name, tx_hash, gas_paid, &reason);
+        return Submission::Failed(RelayerError::SimulationRevert(format!(
+            "{reason} (on chain)"
+        )));
+    }
+    if abandoned {
+        return match web3.eth_get_transaction_count(signer.address()).await {
+            Ok(nonce) if nonce > call.get_nonce() => Submission::Dropped,
+            _ => Submission::Pending,
+        };
+    }
+    if submitted.elapsed() >= ctx.receipt_timeout {
+        error!(
+            tx:% = relay.tx.id(), tx_hash:% = hash, rpc:% = redact_url(&web3.get_url());
+            "Timed out waiting for confirmation of {hash}"
+        );
+        // the transaction was already broadcast so this must never be retried, doing so would pay for a
+        // second submission of a command that may still land
+        return Submission::Abandoned(RelayerError::Unconfirmed(format!(
+            "Timed out waiting for confirmation of {hash}"
+        )));
+    }
+    // our relay would only start failing while pending if a competitor relayed the same command first, or if the
+    // market moved against it, which may well move back before it is included
+    if let Err(e) = web3.eth_call(request.clone()).await
+        && let RelayerError::SimulationRevert(reason) = RelayerError::from(e)
+    {
+        // our own relay landing since the receipt was checked consumes the nonce too, only cancel if it hasn't
+        if let Ok(Some(_)) = web3.eth_get_transaction_receipt(tx_hash).await {
+            return Submission::Pending;
+        }
+        let expired = relay
+            .tx
+            .decode_conditions()
+            .is_ok_and(|conds| !conds.is_live(unix_time()));
+        if !expired && !confirmed_revert(ctx, tx_hash, request).await {
+            return Submission::Pending;
+        }
+        REVERTS.lock().unwrap().remove(&tx_hash);
+        warn!(
+            decision = "already_relayed",
+            tx_hash:% = hash;
+            "Relay {hash} was overtaken by someone else or can no longer succeed ({reason}), cancelling it"
+        );
+        cancel_transaction(ctx, signer, call).await;
+        record_wasted_relay();
+        return Submission::Abandoned(RelayerError::AlreadyRelayed(reason));
+    }
+    REVERTS.lock().unwrap().remove(&tx_hash);
+    Submission::Pending
+}
+
+/// Replays a pending relay that reverted at the latest block against the pending block, true once it has reverted
+/// there at `REVERTS_BEFORE_CANCEL` different blocks in a row. A call that succeeds starts the count over.
+async fn confirmed_revert(
+    ctx: &RelayContext,
+    tx_hash: Uint256,
+    request: TransactionRequest,
+) -> bool {
+    let web3 = ctx.web3();
+    let client = HttpClient::new(&web3.get_url());
+    let call =
+        client.request_method::<_, Data>("eth_call", (request, "pending"), web3.get_timeout());
+    let (call, block) = join(call, web3.eth_block_number()).await;
+    let reverted =
+        call.is_err_and(|e| matches!(RelayerError::from(e), RelayerError::SimulationRevert(_)));
+    let mut reverts = REVERTS.lock().unwrap();
+    if !reverted {
+        reverts.remove(&tx_hash);
+        return false;
+    }
+    // the block can't be read, this revert is left uncounted rather than risk counting one block twice
+    let Ok(block) = block else {
+        return false;
+    };
+    let (count, last) = reverts.entry(tx_hash).or_insert((0, Uint256::default()));
+    if *count == 0 || *last != block {
+        *count += 1;
+        *last = block;
+    }
+    *count >= REVERTS_BEFORE_CANCEL
+}
+
+/// Waits until any chain produces a new block, checking heads every `interval`, so that each relay cycle starts
+/// as soon as there is new state to act on. Returns after `max_wait` regardless, or as soon as shutdown is requested.
+pub async fn wait_for_new_block(chains: &[RelayContext], interval: Duration, max_wait: Duration) {
+    if wait_for_tendermint_block(max_wait).await {
+        return;
+    }
+    let heads = chain_heads(chains).await;
+    let deadline = Instant::now() + max_wait;
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        if sleep_or_shutdown(interval.min(remaining)).await {
+            return;
+        }
+        let new_heads = chain_heads(chains).await;
+        if new_heads
+            .iter()
+            .zip(&heads)
+            .any(|(new, old)| new.is_some() && new != old)
+        {
+            trace!("New block, starting relay cycle");
+            return;
+        }
+    }
+}
+
+/// Latest block number of every chain, `None` where it could not be fetched
+async fn chain_heads(chains: &[RelayContext]) -> Vec<Option<Uint256>> {
+    let mut heads = Vec::new();
+    for ctx in chains {
+        heads.push(ctx.web3().eth_synced_block_number().await.ok());
+    }
+    heads
+}
+
+/// Values a tip in the gas token, according to the decimals of its token so that tokens with fewer decimals than
+/// the gas token aren't undervalued, along with the cost of selling it for the gas token afterwards.
+/// A price lookup failure is returned as a `PriceError` so the transaction can be retried.
+pub(crate) async fn value_tip(
+    ctx: &RelayContext,
+    tip: Uint256,
+    tip_token: Address,
+    gas_price: Uint256,
+) -> Result<TipValuation, RelayerError> {
+    let value = if ctx.is_gas_token(tip_token) {
+        debug!("Tip is paid in the gas token, skipping the price lookup");
+        tip
+    } else if let Some(quoter) = &ctx.swap_quoter {
+        match quoter.quote(ctx.web3(), tip_token, tip).await {
+            Ok(value) => value,
+            Err(e) => {
+                error!(rpc:% = redact_url(&ctx.web3().get_url()); "Failed to quote tip value in gas token: {e}");
+                return Err(e);
+            }
+        }
+    } else {
+        let decimals = token_decimals(ctx.web3(), ctx.chain_id, tip_token).await?;
+        match ctx
+            .price_source
+            .value_in_gas_token(TokenAmount {
+                chain_id: ctx.chain_id,
+                token: tip_token,
+                decimals,
+                amount: tip,
+            })
+            .await
+        {
+            Ok(value) => value,
+            Err(e) => {
+                error!("Failed to fetch tip value in gas token: {e}");
+                return Err(e);
+            }
+        }
+    };
+    let limits = relay_limits();
+    // selling the tip costs a transaction of its own plus pool fees, which swap quotes already include
+    let liquidation_cost = if ctx.is_gas_token(tip_token) {
+        Uint256::default()
+    } else {
+        let fees = match ctx.swap_quoter {
+            Some(_) => Uint256::default(),
+            None => value * limits.liquidation_fee_bps.into() / 10_000u16.into(),
+        };
+        gas_price * limits.liquidation_gas.into() + fees
+    };
+    if liquidation_cost > Uint256::default() {
+        debug!("Liquidating the tip is estimated to cost {liquidation_cost}");
+    }
+    Ok(TipValuation {
+        token: tip_token,
+        amount: tip,
+        value,
+        liquidation_cost,
+    })
+}
+
+/// Picks the account to relay with based on who the tip will pay, transactions paying the sender (0x100),
+/// origin (0x200) or one of the operator's `tip_receivers` can be relayed by any of our accounts, `preferred`
+/// if provided, transactions locked to one of our accounts must be sent from it and anything else will not
+/// pay us at all
+pub(crate) fn select_signer<'a>(
+    ctx: &'a RelayContext,
+    receiver: Address,
+    preferred: Option<&'a Signer>,
+) -> Option<&'a Signer> {
+    if ctx.layout.pays_any_sender(receiver) || ctx.tip_receivers.contains(&receiver) {
+        Some(preferred.unwrap_or_else(|| ctx.signers.next()))
+    } else {
+        ctx.signers.find(receiver)
+    }
+}
+
+/// A relay that has passed every check and is ready to broadcast
+pub struct PreparedRelay<'a> {
+    pub ctx: &'a RelayContext,
+    pub tx: &'a GaslessTransaction,
+    pub signer: &'a Signer,
+    /// signed `userCmdRelayer` transaction for this relay alone, left unsigned when quoting
+    pub call: Transaction,
+    pub gas_used: Uint256,
+    pub gas_price: Uint256,
+    /// gas price the tip had to cover and by how much
+    pub fees: FeeData,
+    pub tip: TipValuation,
+    /// false only for a quote of a transaction that would be skipped as unprofitable
+    pub profitable: bool,
+    pub tip_token: Address,
+    pub tip_amount: Uint256,
+    /// the tip does not pay `msg.sender`, which would be the multicall contract inside a batch
+    pub batchable: bool,
+    /// evaluated while standing by for the elected leader, so it wasn't claimed and is never submitted
+    pub standby: bool,
+}
+
+async fn relay_transaction(
+    ctx: &RelayContext,
+    orchestrator: Option<&Orchestrator>,
+    tx: &GaslessTransaction,
+    preferred: Option<&Signer>,
+) -> Result<Option<Uint256>, RelayerError> {
+    let relay = prepare_relay(ctx, Evaluation::Relay(orchestrator), tx, preferred).await?;
+    submit_relay(ctx, orchestrator, relay).await
+}
+
+/// Evaluates a single transaction end to end, exactly as it would be when relaying, without claiming or
+/// sending it. Returns the relay that would be submitted, or `RelayerError::Skipped` if it would be skipped.
+pub async fn evaluate_transaction<'a>(
+    ctx: &'a RelayContext,
+    tx: &'a GaslessTransaction,
+) -> Result<PreparedRelay<'a>, RelayerError> {
+    prepare_relay(ctx, Evaluation::Relay(None), tx, None).await
+}
+
+/// What `prepare_relay` evaluates a transaction for. Relaying commits to it: it is leased and claimed, its user's
+/// rate limit is charged and its subsidy reserved. A quote runs the same checks without any of those side effects.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Evaluation<'a> {
+    /// relayed from `orchestrator`'s queue, or from nowhere in particular
+    Relay(Option<&'a Orchestrator>),
+    /// quoted as if submitted to the orchestrator at this url, for its `[[override]]` limits
+    Quote(Option<&'a str>),
+}
+
+impl Evaluation<'_> {
+    fn orchestrator_url(&self) -> Option<&str> {
+        match self {
+            Evaluation::Relay(orchestrator) => orchestrator.map(|o| o.url.as_str()),
+            Evaluation::Quote(url) => *url,
+        }
+    }
+
+    fn is_quote(&self) -> bool {
+        matches!(self, Evaluation::Quote(_))
+    }
+}
+
+/// Runs every check on a transaction up to the point of broadcasting it, failing with `RelayerError::Skipped` if
+/// it should not be relayed. When quoting, an unprofitable transaction is returned rather than skipped, so that the
+/// tip it would need can be worked out.
+pub(crate) async fn prepare_relay<'a>(
+    ctx: &'a RelayContext,
+    evaluation: Evaluation<'_>,
+    tx: &'a GaslessTransaction,
+    preferred: Option<&'a Signer>,
+) -> Result<PreparedRelay<'a>, RelayerError> {
+    let web3 = ctx.web3();
+    let quoting = evaluation.is_quote();
+    let audit = |inputs| {
+        if !quoting {
+            record_audit_inputs(tx, inputs);
+        }
+    };
+    trace!("!!!!! STARTING TRANSACTION RELAY LOGGING !!!!!");
+
+    // Check if transaction data is valid before attempting to parse
+    if tx.cmd.is_empty() {
+        error!(tx:% = tx.id(); "Transaction command data is empty, skipping transaction");
+        return Err(RelayerError::invalid_tx("Empty transaction command data"));
+    }
+
+    if chain_congested(ctx.chain_id) {
+        info!(
+            tx:% = tx.id(),
+            decision = "congested",
+            skip_reason:% = SkipReason::Congested;
+            "Transaction {} is left until blocks of {} are less congested",
+            tx.id(),
+            ctx.name
+        );
+        return Err(RelayerError::Skipped(SkipReason::Congested));
+    }
+
+    let relay_function = match ctx.relay_target(tx) {
+        Ok((_, relay_function)) => relay_function,
+        Err(e) => {
+            warn!(
+                tx:% = tx.id(),
+                decision = "unknown_target",
+                skip_reason:% = SkipReason::DecodeError;
+                "Transaction {} refused: {e}",
+                tx.id()
+            );
+            return Err(e);
+        }
+    };
+
+    let decode_span = info_span!("decode").entered();
+    let command = decode_command(tx.callpath, &tx.cmd);
+    trace!("Command {command:?}");
+    let limits = relay_limits();
+    if !limits.allowed_commands.allows(&command) {
+        info!(
+            tx:% = tx.id(),
+            decision = "command_not_allowed",
+            skip_reason:% = SkipReason::CommandNotAllowed;
+            "Transaction {} is a {} command (callpath {}), which is not allowed, skipping",
+            tx.id(),
+            command.kind,
+            command.callpath
+        );
+        return Err(RelayerError::Skipped(SkipReason::CommandNotAllowed));
+    }
+
+    let conds = tx.decode_conditions()?;
+    if !conds.is_live(unix_time()) {
+        info!(
+            tx:% = tx.id(),
+            decision = "outside_validity_window",
+            skip_reason:% = SkipReason::Expired;
+            "Transaction {} is expired or not yet valid, skipping",
+            tx.id()
+        );
+        return Err(RelayerError::Skipped(SkipReason::Expired));
+    }
+    let urgency = urgency(ctx.chain_id, conds.deadline, unix_time());
+    if urgency == Urgency::Unreachable {
+        info!(
+            tx:% = tx.id(),
+            decision = "deadline_unreachable",
+            skip_reason:% = SkipReason::DeadlineUnreachable;
+            "Transaction {} expires at {}, sooner than relays on {} are currently included, skipping",
+            tx.id(),
+            conds.deadline,
+            ctx.name
+        );
+        return Err(RelayerError::Skipped(SkipReason::DeadlineUnreachable));
+    }
+
+    // the user is only recovered when a per user check needs it
+    let client = if bans_enabled()
+        || simulation_budget_enabled()
+        || rate_limit_enabled()
+        || funds_check_enabled()
+        || !limits.partners.is_empty()
+    {
+        Some(tx.client(ctx.contract_address)?)
+    } else {
+        None
+    };
+    let partner = client.filter(|client| limits.partners.contains(client));
+
+    if let Some(client) = client
+        && is_banned(client)
+    {
+        info!(
+            tx:% = tx.id(),
+            decision = "banned_user",
+            skip_reason:% = SkipReason::BannedUser;
+            "Transaction {} is from banned user {}, skipping",
+            tx.id(),
+            display_user(client)
+        );
+        return Err(RelayerError::Skipped(SkipReason::BannedUser));
+    }
+
+    if let Some(client) = client
+        && !within_simulation_budget(client)
+    {
+        info!(
+            tx:% = tx.id(),
+            decision = "simulation_budget_exceeded",
+            skip_reason:% = SkipReason::SimulationBudgetExceeded;
+            "Transactions of {} failed simulation too often, skipping {} without simulating it",
+            display_user(client),
+            tx.id()
+        );
+        return Err(RelayerError::Skipped(SkipReason::SimulationBudgetExceeded));
+    }
+
+    if let Some(client) = client
+        && rate_limit_enabled()
+        && !quoting
+        && !within_rate_limit(client, tx.sig_hash())
+    {
+        info!(
+            tx:% = tx.id(),
+            decision = "rate_limited",
+            skip_reason:% = SkipReason::RateLimited;
+            "Transaction {} from {} exceeds the per user rate limit, skipping",
+            tx.id(),
+            display_user(client)
+        );
+        return Err(RelayerError::Skipped(SkipReason::RateLimited));
+    }
+
+    // Decode tip data using proper ABI decoding
+    let (tip_token, tip_amount, signer, batchable) = if !tx.tip.is_empty() {
+        let RelayerTip {
+            token,
+            amount,
+            receiver,
+        } = ctx.decode_tip(tx)?;
+        trace!("Decoded Tip:");
+        trace!("  Token: 0x{token:?}");
+        trace!("  Amount: {amount}");
+        trace!("  Receiver: {receiver:?}");
+
+        if let Some(signer) = select_signer(ctx, receiver, preferred) {
+            let batchable = receiver != ctx.layout.sender_receiver;
+            (token, Uint256::from(amount), signer, batchable)
+        } else {
+            info!(
+                tx:% = tx.id(),
+                decision = "invalid_receiver",
+                skip_reason:% = SkipReason::InvalidReceiver;
+                "Transaction with invalid receiver address {receiver}, skipping"
+            );
+            return Err(RelayerError::Skipped(SkipReason::InvalidReceiver));
+        }
+    } else {
+        info!(
+            tx:% = tx.id(),
+            decision = "no_tip",
+            skip_reason:% = SkipReason::NoTip;
+            "Transaction with no tip data, skipping"
+        );
+        return Err(RelayerError::Skipped(SkipReason::NoTip));
+    };
+
+    drop(decode_span);
+    resolve_token(web3, ctx.chain_id, tip_token).await;
+
+    // `[[override]]` sections may restrict tip tokens and replace the margin and gas limit for this orchestrator
+    // or token
+    let orchestrator_url = evaluation.orchestrator_url();
+    if !limits.allows_tip_token(orchestrator_url, tip_token) {
+        info!(
+            tx:% = tx.id(),
+            decision = "token_not_allowed",
+            skip_reason:% = SkipReason::TokenNotAllowed,
+            tip_token:% = display_token(ctx.chain_id, tip_token);
+            "Transaction {} tips in {}, which is not allowed, skipping",
+            tx.id(),
+            display_token(ctx.chain_id, tip_token)
+        );
+        return Err(RelayerError::Skipped(SkipReason::TokenNotAllowed));
+    }
+    let limits = limits.for_tip(orchestrator_url, tip_token);
+
+    // prefetches are left for the relay to use
+    let prefetched = if quoting {
+        None
+    } else {
+        PREFETCHED.lock().unwrap().remove(&tx.sig_hash())
+    };
+    // a transaction is only prefetched once its user's funds passed the check
+    if prefetched.is_none()
+        && let Some(client) = client.filter(|_| funds_check_enabled())
+    {
+        match check_funds(ctx, tx, client).await {
+            Ok(Some(shortfall)) => {
+                info!(
+                    tx:% = tx.id(),
+                    decision = "insufficient_funds",
+                    skip_reason:% = SkipReason::InsufficientFunds,
+                    tip_token:% = display_token(ctx.chain_id, tip_token),
+                    tip_value:% = tip_amount;
+                    "Transaction {} can't be paid for, {shortfall}, skipping without simulating it",
+                    tx.id()
+                );
+                return Err(RelayerError::Skipped(SkipReason::InsufficientFunds));
+            }
+            Ok(None) => {}
+            Err(e) => debug!(
+                "Failed to check the funds of {} for {}: {e}",
+                client,
+                tx.id()
+            ),
+        }
+    }
+    if let Some(Prefetched {
+        gas_used: Err(reason),
+        ..
+    }) = &prefetched
+    {
+        return Err(estimate_reverted(tx, tip_token, tip_amount, reason.clone()));
+    }
+
+    // commands relayed often enough have a known gas use, transactions whose tip can't cover it are ruled out
+    // without paying for an estimate. Quotes estimate anyway, to tell what tip would be enough
+    let mut valued_tip = None;
+    if prefetched.is_none()
+        && !quoting
+        && cached_estimate(&tx.sig_hash()).is_none()
+        && let Some(min_gas) = expected_min_gas(ctx.chain_id, &command)
+    {
+        let gas_price = gas_price(web3, ctx.chain_id).await?;
+        let tip = value_tip(ctx, tip_amount, tip_token, gas_price)
+            .instrument(info_span!("price"))
+            .await?;
+        let required_gas_price = pessimistic_gas_price(web3, ctx.chain_id, gas_price).await;
+        if partner.is_none()
+            && !subsidy_enabled()
+            && predicted_unprofitable(
+                ctx,
+                tx,
+                min_gas,
+                tip,
+                required_gas_price,
+                limits.profit_margin_percent,
+            )
+        {
+            info!(
+                tx:% = tx.id(),
+                decision = "predicted_unprofitable",
+                skip_reason:% = SkipReason::Unprofitable,
+                tip_token:% = display_token(ctx.chain_id, tip_token),
+                tip_value:% = tip_amount,
+                gas_estimate:% = min_gas;
+                "Transaction {} can't cover the {min_gas} gas its command uses at the least, skipping without estimating it",
+                tx.id()
+            );
+            audit(AuditInputs::new(
+                min_gas,
+                gas_price,
+                required_gas_price,
+                &tip,
+                "predicted_unprofitable",
+            ));
+            return Err(RelayerError::Unprofitable);
+        }
+        valued_tip = Some(tip);
+    }
+
+    // redundant instances only relay from the accounts they hold the lease on. A standby of an elected leader
+    // evaluates transactions in full, but never claims or submits them
+    let standby = !is_leader();
+    if !ctx.dry_run && !standby && !quoting && !holds_account_lease(ctx.chain_id, signer.address())
+    {
+        info!(
+            tx:% = tx.id(),
+            decision = "standby",
+            skip_reason:% = SkipReason::Standby;
+            "Transaction {} would be relayed from {}, which another instance holds the lease on, standing by",
+            tx.id(),
+            signer.address()
+        );
+        return Err(RelayerError::Skipped(SkipReason::Standby));
+    }
+
+    // the calldata is encoded once, and the relay's gas only estimated if the prefetch didn't already
+    let data = user_cmd_relayer_data(relay_function, tx)?;
+    let estimate = prefetched
+        .as_ref()
+        .and_then(|p| p.gas_used.as_ref().ok().copied())
+        .or_else(|| cached_estimate(&tx.sig_hash()));
+    let (call, urgency_premium, gas_used) =
+        match user_cmd_relayer_tx(ctx, signer, tx, data, estimate, urgency).await {
+            // quotes are never sent, so they aren't signed either
+            Ok((unsigned, premium, gas_used)) if quoting => Ok((unsigned, premium, gas_used)),
+            Ok((unsigned, premium, gas_used)) => signer
+                .sign_transaction(unsigned)
+                .await
+                .map(|call| (call, premium, gas_used)),
+            Err(e) => Err(e),
+        }
+        .map_err(|e| match e {
+            RelayerError::SimulationRevert(reason) => {
+                estimate_reverted(tx, tip_token, tip_amount, reason)
+            }
+            e => {
+                debug!("Failed to prepare transaction: {e:?}");
+                e
+            }
+        })?;
+    trace!("Tx from: {}", signer.address());
+    // estimates with an access list are only good for the call carrying it
+    let has_access_list =
+        matches!(&call, Transaction::Eip1559 { access_list, .. } if !access_list.is_empty());
+    if estimate.is_none() && !has_access_list && !quoting {
+        cache_estimate(tx.sig_hash(), gas_used);
+    }
+    info!("Gas estimate: {gas_used}");
+    if let Some(max_gas_limit) = limits.max_gas_limit
+        && gas_used > max_gas_limit
+    {
+        info!(
+            tx:% = tx.id(),
+            decision = "gas_limit_exceeded",
+            skip_reason:% = SkipReason::TooLarge,
+            tip_token:% = display_token(ctx.chain_id, tip_token),
+            tip_value:% = tip_amount,
+            gas_estimate:% = gas_used;
+            "Transaction {} gas estimate {gas_used} exceeds the limit of {max_gas_limit}, skipping",
+            tx.id()
+        );
+        return Err(RelayerError::Skipped(SkipReason::TooLarge));
+    }
+    if let Some(block_gas_cap) = block_gas_cap(ctx.chain_id)
+        && gas_used > block_gas_cap
+    {
+        info!(
+            tx:% = tx.id(),
+            decision = "block_gas_limit_exceeded",
+            skip_reason:% = SkipReason::TooLarge,
+            tip_token:% = display_token(ctx.chain_id, tip_token),
+            tip_value:% = tip_amount,
+            gas_estimate:% = gas_used;
+            "Transaction {} gas estimate {gas_used} takes up too much of the block gas limit, at most {block_gas_cap}, skipping",
+            tx.id()
+        );
+        return Err(RelayerError::Skipped(SkipReason::TooLarge));
+    }
+
+    // an urgent relay pays its raised priority fee on top of the usual price
+    let gas_price = gas_price(web3, ctx.chain_id).await? + urgency_premium;
+
+    // above the ceiling we either pause entirely or hold out for unusually large tips
+    let gas_cost_multiple = match limits.max_gas_price {
+        Some(max_gas_price) if gas_price > max_gas_price => match limits.over_cap_tip_multiple {
+            Some(multiple) => {
+                info!(
+                    "Gas price {gas_price} is above the ceiling of {max_gas_price}, requiring a tip of {multiple}x the gas cost"
+                );
+                multiple
+            }
+            None => {
+                info!(
+                    tx:% = tx.id(),
+                    decision = "gas_price_too_high",
+                    skip_reason:% = SkipReason::GasPriceTooHigh,
+                    tip_token:% = display_token(ctx.chain_id, tip_token),
+                    tip_value:% = tip_amount,
+                    gas_estimate:% = gas_used;
+                    "Gas price {gas_price} is above the ceiling of {max_gas_price}, not relaying until it falls"
+                );
+                return Err(RelayerError::Skipped(SkipReason::GasPriceTooHigh));
+            }
+        },
+        _ => 1,
+    };
+
+    let tip = match prefetched.and_then(|p| p.tip).or(valued_tip) {
+        Some(tip) => tip,
+        None => {
+            value_tip(ctx, tip_amount, tip_token, gas_price)
+                .instrument(info_span!("price"))
+                .await?
+        }
+    };
+    // the tip has to cover what gas may cost by the time the relay is included, not just what it costs now
+    let required_gas_price = pessimistic_gas_price(web3, ctx.chain_id, gas_price - urgency_premium)
+        .await
+        + urgency_premium;
+    let verdict =
+        |verdict| AuditInputs::new(gas_used, gas_price, required_gas_price, &tip, verdict);
+    let fees = FeeData {
+        gas_price: required_gas_price,
+        gas_cost_multiple,
+        profit_margin_percent: limits.profit_margin_percent,
+    };
+    // the smallest tip announced to orchestrators when registering holds whatever gas costs, partners aside
+    let below_min_tip = partner.is_none()
+        && registration_policy()
+            .and_then(|policy| policy.min_tip)
+            .is_some_and(|min_tip| tip.net_value() < min_tip);
+    if below_min_tip && !quoting {
+        info!(
+            tx:% = tx.id(),
+            decision = "below_min_tip",
+            skip_reason:% = SkipReason::Unprofitable,
+            tip_token:% = display_token(ctx.chain_id, tip_token),
+            tip_value:% = tip_amount,
+            gas_estimate:% = gas_used;
+            "Transaction {} tips less than the minimum tip announced to orchestrators, skipping",
+            tx.id()
+        );
+        audit(verdict("below_min_tip"));
+        return Err(RelayerError::Unprofitable);
+    }
+    let mut profitable = !below_min_tip;
+    if ctx.profit_policy.is_profitable(&ProfitInputs {
+        tx: &decode_transaction(tx, ctx.contract_address),
+        gas_estimate: gas_used,
+        fees,
+        tip,
+    }) {
+        trace!("Transaction is profitable, proceeding to send");
+        if !quoting {
+            record_tip_value(tx, tip.net_value());
+        }
+        audit(verdict("profitable"));
+    } else if let Some(partner) = partner {
+        info!(
+            tx:% = tx.id(),
+            decision = "subsidized",
+            tip_token:% = display_token(ctx.chain_id, tip_token),
+            tip_value:% = tip_amount,
+            gas_estimate:% = gas_used;
+            "Transaction {} from partner {} is not profitable, relaying it anyway",
+            tx.id(),
+            display_user(partner)
+        );
+        if !quoting {
+            record_tip_value(tx, tip.net_value());
+        }
+        audit(verdict("subsidized"));
+    } else if subsidy_enabled() {
+        // reserved at the most the relay could cost, settled at what it did once final
+        let max_cost = max_cost(&call);
+        let shortfall = if max_cost > tip.net_value() {
+            max_cost - tip.net_value()
+        } else {
+            Uint256::default()
+        };
+        let sponsored = tx.client(ctx.contract_address).is_ok_and(|user| {
+            if quoting {
+                subsidy_available(tx, user, shortfall)
+            } else {
+                reserve_subsidy(tx, user, shortfall)
+            }
+        });
+        if !sponsored {
+            info!(
+                tx:% = tx.id(),
+                decision = "subsidy_exhausted",
+                skip_reason:% = SkipReason::SubsidyExhausted,
+                tip_token:% = display_token(ctx.chain_id, tip_token),
+                tip_value:% = tip_amount,
+                gas_estimate:% = gas_used;
+                "Transaction {} is not profitable and neither the subsidy budget nor its user's share of it can cover its shortfall of {shortfall} wei, skipping",
+                tx.id()
+            );
+            audit(verdict("subsidy_exhausted"));
+            return Err(RelayerError::Skipped(SkipReason::SubsidyExhausted));
+        }
+        info!(
+            tx:% = tx.id(),
+            decision = "sponsored",
+            tip_token:% = display_token(ctx.chain_id, tip_token),
+            tip_value:% = tip_amount,
+            gas_estimate:% = gas_used;
+            "Transaction {} is not profitable, relaying it on up to {shortfall} wei of the subsidy budget",
+            tx.id()
+        );
+        if !quoting {
+            record_tip_value(tx, tip.net_value());
+        }
+        audit(verdict("sponsored"));
+    } else if quoting {
+        profitable = false;
+    } else {
+        info!(
+            tx:% = tx.id(),
+            decision = "unprofitable",
+            skip_reason:% = SkipReason::Unprofitable,
+            tip_token:% = display_token(ctx.chain_id, tip_token),
+            tip_value:% = tip_amount,
+            gas_estimate:% = gas_used;
+            "Transaction is not profitable, skipping"
+        );
+        audit(verdict("unprofitable"));
+        return Err(RelayerError::Unprofitable);
+    }
+
+    // state may have changed since we estimated gas, for example another relayer may have already landed this
+    // command, so simulate once more against the latest block right before committing to a broadcast
+    trace!("Re-simulating transaction against the latest block");
+    match web3
+        .eth_call(TransactionRequest::from_transaction(
+            &call,
+            signer.address(),
+        ))
+        .instrument(info_span!("simulate"))
+        .await
+    {
+        Ok(_) => trace!("Re-simulation succeeded"),
+        Err(e) => match RelayerError::from(e) {
+            RelayerError::SimulationRevert(reason) => {
+                info!(
+                    tx:% = tx.id(),
+                    decision = "simulation_failed",
+                    skip_reason:% = SkipReason::SimulationFailed,
+                    tip_token:% = display_token(ctx.chain_id, tip_token),
+                    tip_value:% = tip_amount,
+                    gas_estimate:% = gas_used,
+                    revert_reason:% = reason;
+                    "Transaction {} now fails simulation, skipping: {reason}",
+                    tx.id()
+                );
+                return Err(RelayerError::SimulationRevert(reason));
+            }
+            e => {
+                error!(
+                    tx:% = tx.id(), rpc:% = redact_url(&web3.get_url());
+                    "Failed to re-simulate transaction: {e:?}"
+                );
+                return Err(e);
+            }
+        },
+    }
+
+    if let Some(min_tip) = limits.trace_min_tip
+        && tip.net_value() >= min_tip
+    {
+        trace!("Tracing high value transaction before committing to a broadcast");
+        if let Err(e) = inspect_relay(ctx, &call, signer.address(), tip_token, tip_amount)
+            .instrument(info_span!("trace"))
+            .await
+        {
+            info!(
+                tx:% = tx.id(),
+                decision = "trace_rejected",
+                skip_reason:% = SkipReason::TraceRejected,
+                tip_token:% = display_token(ctx.chain_id, tip_token),
+                tip_value:% = tip_amount,
+                gas_estimate:% = gas_used;
+                "Transaction {} failed inspection of its trace, skipping: {e}",
+                tx.id()
+            );
+            return Err(e);
+        }
+    }
+
+    if let Some(competitor) = competing_relay(ctx.chain_id, tx) {
+        info!(
+            tx:% = tx.id(),
+            decision = "competing_relay",
+            skip_reason:% = SkipReason::CompetingRelay,
+            tip_token:% = display_token(ctx.chain_id, tip_token),
+            tip_value:% = tip_amount,
+            gas_estimate:% = gas_used;
+            "Transaction {} is already being relayed by {competitor}, skipping",
+            tx.id()
+        );
+        return Err(RelayerError::Skipped(SkipReason::CompetingRelay));
+    }
+
+    // each transaction is only relayed by one of the redundant instances, it is leased once every check passed so
+    // one we turn down is never held from the others
+    if !ctx.dry_run && !standby && !quoting && !acquire_relay_lease(tx).await {
+        info!(
+            tx:% = tx.id(),
+            decision = "leased_elsewhere",
+            skip_reason:% = SkipReason::LeasedElsewhere;
+            "Transaction {} is leased by another instance, skipping",
+            tx.id()
+        );
+        return Err(RelayerError::Skipped(SkipReason::LeasedElsewhere));
+    }
+
+    // the transaction is only reserved once every check passed, so one we turn down is never held from other
+    // relayers. There's no point claiming in dry run mode since we will never submit
+    if let (true, false, false, Evaluation::Relay(Some(orchestrator))) =
+        (ctx.claim, ctx.dry_run, standby, evaluation)
+    {
+        if !claim_transaction(orchestrator, tx, signer).await {
+            info!(
+                tx:% = tx.id(),
+                decision = "claimed_elsewhere",
+                skip_reason:% = SkipReason::ClaimedElsewhere;
+                "Transaction {} claimed by another relayer, skipping",
+                tx.id()
+            );
+            return Err(RelayerError::Skipped(SkipReason::ClaimedElsewhere));
+        }
+        trace!("Claimed transaction {}", tx.id());
+    }
+
+    if !quoting {
+        record_decision(ctx, tx, "accepted", None, None, None, audit_inputs(tx));
+    }
+    Ok(PreparedRelay {
+        ctx,
+        tx,
+        signer,
+        call,
+        gas_used,
+        gas_price,
+        fees,
+        tip,
+        profitable,
+        tip_token,
+        tip_amount,
+        batchable,
+        standby,
+    })
+}
+
+/// Logs why `tx` reverts when its gas is estimated, returning the reason as an error
+fn estimate_reverted(
+    tx: &GaslessTransaction,
+    tip_token: Address,
+    tip_amount: Uint256,
+    reason: String,
+) -> RelayerError {
+    info!(
+        tx:% = tx.id(),
+        decision = "estimate_reverted",
+        skip_reason:% = SkipReason::SimulationFailed,
+        tip_token:% = display_token(tx.chain_id, tip_token),
+        tip_value:% = tip_amount,
+        revert_reason:% = reason;
+        "Transaction {} reverts during gas estimation: {reason}",
+        tx.id()
+    );
+    RelayerError::SimulationRevert(reason)
+}
+
+/// Broadcasts a single prepared relay, returning its hash without waiting for it to be included
+async fn submit_relay(
+    ctx: &RelayContext,
+    orchestrator: Option<&Orchestrator>,
+    relay: PreparedRelay<'_>,
+) -> Result<Option<Uint256>, RelayerError> {
+    let PreparedRelay {
+        tx,
+        signer,
+        call,
+        gas_used,
+        gas_price,
+        tip_token,
+        tip_amount,
+        standby,
+        ..
+    } = relay;
+    if standby || (!ctx.dry_run && !is_leader()) {
+        info!(
+            tx:% = tx.id(),
+            decision = "standby",
+            tip_token:% = display_token(ctx.chain_id, tip_token),
+            tip_value:% = tip_amount,
+            gas_estimate:% = gas_used;
+            "Not the leader, standing by instead of submitting transaction {} on {} from {}",
+            tx.id(),
+            ctx.name,
+            signer.address()
+        );
+        take_tip_value(tx);
+        return Ok(None);
+    }
+    if ctx.dry_run {
+        info!(
+            tx:% = tx.id(),
+            decision = "dry_run",
+            tip_token:% = display_token(ctx.chain_id, tip_token),
+            tip_value:% = tip_amount,
+            gas_estimate:% = gas_used;
+            "Dry run: would submit userCmdRelayer on {} from {} to {} with nonce {} gas limit {} gas price {} tip {} of token {} calldata 0x{}",
+            ctx.name,
+            signer.address(),
+            call.get_to(),
+            call.get_nonce(),
+            call.get_gas_limit(),
+            gas_price,
+            tip_amount,
+            tip_token,
+            hex::encode(call.data_ref())
+        );
+        if is_observing() {
+            record_observation(ctx, tx, take_tip_value(tx), gas_used * gas_price);
+        }
+        return Ok(None);
+    }
+    broadcast(ctx, orchestrator, signer, call, &[tx])
+        .instrument(info_span!("submit"))
+        .await
+        .map(Some)
+}
+
+/// Bundles prepared relays into one multicall transaction. Each call is allowed to fail independently, if the
+/// bundle itself can't be built every relay falls back to being prepared and submitted on its own.
+async fn relay_batch(orchestrator: Option<&Orchestrator>, batch: Vec<PreparedRelay<'_>>) {
+    let Some(first) = batch.first() else {
+        return;
+    };
+    let (ctx, signer) = (first.ctx, first.signer);
+    let Some(multicall) = ctx.multicall else {
+        return;
+    };
+    // while standing by each relay is passed to `submit_relay` on its own, which skips it
+    if batch.len() == 1 || batch.iter().any(|relay| relay.standby) || (!ctx.dry_run && !is_leader())
+    {
+        for relay in batch {
+            let tx = relay.tx;
+            let result = submit_relay(ctx, orchestrator, relay).await;
+            handle_relay_result(ctx, orchestrator, tx, result);
+        }
+        return;
+    }
+
+    let ids: Vec<String> = batch.iter().map(|relay| relay.tx.id()).collect();
+    let calls: Vec<(Address, Vec<u8>)> = batch
+        .iter()
+        .map(|relay| (relay.call.get_to(), relay.call.data_ref().to_vec()))
+        .collect();
+    let data = encode_aggregate3(&calls);
+    let call = match prepare_unsigned_transaction(
+        ctx.web3(),
+        ctx.chain_id,
+        signer.address(),
+        multicall,
+        data,
+        2.0,
+    )
+    .await
+    {
+        Ok(unsigned) => signer.sign_transaction(unsigned).await,
+        Err(e) => Err(e),
+    };
+    let call = match call {
+        Ok(call) => call,
+        Err(e) => {
+            warn!(
+                "Failed to build batch of {} relays, submitting individually: {e}",
+                batch.len()
+            );
+            for relay in batch {
+                let result = relay_transaction(ctx, orchestrator, relay.tx, None).await;
+                handle_relay_result(ctx, orchestrator, relay.tx, result);
+            }
+            return;
+        }
+    };
+
+    if ctx.dry_run {
+        info!(
+            "Dry run: would submit batch of {} relays ({}) on {} from {} via multicall {} with nonce {} gas limit {}",
+            batch.len(),
+            ids.join(", "),
+            ctx.name,
+            signer.address(),
+            multicall,
+            call.get_nonce(),
+            call.get_gas_limit()
+        );
+        for relay in &batch {
+            if is_observing() {
+                let gas_cost = relay.gas_used * relay.gas_price;
+                record_observation(ctx, relay.tx, take_tip_value(relay.tx), gas_cost);
+            }
+            handle_relay_result(ctx, orchestrator, relay.tx, Ok(None));
+        }
+        return;
+    }
+
+    let txs: Vec<&GaslessTransaction> = batch.iter().map(|relay| relay.tx).collect();
+    match broadcast(ctx, orchestrator, signer, call, &txs)
+        .instrument(info_span!("submit_batch", size = batch.len()))
+        .await
+    {
+        Ok(tx_hash) => {
+            for relay in &batch {
+                handle_relay_result(ctx, orchestrator, relay.tx, Ok(Some(tx_hash)));
+            }
+        }
+        Err(e) => {
+            ctx.rpc.report_error(&e);
+            for relay in &batch {
+                release_relay_lease(relay.tx);
+                record_failure(orchestrator, relay.tx, &e);
+            }
+            debug!("Batch relay failed: {e}");
+        }
+    }
+}
+
+/// Sends a signed relay of `txs`, which are then tracked by `process_confirmations` until they are final
+async fn broadcast(
+    ctx: &RelayContext,
+    orchestrator: Option<&Orchestrator>,
+    signer: &Signer,
+    call: Transaction,
+    txs: &[&GaslessTransaction],
+) -> Result<Uint256, RelayerError> {
+    let id = txs.iter().map(|tx| tx.id()).collect::<Vec<_>>().join(",");
+    trace!("Submitting transaction...");
+    for _ in txs {
+        record_relay_attempt();
+    }
+    match send_transaction(ctx, call.clone()).await {
+        Ok(pending_tx) => {
+            let tx_hash = display_uint256_as_address(pending_tx);
+            info!(
+                tx:% = id, decision = "submitted", tx_hash:% = tx_hash;
+                "Transaction submitted on {} from {} with hash {tx_hash}, tracking it in the background",
+                ctx.name,
+                signer.address()
+            );
+            add_in_flight(tx_hash, id);
+            for tx in txs {
+                let inputs = take_audit_inputs(tx);
+                record_decision(ctx, tx, "submitted", None, None, Some(pending_tx), inputs);
+                watch_relay(ctx.chain_id, tx, orchestrator, pending_tx, signer, &call);
+                // only submitted relays use up their user's quota, skipped transactions don't
+                if rate_limit_enabled()
+                    && let Ok(client) = tx.client(ctx.contract_address)
+                {
+                    charge_rate_limit(client, tx.sig_hash());
+                }
+            }
+            Ok(pending_tx)
+        }
+        Err(e) => {
+            error!(tx:% = id, rpc:% = redact_url(&ctx.web3().get_url()); "Transaction failed: {e:?}");
+            Err(e.into())
+        }
+    }
+}
+
+/// Sends a signed transaction through the private RPC if one is configured, falling back to public broadcast
+pub(crate) async fn send_transaction(
+    ctx: &RelayContext,
+    call: Transaction,
+) -> Result<Uint256, Web3Error> {
+    match &ctx.private_rpc {
+        Some(private_rpc) => match private_rpc.send_prepared_transaction(call.clone()).await {
+            Ok(hash) => Ok(hash),
+            Err(e) => {
+                warn!(
+                    "Private submission on {} failed, broadcasting publicly: {e}",
+                    ctx.name
+                );
+                ctx.web3().send_prepared_transaction(call).await
+            }
+        },
+        None => ctx.web3().send_prepared_transaction(call).await,
+    }
+}
+
+/// Signs and sends a transaction of the relayer's own, such as a sweep or a payout, built by
+/// `prepare_unsigned_transaction`. It isn't watched like relays are, so its nonce is held as the account's floor
+/// until the node counts it, or until the floor expires if it was dropped. Nothing is sent once gas spending has
+/// reached its cap, and the most it can cost counts against the cap as soon as it is sent.
+pub(crate) async fn send_side_transaction(
+    ctx: &RelayContext,
+    signer: &Signer,
+    unsigned: Transaction,
+) -> Result<Uint256, RelayerError> {
+    // only the instance relaying from the account hands out its nonces
+    if !may_broadcast(ctx.chain_id, signer.address()) {
+        return Err(RelayerError::Skipped(SkipReason::Standby));
+    }
+    if spend_cap_reached() {
+        return Err(RelayerError::SpendCapReached);
+    }
+    let nonce = unsigned.get_nonce();
+    let cost = max_cost(&unsigned);
+    let call = signer.sign_transaction(unsigned).await?;
+    let tx_hash = send_transaction(ctx, call).await?;
+    set_nonce_floor(ctx.chain_id, signer.address(), nonce + 1u8.into());
+    record_spend(cost);
+    Ok(tx_hash)
+}
+
+/// As `send_side_transaction` for a command of `kind` with `id` that must only ever be executed once, such as a
+/// protocol command. It is recorded in the history before it is sent and forgotten again if sending fails.
+pub(crate) async fn send_side_transaction_once(
+    ctx: &RelayContext,
+    signer: &Signer,
+    unsigned: Transaction,
+    kind: &str,
+    id: &str,
+) -> Result<Uint256, RelayerError> {
+    record_execution(kind, id, ctx.chain_id).map_err(RelayerError::HistoryError)?;
+    match send_side_transaction(ctx, signer, unsigned).await {
+        Ok(tx_hash) => {
+            finish_execution(kind, id, Some(&display_uint256_as_address(tx_hash)));
+            Ok(tx_hash)
+        }
+        Err(e) => {
+            finish_execution(kind, id, None);
+            Err(e)
+        }
+    }
+}
+
+/// Replaces our pending `call` with a zero value transfer to ourselves at the same nonce, paying twice the
+/// fees so nodes accept the replacement. This costs 21000 gas rather than the gas of a guaranteed revert. The
+/// cancellation's receipt isn't waited for, so the most it can cost counts against the spend cap once it is sent.
+async fn cancel_transaction(ctx: &RelayContext, signer: &Signer, call: &Transaction) {
+    let Transaction::Eip1559 {
+        chain_id,
+        nonce,
+        max_priority_fee_per_gas,
+        max_fee_per_gas,
+        ..
+    } = call
+    else {
+        return;
+    };
+    let cancel = Transaction::Eip1559 {
+        chain_id: *chain_id,
+        nonce: *nonce,
+        max_priority_fee_per_gas: *max_priority_fee_per_gas * 2u8.into(),
+        max_fee_per_gas: *max_fee_per_gas * 2u8.into(),
+        gas_limit: 21000u32.into(),
+        to: signer.address(),
+        value: 0u8.into(),
+        data: Vec::new(),
+        signature: None,
+        access_list: Vec::new(),
+    };
+    let cost = max_cost(&cancel);
+    let result = match signer.sign_transaction(cancel).await {
+        Ok(cancel) => send_transaction(ctx, cancel)
+            .await
+            .map_err(RelayerError::from),
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(hash) => {
+            record_spend(cost);
+            info!(
+                "Sent cancellation {} for nonce {nonce} on {}",
+                display_uint256_as_address(hash),
+                ctx.name
+            )
+        }
+        Err(e) => warn!("Failed to cancel nonce {nonce} on {}: {e}", ctx.name),
+    }
+}
+
+/// Calldata relaying `tx` through `relay_function` of the dex or a periphery contract, which takes the callpath
+/// followed by the command, conditions, tip and signature as `bytes`. This runs for every transaction evaluated, so
+/// the fields are encoded straight into one buffer rather than copied into ABI tokens first.
+pub(crate) fn user_cmd_relayer_data(
+    relay_function: &str,
+    tx: &GaslessTransaction,
+) -> Result<Vec<u8>, RelayerError> {
+    let args = [&tx.cmd, &tx.conds, &tx.tip, &tx.sig];
+    let padded = |len: usize| len.div_ceil(32) * 32;
+    let head = 32 * (1 + args.len());
+    let tails: usize = args.iter().map(|arg| 32 + padded(arg.len())).sum();
+    let mut data = Vec::with_capacity(4 + head + tails);
+    data.extend_from_slice(&derive_method_id(relay_function)?);
+    data.extend_from_slice(&abi_word(tx.callpath.into()));
+    let mut offset = head;
+    for arg in args {
+        data.extend_from_slice(&abi_word(offset));
+        offset += 32 + padded(arg.len());
+    }
+    for arg in args {
+        data.extend_from_slice(&abi_word(arg.len()));
+        data.extend_from_slice(arg);
+        data.resize(data.len() + padded(arg.len()) - arg.len(), 0);
+    }
+    Ok(data)
+}
+
+/// `value` as a big endian ABI word
+fn abi_word(value: usize) -> [u8; 32] {
+    let mut word = [0u8; 32];
+    word[24..].copy_from_slice(&(value as u64).to_be_bytes());
+    word
+}
+
+/// Prepares the relay of `tx` from `signer` carrying `data`, its calldata, reusing `estimate` of its gas if one was
+/// already made. Returns the unsigned relay along with the premium its priority fee was raised by for `urgency` and
+/// its gas estimate.
+pub async fn user_cmd_relayer_tx(
+    ctx: &RelayContext,
+    signer: &Signer,
+    tx: &GaslessTransaction,
+    data: Vec<u8>,
+    estimate: Option<Uint256>,
+    urgency: Urgency,
+) -> Result<(Transaction, Uint256, Uint256), RelayerError> {
+    let (target, _) = ctx.relay_target(tx)?;
+    let (mut unsigned, gas_used) = prepare_estimated_transaction(
+        ctx.web3(),
+        tx.chain_id,
+        signer.address(),
+        target,
+        data,
+        2.0,
+        estimate,
+    )
+    .await?;
+    let premium = match urgency {
+        Urgency::Urgent => escalate_priority_fee(&mut unsigned),
+        _ => Uint256::default(),
+    };
+    Ok((unsigned, premium, gas_used))
+}
+
+/// Resyncs the nonces of every relayer account on `ctx` with the node's pending pool, so that relays sent before a
+/// restart which are still waiting in the pool are never given a nonce again
+pub async fn resync_nonces(ctx: &RelayContext) {
+    let web3 = ctx.web3();
+    for signer in ctx.signers.signers() {
+        let address = signer.address();
+        let client = HttpClient::new(&web3.get_url());
+        let pending = client.request_method::<_, Uint256>(
+            "eth_getTransactionCount",
+            vec![address.to_string(), "pending".to_string()],
+            web3.get_timeout(),
+        );
+        let (latest, pending) = match join(web3.eth_get_transaction_count(address), pending).await {
+            (Ok(latest), Ok(pending)) => (latest, pending),
+            (Err(e), _) | (_, Err(e)) => {
+                warn!(
+                    "Failed to resync the nonce of {address} on {}: {e}",
+                    ctx.name
+                );
+                continue;
+            }
+        };
+        let tracked = next_nonce(ctx.chain_id, address, latest);
+        if pending > tracked {
+            warn!(
+                "{} transactions from {address} are pending on {} without being tracked, starting from nonce {pending}",
+                pending - tracked,
+                ctx.name
+            );
+        }
+        set_nonce_floor(ctx.chain_id, address, pending);
+    }
+}
+
+/// Builds an unsigned EIP-1559 transaction the same way `Web3::prepare_transaction` does, so that it can be
+/// signed by a key that is not held in process. Fails if the node is not on `expected_chain_id`, as the relay
+/// would be signed for the wrong chain.
+pub(crate) async fn prepare_unsigned_transaction(
+    web3: &Web3,
+    expected_chain_id: u64,
+    own_address: Address,
+    to: Address,
+    data: Vec<u8>,
+    gas_limit_multiplier: f32,
+) -> Result<Transaction, RelayerError> {
+    prepare_estimated_transaction(
+        web3,
+        expected_chain_id,
+        own_address,
+        to,
+        data,
+        gas_limit_multiplier,
+        None,
+    )
+    .await
+    .map(|(transaction, _)| transaction)
+}
+
+/// As `prepare_unsigned_transaction`, reusing `estimate` of the gas the transaction uses instead of estimating it
+/// if there is one. Returns the transaction along with the gas estimate its limit was set from.
+async fn prepare_estimated_transaction(
+    web3: &Web3,
+    expected_chain_id: u64,
+    own_address: Address,
+    to: Address,
+    data: Vec<u8>,
+    gas_limit_multiplier: f32,
+    estimate: Option<Uint256>,
+) -> Result<(Transaction, Uint256), RelayerError> {
+    let (balance, nonce, base_fee_per_gas, chain_id, fees) = join5(
+        web3.eth_get_balance(own_address),
+        web3.eth_get_transaction_count(own_address),
+        web3.get_base_fee_per_gas(),
+        web3.eth_chainid(),
+        suggest_fees(web3, expected_chain_id),
+    )
+    .await;
+    let (balance, nonce, base_fee_per_gas, chain_id, fees) =
+        (balance?, nonce?, base_fee_per_gas?, chain_id?, fees?);
+    // relays still pending aren't counted by the node at the latest block
+    let nonce = next_nonce(expected_chain_id, own_address, nonce);
+    if chain_id != expected_chain_id {
+        return Err(RelayerError::WrongChain {
+            expected: expected_chain_id,
+            actual: chain_id,
+        });
+    }
+    let base_fee_per_gas = base_fee_per_gas.ok_or(Web3Error::PreLondon)?;
+    // relays still pending can spend from the balance the node reports at the latest block
+    let pending = pending_spend(expected_chain_id, own_address);
+    let balance = if balance > pending {
+        balance - pending
+    } else {
+        Uint256::default()
+    };
+    // the reserve is left untouched for liquidating tips and cancelling stuck relays
+    let reserve = relay_limits().reserve_balance;
+    if !reserve.is_zero() && balance <= reserve {
+        return Err(RelayerError::BelowReserve { balance, reserve });
+    }
+    let spendable = balance - reserve;
+    if balance.is_zero() {
+        return Err(Web3Error::InsufficientGas {
+            balance,
+            base_gas: base_fee_per_gas,
+            gas_required: 0u8.into(),
+        }
+        .into());
+    }
+
+    // the fee actually paid is set by the block we land in, leave room for the base fee to rise
+    let (max_priority_fee_per_gas, max_fee_per_gas) = match fees {
+        Some(fees) => (fees.priority_fee, fees.max_fee_per_gas()),
+        None => (1u8.into(), base_fee_per_gas * 2u8.into()),
+    };
+    let mut transaction = Transaction::Eip1559 {
+        chain_id: chain_id.into(),
+        nonce,
+        max_priority_fee_per_gas,
+        max_fee_per_gas,
+        gas_limit: 0u8.into(),
+        to,
+        value: 0u8.into(),
+        data,
+        signature: None,
+        access_list: Vec::new(),
+    };
+    let request = TransactionRequest::from_transaction(&transaction, own_address);
+    let mut gas_used = match estimate {
+        Some(gas) => gas,
+        None => match web3
+            .eth_estimate_gas(request.clone())
+            .instrument(info_span!("estimate_gas"))
+            .await
+            .map_err(RelayerError::from)
+        {
+            Ok(gas) => gas,
+            // a revert is the transaction's fault and logged where it is skipped
+            Err(RelayerError::SimulationRevert(reason)) => {
+                return Err(RelayerError::SimulationRevert(reason));
+            }
+            Err(e) => {
+                error!(to:% = to, rpc:% = redact_url(&web3.get_url()); "Failed to estimate gas: {e:?}");
+                return Err(e);
+            }
+        },
+    };
+    if let Some((list, gas)) = cheaper_access_list(web3, &request, gas_used).await
+        && let Transaction::Eip1559 { access_list, .. } = &mut transaction
+    {
+        *access_list = list;
+        gas_used = gas;
+    }
+    let gas_limit: Uint256 = match gas_used.to_u128() {
+        Some(v) => ((v as f32 * gas_limit_multiplier) as u128).into(),
+        None => gas_used * (gas_limit_multiplier.round() as u128).into(),
+    };
+    transaction.set_gas_limit(gas_limit);
+
+    // never promise more gas than we can pay for, lower the max fee to what we can afford if possible
+    if max_fee_per_gas * gas_limit > spendable {
+        if base_fee_per_gas * gas_limit > spendable {
+            if !reserve.is_zero() {
+                return Err(RelayerError::BelowReserve { balance, reserve });
+            }
+            return Err(Web3Error::InsufficientGas {
+                balance,
+                base_gas: base_fee_per_gas,
+                gas_required: gas_limit,
+            }
+            .into());
+        }
+        let affordable = spendable / gas_limit;
+        transaction.set_max_fee_per_gas(affordable);
+        // the priority fee can never exceed the max fee
+        if max_priority_fee_per_gas > affordable {
+            transaction.set_max_priority_fee_per_gas(affordable);
+        }
+    }
+    Ok((transaction, gas_used))
+}
+
+/// Most the relays `address` has pending on `chain_id` could still spend, a batch is counted once for all of the
+/// transactions in it
+pub(crate) fn pending_spend(chain_id: u64, address: Address) -> Uint256 {
+    let mut counted = HashSet::new();
+    watched_relays(chain_id)
+        .iter()
+        .filter(|w| w.signer.address() == address && w.block().is_none())
+        .filter(|w| counted.insert(w.tx_hash))
+        .fold(Uint256::default(), |sum, w| sum + max_cost(&w.call))
+}
+
+/// Most `call` can cost in gas, its gas limit at its max fee
+fn max_cost(call: &Transaction) -> Uint256 {
+    let max_fee_per_gas = match call {
+        Transaction::Legacy { gas_price, .. } => *gas_price,
+        Transaction::Eip2930 { gas_price, .. } => *gas_price,
+        Transaction::Eip1559 {
+            max_fee_per_gas, ..
+        } => *max_fee_per_gas,
+    };
+    call.get_gas_limit() * max_fee_per_gas
+}
+
+pub fn get_call_data(request: &Transaction) -> Data {
+    match request {
+        Transaction::Legacy { data, .. } => Data(data.clone()),
+        Transaction::Eip1559 { data, .. } => Data(data.clone()),
+        Transaction::Eip2930 { data, .. } => Data(data.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::USER_CMD_RELAYER_SIG;
+    use clarity::abi::encode_call;
+
+    fn transaction(callpath: u16, lens: [usize; 4]) -> GaslessTransaction {
+        let field = |len: usize, fill: u8| (0..len).map(|i| fill ^ i as u8).collect();
+        GaslessTransaction {
+            chain_id: 258432,
+            callpath,
+            cmd: field(lens[0], 0x11),
+            conds: field(lens[1], 0x22),
+            tip: field(lens[2], 0x33),
+            sig: field(lens[3], 0x44),
+            submitted_at: 0,
+            target: None,
+        }
+    }
+
+    #[test]
+    fn relayer_data_matches_encode_call() {
+        // encode_call panics on empty bytes, which are refused before a relay is encoded anyway
+        let cases = [
+            (0, [1, 1, 1, 1]),
+            (1, [1, 31, 32, 33]),
+            (2, [320, 160, 96, 65]),
+            (u16::MAX, [64, 63, 65, 1000]),
+        ];
+        for (callpath, lens) in cases {
+            let tx = transaction(callpath, lens);
+            let expected = encode_call(
+                USER_CMD_RELAYER_SIG,
+                &[
+                    tx.callpath.into(),
+                    tx.cmd.clone().into(),
+                    tx.conds.clone().into(),
+                    tx.tip.clone().into(),
+                    tx.sig.clone().into(),
+                ],
+            )
+            .unwrap();
+            let data = user_cmd_relayer_data(USER_CMD_RELAYER_SIG, &tx).unwrap();
+            assert_eq!(
+                data, expected,
+                "callpath {callpath}, field lengths {lens:?}"
+            );
+        }
+    }
+}
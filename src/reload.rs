@@ -0,0 +1,35 @@
+//! Configuration reload requests. SIGHUP and the admin API only raise a flag, the relay loop re-reads the
+//! configuration between cycles so that settings never change in the middle of relaying a transaction.
+
+use log::info;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static RELOAD_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Asks the relay loop to reload the configuration before its next cycle
+pub fn request_reload() {
+    RELOAD_REQUESTED.store(true, Ordering::Relaxed);
+}
+
+/// True if a reload was requested since the last call
+pub fn take_reload_request() -> bool {
+    RELOAD_REQUESTED.swap(false, Ordering::Relaxed)
+}
+
+/// Spawns a task that reopens the log file and requests a configuration reload on every SIGHUP
+#[cfg(unix)]
+pub fn listen_for_reload_signal() {
+    use crate::logging::reopen_log_file;
+    use actix_rt::signal::unix::{SignalKind, signal};
+    let mut hangup = signal(SignalKind::hangup()).expect("Failed to register SIGHUP handler");
+    actix_rt::spawn(async move {
+        while hangup.recv().await.is_some() {
+            reopen_log_file();
+            info!("SIGHUP received, reloading configuration");
+            request_reload();
+        }
+    });
+}
+
+#[cfg(not(unix))]
+pub fn listen_for_reload_signal() {}
@@ -0,0 +1,255 @@
+//! Reporting of panics and error level logs to Sentry or a generic webhook, so that crashes and persistent
+//! errors in unattended deployments are noticed. Structured fields attached to the log, such as the orchestrator
+//! url, the transaction's sig hash and the RPC endpoint, are sent along as context. Reports are sent from a
+//! thread of their own so reporting never holds up relaying, only a panic waits for its report to go out.
+
+use crate::proxy::http_client;
+use log::kv::{Key, Value, VisitSource};
+use log::{Level, Record, warn};
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::panic::PanicHookInfo;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::{UnboundedSender, unbounded_channel};
+
+/// Name of the thread reports are sent from, errors it logs itself are never reported
+const REPORTER_THREAD: &str = "error-reporter";
+
+/// Longest tag value Sentry accepts, longer fields are only sent as extra context
+const MAX_TAG_LEN: usize = 200;
+
+/// Where errors are reported
+#[derive(Debug, Clone)]
+pub enum ReportSink {
+    Sentry {
+        /// the project's store endpoint
+        store_url: String,
+        /// `X-Sentry-Auth` header carrying the DSN's public key
+        auth: String,
+    },
+    /// url every report is posted to as JSON
+    Webhook(String),
+}
+
+impl ReportSink {
+    /// Sink for a Sentry DSN of the form `https://<public key>@<host>/<project id>`
+    pub fn sentry(dsn: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid Sentry DSN {dsn}");
+        let (scheme, rest) = dsn.split_once("://").ok_or_else(invalid)?;
+        let (key, rest) = rest.split_once('@').ok_or_else(invalid)?;
+        let key = key.split(':').next().unwrap_or_default();
+        let (host, path) = rest.split_once('/').ok_or_else(invalid)?;
+        let (prefix, project) = match path.trim_end_matches('/').rsplit_once('/') {
+            Some((prefix, project)) => (format!("/{prefix}"), project),
+            None => (String::new(), path.trim_end_matches('/')),
+        };
+        if key.is_empty() || host.is_empty() || project.is_empty() {
+            return Err(invalid());
+        }
+        Ok(ReportSink::Sentry {
+            store_url: format!("{scheme}://{host}{prefix}/api/{project}/store/"),
+            auth: format!(
+                "Sentry sentry_version=7, sentry_client=althea-l1-relayer/{}, sentry_key={key}",
+                env!("CARGO_PKG_VERSION")
+            ),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ReportPolicy {
+    pub sinks: Vec<ReportSink>,
+    /// timeout of each request to a sink, and how long a panic waits for its report to go out
+    pub timeout: Duration,
+}
+
+/// An error or panic, the body of webhook reports
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorReport {
+    /// `error` for error level logs, `fatal` for panics
+    pub level: &'static str,
+    pub message: String,
+    /// module the error was logged from
+    pub target: String,
+    /// structured fields attached to the log
+    pub fields: BTreeMap<String, String>,
+    /// unix timestamp in seconds
+    pub timestamp: u64,
+    pub version: &'static str,
+}
+
+impl ErrorReport {
+    fn new(level: &'static str, message: String, target: String) -> Self {
+        ErrorReport {
+            level,
+            message,
+            target,
+            fields: BTreeMap::new(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            version: env!("CARGO_PKG_VERSION"),
+        }
+    }
+
+    fn sentry_event(&self) -> serde_json::Value {
+        let tags: BTreeMap<&String, &String> = self
+            .fields
+            .iter()
+            .filter(|(_, value)| value.len() <= MAX_TAG_LEN)
+            .collect();
+        json!({
+            "event_id": format!("{:032x}", rand::random::<u128>()),
+            "timestamp": self.timestamp,
+            "level": self.level,
+            "logger": self.target,
+            "platform": "other",
+            "release": format!("althea-l1-relayer@{}", self.version),
+            "message": { "formatted": self.message },
+            "tags": tags,
+            "extra": self.fields,
+        })
+    }
+}
+
+struct Reporter {
+    sender: UnboundedSender<ErrorReport>,
+    timeout: Duration,
+}
+
+static REPORTER: OnceLock<Reporter> = OnceLock::new();
+
+/// Reports queued or being sent
+static PENDING: AtomicUsize = AtomicUsize::new(0);
+
+/// Starts reporting panics and error level logs to the sinks of `policy`
+pub fn init_error_reporting(policy: ReportPolicy) {
+    let (sender, mut receiver) = unbounded_channel::<ErrorReport>();
+    let timeout = policy.timeout;
+    let spawned = std::thread::Builder::new()
+        .name(REPORTER_THREAD.to_string())
+        .spawn(move || {
+            actix_rt::System::new().block_on(async move {
+                while let Some(report) = receiver.recv().await {
+                    for sink in &policy.sinks {
+                        send_report(sink, &report, policy.timeout).await;
+                    }
+                    PENDING.fetch_sub(1, Ordering::SeqCst);
+                }
+            })
+        });
+    if let Err(e) = spawned {
+        warn!("Failed to start error reporting: {e}");
+        return;
+    }
+    if REPORTER.set(Reporter { sender, timeout }).is_err() {
+        return;
+    }
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        report_panic(info);
+        previous(info);
+    }));
+}
+
+async fn send_report(sink: &ReportSink, report: &ErrorReport, timeout: Duration) {
+    let client = http_client(None, timeout);
+    let (url, result) = match sink {
+        ReportSink::Sentry { store_url, auth } => (
+            store_url,
+            client
+                .post(store_url)
+                .insert_header(("X-Sentry-Auth", auth.as_str()))
+                .send_json(&report.sentry_event())
+                .await,
+        ),
+        ReportSink::Webhook(url) => (url, client.post(url).send_json(report).await),
+    };
+    match result {
+        Ok(response) if response.status().is_success() => {}
+        Ok(response) => warn!("{url} rejected an error report: {}", response.status()),
+        Err(e) => warn!("Failed to send an error report to {url}: {e}"),
+    }
+}
+
+fn queue(report: ErrorReport) -> bool {
+    let Some(reporter) = REPORTER.get() else {
+        return false;
+    };
+    PENDING.fetch_add(1, Ordering::SeqCst);
+    if reporter.sender.send(report).is_err() {
+        PENDING.fetch_sub(1, Ordering::SeqCst);
+        return false;
+    }
+    true
+}
+
+/// Reports an error level log record, called by the logger for every record it writes
+pub fn report_log(record: &Record) {
+    if record.level() != Level::Error
+        || std::thread::current().name() == Some(REPORTER_THREAD)
+        || REPORTER.get().is_none()
+    {
+        return;
+    }
+    let mut report = ErrorReport::new(
+        "error",
+        record.args().to_string(),
+        record.target().to_string(),
+    );
+    let _ = record
+        .key_values()
+        .visit(&mut ReportFields(&mut report.fields));
+    queue(report);
+}
+
+/// Reports a panic, blocking until it has been sent since the process is likely about to exit
+fn report_panic(info: &PanicHookInfo) {
+    let message = info
+        .payload()
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| info.payload().downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "panic".to_string());
+    let mut report = ErrorReport::new("fatal", message, "panic".to_string());
+    if let Some(location) = info.location() {
+        report
+            .fields
+            .insert("location".into(), location.to_string());
+    }
+    let thread = std::thread::current();
+    if let Some(name) = thread.name() {
+        report.fields.insert("thread".into(), name.to_string());
+    }
+    let Some(reporter) = REPORTER.get() else {
+        return;
+    };
+    if !queue(report) || thread.name() == Some(REPORTER_THREAD) {
+        return;
+    }
+    let deadline = Instant::now() + reporter.timeout;
+    while PENDING.load(Ordering::SeqCst) > 0 && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// Waits up to `timeout` for queued reports to be sent, so errors logged right before exiting aren't lost
+pub async fn flush_error_reports(timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+    while PENDING.load(Ordering::SeqCst) > 0 && Instant::now() < deadline {
+        actix_rt::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+struct ReportFields<'a>(&'a mut BTreeMap<String, String>);
+
+impl<'kvs> VisitSource<'kvs> for ReportFields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+        self.0.insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+}
@@ -0,0 +1,243 @@
+//! Retry queue for transactions whose relay attempt failed for reasons that are likely to go away on their own,
+//! such as an RPC hiccup or a price API outage. Permanent failures like reverts are never retried, and
+//! transactions that can never be relayed at all are blacklisted so they aren't evaluated again. Transactions
+//! that keep failing permanently are put on an increasing cooldown and eventually blacklisted too.
+
+use crate::orchestrator::{GaslessTransaction, Orchestrator};
+use log::{debug, warn};
+use std::collections::{HashMap, HashSet};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+/// Backoff is never allowed to grow beyond this
+pub const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+/// Cooldown of repeatedly failing transactions is never allowed to grow beyond this, failure counts are
+/// forgotten once a transaction hasn't failed for this long
+pub const MAX_FAILURE_COOLDOWN: Duration = Duration::from_secs(3600);
+
+#[derive(Debug, Clone)]
+pub struct RetryEntry {
+    pub tx: GaslessTransaction,
+    /// orchestrator the transaction came from, if any
+    pub orchestrator: Option<Orchestrator>,
+    pub attempts: u32,
+    pub next_attempt: Instant,
+    pub last_error: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub base_delay: Duration,
+    pub max_attempts: u32,
+    /// cooldown after the first permanent failure of a transaction, doubling with each consecutive failure
+    pub failure_cooldown: Duration,
+    /// transactions failing with the same error this many times in a row are blacklisted
+    pub max_identical_failures: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            base_delay: Duration::from_secs(5),
+            max_attempts: 5,
+            failure_cooldown: Duration::from_secs(30),
+            max_identical_failures: 10,
+        }
+    }
+}
+
+/// Consecutive permanent failures of one transaction
+#[derive(Debug, Clone)]
+struct FailureRecord {
+    count: u32,
+    last_error: String,
+    cooldown_until: Instant,
+}
+
+#[derive(Debug, Default)]
+struct RetryQueue {
+    policy: RetryPolicy,
+    entries: HashMap<[u8; 32], RetryEntry>,
+    failures: HashMap<[u8; 32], FailureRecord>,
+    /// transient failures of each transaction with when the last one happened, kept across polls and skips so the
+    /// attempt cap holds until the transaction is relayed or hasn't failed for `MAX_FAILURE_COOLDOWN`
+    attempts: HashMap<[u8; 32], (u32, Instant)>,
+}
+
+static RETRY_QUEUE: LazyLock<Mutex<RetryQueue>> =
+    LazyLock::new(|| Mutex::new(RetryQueue::default()));
+
+/// Signature hashes of transactions that can never be relayed
+static BLACKLIST: LazyLock<Mutex<HashSet<[u8; 32]>>> = LazyLock::new(|| Mutex::new(HashSet::new()));
+
+pub fn set_retry_policy(policy: RetryPolicy) {
+    RETRY_QUEUE.lock().unwrap().policy = policy;
+}
+
+/// Queues a transaction to be relayed again in the next cycle without counting it as a failed attempt, such as
+/// when our relay of it was reorged out
+pub fn requeue(tx: &GaslessTransaction, orchestrator: Option<&Orchestrator>) {
+    let mut queue = RETRY_QUEUE.lock().unwrap();
+    let key = tx.sig_hash();
+    let attempts = queue.attempts.get(&key).map_or(0, |(count, _)| *count);
+    queue.entries.insert(
+        key,
+        RetryEntry {
+            tx: tx.clone(),
+            orchestrator: orchestrator.cloned(),
+            attempts,
+            next_attempt: Instant::now(),
+            last_error: "relay was reorged out".to_string(),
+        },
+    );
+}
+
+/// Schedules another attempt for a transaction that failed transiently, doubling the delay each time.
+/// Once the attempt cap is reached the transaction is no longer retried, it is only evaluated again when an
+/// orchestrator serves it.
+pub fn schedule_retry(tx: &GaslessTransaction, orchestrator: Option<&Orchestrator>, error: String) {
+    let mut queue = RETRY_QUEUE.lock().unwrap();
+    let policy = queue.policy.clone();
+    let now = Instant::now();
+    queue
+        .attempts
+        .retain(|_, (_, last)| now.saturating_duration_since(*last) < MAX_FAILURE_COOLDOWN);
+    let key = tx.sig_hash();
+    let attempts = queue.attempts.get(&key).map_or(0, |(count, _)| *count) + 1;
+    queue.attempts.insert(key, (attempts, now));
+    if attempts > policy.max_attempts {
+        warn!(
+            "Transaction {} failed {} times, giving up: {error}",
+            tx.id(),
+            attempts - 1
+        );
+        queue.entries.remove(&key);
+        return;
+    }
+    let delay = policy
+        .base_delay
+        .saturating_mul(2u32.saturating_pow(attempts - 1))
+        .min(MAX_RETRY_DELAY);
+    debug!(
+        "Retrying transaction {} in {}s (attempt {attempts}/{})",
+        tx.id(),
+        delay.as_secs(),
+        policy.max_attempts
+    );
+    queue.entries.insert(
+        key,
+        RetryEntry {
+            tx: tx.clone(),
+            orchestrator: orchestrator.cloned(),
+            attempts,
+            next_attempt: now + delay,
+            last_error: error,
+        },
+    );
+}
+
+/// Puts a transaction that failed permanently on cooldown, doubling the cooldown with each consecutive failure.
+/// Returns true if it has now failed with the same error often enough that it should be blacklisted.
+pub fn record_failure_cooldown(tx: &GaslessTransaction, error: String) -> bool {
+    let mut queue = RETRY_QUEUE.lock().unwrap();
+    let policy = queue.policy.clone();
+    let now = Instant::now();
+    queue
+        .failures
+        .retain(|_, f| now.saturating_duration_since(f.cooldown_until) < MAX_FAILURE_COOLDOWN);
+    let key = tx.sig_hash();
+    let count = match queue.failures.get(&key) {
+        Some(previous) if previous.last_error == error => previous.count + 1,
+        _ => 1,
+    };
+    if count >= policy.max_identical_failures {
+        queue.failures.remove(&key);
+        return true;
+    }
+    let cooldown = policy
+        .failure_cooldown
+        .saturating_mul(2u32.saturating_pow(count - 1))
+        .min(MAX_FAILURE_COOLDOWN);
+    debug!(
+        "Transaction {} failed {count} times in a row, cooling down for {}s",
+        tx.id(),
+        cooldown.as_secs()
+    );
+    queue.failures.insert(
+        key,
+        FailureRecord {
+            count,
+            last_error: error,
+            cooldown_until: now + cooldown,
+        },
+    );
+    false
+}
+
+/// True if this transaction failed permanently recently and should not be attempted yet
+pub fn is_cooling_down(tx: &GaslessTransaction) -> bool {
+    RETRY_QUEUE
+        .lock()
+        .unwrap()
+        .failures
+        .get(&tx.sig_hash())
+        .is_some_and(|f| f.cooldown_until > Instant::now())
+}
+
+/// Forgets the consecutive failures and the retry attempts of a transaction once it has been relayed
+pub fn clear_failures(tx: &GaslessTransaction) {
+    let mut queue = RETRY_QUEUE.lock().unwrap();
+    queue.failures.remove(&tx.sig_hash());
+    queue.attempts.remove(&tx.sig_hash());
+}
+
+/// Removes a transaction from the retry queue once it has been relayed, skipped or permanently failed, its attempts
+/// are still counted
+pub fn clear_retry(tx: &GaslessTransaction) {
+    RETRY_QUEUE.lock().unwrap().entries.remove(&tx.sig_hash());
+}
+
+/// True if this transaction is waiting out a backoff period and should not be attempted yet
+pub fn is_backing_off(tx: &GaslessTransaction) -> bool {
+    RETRY_QUEUE
+        .lock()
+        .unwrap()
+        .entries
+        .get(&tx.sig_hash())
+        .map(|e| e.next_attempt > Instant::now())
+        .unwrap_or(false)
+}
+
+/// Returns all entries whose backoff has elapsed
+pub fn due_retries() -> Vec<RetryEntry> {
+    let now = Instant::now();
+    RETRY_QUEUE
+        .lock()
+        .unwrap()
+        .entries
+        .values()
+        .filter(|e| e.next_attempt <= now)
+        .cloned()
+        .collect()
+}
+
+/// Every transaction currently waiting to be retried
+pub fn retry_queue() -> Vec<RetryEntry> {
+    RETRY_QUEUE
+        .lock()
+        .unwrap()
+        .entries
+        .values()
+        .cloned()
+        .collect()
+}
+
+/// Never attempts `tx` again, for transactions that are malformed and can't possibly be relayed
+pub fn blacklist(tx: &GaslessTransaction) {
+    clear_retry(tx);
+    BLACKLIST.lock().unwrap().insert(tx.sig_hash());
+}
+
+pub fn is_blacklisted(tx: &GaslessTransaction) -> bool {
+    BLACKLIST.lock().unwrap().contains(&tx.sig_hash())
+}
@@ -0,0 +1,128 @@
+//! Decoding of the revert data returned when a call fails, turning `Error(string)`, `Panic(uint256)` and known
+//! custom errors into a readable reason instead of an opaque hex blob.
+
+use clarity::abi::{derive_method_id, parse_address, parse_u64, parse_uint256};
+use web30::jsonrpc::error::Web3Error;
+
+/// Selector of `Error(string)`, raised by `require` and `revert` with a message
+const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+/// Selector of `Panic(uint256)`, raised by failed assertions and arithmetic errors
+const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+
+/// Custom errors that may bubble up out of the dex, it uses `require` messages itself but the tokens it
+/// moves commonly revert with these
+const CUSTOM_ERRORS: &[&str] = &[
+    "ERC20InsufficientBalance(address,uint256,uint256)",
+    "ERC20InsufficientAllowance(address,uint256,uint256)",
+    "ERC20InvalidSender(address)",
+    "ERC20InvalidReceiver(address)",
+    "ERC20InvalidApprover(address)",
+    "ERC20InvalidSpender(address)",
+    "SafeERC20FailedOperation(address)",
+    "AddressEmptyCode(address)",
+    "FailedCall()",
+];
+
+/// Parts of revert reasons, lowercased, that are the user's doing rather than the market's: a signature that doesn't
+/// verify, a balance or allowance the user doesn't have, or input the contracts refuse. Price movement and fills by
+/// others make relays revert too, but those aren't held against the user.
+const USER_FAULTS: &[&str] = &[
+    "signature",
+    "ecdsa",
+    "insufficient",
+    "exceeds balance",
+    "allowance",
+    "erc20invalid",
+    "malformed",
+];
+
+/// True if a relay reverting with `reason` is the user's fault
+pub fn is_user_fault(reason: &str) -> bool {
+    let reason = reason.to_lowercase();
+    USER_FAULTS.iter().any(|fault| reason.contains(fault))
+}
+
+/// Decodes raw revert data, returns `None` if it is empty
+pub fn decode_revert(data: &[u8]) -> Option<String> {
+    let (selector, args) = data.split_at_checked(4)?;
+    if selector == ERROR_SELECTOR {
+        return Some(match decode_string(args) {
+            Some(message) => message,
+            None => format!("malformed Error(string) 0x{}", hex::encode(args)),
+        });
+    }
+    if selector == PANIC_SELECTOR {
+        return Some(match parse_u64(args, 0) {
+            Ok(code) => format!("panic 0x{code:02x} ({})", panic_reason(code)),
+            Err(_) => format!("malformed Panic(uint256) 0x{}", hex::encode(args)),
+        });
+    }
+    for signature in CUSTOM_ERRORS {
+        if derive_method_id(signature).is_ok_and(|id| id == selector) {
+            return Some(format_custom_error(signature, args));
+        }
+    }
+    Some(format!(
+        "unknown error 0x{} with data 0x{}",
+        hex::encode(selector),
+        hex::encode(args)
+    ))
+}
+
+/// Extracts and decodes the revert data carried by a JSON-RPC error, if there is any
+pub fn revert_reason(error: &Web3Error) -> Option<String> {
+    let Web3Error::JsonRpcError { data, .. } = error else {
+        return None;
+    };
+    // web30 keeps the data as the debug print of the JSON value, such as `Some(String("0x08c379a0..."))`
+    let start = data.find("0x")? + 2;
+    let data = &data[start..];
+    let end = data
+        .find(|c: char| !c.is_ascii_hexdigit())
+        .unwrap_or(data.len());
+    decode_revert(&hex::decode(&data[..end]).ok()?)
+}
+
+fn decode_string(args: &[u8]) -> Option<String> {
+    let offset = parse_u64(args, 0).ok()? as usize;
+    let len = parse_u64(args, offset).ok()? as usize;
+    let start = offset.checked_add(32)?;
+    let bytes = args.get(start..start.checked_add(len)?)?;
+    Some(String::from_utf8_lossy(bytes).into_owned())
+}
+
+fn panic_reason(code: u64) -> &'static str {
+    match code {
+        0x01 => "assertion failed",
+        0x11 => "arithmetic overflow or underflow",
+        0x12 => "division or modulo by zero",
+        0x21 => "invalid enum value",
+        0x22 => "invalid storage byte array",
+        0x31 => "pop on empty array",
+        0x32 => "array index out of bounds",
+        0x41 => "out of memory",
+        0x51 => "call to uninitialized function",
+        _ => "unknown panic",
+    }
+}
+
+/// Formats a custom error with its static arguments decoded, `signature` only uses address and uint256 arguments
+fn format_custom_error(signature: &str, args: &[u8]) -> String {
+    let (name, types) = signature
+        .trim_end_matches(')')
+        .split_once('(')
+        .unwrap_or((signature, ""));
+    let values: Vec<String> = types
+        .split(',')
+        .filter(|ty| !ty.is_empty())
+        .enumerate()
+        .map(|(idx, ty)| {
+            let result = match ty {
+                "address" => parse_address(args, idx * 32).map(|a| a.to_string()),
+                _ => parse_uint256(args, idx * 32).map(|v| v.to_string()),
+            };
+            result.unwrap_or_else(|_| "?".to_string())
+        })
+        .collect();
+    format!("{name}({})", values.join(", "))
+}
@@ -0,0 +1,240 @@
+//! Pool of RPC endpoints for a single chain. Every endpoint is probed each loop to track its latency,
+//! error rate and head block, the healthiest endpoint is used for relaying and endpoints that fall too far
+//! behind the best known head are quarantined, since estimating gas against a stale node produces bogus results.
+
+use crate::error::RelayerError;
+use crate::proxy::rpc_url;
+use awc::http::Uri;
+use log::{info, warn};
+use num_traits::ToPrimitive;
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+use std::time::{Duration, Instant};
+use web30::client::Web3;
+
+/// Weight given to the newest sample in the latency and error rate moving averages
+const EWMA_WEIGHT: f64 = 0.2;
+/// An endpoint only replaces a healthy active endpoint if its score is better by this factor, avoids
+/// flapping between endpoints with near identical scores
+const SWITCH_THRESHOLD: f64 = 0.8;
+
+/// Health of one endpoint as exposed on the status endpoint
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EndpointHealth {
+    pub url: String,
+    /// moving average of probe latency in milliseconds
+    pub latency_ms: Option<f64>,
+    /// moving average of the fraction of requests that failed
+    pub error_rate: f64,
+    pub head: Option<u64>,
+    pub quarantined: bool,
+    pub active: bool,
+}
+
+#[derive(Debug, Default)]
+struct EndpointState {
+    latency_ms: Option<f64>,
+    error_rate: f64,
+    head: Option<u64>,
+    /// when `head` last changed
+    head_changed_at: Option<Instant>,
+    quarantined: bool,
+}
+
+impl EndpointState {
+    fn record_outcome(&mut self, failed: bool) {
+        let sample = if failed { 1.0 } else { 0.0 };
+        self.error_rate = self.error_rate * (1.0 - EWMA_WEIGHT) + sample * EWMA_WEIGHT;
+    }
+
+    fn record_latency(&mut self, latency: Duration) {
+        let sample = latency.as_secs_f64() * 1000.0;
+        self.latency_ms = Some(match self.latency_ms {
+            Some(avg) => avg * (1.0 - EWMA_WEIGHT) + sample * EWMA_WEIGHT,
+            None => sample,
+        });
+    }
+
+    /// Lower is better, latency penalized heavily by errors. Endpoints that have never answered score worst.
+    fn score(&self) -> f64 {
+        match self.latency_ms {
+            Some(latency) => latency.max(1.0) * (1.0 + 10.0 * self.error_rate),
+            None => f64::MAX,
+        }
+    }
+}
+
+pub struct RpcPool {
+    urls: Vec<String>,
+    clients: Vec<Web3>,
+    endpoints: RefCell<Vec<EndpointState>>,
+    active: Cell<usize>,
+    /// an endpoint whose head has not advanced for this long is quarantined
+    stale_after: Duration,
+    /// an endpoint whose head is more than this many blocks behind the best known head is quarantined
+    max_lag: u64,
+}
+
+impl RpcPool {
+    pub fn new(urls: &[String], timeout: Duration, stale_after: Duration, max_lag: u64) -> RpcPool {
+        assert!(!urls.is_empty(), "At least one RPC endpoint is required");
+        RpcPool {
+            urls: urls.to_vec(),
+            clients: urls
+                .iter()
+                .map(|url| Web3::new(&rpc_url(url), timeout))
+                .collect(),
+            endpoints: RefCell::new(urls.iter().map(|_| EndpointState::default()).collect()),
+            active: Cell::new(0),
+            stale_after,
+            max_lag,
+        }
+    }
+
+    /// Client for the currently active endpoint
+    pub fn web3(&self) -> &Web3 {
+        &self.clients[self.active.get()]
+    }
+
+    pub fn active_url(&self) -> &str {
+        &self.urls[self.active.get()]
+    }
+
+    pub fn urls(&self) -> &[String] {
+        &self.urls
+    }
+
+    /// Records a failed request against the active endpoint if `error` indicates the node itself is failing
+    /// rather than the request being rejected, quarantining it and switching to the healthiest alternative
+    pub fn report_error(&self, error: &RelayerError) {
+        if !error.is_endpoint_failure() {
+            return;
+        }
+        {
+            let mut endpoints = self.endpoints.borrow_mut();
+            let endpoint = &mut endpoints[self.active.get()];
+            endpoint.record_outcome(true);
+            endpoint.quarantined = true;
+        }
+        warn!(
+            "RPC endpoint {} failed: {error}",
+            redact_url(self.active_url())
+        );
+        self.select();
+    }
+
+    /// Probes every endpoint for its head block, updates health scores and quarantine status and
+    /// switches to the healthiest endpoint. Returns the head of the endpoint now active.
+    pub async fn check_head(&self) -> Option<u64> {
+        let mut answered = vec![false; self.clients.len()];
+        for (idx, client) in self.clients.iter().enumerate() {
+            let start = Instant::now();
+            let result = client.eth_block_number().await;
+            let latency = start.elapsed();
+            let mut endpoints = self.endpoints.borrow_mut();
+            let endpoint = &mut endpoints[idx];
+            match result.ok().and_then(|block| block.to_u64()) {
+                Some(block) => {
+                    answered[idx] = true;
+                    endpoint.record_outcome(false);
+                    endpoint.record_latency(latency);
+                    if endpoint.head != Some(block) {
+                        endpoint.head = Some(block);
+                        endpoint.head_changed_at = Some(Instant::now());
+                    }
+                }
+                None => {
+                    endpoint.record_outcome(true);
+                    endpoint.quarantined = true;
+                }
+            }
+        }
+
+        {
+            let mut endpoints = self.endpoints.borrow_mut();
+            let best = endpoints.iter().filter_map(|e| e.head).max();
+            for (idx, endpoint) in endpoints.iter_mut().enumerate() {
+                // endpoints that failed to answer stay quarantined until they do
+                let (true, Some(head), Some(best), Some(changed)) =
+                    (answered[idx], endpoint.head, best, endpoint.head_changed_at)
+                else {
+                    continue;
+                };
+                let lagging = best.saturating_sub(head) > self.max_lag;
+                let stuck = changed.elapsed() > self.stale_after;
+                let quarantined = lagging || stuck;
+                if quarantined && !endpoint.quarantined {
+                    warn!(
+                        "Quarantining RPC endpoint {} at block {head}, best known block is {best}",
+                        redact_url(&self.urls[idx])
+                    );
+                } else if !quarantined && endpoint.quarantined {
+                    info!("RPC endpoint {} has recovered", redact_url(&self.urls[idx]));
+                }
+                endpoint.quarantined = quarantined;
+            }
+        }
+
+        self.select();
+        self.endpoints.borrow()[self.active.get()].head
+    }
+
+    /// Switches to the healthiest endpoint that is not quarantined, or the best scoring endpoint if every
+    /// endpoint is quarantined
+    fn select(&self) {
+        let endpoints = self.endpoints.borrow();
+        let active = self.active.get();
+        let candidates = || endpoints.iter().enumerate();
+        let best = candidates()
+            .filter(|(_, e)| !e.quarantined)
+            .min_by(|(_, a), (_, b)| a.score().total_cmp(&b.score()))
+            .or_else(|| candidates().min_by(|(_, a), (_, b)| a.score().total_cmp(&b.score())))
+            .map(|(idx, _)| idx)
+            .unwrap_or(active);
+        if best == active {
+            return;
+        }
+        let current = &endpoints[active];
+        let switch =
+            current.quarantined || endpoints[best].score() < current.score() * SWITCH_THRESHOLD;
+        if switch {
+            info!(
+                "Switching RPC endpoint from {} to {}",
+                redact_url(&self.urls[active]),
+                redact_url(&self.urls[best])
+            );
+            self.active.set(best);
+        }
+    }
+
+    /// Current health of every endpoint in the pool
+    pub fn health(&self) -> Vec<EndpointHealth> {
+        self.endpoints
+            .borrow()
+            .iter()
+            .enumerate()
+            .map(|(idx, e)| EndpointHealth {
+                url: self.urls[idx].clone(),
+                latency_ms: e.latency_ms,
+                error_rate: e.error_rate,
+                head: e.head,
+                quarantined: e.quarantined,
+                active: idx == self.active.get(),
+            })
+            .collect()
+    }
+}
+
+/// `url` as scheme://host, with the port if it has one, for logs and error reports. Credentials, paths and query
+/// strings are left out since providers put API keys in them.
+pub fn redact_url(url: &str) -> String {
+    let Ok(uri) = url.parse::<Uri>() else {
+        return "<invalid url>".to_string();
+    };
+    let host = uri.host().unwrap_or_default();
+    match (uri.scheme_str(), uri.port_u16()) {
+        (Some(scheme), Some(port)) => format!("{scheme}://{host}:{port}"),
+        (Some(scheme), None) => format!("{scheme}://{host}"),
+        (None, _) => host.to_string(),
+    }
+}
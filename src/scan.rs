@@ -0,0 +1,233 @@
+//! Historical scan of relays sent to the dex, for understanding the competition. Blocks in a range are walked for
+//! direct calls to the dex's relay function, each attributed to the account that sent it, and per account the
+//! tips captured by relays that succeeded are set against the gas spent by every relay, reverted ones included.
+//! Tips are also broken down by the user who signed each command, recovered from its signature. Relays sent
+//! through a multicall are not picked up.
+
+use crate::layout::ContractLayout;
+use crate::orchestrator::GaslessTransaction;
+use clarity::abi::derive_method_id;
+use clarity::{Address, Uint256};
+use futures::stream::{self, StreamExt};
+use log::{info, warn};
+use num_traits::ToPrimitive;
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+use web30::jsonrpc::client::HttpClient;
+use web30::jsonrpc::error::Web3Error;
+
+/// Blocks fetched at once
+const CONCURRENT_BLOCKS: usize = 8;
+
+/// How often progress is logged, in blocks
+const PROGRESS_INTERVAL: u64 = 1000;
+
+/// Relays sent by one account over the scanned blocks
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RelayerStats {
+    pub relayer: Address,
+    pub relays: u64,
+    pub reverted: u64,
+    /// in wei, by every relay including reverted ones
+    pub gas_spent: Uint256,
+    /// amount captured by successful relays, by tip token
+    pub tips: BTreeMap<Address, Uint256>,
+    /// the same tips by the user who signed the command, then by tip token
+    pub tips_by_user: BTreeMap<Address, BTreeMap<Address, Uint256>>,
+}
+
+impl RelayerStats {
+    /// Tips paid in the gas token less the gas spent, negative if the gas cost more
+    fn native_net(&self) -> String {
+        let native = self
+            .tips
+            .get(&Address::default())
+            .copied()
+            .unwrap_or_default();
+        if native >= self.gas_spent {
+            (native - self.gas_spent).to_string()
+        } else {
+            format!("-{}", self.gas_spent - native)
+        }
+    }
+}
+
+impl fmt::Display for RelayerStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} relays {} reverted {} gas spent {} native net {}",
+            self.relayer,
+            self.relays,
+            self.reverted,
+            self.gas_spent,
+            self.native_net()
+        )?;
+        for (token, amount) in &self.tips {
+            write!(f, "\n    tips {amount} of {token}")?;
+        }
+        for (user, tips) in &self.tips_by_user {
+            for (token, amount) in tips {
+                write!(f, "\n    from {user} tips {amount} of {token}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `index`th argument of `data`, a call with dynamic `bytes` arguments, or `None` if it is malformed
+fn bytes_arg(data: &[u8], index: usize) -> Option<Vec<u8>> {
+    let args = data.get(4..)?;
+    let word = |at: usize| -> Option<usize> {
+        let word = args.get(at..at.checked_add(32)?)?;
+        if word[..24].iter().any(|b| *b != 0) {
+            return None;
+        }
+        Some(u64::from_be_bytes(word[24..].try_into().ok()?) as usize)
+    };
+    let offset = word(32 * index)?;
+    let start = offset.checked_add(32)?;
+    let end = start.checked_add(word(offset)?)?;
+    args.get(start..end).map(<[u8]>::to_vec)
+}
+
+/// The gasless transaction relayed by `data`, a call to the relay function on `chain_id`, or `None` if it is malformed
+fn relayed_transaction(data: &[u8], chain_id: u64) -> Option<GaslessTransaction> {
+    let callpath = data.get(4 + 30..4 + 32)?;
+    Some(GaslessTransaction {
+        chain_id,
+        callpath: u16::from_be_bytes(callpath.try_into().ok()?),
+        cmd: bytes_arg(data, 1)?,
+        conds: bytes_arg(data, 2)?,
+        tip: bytes_arg(data, 3)?,
+        sig: bytes_arg(data, 4)?,
+        submitted_at: 0,
+        target: None,
+    })
+}
+
+fn hex_field(value: &Value) -> Option<Vec<u8>> {
+    let hex = value.as_str()?;
+    hex::decode(hex.strip_prefix("0x").unwrap_or(hex)).ok()
+}
+
+fn uint_field(value: &Value) -> Uint256 {
+    value
+        .as_str()
+        .and_then(|v| Uint256::from_str(v).ok())
+        .unwrap_or_default()
+}
+
+/// Scans blocks `from` to `to` inclusive on the RPC at `url` for relays to `dex`, returning the stats of every
+/// account that relayed, most active first
+pub async fn scan_relays(
+    url: &str,
+    timeout: Duration,
+    dex: Address,
+    layout: &ContractLayout,
+    from: u64,
+    to: u64,
+) -> Result<Vec<RelayerStats>, Web3Error> {
+    let client = HttpClient::new(url);
+    let selector = derive_method_id(&layout.relay_function).expect("Invalid relay function");
+    let chain_id: Uint256 = client
+        .request_method("eth_chainId", Vec::<String>::new(), timeout)
+        .await?;
+    let chain_id = chain_id
+        .to_u64()
+        .ok_or_else(|| Web3Error::BadResponse(format!("Invalid chain id {chain_id}")))?;
+    let mut stats: HashMap<Address, RelayerStats> = HashMap::new();
+    let mut blocks = stream::iter(from..=to)
+        .map(|number| {
+            let client = &client;
+            async move {
+                let block: Value = client
+                    .request_method(
+                        "eth_getBlockByNumber",
+                        vec![json!(format!("{number:#x}")), json!(true)],
+                        timeout,
+                    )
+                    .await?;
+                Ok::<_, Web3Error>((number, block))
+            }
+        })
+        .buffered(CONCURRENT_BLOCKS);
+    while let Some(result) = blocks.next().await {
+        let (number, block) = result?;
+        if number > from && (number - from).is_multiple_of(PROGRESS_INTERVAL) {
+            info!("Scanned up to block {number} of {to}");
+        }
+        let Some(txs) = block["transactions"].as_array() else {
+            continue;
+        };
+        for tx in txs {
+            let to_dex = tx["to"]
+                .as_str()
+                .and_then(|to| Address::from_str(to).ok())
+                .is_some_and(|to| to == dex);
+            let Some(input) = hex_field(&tx["input"]) else {
+                continue;
+            };
+            let Some(relayer) = tx["from"].as_str().and_then(|a| Address::from_str(a).ok()) else {
+                continue;
+            };
+            if !to_dex || !input.starts_with(&selector) {
+                continue;
+            }
+            let receipt: Value = client
+                .request_method(
+                    "eth_getTransactionReceipt",
+                    vec![tx["hash"].clone()],
+                    timeout,
+                )
+                .await?;
+            if receipt.is_null() {
+                warn!("No receipt for relay {}, skipping it", tx["hash"]);
+                continue;
+            }
+            let entry = stats.entry(relayer).or_insert_with(|| RelayerStats {
+                relayer,
+                ..Default::default()
+            });
+            entry.relays += 1;
+            entry.gas_spent +=
+                uint_field(&receipt["gasUsed"]) * uint_field(&receipt["effectiveGasPrice"]);
+            if receipt["status"].as_str() != Some("0x1") {
+                entry.reverted += 1;
+                continue;
+            }
+            let Some(relayed) = relayed_transaction(&input, chain_id) else {
+                warn!("Failed to decode relay {}", tx["hash"]);
+                continue;
+            };
+            let tip = match layout.decode_tip(&relayed.tip) {
+                Ok(tip) => tip,
+                Err(_) => {
+                    warn!("Failed to decode the tip of relay {}", tx["hash"]);
+                    continue;
+                }
+            };
+            *entry.tips.entry(tip.token).or_default() += tip.amount.into();
+            // the relayer only captures the tip, the user who signed the command is the one paying it
+            match relayed.client(dex) {
+                Ok(user) => {
+                    *entry
+                        .tips_by_user
+                        .entry(user)
+                        .or_default()
+                        .entry(tip.token)
+                        .or_default() += tip.amount.into()
+                }
+                Err(e) => warn!("Failed to recover the user of relay {}: {e}", tx["hash"]),
+            }
+        }
+    }
+    let mut stats: Vec<RelayerStats> = stats.into_values().collect();
+    stats.sort_by_key(|s| Reverse(s.relays));
+    Ok(stats)
+}
@@ -0,0 +1,231 @@
+//! Running unattended on hosts without systemd. On Unix init systems such as OpenRC, runit or BSD rc supervise
+//! the relayer in the foreground and find it through a PID file. On Windows the relayer runs under the service
+//! control manager, which starts it, stops it on request or at system shutdown and restarts it on failure, the
+//! same as systemd would.
+
+use log::warn;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Name the relayer registers its control handler under, the service control manager doesn't check it for a
+/// service that has its process to itself so the relayer can be installed under any name
+pub const SERVICE_NAME: &str = "althea-l1-relayer";
+
+/// PID file written while the relayer runs, removed when it exits
+pub struct PidFile(PathBuf);
+
+impl PidFile {
+    /// Writes the PID of this process to `path`. A PID file left behind by a relayer that didn't exit cleanly is
+    /// replaced, one naming a process that is still running is refused so two relayers don't run from it.
+    pub fn create(path: &Path) -> Result<Self, String> {
+        if let Ok(contents) = std::fs::read_to_string(path)
+            && let Ok(pid) = contents.trim().parse::<u32>()
+            && pid != std::process::id()
+            && is_running(pid)
+        {
+            return Err(format!(
+                "PID file {} names process {pid}, which is still running",
+                path.display()
+            ));
+        }
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .map_err(|e| format!("Failed to write PID file {}: {e}", path.display()))?;
+        writeln!(file, "{}", std::process::id())
+            .map_err(|e| format!("Failed to write PID file {}: {e}", path.display()))?;
+        Ok(PidFile(path.to_path_buf()))
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.0) {
+            warn!("Failed to remove PID file {}: {e}", self.0.display());
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // signal 0 only checks that the process exists, a process owned by another user can't be signalled but
+    // still exists
+    let result = unsafe { libc::kill(pid, 0) };
+    result == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn is_running(_pid: u32) -> bool {
+    false
+}
+
+/// Log file a service writes to when none is given, next to the executable since a Windows service starts in
+/// the system directory and its standard output is discarded
+pub fn default_log_file() -> Option<PathBuf> {
+    if cfg!(windows) {
+        std::env::current_exe()
+            .ok()
+            .map(|exe| exe.with_file_name(format!("{SERVICE_NAME}.log")))
+    } else {
+        None
+    }
+}
+
+/// Connects to the service control manager, which then stops the relayer through the same shutdown as a
+/// signal. Fails if the relayer wasn't started as a service.
+#[cfg(windows)]
+pub fn start_service() -> Result<(), String> {
+    windows::start()
+}
+
+/// Runs in the foreground under whatever supervises it, there is nothing to connect to
+#[cfg(not(windows))]
+pub fn start_service() -> Result<(), String> {
+    Ok(())
+}
+
+/// Tells the service control manager the relayer has stopped, the last thing it does before exiting
+#[cfg(windows)]
+pub fn report_service_stopped() {
+    windows::report_stopped();
+}
+
+#[cfg(not(windows))]
+pub fn report_service_stopped() {}
+
+#[cfg(windows)]
+mod windows {
+    use super::SERVICE_NAME;
+    use crate::shutdown::request_shutdown;
+    use log::info;
+    use std::ffi::c_void;
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc::{Sender, channel};
+    use std::time::Duration;
+    use windows_sys::Win32::System::Services::{
+        RegisterServiceCtrlHandlerExW, SERVICE_ACCEPT_SHUTDOWN, SERVICE_ACCEPT_STOP,
+        SERVICE_CONTROL_INTERROGATE, SERVICE_CONTROL_SHUTDOWN, SERVICE_CONTROL_STOP,
+        SERVICE_RUNNING, SERVICE_STATUS, SERVICE_STATUS_HANDLE, SERVICE_STOP_PENDING,
+        SERVICE_STOPPED, SERVICE_TABLE_ENTRYW, SERVICE_WIN32_OWN_PROCESS, SetServiceStatus,
+        StartServiceCtrlDispatcherW,
+    };
+    use windows_sys::core::PWSTR;
+
+    /// How long the service control manager is asked to wait for in-flight work to finish once stopped
+    const STOP_WAIT_HINT: Duration = Duration::from_secs(30);
+
+    const ERROR_CALL_NOT_IMPLEMENTED: u32 = 120;
+
+    /// Handle statuses are reported through, zero until the control handler is registered
+    static STATUS_HANDLE: AtomicUsize = AtomicUsize::new(0);
+
+    /// Tells `start` whether the service control manager took the relayer on
+    static STARTED: Mutex<Option<Sender<Result<(), String>>>> = Mutex::new(None);
+
+    fn service_name() -> Vec<u16> {
+        SERVICE_NAME.encode_utf16().chain([0]).collect()
+    }
+
+    pub fn start() -> Result<(), String> {
+        let (sender, receiver) = channel();
+        *STARTED.lock().unwrap() = Some(sender.clone());
+        // the dispatcher blocks for as long as the service runs, the relayer itself keeps the main thread
+        std::thread::spawn(move || {
+            let mut name = service_name();
+            let table = [
+                SERVICE_TABLE_ENTRYW {
+                    lpServiceName: name.as_mut_ptr(),
+                    lpServiceProc: Some(service_main),
+                },
+                SERVICE_TABLE_ENTRYW {
+                    lpServiceName: std::ptr::null_mut(),
+                    lpServiceProc: None,
+                },
+            ];
+            if unsafe { StartServiceCtrlDispatcherW(table.as_ptr()) } == 0 {
+                let _ = sender.send(Err(format!(
+                    "Not started by the service control manager: {}",
+                    std::io::Error::last_os_error()
+                )));
+            }
+        });
+        receiver
+            .recv_timeout(Duration::from_secs(30))
+            .map_err(|_| "Timed out connecting to the service control manager".to_string())?
+    }
+
+    unsafe extern "system" fn service_main(_argc: u32, _argv: *mut PWSTR) {
+        let name = service_name();
+        let handle = unsafe {
+            RegisterServiceCtrlHandlerExW(name.as_ptr(), Some(control_handler), std::ptr::null())
+        };
+        let result = if handle as usize == 0 {
+            Err(format!(
+                "Failed to register the service control handler: {}",
+                std::io::Error::last_os_error()
+            ))
+        } else {
+            STATUS_HANDLE.store(handle as usize, Ordering::Relaxed);
+            set_status(SERVICE_RUNNING);
+            info!("Running as the {SERVICE_NAME} service");
+            Ok(())
+        };
+        if let Some(sender) = STARTED.lock().unwrap().take() {
+            let _ = sender.send(result);
+        }
+    }
+
+    unsafe extern "system" fn control_handler(
+        control: u32,
+        _event_type: u32,
+        _event_data: *mut c_void,
+        _context: *mut c_void,
+    ) -> u32 {
+        match control {
+            SERVICE_CONTROL_STOP | SERVICE_CONTROL_SHUTDOWN => {
+                info!("Service stop requested, finishing in-flight work");
+                set_status(SERVICE_STOP_PENDING);
+                request_shutdown();
+                0
+            }
+            SERVICE_CONTROL_INTERROGATE => 0,
+            _ => ERROR_CALL_NOT_IMPLEMENTED,
+        }
+    }
+
+    pub fn report_stopped() {
+        set_status(SERVICE_STOPPED);
+    }
+
+    fn set_status(state: u32) {
+        let handle = STATUS_HANDLE.load(Ordering::Relaxed);
+        if handle == 0 {
+            return;
+        }
+        let status = SERVICE_STATUS {
+            dwServiceType: SERVICE_WIN32_OWN_PROCESS,
+            dwCurrentState: state,
+            dwControlsAccepted: if state == SERVICE_RUNNING {
+                SERVICE_ACCEPT_STOP | SERVICE_ACCEPT_SHUTDOWN
+            } else {
+                0
+            },
+            dwWin32ExitCode: 0,
+            dwServiceSpecificExitCode: 0,
+            dwCheckPoint: 0,
+            dwWaitHint: if state == SERVICE_STOP_PENDING {
+                STOP_WAIT_HINT.as_millis() as u32
+            } else {
+                0
+            },
+        };
+        unsafe { SetServiceStatus(handle as SERVICE_STATUS_HANDLE, &status) };
+    }
+}
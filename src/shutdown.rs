@@ -0,0 +1,63 @@
+//! Shutdown coordination, signal handlers flip a process wide flag that the relay loop checks between units of
+//! work so that in-flight submissions can finish before the process exits.
+
+use crate::systemd::notify_stopping;
+use log::{info, warn};
+use std::sync::LazyLock;
+use std::time::Duration;
+use tokio::sync::watch;
+
+static SHUTDOWN: LazyLock<watch::Sender<bool>> = LazyLock::new(|| watch::channel(false).0);
+
+/// Requests that the relayer stop accepting new work and exit
+pub fn request_shutdown() {
+    SHUTDOWN.send_replace(true);
+}
+
+pub fn is_shutting_down() -> bool {
+    *SHUTDOWN.borrow()
+}
+
+/// Resolves once shutdown has been requested
+pub async fn wait_for_shutdown() {
+    let mut receiver = SHUTDOWN.subscribe();
+    // the sender is static and never dropped so this can't error
+    let _ = receiver.wait_for(|shutdown| *shutdown).await;
+}
+
+/// Sleeps for `duration` or until shutdown is requested, returns true if woken by shutdown
+pub async fn sleep_or_shutdown(duration: Duration) -> bool {
+    tokio::select! {
+        _ = actix_rt::time::sleep(duration) => false,
+        _ = wait_for_shutdown() => true,
+    }
+}
+
+/// Spawns a task that requests shutdown on SIGINT or SIGTERM, a second signal exits immediately
+pub fn listen_for_signals() {
+    actix_rt::spawn(async {
+        wait_for_signal().await;
+        info!("Shutdown signal received, finishing in-flight work");
+        request_shutdown();
+        notify_stopping();
+        wait_for_signal().await;
+        warn!("Second shutdown signal received, exiting immediately");
+        std::process::exit(1);
+    });
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+    use actix_rt::signal::unix::{SignalKind, signal};
+    let mut terminate =
+        signal(SignalKind::terminate()).expect("Failed to register SIGTERM handler");
+    tokio::select! {
+        _ = actix_rt::signal::ctrl_c() => {},
+        _ = terminate.recv() => {},
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    let _ = actix_rt::signal::ctrl_c().await;
+}
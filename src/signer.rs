@@ -0,0 +1,186 @@
+//! Signing abstraction so the relayer can either hold its key in process or delegate signing to a remote
+//! signing service speaking the `eth_signTransaction` / `eth_sign` JSON-RPC methods, such as web3signer or
+//! Clef in front of an HSM.
+
+use crate::error::RelayerError;
+use crate::key::LocalKey;
+use clarity::{Address, Signature, Transaction};
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::time::Duration;
+use web30::jsonrpc::client::HttpClient;
+use web30::jsonrpc::error::Web3Error;
+use web30::types::{Data, UnpaddedHex};
+
+/// A signing service holding the key for `address`
+#[derive(Debug, Clone)]
+pub struct RemoteSigner {
+    pub url: String,
+    pub address: Address,
+    pub timeout: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub enum Signer {
+    Local(LocalKey),
+    Remote(RemoteSigner),
+    /// An address we hold no key for, transactions are only ever simulated from it
+    Watch(Address),
+}
+
+/// Transaction object accepted by `eth_signTransaction`
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SignTransactionRequest {
+    from: Address,
+    to: Address,
+    nonce: UnpaddedHex,
+    gas: UnpaddedHex,
+    max_fee_per_gas: UnpaddedHex,
+    max_priority_fee_per_gas: UnpaddedHex,
+    value: UnpaddedHex,
+    data: Data,
+    chain_id: UnpaddedHex,
+}
+
+/// web3signer returns the raw signed transaction directly while Clef wraps it in an object
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum SignTransactionResponse {
+    Raw(String),
+    Wrapped { raw: String },
+}
+
+impl Signer {
+    pub fn address(&self) -> Address {
+        match self {
+            Signer::Local(key) => key.address(),
+            Signer::Remote(remote) => remote.address,
+            Signer::Watch(address) => *address,
+        }
+    }
+
+    /// Signs an unsigned EIP-1559 transaction, remote signatures are checked to actually come from our address
+    pub async fn sign_transaction(&self, tx: Transaction) -> Result<Transaction, RelayerError> {
+        let remote = match self {
+            Signer::Local(key) => return Ok(key.sign_transaction(tx)),
+            // left unsigned, it is never broadcast
+            Signer::Watch(_) => return Ok(tx),
+            Signer::Remote(remote) => remote,
+        };
+        let Transaction::Eip1559 {
+            chain_id,
+            nonce,
+            max_priority_fee_per_gas,
+            max_fee_per_gas,
+            gas_limit,
+            to,
+            value,
+            data,
+            ..
+        } = &tx
+        else {
+            return Err(RelayerError::signer(
+                "Remote signing is only supported for EIP-1559 transactions",
+            ));
+        };
+        let request = SignTransactionRequest {
+            from: remote.address,
+            to: *to,
+            nonce: (*nonce).into(),
+            gas: (*gas_limit).into(),
+            max_fee_per_gas: (*max_fee_per_gas).into(),
+            max_priority_fee_per_gas: (*max_priority_fee_per_gas).into(),
+            value: (*value).into(),
+            data: Data(data.clone()),
+            chain_id: (*chain_id).into(),
+        };
+        let response: SignTransactionResponse = HttpClient::new(&remote.url)
+            .request_method("eth_signTransaction", vec![request], remote.timeout)
+            .await
+            .map_err(signer_error)?;
+        let raw = match response {
+            SignTransactionResponse::Raw(raw) | SignTransactionResponse::Wrapped { raw } => raw,
+        };
+        let raw = hex::decode(raw.trim_start_matches("0x")).map_err(RelayerError::signer)?;
+        let signed = Transaction::decode_from_rlp(&raw).map_err(RelayerError::signer)?;
+        let sender = signed.sender().map_err(RelayerError::signer)?;
+        if sender != remote.address {
+            return Err(RelayerError::signer(format!(
+                "Remote signer returned a transaction signed by {sender} instead of {}",
+                remote.address
+            )));
+        }
+        Ok(signed)
+    }
+
+    /// Signs `data` as an Ethereum signed message
+    pub async fn sign_message(&self, data: &[u8]) -> Result<Signature, RelayerError> {
+        match self {
+            Signer::Local(key) => Ok(key.sign_message(data)),
+            Signer::Remote(remote) => {
+                let signature: String = HttpClient::new(&remote.url)
+                    .request_method(
+                        "eth_sign",
+                        vec![
+                            remote.address.to_string(),
+                            format!("0x{}", hex::encode(data)),
+                        ],
+                        remote.timeout,
+                    )
+                    .await
+                    .map_err(signer_error)?;
+                let signature = hex::decode(signature.trim_start_matches("0x"))
+                    .map_err(RelayerError::signer)?;
+                Signature::from_bytes(&signature).map_err(RelayerError::signer)
+            }
+            Signer::Watch(address) => Err(RelayerError::signer(format!(
+                "No key for watch-only address {address}"
+            ))),
+        }
+    }
+}
+
+/// Signer outages are worth retrying, wrapping them also keeps them from being mistaken for RPC node failures
+fn signer_error(e: Web3Error) -> RelayerError {
+    RelayerError::SignerTransient(e.to_string())
+}
+
+/// A set of funded relayer accounts, relays are spread across them round robin so that one stuck nonce
+/// or a per account RPC rate limit doesn't stall the whole relayer
+#[derive(Debug, Clone)]
+pub struct SignerPool {
+    signers: Vec<Signer>,
+    next: Cell<usize>,
+}
+
+impl SignerPool {
+    pub fn new(signers: Vec<Signer>) -> SignerPool {
+        assert!(!signers.is_empty(), "At least one relayer key is required");
+        SignerPool {
+            signers,
+            next: Cell::new(0),
+        }
+    }
+
+    pub fn signers(&self) -> &[Signer] {
+        &self.signers
+    }
+
+    /// The first configured signer, used where a single stable identity is needed such as orchestrator auth
+    pub fn primary(&self) -> &Signer {
+        &self.signers[0]
+    }
+
+    /// The signer for `address` if it is part of this pool
+    pub fn find(&self, address: Address) -> Option<&Signer> {
+        self.signers.iter().find(|s| s.address() == address)
+    }
+
+    /// The next signer in round robin order
+    pub fn next(&self) -> &Signer {
+        let idx = self.next.get() % self.signers.len();
+        self.next.set(idx + 1);
+        &self.signers[idx]
+    }
+}
@@ -0,0 +1,156 @@
+//! Why a transaction wasn't relayed. Every transaction the relayer passes over is given one of these reasons,
+//! which is logged as `skip_reason`, counted in `/metrics` and `/status` and written to the history, so skips can
+//! be aggregated instead of picked out of log messages.
+
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    /// for a chain the relayer isn't configured for
+    UnknownChain,
+    /// failed before in a way it can never succeed
+    Blacklisted,
+    /// we already relayed it
+    AlreadyRelayed,
+    /// submitted to its orchestrator longer ago than `--max-tx-age-secs`
+    Stale,
+    /// another transaction for the same salt and nonce is evaluated instead
+    Conflict,
+    /// a command kind that isn't allowed
+    CommandNotAllowed,
+    /// expired or not yet valid
+    Expired,
+    /// expires sooner than relays are currently included
+    DeadlineUnreachable,
+    /// signed by a banned user
+    BannedUser,
+    /// its user's transactions failed simulation too often
+    SimulationBudgetExceeded,
+    /// its user is over the per user rate limit
+    RateLimited,
+    /// carries no tip
+    NoTip,
+    /// tips a receiver that isn't one of our accounts
+    InvalidReceiver,
+    /// tips in a token an `[[override]]` section doesn't allow
+    TokenNotAllowed,
+    /// its user doesn't hold, or hasn't approved the dex for, the tip or swap input
+    InsufficientFunds,
+    /// the tip doesn't cover the gas
+    Unprofitable,
+    /// unprofitable and the subsidy budget can't cover it
+    SubsidyExhausted,
+    /// would be relayed from an account another instance holds the lease on
+    Standby,
+    /// another instance holds its lease
+    LeasedElsewhere,
+    /// another relayer claimed it from the orchestrator
+    ClaimedElsewhere,
+    /// expected to earn too little to be evaluated while latency is over `--latency-slo-secs`
+    Deprioritized,
+    /// its chain's recent blocks are above `--congestion-pause-threshold`
+    Congested,
+    /// its gas estimate is above `--max-gas-limit`
+    TooLarge,
+    /// gas is above `--max-gas-price`
+    GasPriceTooHigh,
+    /// reverts when estimated or simulated
+    SimulationFailed,
+    /// tracing it showed effects it shouldn't have
+    TraceRejected,
+    /// someone else is relaying it
+    CompetingRelay,
+    /// someone else consumed its nonce while our relay was pending
+    NonceConsumed,
+    /// a field can't be decoded, or names something that can't be relayed such as an unknown relay target
+    DecodeError,
+}
+
+impl SkipReason {
+    pub const ALL: [SkipReason; 29] = [
+        SkipReason::UnknownChain,
+        SkipReason::Blacklisted,
+        SkipReason::AlreadyRelayed,
+        SkipReason::Stale,
+        SkipReason::Conflict,
+        SkipReason::CommandNotAllowed,
+        SkipReason::Expired,
+        SkipReason::DeadlineUnreachable,
+        SkipReason::BannedUser,
+        SkipReason::SimulationBudgetExceeded,
+        SkipReason::RateLimited,
+        SkipReason::NoTip,
+        SkipReason::InvalidReceiver,
+        SkipReason::TokenNotAllowed,
+        SkipReason::InsufficientFunds,
+        SkipReason::Unprofitable,
+        SkipReason::SubsidyExhausted,
+        SkipReason::Standby,
+        SkipReason::LeasedElsewhere,
+        SkipReason::ClaimedElsewhere,
+        SkipReason::Deprioritized,
+        SkipReason::Congested,
+        SkipReason::TooLarge,
+        SkipReason::GasPriceTooHigh,
+        SkipReason::SimulationFailed,
+        SkipReason::TraceRejected,
+        SkipReason::CompetingRelay,
+        SkipReason::NonceConsumed,
+        SkipReason::DecodeError,
+    ];
+
+    /// Name of the reason in logs, metric labels and the history
+    pub fn name(&self) -> &'static str {
+        match self {
+            SkipReason::UnknownChain => "unknown_chain",
+            SkipReason::Blacklisted => "blacklisted",
+            SkipReason::AlreadyRelayed => "already_relayed",
+            SkipReason::Stale => "stale",
+            SkipReason::Conflict => "conflict",
+            SkipReason::CommandNotAllowed => "command_not_allowed",
+            SkipReason::Expired => "expired",
+            SkipReason::DeadlineUnreachable => "deadline_unreachable",
+            SkipReason::BannedUser => "banned_user",
+            SkipReason::SimulationBudgetExceeded => "simulation_budget_exceeded",
+            SkipReason::RateLimited => "rate_limited",
+            SkipReason::NoTip => "no_tip",
+            SkipReason::InvalidReceiver => "invalid_receiver",
+            SkipReason::TokenNotAllowed => "token_not_allowed",
+            SkipReason::InsufficientFunds => "insufficient_funds",
+            SkipReason::Unprofitable => "unprofitable",
+            SkipReason::SubsidyExhausted => "subsidy_exhausted",
+            SkipReason::Standby => "standby",
+            SkipReason::LeasedElsewhere => "leased_elsewhere",
+            SkipReason::ClaimedElsewhere => "claimed_elsewhere",
+            SkipReason::Deprioritized => "deprioritized",
+            SkipReason::Congested => "congested",
+            SkipReason::TooLarge => "too_large",
+            SkipReason::GasPriceTooHigh => "gas_price_too_high",
+            SkipReason::SimulationFailed => "simulation_failed",
+            SkipReason::TraceRejected => "trace_rejected",
+            SkipReason::CompetingRelay => "competing_relay",
+            SkipReason::NonceConsumed => "nonce_consumed",
+            SkipReason::DecodeError => "decode_error",
+        }
+    }
+}
+
+impl Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for SkipReason {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        SkipReason::ALL
+            .into_iter()
+            .find(|reason| reason.name() == s)
+            .ok_or_else(|| format!("Unknown skip reason {s}"))
+    }
+}
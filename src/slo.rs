@@ -0,0 +1,112 @@
+//! End to end latency, the time from a transaction's `submitted_at` at its orchestrator to our relay of it being
+//! included in a block. Every inclusion is counted in a histogram on `/metrics`. With a latency SLO configured the
+//! 95th percentile of recent inclusions is checked against it each cycle, and while it is exceeded transactions
+//! expected to earn little are deferred so that the ones worth relaying are picked up sooner. Slow samples age out
+//! of the window, so deferring lasts only until relays are included in time again.
+
+use crate::orchestrator::GaslessTransaction;
+use crate::status::{record_inclusion_latency, record_latency_p95, unix_timestamp};
+use clarity::Uint256;
+use log::{info, warn};
+use std::collections::VecDeque;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+/// Most inclusions the percentile is taken over
+const WINDOW_SAMPLES: usize = 100;
+
+/// Inclusions older than this no longer count towards the percentile
+const MAX_SAMPLE_AGE: Duration = Duration::from_secs(30 * 60);
+
+/// Most relays remembered as sampled, so one that is included again after a reorg isn't sampled twice
+const MAX_SAMPLED: usize = 1000;
+
+/// Inclusions needed before the SLO can be considered exceeded, so a single slow relay doesn't trigger it
+const MIN_SAMPLES: usize = 5;
+
+#[derive(Debug, Clone, Copy)]
+pub struct LatencySlo {
+    /// 95th percentile end to end latency to stay under
+    pub target: Duration,
+    /// while the SLO is exceeded transactions expected to earn less than this many wei of the gas token are deferred
+    pub min_profit: Uint256,
+}
+
+#[derive(Debug, Default)]
+struct LatencyState {
+    slo: Option<LatencySlo>,
+    /// when each recent inclusion was seen and its latency in seconds, oldest first
+    samples: VecDeque<(Instant, u64)>,
+    /// signature hashes of the most recently sampled transactions, oldest first
+    sampled: VecDeque<[u8; 32]>,
+    exceeded: bool,
+}
+
+static STATE: LazyLock<Mutex<LatencyState>> = LazyLock::new(|| Mutex::new(LatencyState::default()));
+
+pub fn set_latency_slo(slo: Option<LatencySlo>) {
+    STATE.lock().unwrap().slo = slo;
+}
+
+/// Samples the end to end latency of `tx`, called when its relay is seen included. A relay included again after
+/// a reorg was already sampled the first time and is left out.
+pub fn record_inclusion(tx: &GaslessTransaction) {
+    // transactions relayed by hand or read from a file carry no submission time
+    if tx.submitted_at == 0 {
+        return;
+    }
+    let mut state = STATE.lock().unwrap();
+    let sig_hash = tx.sig_hash();
+    if state.sampled.contains(&sig_hash) {
+        return;
+    }
+    if state.sampled.len() >= MAX_SAMPLED {
+        state.sampled.pop_front();
+    }
+    state.sampled.push_back(sig_hash);
+    let latency = unix_timestamp().saturating_sub(tx.submitted_at);
+    record_inclusion_latency(latency);
+    if state.samples.len() >= WINDOW_SAMPLES {
+        state.samples.pop_front();
+    }
+    state.samples.push_back((Instant::now(), latency));
+}
+
+/// 95th percentile latency in seconds of the inclusions in the window, None with too few of them
+fn p95(samples: &VecDeque<(Instant, u64)>) -> Option<u64> {
+    if samples.len() < MIN_SAMPLES {
+        return None;
+    }
+    let mut latencies: Vec<u64> = samples.iter().map(|(_, latency)| *latency).collect();
+    latencies.sort_unstable();
+    let rank = (latencies.len() * 95).div_ceil(100);
+    latencies.get(rank.saturating_sub(1)).copied()
+}
+
+/// The SLO if it is currently exceeded, None if it is met or none is configured. Checked once per cycle, logging
+/// when it starts and stops being exceeded.
+pub fn exceeded_latency_slo() -> Option<LatencySlo> {
+    let mut state = STATE.lock().unwrap();
+    state
+        .samples
+        .retain(|(at, _)| at.elapsed() < MAX_SAMPLE_AGE);
+    let p95 = p95(&state.samples);
+    record_latency_p95(p95);
+    let slo = state.slo?;
+    let exceeded = p95.is_some_and(|p95| Duration::from_secs(p95) > slo.target);
+    if exceeded && !state.exceeded {
+        warn!(
+            "95th percentile latency of {}s is over the SLO of {}s, deferring transactions expected to earn less than {} wei",
+            p95.unwrap_or_default(),
+            slo.target.as_secs(),
+            slo.min_profit
+        );
+    } else if !exceeded && state.exceeded {
+        info!(
+            "Latency is back within the SLO of {}s, evaluating every transaction",
+            slo.target.as_secs()
+        );
+    }
+    state.exceeded = exceeded;
+    exceeded.then_some(slo)
+}
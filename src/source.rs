@@ -0,0 +1,158 @@
+//! Where pending gasless transactions come from. Orchestrators are the usual source, transactions can also be
+//! read from a local JSON file or from stdin, which is handy for testing and for handing transactions to the
+//! relayer through other channels. Embedders can add their own sources by implementing `TransactionSource`.
+
+use crate::error::RelayerError;
+use crate::orchestrator::{GaslessTransaction, Orchestrator, fetch_with_failover};
+use crate::signer::Signer;
+use futures::channel::mpsc::{UnboundedReceiver, unbounded};
+use futures::future::LocalBoxFuture;
+use log::{debug, info, warn};
+use std::cell::{Cell, RefCell};
+use std::io::BufRead;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+pub trait TransactionSource {
+    /// Identifies the source in logs and on the status endpoint
+    fn name(&self) -> String;
+
+    /// Transactions that are pending right now, `signer` is the relayer identity presented to the source
+    fn fetch_pending<'a>(
+        &'a self,
+        signer: &'a Signer,
+    ) -> LocalBoxFuture<'a, Result<Vec<GaslessTransaction>, RelayerError>>;
+
+    /// Orchestrator transactions from this source are claimed from and reported back to, if any
+    fn orchestrator(&self) -> Option<&Orchestrator> {
+        None
+    }
+}
+
+/// Polls the orchestrator, failing over between its addresses
+impl TransactionSource for Orchestrator {
+    fn name(&self) -> String {
+        self.url.clone()
+    }
+
+    fn fetch_pending<'a>(
+        &'a self,
+        signer: &'a Signer,
+    ) -> LocalBoxFuture<'a, Result<Vec<GaslessTransaction>, RelayerError>> {
+        Box::pin(fetch_with_failover(self, signer))
+    }
+
+    fn orchestrator(&self) -> Option<&Orchestrator> {
+        Some(self)
+    }
+}
+
+/// A JSON file holding one transaction or an array of them, in the format orchestrators serve. The file is
+/// read again whenever it is modified, so transactions can be handed over by rewriting it.
+pub struct FileSource {
+    path: PathBuf,
+    /// modification time of the contents last returned
+    modified: Cell<Option<SystemTime>>,
+}
+
+impl FileSource {
+    pub fn new(path: PathBuf) -> FileSource {
+        FileSource {
+            path,
+            modified: Cell::new(None),
+        }
+    }
+}
+
+impl TransactionSource for FileSource {
+    fn name(&self) -> String {
+        format!("file:{}", self.path.display())
+    }
+
+    fn fetch_pending<'a>(
+        &'a self,
+        _signer: &'a Signer,
+    ) -> LocalBoxFuture<'a, Result<Vec<GaslessTransaction>, RelayerError>> {
+        Box::pin(async move {
+            let error = |e: &dyn std::fmt::Display| {
+                RelayerError::OrchestratorError(format!("{}: {e}", self.path.display()))
+            };
+            let modified = std::fs::metadata(&self.path)
+                .and_then(|metadata| metadata.modified())
+                .map_err(|e| error(&e))?;
+            if self.modified.get() == Some(modified) {
+                return Ok(Vec::new());
+            }
+            let json = std::fs::read_to_string(&self.path).map_err(|e| error(&e))?;
+            let txs = match serde_json::from_str::<Vec<GaslessTransaction>>(&json) {
+                Ok(txs) => txs,
+                Err(_) => vec![serde_json::from_str(&json).map_err(|e| error(&e))?],
+            };
+            self.modified.set(Some(modified));
+            info!(
+                "Read {} transactions from {}",
+                txs.len(),
+                self.path.display()
+            );
+            Ok(txs)
+        })
+    }
+}
+
+/// Transactions written to stdin as JSON, one per line
+pub struct StdinSource {
+    receiver: RefCell<UnboundedReceiver<GaslessTransaction>>,
+}
+
+impl StdinSource {
+    /// Starts reading stdin on a background thread until it is closed
+    pub fn new() -> StdinSource {
+        let (sender, receiver) = unbounded();
+        std::thread::spawn(move || {
+            for line in std::io::stdin().lock().lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match serde_json::from_str(&line) {
+                    Ok(tx) => {
+                        if sender.unbounded_send(tx).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => warn!("Ignoring invalid transaction on stdin: {e}"),
+                }
+            }
+            debug!("Stdin closed, no more transactions will be read from it");
+        });
+        StdinSource {
+            receiver: RefCell::new(receiver),
+        }
+    }
+}
+
+impl Default for StdinSource {
+    fn default() -> Self {
+        StdinSource::new()
+    }
+}
+
+impl TransactionSource for StdinSource {
+    fn name(&self) -> String {
+        "stdin".to_string()
+    }
+
+    fn fetch_pending<'a>(
+        &'a self,
+        _signer: &'a Signer,
+    ) -> LocalBoxFuture<'a, Result<Vec<GaslessTransaction>, RelayerError>> {
+        let mut receiver = self.receiver.borrow_mut();
+        let mut txs = Vec::new();
+        while let Ok(Some(tx)) = receiver.try_next() {
+            txs.push(tx);
+        }
+        Box::pin(async move { Ok(txs) })
+    }
+}
@@ -0,0 +1,311 @@
+//! Revenue sharing for relayer pools. Tips to the shared 0x100/0x200 receivers are paid to whichever pool member's
+//! account sends the relay, so pools split them among their partners afterwards. Once a relay with such a tip is
+//! final, each partner's percentage of the tip is recorded in the history's `tip_splits` ledger, and on a schedule
+//! the shares owed are transferred from the relayer account that received the tip, one transfer per partner and
+//! token. A share only counts as paid once its payout's receipt shows it succeeded, a payout that reverted or was
+//! dropped leaves its shares owed for the next one. Like sweeps, payouts run between relay cycles so they are
+//! never given a nonce a relay is using.
+
+use crate::confirm::WatchedRelay;
+use crate::error::RelayerError;
+use crate::history::{
+    TipSplit, mark_tip_splits_pending, owed_tip_splits, record_tip_split, settle_tip_split_payout,
+};
+use crate::lease::holds_account_lease;
+use crate::observer::is_observing;
+use crate::orchestrator::RelayerTip;
+use crate::relay::{
+    RelayContext, prepare_unsigned_transaction, relay_limits, send_side_transaction,
+};
+use crate::signer::Signer;
+use crate::status::unix_timestamp;
+use crate::sweep::TRANSFER_SIG;
+use crate::tokens::display_token;
+use clarity::abi::{AbiToken, encode_call};
+use clarity::utils::display_uint256_as_address;
+use clarity::{Address, Transaction, Uint256};
+use log::{info, warn};
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone)]
+pub struct SplitPolicy {
+    /// partners along with their share of each tip in basis points
+    pub partners: Vec<(Address, u32)>,
+    pub interval: Duration,
+}
+
+static POLICY: LazyLock<Mutex<Option<SplitPolicy>>> = LazyLock::new(|| Mutex::new(None));
+
+/// When shares on each chain were last paid out, by chain id
+static LAST_PAYOUT: LazyLock<Mutex<HashMap<u64, Instant>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+pub fn set_split_policy(policy: Option<SplitPolicy>) {
+    *POLICY.lock().unwrap() = policy;
+}
+
+pub fn split_policy() -> Option<SplitPolicy> {
+    POLICY.lock().unwrap().clone()
+}
+
+/// Parses a partner given as `ADDRESS=PERCENT`, the percentage taking up to two decimals, into the partner and its
+/// share in basis points
+pub fn parse_tip_split(entry: &str) -> Result<(Address, u32), String> {
+    let (partner, percent) = entry
+        .split_once('=')
+        .ok_or_else(|| format!("Invalid tip split {entry}, expected <ADDRESS>=<PERCENT>"))?;
+    let partner = Address::from_str(partner.trim()).map_err(|e| format!("{entry}: {e}"))?;
+    let percent: f64 = percent
+        .trim()
+        .parse()
+        .map_err(|e| format!("{entry}: {e}"))?;
+    if !(0.0..=100.0).contains(&percent) {
+        return Err(format!("{entry}: percentage must be between 0 and 100"));
+    }
+    Ok((partner, (percent * 100.0).round() as u32))
+}
+
+/// Records the partners' shares of the tip of `watched`, which just became final with its tip paid in full. Only
+/// tips to the shared receivers are split, a tip paid to a fixed address was never ours to share.
+pub fn accrue_tip_split(ctx: &RelayContext, watched: &WatchedRelay, tip: &RelayerTip) {
+    let Some(policy) = split_policy() else {
+        return;
+    };
+    if !ctx.layout.pays_any_sender(tip.receiver) {
+        return;
+    }
+    for (partner, bps) in &policy.partners {
+        let amount = Uint256::from(tip.amount) * (*bps).into() / 10_000u16.into();
+        if amount.is_zero() || *partner == watched.signer.address() {
+            continue;
+        }
+        let split = TipSplit {
+            timestamp: unix_timestamp(),
+            chain_id: ctx.chain_id,
+            sig_hash: watched.tx.id(),
+            relayer: watched.signer.address().to_string(),
+            partner: partner.to_string(),
+            token: tip.token.to_string(),
+            amount: amount.to_string(),
+            payout_tx_hash: None,
+            payout_nonce: None,
+            paid: false,
+        };
+        if let Err(e) = record_tip_split(&split) {
+            warn!(
+                "Failed to record the share of {partner} in the tip of {}: {e}",
+                watched.tx.id()
+            );
+        }
+    }
+}
+
+/// Total of `token` that `relayer` owes partners on `chain_id` and hasn't paid yet, so that sweeps leave it behind
+pub fn owed_to_partners(chain_id: u64, relayer: Address, token: Address) -> Uint256 {
+    owed_tip_splits(chain_id)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|(_, split)| {
+            Address::from_str(&split.relayer).ok() == Some(relayer)
+                && Address::from_str(&split.token).ok() == Some(token)
+        })
+        .map(|(_, split)| Uint256::from_str(&split.amount).unwrap_or_default())
+        .fold(Uint256::default(), |total, amount| total + amount)
+}
+
+/// Pays out the shares owed on the chain of `ctx` if tip splitting is enabled and they weren't paid out within the
+/// interval
+pub async fn pay_tip_splits_if_due(ctx: &RelayContext) {
+    let Some(policy) = split_policy() else {
+        return;
+    };
+    {
+        let mut last_payout = LAST_PAYOUT.lock().unwrap();
+        if last_payout
+            .get(&ctx.chain_id)
+            .is_some_and(|at| at.elapsed() < policy.interval)
+        {
+            return;
+        }
+        last_payout.insert(ctx.chain_id, Instant::now());
+    }
+    if is_observing() {
+        return;
+    }
+    let owed = match owed_tip_splits(ctx.chain_id) {
+        Ok(owed) => owed,
+        Err(e) => {
+            warn!("Failed to read the tip shares owed on {}: {e}", ctx.name);
+            return;
+        }
+    };
+    // payouts already sent are settled from their receipts, the shares of one that failed are paid at the next
+    let mut sent: BTreeMap<String, (String, Option<String>)> = BTreeMap::new();
+    let mut unsent = Vec::new();
+    for (id, split) in owed {
+        match &split.payout_tx_hash {
+            Some(tx_hash) => {
+                sent.insert(
+                    tx_hash.clone(),
+                    (split.relayer.clone(), split.payout_nonce.clone()),
+                );
+            }
+            None => unsent.push((id, split)),
+        }
+    }
+    for (tx_hash, (relayer, nonce)) in sent {
+        if let Err(e) = settle_payout(ctx, &tx_hash, &relayer, nonce.as_deref()).await {
+            warn!("Failed to check the payout {tx_hash} on {}: {e}", ctx.name);
+        }
+    }
+    // shares are paid out together, one transfer for each partner and token an account owes
+    let mut payouts: BTreeMap<(String, String, String), (Uint256, Vec<i64>)> = BTreeMap::new();
+    for (id, split) in unsent {
+        let (amount, ids) = payouts
+            .entry((split.relayer, split.partner, split.token))
+            .or_default();
+        *amount += Uint256::from_str(&split.amount).unwrap_or_default();
+        ids.push(id);
+    }
+    for ((relayer, partner, token), (amount, ids)) in payouts {
+        // payouts use the account's nonces, which only the instance holding its lease hands out
+        let Some(signer) = ctx
+            .signers
+            .signers()
+            .iter()
+            .find(|signer| signer.address().to_string() == relayer)
+        else {
+            continue;
+        };
+        if !holds_account_lease(ctx.chain_id, signer.address()) {
+            continue;
+        }
+        let (Ok(partner), Ok(token)) = (Address::from_str(&partner), Address::from_str(&token))
+        else {
+            continue;
+        };
+        match pay_share(ctx, signer, partner, token, amount).await {
+            Ok(Some((tx_hash, nonce))) => {
+                if let Err(e) = mark_tip_splits_pending(&ids, &tx_hash, &nonce.to_string()) {
+                    warn!("Failed to record the payout {tx_hash} to {partner} in the ledger: {e}");
+                }
+            }
+            Ok(None) => {}
+            Err(e) => warn!(
+                "Failed to pay {amount} of {} from {} to {partner} on {}: {e}",
+                display_token(ctx.chain_id, token),
+                signer.address(),
+                ctx.name
+            ),
+        }
+    }
+}
+
+/// Settles the payout `tx_hash` sent from `relayer` with `nonce`. Its shares are paid once its receipt shows it
+/// succeeded, and owed again once it reverted or its nonce was used by another transaction, so it can never be
+/// included. Otherwise it is still pending and left alone.
+async fn settle_payout(
+    ctx: &RelayContext,
+    tx_hash: &str,
+    relayer: &str,
+    nonce: Option<&str>,
+) -> Result<(), RelayerError> {
+    let web3 = ctx.web3();
+    let hash = Uint256::from_str(tx_hash).map_err(RelayerError::invalid_tx)?;
+    let succeeded = match web3.eth_get_transaction_receipt(hash).await? {
+        Some(receipt) if receipt.get_block_number().is_some() => receipt.get_success(),
+        _ => {
+            let (Ok(relayer), Some(Ok(nonce))) =
+                (Address::from_str(relayer), nonce.map(Uint256::from_str))
+            else {
+                return Ok(());
+            };
+            if web3.eth_get_transaction_count(relayer).await? <= nonce {
+                return Ok(());
+            }
+            false
+        }
+    };
+    if succeeded {
+        info!("Payout {tx_hash} of tip shares on {} succeeded", ctx.name);
+    } else {
+        warn!(
+            "Payout {tx_hash} of tip shares on {} reverted or was dropped, its shares are paid again",
+            ctx.name
+        );
+    }
+    settle_tip_split_payout(tx_hash, succeeded).map_err(RelayerError::HistoryError)
+}
+
+/// Transfers `amount` of `token` from `signer` to `partner`, returning the hash and nonce of the transfer or None if
+/// nothing was sent
+async fn pay_share(
+    ctx: &RelayContext,
+    signer: &Signer,
+    partner: Address,
+    token: Address,
+    amount: Uint256,
+) -> Result<Option<(String, Uint256)>, RelayerError> {
+    let mut unsigned = if token == Address::default() {
+        let mut unsigned = prepare_unsigned_transaction(
+            ctx.web3(),
+            ctx.chain_id,
+            signer.address(),
+            partner,
+            Vec::new(),
+            1.5,
+        )
+        .await?;
+        if let Transaction::Eip1559 { value, .. } = &mut unsigned {
+            *value = amount;
+        }
+        unsigned
+    } else {
+        let data = encode_call(
+            TRANSFER_SIG,
+            &[AbiToken::Address(partner), AbiToken::Uint(amount)],
+        )?;
+        prepare_unsigned_transaction(ctx.web3(), ctx.chain_id, signer.address(), token, data, 1.5)
+            .await?
+    };
+    // the transfer's fee and a native share are paid out of what the account holds above the reserve
+    if let Transaction::Eip1559 {
+        max_fee_per_gas,
+        gas_limit,
+        value,
+        ..
+    } = &mut unsigned
+    {
+        let needed = *max_fee_per_gas * *gas_limit + *value + relay_limits().reserve_balance;
+        let balance = ctx.web3().eth_get_balance(signer.address()).await?;
+        if balance < needed {
+            warn!(
+                "Not paying tip shares from {} on {} yet, it holds {balance} wei but the payout needs {needed} wei",
+                signer.address(),
+                ctx.name
+            );
+            return Ok(None);
+        }
+    }
+    let what = format!("{amount} of {}", display_token(ctx.chain_id, token));
+    if ctx.dry_run {
+        info!(
+            "Dry run, not paying {what} from {} to {partner} on {}",
+            signer.address(),
+            ctx.name
+        );
+        return Ok(None);
+    }
+    let nonce = unsigned.get_nonce();
+    let tx_hash = send_side_transaction(ctx, signer, unsigned).await?;
+    let tx_hash = display_uint256_as_address(tx_hash);
+    info!(
+        "Sent {what} of tip shares from {} to {partner} on {} with {tx_hash}",
+        signer.address(),
+        ctx.name
+    );
+    Ok(Some((tx_hash, nonce)))
+}
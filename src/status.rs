@@ -0,0 +1,598 @@
+//! Process wide relayer status, updated by the relay loop and served over HTTP by the optional
+//! status server for liveness probes, monitoring and the dashboard.
+
+use crate::dashboard::{dashboard, dashboard_data};
+use crate::orchestrator::GaslessTransaction;
+use crate::proxy::http_workers;
+use crate::quality::OrchestratorQuality;
+use crate::rpc::EndpointHealth;
+use crate::skip::SkipReason;
+use crate::throttle::Outbound;
+use actix_web::{App, HttpResponse, HttpServer, Responder, web};
+use clarity::{Address, Uint256};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt::Write;
+use std::sync::{LazyLock, Mutex, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A relay that has been broadcast but not yet confirmed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InFlightTransaction {
+    /// hash of our on chain transaction
+    pub tx_hash: String,
+    /// id of the gasless transaction being relayed
+    pub id: String,
+    /// unix timestamp at which we broadcast
+    pub submitted_at: u64,
+}
+
+/// A relay that has been included in a block but is not yet `confirmation_blocks` deep
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnconfirmedTransaction {
+    /// hash of our on chain transaction
+    pub tx_hash: String,
+    /// id of the gasless transaction being relayed
+    pub id: String,
+    /// block the relay is currently included in, unknown until it is first checked
+    pub block: Option<Uint256>,
+    pub confirmations: u64,
+}
+
+/// Use of the daily subsidy budget, in wei of the gas token
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubsidyStatus {
+    pub budget: Uint256,
+    /// settled within the last day
+    pub spent: Uint256,
+    /// most the sponsored relays still in flight may cost
+    pub reserved: Uint256,
+}
+
+/// A transaction an orchestrator served that isn't encoded the way the dex expects
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MalformedSubmission {
+    /// id of the gasless transaction
+    pub id: String,
+    /// which field is malformed and how
+    pub error: String,
+    /// unix timestamp at which it was found malformed
+    pub seen_at: u64,
+}
+
+/// Malformed submissions kept per orchestrator, the most recent ones
+const MAX_MALFORMED_SUBMISSIONS: usize = 20;
+
+/// A pending transaction a source served on its last poll
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueuedTransaction {
+    /// id of the gasless transaction
+    pub id: String,
+    pub chain_id: u64,
+}
+
+/// Upper bounds in seconds of the end to end latency histogram buckets
+const LATENCY_BUCKETS: [u64; 10] = [5, 10, 15, 30, 60, 120, 300, 600, 1800, 3600];
+
+/// Seconds from a transaction's submission to its orchestrator to our relay of it being included
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LatencyHistogram {
+    /// inclusions at or below each of `LATENCY_BUCKETS`, cumulative
+    pub buckets: Vec<u64>,
+    pub sum: u64,
+    pub count: u64,
+}
+
+/// Balance of one relayer account in a token it earned tips in
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenBalance {
+    pub token: String,
+    /// `symbol()` of the token, if it could be read
+    #[serde(default)]
+    pub symbol: Option<String>,
+    pub balance: Uint256,
+    /// unknown if the token's `decimals()` could not be read
+    pub decimals: Option<u8>,
+}
+
+/// Balance of one relayer account
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WalletStatus {
+    pub address: String,
+    pub balance: Option<Uint256>,
+    /// balances of the tokens relays on this chain were tipped in and of the wrapped gas token
+    pub tokens: Vec<TokenBalance>,
+}
+
+/// Status of one of the chains this relayer serves
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChainStatus {
+    pub name: String,
+    pub last_block: Option<Uint256>,
+    /// sum of the known balances of every relayer account on this chain
+    pub wallet_balance: Option<Uint256>,
+    pub wallets: Vec<WalletStatus>,
+    pub rpc_endpoints: Vec<EndpointHealth>,
+    /// relays that are included but not yet final
+    pub unconfirmed: Vec<UnconfirmedTransaction>,
+    /// unix timestamp token balances were last checked
+    pub tokens_checked_at: Option<u64>,
+    /// sum of the token balances of every relayer account on this chain, the tips not yet liquidated
+    pub token_balances: Vec<TokenBalance>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RelayerStatus {
+    pub relayer_address: Option<String>,
+    /// unix timestamp of the last successful poll of each orchestrator
+    pub last_successful_poll: HashMap<String, u64>,
+    /// orchestrators that are currently not polled because they kept failing
+    pub open_circuits: Vec<String>,
+    /// pickup latency, duplicate and invalid rates and resulting poll frequency of each orchestrator
+    pub orchestrator_quality: HashMap<String, OrchestratorQuality>,
+    /// most recent malformed transactions served by each orchestrator, for reporting back to its operator
+    pub malformed_submissions: HashMap<String, Vec<MalformedSubmission>>,
+    /// malformed transactions served by each orchestrator since startup
+    pub malformed_counts: HashMap<String, u64>,
+    /// transactions not relayed since startup, by reason
+    pub skipped: BTreeMap<SkipReason, u64>,
+    /// transactions served by each orchestrator that weren't relayed since startup, by reason, for reporting back
+    /// to its operator
+    pub skipped_by_orchestrator: HashMap<String, BTreeMap<SkipReason, u64>>,
+    /// margin in percent currently required on top of the gas cost, including any adaptive increase
+    pub profit_margin_percent: Option<u32>,
+    /// end to end latency of every relay included since startup
+    pub inclusion_latency: LatencyHistogram,
+    /// 95th percentile end to end latency of recent inclusions, None until there are enough of them
+    pub latency_p95_secs: Option<u64>,
+    /// unix timestamp of the last completed main loop iteration
+    pub last_loop: Option<u64>,
+    /// whether this instance is the elected leader, None without leader election
+    pub leader: Option<bool>,
+    /// idempotent requests sent again after a transient failure since startup, by service
+    pub http_retries: HashMap<String, u64>,
+    /// use of the subsidy budget, None without one
+    pub subsidy: Option<SubsidyStatus>,
+    /// keyed by chain id
+    pub chains: HashMap<u64, ChainStatus>,
+    pub in_flight: Vec<InFlightTransaction>,
+    /// pending transactions each source served on its last poll, by source
+    pub queue: HashMap<String, Vec<QueuedTransaction>>,
+    /// seconds after which a main loop that has not completed an iteration is considered unhealthy
+    pub loop_stale_after: u64,
+}
+
+static STATUS: LazyLock<RwLock<RelayerStatus>> =
+    LazyLock::new(|| RwLock::new(RelayerStatus::default()));
+
+/// Tokens whose balances are checked, by chain id
+static TRACKED_TOKENS: LazyLock<Mutex<HashMap<u64, BTreeSet<Address>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Token balances take a call per account and token, so they are checked less often than native balances
+const TOKEN_BALANCE_INTERVAL: u64 = 60;
+
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+pub fn get_status() -> RelayerStatus {
+    STATUS.read().unwrap().clone()
+}
+
+pub fn init_status(relayer_address: String, loop_stale_after: u64) {
+    let mut status = STATUS.write().unwrap();
+    status.relayer_address = Some(relayer_address);
+    status.loop_stale_after = loop_stale_after;
+}
+
+pub fn init_chain_status(chain_id: u64, name: String, relayer_addresses: Vec<String>) {
+    STATUS.write().unwrap().chains.insert(
+        chain_id,
+        ChainStatus {
+            name,
+            wallets: relayer_addresses
+                .into_iter()
+                .map(|address| WalletStatus {
+                    address,
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        },
+    );
+}
+
+pub fn record_poll_success(orchestrator_url: &str) {
+    STATUS
+        .write()
+        .unwrap()
+        .last_successful_poll
+        .insert(orchestrator_url.to_string(), unix_timestamp());
+}
+
+pub fn record_open_circuits(open_circuits: Vec<String>) {
+    STATUS.write().unwrap().open_circuits = open_circuits;
+}
+
+pub fn record_orchestrator_quality(quality: HashMap<String, OrchestratorQuality>) {
+    STATUS.write().unwrap().orchestrator_quality = quality;
+}
+
+pub fn record_malformed(orchestrator_url: &str, id: String, error: String) {
+    let mut status = STATUS.write().unwrap();
+    *status
+        .malformed_counts
+        .entry(orchestrator_url.to_string())
+        .or_default() += 1;
+    let submissions = status
+        .malformed_submissions
+        .entry(orchestrator_url.to_string())
+        .or_default();
+    if submissions.len() >= MAX_MALFORMED_SUBMISSIONS {
+        submissions.remove(0);
+    }
+    submissions.push(MalformedSubmission {
+        id,
+        error,
+        seen_at: unix_timestamp(),
+    });
+}
+
+/// Signature hash of a skipped transaction and the reason it was skipped for
+type CountedSkip = ([u8; 32], SkipReason);
+
+/// Transactions already counted as skipped, with the reason, cleared when it grows too large
+static SKIPS_COUNTED: LazyLock<Mutex<HashSet<CountedSkip>>> =
+    LazyLock::new(|| Mutex::new(HashSet::new()));
+
+/// Most transaction and reason pairs remembered as counted
+const MAX_SKIPS_COUNTED: usize = 100_000;
+
+/// Counts `tx` as not relayed for `reason`, against the orchestrator that served it if there was one.
+/// Transactions are evaluated again on every poll, so each one is counted once per reason
+pub fn record_skip(orchestrator_url: Option<&str>, tx: &GaslessTransaction, reason: SkipReason) {
+    {
+        let mut counted = SKIPS_COUNTED.lock().unwrap();
+        if counted.len() >= MAX_SKIPS_COUNTED {
+            counted.clear();
+        }
+        if !counted.insert((tx.sig_hash(), reason)) {
+            return;
+        }
+    }
+    let mut status = STATUS.write().unwrap();
+    *status.skipped.entry(reason).or_default() += 1;
+    if let Some(url) = orchestrator_url {
+        *status
+            .skipped_by_orchestrator
+            .entry(url.to_string())
+            .or_default()
+            .entry(reason)
+            .or_default() += 1;
+    }
+}
+
+pub fn record_inclusion_latency(secs: u64) {
+    let mut status = STATUS.write().unwrap();
+    let histogram = &mut status.inclusion_latency;
+    histogram.buckets.resize(LATENCY_BUCKETS.len(), 0);
+    for (bucket, bound) in histogram.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+        if secs <= bound {
+            *bucket += 1;
+        }
+    }
+    histogram.sum += secs;
+    histogram.count += 1;
+}
+
+pub fn record_latency_p95(secs: Option<u64>) {
+    STATUS.write().unwrap().latency_p95_secs = secs;
+}
+
+pub fn record_profit_margin(margin: u32) {
+    STATUS.write().unwrap().profit_margin_percent = Some(margin);
+}
+
+pub fn record_leader(leader: bool) {
+    STATUS.write().unwrap().leader = Some(leader);
+}
+
+pub fn record_http_retry(service: Outbound) {
+    *STATUS
+        .write()
+        .unwrap()
+        .http_retries
+        .entry(service.name().to_string())
+        .or_default() += 1;
+}
+
+pub fn record_subsidy(subsidy: SubsidyStatus) {
+    STATUS.write().unwrap().subsidy = Some(subsidy);
+}
+
+pub fn record_loop() {
+    STATUS.write().unwrap().last_loop = Some(unix_timestamp());
+}
+
+/// Records the latest block and the balance of each relayer account, in the same order the accounts were
+/// passed to `init_chain_status`
+pub fn record_chain_status(chain_id: u64, block: Option<Uint256>, balances: Vec<Option<Uint256>>) {
+    let mut status = STATUS.write().unwrap();
+    let chain = status.chains.entry(chain_id).or_default();
+    if block.is_some() {
+        chain.last_block = block;
+    }
+    for (wallet, balance) in chain.wallets.iter_mut().zip(balances) {
+        if balance.is_some() {
+            wallet.balance = balance;
+        }
+    }
+    chain.wallet_balance = chain
+        .wallets
+        .iter()
+        .filter_map(|w| w.balance)
+        .reduce(|a, b| a + b);
+}
+
+/// Checks the balance of `token` on the chain from now on, the token a relay was just tipped in. A token that
+/// wasn't tracked yet is checked on the next status update.
+pub fn track_token(chain_id: u64, token: Address) {
+    let added = TRACKED_TOKENS
+        .lock()
+        .unwrap()
+        .entry(chain_id)
+        .or_default()
+        .insert(token);
+    if added && let Some(chain) = STATUS.write().unwrap().chains.get_mut(&chain_id) {
+        chain.tokens_checked_at = None;
+    }
+}
+
+pub fn tracked_tokens(chain_id: u64) -> Vec<Address> {
+    TRACKED_TOKENS
+        .lock()
+        .unwrap()
+        .get(&chain_id)
+        .map(|tokens| tokens.iter().copied().collect())
+        .unwrap_or_default()
+}
+
+/// True once token balances on the chain haven't been checked for `TOKEN_BALANCE_INTERVAL` seconds
+pub fn token_balances_due(chain_id: u64) -> bool {
+    STATUS
+        .read()
+        .unwrap()
+        .chains
+        .get(&chain_id)
+        .and_then(|chain| chain.tokens_checked_at)
+        .is_none_or(|checked_at| {
+            unix_timestamp().saturating_sub(checked_at) >= TOKEN_BALANCE_INTERVAL
+        })
+}
+
+/// Records the token balances of each relayer account, in the same order the accounts were passed to
+/// `init_chain_status`
+pub fn record_token_balances(chain_id: u64, balances: Vec<Vec<TokenBalance>>) {
+    let mut status = STATUS.write().unwrap();
+    let chain = status.chains.entry(chain_id).or_default();
+    chain.tokens_checked_at = Some(unix_timestamp());
+    for (wallet, tokens) in chain.wallets.iter_mut().zip(balances) {
+        wallet.tokens = tokens;
+    }
+    let mut totals: BTreeMap<String, TokenBalance> = BTreeMap::new();
+    for token in chain.wallets.iter().flat_map(|wallet| &wallet.tokens) {
+        totals
+            .entry(token.token.clone())
+            .and_modify(|total| total.balance += token.balance)
+            .or_insert_with(|| token.clone());
+    }
+    chain.token_balances = totals.into_values().collect();
+}
+
+/// Records the pending transactions `source` served on its last poll
+pub fn record_queue(source: &str, queue: Vec<QueuedTransaction>) {
+    STATUS
+        .write()
+        .unwrap()
+        .queue
+        .insert(source.to_string(), queue);
+}
+
+pub fn record_rpc_health(chain_id: u64, rpc_endpoints: Vec<EndpointHealth>) {
+    STATUS
+        .write()
+        .unwrap()
+        .chains
+        .entry(chain_id)
+        .or_default()
+        .rpc_endpoints = rpc_endpoints;
+}
+
+pub fn record_unconfirmed(chain_id: u64, unconfirmed: Vec<UnconfirmedTransaction>) {
+    STATUS
+        .write()
+        .unwrap()
+        .chains
+        .entry(chain_id)
+        .or_default()
+        .unconfirmed = unconfirmed;
+}
+
+pub fn add_in_flight(tx_hash: String, id: String) {
+    STATUS.write().unwrap().in_flight.push(InFlightTransaction {
+        tx_hash,
+        id,
+        submitted_at: unix_timestamp(),
+    });
+}
+
+pub fn remove_in_flight(tx_hash: &str) {
+    STATUS
+        .write()
+        .unwrap()
+        .in_flight
+        .retain(|tx| tx.tx_hash != tx_hash);
+}
+
+/// The relayer is healthy as long as the main loop keeps completing iterations
+pub fn is_healthy() -> bool {
+    let status = STATUS.read().unwrap();
+    match status.last_loop {
+        Some(last_loop) => unix_timestamp().saturating_sub(last_loop) <= status.loop_stale_after,
+        // still starting up
+        None => true,
+    }
+}
+
+async fn healthz() -> impl Responder {
+    if is_healthy() {
+        HttpResponse::Ok().body("OK")
+    } else {
+        HttpResponse::ServiceUnavailable().body("Relay loop stalled")
+    }
+}
+
+async fn status() -> impl Responder {
+    HttpResponse::Ok().json(get_status())
+}
+
+/// `amount` base units of a token with `decimals` decimals as a float, precise enough for a gauge
+pub(crate) fn whole_units(amount: Uint256, decimals: u8) -> f64 {
+    amount.to_string().parse::<f64>().unwrap_or(f64::MAX) / 10f64.powi(decimals.into())
+}
+
+/// Balances in the Prometheus text format, in whole tokens. Tokens whose decimals are unknown are left out.
+fn render_metrics(status: &RelayerStatus) -> String {
+    let mut metrics = String::new();
+    metrics += "# HELP relayer_balance Balance of a relayer account in the gas token\n";
+    metrics += "# TYPE relayer_balance gauge\n";
+    for (chain_id, chain) in &status.chains {
+        for wallet in &chain.wallets {
+            if let Some(balance) = wallet.balance {
+                let _ = writeln!(
+                    metrics,
+                    "relayer_balance{{chain_id=\"{chain_id}\",account=\"{}\"}} {}",
+                    wallet.address,
+                    whole_units(balance, 18)
+                );
+            }
+        }
+    }
+    metrics +=
+        "# HELP relayer_token_balance Balance of a relayer account in a token it earned tips in\n";
+    metrics += "# TYPE relayer_token_balance gauge\n";
+    for (chain_id, chain) in &status.chains {
+        for wallet in &chain.wallets {
+            for token in &wallet.tokens {
+                if let Some(decimals) = token.decimals {
+                    let _ = writeln!(
+                        metrics,
+                        "relayer_token_balance{{chain_id=\"{chain_id}\",account=\"{}\",token=\"{}\"}} {}",
+                        wallet.address,
+                        token.token,
+                        whole_units(token.balance, decimals)
+                    );
+                }
+            }
+        }
+    }
+    metrics += "# HELP relayer_http_retries_total Requests sent again after a transient failure\n";
+    metrics += "# TYPE relayer_http_retries_total counter\n";
+    for (service, retries) in &status.http_retries {
+        let _ = writeln!(
+            metrics,
+            "relayer_http_retries_total{{service=\"{service}\"}} {retries}"
+        );
+    }
+    metrics += "# HELP relayer_skipped_total Transactions not relayed, by reason\n";
+    metrics += "# TYPE relayer_skipped_total counter\n";
+    for (reason, skipped) in &status.skipped {
+        let _ = writeln!(
+            metrics,
+            "relayer_skipped_total{{reason=\"{reason}\"}} {skipped}"
+        );
+    }
+    metrics += "# HELP relayer_inclusion_latency_seconds Time from a transaction's submission to its orchestrator to our relay of it being included\n";
+    metrics += "# TYPE relayer_inclusion_latency_seconds histogram\n";
+    let histogram = &status.inclusion_latency;
+    for (bound, count) in LATENCY_BUCKETS.iter().zip(
+        histogram
+            .buckets
+            .iter()
+            .copied()
+            .chain(std::iter::repeat(0)),
+    ) {
+        let _ = writeln!(
+            metrics,
+            "relayer_inclusion_latency_seconds_bucket{{le=\"{bound}\"}} {count}"
+        );
+    }
+    let _ = writeln!(
+        metrics,
+        "relayer_inclusion_latency_seconds_bucket{{le=\"+Inf\"}} {}",
+        histogram.count
+    );
+    let _ = writeln!(
+        metrics,
+        "relayer_inclusion_latency_seconds_sum {}",
+        histogram.sum
+    );
+    let _ = writeln!(
+        metrics,
+        "relayer_inclusion_latency_seconds_count {}",
+        histogram.count
+    );
+    if let Some(p95) = status.latency_p95_secs {
+        metrics += "# HELP relayer_inclusion_latency_p95_seconds 95th percentile latency of recent inclusions\n";
+        metrics += "# TYPE relayer_inclusion_latency_p95_seconds gauge\n";
+        let _ = writeln!(metrics, "relayer_inclusion_latency_p95_seconds {p95}");
+    }
+    if let Some(subsidy) = &status.subsidy {
+        metrics += "# HELP relayer_subsidy_budget Subsidy budget within any day in the gas token\n";
+        metrics += "# TYPE relayer_subsidy_budget gauge\n";
+        let _ = writeln!(
+            metrics,
+            "relayer_subsidy_budget {}",
+            whole_units(subsidy.budget, 18)
+        );
+        metrics += "# HELP relayer_subsidy_committed Subsidies spent within the last day or reserved by relays in flight, in the gas token\n";
+        metrics += "# TYPE relayer_subsidy_committed gauge\n";
+        let _ = writeln!(
+            metrics,
+            "relayer_subsidy_committed {}",
+            whole_units(subsidy.spent + subsidy.reserved, 18)
+        );
+    }
+    metrics
+}
+
+async fn metrics() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(render_metrics(&get_status()))
+}
+
+/// Starts the status server on `bind` in the background, serving `/healthz`, `/status`, `/metrics` and the
+/// dashboard
+pub fn start_status_server(bind: &str) -> std::io::Result<()> {
+    let server = HttpServer::new(|| {
+        App::new()
+            .route("/healthz", web::get().to(healthz))
+            .route("/status", web::get().to(status))
+            .route("/metrics", web::get().to(metrics))
+            .route("/dashboard", web::get().to(dashboard))
+            .route("/dashboard/data", web::get().to(dashboard_data))
+    })
+    .workers(http_workers())
+    .disable_signals()
+    .bind(bind)?
+    .run();
+    info!("Status server listening on {bind}");
+    actix_rt::spawn(server);
+    Ok(())
+}
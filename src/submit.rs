@@ -0,0 +1,294 @@
+//! Local submission of transactions, served on `POST /submit` so that a trusted frontend can hand its users'
+//! transactions straight to this relayer instead of going through a public orchestrator. Each frontend is given its
+//! own key and becomes a `TransactionSource` of its own, so its submissions go through the same checks as
+//! everything else and show up under its name on the status endpoint.
+//!
+//! A transaction is only accepted once, the same signature submitted again while it is remembered is rejected as
+//! a replay, and each source may submit at most a set number of transactions within a sliding window. Accepted
+//! transactions stay queued, and are offered again every cycle, until they are relayed, can never be relayed or
+//! expire, and each source's queue is bounded.
+
+use crate::confirm::is_watched;
+use crate::error::RelayerError;
+use crate::orchestrator::{API_KEY_HEADER, GaslessTransaction};
+use crate::proxy::http_workers;
+use crate::retry::is_blacklisted;
+use crate::signer::Signer;
+use crate::source::TransactionSource;
+use crate::status::unix_timestamp;
+use actix_web::{App, HttpRequest, HttpResponse, HttpServer, Responder, web};
+use futures::channel::mpsc::{UnboundedReceiver, UnboundedSender, unbounded};
+use futures::future::LocalBoxFuture;
+use log::{debug, info, warn};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long an accepted transaction is remembered to reject replays of it
+const REPLAY_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Most transactions one request may submit
+const MAX_BATCH: usize = 100;
+
+/// Most transactions kept queued for one source, the oldest are dropped beyond that
+const MAX_QUEUED: usize = 10_000;
+
+/// Signature hashes of the transactions accepted within the replay window
+#[derive(Default)]
+struct AcceptedTransactions {
+    ids: HashSet<[u8; 32]>,
+    /// the same ids as (accepted at, signature hash) pairs oldest first, to expire them in order
+    expiry: VecDeque<(Instant, [u8; 32])>,
+}
+
+static ACCEPTED: LazyLock<Mutex<AcceptedTransactions>> =
+    LazyLock::new(|| Mutex::new(AcceptedTransactions::default()));
+
+/// A frontend allowed to submit transactions, identified by the key it presents in the `X-Api-Key` header
+#[derive(Debug, Clone)]
+pub struct SubmitKey {
+    pub name: String,
+    pub key: String,
+}
+
+impl SubmitKey {
+    /// Parses `<NAME>=<KEY>`
+    pub fn parse(entry: &str) -> Result<SubmitKey, String> {
+        match entry.split_once('=') {
+            Some((name, key)) if !name.is_empty() && !key.is_empty() => Ok(SubmitKey {
+                name: name.to_string(),
+                key: key.to_string(),
+            }),
+            _ => Err(format!("Invalid submit key {entry}, expected <NAME>=<KEY>")),
+        }
+    }
+}
+
+/// Limits how many transactions each source may submit
+#[derive(Debug, Clone)]
+pub struct SubmitLimit {
+    /// most transactions accepted from one source within `window`
+    pub max_submissions: u32,
+    pub window: Duration,
+}
+
+/// One source as seen by the server
+struct Submitter {
+    key: String,
+    sender: UnboundedSender<GaslessTransaction>,
+    /// times transactions from this source were accepted within the window, oldest first
+    submissions: VecDeque<Instant>,
+}
+
+struct Submitters {
+    sources: HashMap<String, Submitter>,
+    limit: SubmitLimit,
+}
+
+/// Body of `POST /submit`, one transaction or an array of them in the format orchestrators serve
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum Submission {
+    One(Box<GaslessTransaction>),
+    Many(Vec<GaslessTransaction>),
+}
+
+/// Whether `a` and `b` are equal, taking as long to find out wherever they differ so that a key can't be guessed
+/// byte by byte from how quickly it is rejected
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Returns the name of the source whose key `request` carries, every key is compared
+fn authorize(submitters: &Submitters, request: &HttpRequest) -> Option<String> {
+    let presented = request.headers().get(API_KEY_HEADER)?.as_bytes();
+    submitters
+        .sources
+        .iter()
+        .fold(None, |found, (name, submitter)| {
+            if constant_time_eq(submitter.key.as_bytes(), presented) {
+                Some(name.clone())
+            } else {
+                found
+            }
+        })
+}
+
+/// Records `ids` as accepted unless any of them already was, returning the first replayed one
+fn accept_once(ids: &[[u8; 32]]) -> Result<(), [u8; 32]> {
+    let now = Instant::now();
+    let mut accepted = ACCEPTED.lock().unwrap();
+    while let Some((_, id)) = accepted
+        .expiry
+        .pop_front_if(|(at, _)| now.duration_since(*at) >= REPLAY_WINDOW)
+    {
+        accepted.ids.remove(&id);
+    }
+    for (i, id) in ids.iter().enumerate() {
+        if accepted.ids.contains(id) || ids[..i].contains(id) {
+            return Err(*id);
+        }
+    }
+    accepted.ids.extend(ids.iter().copied());
+    accepted.expiry.extend(ids.iter().map(|id| (now, *id)));
+    Ok(())
+}
+
+async fn submit(
+    submitters: web::Data<Mutex<Submitters>>,
+    request: HttpRequest,
+    body: web::Bytes,
+) -> impl Responder {
+    let mut submitters = submitters.lock().unwrap();
+    let Some(name) = authorize(&submitters, &request) else {
+        return HttpResponse::Unauthorized().finish();
+    };
+    let txs = match serde_json::from_slice(&body) {
+        Ok(Submission::One(tx)) => vec![*tx],
+        Ok(Submission::Many(txs)) => txs,
+        Err(e) => return HttpResponse::BadRequest().body(format!("Invalid transaction: {e}")),
+    };
+    if txs.len() > MAX_BATCH {
+        return HttpResponse::PayloadTooLarge()
+            .body(format!("At most {MAX_BATCH} transactions per request"));
+    }
+    let limit = submitters.limit.clone();
+    let submitter = submitters.sources.get_mut(&name).unwrap();
+    let now = Instant::now();
+    while submitter
+        .submissions
+        .front()
+        .is_some_and(|at| now.duration_since(*at) >= limit.window)
+    {
+        submitter.submissions.pop_front();
+    }
+    if submitter.submissions.len() + txs.len() > limit.max_submissions as usize {
+        debug!(
+            "Rate limited submission of {} transactions from {name}",
+            txs.len()
+        );
+        return HttpResponse::TooManyRequests().finish();
+    }
+    let ids: Vec<[u8; 32]> = txs.iter().map(|tx| tx.sig_hash()).collect();
+    if let Err(id) = accept_once(&ids) {
+        return HttpResponse::Conflict().body(format!(
+            "Transaction 0x{} was already submitted",
+            hex::encode(id)
+        ));
+    }
+    let ids: Vec<String> = txs.iter().map(|tx| tx.id()).collect();
+    for tx in txs {
+        submitter.submissions.push_back(now);
+        if submitter.sender.unbounded_send(tx).is_err() {
+            return HttpResponse::ServiceUnavailable().body("Relayer is shutting down");
+        }
+    }
+    debug!("Accepted {} transactions from {name}", ids.len());
+    HttpResponse::Accepted().json(ids)
+}
+
+/// Transactions submitted with one key
+pub struct SubmitSource {
+    name: String,
+    receiver: RefCell<UnboundedReceiver<GaslessTransaction>>,
+    /// accepted transactions with when they were, kept until they are relayed or expire so that one skipped in a
+    /// cycle is evaluated again in the next
+    queued: RefCell<Vec<(Instant, GaslessTransaction)>>,
+}
+
+impl TransactionSource for SubmitSource {
+    fn name(&self) -> String {
+        format!("submit:{}", self.name)
+    }
+
+    fn fetch_pending<'a>(
+        &'a self,
+        _signer: &'a Signer,
+    ) -> LocalBoxFuture<'a, Result<Vec<GaslessTransaction>, RelayerError>> {
+        let mut receiver = self.receiver.borrow_mut();
+        let mut queued = self.queued.borrow_mut();
+        while let Ok(Some(tx)) = receiver.try_next() {
+            queued.push((Instant::now(), tx));
+        }
+        let now = unix_timestamp();
+        queued.retain(|(accepted, tx)| {
+            if is_watched(tx) {
+                debug!(
+                    "Submitted transaction {} was relayed, dequeuing it",
+                    tx.id()
+                );
+                return false;
+            }
+            if is_blacklisted(tx) {
+                debug!(
+                    "Submitted transaction {} can never be relayed, dequeuing it",
+                    tx.id()
+                );
+                return false;
+            }
+            // transactions not yet valid stay queued until they are
+            let expired = tx
+                .decode_conditions()
+                .is_ok_and(|conds| conds.deadline != 0 && now > conds.deadline);
+            if expired || accepted.elapsed() >= REPLAY_WINDOW {
+                debug!("Submitted transaction {} expired, dequeuing it", tx.id());
+                return false;
+            }
+            true
+        });
+        if queued.len() > MAX_QUEUED {
+            let excess = queued.len() - MAX_QUEUED;
+            warn!(
+                "{} has more than {MAX_QUEUED} transactions queued, dropping the {excess} oldest",
+                self.name()
+            );
+            queued.drain(..excess);
+        }
+        let txs = queued.iter().map(|(_, tx)| tx.clone()).collect();
+        Box::pin(async move { Ok(txs) })
+    }
+}
+
+/// Starts the submission server on `bind` in the background, serving `POST /submit` to requests carrying one of
+/// `keys`. Returns a source for each key.
+pub fn start_submit_server(
+    bind: &str,
+    keys: Vec<SubmitKey>,
+    limit: SubmitLimit,
+) -> std::io::Result<Vec<SubmitSource>> {
+    let mut sources = Vec::new();
+    let mut submitters = HashMap::new();
+    for SubmitKey { name, key } in keys {
+        let (sender, receiver) = unbounded();
+        sources.push(SubmitSource {
+            name: name.clone(),
+            receiver: RefCell::new(receiver),
+            queued: RefCell::new(Vec::new()),
+        });
+        submitters.insert(
+            name,
+            Submitter {
+                key,
+                sender,
+                submissions: VecDeque::new(),
+            },
+        );
+    }
+    let submitters = web::Data::new(Mutex::new(Submitters {
+        sources: submitters,
+        limit,
+    }));
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(submitters.clone())
+            .route("/submit", web::post().to(submit))
+    })
+    .workers(http_workers())
+    .disable_signals()
+    .bind(bind)?
+    .run();
+    info!("Submission server listening on {bind}");
+    actix_rt::spawn(server);
+    Ok(sources)
+}
@@ -0,0 +1,271 @@
+//! Budget for relaying transactions whose tip doesn't cover their gas, for dApps that sponsor relaying for their
+//! users rather than relay for profit. Up to a set amount of ALTHEA within any day may go into making up the
+//! difference. A sponsored relay reserves the most it could cost before it is broadcast and is settled at what it
+//! actually cost once final, so the budget holds however many relays are in flight. Once it is used up only
+//! transactions that pay for themselves are relayed, until enough spending has aged out of the last day. Each
+//! user may only take up part of the budget, so a single address can't sponsor itself through all of it.
+
+use crate::alert::alert_on_subsidy;
+use crate::orchestrator::GaslessTransaction;
+use crate::status::{SubsidyStatus, record_subsidy, unix_timestamp};
+use crate::units::display_ether;
+use clarity::{Address, Uint256};
+use log::{info, warn};
+use num_traits::ToPrimitive;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+const DAY: u64 = 24 * 60 * 60;
+
+/// How often the ledger is written when spending changed, away from the relay path
+const LEDGER_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct SubsidyBudget {
+    /// most wei of the gas token spent on subsidies within any day
+    pub per_day: Uint256,
+    /// most wei of the gas token spent on the subsidies of any one user within any day
+    pub per_user_per_day: Uint256,
+    /// percentages of the budget an alert is sent at as spending passes them
+    pub alert_percents: Vec<u32>,
+    /// file spending is kept in, so that a restart doesn't hand out the day's budget a second time
+    pub ledger: Option<PathBuf>,
+}
+
+/// Subsidy spent at a unix timestamp, as kept in the ledger
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Spending {
+    at: u64,
+    amount: Uint256,
+    /// user the relay was sponsored for, missing in ledgers written before the per user cap
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    user: Option<Address>,
+}
+
+#[derive(Debug, Default)]
+struct Subsidies {
+    /// settled subsidies of the last day, oldest first
+    spent: Vec<Spending>,
+    /// user and most each sponsored relay still in flight may cost, by signature hash
+    reserved: HashMap<[u8; 32], (Address, Uint256)>,
+    /// highest alert percentage passed, so each is only alerted once until spending falls below it again
+    alerted: u32,
+    /// whether spending changed since the ledger was last written
+    dirty: bool,
+}
+
+static BUDGET: LazyLock<Mutex<Option<SubsidyBudget>>> = LazyLock::new(|| Mutex::new(None));
+
+static SUBSIDIES: LazyLock<Mutex<Subsidies>> = LazyLock::new(|| Mutex::new(Subsidies::default()));
+
+/// Sets the daily budget, loading the spending kept in its ledger by a previous run. Relays still in flight when
+/// that run stopped were written down at what they could have cost at most, a missing ledger means nothing was
+/// spent yet.
+pub fn set_subsidy_budget(budget: Option<SubsidyBudget>) -> Result<(), String> {
+    if let Some(path) = budget.as_ref().and_then(|budget| budget.ledger.as_deref()) {
+        let spent: Vec<Spending> = match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents).map_err(|e| e.to_string())?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.to_string()),
+        };
+        SUBSIDIES.lock().unwrap().spent = spent;
+        let path = path.to_path_buf();
+        std::thread::spawn(move || {
+            loop {
+                std::thread::sleep(LEDGER_FLUSH_INTERVAL);
+                flush_ledger(&path);
+            }
+        });
+    }
+    *BUDGET.lock().unwrap() = budget;
+    update(&mut SUBSIDIES.lock().unwrap());
+    Ok(())
+}
+
+/// True if transactions that aren't profitable may be relayed on the budget
+pub fn subsidy_enabled() -> bool {
+    BUDGET.lock().unwrap().is_some()
+}
+
+/// True if both the budget and the share of it of `user` have something left
+pub fn subsidy_left(user: Address) -> bool {
+    let Some(budget) = BUDGET.lock().unwrap().clone() else {
+        return false;
+    };
+    let mut subsidies = SUBSIDIES.lock().unwrap();
+    prune(&mut subsidies);
+    committed(&subsidies, None) < budget.per_day
+        && committed(&subsidies, Some(user)) < budget.per_user_per_day
+}
+
+/// Reserves `shortfall`, the most the relay of `tx` signed by `user` could cost beyond what its tip is worth, if
+/// both the budget and the user's share of it have that much left. A reservation made earlier for `tx` is replaced.
+pub fn reserve_subsidy(tx: &GaslessTransaction, user: Address, shortfall: Uint256) -> bool {
+    let Some(budget) = BUDGET.lock().unwrap().clone() else {
+        return false;
+    };
+    let mut subsidies = SUBSIDIES.lock().unwrap();
+    subsidies.reserved.remove(&tx.sig_hash());
+    prune(&mut subsidies);
+    if committed(&subsidies, None) + shortfall > budget.per_day
+        || committed(&subsidies, Some(user)) + shortfall > budget.per_user_per_day
+    {
+        return false;
+    }
+    subsidies.reserved.insert(tx.sig_hash(), (user, shortfall));
+    update(&mut subsidies);
+    true
+}
+
+/// True if `reserve_subsidy` would reserve `shortfall` for `tx` signed by `user`, without reserving it
+pub fn subsidy_available(tx: &GaslessTransaction, user: Address, shortfall: Uint256) -> bool {
+    let Some(budget) = BUDGET.lock().unwrap().clone() else {
+        return false;
+    };
+    let mut subsidies = SUBSIDIES.lock().unwrap();
+    prune(&mut subsidies);
+    // a reservation already made for `tx` would be replaced
+    let replaced = subsidies
+        .reserved
+        .get(&tx.sig_hash())
+        .map(|(_, amount)| *amount)
+        .unwrap_or_default();
+    committed(&subsidies, None) + shortfall <= budget.per_day + replaced
+        && committed(&subsidies, Some(user)) + shortfall <= budget.per_user_per_day + replaced
+}
+
+/// Settles the reservation of `tx`, if it has one, at `cost`, or at the reserved amount if the cost is unknown
+pub fn settle_subsidy(tx: &GaslessTransaction, cost: Option<Uint256>) {
+    let mut subsidies = SUBSIDIES.lock().unwrap();
+    let Some((user, reserved)) = subsidies.reserved.remove(&tx.sig_hash()) else {
+        return;
+    };
+    let amount = cost.unwrap_or(reserved);
+    if !amount.is_zero() {
+        subsidies.spent.push(Spending {
+            at: unix_timestamp(),
+            amount,
+            user: Some(user),
+        });
+    }
+    update(&mut subsidies);
+}
+
+/// Releases the reservation of `tx`, if it has one, for a relay that was never broadcast
+pub fn release_subsidy(tx: &GaslessTransaction) {
+    let mut subsidies = SUBSIDIES.lock().unwrap();
+    if subsidies.reserved.remove(&tx.sig_hash()).is_some() {
+        update(&mut subsidies);
+    }
+}
+
+/// Forgets spending older than a day
+fn prune(subsidies: &mut Subsidies) {
+    let now = unix_timestamp();
+    subsidies
+        .spent
+        .retain(|spending| now.saturating_sub(spending.at) < DAY);
+}
+
+/// Spent within the last day and reserved by relays in flight, on everyone or only on `user`
+fn committed(subsidies: &Subsidies, user: Option<Address>) -> Uint256 {
+    subsidies
+        .spent
+        .iter()
+        .filter(|spending| user.is_none() || spending.user == user)
+        .map(|spending| spending.amount)
+        .chain(
+            subsidies
+                .reserved
+                .values()
+                .filter(|(reserved_for, _)| user.is_none_or(|user| user == *reserved_for))
+                .map(|(_, amount)| *amount),
+        )
+        .fold(Uint256::default(), |total, amount| total + amount)
+}
+
+/// Drops spending older than a day, alerts on thresholds passed either way and marks the ledger for writing
+fn update(subsidies: &mut Subsidies) {
+    let Some(budget) = BUDGET.lock().unwrap().clone() else {
+        return;
+    };
+    prune(subsidies);
+    let committed = committed(subsidies, None);
+    let percent = if budget.per_day.is_zero() {
+        100
+    } else {
+        (committed * 100u8.into() / budget.per_day)
+            .to_u32()
+            .unwrap_or(u32::MAX)
+    };
+    let passed = budget
+        .alert_percents
+        .iter()
+        .copied()
+        .chain([100])
+        .filter(|threshold| percent >= *threshold)
+        .max()
+        .unwrap_or_default();
+    if passed > subsidies.alerted {
+        if passed >= 100 {
+            warn!(
+                "Subsidy budget of {} ALTHEA a day is used up, only relaying transactions that pay for themselves",
+                display_ether(budget.per_day)
+            );
+        } else {
+            info!(
+                "{passed}% of the subsidy budget of {} ALTHEA a day is used",
+                display_ether(budget.per_day)
+            );
+        }
+        alert_on_subsidy(passed, committed, budget.per_day);
+    } else if passed < subsidies.alerted && subsidies.alerted >= 100 {
+        info!("Subsidy budget has room again, relaying sponsored transactions");
+        alert_on_subsidy(passed, committed, budget.per_day);
+    }
+    subsidies.alerted = passed;
+    let reserved = subsidies
+        .reserved
+        .values()
+        .fold(Uint256::default(), |total, (_, amount)| total + *amount);
+    record_subsidy(SubsidyStatus {
+        budget: budget.per_day,
+        spent: committed - reserved,
+        reserved,
+    });
+    subsidies.dirty = true;
+}
+
+/// Rewrites the ledger, if spending changed since it was last written, with relays in flight written down at
+/// their reservation. Runs on its own thread so the relay path never waits on the disk, a failed write is logged
+/// and retried on the next flush.
+fn flush_ledger(path: &Path) {
+    let entries: Vec<Spending> = {
+        let mut subsidies = SUBSIDIES.lock().unwrap();
+        if !subsidies.dirty {
+            return;
+        }
+        subsidies.dirty = false;
+        let now = unix_timestamp();
+        subsidies
+            .spent
+            .iter()
+            .cloned()
+            .chain(subsidies.reserved.values().map(|(user, amount)| Spending {
+                at: now,
+                amount: *amount,
+                user: Some(*user),
+            }))
+            .collect()
+    };
+    let contents = serde_json::to_string_pretty(&entries).expect("Spending always serializes");
+    // written to a temporary file first so a crash mid write never leaves a truncated ledger
+    let tmp = path.with_extension("tmp");
+    if let Err(e) = std::fs::write(&tmp, contents).and_then(|_| std::fs::rename(&tmp, path)) {
+        warn!("Failed to write subsidy ledger {}: {e}", path.display());
+        SUBSIDIES.lock().unwrap().dirty = true;
+    }
+}
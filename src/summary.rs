@@ -0,0 +1,222 @@
+//! Scheduled summary of the relaying done over the last period, by default a day, logged and optionally pushed
+//! to a webhook. Covers relays attempted and succeeded, gas spent, tips earned per token, the estimated net
+//! profit, the most common failure reasons and the tip tokens held, so operators don't have to piece it together
+//! from the logs.
+
+use crate::error::RelayerError;
+use crate::orchestrator::RelayerTip;
+use crate::proxy::http_client;
+use crate::shutdown::sleep_or_shutdown;
+use crate::status::{TokenBalance, get_status, whole_units};
+use crate::units::display_ether;
+use clarity::Uint256;
+use log::{info, warn};
+use serde::Serialize;
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::{self, Display};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+/// Number of failure reasons listed in a summary
+const TOP_FAILURES: usize = 5;
+
+#[derive(Debug, Clone)]
+pub struct SummaryPolicy {
+    /// how often a summary is produced, each one covering the period since the previous one
+    pub interval: Duration,
+    /// url the summary is posted to as JSON, besides being logged
+    pub webhook: Option<String>,
+    pub timeout: Duration,
+}
+
+#[derive(Debug, Clone)]
+enum Event {
+    Attempted,
+    Final {
+        tip: Option<(String, Uint256)>,
+        /// net value of the tip in wei, if it was valued when relaying
+        tip_value: Option<Uint256>,
+    },
+    Failed(String),
+    GasSpent(Uint256),
+}
+
+/// Relaying done over one period
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Summary {
+    /// length of the period covered in seconds
+    pub period: u64,
+    /// relays broadcast
+    pub attempted: u64,
+    /// relays that became final
+    pub succeeded: u64,
+    /// relays that failed for any reason other than being unprofitable or relayed by someone else
+    pub failed: u64,
+    /// gas paid in wei by every relay that made it on chain, including reverted ones
+    pub gas_spent: Uint256,
+    /// tips earned by final relays, in base units of each token
+    pub tips: BTreeMap<String, Uint256>,
+    /// value of the tips earned less the gas spent, in ALTHEA. Tips that weren't valued count as nothing.
+    pub net_althea: f64,
+    /// most common failure reasons with their counts
+    pub top_failures: Vec<(String, u64)>,
+    /// tips not yet liquidated, the token balances of every relayer account on each chain by chain name
+    pub inventory: BTreeMap<String, Vec<TokenBalance>>,
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.period {
+            period if period % 3600 == 0 => {
+                writeln!(f, "Relay summary for the last {} hours", period / 3600)?
+            }
+            period => writeln!(f, "Relay summary for the last {period} seconds")?,
+        }
+        writeln!(
+            f,
+            "Relays attempted: {}, succeeded: {}, failed: {}",
+            self.attempted, self.succeeded, self.failed
+        )?;
+        writeln!(f, "Gas spent: {} ALTHEA", display_ether(self.gas_spent))?;
+        for (token, amount) in &self.tips {
+            writeln!(f, "Tips earned in {token}: {amount}")?;
+        }
+        write!(f, "Estimated net profit: {:.6} ALTHEA", self.net_althea)?;
+        for (chain, tokens) in &self.inventory {
+            for token in tokens {
+                match token.decimals {
+                    Some(decimals) => write!(
+                        f,
+                        "\nHeld on {chain}: {:.6} of {}",
+                        whole_units(token.balance, decimals),
+                        token.token
+                    )?,
+                    None => write!(
+                        f,
+                        "\nHeld on {chain}: {} base units of {}",
+                        token.balance, token.token
+                    )?,
+                }
+            }
+        }
+        for (reason, count) in &self.top_failures {
+            write!(f, "\n{count} failed with: {reason}")?;
+        }
+        Ok(())
+    }
+}
+
+static POLICY: LazyLock<Mutex<Option<SummaryPolicy>>> = LazyLock::new(|| Mutex::new(None));
+
+static EVENTS: LazyLock<Mutex<VecDeque<(Instant, Event)>>> =
+    LazyLock::new(|| Mutex::new(VecDeque::new()));
+
+pub fn set_summary_policy(policy: Option<SummaryPolicy>) {
+    *POLICY.lock().unwrap() = policy;
+}
+
+fn record(event: Event) {
+    let Some(policy) = POLICY.lock().unwrap().clone() else {
+        return;
+    };
+    let mut events = EVENTS.lock().unwrap();
+    while events
+        .front()
+        .is_some_and(|(at, _)| at.elapsed() > policy.interval)
+    {
+        events.pop_front();
+    }
+    events.push_back((Instant::now(), event));
+}
+
+/// Counts a relay that was broadcast
+pub fn record_relay_attempt() {
+    record(Event::Attempted);
+}
+
+/// Counts a final relay paying `tip`, `tip_value` being the net value of the tip in wei if known
+pub fn record_final_relay(tip: Option<RelayerTip>, tip_value: Option<Uint256>) {
+    record(Event::Final {
+        tip: tip.map(|tip| (tip.token.to_string(), tip.amount.into())),
+        tip_value,
+    });
+}
+
+/// Counts a failed relay
+pub fn record_failed_relay(e: &RelayerError) {
+    record(Event::Failed(e.to_string()));
+}
+
+/// Adds the gas paid for a relay that made it on chain
+pub fn record_gas_spent(gas_paid: Uint256) {
+    record(Event::GasSpent(gas_paid));
+}
+
+/// Summarizes the relaying done within the last `period`
+pub fn summarize(period: Duration) -> Summary {
+    let mut summary = Summary {
+        period: period.as_secs(),
+        ..Default::default()
+    };
+    let mut earned = Uint256::default();
+    let mut failures: HashMap<String, u64> = HashMap::new();
+    for (_, event) in EVENTS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(at, _)| at.elapsed() <= period)
+    {
+        match event {
+            Event::Attempted => summary.attempted += 1,
+            Event::Final { tip, tip_value } => {
+                summary.succeeded += 1;
+                if let Some((token, amount)) = tip {
+                    *summary.tips.entry(token.clone()).or_default() += *amount;
+                }
+                earned += tip_value.unwrap_or_default();
+            }
+            Event::Failed(reason) => {
+                summary.failed += 1;
+                *failures.entry(reason.clone()).or_default() += 1;
+            }
+            Event::GasSpent(gas) => summary.gas_spent += *gas,
+        }
+    }
+    summary.net_althea = display_ether(earned) - display_ether(summary.gas_spent);
+    let mut failures: Vec<(String, u64)> = failures.into_iter().collect();
+    failures.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    failures.truncate(TOP_FAILURES);
+    summary.top_failures = failures;
+    summary.inventory = get_status()
+        .chains
+        .into_values()
+        .filter(|chain| !chain.token_balances.is_empty())
+        .map(|chain| (chain.name, chain.token_balances))
+        .collect();
+    summary
+}
+
+/// Logs a summary every `interval` and posts it to the webhook if there is one, until shutdown
+pub async fn report_summaries(policy: SummaryPolicy) {
+    while !sleep_or_shutdown(policy.interval).await {
+        let summary = summarize(policy.interval);
+        info!("{summary}");
+        let Some(url) = &policy.webhook else {
+            continue;
+        };
+        let body = json!({ "text": summary.to_string(), "summary": summary });
+        match http_client(None, policy.timeout)
+            .post(url)
+            .send_json(&body)
+            .await
+        {
+            Ok(response) if response.status().is_success() => {}
+            Ok(response) => warn!(
+                "Summary webhook {url} rejected the summary: {}",
+                response.status()
+            ),
+            Err(e) => warn!("Failed to post the summary to {url}: {e}"),
+        }
+    }
+}
@@ -0,0 +1,209 @@
+//! Sweeping of profits to a cold wallet. The relayer key has to be hot, so whatever it holds is at risk if the
+//! host is compromised. On a schedule the ALTHEA each relayer account holds above a ceiling, and its balances of
+//! chosen tip tokens above what it is meant to keep, are transferred to a cold address. Sweeps run between relay
+//! cycles and are sent the way every transaction of the relayer's own is, so they take the account's next nonce
+//! after its pending relays, are only sent by the instance holding the account's lease and count against the spend
+//! cap.
+
+use crate::error::RelayerError;
+use crate::lease::holds_account_lease;
+use crate::observer::is_observing;
+use crate::relay::{
+    RelayContext, pending_spend, prepare_unsigned_transaction, relay_limits, send_side_transaction,
+};
+use crate::signer::Signer;
+use crate::split::owed_to_partners;
+use crate::tokens::{display_token, resolve_token};
+use clarity::abi::{AbiToken, encode_call};
+use clarity::utils::display_uint256_as_address;
+use clarity::{Address, Transaction, Uint256};
+use log::{info, warn};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+pub(crate) const TRANSFER_SIG: &str = "transfer(address,uint256)";
+
+#[derive(Debug, Clone)]
+pub struct SweepPolicy {
+    /// cold address swept balances are sent to
+    pub to: Address,
+    /// wei of ALTHEA each relayer account keeps, everything above is swept. ALTHEA isn't swept if None.
+    pub ceiling: Option<Uint256>,
+    /// tokens to sweep, along with the balance in base units each relayer account keeps
+    pub tokens: Vec<(Address, Uint256)>,
+    pub interval: Duration,
+}
+
+static POLICY: LazyLock<Mutex<Option<SweepPolicy>>> = LazyLock::new(|| Mutex::new(None));
+
+/// When each chain was last swept, by chain id
+static LAST_SWEEP: LazyLock<Mutex<HashMap<u64, Instant>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+pub fn set_sweep_policy(policy: Option<SweepPolicy>) {
+    *POLICY.lock().unwrap() = policy;
+}
+
+/// Parses a token to sweep given as `TOKEN[=KEEP]`, `KEEP` being the balance in base units left behind
+pub fn parse_sweep_token(entry: &str) -> Result<(Address, Uint256), String> {
+    let (token, keep) = entry.split_once('=').unwrap_or((entry, "0"));
+    let token = Address::from_str(token.trim()).map_err(|e| format!("{entry}: {e}"))?;
+    let keep = Uint256::from_str(keep.trim()).map_err(|e| format!("{entry}: {e}"))?;
+    Ok((token, keep))
+}
+
+/// Sweeps the relayer accounts of `ctx` if sweeping is enabled and the chain wasn't swept within the interval
+pub async fn sweep_if_due(ctx: &RelayContext) {
+    let Some(policy) = POLICY.lock().unwrap().clone() else {
+        return;
+    };
+    {
+        let mut last_sweep = LAST_SWEEP.lock().unwrap();
+        if last_sweep
+            .get(&ctx.chain_id)
+            .is_some_and(|at| at.elapsed() < policy.interval)
+        {
+            return;
+        }
+        last_sweep.insert(ctx.chain_id, Instant::now());
+    }
+    if is_observing() {
+        return;
+    }
+    for signer in ctx.signers.signers() {
+        // sweeps use the account's nonces, which only the instance holding its lease hands out
+        if signer.address() == policy.to || !holds_account_lease(ctx.chain_id, signer.address()) {
+            continue;
+        }
+        // tokens go first, the transfers are paid for out of the ALTHEA that isn't swept
+        for (token, keep) in &policy.tokens {
+            if let Err(e) = sweep_token(ctx, &policy, signer, *token, *keep).await {
+                warn!(
+                    "Failed to sweep {} from {} on {}: {e}",
+                    display_token(ctx.chain_id, *token),
+                    signer.address(),
+                    ctx.name
+                );
+            }
+        }
+        if let Some(ceiling) = policy.ceiling
+            && let Err(e) = sweep_native(ctx, &policy, signer, ceiling).await
+        {
+            warn!(
+                "Failed to sweep ALTHEA from {} on {}: {e}",
+                signer.address(),
+                ctx.name
+            );
+        }
+    }
+}
+
+async fn sweep_token(
+    ctx: &RelayContext,
+    policy: &SweepPolicy,
+    signer: &Signer,
+    token: Address,
+    keep: Uint256,
+) -> Result<(), RelayerError> {
+    let balance = ctx
+        .web3()
+        .get_erc20_balance(token, signer.address(), Vec::new())
+        .await?;
+    // tip shares owed to pool partners are theirs, not profit
+    let keep = keep + owed_to_partners(ctx.chain_id, signer.address(), token);
+    if balance <= keep {
+        return Ok(());
+    }
+    let amount = balance - keep;
+    let data = encode_call(
+        TRANSFER_SIG,
+        &[AbiToken::Address(policy.to), AbiToken::Uint(amount)],
+    )?;
+    let unsigned =
+        prepare_unsigned_transaction(ctx.web3(), ctx.chain_id, signer.address(), token, data, 1.5)
+            .await?;
+    resolve_token(ctx.web3(), ctx.chain_id, token).await;
+    let what = format!("{amount} of {}", display_token(ctx.chain_id, token));
+    submit(ctx, policy, signer, unsigned, &what).await
+}
+
+async fn sweep_native(
+    ctx: &RelayContext,
+    policy: &SweepPolicy,
+    signer: &Signer,
+    ceiling: Uint256,
+) -> Result<(), RelayerError> {
+    let balance = ctx.web3().eth_get_balance(signer.address()).await?;
+    // pending relays still spend from the balance, and tip shares owed to pool partners are theirs
+    let keep = ceiling
+        + pending_spend(ctx.chain_id, signer.address())
+        + owed_to_partners(ctx.chain_id, signer.address(), Address::default());
+    if balance <= keep {
+        return Ok(());
+    }
+    let amount = balance - keep;
+    let mut unsigned = prepare_unsigned_transaction(
+        ctx.web3(),
+        ctx.chain_id,
+        signer.address(),
+        policy.to,
+        Vec::new(),
+        1.5,
+    )
+    .await?;
+    let Transaction::Eip1559 {
+        max_fee_per_gas,
+        gas_limit,
+        value,
+        ..
+    } = &mut unsigned
+    else {
+        return Ok(());
+    };
+    // the fee is paid out of the ceiling, the reserve stays untouched
+    let max_cost = *max_fee_per_gas * *gas_limit;
+    let reserve = relay_limits().reserve_balance;
+    if ceiling <= reserve || max_cost > ceiling - reserve {
+        warn!(
+            "Not sweeping ALTHEA from {} on {}, a sweep may cost up to {max_cost} wei which is more than the ceiling leaves above the reserve",
+            signer.address(),
+            ctx.name
+        );
+        return Ok(());
+    }
+    if amount <= max_cost {
+        return Ok(());
+    }
+    *value = amount;
+    let what = format!("{amount} wei of ALTHEA");
+    submit(ctx, policy, signer, unsigned, &what).await
+}
+
+async fn submit(
+    ctx: &RelayContext,
+    policy: &SweepPolicy,
+    signer: &Signer,
+    unsigned: Transaction,
+    what: &str,
+) -> Result<(), RelayerError> {
+    if ctx.dry_run {
+        info!(
+            "Dry run, not sweeping {what} from {} to {} on {}",
+            signer.address(),
+            policy.to,
+            ctx.name
+        );
+        return Ok(());
+    }
+    let tx_hash = send_side_transaction(ctx, signer, unsigned).await?;
+    info!(
+        "Swept {what} from {} to {} on {} with {}",
+        signer.address(),
+        policy.to,
+        ctx.name,
+        display_uint256_as_address(tx_hash)
+    );
+    Ok(())
+}
@@ -0,0 +1,51 @@
+//! systemd service notifications. When started by systemd with `Type=notify` the relayer reports readiness once
+//! it has polled an orchestrator and reached every chain, and pings the watchdog every loop iteration so that a
+//! relayer stuck somewhere is restarted. Without `NOTIFY_SOCKET` in the environment every call is a no-op.
+
+use log::{debug, info};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static READY: AtomicBool = AtomicBool::new(false);
+
+/// Tells systemd the relayer is up, only the first call has any effect
+pub fn notify_ready() {
+    if !READY.swap(true, Ordering::Relaxed) {
+        info!("Relayer is ready");
+        notify("READY=1");
+    }
+}
+
+/// Resets the systemd watchdog timer
+pub fn notify_watchdog() {
+    notify("WATCHDOG=1");
+}
+
+pub fn notify_stopping() {
+    notify("STOPPING=1");
+}
+
+#[cfg(unix)]
+fn notify(state: &str) {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::UnixDatagram;
+
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    let result = UnixDatagram::unbound().and_then(|socket| match path.as_bytes() {
+        // a leading @ denotes a socket in the abstract namespace
+        #[cfg(target_os = "linux")]
+        [b'@', name @ ..] => {
+            use std::os::linux::net::SocketAddrExt;
+            use std::os::unix::net::SocketAddr;
+            socket.send_to_addr(state.as_bytes(), &SocketAddr::from_abstract_name(name)?)
+        }
+        _ => socket.send_to(state.as_bytes(), &path),
+    });
+    if let Err(e) = result {
+        debug!("Failed to notify systemd of {state}: {e}");
+    }
+}
+
+#[cfg(not(unix))]
+fn notify(_state: &str) {}
@@ -0,0 +1,36 @@
+//! Optional OpenTelemetry tracing. The fetch, decode, price, simulate and submit stages of the relay pipeline are
+//! instrumented with `tracing` spans, when an OTLP endpoint is configured they are exported so latency can be
+//! broken down per transaction across the orchestrator, price API and RPC calls. Without one the spans are no-ops.
+
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use tracing::Level;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::layer::{Layer, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+
+const SERVICE_NAME: &str = "althea-l1-relayer";
+
+/// Exports spans over OTLP/HTTP to `endpoint`, such as `http://localhost:4318/v1/traces`. The returned provider
+/// must be shut down on exit to flush spans that have not been exported yet.
+pub fn init_tracing(endpoint: &str) -> Result<SdkTracerProvider, Box<dyn std::error::Error>> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name(SERVICE_NAME).build())
+        .build();
+    tracing_subscriber::registry()
+        .with(
+            tracing_opentelemetry::layer()
+                .with_tracer(provider.tracer(SERVICE_NAME))
+                // dependencies such as the HTTP clients have spans of their own, only ours are of interest
+                .with_filter(Targets::new().with_target("althea_l1_relayer", Level::INFO)),
+        )
+        .try_init()?;
+    Ok(provider)
+}
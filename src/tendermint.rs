@@ -0,0 +1,136 @@
+//! Block notifications from a Tendermint/CometBFT node. Althea L1 is a Cosmos chain, so its CometBFT RPC can push
+//! `NewBlock` events over a websocket as soon as a block is committed, well before polling the EVM RPC notices
+//! it. The events are only used to start the next relay cycle sooner, everything else still goes through the EVM
+//! RPC, and block polling takes over again while the websocket is down.
+
+use crate::orchestrator::{SUBSCRIPTION_IDLE_TIMEOUT, SUBSCRIPTION_RECONNECT_DELAY};
+use crate::proxy::http_client;
+use crate::shutdown::{is_shutting_down, sleep_or_shutdown};
+use awc::ws::{Frame, Message};
+use futures::{SinkExt, StreamExt};
+use log::{debug, info, trace, warn};
+use serde_json::{Value, json};
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+const NEW_BLOCK_QUERY: &str = "tm.event='NewBlock'";
+
+/// Signalled on every new block, holds on to one signal if nobody is waiting yet
+static NEW_BLOCK: LazyLock<Notify> = LazyLock::new(Notify::new);
+
+static CONNECTED: AtomicBool = AtomicBool::new(false);
+
+/// Websocket url of the CometBFT RPC at `url`, which may be given as its http(s) url
+pub fn websocket_url(url: &str) -> String {
+    let url = url.trim_end_matches('/');
+    let url = if let Some(rest) = url.strip_prefix("http://") {
+        format!("ws://{rest}")
+    } else if let Some(rest) = url.strip_prefix("https://") {
+        format!("wss://{rest}")
+    } else {
+        url.to_string()
+    };
+    if url.ends_with("/websocket") {
+        url
+    } else {
+        format!("{url}/websocket")
+    }
+}
+
+/// True while subscribed to new blocks
+pub fn tendermint_connected() -> bool {
+    CONNECTED.load(Ordering::Relaxed)
+}
+
+/// Waits up to `max_wait` for the next block, returns false straight away if not subscribed
+pub async fn wait_for_tendermint_block(max_wait: Duration) -> bool {
+    if !tendermint_connected() {
+        return false;
+    }
+    tokio::select! {
+        _ = NEW_BLOCK.notified() => trace!("New block from CometBFT, starting relay cycle"),
+        _ = sleep_or_shutdown(max_wait) => {}
+    }
+    true
+}
+
+/// Keeps a subscription to new blocks on the CometBFT RPC at `url` open until shutdown
+pub async fn tendermint_subscribe_loop(url: String, timeout: Duration) {
+    let url = websocket_url(&url);
+    while !is_shutting_down() {
+        match subscribe(&url, timeout).await {
+            Ok(()) => warn!("Block subscription to {url} closed, falling back to block polling"),
+            Err(e) => {
+                warn!("Block subscription to {url} failed, falling back to block polling: {e}")
+            }
+        }
+        CONNECTED.store(false, Ordering::Relaxed);
+        if sleep_or_shutdown(SUBSCRIPTION_RECONNECT_DELAY).await {
+            return;
+        }
+    }
+}
+
+async fn subscribe(url: &str, timeout: Duration) -> Result<(), String> {
+    debug!("Opening block subscription to {url}");
+    let (_, mut socket) = http_client(None, timeout)
+        .ws(url)
+        .connect()
+        .await
+        .map_err(|e| e.to_string())?;
+    let request = json!({
+        "jsonrpc": "2.0",
+        "method": "subscribe",
+        "id": 1,
+        "params": { "query": NEW_BLOCK_QUERY },
+    });
+    socket
+        .send(Message::Text(request.to_string().into()))
+        .await
+        .map_err(|e| e.to_string())?;
+    loop {
+        let frame = match actix_rt::time::timeout(SUBSCRIPTION_IDLE_TIMEOUT, socket.next()).await {
+            Ok(Some(frame)) => frame.map_err(|e| e.to_string())?,
+            Ok(None) => return Ok(()),
+            Err(_) => {
+                return Err(format!(
+                    "no block for {} seconds",
+                    SUBSCRIPTION_IDLE_TIMEOUT.as_secs()
+                ));
+            }
+        };
+        let text = match frame {
+            Frame::Text(text) => text,
+            Frame::Ping(data) => {
+                socket
+                    .send(Message::Pong(data))
+                    .await
+                    .map_err(|e| e.to_string())?;
+                continue;
+            }
+            Frame::Close(_) => return Ok(()),
+            _ => continue,
+        };
+        let message: Value = serde_json::from_slice(&text).map_err(|e| e.to_string())?;
+        if let Some(error) = message.get("error") {
+            return Err(error.to_string());
+        }
+        let result = &message["result"];
+        // the subscription is confirmed with an empty result, events carry the query they matched
+        if result["query"].is_null() {
+            if !CONNECTED.swap(true, Ordering::Relaxed) {
+                info!("Subscribed to new blocks on {url}");
+            }
+            continue;
+        }
+        trace!(
+            "New block {} from {url}",
+            result["data"]["value"]["block"]["header"]["height"]
+                .as_str()
+                .unwrap_or("?")
+        );
+        NEW_BLOCK.notify_one();
+    }
+}
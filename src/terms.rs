@@ -0,0 +1,64 @@
+//! Acceptance of the terms of use. Rather than passing `--agree` on every start, operators can agree once, on the
+//! terminal or with `--agree`, and have the acceptance recorded in a state directory. The record carries the version
+//! of the terms that was agreed to, so changed terms have to be accepted again.
+
+use crate::status::unix_timestamp;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::Path;
+
+pub const TERMS: &str = "This software is provided AS IS as a reference gassless transaction relayer. This software may contain bugs, lose funds, or even spend all the ALTHEA it has access to.\
+do not put more tokens in the wallet than you can afford to lose. Monitor this application closely at all times. Default RPC endpoints are not guaranteed to stay online, or to be accurate. \
+You have a license under Apache-2.0 to modify and improve this software with attribution. No support or updates are guaranteed. This software is used entirely at your own risk.";
+
+/// Version of `TERMS`, to be bumped whenever their text changes so that acceptances of older terms no longer count
+pub const TERMS_VERSION: u32 = 1;
+
+const ACCEPTANCE_FILE: &str = "terms-accepted.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TermsAcceptance {
+    /// version of the terms that were accepted
+    pub version: u32,
+    /// unix timestamp of the acceptance
+    pub accepted_at: u64,
+}
+
+/// The acceptance recorded in `state_dir`, None if the terms were never accepted there
+pub fn recorded_acceptance(state_dir: &Path) -> Result<Option<TermsAcceptance>, String> {
+    let path = state_dir.join(ACCEPTANCE_FILE);
+    match std::fs::read_to_string(&path) {
+        Ok(contents) => serde_json::from_str(&contents)
+            .map(Some)
+            .map_err(|e| format!("{}: {e}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("{}: {e}", path.display())),
+    }
+}
+
+/// Records in `state_dir` that the current terms were accepted, creating the directory if needed
+pub fn record_acceptance(state_dir: &Path) -> Result<TermsAcceptance, String> {
+    let acceptance = TermsAcceptance {
+        version: TERMS_VERSION,
+        accepted_at: unix_timestamp(),
+    };
+    let path = state_dir.join(ACCEPTANCE_FILE);
+    let contents = serde_json::to_string_pretty(&acceptance).expect("Acceptance always serializes");
+    // written to a temporary file first so a crash mid write never leaves a truncated record
+    let tmp = path.with_extension("tmp");
+    std::fs::create_dir_all(state_dir)
+        .and_then(|_| std::fs::write(&tmp, contents))
+        .and_then(|_| std::fs::rename(&tmp, &path))
+        .map_err(|e| format!("{}: {e}", path.display()))?;
+    Ok(acceptance)
+}
+
+/// Shows the terms and asks the operator to agree to them by typing `agree`
+pub fn confirm_interactively() -> std::io::Result<bool> {
+    println!("{TERMS}");
+    print!("Type \"agree\" to agree to these terms (version {TERMS_VERSION}): ");
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(answer.trim().eq_ignore_ascii_case("agree"))
+}
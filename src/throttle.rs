@@ -0,0 +1,102 @@
+//! Politeness towards the services the relayer calls. Orchestrators, RPC endpoints and the price API can each be
+//! given a ceiling on how often they are called, and requests over it wait their turn instead of failing. A large
+//! pending queue is then worked through at a pace public endpoints tolerate, rather than getting the relayer banned.
+//! Each orchestrator and each RPC endpoint has a ceiling of its own, price lookups share one.
+
+use log::debug;
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+/// Most requests within a window, bursts of up to `requests` are allowed as long as the window isn't exceeded
+#[derive(Debug, Clone, Copy)]
+pub struct Ceiling {
+    pub requests: u32,
+    pub per: Duration,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OutboundLimits {
+    /// requests to each orchestrator, over HTTP or gRPC
+    pub orchestrator: Option<Ceiling>,
+    /// JSON-RPC requests to each RPC endpoint
+    pub rpc: Option<Ceiling>,
+    /// requests to the price API
+    pub price: Option<Ceiling>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Outbound {
+    Orchestrator,
+    Rpc,
+    Price,
+}
+
+impl Outbound {
+    pub fn name(self) -> &'static str {
+        match self {
+            Outbound::Orchestrator => "orchestrator",
+            Outbound::Rpc => "rpc",
+            Outbound::Price => "price",
+        }
+    }
+}
+
+static LIMITS: LazyLock<Mutex<OutboundLimits>> =
+    LazyLock::new(|| Mutex::new(OutboundLimits::default()));
+
+/// When the next request to each endpoint would be due if requests were evenly spaced, the ceiling is kept by
+/// holding a request back while this is more than a window ahead of it
+static SCHEDULE: LazyLock<Mutex<HashMap<(Outbound, String), Instant>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+pub fn set_outbound_limits(limits: OutboundLimits) {
+    *LIMITS.lock().unwrap() = limits;
+}
+
+/// True if RPC requests are throttled, they then have to pass through the local bridge
+pub fn rpc_throttled() -> bool {
+    LIMITS.lock().unwrap().rpc.is_some()
+}
+
+/// Waits until a request to `endpoint` of `service` is within its ceiling, returning at once if there is none
+pub async fn throttle(service: Outbound, endpoint: &str) {
+    let limits = *LIMITS.lock().unwrap();
+    let ceiling = match service {
+        Outbound::Orchestrator => limits.orchestrator,
+        Outbound::Rpc => limits.rpc,
+        Outbound::Price => limits.price,
+    };
+    let Some(ceiling) = ceiling.filter(|ceiling| ceiling.requests > 0) else {
+        return;
+    };
+    let interval = ceiling.per / ceiling.requests;
+    let key = (service, endpoint.to_string());
+    let mut held_back = false;
+    // a slot is only taken once the request may go, so requests abandoned while waiting, such as by a caller that
+    // timed out, don't hold up the ones behind them
+    loop {
+        let now = Instant::now();
+        let wait = {
+            let mut schedule = SCHEDULE.lock().unwrap();
+            let due = schedule.get(&key).map_or(now, |due| (*due).max(now)) + interval;
+            let wait = due
+                .checked_sub(ceiling.per)
+                .map_or(Duration::ZERO, |start| start.saturating_duration_since(now));
+            if wait.is_zero() {
+                schedule.insert(key, due);
+                return;
+            }
+            wait
+        };
+        if !held_back {
+            debug!(
+                "Holding back a request to {endpoint} to stay within {} per {}s",
+                ceiling.requests,
+                ceiling.per.as_secs()
+            );
+            held_back = true;
+        }
+        actix_rt::time::sleep(wait).await;
+    }
+}
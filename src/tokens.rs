@@ -0,0 +1,122 @@
+//! Symbols and names of tip tokens, so that logs, metrics, alerts and the history name tokens rather than only
+//! giving their addresses. They are read from the token's `symbol()` and `name()` on first use and cached after
+//! that. Both are chosen by whoever deployed the token, so they are cleaned up before being shown anywhere and
+//! the address is always shown alongside the symbol, a token can claim any symbol it likes.
+
+use clarity::Address;
+use log::debug;
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use web30::client::Web3;
+
+/// Longest symbol or name kept, anything longer is cut off
+const MAX_LENGTH: usize = 32;
+
+/// Most tokens whose metadata is cached, a transaction can tip in any token so the cache is cleared when it is full
+const MAX_TOKENS: usize = 10_000;
+
+/// How long a token whose metadata couldn't be read is shown by address only before it is tried again
+const RETRY_AFTER: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenMetadata {
+    pub symbol: String,
+    pub name: String,
+}
+
+/// Metadata of a token as last read, None if it couldn't be read
+#[derive(Debug, Clone)]
+struct Lookup {
+    at: Instant,
+    metadata: Option<TokenMetadata>,
+}
+
+/// Metadata of each tip token by chain id
+static METADATA: LazyLock<Mutex<HashMap<(u64, Address), Lookup>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Metadata of `token` on the chain with `chain_id`, read from the token unless it is cached. None for the
+/// native gas token and tokens that don't implement `symbol()`.
+pub async fn resolve_token(web3: &Web3, chain_id: u64, token: Address) -> Option<TokenMetadata> {
+    if token == Address::default() {
+        return None;
+    }
+    if let Some(lookup) = METADATA.lock().unwrap().get(&(chain_id, token))
+        && (lookup.metadata.is_some() || lookup.at.elapsed() < RETRY_AFTER)
+    {
+        return lookup.metadata.clone();
+    }
+    let symbol = web3
+        .get_erc20_symbol(token, Address::default(), Vec::new())
+        .await;
+    let metadata = match symbol.map(|symbol| clean(&symbol)) {
+        Ok(symbol) if !symbol.is_empty() => {
+            // a name is optional, the symbol stands in for a token without one
+            let name = web3
+                .get_erc20_name(token, Address::default(), Vec::new())
+                .await
+                .map(|name| clean(&name))
+                .ok()
+                .filter(|name| !name.is_empty())
+                .unwrap_or_else(|| symbol.clone());
+            debug!("Token {token} on chain {chain_id} is {name} ({symbol})");
+            Some(TokenMetadata { symbol, name })
+        }
+        Ok(_) => {
+            debug!("Token {token} on chain {chain_id} has no symbol");
+            None
+        }
+        Err(e) => {
+            debug!("Failed to read the symbol of token {token} on chain {chain_id}: {e}");
+            None
+        }
+    };
+    let mut cache = METADATA.lock().unwrap();
+    if cache.len() >= MAX_TOKENS {
+        cache.clear();
+    }
+    cache.insert(
+        (chain_id, token),
+        Lookup {
+            at: Instant::now(),
+            metadata: metadata.clone(),
+        },
+    );
+    metadata
+}
+
+/// Forgets the metadata of every token resolved so far
+pub fn clear_token_cache() {
+    METADATA.lock().unwrap().clear();
+}
+
+/// Symbol of `token` if it was already resolved
+pub fn token_symbol(chain_id: u64, token: Address) -> Option<String> {
+    METADATA
+        .lock()
+        .unwrap()
+        .get(&(chain_id, token))
+        .and_then(|lookup| lookup.metadata.as_ref())
+        .map(|metadata| metadata.symbol.clone())
+}
+
+/// `token` as logs and alerts show it, its symbol followed by its address if it was resolved
+pub fn display_token(chain_id: u64, token: Address) -> String {
+    match token_symbol(chain_id, token) {
+        Some(symbol) => format!("{symbol} ({token})"),
+        None => token.to_string(),
+    }
+}
+
+/// Keeps the characters symbols and names are made of, so that a token can't inject anything into log lines,
+/// metric labels or alerts
+fn clean(value: &str) -> String {
+    value
+        .chars()
+        .filter(|c| c.is_alphanumeric() || matches!(c, ' ' | '.' | '-' | '_' | '+' | '$'))
+        .take(MAX_LENGTH)
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
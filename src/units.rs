@@ -0,0 +1,9 @@
+//! Amounts of the gas token as they are shown to operators, in logs, alerts and summaries, rather than in wei.
+
+use clarity::Uint256;
+use num_traits::ToPrimitive;
+
+/// Wei of the gas token as a decimal amount of ALTHEA, for logs and alerts
+pub fn display_ether(wei: Uint256) -> f64 {
+    wei.to_u128().unwrap_or(u128::MAX) as f64 / 1e18
+}
@@ -0,0 +1,107 @@
+//! Deadline aware relaying. A transaction whose deadline is close is evaluated ahead of the rest of the queue and
+//! relayed with a raised priority fee so that it lands in time. One whose deadline is closer than our relays have
+//! lately taken to be included can't plausibly make it, and is skipped rather than paying for a relay that
+//! reverts once the deadline has passed.
+
+use clarity::{Transaction, Uint256};
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+/// Weight of the newest inclusion in the moving average of how long relays take to be included
+const SMOOTHING: f64 = 0.3;
+
+#[derive(Debug, Clone)]
+pub struct UrgencyPolicy {
+    /// transactions whose deadline is closer than this are urgent
+    pub window: Duration,
+    /// the priority fee of urgent relays is raised by this many percent
+    pub fee_bump_percent: u32,
+    /// most priority fee in wei per gas an urgent relay is raised to
+    pub max_priority_fee: Option<Uint256>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Urgency {
+    /// no deadline, or one far enough away
+    Normal,
+    /// the deadline is within the urgency window
+    Urgent,
+    /// the deadline is closer than a relay takes to be included
+    Unreachable,
+}
+
+static POLICY: LazyLock<Mutex<Option<UrgencyPolicy>>> = LazyLock::new(|| Mutex::new(None));
+
+/// Moving average of the seconds our relays took from broadcast to inclusion, by chain id
+static LATENCY: LazyLock<Mutex<HashMap<u64, f64>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+pub fn set_urgency_policy(policy: Option<UrgencyPolicy>) {
+    *POLICY.lock().unwrap() = policy;
+}
+
+/// Records that a relay on `chain_id` was included `latency` after it was broadcast
+pub fn record_inclusion_latency(chain_id: u64, latency: Duration) {
+    let mut averages = LATENCY.lock().unwrap();
+    let latency = latency.as_secs_f64();
+    averages
+        .entry(chain_id)
+        .and_modify(|average| *average += (latency - *average) * SMOOTHING)
+        .or_insert(latency);
+}
+
+/// How long relays on `chain_id` have lately taken to be included, None until one was
+pub fn inclusion_latency(chain_id: u64) -> Option<Duration> {
+    LATENCY
+        .lock()
+        .unwrap()
+        .get(&chain_id)
+        .map(|secs| Duration::from_secs_f64(*secs))
+}
+
+/// How urgent relaying a transaction with unix time `deadline` on `chain_id` is at unix time `now`, a zero
+/// deadline being no deadline. Everything is `Normal` unless deadline aware relaying is enabled.
+pub fn urgency(chain_id: u64, deadline: u64, now: u64) -> Urgency {
+    let Some(policy) = POLICY.lock().unwrap().clone() else {
+        return Urgency::Normal;
+    };
+    if deadline == 0 {
+        return Urgency::Normal;
+    }
+    let remaining = Duration::from_secs(deadline.saturating_sub(now));
+    if inclusion_latency(chain_id).is_some_and(|latency| remaining < latency) {
+        Urgency::Unreachable
+    } else if remaining < policy.window {
+        Urgency::Urgent
+    } else {
+        Urgency::Normal
+    }
+}
+
+/// Raises the priority fee of an urgent relay by the configured percentage, capped by the configured maximum and
+/// by the max fee already budgeted for it, which is left as is so the relay never costs more than was affordable.
+/// Returns the increase in wei per gas.
+pub fn escalate_priority_fee(call: &mut Transaction) -> Uint256 {
+    let Some(policy) = POLICY.lock().unwrap().clone() else {
+        return Uint256::default();
+    };
+    let Transaction::Eip1559 {
+        max_priority_fee_per_gas,
+        max_fee_per_gas,
+        ..
+    } = call
+    else {
+        return Uint256::default();
+    };
+    let current = *max_priority_fee_per_gas;
+    let mut raised = current * (100 + policy.fee_bump_percent).into() / 100u8.into();
+    if let Some(max) = policy.max_priority_fee {
+        raised = raised.min(max);
+    }
+    raised = raised.min(*max_fee_per_gas);
+    if raised <= current {
+        return Uint256::default();
+    }
+    *max_priority_fee_per_gas = raised;
+    raised - current
+}